@@ -0,0 +1,76 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use melange_db::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 统计分配次数，比较两种读取方式
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const N: u32 = 10_000;
+
+// 只读取4KB值中的两个字段
+fn fields(value: &[u8]) -> u32 {
+    u32::from(value[0]) + u32::from(value[value.len() - 1])
+}
+
+fn get_in_place_benchmark(c: &mut Criterion) {
+    // 禁用自动flush，所有叶子节点都在缓存中
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .cache_capacity_bytes(256 * 1024 * 1024)
+        .open()
+        .unwrap();
+    let keys: Vec<[u8; 4]> = (0..N).map(u32::to_be_bytes).collect();
+    for key in &keys {
+        db.insert(key, vec![key[3]; 4096]).unwrap();
+    }
+
+    let get = || -> u32 {
+        keys.iter().map(|key| fields(&db.get(key).unwrap().unwrap())).sum()
+    };
+    let get_in_place = || -> u32 {
+        keys.iter().map(|key| db.get_in_place(key, fields).unwrap().unwrap()).sum()
+    };
+    let contains_key = || -> u32 {
+        keys.iter().map(|key| u32::from(db.contains_key(key).unwrap())).sum()
+    };
+
+    // 预热缓存后比较读取所有键的分配次数
+    assert_eq!(get(), get_in_place());
+    for (name, read) in [
+        ("get", &get as &dyn Fn() -> u32),
+        ("get_in_place", &get_in_place),
+        ("contains_key", &contains_key),
+    ] {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        read();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("{}: 读取 {} 个键，分配 {} 次", name, N, allocations);
+    }
+
+    let mut group = c.benchmark_group("get_in_place");
+    group.bench_function("get", |b| b.iter(get));
+    group.bench_function("get_in_place", |b| b.iter(get_in_place));
+    group.bench_function("contains_key", |b| b.iter(contains_key));
+    group.finish();
+}
+
+criterion_group!(benches, get_in_place_benchmark);
+criterion_main!(benches);
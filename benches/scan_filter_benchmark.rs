@@ -0,0 +1,78 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use melange_db::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 统计分配次数，比较两种扫描方式
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const N: u32 = 100_000;
+
+// 值的第一个字节为1的行约占2%
+fn is_kept(value: &[u8]) -> bool {
+    value[0] == 1
+}
+
+fn scan_filter_benchmark(c: &mut Criterion) {
+    // 禁用自动flush，所有叶子节点都在缓存中
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .cache_capacity_bytes(256 * 1024 * 1024)
+        .open()
+        .unwrap();
+    let tree = db.open_tree("analytics").unwrap();
+    for i in 0..N {
+        let mut value = vec![u8::from(i.is_multiple_of(50))];
+        value.resize(128, (i % 251) as u8);
+        tree.insert(i.to_be_bytes(), value).unwrap();
+    }
+
+    let filter_after_iter = || -> Vec<u8> {
+        tree.iter()
+            .map(|item| item.unwrap())
+            .filter(|(_, v)| is_kept(v))
+            .map(|(_, v)| v[1])
+            .collect()
+    };
+    let scan_filter = || -> Vec<u8> {
+        tree.scan_filter::<&[u8], _, _, _>(.., |_, v| is_kept(v).then(|| v[1]))
+            .unwrap()
+    };
+
+    // 预热缓存后比较一次完整扫描的分配次数
+    assert_eq!(filter_after_iter(), scan_filter());
+    for (name, scan) in [
+        ("iter后过滤", &filter_after_iter as &dyn Fn() -> Vec<u8>),
+        ("scan_filter", &scan_filter),
+    ] {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let kept = scan().len();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("{}: 保留 {} / {} 行，分配 {} 次", name, kept, N, allocations);
+    }
+
+    let mut group = c.benchmark_group("scan_filter");
+    group.bench_function("filter_after_iter", |b| b.iter(filter_after_iter));
+    group.bench_function("scan_filter", |b| b.iter(scan_filter));
+    group.finish();
+}
+
+criterion_group!(benches, scan_filter_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,658 @@
+//! YCSB风格的基准测试工具，输出机器可读的结果，用于跨提交跟踪性能回归
+//!
+//! 只使用公开API。所有随机数都来自固定的种子，相同的参数产生相同的操作序列。
+//!
+//! ```text
+//! cargo run --release --example bench -- --workload a --duration 10 --output json > baseline.json
+//! cargo run --release --example bench -- --workload a --duration 10 --compare baseline.json
+//! ```
+//!
+//! 工作负载：
+//!
+//! * `a`：50%读取，50%更新
+//! * `b`：95%读取，5%更新
+//! * `c`：100%读取
+//!
+//! 使用 `--compare` 时，如果总吞吐量或任何一种操作的吞吐量比基线低了超过
+//! `--threshold` 百分比，进程以非零状态退出。
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use melange_db::{CompressionAlgorithm, Config, Db};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Zipf};
+use serde::{Deserialize, Serialize};
+
+const USAGE: &str = "\
+用法: bench [选项]
+
+    --workload a|b|c           工作负载 (默认 a)
+    --keys N                   键的数量 (默认 100000)
+    --value-size N             值的字节数 (默认 100)
+    --threads N                工作线程数 (默认 4)
+    --duration SECS            运行阶段的秒数 (默认 10)
+    --distribution zipfian|uniform
+                               键的访问分布 (默认 zipfian)
+    --compression zstd|lz4|none
+                               压缩算法 (默认根据编译特性选择)
+    --fanout 64|256|1024       LEAF_FANOUT (默认 1024)
+    --seed N                   随机数种子 (默认 42)
+    --path DIR                 数据库目录 (默认使用临时目录)
+    --output text|json|csv     输出格式 (默认 text)
+    --compare FILE             与JSON格式的基线结果比较
+    --threshold PCT            允许的吞吐量下降百分比 (默认 10)
+";
+
+/// YCSB工作负载的操作比例
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    A,
+    B,
+    C,
+}
+
+impl Workload {
+    fn read_fraction(self) -> f64 {
+        match self {
+            Workload::A => 0.5,
+            Workload::B => 0.95,
+            Workload::C => 1.0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Workload::A => "a",
+            Workload::B => "b",
+            Workload::C => "c",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDistribution {
+    Zipfian,
+    Uniform,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub workload: Workload,
+    pub keys: u64,
+    pub value_size: usize,
+    pub threads: usize,
+    pub duration: Duration,
+    pub distribution: KeyDistribution,
+    pub compression: CompressionAlgorithm,
+    pub fanout: usize,
+    pub seed: u64,
+    pub path: Option<PathBuf>,
+    pub output: OutputFormat,
+    pub compare: Option<PathBuf>,
+    pub threshold_percent: f64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            workload: Workload::A,
+            keys: 100_000,
+            value_size: 100,
+            threads: 4,
+            duration: Duration::from_secs(10),
+            distribution: KeyDistribution::Zipfian,
+            compression: CompressionAlgorithm::default(),
+            fanout: 1024,
+            seed: 42,
+            path: None,
+            output: OutputFormat::Text,
+            compare: None,
+            threshold_percent: 10.0,
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} 的值无效: {}", flag, value))
+}
+
+/// 解析命令行参数，`--help` 返回 `Ok(None)`
+pub fn parse_args<I, S>(args: I) -> Result<Option<Options>, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(flag) = args.next() {
+        let flag = flag.as_ref();
+        if flag == "--help" || flag == "-h" {
+            return Ok(None);
+        }
+
+        let value = args.next().ok_or_else(|| format!("{} 缺少参数值", flag))?;
+        let value = value.as_ref();
+
+        match flag {
+            "--workload" => {
+                options.workload = match value {
+                    "a" | "A" => Workload::A,
+                    "b" | "B" => Workload::B,
+                    "c" | "C" => Workload::C,
+                    _ => return Err(format!("未知的工作负载: {}", value)),
+                }
+            }
+            "--keys" => options.keys = parse_number(flag, value)?,
+            "--value-size" => options.value_size = parse_number(flag, value)?,
+            "--threads" => options.threads = parse_number(flag, value)?,
+            "--duration" => {
+                let secs: f64 = parse_number(flag, value)?;
+                options.duration = Duration::try_from_secs_f64(secs)
+                    .map_err(|_| format!("{} 的值无效: {}", flag, value))?;
+            }
+            "--distribution" => {
+                options.distribution = match value {
+                    "zipfian" => KeyDistribution::Zipfian,
+                    "uniform" => KeyDistribution::Uniform,
+                    _ => return Err(format!("未知的键分布: {}", value)),
+                }
+            }
+            "--compression" => {
+                options.compression = match value {
+                    "zstd" => CompressionAlgorithm::Zstd,
+                    "lz4" => CompressionAlgorithm::Lz4,
+                    "none" => CompressionAlgorithm::None,
+                    _ => return Err(format!("未知的压缩算法: {}", value)),
+                }
+            }
+            "--fanout" => {
+                options.fanout = parse_number(flag, value)?;
+                if ![64, 256, 1024].contains(&options.fanout) {
+                    return Err(format!("不支持的LEAF_FANOUT: {}", value));
+                }
+            }
+            "--seed" => options.seed = parse_number(flag, value)?,
+            "--path" => options.path = Some(PathBuf::from(value)),
+            "--output" => {
+                options.output = match value {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "csv" => OutputFormat::Csv,
+                    _ => return Err(format!("未知的输出格式: {}", value)),
+                }
+            }
+            "--compare" => options.compare = Some(PathBuf::from(value)),
+            "--threshold" => options.threshold_percent = parse_number(flag, value)?,
+            _ => return Err(format!("未知的选项: {}", flag)),
+        }
+    }
+
+    if options.keys == 0 || options.threads == 0 {
+        return Err("--keys 和 --threads 必须大于0".to_string());
+    }
+
+    Ok(Some(options))
+}
+
+/// 每个2的幂区间分为32个子桶，相对误差不超过约3%
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS as usize) + 1) * SUB_BUCKETS as usize;
+
+/// HDR风格的固定桶延迟直方图，以纳秒记录
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram { counts: vec![0; BUCKETS], count: 0, max: 0 }
+    }
+}
+
+impl Histogram {
+    fn bucket(value: u64) -> usize {
+        if value < SUB_BUCKETS {
+            return value as usize;
+        }
+        let magnitude = 63 - value.leading_zeros();
+        let shift = magnitude - SUB_BUCKET_BITS;
+        let sub_bucket = (value >> shift) & (SUB_BUCKETS - 1);
+        ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+    }
+
+    /// 桶中的最大值
+    fn bucket_upper_bound(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket;
+        }
+        let shift = bucket / SUB_BUCKETS - 1;
+        let sub_bucket = bucket % SUB_BUCKETS;
+        let lower = (SUB_BUCKETS + sub_bucket) << shift;
+        lower + ((1 << shift) - 1)
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[Self::bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// 百分位数 (0-100) 对应的延迟，单位纳秒
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bucket_upper_bound(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// 一种操作的统计结果，延迟单位为微秒
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationReport {
+    pub name: String,
+    pub count: u64,
+    pub throughput_ops_per_sec: f64,
+    pub p50_us: f64,
+    pub p95_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+impl OperationReport {
+    fn new(name: &str, histogram: &Histogram, elapsed: Duration) -> OperationReport {
+        let us = |nanos: u64| nanos as f64 / 1000.0;
+        OperationReport {
+            name: name.to_string(),
+            count: histogram.count(),
+            throughput_ops_per_sec: histogram.count() as f64 / elapsed.as_secs_f64(),
+            p50_us: us(histogram.percentile(50.0)),
+            p95_us: us(histogram.percentile(95.0)),
+            p99_us: us(histogram.percentile(99.0)),
+            max_us: us(histogram.max),
+        }
+    }
+}
+
+/// 一次基准测试的参数和结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub workload: String,
+    pub keys: u64,
+    pub value_size: usize,
+    pub threads: usize,
+    pub distribution: String,
+    pub compression: String,
+    pub fanout: usize,
+    pub seed: u64,
+    pub duration_secs: f64,
+    /// 运行阶段所有操作的吞吐量，不包括加载阶段
+    pub throughput_ops_per_sec: f64,
+    /// 加载阶段的插入，以及运行阶段的读取和更新
+    pub operations: Vec<OperationReport>,
+}
+
+impl Report {
+    /// 描述工作负载的参数，只有参数相同的结果才可以比较
+    fn parameters(&self) -> (&str, u64, usize, usize, &str, &str, usize, u64) {
+        (
+            &self.workload,
+            self.keys,
+            self.value_size,
+            self.threads,
+            &self.distribution,
+            &self.compression,
+            self.fanout,
+            self.seed,
+        )
+    }
+}
+
+fn key(index: u64) -> [u8; 8] {
+    index.to_be_bytes()
+}
+
+/// 把zipf分布的排名打散到整个键空间，使热点键不集中在相邻的叶子节点中
+fn scramble(rank: u64, keys: u64) -> u64 {
+    let mut x = rank.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x ^= x >> 29;
+    x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x ^= x >> 32;
+    x % keys
+}
+
+struct KeyChooser {
+    keys: u64,
+    zipf: Option<Zipf<f64>>,
+}
+
+impl KeyChooser {
+    fn new(options: &Options) -> KeyChooser {
+        let zipf = match options.distribution {
+            // YCSB默认的zipf常数
+            KeyDistribution::Zipfian => Some(Zipf::new(options.keys as f64, 0.99).unwrap()),
+            KeyDistribution::Uniform => None,
+        };
+        KeyChooser { keys: options.keys, zipf }
+    }
+
+    fn next(&self, rng: &mut StdRng) -> u64 {
+        match &self.zipf {
+            Some(zipf) => scramble(zipf.sample(rng) as u64 - 1, self.keys),
+            None => rng.random_range(0..self.keys),
+        }
+    }
+}
+
+fn thread_seed(seed: u64, thread: usize) -> u64 {
+    seed ^ (thread as u64 + 1).wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn random_value(rng: &mut StdRng, value_size: usize) -> Vec<u8> {
+    // 一半随机字节，一半重复字节，使压缩算法有意义
+    let mut value = vec![b'v'; value_size];
+    rng.fill(&mut value[..value_size / 2]);
+    value
+}
+
+#[derive(Default)]
+struct ThreadResult {
+    reads: Histogram,
+    updates: Histogram,
+}
+
+fn load<const LEAF_FANOUT: usize>(db: &Db<LEAF_FANOUT>, options: &Options) -> io::Result<(Histogram, Duration)> {
+    let start = Instant::now();
+    let histograms = thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.threads)
+            .map(|thread| {
+                // 每个线程使用自己的句柄，句柄中的epoch状态不能跨线程共享
+                let db = db.clone();
+                scope.spawn(move || -> io::Result<Histogram> {
+                    let mut rng = StdRng::seed_from_u64(thread_seed(options.seed, thread));
+                    let mut histogram = Histogram::default();
+                    let mut index = thread as u64;
+                    while index < options.keys {
+                        let value = random_value(&mut rng, options.value_size);
+                        let before = Instant::now();
+                        db.insert(key(index), value)?;
+                        histogram.record(before.elapsed());
+                        index += options.threads as u64;
+                    }
+                    Ok(histogram)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    let elapsed = start.elapsed();
+
+    let mut total = Histogram::default();
+    for histogram in &histograms {
+        total.merge(histogram);
+    }
+    Ok((total, elapsed))
+}
+
+fn run_phase<const LEAF_FANOUT: usize>(db: &Db<LEAF_FANOUT>, options: &Options) -> io::Result<(ThreadResult, Duration)> {
+    let chooser = KeyChooser::new(options);
+    let read_fraction = options.workload.read_fraction();
+
+    let start = Instant::now();
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.threads)
+            .map(|thread| {
+                let chooser = &chooser;
+                let db = db.clone();
+                scope.spawn(move || -> io::Result<ThreadResult> {
+                    let mut rng = StdRng::seed_from_u64(thread_seed(!options.seed, thread));
+                    let mut result = ThreadResult::default();
+                    while start.elapsed() < options.duration {
+                        let index = chooser.next(&mut rng);
+                        if rng.random::<f64>() < read_fraction {
+                            let before = Instant::now();
+                            let value = db.get(key(index))?;
+                            result.reads.record(before.elapsed());
+                            assert!(value.is_some(), "键 {} 不存在", index);
+                        } else {
+                            let value = random_value(&mut rng, options.value_size);
+                            let before = Instant::now();
+                            db.insert(key(index), value)?;
+                            result.updates.record(before.elapsed());
+                        }
+                    }
+                    Ok(result)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    let elapsed = start.elapsed();
+
+    let mut total = ThreadResult::default();
+    for result in &results {
+        total.reads.merge(&result.reads);
+        total.updates.merge(&result.updates);
+    }
+    Ok((total, elapsed))
+}
+
+fn run_with_fanout<const LEAF_FANOUT: usize>(options: &Options) -> io::Result<Report> {
+    let config = match &options.path {
+        Some(path) => Config::new().path(path),
+        None => Config::tmp()?,
+    };
+    let db: Db<LEAF_FANOUT> = config.compression_algorithm(options.compression).open()?;
+
+    let (inserts, load_elapsed) = load(&db, options)?;
+    let (result, elapsed) = run_phase(&db, options)?;
+
+    let mut operations = vec![OperationReport::new("insert", &inserts, load_elapsed)];
+    for (name, histogram) in [("read", &result.reads), ("update", &result.updates)] {
+        if histogram.count() > 0 {
+            operations.push(OperationReport::new(name, histogram, elapsed));
+        }
+    }
+    let total_ops = result.reads.count() + result.updates.count();
+
+    Ok(Report {
+        workload: options.workload.name().to_string(),
+        keys: options.keys,
+        value_size: options.value_size,
+        threads: options.threads,
+        distribution: format!("{:?}", options.distribution).to_lowercase(),
+        compression: format!("{:?}", options.compression).to_lowercase(),
+        fanout: LEAF_FANOUT,
+        seed: options.seed,
+        duration_secs: elapsed.as_secs_f64(),
+        throughput_ops_per_sec: total_ops as f64 / elapsed.as_secs_f64(),
+        operations,
+    })
+}
+
+/// 加载数据并运行工作负载
+pub fn run(options: &Options) -> io::Result<Report> {
+    match options.fanout {
+        64 => run_with_fanout::<64>(options),
+        256 => run_with_fanout::<256>(options),
+        1024 => run_with_fanout::<1024>(options),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("不支持的LEAF_FANOUT: {}", other),
+        )),
+    }
+}
+
+/// 按输出格式渲染结果
+pub fn render(report: &Report, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Json => serde_json::to_string_pretty(report).unwrap(),
+        OutputFormat::Csv => {
+            let mut csv = String::from("operation,count,throughput_ops_per_sec,p50_us,p95_us,p99_us,max_us\n");
+            for op in &report.operations {
+                csv.push_str(&format!(
+                    "{},{},{:.1},{:.3},{:.3},{:.3},{:.3}\n",
+                    op.name, op.count, op.throughput_ops_per_sec, op.p50_us, op.p95_us, op.p99_us, op.max_us
+                ));
+            }
+            csv
+        }
+        OutputFormat::Text => {
+            let mut text = format!(
+                "工作负载 {} | {} 个键 | 值 {} 字节 | {} 线程 | {} 分布 | 压缩 {} | LEAF_FANOUT {} | 种子 {}\n\
+                 运行 {:.2} 秒，吞吐量 {:.0} ops/s\n",
+                report.workload,
+                report.keys,
+                report.value_size,
+                report.threads,
+                report.distribution,
+                report.compression,
+                report.fanout,
+                report.seed,
+                report.duration_secs,
+                report.throughput_ops_per_sec,
+            );
+            for op in &report.operations {
+                text.push_str(&format!(
+                    "  {:<7} {:>10} 次 {:>12.0} ops/s  p50 {:>9.2}µs  p95 {:>9.2}µs  p99 {:>9.2}µs  max {:>10.2}µs\n",
+                    op.name, op.count, op.throughput_ops_per_sec, op.p50_us, op.p95_us, op.p99_us, op.max_us
+                ));
+            }
+            text
+        }
+    }
+}
+
+/// 与基线比较，返回吞吐量下降超过 `threshold_percent` 的项目。参数不同的结果不可比较
+pub fn compare(report: &Report, baseline: &Report, threshold_percent: f64) -> Result<Vec<String>, String> {
+    if report.parameters() != baseline.parameters() {
+        return Err(format!(
+            "基线的参数与本次运行不同: {:?} != {:?}",
+            baseline.parameters(),
+            report.parameters()
+        ));
+    }
+
+    let floor = 1.0 - threshold_percent / 100.0;
+    let mut regressions = vec![];
+    let mut check = |name: &str, current: f64, baseline: f64| {
+        if current < baseline * floor {
+            regressions.push(format!(
+                "{}: {:.0} ops/s，基线 {:.0} ops/s ({:+.1}%)",
+                name,
+                current,
+                baseline,
+                (current / baseline - 1.0) * 100.0
+            ));
+        }
+    };
+
+    check("total", report.throughput_ops_per_sec, baseline.throughput_ops_per_sec);
+    for op in &report.operations {
+        if let Some(base) = baseline.operations.iter().find(|base| base.name == op.name) {
+            check(&op.name, op.throughput_ops_per_sec, base.throughput_ops_per_sec);
+        }
+    }
+
+    Ok(regressions)
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let baseline = options.compare.as_ref().map(|path| {
+        let json = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("无法读取基线 {:?}: {}", path, e);
+            process::exit(2);
+        });
+        serde_json::from_str::<Report>(&json).unwrap_or_else(|e| {
+            eprintln!("无法解析基线 {:?}: {}", path, e);
+            process::exit(2);
+        })
+    });
+
+    let report = run(&options).unwrap_or_else(|e| {
+        eprintln!("基准测试失败: {}", e);
+        process::exit(2);
+    });
+    print!("{}", render(&report, options.output));
+    if options.output == OutputFormat::Json {
+        println!();
+    }
+
+    if let Some(baseline) = baseline {
+        match compare(&report, &baseline, options.threshold_percent) {
+            Ok(regressions) if regressions.is_empty() => {
+                eprintln!("吞吐量没有超过 {}% 的下降", options.threshold_percent);
+            }
+            Ok(regressions) => {
+                eprintln!("吞吐量下降超过 {}%:", options.threshold_percent);
+                for regression in regressions {
+                    eprintln!("  {}", regression);
+                }
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! 离线分析一个树的叶子节点布局：叶子节点数、大小、压缩率和填充率的分布，
+//! 用于选择 `LEAF_FANOUT` 和压缩配置
+//!
+//! 用法：cargo run --example layout_report -- <数据库路径> [树的名称]
+//!
+//! 不指定树的名称时分析默认树。数据库必须以下面的 `LEAF_FANOUT` 创建
+
+use std::process::ExitCode;
+
+use melange_db::{Config, Db, LeafFanoutMismatch, LeafInfo, Tree};
+
+const LEAF_FANOUT: usize = 1024;
+const BUCKETS: usize = 10;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("用法: layout_report <数据库路径> [树的名称]");
+        return ExitCode::from(2);
+    };
+    let name = args.next();
+
+    let db: Db<LEAF_FANOUT> = match Config::new().path(&path).flush_every_ms(None).open() {
+        Ok(db) => db,
+        Err(e) => {
+            match LeafFanoutMismatch::from_io_error(&e) {
+                Some(mismatch) => eprintln!(
+                    "数据库以 LEAF_FANOUT = {} 创建，请修改本示例中的 LEAF_FANOUT",
+                    mismatch.created_with
+                ),
+                None => eprintln!("无法打开 {}: {}", path, e),
+            }
+            return ExitCode::from(2);
+        }
+    };
+
+    let layout = match &name {
+        Some(name) => db.leaf_layout(name).map(|leaves| leaves.collect::<Vec<LeafInfo>>()),
+        None => Tree::leaf_layout(&db).map(|leaves| leaves.collect()),
+    };
+    let leaves = match layout {
+        Ok(leaves) => leaves,
+        Err(e) => {
+            eprintln!("无法读取叶子节点: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let entries: usize = leaves.iter().map(|leaf| leaf.entry_count).sum();
+    let in_cache = leaves.iter().filter(|leaf| leaf.in_cache).count();
+    let flushed: Vec<&LeafInfo> = leaves.iter().filter(|leaf| leaf.serialized_bytes.is_some()).collect();
+    let serialized_bytes: usize = flushed.iter().filter_map(|leaf| leaf.serialized_bytes).sum();
+    let uncompressed_bytes: usize = flushed.iter().map(|leaf| leaf.uncompressed_bytes).sum();
+
+    println!("树: {}", name.as_deref().unwrap_or("<默认>"));
+    println!("叶子节点: {} 个（{} 个在缓存中，{} 个已写入磁盘）", leaves.len(), in_cache, flushed.len());
+    println!("条目: {} 个，平均每个叶子节点 {:.1} 个", entries, entries as f64 / leaves.len().max(1) as f64);
+    if serialized_bytes > 0 {
+        println!(
+            "已写入磁盘的叶子节点: {} 字节，未压缩 {} 字节，压缩率 {:.2}",
+            serialized_bytes,
+            uncompressed_bytes,
+            uncompressed_bytes as f64 / serialized_bytes as f64
+        );
+    }
+
+    // 填充率：条目数除以 LEAF_FANOUT，按10%分组
+    let mut histogram = [0_usize; BUCKETS];
+    for leaf in &leaves {
+        let bucket = leaf.entry_count * BUCKETS / LEAF_FANOUT;
+        histogram[bucket.min(BUCKETS - 1)] += 1;
+    }
+    let widest = histogram.iter().copied().max().unwrap_or(0).max(1);
+
+    println!("填充率分布:");
+    for (bucket, count) in histogram.iter().enumerate() {
+        let bar = "#".repeat(count * 50 / widest);
+        println!(
+            "  {:>3}%-{:>3}% {:>8} {}",
+            bucket * 100 / BUCKETS,
+            (bucket + 1) * 100 / BUCKETS,
+            count,
+            bar
+        );
+    }
+
+    ExitCode::SUCCESS
+}
@@ -52,6 +52,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             max_interval_ms: 300,     // 300ms最大间隔
             write_rate_threshold: 18000, // 18K ops/sec阈值，M1+LZ4优势
             accumulated_bytes_threshold: 6 * 1024 * 1024, // 6MB累积字节
+            target_write_amplification: None,
         };
 
         // 清理旧的测试数据库
@@ -52,6 +52,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             max_interval_ms: 200,     // 200ms最大间隔，平衡性能
             write_rate_threshold: 20000, // 20K ops/sec阈值，充分利用M1性能
             accumulated_bytes_threshold: 4 * 1024 * 1024, // 4MB累积字节，更小flush单位
+            target_write_amplification: None,
         };
 
         // 清理旧的测试数据库
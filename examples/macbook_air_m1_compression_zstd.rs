@@ -52,6 +52,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             max_interval_ms: 400,     // 400ms最大间隔
             write_rate_threshold: 15000, // 15K ops/sec阈值，Zstd压缩限制
             accumulated_bytes_threshold: 8 * 1024 * 1024, // 8MB累积字节
+            target_write_amplification: None,
         };
 
         // 清理旧的测试数据库
@@ -56,6 +56,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             max_interval_ms: 500,      // 500ms最大间隔，平衡延迟
             write_rate_threshold: 8000, // 8K ops/sec阈值，适合高端设备
             accumulated_bytes_threshold: 8 * 1024 * 1024, // 8MB累积字节，平衡flush频率
+            target_write_amplification: None,
         };
 
         // 清理旧的测试数据库
@@ -0,0 +1,51 @@
+//! 离线校验 `Db::export_to` 写入的导出文件
+//!
+//! 用法：cargo run --example verify_backup -- <导出文件路径>
+
+use std::fs::File;
+use std::process::ExitCode;
+
+use melange_db::export;
+
+fn main() -> ExitCode {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("用法: verify_backup <导出文件路径>");
+        return ExitCode::from(2);
+    };
+
+    let report = match File::open(&path).and_then(export::verify) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("无法校验 {}: {}", path, e);
+            return ExitCode::from(2);
+        }
+    };
+
+    println!("文件: {}", path);
+    println!("哈希算法: {:?}", report.hash_algorithm);
+    println!("已校验: {} 条记录, {} 字节", report.records, report.bytes_read);
+    for collection in &report.collections {
+        println!(
+            "  {} {}: {} 个键值对",
+            String::from_utf8_lossy(&collection.collection_type),
+            String::from_utf8_lossy(&collection.name),
+            collection.entries
+        );
+    }
+    if let Some(offset) = report.first_corrupt_offset {
+        println!("❌ 第一个损坏的记录位于偏移 {}", offset);
+    }
+    if let Some(offset) = report.truncated_at {
+        println!("❌ 文件在偏移 {} 处的记录中途结束", offset);
+    }
+
+    if report.is_valid() {
+        println!("✅ 导出文件完整");
+        ExitCode::SUCCESS
+    } else {
+        if report.first_corrupt_offset.is_none() && report.truncated_at.is_none() {
+            println!("❌ 文件尾的记录数或哈希值与内容不一致");
+        }
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,231 @@
+//! TinyLFU风格的缓存准入过滤
+//!
+//! 用一个count-min频率草图估计每个叶子节点最近的访问频率。与 `CacheAdvisor`
+//! 一样，缓存按对象ID的最低字节分为256个分片。一个未缓存的叶子节点所在分片的
+//! 主段已满时，只有在它的估计频率超过该分片中访问最少的叶子节点时才被准入，
+//! 否则调用方直接从磁盘读取而不缓存它。
+//!
+//! 草图的计数器上限为15，每记录 `10 * 宽度` 次访问后所有计数器减半，
+//! 使频率反映最近的访问而不是全部历史。
+
+use fnv::FnvHashMap;
+
+/// 每个键在草图中占用的计数器行数
+const ROWS: usize = 4;
+/// 计数器的上限
+const MAX_COUNT: u8 = 15;
+/// 草图的最小和最大宽度（每行的计数器数量）
+const MIN_WIDTH: usize = 1 << 10;
+const MAX_WIDTH: usize = 1 << 20;
+/// 估计的叶子节点平均占用，用于根据缓存容量选择草图宽度
+const ESTIMATED_LEAF_BYTES: usize = 4 * 1024;
+/// 缓存的分片数，与 `CacheAdvisor` 相同
+const SHARDS: usize = 256;
+
+/// count-min频率草图，带周期性的减半衰减
+#[derive(Debug)]
+pub(crate) struct FrequencySketch {
+    counters: Vec<u8>,
+    width_mask: u64,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    /// 创建一个适合容纳约 `expected_items` 个不同键的草图
+    pub(crate) fn new(expected_items: usize) -> FrequencySketch {
+        let width = expected_items.clamp(MIN_WIDTH, MAX_WIDTH).next_power_of_two();
+        FrequencySketch {
+            counters: vec![0; ROWS * width],
+            width_mask: width as u64 - 1,
+            additions: 0,
+            sample_size: 10 * width,
+        }
+    }
+
+    fn index(&self, id: u64, row: usize) -> usize {
+        // splitmix64，每行使用不同的种子
+        let mut x = id ^ (row as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+        row * (self.width_mask as usize + 1) + (x & self.width_mask) as usize
+    }
+
+    /// 记录一次访问
+    pub(crate) fn increment(&mut self, id: u64) {
+        // 只增加最小的计数器（conservative update），减少哈希冲突带来的高估
+        let estimate = self.estimate(id);
+        if estimate < MAX_COUNT {
+            for row in 0..ROWS {
+                let index = self.index(id, row);
+                if self.counters[index] == estimate {
+                    self.counters[index] += 1;
+                }
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.halve();
+        }
+    }
+
+    /// 估计的最近访问次数
+    pub(crate) fn estimate(&self, id: u64) -> u8 {
+        (0..ROWS)
+            .map(|row| self.counters[self.index(id, row)])
+            .min()
+            .unwrap()
+    }
+
+    fn halve(&mut self) {
+        for counter in &mut self.counters {
+            *counter >>= 1;
+        }
+        self.additions /= 2;
+    }
+}
+
+/// 一个缓存分片中的叶子节点及其大小
+#[derive(Debug, Default)]
+struct Shard {
+    bytes: usize,
+    resident: FnvHashMap<u64, usize>,
+}
+
+/// TinyLFU准入过滤器的状态
+#[derive(Debug)]
+pub(crate) struct TinyLfu {
+    sketch: FrequencySketch,
+    shard_capacity: usize,
+    shards: Vec<Shard>,
+}
+
+impl TinyLfu {
+    /// `entry_cache_percent` 与 `CacheAdvisor` 的相同，分片的其余部分为主段
+    pub(crate) fn new(cache_capacity_bytes: usize, entry_cache_percent: u8) -> TinyLfu {
+        let main_percent = 100 - entry_cache_percent.min(100) as usize;
+        TinyLfu {
+            sketch: FrequencySketch::new(cache_capacity_bytes / ESTIMATED_LEAF_BYTES),
+            shard_capacity: cache_capacity_bytes / SHARDS * main_percent / 100,
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+        }
+    }
+
+    /// 改变每个分片主段的容量，草图保持原有的宽度和计数
+    pub(crate) fn resize(&mut self, cache_capacity_bytes: usize, entry_cache_percent: u8) {
+        let main_percent = 100 - entry_cache_percent.min(100) as usize;
+        self.shard_capacity = cache_capacity_bytes / SHARDS * main_percent / 100;
+    }
+
+    fn shard(id: u64) -> usize {
+        id.to_le_bytes()[0] as usize % SHARDS
+    }
+
+    /// 记录一次缓存中的叶子节点的访问
+    pub(crate) fn record_access(&mut self, id: u64, size: usize) {
+        self.sketch.increment(id);
+
+        let shard = &mut self.shards[Self::shard(id)];
+        let previous = shard.resident.insert(id, size).unwrap_or(0);
+        shard.bytes = shard.bytes - previous + size;
+    }
+
+    /// 记录一个被淘汰的叶子节点
+    pub(crate) fn record_eviction(&mut self, id: u64) {
+        let shard = &mut self.shards[Self::shard(id)];
+        if let Some(size) = shard.resident.remove(&id) {
+            shard.bytes -= size;
+        }
+    }
+
+    /// 决定是否把一个未缓存的叶子节点载入缓存。被拒绝的请求同样计入频率，
+    /// 被准入的请求在之后的访问中计入
+    pub(crate) fn admit(&mut self, id: u64) -> bool {
+        let shard = &self.shards[Self::shard(id)];
+
+        // 分片的主段还能容纳一个平均大小的叶子节点
+        let average_size = shard.bytes / shard.resident.len().max(1);
+        if shard.resident.contains_key(&id)
+            || shard.bytes + average_size <= self.shard_capacity
+        {
+            return true;
+        }
+
+        let estimate = self.sketch.estimate(id);
+        let victim = shard
+            .resident
+            .keys()
+            .map(|resident| self.sketch.estimate(*resident))
+            .min()
+            .unwrap();
+        if estimate > victim {
+            return true;
+        }
+
+        self.sketch.increment(id);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_estimates_and_saturates() {
+        let mut sketch = FrequencySketch::new(MIN_WIDTH);
+        for _ in 0..5 {
+            sketch.increment(42);
+        }
+        assert_eq!(sketch.estimate(42), 5);
+        assert_eq!(sketch.estimate(43), 0);
+
+        for _ in 0..100 {
+            sketch.increment(42);
+        }
+        assert_eq!(sketch.estimate(42), MAX_COUNT);
+    }
+
+    #[test]
+    fn test_sketch_ages_old_accesses() {
+        let mut sketch = FrequencySketch::new(MIN_WIDTH);
+        for _ in 0..8 {
+            sketch.increment(1);
+        }
+
+        // 大量其他访问触发减半
+        for id in 0..sketch.sample_size as u64 {
+            sketch.increment(1_000_000 + id);
+        }
+        assert!(sketch.estimate(1) <= 4, "{}", sketch.estimate(1));
+    }
+
+    #[test]
+    fn test_admission_compares_with_shard() {
+        // 每个分片的主段容纳两个100字节的叶子节点
+        let mut filter = TinyLfu::new(SHARDS * 250, 20);
+
+        // 分片未满时准入所有叶子节点
+        assert!(filter.admit(1));
+        filter.record_access(1, 100);
+        assert!(filter.admit(1 + SHARDS as u64));
+        for _ in 0..10 {
+            filter.record_access(1 + SHARDS as u64, 100);
+        }
+
+        // 其他分片不受影响
+        assert!(filter.admit(2));
+
+        // 分片已满，只访问过一次的叶子节点不能替换访问更多的叶子节点
+        let candidate = 1 + 2 * SHARDS as u64;
+        assert!(!filter.admit(candidate));
+        assert!(!filter.admit(candidate));
+        assert!(filter.admit(candidate));
+
+        // 被淘汰的叶子节点腾出空间
+        filter.record_eviction(1);
+        assert!(filter.admit(1 + 3 * SHARDS as u64));
+    }
+}
@@ -131,3 +131,33 @@ use self::testing_allocator::CountAllocator;
 static GLOBAL_ALLOCATOR: CountAllocator = CountAllocator::new();
 
 
+/// 自上次 `reset` 以来分配的字节数
+#[cfg(all(feature = "testing-count-allocator", not(any(feature = "mimalloc", feature = "testing-shred-allocator"))))]
+pub fn allocated() -> usize {
+    GLOBAL_ALLOCATOR.get_stats().0
+}
+
+/// 自上次 `reset` 以来释放的字节数
+#[cfg(all(feature = "testing-count-allocator", not(any(feature = "mimalloc", feature = "testing-shred-allocator"))))]
+pub fn freed() -> usize {
+    GLOBAL_ALLOCATOR.get_stats().1
+}
+
+/// 自上次 `reset` 以来分配但尚未释放的字节数
+#[cfg(all(feature = "testing-count-allocator", not(any(feature = "mimalloc", feature = "testing-shred-allocator"))))]
+pub fn resident() -> usize {
+    let (allocated, freed, _) = GLOBAL_ALLOCATOR.get_stats();
+    allocated.saturating_sub(freed)
+}
+
+/// 自上次 `reset` 以来的分配次数
+#[cfg(all(feature = "testing-count-allocator", not(any(feature = "mimalloc", feature = "testing-shred-allocator"))))]
+pub fn allocations() -> usize {
+    GLOBAL_ALLOCATOR.get_stats().2
+}
+
+/// 将所有计数清零
+#[cfg(all(feature = "testing-count-allocator", not(any(feature = "mimalloc", feature = "testing-shred-allocator"))))]
+pub fn reset() {
+    GLOBAL_ALLOCATOR.reset_stats();
+}
@@ -2,19 +2,236 @@
 //!
 //! 使用SegQueue + Worker线程实现高性能原子计数器操作
 //! 避免直接并发操作持久化层，提高并发性能
+//!
+//! 设置了常驻计数器上限后，内存中的计数器超过上限时，最久未访问的计数器在
+//! 持久化最新的值之后被移出内存，下次访问时再从磁盘加载
+//!
+//! `AtomicWorkerConfig::max_counters` 限制计数器的数量，防止大量不同的计数器
+//! 名称无限制地占用内存和磁盘，见 `CounterEviction`
 
 use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crossbeam_queue::SegQueue;
 use dashmap::DashMap;
-use parking_lot::Mutex;
+use parking_lot::RwLock;
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{debug_log, trace_log, warn_log, error_log, Tree};
+use super::database_worker::{
+    counter_key, decode_counter, encode_counter_value, CounterKind, CounterTypeMismatch,
+    CounterValue, DatabaseOperation, PauseLock, COUNTER_KEY_PREFIX,
+};
+
+/// 按比例缩放计数器时结果的舍入方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// 向下舍入（截断）
+    #[default]
+    Down,
+    /// 向上舍入
+    Up,
+    /// 四舍六入，恰好为 .5 时舍入到偶数（银行家舍入）
+    HalfEven,
+}
+
+/// 原子操作Worker的配置，通过 `Config::atomic_worker` 设置，
+/// 在数据库上创建第一个 `HybridOperationsManager` 时生效
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AtomicWorkerConfig {
+    /// 计数器数量的上限，超过时按 `eviction` 处理。默认为 `None`，即不限制。
+    /// 设置上限后，不在内存中的计数器总是先从磁盘加载
+    pub max_counters: Option<usize>,
+    /// 达到 `max_counters` 时的处理方式
+    pub eviction: CounterEviction,
+    /// 计数器名称的哈希算法
+    pub key_hashing: CounterKeyHashing,
+    /// 内存中的计数器映射的分片数，必须是大于1的2的幂。
+    /// 默认为 `None`，即CPU核心数的4倍向上取整到2的幂
+    pub shard_amount: Option<usize>,
+    /// 预计的计数器数量，内存中的计数器映射预先分配这么多的容量，在创建这些
+    /// 计数器的过程中不需要扩容和重新哈希。默认为 `None`，即从空映射开始增长
+    pub expected_counters: Option<usize>,
+}
+
+impl AtomicWorkerConfig {
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.max_counters == Some(0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "max_counters 必须是正数"));
+        }
+        if let Some(shard_amount) = self.shard_amount
+            && !(shard_amount > 1 && shard_amount.is_power_of_two())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("shard_amount 必须是大于1的2的幂，实际为 {}", shard_amount),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 计数器数量达到 `AtomicWorkerConfig::max_counters` 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CounterEviction {
+    /// 上限是计数器的总数，包括内存中的和只在磁盘上的。达到上限后创建新的
+    /// 计数器的操作返回 `CounterLimitExceeded`，已有的计数器不受影响
+    #[default]
+    RejectNew,
+    /// 上限是内存中的计数器数量。达到上限后，空闲了至少 `idle_for` 的计数器在
+    /// 持久化最新的值之后被移出内存，下次访问时从磁盘重新加载；没有空闲足够久
+    /// 的计数器时，需要新加载或创建计数器的操作返回 `CounterLimitExceeded`。
+    /// 磁盘上的计数器数量不受限制
+    EvictIdlePersisted {
+        /// 计数器最后一次被访问之后至少经过这么久才可以被移出
+        idle_for: Duration,
+    },
+}
+
+/// 计数器名称的哈希算法，决定计数器在内存映射的分片中的分布
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CounterKeyHashing {
+    /// 带随机种子的SipHash（标准库的默认算法），可以抵抗刻意构造的冲突名称
+    #[default]
+    SipHash,
+    /// 带随机种子的XXH3，长名称上更快
+    XxHash3,
+    /// 不带种子的FNV-1a，短名称上最快，只适合名称不受外部控制的场景
+    Fnv,
+}
+
+/// 创建新的计数器会超过 `AtomicWorkerConfig::max_counters` 时返回的错误。
+///
+/// 以 `io::ErrorKind::QuotaExceeded` 的 `io::Error` 的形式返回，
+/// 可以通过 `CounterLimitExceeded::from_io_error` 取出。操作没有被执行
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CounterLimitExceeded {
+    pub counter_name: String,
+    pub max_counters: usize,
+}
+
+impl CounterLimitExceeded {
+    /// 如果 `error` 是由计数器数量达到上限引起的，返回对应的 `CounterLimitExceeded`
+    pub fn from_io_error(error: &io::Error) -> Option<&CounterLimitExceeded> {
+        error.get_ref()?.downcast_ref::<CounterLimitExceeded>()
+    }
+}
+
+impl fmt::Display for CounterLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "counter {:?} would exceed the limit of {} counters",
+            self.counter_name, self.max_counters
+        )
+    }
+}
+
+impl std::error::Error for CounterLimitExceeded {}
+
+impl From<CounterLimitExceeded> for io::Error {
+    fn from(error: CounterLimitExceeded) -> io::Error {
+        io::Error::new(io::ErrorKind::QuotaExceeded, error)
+    }
+}
+
+/// 原子计数器的统计，见 `Stats::counters`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CounterStats {
+    /// 计数器的总数：内存中的，加上只在磁盘上的
+    pub cardinality: usize,
+    /// 内存中的计数器数量
+    pub resident: usize,
+    /// 内存中的计数器映射不扩容时能容纳的计数器数量，随映射扩容而增长
+    pub capacity: usize,
+}
+
+/// 内存中计数器映射的哈希器，按 `CounterKeyHashing` 选择算法
+#[derive(Clone)]
+enum CounterHasher {
+    Sip(RandomState),
+    XxHash3(u64),
+    Fnv,
+}
+
+impl CounterHasher {
+    fn new(key_hashing: CounterKeyHashing) -> CounterHasher {
+        match key_hashing {
+            CounterKeyHashing::SipHash => CounterHasher::Sip(RandomState::new()),
+            CounterKeyHashing::XxHash3 => {
+                CounterHasher::XxHash3(RandomState::new().build_hasher().finish())
+            }
+            CounterKeyHashing::Fnv => CounterHasher::Fnv,
+        }
+    }
+}
 
-use crate::{debug_log, trace_log, warn_log, error_log, info_log};
-use super::database_worker::DatabaseOperation;
+impl BuildHasher for CounterHasher {
+    type Hasher = CounterKeyHasher;
+
+    fn build_hasher(&self) -> CounterKeyHasher {
+        match self {
+            CounterHasher::Sip(state) => CounterKeyHasher::Sip(state.build_hasher()),
+            CounterHasher::XxHash3(seed) => {
+                CounterKeyHasher::XxHash3(Box::new(twox_hash::XxHash3_64::with_seed(*seed)))
+            }
+            CounterHasher::Fnv => CounterKeyHasher::Fnv(fnv::FnvHasher::default()),
+        }
+    }
+}
+
+enum CounterKeyHasher {
+    Sip(DefaultHasher),
+    XxHash3(Box<twox_hash::XxHash3_64>),
+    Fnv(fnv::FnvHasher),
+}
+
+impl Hasher for CounterKeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            CounterKeyHasher::Sip(hasher) => hasher.write(bytes),
+            CounterKeyHasher::XxHash3(hasher) => hasher.write(bytes),
+            CounterKeyHasher::Fnv(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            CounterKeyHasher::Sip(hasher) => hasher.finish(),
+            CounterKeyHasher::XxHash3(hasher) => hasher.finish(),
+            CounterKeyHasher::Fnv(hasher) => hasher.finish(),
+        }
+    }
+}
+
+type CounterMap = DashMap<String, Arc<PortableAtomicU64>, CounterHasher>;
+
+/// 计算 `value * numerator / denominator` 并按 `rounding` 舍入。
+/// 中间结果使用u128，不会溢出；结果超过 `u64::MAX` 时返回 `None`
+fn scale_value(value: u64, numerator: u64, denominator: u64, rounding: RoundingMode) -> Option<u64> {
+    let product = u128::from(value) * u128::from(numerator);
+    let denominator = u128::from(denominator);
+    let quotient = product / denominator;
+    let remainder = product % denominator;
+
+    let round_up = match rounding {
+        RoundingMode::Down => false,
+        RoundingMode::Up => remainder > 0,
+        RoundingMode::HalfEven => match (2 * remainder).cmp(&denominator) {
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => quotient % 2 == 1,
+        },
+    };
+
+    u64::try_from(quotient + u128::from(round_up)).ok()
+}
 
 /// 原子操作类型
 #[derive(Debug, Clone)]
@@ -25,6 +242,13 @@ pub(crate) enum AtomicOperation {
         delta: u64,
         response_tx: std::sync::mpsc::Sender<io::Result<u64>>,
     },
+    /// 有上限的原子递增，递增后会超过 `max` 时拒绝并返回 `None`
+    IncrementBounded {
+        counter_name: String,
+        delta: u64,
+        max: u64,
+        response_tx: std::sync::mpsc::Sender<io::Result<Option<u64>>>,
+    },
     /// 原子递减
     Decrement {
         counter_name: String,
@@ -37,16 +261,12 @@ pub(crate) enum AtomicOperation {
         factor: u64,
         response_tx: std::sync::mpsc::Sender<io::Result<u64>>,
     },
-    /// 原子除法
-    Divide {
-        counter_name: String,
-        divisor: u64,
-        response_tx: std::sync::mpsc::Sender<io::Result<u64>>,
-    },
-    /// 原子百分比计算
-    Percentage {
+    /// 原子按比例缩放：`value * numerator / denominator`，除法和百分比都是它的特例
+    Scale {
         counter_name: String,
-        percentage: u64, // 0-100的百分比值
+        numerator: u64,
+        denominator: u64,
+        rounding: RoundingMode,
         response_tx: std::sync::mpsc::Sender<io::Result<u64>>,
     },
     /// 原子比较和交换
@@ -56,6 +276,12 @@ pub(crate) enum AtomicOperation {
         new_value: u64,
         response_tx: std::sync::mpsc::Sender<io::Result<bool>>,
     },
+    /// 多个计数器的原子比较和交换：`(counter_name, expected, new_value)`
+    /// 全部匹配时才全部写入
+    MultiCompareAndSwap {
+        ops: Vec<(String, u64, u64)>,
+        response_tx: std::sync::mpsc::Sender<io::Result<bool>>,
+    },
     /// 获取计数器值
     Get {
         counter_name: String,
@@ -67,6 +293,225 @@ pub(crate) enum AtomicOperation {
         new_value: u64,
         response_tx: std::sync::mpsc::Sender<io::Result<()>>,
     },
+    /// 浮点计数器的原子累加
+    AddF64 {
+        counter_name: String,
+        delta: f64,
+        response_tx: std::sync::mpsc::Sender<io::Result<f64>>,
+    },
+    /// 获取浮点计数器的值
+    GetF64 {
+        counter_name: String,
+        response_tx: std::sync::mpsc::Sender<io::Result<Option<f64>>>,
+    },
+    /// 重置浮点计数器
+    ResetF64 {
+        counter_name: String,
+        new_value: f64,
+        response_tx: std::sync::mpsc::Sender<io::Result<()>>,
+    },
+}
+
+impl AtomicOperation {
+    fn counter_name(&self) -> &str {
+        match self {
+            AtomicOperation::Increment { counter_name, .. }
+            | AtomicOperation::IncrementBounded { counter_name, .. }
+            | AtomicOperation::Decrement { counter_name, .. }
+            | AtomicOperation::Multiply { counter_name, .. }
+            | AtomicOperation::Scale { counter_name, .. }
+            | AtomicOperation::CompareAndSwap { counter_name, .. }
+            | AtomicOperation::Get { counter_name, .. }
+            | AtomicOperation::Reset { counter_name, .. }
+            | AtomicOperation::AddF64 { counter_name, .. }
+            | AtomicOperation::GetF64 { counter_name, .. }
+            | AtomicOperation::ResetF64 { counter_name, .. } => counter_name,
+            // Worker线程单独处理，这里只用于日志
+            AtomicOperation::MultiCompareAndSwap { ops, .. } => ops.first().map_or("", |(name, _, _)| name),
+        }
+    }
+
+    /// 操作所要求的计数器类型
+    fn kind(&self) -> CounterKind {
+        match self {
+            AtomicOperation::AddF64 { .. }
+            | AtomicOperation::GetF64 { .. }
+            | AtomicOperation::ResetF64 { .. } => CounterKind::F64,
+            _ => CounterKind::U64,
+        }
+    }
+
+    /// 计数器不存在时操作是否会创建它
+    fn creates_counter(&self) -> bool {
+        !matches!(self, AtomicOperation::Get { .. } | AtomicOperation::GetF64 { .. })
+    }
+
+    /// 不执行操作，直接向调用方返回错误
+    fn fail(self, error: io::Error) {
+        match self {
+            AtomicOperation::Increment { response_tx, .. }
+            | AtomicOperation::Decrement { response_tx, .. }
+            | AtomicOperation::Multiply { response_tx, .. }
+            | AtomicOperation::Scale { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+            AtomicOperation::IncrementBounded { response_tx, .. }
+            | AtomicOperation::Get { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+            AtomicOperation::CompareAndSwap { response_tx, .. }
+            | AtomicOperation::MultiCompareAndSwap { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+            AtomicOperation::Reset { response_tx, .. }
+            | AtomicOperation::ResetF64 { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+            AtomicOperation::AddF64 { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+            AtomicOperation::GetF64 { response_tx, .. } => {
+                let _ = response_tx.send(Err(error));
+            }
+        }
+    }
+}
+
+/// 内存中的计数器：u64计数器，以及以位模式存储的f64计数器。
+/// 一个名称最多出现在其中一个映射中
+#[derive(Clone)]
+struct Counters {
+    ints: Arc<CounterMap>,
+    floats: Arc<CounterMap>,
+}
+
+impl Counters {
+    fn new(config: &AtomicWorkerConfig) -> Counters {
+        let hasher = CounterHasher::new(config.key_hashing);
+        let map = |capacity| match config.shard_amount {
+            Some(shard_amount) => Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                capacity,
+                hasher.clone(),
+                shard_amount,
+            )),
+            None => Arc::new(DashMap::with_capacity_and_hasher(capacity, hasher.clone())),
+        };
+        // f64计数器很少见，只为u64计数器预先分配
+        Counters { ints: map(config.expected_counters.unwrap_or(0)), floats: map(0) }
+    }
+
+    fn len(&self) -> usize {
+        self.ints.len() + self.floats.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.ints.capacity() + self.floats.capacity()
+    }
+
+    /// 映射的槽位、计数器名称和计数器值占用的内存（字节），不含哈希表的控制字节
+    fn memory_bytes(&self) -> usize {
+        let slot = std::mem::size_of::<(String, Arc<PortableAtomicU64>)>();
+        // Arc的分配包含强弱引用计数
+        let value = std::mem::size_of::<PortableAtomicU64>() + 2 * std::mem::size_of::<usize>();
+        let entries: usize = self
+            .ints
+            .iter()
+            .chain(self.floats.iter())
+            .map(|entry| entry.key().capacity() + value)
+            .sum();
+        self.capacity() * slot + entries
+    }
+
+    fn contains_key(&self, counter_name: &str) -> bool {
+        self.ints.contains_key(counter_name) || self.floats.contains_key(counter_name)
+    }
+
+    fn kind(&self, counter_name: &str) -> Option<CounterKind> {
+        if self.ints.contains_key(counter_name) {
+            Some(CounterKind::U64)
+        } else if self.floats.contains_key(counter_name) {
+            Some(CounterKind::F64)
+        } else {
+            None
+        }
+    }
+
+    fn load(&self, counter_name: &str) -> Option<CounterValue> {
+        if let Some(counter) = self.ints.get(counter_name) {
+            return Some(CounterValue::U64(counter.load(Ordering::SeqCst)));
+        }
+        let counter = self.floats.get(counter_name)?;
+        Some(CounterValue::F64(f64::from_bits(counter.load(Ordering::SeqCst))))
+    }
+
+    /// 加载持久化的值，内存中已有的计数器保持不变。返回是否加载了
+    fn insert_if_absent(&self, counter_name: String, value: CounterValue) -> bool {
+        if self.contains_key(&counter_name) {
+            return false;
+        }
+        match value {
+            CounterValue::U64(value) => {
+                self.ints.insert(counter_name, Arc::new(PortableAtomicU64::new(value)));
+            }
+            CounterValue::F64(value) => {
+                self.floats.insert(counter_name, Arc::new(PortableAtomicU64::new(value.to_bits())));
+            }
+        }
+        true
+    }
+
+    fn remove(&self, counter_name: &str) {
+        self.ints.remove(counter_name);
+        self.floats.remove(counter_name);
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.ints
+            .iter()
+            .chain(self.floats.iter())
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+/// 浮点计数器的结果为无穷大时饱和到最大的有限值
+fn saturate_f64(counter_name: &str, value: f64) -> f64 {
+    if value.is_infinite() {
+        warn_log!("浮点计数器 {} 溢出，饱和到 {}", counter_name, f64::MAX.copysign(value));
+        f64::MAX.copysign(value)
+    } else {
+        value
+    }
+}
+
+fn reject_nan(value: f64) -> io::Result<()> {
+    if value.is_nan() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "浮点计数器的值不能为NaN"));
+    }
+    Ok(())
+}
+
+/// 常驻计数器的访问顺序和计数器数量的上限，只由Worker线程使用
+struct Residency {
+    /// 用于持久化被移出的计数器和重新加载它们
+    tree: Tree<1024>,
+    /// 每个常驻计数器最后一次被访问的时刻，没有记录的计数器视为最久未访问
+    last_access: HashMap<String, Access>,
+    clock: u64,
+    config: AtomicWorkerConfig,
+    /// 只在磁盘上、不在内存中的计数器数量，与 `AtomicWorker` 共享
+    persisted_only: Arc<AtomicUsize>,
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    tick: u64,
+    at: Instant,
+}
+
+/// 一个只在磁盘上的计数器被加载到了内存中
+fn loaded_from_disk(persisted_only: &AtomicUsize) {
+    let _ = persisted_only.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
 }
 
 /// 原子操作Worker
@@ -75,7 +520,7 @@ pub(crate) enum AtomicOperation {
 /// 内部实现细节，不应直接对外暴露
 pub(crate) struct AtomicWorker {
     /// 内存中的原子计数器 (使用DashMap提供高性能并发访问)
-    counters: Arc<DashMap<String, Arc<AtomicU64>>>,
+    counters: Counters,
 
     /// 操作队列 (无锁并发队列)
     operation_queue: Arc<SegQueue<AtomicOperation>>,
@@ -86,8 +531,19 @@ pub(crate) struct AtomicWorker {
     /// 关闭信号
     shutdown_tx: Option<std::sync::mpsc::Sender<()>>,
 
-    /// 数据库Worker操作队列引用 (用于发送持久化指令)
-    db_queue: Option<Arc<SegQueue<DatabaseOperation>>>,
+    /// 数据库Worker操作队列引用 (用于发送持久化指令)，
+    /// 共享同一个Worker的管理器切换数据库Worker模式时更新
+    db_queue: Arc<RwLock<Option<Arc<SegQueue<DatabaseOperation>>>>>,
+
+    /// 内存中常驻计数器的上限，`None` 表示不限制
+    max_resident: Arc<RwLock<Option<usize>>>,
+
+    /// 只在磁盘上、不在内存中的计数器数量
+    persisted_only: Arc<AtomicUsize>,
+
+    /// 见 `PauseLock`
+    #[cfg(feature = "for-internal-testing-only")]
+    pause: PauseLock,
 }
 
 impl AtomicWorker {
@@ -95,20 +551,63 @@ impl AtomicWorker {
     ///
     /// # Arguments
     /// * `db_queue` - 数据库Worker操作队列引用，用于发送持久化指令
-    pub(crate) fn new(db_queue: Option<Arc<SegQueue<DatabaseOperation>>>) -> Self {
-        let counters = Arc::new(DashMap::new());
+    /// * `tree` - 存储计数器的树，用于持久化被移出内存的计数器和重新加载它们
+    /// * `config` - 计数器数量的上限和内存映射的哈希算法
+    pub(crate) fn new(
+        db_queue: Option<Arc<SegQueue<DatabaseOperation>>>,
+        tree: Tree<1024>,
+        config: AtomicWorkerConfig,
+    ) -> Self {
+        let counters = Counters::new(&config);
         let operation_queue = Arc::new(SegQueue::new());
         let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
 
+        let db_queue = Arc::new(RwLock::new(db_queue));
+        let max_resident = Arc::new(RwLock::new(None));
+        let pause = PauseLock::default();
+
+        // 内存中还没有计数器，磁盘上的都只在磁盘上
+        let mut persisted = 0;
+        for key_res in tree.scan_prefix(COUNTER_KEY_PREFIX).keys() {
+            match key_res {
+                Ok(_) => persisted += 1,
+                Err(e) => {
+                    error_log!("统计持久化的计数器失败: {:?}", e);
+                    break;
+                }
+            }
+        }
+        let persisted_only = Arc::new(AtomicUsize::new(persisted));
+
         let worker_counters = counters.clone();
         let worker_queue = operation_queue.clone();
         let worker_db_queue = db_queue.clone();
+        let worker_max_resident = max_resident.clone();
+        let worker_pause = pause.clone();
+        let residency = Residency {
+            tree,
+            last_access: HashMap::new(),
+            clock: 0,
+            config,
+            persisted_only: persisted_only.clone(),
+        };
 
-        let worker_handle = thread::spawn(move || {
-            debug_log!("原子操作Worker线程启动");
-            Self::worker_loop(worker_counters, worker_queue, worker_db_queue, shutdown_rx);
-            debug_log!("原子操作Worker线程退出");
-        });
+        let worker_handle = thread::Builder::new()
+            .name("melange-atomic-worker".into())
+            .spawn(move || {
+                debug_log!("原子操作Worker线程启动");
+                Self::worker_loop(
+                    worker_counters,
+                    worker_queue,
+                    worker_db_queue,
+                    worker_max_resident,
+                    worker_pause,
+                    residency,
+                    shutdown_rx,
+                );
+                debug_log!("原子操作Worker线程退出");
+            })
+            .expect("无法创建原子操作Worker线程");
 
         Self {
             counters,
@@ -116,14 +615,21 @@ impl AtomicWorker {
             worker_handle: Some(worker_handle),
             shutdown_tx: Some(shutdown_tx),
             db_queue,
+            max_resident,
+            persisted_only,
+            #[cfg(feature = "for-internal-testing-only")]
+            pause,
         }
     }
 
     /// Worker主循环
     fn worker_loop(
-        counters: Arc<DashMap<String, Arc<AtomicU64>>>,
+        counters: Counters,
         operation_queue: Arc<SegQueue<AtomicOperation>>,
-        db_queue: Option<Arc<SegQueue<DatabaseOperation>>>,
+        db_queue: Arc<RwLock<Option<Arc<SegQueue<DatabaseOperation>>>>>,
+        max_resident: Arc<RwLock<Option<usize>>>,
+        pause: PauseLock,
+        mut residency: Residency,
         shutdown_rx: std::sync::mpsc::Receiver<()>,
     ) {
         // 智能休眠参数
@@ -148,7 +654,29 @@ impl AtomicWorker {
 
             // 处理操作队列
             if let Some(operation) = operation_queue.pop() {
-                Self::handle_operation(&counters, operation, &db_queue);
+                let _pause = pause.hold();
+                let current_db_queue = db_queue.read().clone();
+                let current_max_resident = *max_resident.read();
+                if let AtomicOperation::MultiCompareAndSwap { ops, response_tx } = operation {
+                    let result = Self::handle_multi_compare_and_swap(
+                        &counters,
+                        ops,
+                        &current_db_queue,
+                        current_max_resident,
+                        &mut residency,
+                    );
+                    let _ = response_tx.send(result);
+                } else if current_max_resident.is_some() || residency.config.max_counters.is_some() {
+                    Self::handle_operation_with_residency(
+                        &counters,
+                        operation,
+                        &current_db_queue,
+                        current_max_resident,
+                        &mut residency,
+                    );
+                } else {
+                    Self::handle_operation_unbounded(&counters, operation, &current_db_queue, &residency);
+                }
                 // 有操作时重置空闲计数和休眠时间
                 idle_count = 0;
                 current_sleep_us = BASE_SLEEP_US;
@@ -171,17 +699,264 @@ impl AtomicWorker {
         }
     }
 
+    /// 处理单个原子操作，不限制计数器的数量。不在内存中的计数器不从磁盘加载，
+    /// 但它被创建时如果已经持久化在磁盘上，不再计入只在磁盘上的计数器
+    fn handle_operation_unbounded(
+        counters: &Counters,
+        operation: AtomicOperation,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        residency: &Residency,
+    ) {
+        // 在操作发出持久化指令之前检查磁盘
+        let on_disk = (residency.persisted_only.load(Ordering::Acquire) > 0
+            && !counters.contains_key(operation.counter_name()))
+        .then(|| operation.counter_name().to_string())
+        .filter(|counter_name| {
+            residency.tree.contains_key(counter_key(counter_name)).unwrap_or(false)
+        });
+
+        Self::handle_operation(counters, operation, db_queue);
+
+        if let Some(counter_name) = on_disk
+            && counters.contains_key(&counter_name)
+        {
+            loaded_from_disk(&residency.persisted_only);
+        }
+    }
+
+    /// 处理单个原子操作，并把内存中的计数器限制在 `max_resident` 个之内，
+    /// 计数器的数量限制在 `AtomicWorkerConfig::max_counters` 之内。
+    /// 不在内存中的计数器先从磁盘加载，加载失败或超过上限时不执行操作并返回错误
+    fn handle_operation_with_residency(
+        counters: &Counters,
+        operation: AtomicOperation,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        max_resident: Option<usize>,
+        residency: &mut Residency,
+    ) {
+        let counter_name = operation.counter_name().to_string();
+
+        // 在响应调用方之前腾出空间，操作可能创建一个新的计数器
+        let creates = operation.creates_counter();
+        if let Err(e) = Self::make_resident(counters, db_queue, max_resident, &counter_name, creates, &[&counter_name], residency) {
+            operation.fail(e);
+            return;
+        }
+
+        Self::handle_operation(counters, operation, db_queue);
+
+        if !counters.contains_key(&counter_name) {
+            residency.last_access.remove(&counter_name);
+        }
+    }
+
+    /// 把不在内存中的计数器 `counter_name` 从磁盘加载到内存中，记录对它的访问，
+    /// 并在超过 `max_resident` 时移出 `keep` 之外最久未访问的计数器。
+    /// `creates` 表示操作会创建不存在的计数器
+    fn make_resident(
+        counters: &Counters,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        max_resident: Option<usize>,
+        counter_name: &str,
+        creates: bool,
+        keep: &[&str],
+        residency: &mut Residency,
+    ) -> io::Result<()> {
+        if !counters.contains_key(counter_name) {
+            let persisted = match residency.tree.get(counter_key(counter_name)) {
+                Ok(persisted) => persisted.and_then(|bytes| decode_counter(&bytes)),
+                Err(e) => {
+                    error_log!("从磁盘加载计数器 {} 失败: {:?}", counter_name, e);
+                    return Err(e);
+                }
+            };
+
+            if (persisted.is_some() || creates)
+                && let Err(e) = Self::admit_counter(counters, db_queue, counter_name, persisted.is_some(), keep, residency)
+            {
+                debug_log!("计数器数量达到上限，拒绝计数器: {}", counter_name);
+                return Err(e);
+            }
+
+            if let Some(value) = persisted {
+                trace_log!("从磁盘重新加载计数器: {} = {}", counter_name, value);
+                if counters.insert_if_absent(counter_name.to_string(), value) {
+                    loaded_from_disk(&residency.persisted_only);
+                }
+            }
+        }
+
+        residency.clock += 1;
+        let access = Access { tick: residency.clock, at: Instant::now() };
+        residency.last_access.insert(counter_name.to_string(), access);
+        let resident = counters.len() + usize::from(!counters.contains_key(counter_name));
+        if let Some(max_resident) = max_resident
+            && resident > max_resident
+        {
+            Self::evict_cold_counters(counters, db_queue, max_resident, None, keep, residency);
+        }
+        Ok(())
+    }
+
+    /// 检查不在内存中的计数器 `counter_name` 被加载（`persisted` 为真）或创建后
+    /// 是否仍在 `AtomicWorkerConfig::max_counters` 之内。按
+    /// `CounterEviction::EvictIdlePersisted` 处理时，先移出 `keep` 之外空闲足够久的计数器
+    fn admit_counter(
+        counters: &Counters,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        counter_name: &str,
+        persisted: bool,
+        keep: &[&str],
+        residency: &mut Residency,
+    ) -> io::Result<()> {
+        let Some(max_counters) = residency.config.max_counters else {
+            return Ok(());
+        };
+
+        let admitted = match residency.config.eviction {
+            CounterEviction::RejectNew => {
+                persisted
+                    || counters.len() + residency.persisted_only.load(Ordering::Acquire) < max_counters
+            }
+            CounterEviction::EvictIdlePersisted { idle_for } => {
+                if counters.len() >= max_counters {
+                    Self::evict_cold_counters(counters, db_queue, max_counters, Some(idle_for), keep, residency);
+                }
+                counters.len() < max_counters
+            }
+        };
+
+        if admitted {
+            Ok(())
+        } else {
+            Err(CounterLimitExceeded { counter_name: counter_name.to_string(), max_counters }.into())
+        }
+    }
+
+    /// 把 `keep` 之外最久未访问的计数器移出内存，直到加上正在访问的计数器后大约剩下
+    /// `max_resident` 的7/8，使每次移出的代价分摊到多次操作上。设置了 `min_idle` 时
+    /// 只移出至少空闲了这么久的计数器。计数器在最新的值持久化之后才被移出
+    fn evict_cold_counters(
+        counters: &Counters,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        max_resident: usize,
+        min_idle: Option<Duration>,
+        keep: &[&str],
+        residency: &mut Residency,
+    ) {
+        let target = (max_resident - max_resident / 8).min(max_resident - 1);
+        let names: Vec<String> =
+            counters.names().into_iter().filter(|name| !keep.contains(&name.as_str())).collect();
+        let excess = names.len().saturating_sub(target);
+        if excess == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut candidates: Vec<(u64, String)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let access = residency.last_access.get(&name);
+                if let (Some(min_idle), Some(access)) = (min_idle, access)
+                    && now.duration_since(access.at) < min_idle
+                {
+                    return None;
+                }
+                Some((access.map_or(0, |access| access.tick), name))
+            })
+            .collect();
+        let evict_count = excess.min(candidates.len());
+        if evict_count == 0 {
+            return;
+        }
+        candidates.select_nth_unstable(evict_count - 1);
+        candidates.truncate(evict_count);
+
+        // 计数器只在Worker线程中修改，持久化期间内存中的值不会变化
+        let evicted: Vec<(String, CounterValue)> = candidates
+            .into_iter()
+            .filter_map(|(_, name)| {
+                let value = counters.load(&name)?;
+                Some((name, value))
+            })
+            .collect();
+
+        if let Err(e) = Self::persist_evicted(&evicted, db_queue, &residency.tree) {
+            error_log!("持久化被移出的计数器失败，保留在内存中: {:?}", e);
+            return;
+        }
+
+        for (name, _) in &evicted {
+            counters.remove(name);
+            residency.last_access.remove(name);
+        }
+        residency.persisted_only.fetch_add(evicted.len(), Ordering::AcqRel);
+        debug_log!("移出 {} 个最久未访问的计数器，内存中剩余 {} 个", evicted.len(), counters.len());
+    }
+
+    /// 持久化被移出的计数器。启用数据库Worker时通过它的队列持久化，
+    /// 等待最后一个持久化指令完成，保证之前发送的指令不会在之后覆盖这些值
+    fn persist_evicted(
+        evicted: &[(String, CounterValue)],
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        tree: &Tree<1024>,
+    ) -> io::Result<()> {
+        if let Some(db_queue) = db_queue {
+            let mut last_response = None;
+            for (name, value) in evicted {
+                let (response_tx, response_rx) = std::sync::mpsc::channel();
+                db_queue.push(DatabaseOperation::PersistCounter {
+                    counter_name: name.clone(),
+                    value: *value,
+                    response_tx,
+                });
+                last_response = Some(response_rx);
+            }
+
+            match last_response.map(|response_rx| response_rx.recv()) {
+                Some(Ok(result)) => return result.map(|_| ()),
+                // 数据库Worker已关闭，退出前会处理完已提交的指令，下面再直接写入一次
+                Some(Err(_)) => {
+                    warn_log!("数据库Worker连接断开，直接持久化被移出的计数器");
+                }
+                None => return Ok(()),
+            }
+        }
+
+        for (name, value) in evicted {
+            tree.insert(counter_key(name), &encode_counter_value(*value)[..])?;
+        }
+        Ok(())
+    }
+
     /// 处理单个原子操作
     fn handle_operation(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &Counters,
         operation: AtomicOperation,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
     ) {
+        // 只有Worker线程创建计数器，检查之后类型不会改变
+        let expected = operation.kind();
+        if let Some(found) = counters.kind(operation.counter_name())
+            && found != expected
+        {
+            let counter_name = operation.counter_name().to_string();
+            trace_log!("计数器类型不匹配: {} 是 {:?} 计数器", counter_name, found);
+            operation.fail(CounterTypeMismatch { counter_name, expected, found }.into());
+            return;
+        }
+
+        let floats = &*counters.floats;
+        let counters = &*counters.ints;
         match operation {
             AtomicOperation::Increment { counter_name, delta, response_tx } => {
                 let result = Self::handle_increment(counters, &counter_name, delta, db_queue);
                 let _ = response_tx.send(result);
             }
+            AtomicOperation::IncrementBounded { counter_name, delta, max, response_tx } => {
+                let result = Self::handle_increment_bounded(counters, &counter_name, delta, max, db_queue);
+                let _ = response_tx.send(result);
+            }
             AtomicOperation::Decrement { counter_name, delta, response_tx } => {
                 let result = Self::handle_decrement(counters, &counter_name, delta, db_queue);
                 let _ = response_tx.send(result);
@@ -190,18 +965,17 @@ impl AtomicWorker {
                 let result = Self::handle_multiply(counters, &counter_name, factor, db_queue);
                 let _ = response_tx.send(result);
             }
-            AtomicOperation::Divide { counter_name, divisor, response_tx } => {
-                let result = Self::handle_divide(counters, &counter_name, divisor, db_queue);
-                let _ = response_tx.send(result);
-            }
-            AtomicOperation::Percentage { counter_name, percentage, response_tx } => {
-                let result = Self::handle_percentage(counters, &counter_name, percentage, db_queue);
+            AtomicOperation::Scale { counter_name, numerator, denominator, rounding, response_tx } => {
+                let result = Self::handle_scale(counters, &counter_name, numerator, denominator, rounding, db_queue);
                 let _ = response_tx.send(result);
             }
             AtomicOperation::CompareAndSwap { counter_name, expected, new_value, response_tx } => {
                 let result = Self::handle_compare_and_swap(counters, &counter_name, expected, new_value, db_queue);
                 let _ = response_tx.send(result);
             }
+            AtomicOperation::MultiCompareAndSwap { .. } => {
+                unreachable!("多个计数器的比较和交换由Worker主循环处理")
+            }
             AtomicOperation::Get { counter_name, response_tx } => {
                 let result = Self::handle_get(counters, &counter_name);
                 let _ = response_tx.send(result);
@@ -210,12 +984,24 @@ impl AtomicWorker {
                 let result = Self::handle_reset(counters, &counter_name, new_value, db_queue);
                 let _ = response_tx.send(result);
             }
+            AtomicOperation::AddF64 { counter_name, delta, response_tx } => {
+                let result = Self::handle_add_f64(floats, &counter_name, delta, db_queue);
+                let _ = response_tx.send(result);
+            }
+            AtomicOperation::GetF64 { counter_name, response_tx } => {
+                let result = Self::handle_get_f64(floats, &counter_name);
+                let _ = response_tx.send(result);
+            }
+            AtomicOperation::ResetF64 { counter_name, new_value, response_tx } => {
+                let result = Self::handle_reset_f64(floats, &counter_name, new_value, db_queue);
+                let _ = response_tx.send(result);
+            }
         }
     }
 
     /// 处理原子递增操作
     fn handle_increment(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
         delta: u64,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
@@ -225,7 +1011,7 @@ impl AtomicWorker {
         // 获取或创建原子计数器
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         // 执行原子递增（纯内存操作）
@@ -235,20 +1021,64 @@ impl AtomicWorker {
         if let Some(db_queue) = db_queue {
             let persist_op = DatabaseOperation::PersistCounter {
                 counter_name: counter_name.to_string(),
-                value: new_value,
+                value: CounterValue::U64(new_value),
                 response_tx: std::sync::mpsc::channel().0, // 不需要响应，直接丢弃
             };
             db_queue.push(persist_op);
             trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
         }
 
-        trace_log!("原子递增完成: {} = {}", counter_name, new_value);
+        trace_log!(op = "increment", counter = counter_name, value = new_value; "原子递增完成: {} = {}", counter_name, new_value);
         Ok(new_value)
     }
 
+    /// 处理有上限的原子递增操作，只有值发生变化时才持久化
+    fn handle_increment_bounded(
+        counters: &CounterMap,
+        counter_name: &str,
+        delta: u64,
+        max: u64,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+    ) -> io::Result<Option<u64>> {
+        trace_log!("处理有上限的原子递增: {} + {} (max: {})", counter_name, delta, max);
+
+        let counter = counters
+            .entry(counter_name.to_string())
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
+            .clone();
+
+        // 递增后不超过上限时才写入
+        let result = counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+            current.checked_add(delta).filter(|new_value| *new_value <= max)
+        });
+
+        let new_value = match result {
+            Ok(previous) => previous + delta,
+            Err(current) => {
+                trace_log!(op = "increment_bounded", counter = counter_name, value = current, accepted = false; "有上限的原子递增被拒绝: {} = {} (max: {})", counter_name, current, max);
+                return Ok(None);
+            }
+        };
+
+        if delta != 0
+            && let Some(db_queue) = db_queue
+        {
+            let persist_op = DatabaseOperation::PersistCounter {
+                counter_name: counter_name.to_string(),
+                value: CounterValue::U64(new_value),
+                response_tx: std::sync::mpsc::channel().0,
+            };
+            db_queue.push(persist_op);
+            trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
+        }
+
+        trace_log!(op = "increment_bounded", counter = counter_name, value = new_value, accepted = true; "有上限的原子递增完成: {} = {}", counter_name, new_value);
+        Ok(Some(new_value))
+    }
+
     /// 处理获取计数器操作
     fn handle_get(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
     ) -> io::Result<Option<u64>> {
         trace_log!("处理获取计数器: {}", counter_name);
@@ -265,7 +1095,7 @@ impl AtomicWorker {
 
     /// 处理原子递减操作
     fn handle_decrement(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
         delta: u64,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
@@ -275,7 +1105,7 @@ impl AtomicWorker {
         // 获取或创建原子计数器
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         // 执行原子递减（防止下溢）
@@ -292,20 +1122,20 @@ impl AtomicWorker {
         if let Some(db_queue) = db_queue {
             let persist_op = DatabaseOperation::PersistCounter {
                 counter_name: counter_name.to_string(),
-                value: new_value,
+                value: CounterValue::U64(new_value),
                 response_tx: std::sync::mpsc::channel().0,
             };
             db_queue.push(persist_op);
             trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
         }
 
-        trace_log!("原子递减完成: {} = {}", counter_name, new_value);
+        trace_log!(op = "decrement", counter = counter_name, value = new_value; "原子递减完成: {} = {}", counter_name, new_value);
         Ok(new_value)
     }
 
     /// 处理原子乘法操作
     fn handle_multiply(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
         factor: u64,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
@@ -315,7 +1145,7 @@ impl AtomicWorker {
         // 检查乘法溢出
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         let current_value = counter.load(Ordering::SeqCst);
@@ -333,75 +1163,45 @@ impl AtomicWorker {
         if let Some(db_queue) = db_queue {
             let persist_op = DatabaseOperation::PersistCounter {
                 counter_name: counter_name.to_string(),
-                value: new_value,
+                value: CounterValue::U64(new_value),
                 response_tx: std::sync::mpsc::channel().0,
             };
             db_queue.push(persist_op);
             trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
         }
 
-        trace_log!("原子乘法完成: {} = {}", counter_name, new_value);
+        trace_log!(op = "multiply", counter = counter_name, value = new_value; "原子乘法完成: {} = {}", counter_name, new_value);
         Ok(new_value)
     }
 
-    /// 处理原子除法操作
-    fn handle_divide(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+    /// 处理原子按比例缩放操作
+    fn handle_scale(
+        counters: &CounterMap,
         counter_name: &str,
-        divisor: u64,
+        numerator: u64,
+        denominator: u64,
+        rounding: RoundingMode,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
     ) -> io::Result<u64> {
-        trace_log!("处理原子除法: {} / {}", counter_name, divisor);
+        trace_log!("处理原子缩放: {} * {} / {} ({:?})", counter_name, numerator, denominator, rounding);
 
-        if divisor == 0 {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "除数不能为零"));
+        if denominator == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "分母不能为零"));
         }
 
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         let current_value = counter.load(Ordering::SeqCst);
-        let new_value = current_value / divisor;
-
-        counter.store(new_value, Ordering::SeqCst);
-
-        // 立即向DatabaseWorker发送持久化指令
-        if let Some(db_queue) = db_queue {
-            let persist_op = DatabaseOperation::PersistCounter {
-                counter_name: counter_name.to_string(),
-                value: new_value,
-                response_tx: std::sync::mpsc::channel().0,
-            };
-            db_queue.push(persist_op);
-            trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
-        }
-
-        trace_log!("原子除法完成: {} = {}", counter_name, new_value);
-        Ok(new_value)
-    }
-
-    /// 处理原子百分比操作
-    fn handle_percentage(
-        counters: &DashMap<String, Arc<AtomicU64>>,
-        counter_name: &str,
-        percentage: u64,
-        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
-    ) -> io::Result<u64> {
-        trace_log!("处理原子百分比: {} * {}%", counter_name, percentage);
-
-        if percentage > 100 {
-            return Err(io::Error::new(io::ErrorKind::InvalidInput, "百分比值不能超过100"));
-        }
-
-        let counter = counters
-            .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
-            .clone();
-
-        let current_value = counter.load(Ordering::SeqCst);
-        let new_value = (current_value * percentage) / 100;
+        let new_value = match scale_value(current_value, numerator, denominator, rounding) {
+            Some(result) => result,
+            None => {
+                warn_log!("缩放溢出: {} * {} / {}, 设为u64::MAX", current_value, numerator, denominator);
+                u64::MAX
+            }
+        };
 
         counter.store(new_value, Ordering::SeqCst);
 
@@ -409,20 +1209,20 @@ impl AtomicWorker {
         if let Some(db_queue) = db_queue {
             let persist_op = DatabaseOperation::PersistCounter {
                 counter_name: counter_name.to_string(),
-                value: new_value,
+                value: CounterValue::U64(new_value),
                 response_tx: std::sync::mpsc::channel().0,
             };
             db_queue.push(persist_op);
             trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
         }
 
-        trace_log!("原子百分比完成: {} = {}", counter_name, new_value);
+        trace_log!(op = "scale", counter = counter_name, value = new_value; "原子缩放完成: {} = {}", counter_name, new_value);
         Ok(new_value)
     }
 
     /// 处理原子比较和交换操作
     fn handle_compare_and_swap(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
         expected: u64,
         new_value: u64,
@@ -432,7 +1232,7 @@ impl AtomicWorker {
 
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         // 使用原子比较和交换操作
@@ -448,23 +1248,108 @@ impl AtomicWorker {
             if let Some(db_queue) = db_queue {
                 let persist_op = DatabaseOperation::PersistCounter {
                     counter_name: counter_name.to_string(),
-                    value: new_value,
+                    value: CounterValue::U64(new_value),
                     response_tx: std::sync::mpsc::channel().0,
                 };
                 db_queue.push(persist_op);
                 trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
             }
-            trace_log!("原子比较和交换成功: {} = {}", counter_name, new_value);
+            trace_log!(op = "compare_and_swap", counter = counter_name, value = new_value, swapped = true; "原子比较和交换成功: {} = {}", counter_name, new_value);
         } else {
-            trace_log!("原子比较和交换失败: {} 值不匹配", counter_name);
+            trace_log!(op = "compare_and_swap", counter = counter_name, swapped = false; "原子比较和交换失败: {} 值不匹配", counter_name);
         }
 
         Ok(result)
     }
 
+    /// 处理多个计数器的原子比较和交换操作。先检查所有的期望值，全部匹配时才写入
+    /// 所有的新值，并为值发生变化的计数器发送持久化指令。不存在的计数器按0比较，
+    /// 与 `handle_compare_and_swap` 相同
+    fn handle_multi_compare_and_swap(
+        counters: &Counters,
+        ops: Vec<(String, u64, u64)>,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+        max_resident: Option<usize>,
+        residency: &mut Residency,
+    ) -> io::Result<bool> {
+        trace_log!("处理多个计数器的原子比较和交换: {} 个计数器", ops.len());
+
+        let names: Vec<&str> = ops.iter().map(|(name, _, _)| name.as_str()).collect();
+        if let Some(duplicate) = names.iter().enumerate().find_map(|(i, name)| names[..i].contains(name).then_some(name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("计数器 {} 在同一个比较和交换中出现多次", duplicate),
+            ));
+        }
+
+        // 检查之前加载所有的计数器，它们互相之间不会被移出
+        let bounded = max_resident.is_some() || residency.config.max_counters.is_some();
+        let mut on_disk = vec![];
+        for name in &names {
+            if bounded {
+                Self::make_resident(counters, db_queue, max_resident, name, true, &names, residency)?;
+            } else if residency.persisted_only.load(Ordering::Acquire) > 0
+                && !counters.contains_key(name)
+                && residency.tree.contains_key(counter_key(name)).unwrap_or(false)
+            {
+                on_disk.push(*name);
+            }
+
+            if let Some(found) = counters.kind(name)
+                && found != CounterKind::U64
+            {
+                let counter_name = name.to_string();
+                return Err(CounterTypeMismatch { counter_name, expected: CounterKind::U64, found }.into());
+            }
+        }
+
+        // 计数器只在Worker线程中修改，检查和写入之间不会变化
+        let ints = &*counters.ints;
+        let current = |name: &str| ints.get(name).map_or(0, |counter| counter.load(Ordering::SeqCst));
+        if let Some((name, expected, _)) = ops.iter().find(|(name, expected, _)| current(name) != *expected) {
+            trace_log!(op = "multi_compare_and_swap", counter = name.as_str(), swapped = false; "多个计数器的原子比较和交换失败: {} 不等于 {}", name, expected);
+            if bounded {
+                for name in &names {
+                    if !counters.contains_key(name) {
+                        residency.last_access.remove(*name);
+                    }
+                }
+            }
+            return Ok(false);
+        }
+
+        for (name, expected, new_value) in &ops {
+            let counter = ints
+                .entry(name.clone())
+                .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
+                .clone();
+            counter.store(*new_value, Ordering::SeqCst);
+
+            if new_value != expected
+                && let Some(db_queue) = db_queue
+            {
+                db_queue.push(DatabaseOperation::PersistCounter {
+                    counter_name: name.clone(),
+                    value: CounterValue::U64(*new_value),
+                    response_tx: std::sync::mpsc::channel().0,
+                });
+                trace_log!("已发送持久化指令: {} = {}", name, new_value);
+            }
+        }
+
+        for name in on_disk {
+            if counters.contains_key(name) {
+                loaded_from_disk(&residency.persisted_only);
+            }
+        }
+
+        trace_log!(op = "multi_compare_and_swap", swapped = true; "多个计数器的原子比较和交换成功: {} 个计数器", ops.len());
+        Ok(true)
+    }
+
     /// 处理重置计数器操作
     fn handle_reset(
-        counters: &DashMap<String, Arc<AtomicU64>>,
+        counters: &CounterMap,
         counter_name: &str,
         new_value: u64,
         db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
@@ -474,7 +1359,7 @@ impl AtomicWorker {
         // 更新内存中的原子计数器（纯内存操作）
         let counter = counters
             .entry(counter_name.to_string())
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0)))
             .clone();
 
         counter.store(new_value, Ordering::SeqCst);
@@ -483,14 +1368,95 @@ impl AtomicWorker {
         if let Some(db_queue) = db_queue {
             let persist_op = DatabaseOperation::PersistCounter {
                 counter_name: counter_name.to_string(),
-                value: new_value,
+                value: CounterValue::U64(new_value),
                 response_tx: std::sync::mpsc::channel().0, // 不需要响应，直接丢弃
             };
             db_queue.push(persist_op);
             trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
         }
 
-        trace_log!("重置计数器完成: {} = {}", counter_name, new_value);
+        trace_log!(op = "reset", counter = counter_name, value = new_value; "重置计数器完成: {} = {}", counter_name, new_value);
+        Ok(())
+    }
+
+    /// 处理浮点计数器的原子累加操作。NaN被拒绝，结果为无穷大时饱和到最大的有限值
+    fn handle_add_f64(
+        floats: &CounterMap,
+        counter_name: &str,
+        delta: f64,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+    ) -> io::Result<f64> {
+        trace_log!("处理浮点累加: {} + {}", counter_name, delta);
+        reject_nan(delta)?;
+
+        let counter = floats
+            .entry(counter_name.to_string())
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0.0_f64.to_bits())))
+            .clone();
+
+        // 以位模式存储的f64上的CAS循环
+        let mut sum = 0.0;
+        let _ = counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |bits| {
+            sum = f64::from_bits(bits) + delta;
+            Some(sum.clamp(f64::MIN, f64::MAX).to_bits())
+        });
+        let new_value = saturate_f64(counter_name, sum);
+
+        if let Some(db_queue) = db_queue {
+            let persist_op = DatabaseOperation::PersistCounter {
+                counter_name: counter_name.to_string(),
+                value: CounterValue::F64(new_value),
+                response_tx: std::sync::mpsc::channel().0,
+            };
+            db_queue.push(persist_op);
+            trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
+        }
+
+        trace_log!(op = "add_f64", counter = counter_name, value = new_value; "浮点累加完成: {} = {}", counter_name, new_value);
+        Ok(new_value)
+    }
+
+    /// 处理获取浮点计数器操作
+    fn handle_get_f64(
+        floats: &CounterMap,
+        counter_name: &str,
+    ) -> io::Result<Option<f64>> {
+        trace_log!("处理获取浮点计数器: {}", counter_name);
+
+        Ok(floats
+            .get(counter_name)
+            .map(|counter| f64::from_bits(counter.load(Ordering::SeqCst))))
+    }
+
+    /// 处理重置浮点计数器操作
+    fn handle_reset_f64(
+        floats: &CounterMap,
+        counter_name: &str,
+        new_value: f64,
+        db_queue: &Option<Arc<SegQueue<DatabaseOperation>>>,
+    ) -> io::Result<()> {
+        trace_log!("处理重置浮点计数器: {} = {}", counter_name, new_value);
+        reject_nan(new_value)?;
+        let new_value = saturate_f64(counter_name, new_value);
+
+        let counter = floats
+            .entry(counter_name.to_string())
+            .or_insert_with(|| Arc::new(PortableAtomicU64::new(0.0_f64.to_bits())))
+            .clone();
+
+        counter.store(new_value.to_bits(), Ordering::SeqCst);
+
+        if let Some(db_queue) = db_queue {
+            let persist_op = DatabaseOperation::PersistCounter {
+                counter_name: counter_name.to_string(),
+                value: CounterValue::F64(new_value),
+                response_tx: std::sync::mpsc::channel().0,
+            };
+            db_queue.push(persist_op);
+            trace_log!("已发送持久化指令: {} = {}", counter_name, new_value);
+        }
+
+        trace_log!(op = "reset_f64", counter = counter_name, value = new_value; "重置浮点计数器完成: {} = {}", counter_name, new_value);
         Ok(())
     }
 
@@ -514,6 +1480,15 @@ impl AtomicWorker {
 
     /// 提交获取计数器操作
     pub(crate) fn get(&self, counter_name: String) -> io::Result<Option<u64>> {
+        self.get_with_timeout(counter_name, None)
+    }
+
+    /// 提交获取计数器操作，最多等待 `timeout`，`None` 表示一直等待
+    pub(crate) fn get_with_timeout(
+        &self,
+        counter_name: String,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<u64>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = AtomicOperation::Get {
@@ -524,9 +1499,27 @@ impl AtomicWorker {
         self.operation_queue.push(operation);
 
         // 等待Worker处理结果
-        response_rx.recv().unwrap_or_else(|_| {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
-        })
+        match timeout {
+            None => response_rx.recv().unwrap_or_else(|_| {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+            }),
+            Some(timeout) => match response_rx.recv_timeout(timeout) {
+                Ok(result) => result,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("等待原子操作Worker超时（{:?}）", timeout),
+                )),
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                    Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+                }
+            },
+        }
+    }
+
+    /// 暂停Worker，直到返回的guard被释放。正在处理的操作会先完成（测试用）
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn pause(&self) -> parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()> {
+        self.pause.pause()
     }
 
     /// 提交原子递减操作
@@ -563,13 +1556,14 @@ impl AtomicWorker {
         })
     }
 
-    /// 提交原子除法操作
-    pub(crate) fn divide(&self, counter_name: String, divisor: u64) -> io::Result<u64> {
+    /// 提交有上限的原子递增操作，返回递增后的值，递增后会超过 `max` 时返回 `None`
+    pub(crate) fn increment_bounded(&self, counter_name: String, delta: u64, max: u64) -> io::Result<Option<u64>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
-        let operation = AtomicOperation::Divide {
+        let operation = AtomicOperation::IncrementBounded {
             counter_name,
-            divisor,
+            delta,
+            max,
             response_tx,
         };
 
@@ -580,13 +1574,21 @@ impl AtomicWorker {
         })
     }
 
-    /// 提交原子百分比操作
-    pub(crate) fn percentage(&self, counter_name: String, percentage: u64) -> io::Result<u64> {
+    /// 提交原子按比例缩放操作
+    pub(crate) fn scale(
+        &self,
+        counter_name: String,
+        numerator: u64,
+        denominator: u64,
+        rounding: RoundingMode,
+    ) -> io::Result<u64> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
-        let operation = AtomicOperation::Percentage {
+        let operation = AtomicOperation::Scale {
             counter_name,
-            percentage,
+            numerator,
+            denominator,
+            rounding,
             response_tx,
         };
 
@@ -615,6 +1617,17 @@ impl AtomicWorker {
         })
     }
 
+    /// 提交多个计数器的原子比较和交换操作
+    pub(crate) fn multi_compare_and_swap(&self, ops: Vec<(String, u64, u64)>) -> io::Result<bool> {
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+
+        self.operation_queue.push(AtomicOperation::MultiCompareAndSwap { ops, response_tx });
+
+        response_rx.recv().unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+        })
+    }
+
     /// 提交重置计数器操作
     pub(crate) fn reset(&self, counter_name: String, new_value: u64) -> io::Result<()> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
@@ -633,16 +1646,103 @@ impl AtomicWorker {
         })
     }
 
-    /// 加载单个计数器（供Manager调用）
+    /// 提交浮点计数器的原子累加操作
+    pub(crate) fn add_f64(&self, counter_name: String, delta: f64) -> io::Result<f64> {
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+
+        self.operation_queue.push(AtomicOperation::AddF64 {
+            counter_name,
+            delta,
+            response_tx,
+        });
+
+        response_rx.recv().unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+        })
+    }
+
+    /// 提交获取浮点计数器操作
+    pub(crate) fn get_f64(&self, counter_name: String) -> io::Result<Option<f64>> {
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+
+        self.operation_queue.push(AtomicOperation::GetF64 {
+            counter_name,
+            response_tx,
+        });
+
+        response_rx.recv().unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+        })
+    }
+
+    /// 提交重置浮点计数器操作
+    pub(crate) fn reset_f64(&self, counter_name: String, new_value: f64) -> io::Result<()> {
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+
+        self.operation_queue.push(AtomicOperation::ResetF64 {
+            counter_name,
+            new_value,
+            response_tx,
+        });
+
+        response_rx.recv().unwrap_or_else(|_| {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Worker连接断开"))
+        })
+    }
+
+    /// 加载单个计数器（供Manager调用）。内存中已有的计数器比持久化的值更新，
+    /// 保持不变，因此其他共享此Worker的管理器预热时不会用旧值覆盖它
     pub(crate) fn load_counter(&self, counter_name: String, value: u64) {
         trace_log!("加载计数器: {} = {}", counter_name, value);
-        let counter = Arc::new(AtomicU64::new(value));
-        self.counters.insert(counter_name, counter);
+        self.load_persisted(counter_name, CounterValue::U64(value));
+    }
+
+    /// 加载单个浮点计数器（供Manager调用），与 `load_counter` 相同
+    pub(crate) fn load_float_counter(&self, counter_name: String, value: f64) {
+        trace_log!("加载浮点计数器: {} = {}", counter_name, value);
+        self.load_persisted(counter_name, CounterValue::F64(value));
+    }
+
+    fn load_persisted(&self, counter_name: String, value: CounterValue) {
+        if self.counters.insert_if_absent(counter_name, value) {
+            loaded_from_disk(&self.persisted_only);
+        }
+    }
+
+    /// 设置发送持久化指令的数据库Worker操作队列
+    pub(crate) fn set_db_queue(&self, db_queue: Option<Arc<SegQueue<DatabaseOperation>>>) {
+        *self.db_queue.write() = db_queue;
+    }
+
+    /// 设置内存中常驻计数器的上限，`None` 表示不限制（默认）。
+    /// 在下一次原子操作时生效
+    pub(crate) fn set_max_resident_counters(&self, max_resident: Option<usize>) {
+        *self.max_resident.write() = max_resident.map(|max_resident| max_resident.max(1));
+    }
+
+    /// 当前内存中的计数器数量
+    pub(crate) fn resident_counter_count(&self) -> usize {
+        self.counters.len()
+    }
+
+    /// 计数器的总数和内存中的数量
+    pub(crate) fn counter_stats(&self) -> CounterStats {
+        let resident = self.counters.len();
+        CounterStats {
+            cardinality: resident + self.persisted_only.load(Ordering::Acquire),
+            resident,
+            capacity: self.counters.capacity(),
+        }
+    }
+
+    /// 内存中的计数器映射占用的内存（字节），见 `Db::memory_stats`
+    pub(crate) fn counter_memory_bytes(&self) -> usize {
+        self.counters.memory_bytes()
     }
 
     /// 获取所有计数器名称（供调试使用）
     pub(crate) fn get_counter_names(&self) -> Vec<String> {
-        self.counters.iter().map(|entry| entry.key().clone()).collect()
+        self.counters.names()
     }
 }
 
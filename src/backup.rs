@@ -0,0 +1,334 @@
+//! 全量和增量备份
+//!
+//! 一个备份是一个目录，包含两个文件：
+//!
+//! - `objects`：依次编码的叶子节点记录，每条记录包含叶子节点的对象ID和它的
+//!   所有键值对。全量备份包含所有叶子节点，增量备份只包含自上一个备份以来
+//!   被flush过或尚未flush的叶子节点
+//! - `MANIFEST`：备份的 `BackupEpoch`、它所基于的备份的 `BackupEpoch`、
+//!   `objects` 的记录数和校验和，以及每个树的名称和备份时存在的所有叶子节点的
+//!   对象ID。`MANIFEST` 在 `objects` 写完并同步后才写入，没有 `MANIFEST` 的
+//!   目录不是完整的备份
+//!
+//! 每次flush写入的对象ID记录在 `WriteTracker` 中，增量备份据此判断哪些叶子节点
+//! 自上一个备份以来被修改过。记录只保存在内存中，数据库重新打开后，基于之前的
+//! 进程创建的备份的增量备份会包含所有叶子节点。
+//!
+//! 恢复时按从新到旧的顺序读取备份，每个在最后一个备份中存在的叶子节点取它最后
+//! 一次出现时的内容，之后被删除的叶子节点不在最后一个备份的对象ID列表中，被忽略。
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use fault_injection::{annotate, fallible};
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+
+use crate::{FlushEpoch, ObjectId};
+
+const MANIFEST: &str = "MANIFEST";
+const OBJECTS: &str = "objects";
+const FORMAT_VERSION: u32 = 1;
+
+/// 一个备份对应的时刻，传给 `Db::backup_incremental` 以备份这之后的修改
+///
+/// 只在创建它的进程中有意义：数据库重新打开后，以之前的 `BackupEpoch`
+/// 创建的增量备份包含所有叶子节点。可以通过 `BackupEpoch::of_backup`
+/// 从已有的备份中读取。
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct BackupEpoch {
+    instance: u64,
+    flushed_epoch: u64,
+}
+
+impl BackupEpoch {
+    /// 读取一个备份目录对应的 `BackupEpoch`
+    pub fn of_backup<P: AsRef<Path>>(backup: P) -> io::Result<BackupEpoch> {
+        Ok(Manifest::read(backup.as_ref())?.epoch)
+    }
+}
+
+/// 记录当前进程中每个对象最后一次被flush写入的epoch
+#[derive(Debug)]
+pub(crate) struct WriteTracker {
+    instance: u64,
+    written: Mutex<FnvHashMap<ObjectId, u64>>,
+}
+
+impl Default for WriteTracker {
+    fn default() -> WriteTracker {
+        let nanos = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap_or_default().as_nanos();
+        WriteTracker {
+            instance: (nanos as u64) ^ (u64::from(std::process::id()) << 32),
+            written: Mutex::default(),
+        }
+    }
+}
+
+impl WriteTracker {
+    pub(crate) fn record(&self, object_ids: &[ObjectId], epoch: FlushEpoch) {
+        let mut written = self.written.lock();
+        for object_id in object_ids {
+            written.insert(*object_id, epoch.get());
+        }
+    }
+
+    pub(crate) fn epoch(&self, max_flushed_epoch: u64) -> BackupEpoch {
+        BackupEpoch { instance: self.instance, flushed_epoch: max_flushed_epoch }
+    }
+
+    /// 返回判断一个对象在 `since` 之后是否被写入过的函数，`since` 来自
+    /// 之前的进程时所有对象都视为被写入过
+    pub(crate) fn written_since(
+        &self,
+        since: Option<BackupEpoch>,
+        current: BackupEpoch,
+    ) -> io::Result<impl Fn(ObjectId) -> bool + '_> {
+        let after = match since {
+            Some(since) if since.instance == self.instance => {
+                if since.flushed_epoch > current.flushed_epoch {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{:?} is later than the current {:?}", since, current),
+                    ));
+                }
+                Some(since.flushed_epoch)
+            }
+            _ => None,
+        };
+        Ok(move |object_id| match after {
+            Some(after) => self.written.lock().get(&object_id).is_some_and(|epoch| *epoch > after),
+            None => true,
+        })
+    }
+}
+
+/// 备份中的一个树
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CollectionManifest {
+    /// 树的名称，默认树为 `None`
+    pub name: Option<Vec<u8>>,
+    /// 备份时树的所有叶子节点
+    pub live_objects: Vec<u64>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    format_version: u32,
+    epoch: BackupEpoch,
+    since: Option<BackupEpoch>,
+    records: u64,
+    crc: u32,
+    collections: Vec<CollectionManifest>,
+}
+
+impl Manifest {
+    fn read(backup: &Path) -> io::Result<Manifest> {
+        let bytes = fs::read(backup.join(MANIFEST)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{:?} is not a complete backup, failed to read its manifest: {}", backup, e),
+            )
+        })?;
+        let (manifest, _): (Manifest, usize) =
+            bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if manifest.format_version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("backup {:?} has unknown format version {}", backup, manifest.format_version),
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ObjectRecord {
+    object_id: u64,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// 向一个新的备份目录写入叶子节点记录
+pub(crate) struct BackupWriter {
+    directory: std::path::PathBuf,
+    objects: BufWriter<CrcWriter>,
+    records: u64,
+    collections: Vec<CollectionManifest>,
+}
+
+impl BackupWriter {
+    /// 创建备份目录，目录已存在时必须为空
+    pub(crate) fn create(directory: &Path) -> io::Result<BackupWriter> {
+        fallible!(fs::create_dir_all(directory));
+        if fallible!(fs::read_dir(directory)).next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("backup destination {:?} is not empty", directory),
+            ));
+        }
+
+        let file = fallible!(fs::File::create(directory.join(OBJECTS)));
+        Ok(BackupWriter {
+            directory: directory.to_path_buf(),
+            objects: BufWriter::new(CrcWriter {
+                file,
+                hasher: crc32fast::Hasher::new(),
+            }),
+            records: 0,
+            collections: vec![],
+        })
+    }
+
+    pub(crate) fn add_collection(&mut self, collection: CollectionManifest) {
+        self.collections.push(collection);
+    }
+
+    pub(crate) fn write_object(
+        &mut self,
+        object_id: ObjectId,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let record = ObjectRecord { object_id: object_id.0.get(), entries };
+        bincode::serde::encode_into_std_write(&record, &mut self.objects, bincode::config::standard())
+            .map_err(io::Error::other)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// 同步 `objects` 后写入 `MANIFEST`
+    pub(crate) fn finish(self, epoch: BackupEpoch, since: Option<BackupEpoch>) -> io::Result<()> {
+        let crc_writer = self.objects.into_inner().map_err(io::IntoInnerError::into_error)?;
+        fallible!(crc_writer.file.sync_all());
+
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            epoch,
+            since,
+            records: self.records,
+            crc: crc_writer.hasher.finalize(),
+            collections: self.collections,
+        };
+        let bytes = bincode::serde::encode_to_vec(&manifest, bincode::config::standard())
+            .map_err(io::Error::other)?;
+
+        let tmp_path = self.directory.join(format!("{MANIFEST}.tmp"));
+        let mut file = fallible!(fs::File::create(&tmp_path));
+        fallible!(file.write_all(&bytes));
+        fallible!(file.sync_all());
+        drop(file);
+        fallible!(fs::rename(&tmp_path, self.directory.join(MANIFEST)));
+        crate::platform_utils::sync_directory(&self.directory).map_err(|e| annotate!(e))
+    }
+}
+
+/// 一个全量备份和基于它的增量备份
+pub(crate) struct RestoreChain {
+    /// 按从新到旧排列的备份目录和它们的清单
+    backups: Vec<(std::path::PathBuf, Manifest)>,
+}
+
+impl RestoreChain {
+    /// 读取并检查备份链：`base` 必须是全量备份，每个增量备份必须基于前一个备份
+    pub(crate) fn open(base: &Path, deltas: &[&Path]) -> io::Result<RestoreChain> {
+        let mut backups = vec![];
+        let mut previous: Option<BackupEpoch> = None;
+        for (i, directory) in std::iter::once(base).chain(deltas.iter().copied()).enumerate() {
+            let manifest = Manifest::read(directory)?;
+            if manifest.since != previous {
+                let error = if i == 0 {
+                    format!("base backup {:?} is an incremental backup", directory)
+                } else {
+                    format!(
+                        "incremental backup {:?} is not based on the backup before it",
+                        directory
+                    )
+                };
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+            }
+            previous = Some(manifest.epoch);
+            backups.push((directory.to_path_buf(), manifest));
+        }
+        backups.reverse();
+        Ok(RestoreChain { backups })
+    }
+
+    /// 最后一个备份中的树
+    pub(crate) fn collections(&self) -> &[CollectionManifest] {
+        &self.backups[0].1.collections
+    }
+
+    /// 以树在 `collections` 中的序号和叶子节点的键值对调用 `f`，
+    /// 每个叶子节点只使用它最后一次出现时的内容
+    pub(crate) fn for_each_object(
+        &self,
+        mut f: impl FnMut(usize, Vec<(Vec<u8>, Vec<u8>)>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut remaining: FnvHashMap<u64, usize> = FnvHashMap::default();
+        for (index, collection) in self.collections().iter().enumerate() {
+            remaining.extend(collection.live_objects.iter().map(|object_id| (*object_id, index)));
+        }
+
+        for (directory, manifest) in &self.backups {
+            let file = fallible!(fs::File::open(directory.join(OBJECTS)));
+            let mut reader = CrcReader { inner: BufReader::new(file), hasher: crc32fast::Hasher::new() };
+            for _ in 0..manifest.records {
+                let record: ObjectRecord =
+                    bincode::serde::decode_from_std_read(&mut reader, bincode::config::standard())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if let Some(index) = remaining.remove(&record.object_id) {
+                    f(index, record.entries)?;
+                }
+            }
+            if reader.hasher.finalize() != manifest.crc {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checksum mismatch in the objects of backup {:?}", directory),
+                ));
+            }
+        }
+
+        if !remaining.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} leaves are missing from the backup chain", remaining.len()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 计算写入内容校验和的文件
+struct CrcWriter {
+    file: fs::File,
+    hasher: crc32fast::Hasher,
+}
+
+impl Write for CrcWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 计算读取内容校验和的读取器
+struct CrcReader<R> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
@@ -0,0 +1,367 @@
+//! 批量写入的溢出存储
+//!
+//! 当 `Batch` 在内存中累积的操作超过溢出阈值时，将当前的有序操作写入一个临时文件
+//! （一个"段"），然后清空内存。应用批量写入时，将所有段与内存中剩余的操作做
+//! 多路归并，得到与纯内存路径相同的按键排序、后写覆盖先写的操作流。
+//!
+//! 段文件使用简单的记录格式：
+//!
+//! ```text
+//! [key_len: u32 LE][key][present: u8][value_len: u32 LE][value]
+//! ```
+//!
+//! 删除操作的 `present` 为0且没有值部分。每个段记录所有记录的CRC32，
+//! 读到段末尾时校验，防止应用被截断或损坏的数据。
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::Arc;
+
+use crate::InlineArray;
+
+pub(crate) type SpillEntry = (InlineArray, Option<InlineArray>);
+
+type Source = Box<dyn Iterator<Item = io::Result<SpillEntry>>>;
+
+/// `Batch` 的溢出状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BatchSpill {
+    /// 内存中的操作超过该字节数后写入临时文件
+    pub(crate) threshold: usize,
+    /// 内存中操作的键和值的总字节数（估算）
+    pub(crate) in_memory_bytes: usize,
+    /// 已溢出的段，从旧到新
+    pub(crate) runs: Vec<Arc<SpillRun>>,
+}
+
+impl BatchSpill {
+    pub(crate) fn new(threshold: usize) -> BatchSpill {
+        BatchSpill {
+            threshold,
+            in_memory_bytes: 0,
+            runs: vec![],
+        }
+    }
+
+    /// 将内存中的操作写入一个新的段并清空内存
+    pub(crate) fn spill(
+        &mut self,
+        writes: &mut BTreeMap<InlineArray, Option<InlineArray>>,
+    ) -> io::Result<()> {
+        let run = SpillRun::write(writes)?;
+        self.runs.push(Arc::new(run));
+        writes.clear();
+        self.in_memory_bytes = 0;
+        Ok(())
+    }
+
+    /// 归并所有段和内存中的操作，按键排序，相同的键只保留最新的操作
+    pub(crate) fn merged(
+        &self,
+        writes: BTreeMap<InlineArray, Option<InlineArray>>,
+    ) -> io::Result<MergedWrites> {
+        let mut sources: Vec<Source> = Vec::with_capacity(self.runs.len() + 1);
+        for run in &self.runs {
+            sources.push(Box::new(run.reader()?));
+        }
+        sources.push(Box::new(writes.into_iter().map(Ok)));
+
+        MergedWrites::new(sources)
+    }
+
+    /// 所有段中的操作数，不同的段中相同的键各计一次
+    pub(crate) fn spilled_entries(&self) -> usize {
+        self.runs.iter().map(|run| run.entries as usize).sum()
+    }
+}
+
+/// 一个已写入临时文件的有序操作段，文件在段被释放时删除
+pub(crate) struct SpillRun {
+    file: tempfile::NamedTempFile,
+    entries: u64,
+    crc: u32,
+}
+
+impl fmt::Debug for SpillRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillRun")
+            .field("path", &self.file.path())
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+impl PartialEq for SpillRun {
+    fn eq(&self, other: &SpillRun) -> bool {
+        self.file.path() == other.file.path()
+    }
+}
+
+impl Eq for SpillRun {}
+
+impl SpillRun {
+    fn write(writes: &BTreeMap<InlineArray, Option<InlineArray>>) -> io::Result<SpillRun> {
+        let mut file = tempfile::Builder::new()
+            .prefix("melange_db_batch_spill")
+            .tempfile()?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut writer = BufWriter::new(file.as_file_mut());
+        for (key, value_opt) in writes {
+            let mut record = |bytes: &[u8]| -> io::Result<()> {
+                hasher.update(bytes);
+                writer.write_all(bytes)
+            };
+
+            record(&(key.len() as u32).to_le_bytes())?;
+            record(key)?;
+            match value_opt {
+                Some(value) => {
+                    record(&[1])?;
+                    record(&(value.len() as u32).to_le_bytes())?;
+                    record(value)?;
+                }
+                None => record(&[0])?,
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+
+        Ok(SpillRun {
+            file,
+            entries: writes.len() as u64,
+            crc: hasher.finalize(),
+        })
+    }
+
+    fn reader(&self) -> io::Result<RunReader> {
+        Ok(RunReader {
+            reader: BufReader::new(File::open(self.file.path())?),
+            remaining: self.entries,
+            hasher: crc32fast::Hasher::new(),
+            expected_crc: self.crc,
+        })
+    }
+}
+
+/// 顺序读取一个段中的操作
+struct RunReader {
+    reader: BufReader<File>,
+    remaining: u64,
+    hasher: crc32fast::Hasher,
+    expected_crc: u32,
+}
+
+impl RunReader {
+    fn read_bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.reader.read_exact(&mut buf)?;
+        self.hasher.update(&buf);
+        Ok(buf)
+    }
+
+    fn read_len(&mut self) -> io::Result<usize> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn read_entry(&mut self) -> io::Result<SpillEntry> {
+        let key_len = self.read_len()?;
+        let key = InlineArray::from(self.read_bytes(key_len)?);
+
+        let value = match self.read_bytes(1)?[0] {
+            0 => None,
+            1 => {
+                let value_len = self.read_len()?;
+                Some(InlineArray::from(self.read_bytes(value_len)?))
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("批量写入溢出文件中的无效记录标记: {}", other),
+                ));
+            }
+        };
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            let crc = std::mem::take(&mut self.hasher).finalize();
+            if crc != self.expected_crc {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "批量写入溢出文件校验失败",
+                ));
+            }
+        }
+
+        Ok((key, value))
+    }
+}
+
+impl Iterator for RunReader {
+    type Item = io::Result<SpillEntry>;
+
+    fn next(&mut self) -> Option<io::Result<SpillEntry>> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let ret = self.read_entry();
+        if ret.is_err() {
+            // 出错后不再继续读取
+            self.remaining = 0;
+        }
+        Some(ret)
+    }
+}
+
+/// 多个有序操作源的归并，源的下标越大越新
+pub(crate) struct MergedWrites {
+    sources: Vec<Source>,
+    heads: Vec<Option<Option<InlineArray>>>,
+    heap: BinaryHeap<Reverse<(InlineArray, Reverse<usize>)>>,
+}
+
+impl MergedWrites {
+    fn new(sources: Vec<Source>) -> io::Result<MergedWrites> {
+        let mut merged = MergedWrites {
+            heads: vec![None; sources.len()],
+            sources,
+            heap: BinaryHeap::new(),
+        };
+
+        for idx in 0..merged.sources.len() {
+            merged.advance(idx)?;
+        }
+
+        Ok(merged)
+    }
+
+    /// 读取源 `idx` 的下一个操作放入堆中
+    fn advance(&mut self, idx: usize) -> io::Result<()> {
+        if let Some(entry) = self.sources[idx].next() {
+            let (key, value) = entry?;
+            self.heads[idx] = Some(value);
+            self.heap.push(Reverse((key, Reverse(idx))));
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> io::Result<Option<SpillEntry>> {
+        // 相同的键中最新的源排在最前面
+        let Some(Reverse((key, Reverse(idx)))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let value = self.heads[idx].take().unwrap();
+        self.advance(idx)?;
+
+        // 丢弃较旧的源中被覆盖的操作
+        while let Some(Reverse((next_key, _))) = self.heap.peek() {
+            if *next_key != key {
+                break;
+            }
+            let Reverse((_, Reverse(older))) = self.heap.pop().unwrap();
+            self.heads[older] = None;
+            self.advance(older)?;
+        }
+
+        Ok(Some((key, value)))
+    }
+}
+
+impl Iterator for MergedWrites {
+    type Item = io::Result<SpillEntry>;
+
+    fn next(&mut self) -> Option<io::Result<SpillEntry>> {
+        match self.next_entry() {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.heap.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &[u8], value: Option<&[u8]>) -> SpillEntry {
+        (InlineArray::from(key), value.map(InlineArray::from))
+    }
+
+    #[test]
+    fn test_merge_newest_wins() {
+        let mut spill = BatchSpill::new(0);
+
+        let mut writes = BTreeMap::new();
+        writes.insert(
+            InlineArray::from(&b"a"[..]),
+            Some(InlineArray::from(&b"1"[..])),
+        );
+        writes.insert(
+            InlineArray::from(&b"c"[..]),
+            Some(InlineArray::from(&b"1"[..])),
+        );
+        spill.spill(&mut writes).unwrap();
+
+        writes.insert(
+            InlineArray::from(&b"b"[..]),
+            Some(InlineArray::from(&b"2"[..])),
+        );
+        writes.insert(InlineArray::from(&b"c"[..]), None);
+        spill.spill(&mut writes).unwrap();
+
+        writes.insert(
+            InlineArray::from(&b"a"[..]),
+            Some(InlineArray::from(&b"3"[..])),
+        );
+        writes.insert(
+            InlineArray::from(&b"d"[..]),
+            Some(InlineArray::from(&b""[..])),
+        );
+
+        let merged: Vec<SpillEntry> = spill
+            .merged(writes)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            merged,
+            vec![
+                entry(b"a", Some(b"3")),
+                entry(b"b", Some(b"2")),
+                entry(b"c", None),
+                entry(b"d", Some(b"")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_corrupted_run_is_detected() {
+        let mut spill = BatchSpill::new(0);
+        let mut writes = BTreeMap::new();
+        writes.insert(
+            InlineArray::from(&b"key"[..]),
+            Some(InlineArray::from(&b"value"[..])),
+        );
+        spill.spill(&mut writes).unwrap();
+
+        let path = spill.runs[0].file.path().to_owned();
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        std::fs::write(&path, data).unwrap();
+
+        let err = spill
+            .merged(BTreeMap::new())
+            .and_then(|merged| merged.collect::<io::Result<Vec<_>>>())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,296 @@
+//! 单独存储的大值（blob）
+//!
+//! 配置 `Config::inline_value_threshold` 后，大于阈值的值不放在叶子节点中，
+//! 而是各自写入数据库目录的 `blobs` 子目录中的一个文件，叶子节点中只保留
+//! 引用，使叶子节点保持紧凑，扫描键和相邻的小值时不需要读取和反序列化大值。
+//!
+//! 启用时叶子节点中的每个值都带有一个标记字节：
+//!
+//! ```text
+//! [0x00][value]                                         内联的值
+//! [0x01][blob_id: u64 LE][len: u64 LE][crc32: u32 LE]   blob的引用
+//! ```
+//!
+//! 带标记的叶子节点序列化时以 `BLOB_MARKER` 开头，因此去掉配置后仍然可以
+//! 读取，读取时引用被替换为blob的内容。
+//!
+//! blob文件在写入叶子节点之前创建，在下一次flush写入元数据之前同步到磁盘，
+//! 所以持久化的叶子节点引用的blob总是完整的。值被覆盖或删除后，旧的blob在
+//! 包含这次修改的flush完成之后才被删除。崩溃时已写入但还没有被持久化的
+//! 叶子节点引用的blob文件会残留在目录中，不影响正确性。
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use fault_injection::{annotate, fallible};
+use inline_array::InlineArray;
+use parking_lot::Mutex;
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{platform_utils, warn_log};
+
+/// 叶子节点中的值带有标记字节的叶子节点的标记字节，之后是一个普通的
+/// 序列化的叶子节点
+pub(crate) const BLOB_MARKER: u8 = 0xFB;
+
+const BLOB_DIR: &str = "blobs";
+const TAG_INLINE: u8 = 0;
+const TAG_BLOB: u8 = 1;
+const BLOB_REF_LEN: usize = 1 + 8 + 8 + 4;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// 叶子节点中的一个blob引用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlobRef {
+    id: u64,
+    len: u64,
+    crc: u32,
+}
+
+impl BlobRef {
+    fn encode(&self) -> InlineArray {
+        let mut buf = Vec::with_capacity(BLOB_REF_LEN);
+        buf.push(TAG_BLOB);
+        buf.extend_from_slice(&self.id.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.crc.to_le_bytes());
+        InlineArray::from(buf)
+    }
+
+    fn decode(stored: &[u8]) -> io::Result<BlobRef> {
+        if stored.len() != BLOB_REF_LEN {
+            return Err(invalid_data(format!("blob引用的长度 {} 无效", stored.len())));
+        }
+        Ok(BlobRef {
+            id: u64::from_le_bytes(stored[1..9].try_into().unwrap()),
+            len: u64::from_le_bytes(stored[9..17].try_into().unwrap()),
+            crc: u32::from_le_bytes(stored[17..21].try_into().unwrap()),
+        })
+    }
+}
+
+/// 大值的存储目录
+#[derive(Debug)]
+pub(crate) struct BlobStore {
+    directory: PathBuf,
+    threshold: Option<usize>,
+    next_id: PortableAtomicU64,
+    /// 已写入但还没有同步到磁盘的blob
+    unsynced: Mutex<Vec<u64>>,
+    /// 不再被内存中的叶子节点引用的blob，包含这些修改的flush完成后删除
+    released: Mutex<Vec<u64>>,
+}
+
+impl BlobStore {
+    /// 打开数据库目录中的blob目录。`threshold` 为 `None` 时不写入新的blob，
+    /// 但仍然可以读取之前写入的blob
+    pub(crate) fn open(path: &Path, threshold: Option<usize>) -> io::Result<BlobStore> {
+        let directory = path.join(BLOB_DIR);
+
+        let mut next_id = 0;
+        match fs::read_dir(&directory) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = fallible!(entry);
+                    let name = entry.file_name();
+                    match name.to_str().and_then(|name| u64::from_str_radix(name, 16).ok()) {
+                        Some(id) => next_id = next_id.max(id + 1),
+                        None => {
+                            warn_log!("忽略blob目录中的未知文件 {:?}", name);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(annotate!(e)),
+        }
+
+        Ok(BlobStore {
+            directory,
+            threshold,
+            next_id: PortableAtomicU64::new(next_id),
+            unsynced: Mutex::default(),
+            released: Mutex::default(),
+        })
+    }
+
+    /// 不单独存储任何值的实例，用于单元测试
+    #[cfg(test)]
+    pub(crate) fn disabled() -> BlobStore {
+        BlobStore {
+            directory: PathBuf::new(),
+            threshold: None,
+            next_id: PortableAtomicU64::new(0),
+            unsynced: Mutex::default(),
+            released: Mutex::default(),
+        }
+    }
+
+    /// 叶子节点中的值是否带有标记字节
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.threshold.is_some()
+    }
+
+    fn blob_path(&self, id: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}", id))
+    }
+
+    /// 将值转换为叶子节点中存储的形式，大于阈值的值写入一个新的blob
+    pub(crate) fn store(&self, value: InlineArray) -> io::Result<InlineArray> {
+        let Some(threshold) = self.threshold else {
+            return Ok(value);
+        };
+
+        if value.len() <= threshold {
+            let mut buf = Vec::with_capacity(value.len() + 1);
+            buf.push(TAG_INLINE);
+            buf.extend_from_slice(&value);
+            return Ok(InlineArray::from(buf));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if id == 0 || !self.directory.exists() {
+            fallible!(fs::create_dir_all(&self.directory));
+            fallible!(platform_utils::sync_directory(&self.directory));
+        }
+
+        let mut file = fallible!(fs::File::create(self.blob_path(id)));
+        fallible!(file.write_all(&value));
+        self.unsynced.lock().push(id);
+
+        Ok(BlobRef { id, len: value.len() as u64, crc: crc32fast::hash(&value) }.encode())
+    }
+
+    /// 从叶子节点中存储的形式读取值，blob引用从对应的文件读取
+    pub(crate) fn load(&self, stored: &InlineArray) -> io::Result<InlineArray> {
+        if self.is_enabled() {
+            self.untag(stored)
+        } else {
+            Ok(stored.clone())
+        }
+    }
+
+    /// 叶子节点中存储的值在磁盘上占用的字节数，blob引用加上blob文件的长度
+    pub(crate) fn stored_len(&self, stored: &InlineArray) -> usize {
+        if !self.is_enabled() || stored.first() != Some(&TAG_BLOB) {
+            return stored.len();
+        }
+        match BlobRef::decode(stored) {
+            Ok(blob) => stored.len() + blob.len as usize,
+            Err(_) => stored.len(),
+        }
+    }
+
+    /// 以借用的值调用 `f`，只有单独存储的值需要读取和复制
+    pub(crate) fn with_value<R>(
+        &self,
+        stored: &InlineArray,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> io::Result<R> {
+        if !self.is_enabled() {
+            return Ok(f(stored));
+        }
+        match stored.first() {
+            Some(&TAG_INLINE) => Ok(f(&stored[1..])),
+            _ => Ok(f(&self.untag(stored)?)),
+        }
+    }
+
+    fn read_blob(&self, blob: BlobRef) -> io::Result<InlineArray> {
+        let value = fallible!(fs::read(self.blob_path(blob.id)));
+        if value.len() as u64 != blob.len || crc32fast::hash(&value) != blob.crc {
+            return Err(invalid_data(format!(
+                "blob {:016x} 的内容与叶子节点中的引用不一致",
+                blob.id
+            )));
+        }
+        Ok(InlineArray::from(value))
+    }
+
+    /// 记录叶子节点中存储的值已被覆盖或删除，如果是blob引用，则在包含这次
+    /// 修改的flush完成后删除blob。必须在修改叶子节点时持有的flush epoch
+    /// 结束之前调用
+    pub(crate) fn release(&self, stored: &InlineArray) {
+        if !self.is_enabled() || stored.first() != Some(&TAG_BLOB) {
+            return;
+        }
+        if let Ok(blob) = BlobRef::decode(stored) {
+            self.released.lock().push(blob.id);
+        }
+    }
+
+    /// 带标记的叶子节点中的值转换为不带标记的形式，用于读取去掉配置之前写入的
+    /// 叶子节点
+    pub(crate) fn untag(&self, stored: &InlineArray) -> io::Result<InlineArray> {
+        match stored.first() {
+            Some(&TAG_INLINE) => Ok(InlineArray::from(&stored[1..])),
+            Some(&TAG_BLOB) => self.read_blob(BlobRef::decode(stored)?),
+            _ => Err(invalid_data(format!("叶子节点中的值的标记字节无效: {:?}", stored.first()))),
+        }
+    }
+
+    /// 不带标记的值转换为内联的带标记的形式，用于读取启用配置之前写入的叶子节点
+    pub(crate) fn tag_inline(value: &InlineArray) -> InlineArray {
+        let mut buf = Vec::with_capacity(value.len() + 1);
+        buf.push(TAG_INLINE);
+        buf.extend_from_slice(value);
+        InlineArray::from(buf)
+    }
+
+    /// 取出到目前为止被释放的blob。必须在flush推进epoch之前调用，
+    /// 这样取出的blob都是被这次flush包含的修改释放的
+    pub(crate) fn take_released(&self) -> Vec<u64> {
+        std::mem::take(&mut *self.released.lock())
+    }
+
+    /// 将 `take_released` 取出的blob放回，在flush失败之后调用，
+    /// 由重试这次flush的下一次flush删除
+    pub(crate) fn restore_released(&self, released: Vec<u64>) {
+        self.released.lock().extend(released);
+    }
+
+    /// 将已写入的blob同步到磁盘，必须在flush写入元数据之前调用。
+    /// 同步失败时这些blob由下一次flush重新同步
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        let unsynced = std::mem::take(&mut *self.unsynced.lock());
+        if unsynced.is_empty() {
+            return Ok(());
+        }
+
+        let res = self.sync_files(&unsynced);
+        if res.is_err() {
+            self.unsynced.lock().extend(unsynced);
+        }
+        res
+    }
+
+    fn sync_files(&self, ids: &[u64]) -> io::Result<()> {
+        for id in ids {
+            let file = fallible!(fs::File::open(self.blob_path(*id)));
+            fallible!(file.sync_all());
+        }
+        fallible!(platform_utils::sync_directory(&self.directory));
+
+        Ok(())
+    }
+
+    /// 删除 `take_released` 取出的blob，在flush成功写入元数据之后调用
+    pub(crate) fn remove_released(&self, released: Vec<u64>) {
+        let released: HashSet<u64> = released.into_iter().collect();
+        for id in released {
+            match fs::remove_file(self.blob_path(id)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn_log!("删除blob {:016x} 失败: {:?}", id, e);
+                }
+            }
+        }
+    }
+}
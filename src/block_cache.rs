@@ -11,12 +11,14 @@
 //! - 并发安全访问
 
 use std::collections::{HashMap, LinkedList, VecDeque};
-use std::sync::{Arc, RwLock, Mutex};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 use parking_lot::RwLock as ParkingRwLock;
 use serde::{Serialize, Deserialize};
+use crate::portable_atomic::PortableAtomicU64;
 use crate::debug_log;
 
 /// 缓存块
@@ -72,6 +74,18 @@ pub struct CacheConfig {
     pub enable_compression: bool,
     /// 压缩阈值（字节）
     pub compression_threshold: usize,
+    /// 根据各层的命中分布自适应地调整热/温/冷缓存的容量，总容量保持为 `max_size`
+    pub adaptive_tiers: bool,
+    /// 自适应调整的间隔（查询次数）
+    pub adaptive_tiers_interval: u64,
+}
+
+impl CacheConfig {
+    /// 启用或禁用各层容量的自适应调整
+    pub fn adaptive_tiers(mut self, enabled: bool) -> Self {
+        self.adaptive_tiers = enabled;
+        self
+    }
 }
 
 impl Default for CacheConfig {
@@ -84,6 +98,8 @@ impl Default for CacheConfig {
             prefetch_window: 4,
             enable_compression: true,
             compression_threshold: 1024, // 1KB
+            adaptive_tiers: false,
+            adaptive_tiers_interval: 1024,
         }
     }
 }
@@ -109,6 +125,8 @@ struct LruCache {
     current_size: usize,
     /// 最大大小
     max_size: usize,
+    /// 因容量不足被淘汰、尚未被取走的块
+    evicted: Vec<CacheBlock>,
 }
 
 impl LruCache {
@@ -119,39 +137,38 @@ impl LruCache {
             tail: None,
             current_size: 0,
             max_size,
+            evicted: Vec::new(),
         }
     }
 
     fn get(&mut self, block_id: u64) -> Option<CacheBlock> {
-        if self.map.contains_key(&block_id) {
-            let node = self.map.remove(&block_id).unwrap();
-            let block = node.block.clone();
+        // 节点在堆上的地址不变，从哈希表中取出后链表指针仍然有效
+        let mut node = self.map.remove(&block_id)?;
+        self.move_to_head(&mut node);
 
-            // 重新插入以移动到头部，但不增加大小
-            let block_size = block.size;
-            self.current_size = self.current_size.saturating_sub(block_size);
-            self.put_without_size_check(block.clone());
+        node.block.access_count = node.block.access_count.saturating_add(1);
+        node.block.last_access = Instant::now();
+        let block = node.block.clone();
 
-            Some(block)
-        } else {
-            None
-        }
+        self.map.insert(block_id, node);
+        Some(block)
     }
 
     fn put(&mut self, block: CacheBlock) -> Option<CacheBlock> {
         let block_size = block.size;
 
-        // 如果块已存在，移除旧块
-        if self.map.contains_key(&block.block_id) {
-            let old_node = self.map.remove(&block.block_id).unwrap();
+        // 如果块已存在，先从链表和哈希表中移除旧块
+        let old_block = self.map.remove(&block.block_id).map(|mut old_node| {
+            self.unlink(&mut old_node);
             self.current_size = self.current_size.saturating_sub(old_node.block.size);
-            return Some(old_node.block);
-        }
+            old_node.block
+        });
 
         // 检查是否需要淘汰
         while self.current_size + block_size > self.max_size {
-            if let Some(evicted) = self.evict() {
-                self.current_size -= evicted.size;
+            if let Some(evicted_block) = self.evict() {
+                self.current_size -= evicted_block.size;
+                self.evicted.push(evicted_block);
             } else {
                 break;
             }
@@ -183,37 +200,26 @@ impl LruCache {
         self.map.insert(block.block_id, node);
         self.current_size += block_size;
 
-        None
+        old_block
     }
 
-    fn put_without_size_check(&mut self, block: CacheBlock) {
-        let block_size = block.size;
-
-        // 创建新节点
-        let mut node = Box::new(LruNode {
-            block: block.clone(),
-            prev: None,
-            next: self.head,
-        });
-
-        let node_ptr = node.as_mut() as *mut LruNode;
-
-        // 更新头部
-        if let Some(head) = self.head {
-            unsafe {
-                (*head).prev = Some(node_ptr);
-            }
+    /// 将节点从双向链表中摘除
+    fn unlink(&mut self, node: &mut LruNode) {
+        match node.prev {
+            Some(prev) => unsafe { (*prev).next = node.next },
+            None => self.head = node.next,
         }
-        self.head = Some(node_ptr);
-
-        // 如果是第一个节点，设置尾部
-        if self.tail.is_none() {
-            self.tail = Some(node_ptr);
+        match node.next {
+            Some(next) => unsafe { (*next).prev = node.prev },
+            None => self.tail = node.prev,
         }
+        node.prev = None;
+        node.next = None;
+    }
 
-        // 插入哈希表
-        self.map.insert(block.block_id, node);
-        self.current_size += block_size;
+    /// 当前缓存的所有块
+    fn blocks(&self) -> impl Iterator<Item = &CacheBlock> {
+        self.map.values().map(|node| &node.block)
     }
 
     fn move_to_head(&mut self, node: &mut Box<LruNode>) {
@@ -283,6 +289,11 @@ impl LruCache {
         }
     }
 
+    /// 取走自上次调用以来被淘汰的块
+    fn take_evicted(&mut self) -> Vec<CacheBlock> {
+        std::mem::take(&mut self.evicted)
+    }
+
     fn clear(&mut self) {
         self.map.clear();
         self.head = None;
@@ -297,10 +308,29 @@ impl LruCache {
     fn size(&self) -> usize {
         self.current_size
     }
+
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    /// 调整最大容量，缩小时淘汰最久未使用的块直到不超过新容量
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        while self.current_size > self.max_size {
+            if let Some(evicted_block) = self.evict() {
+                self.current_size -= evicted_block.size;
+                self.evicted.push(evicted_block);
+            } else {
+                break;
+            }
+        }
+    }
 }
 
+/// 块被淘汰时调用的回调
+pub type EvictionCallback = Arc<dyn Fn(&CacheBlock) + Send + Sync>;
+
 /// 分级块缓存
-#[derive(Debug)]
 pub struct TieredBlockCache {
     /// 热缓存（最近访问）
     hot_cache: Arc<ParkingRwLock<LruCache>>,
@@ -312,10 +342,28 @@ pub struct TieredBlockCache {
     config: CacheConfig,
     /// 预取队列
     prefetch_queue: Arc<Mutex<VecDeque<u64>>>,
-    /// 访问模式检测
-    access_patterns: Arc<RwLock<HashMap<u64, AccessPattern>>>,
+    /// 最近一次写入的块ID，用于检测顺序访问。
+    /// 访问模式保存在 `CacheBlock` 中，随块一起被淘汰
+    last_put_block_id: PortableAtomicU64,
     /// 统计信息
-    stats: Arc<RwLock<CacheStats>>,
+    stats: Arc<ParkingRwLock<CacheStats>>,
+    /// 上一次自适应调整时的统计信息
+    last_rebalance: Mutex<CacheStats>,
+    /// 淘汰回调
+    on_evict: Arc<ParkingRwLock<Option<EvictionCallback>>>,
+}
+
+impl std::fmt::Debug for TieredBlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TieredBlockCache")
+            .field("hot_cache", &self.hot_cache)
+            .field("warm_cache", &self.warm_cache)
+            .field("cold_cache", &self.cold_cache)
+            .field("config", &self.config)
+            .field("stats", &self.stats)
+            .field("has_on_evict", &self.on_evict.read().is_some())
+            .finish()
+    }
 }
 
 /// 缓存统计信息（内部实现细节）
@@ -332,8 +380,14 @@ pub struct CacheStats {
     pub cold_hits: u64,
     pub total_bytes_served: u64,
     pub compression_ratio: f64,
+    pub tier_rebalances: u64,
 }
 
+/// 自适应调整时每层至少保留的容量比例
+const MIN_TIER_SHARE: f64 = 0.05;
+/// 每次自适应调整向目标比例移动的幅度
+const TIER_ADAPT_STEP: f64 = 0.5;
+
 impl TieredBlockCache {
     pub fn new(config: CacheConfig) -> Self {
         let hot_size = (config.max_size as f64 * 0.1) as usize;  // 10% 热缓存
@@ -348,13 +402,32 @@ impl TieredBlockCache {
             cold_cache: Arc::new(ParkingRwLock::new(LruCache::new(cold_size))),
             config,
             prefetch_queue: Arc::new(Mutex::new(VecDeque::new())),
-            access_patterns: Arc::new(RwLock::new(HashMap::new())),
-            stats: Arc::new(RwLock::new(CacheStats::default())),
+            last_put_block_id: PortableAtomicU64::new(u64::MAX),
+            stats: Arc::new(ParkingRwLock::new(CacheStats::default())),
+            last_rebalance: Mutex::new(CacheStats::default()),
+            on_evict: Arc::new(ParkingRwLock::new(None)),
         }
     }
 
+    /// 设置淘汰回调，每个被淘汰的块在丢弃前都会传给回调
+    ///
+    /// 回调在释放缓存层的写锁之后调用，因此可以在回调中安全地访问缓存。
+    pub fn on_evict(&self, callback: EvictionCallback) {
+        *self.on_evict.write() = Some(callback);
+    }
+
     /// 获取缓存块
     pub fn get(&self, block_id: u64) -> Option<CacheBlock> {
+        let ret = self.lookup(block_id);
+
+        if self.config.adaptive_tiers {
+            self.maybe_rebalance_tiers();
+        }
+
+        ret
+    }
+
+    fn lookup(&self, block_id: u64) -> Option<CacheBlock> {
         // 先尝试热缓存
         if let Some(block) = self.hot_cache.write().get(block_id) {
             self.update_stats(true, CacheTier::Hot);
@@ -385,7 +458,7 @@ impl TieredBlockCache {
     /// 存储缓存块
     pub fn put(&self, mut block: CacheBlock) {
         // 更新访问模式
-        self.update_access_pattern(block.block_id);
+        self.update_access_pattern(&mut block);
 
         // 压缩大块
         if self.config.enable_compression && block.size > self.config.compression_threshold {
@@ -396,7 +469,7 @@ impl TieredBlockCache {
         }
 
         // 存储到温缓存（新数据通常有一定的访问频率）
-        self.warm_cache.write().put(block.clone());
+        self.put_into_tier(&self.warm_cache, block.clone());
 
         // 触发预取
         if self.config.enable_prefetch {
@@ -406,12 +479,104 @@ impl TieredBlockCache {
 
     /// 提升块到热缓存
     fn promote_to_hot(&self, block: CacheBlock) {
-        self.hot_cache.write().put(block);
+        self.put_into_tier(&self.hot_cache, block);
     }
 
     /// 提升块到温缓存
     fn promote_to_warm(&self, block: CacheBlock) {
-        self.warm_cache.write().put(block);
+        self.put_into_tier(&self.warm_cache, block);
+    }
+
+    /// 将块存入指定的缓存层，并在释放写锁后处理被淘汰的块
+    fn put_into_tier(&self, tier: &ParkingRwLock<LruCache>, block: CacheBlock) {
+        let evicted = {
+            let mut tier = tier.write();
+            tier.put(block);
+            tier.take_evicted()
+        };
+
+        self.handle_evicted(evicted);
+    }
+
+    /// 在不持有任何缓存层的锁时统计被淘汰的块并调用淘汰回调
+    fn handle_evicted(&self, evicted: Vec<CacheBlock>) {
+        if evicted.is_empty() {
+            return;
+        }
+
+        self.stats.write().evictions += evicted.len() as u64;
+
+        let callback = self.on_evict.read().clone();
+        if let Some(callback) = callback {
+            for block in &evicted {
+                callback(block);
+            }
+        }
+    }
+
+    /// 距离上一次调整的查询次数达到 `adaptive_tiers_interval` 时调整各层容量
+    fn maybe_rebalance_tiers(&self) {
+        // 其他线程正在调整时跳过
+        let Ok(mut last) = self.last_rebalance.try_lock() else {
+            return;
+        };
+
+        let current = self.stats();
+        let lookups = current.hits + current.misses;
+        if lookups < last.hits + last.misses + self.config.adaptive_tiers_interval {
+            return;
+        }
+
+        let window_hits = [
+            current.hot_hits - last.hot_hits,
+            current.warm_hits - last.warm_hits,
+            current.cold_hits - last.cold_hits,
+        ];
+        *last = current;
+        drop(last);
+
+        self.rebalance_tiers(window_hits);
+    }
+
+    /// 将各层的容量向这段时间内的命中比例移动一步，每层至少保留
+    /// `MIN_TIER_SHARE` 的容量，总容量保持为 `max_size`
+    fn rebalance_tiers(&self, window_hits: [u64; 3]) {
+        let total_hits: u64 = window_hits.iter().sum();
+        if total_hits == 0 {
+            return;
+        }
+
+        let max_size = self.config.max_size as f64;
+        let tiers = [&self.hot_cache, &self.warm_cache, &self.cold_cache];
+
+        let mut capacities = [0_usize; 3];
+        for i in 0..2 {
+            let current_share = tiers[i].read().capacity() as f64 / max_size;
+            let target_share = MIN_TIER_SHARE
+                + (1.0 - 3.0 * MIN_TIER_SHARE) * window_hits[i] as f64
+                    / total_hits as f64;
+            let share = current_share + (target_share - current_share) * TIER_ADAPT_STEP;
+            capacities[i] = (max_size * share) as usize;
+        }
+        capacities[2] = self.config.max_size - capacities[0] - capacities[1];
+
+        debug_log!(
+            "调整分级块缓存容量: 热={}, 温={}, 冷={}",
+            capacities[0],
+            capacities[1],
+            capacities[2]
+        );
+
+        for (tier, capacity) in tiers.into_iter().zip(capacities) {
+            let evicted = {
+                let mut tier = tier.write();
+                tier.set_max_size(capacity);
+                tier.take_evicted()
+            };
+            self.handle_evicted(evicted);
+        }
+
+        self.stats.write().tier_rebalances += 1;
     }
 
     /// 触发预取
@@ -433,17 +598,14 @@ impl TieredBlockCache {
         queue.pop_front()
     }
 
-    /// 更新访问模式
-    fn update_access_pattern(&self, block_id: u64) {
-        let mut patterns = self.access_patterns.write().unwrap();
-        let pattern = patterns.entry(block_id).or_insert(AccessPattern::Unknown);
+    /// 更新访问模式：紧接着上一个写入的块之后的块视为顺序访问
+    fn update_access_pattern(&self, block: &mut CacheBlock) {
+        let last = self.last_put_block_id.swap(block.block_id, Ordering::Relaxed);
 
-        // 简单的访问模式检测逻辑
-        // 实际实现中可能需要更复杂的算法
-        *pattern = match pattern {
-            AccessPattern::Unknown => AccessPattern::Sequential,
-            AccessPattern::Sequential => AccessPattern::Sequential,
-            AccessPattern::Random => AccessPattern::Random,
+        block.access_pattern = if last.wrapping_add(1) == block.block_id {
+            AccessPattern::Sequential
+        } else {
+            AccessPattern::Random
         };
     }
 
@@ -465,7 +627,7 @@ impl TieredBlockCache {
 
     /// 更新统计信息
     fn update_stats(&self, hit: bool, tier: CacheTier) {
-        let mut stats = self.stats.write().unwrap();
+        let mut stats = self.stats.write();
 
         if hit {
             stats.hits += 1;
@@ -481,7 +643,7 @@ impl TieredBlockCache {
 
     /// 获取统计信息
     pub fn stats(&self) -> CacheStats {
-        self.stats.read().unwrap().clone()
+        self.stats.read().clone()
     }
 
     /// 清空所有缓存
@@ -490,7 +652,31 @@ impl TieredBlockCache {
         self.warm_cache.write().clear();
         self.cold_cache.write().clear();
         self.prefetch_queue.lock().unwrap().clear();
-        self.access_patterns.write().unwrap().clear();
+    }
+
+    /// 获取缓存中每个块的访问概况 `(块ID, 访问模式, 访问次数)`，
+    /// 按访问次数从高到低排序。同一个块可能同时存在于多个层级中，
+    /// 此时取访问次数最高的记录
+    pub fn access_profile(&self) -> Vec<(u64, AccessPattern, u32)> {
+        let mut profile: HashMap<u64, (AccessPattern, u32)> = HashMap::new();
+
+        for tier in [&self.hot_cache, &self.warm_cache, &self.cold_cache] {
+            for block in tier.read().blocks() {
+                let entry = profile
+                    .entry(block.block_id)
+                    .or_insert((block.access_pattern, block.access_count));
+                if block.access_count > entry.1 {
+                    *entry = (block.access_pattern, block.access_count);
+                }
+            }
+        }
+
+        let mut profile: Vec<(u64, AccessPattern, u32)> = profile
+            .into_iter()
+            .map(|(block_id, (pattern, count))| (block_id, pattern, count))
+            .collect();
+        profile.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        profile
     }
 
     /// 获取缓存大小信息
@@ -502,6 +688,9 @@ impl TieredBlockCache {
             hot_blocks: self.hot_cache.read().len(),
             warm_blocks: self.warm_cache.read().len(),
             cold_blocks: self.cold_cache.read().len(),
+            hot_capacity: self.hot_cache.read().capacity(),
+            warm_capacity: self.warm_cache.read().capacity(),
+            cold_capacity: self.cold_cache.read().capacity(),
         }
     }
 }
@@ -523,6 +712,9 @@ pub struct CacheSizeInfo {
     pub hot_blocks: usize,
     pub warm_blocks: usize,
     pub cold_blocks: usize,
+    pub hot_capacity: usize,
+    pub warm_capacity: usize,
+    pub cold_capacity: usize,
 }
 
 /// 智能缓存管理器
@@ -588,6 +780,12 @@ impl CacheManager {
     pub fn size_info(&self) -> CacheSizeInfo {
         self.block_cache.size_info()
     }
+
+    /// 获取缓存认为的热点块 `(块ID, 访问模式, 访问次数)`，按访问次数从高到低排序。
+    /// 只包含当前仍在缓存中的块，可用于缓存预热和排查
+    pub fn access_profile(&self) -> Vec<(u64, AccessPattern, u32)> {
+        self.block_cache.access_profile()
+    }
 }
 
 #[cfg(test)]
@@ -673,4 +871,172 @@ mod tests {
         assert!(cached_block.is_some());
         assert_eq!(cached_block.unwrap().data, data);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_on_evict_callback() {
+        let config = CacheConfig {
+            max_size: 10_000, // 温缓存为3000字节
+            enable_compression: false,
+            enable_prefetch: false,
+            ..CacheConfig::default()
+        };
+        let cache = TieredBlockCache::new(config);
+
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let observed_clone = observed.clone();
+        cache.on_evict(Arc::new(move |block: &CacheBlock| {
+            observed_clone.lock().unwrap().push(block.block_id);
+        }));
+
+        for block_id in 0..10 {
+            cache.put(CacheBlock {
+                data: vec![0u8; 1000],
+                block_id,
+                access_count: 1,
+                last_access: Instant::now(),
+                created_at: Instant::now(),
+                size: 1000,
+                access_pattern: AccessPattern::Unknown,
+            });
+        }
+
+        // 温缓存只能容纳最近的3个块，其余的按插入顺序被淘汰
+        assert_eq!(*observed.lock().unwrap(), (0..7).collect::<Vec<u64>>());
+        assert_eq!(cache.stats().evictions, 7);
+        assert_eq!(cache.size_info().warm_blocks, 3);
+    }
+
+    #[test]
+    fn test_access_profile() {
+        let config = CacheConfig {
+            enable_compression: false,
+            enable_prefetch: false,
+            ..CacheConfig::default()
+        };
+        let manager = CacheManager::new(config);
+
+        for block_id in [10, 11, 12, 40] {
+            manager.write_block(block_id, vec![0u8; 100]);
+        }
+        for _ in 0..3 {
+            manager.read_block(12).unwrap();
+        }
+        manager.read_block(40).unwrap();
+
+        let profile = manager.access_profile();
+        let ids: Vec<u64> = profile.iter().map(|(id, _, _)| *id).collect();
+        assert_eq!(ids, vec![12, 40, 10, 11]);
+        assert_eq!(profile[0].2, 4);
+        assert_eq!(profile[1].2, 2);
+
+        // 11和12紧接在前一个块之后写入，40不是
+        let pattern = |id: u64| profile.iter().find(|p| p.0 == id).unwrap().1;
+        assert!(matches!(pattern(11), AccessPattern::Sequential));
+        assert!(matches!(pattern(12), AccessPattern::Sequential));
+        assert!(matches!(pattern(40), AccessPattern::Random));
+    }
+
+    #[test]
+    fn test_access_profile_bounded_by_cached_blocks() {
+        let config = CacheConfig {
+            max_size: 64 * 1024,
+            enable_compression: false,
+            enable_prefetch: false,
+            ..CacheConfig::default()
+        };
+        let manager = CacheManager::new(config);
+
+        // 100万个不同的块经过一个很小的缓存，访问模式随块一起被淘汰
+        for block_id in 0..1_000_000_u64 {
+            manager.write_block(block_id, vec![0u8; 64]);
+            if block_id % 1000 == 0 {
+                manager.read_block(block_id).unwrap();
+            }
+        }
+
+        let info = manager.size_info();
+        let cached_blocks = info.hot_blocks + info.warm_blocks + info.cold_blocks;
+        let profile = manager.access_profile();
+
+        assert!(profile.len() <= cached_blocks);
+        assert!(cached_blocks <= 64 * 1024 / 64);
+        assert!(profile.iter().any(|(id, _, _)| *id == 999_999));
+        assert!(profile.iter().all(|(id, _, _)| *id >= 990_000 || id % 1000 == 0));
+    }
+
+    fn adaptive_manager(adaptive: bool) -> CacheManager {
+        let config = CacheConfig {
+            max_size: 100_000, // 初始为 热=10000, 温=30000, 冷=60000
+            enable_compression: false,
+            enable_prefetch: false,
+            adaptive_tiers_interval: 100,
+            ..CacheConfig::default()
+        }
+        .adaptive_tiers(adaptive);
+        CacheManager::new(config)
+    }
+
+    #[test]
+    fn test_adaptive_tiers_grow_hot_for_point_lookups() {
+        let manager = adaptive_manager(true);
+
+        for block_id in 0..50 {
+            manager.write_block(block_id, vec![0u8; 100]);
+        }
+        // 反复查询同一小批块，除第一次外都命中热缓存
+        for _ in 0..100 {
+            for block_id in 0..50 {
+                manager.read_block(block_id).unwrap();
+            }
+        }
+
+        let info = manager.size_info();
+        assert!(manager.stats().tier_rebalances > 0);
+        assert!(info.hot_capacity > 50_000, "{:?}", info);
+        assert!(info.warm_capacity >= 5_000 && info.cold_capacity >= 5_000);
+        assert_eq!(info.hot_capacity + info.warm_capacity + info.cold_capacity, 100_000);
+
+        // 没有启用自适应调整时保持初始比例
+        let manager = adaptive_manager(false);
+        for block_id in 0..50 {
+            manager.write_block(block_id, vec![0u8; 100]);
+        }
+        for _ in 0..100 {
+            for block_id in 0..50 {
+                manager.read_block(block_id).unwrap();
+            }
+        }
+        let info = manager.size_info();
+        assert_eq!(manager.stats().tier_rebalances, 0);
+        assert_eq!(info.hot_capacity, 10_000);
+    }
+
+    #[test]
+    fn test_adaptive_tiers_shrink_hot_for_scans() {
+        let manager = adaptive_manager(true);
+
+        // 先让热缓存变大并装满
+        for block_id in 0..80 {
+            manager.write_block(block_id, vec![0u8; 100]);
+        }
+        for _ in 0..100 {
+            for block_id in 0..80 {
+                manager.read_block(block_id).unwrap();
+            }
+        }
+        let grown = manager.size_info();
+        assert!(grown.hot_capacity > 50_000);
+
+        // 顺序扫描：每个块写入后只读取一次，命中温缓存
+        for block_id in 1000..5000 {
+            manager.write_block(block_id, vec![0u8; 10]);
+            manager.read_block(block_id).unwrap();
+        }
+
+        let scanned = manager.size_info();
+        assert!(scanned.warm_capacity > grown.warm_capacity, "{:?}", scanned);
+        assert!(scanned.hot_capacity < grown.hot_capacity, "{:?}", scanned);
+        assert!(scanned.hot_size <= scanned.hot_capacity);
+        assert!(manager.stats().evictions > 0);
+    }
+}
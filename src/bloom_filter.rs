@@ -10,24 +10,33 @@
 //! - 并发安全访问
 
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::collections::hash_map::DefaultHasher;
 use serde::{Serialize, Deserialize};
 use parking_lot::RwLock;
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
 
+/// 每个缓存行中的u64数量
+const WORDS_PER_LINE: usize = 8;
+
+/// 位图中一个缓存行大小的块，使位图按64字节对齐
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, align(64))]
+struct CacheLine([u64; WORDS_PER_LINE]);
+
 /// 多重哈希函数的布隆过滤器
 #[derive(Debug, Clone)]
 pub struct BloomFilter {
-    /// 位图数据
-    bitmap: Vec<u64>,
+    /// 位图数据，按缓存行分配
+    bitmap: Vec<CacheLine>,
     /// 位图大小（以位为单位）
     bit_count: usize,
     /// 哈希函数数量
     hash_count: usize,
     /// 已插入的元素数量
-    element_count: Arc<AtomicU64>,
+    element_count: Arc<PortableAtomicU64>,
     /// 期望的误判率
     target_fpp: f64,
 }
@@ -46,15 +55,15 @@ impl BloomFilter {
         let bit_count = Self::optimal_bit_count(expected_elements, false_positive_rate);
         let hash_count = Self::optimal_hash_count(bit_count, expected_elements);
 
-        // 计算需要的u64数量
-        let word_count = (bit_count + 63) / 64;
-        let bitmap = vec![0; word_count];
+        // 计算需要的缓存行数量
+        let line_count = bit_count.div_ceil(64 * WORDS_PER_LINE);
+        let bitmap = vec![CacheLine::default(); line_count];
 
         Self {
             bitmap,
             bit_count,
             hash_count,
-            element_count: Arc::new(AtomicU64::new(0)),
+            element_count: Arc::new(PortableAtomicU64::new(0)),
             target_fpp: false_positive_rate,
         }
     }
@@ -79,14 +88,8 @@ impl BloomFilter {
         let hashes = self.compute_hashes(data);
 
         for hash in hashes {
-            let bit_index = (hash % self.bit_count as u64) as usize;
-            let word_index = bit_index / 64;
-            let bit_offset = bit_index % 64;
-
-            if word_index < self.bitmap.len() {
-                let mask = 1u64 << bit_offset;
-                self.bitmap[word_index] |= mask;
-            }
+            let (line, word, mask) = self.locate(hash);
+            self.bitmap[line].0[word] |= mask;
         }
 
         self.element_count.fetch_add(1, Ordering::Relaxed);
@@ -97,16 +100,8 @@ impl BloomFilter {
         let hashes = self.compute_hashes(data);
 
         for hash in hashes {
-            let bit_index = (hash % self.bit_count as u64) as usize;
-            let word_index = bit_index / 64;
-            let bit_offset = bit_index % 64;
-
-            if word_index >= self.bitmap.len() {
-                return false;
-            }
-
-            let mask = 1u64 << bit_offset;
-            if (self.bitmap[word_index] & mask) == 0 {
+            let (line, word, mask) = self.locate(hash);
+            if (self.bitmap[line].0[word] & mask) == 0 {
                 return false;
             }
         }
@@ -114,20 +109,20 @@ impl BloomFilter {
         true
     }
 
-    /// 计算多重哈希值
-    fn compute_hashes(&self, data: &[u8]) -> Vec<u64> {
-        let mut hashes = Vec::with_capacity(self.hash_count);
+    /// 哈希值对应的位所在的缓存行、缓存行中的u64和位掩码
+    fn locate(&self, hash: u64) -> (usize, usize, u64) {
+        let bit_index = (hash % self.bit_count as u64) as usize;
+        let word_index = bit_index / 64;
+        (word_index / WORDS_PER_LINE, word_index % WORDS_PER_LINE, 1u64 << (bit_index % 64))
+    }
 
+    /// 计算多重哈希值，不分配内存
+    fn compute_hashes(&self, data: &[u8]) -> impl Iterator<Item = u64> + use<> {
         // 使用双重哈希技术生成多个哈希值
         let hash1 = self.hash(data, 0);
         let hash2 = self.hash(data, hash1);
 
-        for i in 0..self.hash_count {
-            let combined_hash = hash1.wrapping_add((i as u64).wrapping_mul(hash2));
-            hashes.push(combined_hash);
-        }
-
-        hashes
+        (0..self.hash_count as u64).map(move |i| hash1.wrapping_add(i.wrapping_mul(hash2)))
     }
 
     /// 单一哈希函数
@@ -149,6 +144,9 @@ impl BloomFilter {
     }
 
     /// 计算当前的误判率
+    ///
+    /// 根据已插入的元素数量估算，重复插入的元素也会被计入，
+    /// 此时结果偏高，参见 `estimated_fpp_from_fill`
     pub fn current_false_positive_rate(&self) -> f64 {
         let n = self.len() as f64;
         let m = self.bit_count as f64;
@@ -159,33 +157,64 @@ impl BloomFilter {
         (1.0 - exp).powf(k)
     }
 
+    /// 位图中实际被置位的位所占的比例
+    pub fn measured_fill_ratio(&self) -> f64 {
+        let set_bits: u64 = self
+            .bitmap
+            .iter()
+            .flat_map(|line| line.0)
+            .map(|word| u64::from(word.count_ones()))
+            .sum();
+        set_bits as f64 / self.bit_count as f64
+    }
+
+    /// 根据位图的实际填充率估算误判率
+    ///
+    /// 一个不存在的元素的 `k` 个位都被置位的概率为 `fill^k`，它只取决于位图本身，
+    /// 不受重复插入的影响，比 `current_false_positive_rate` 更准确
+    pub fn estimated_fpp_from_fill(&self) -> f64 {
+        self.measured_fill_ratio().powi(self.hash_count as i32)
+    }
+
     /// 检查是否需要扩容
     pub fn needs_resize(&self) -> bool {
         let current_fpp = self.current_false_positive_rate();
         current_fpp > self.target_fpp * 1.5 // 容忍50%的误差
     }
 
-    /// 扩容布隆过滤器
-    pub fn resize(&mut self) {
-        let new_element_count = (self.len() as usize * 2).max(1024);
-        let mut new_filter = Self::new(new_element_count, self.target_fpp);
-
-        // 重新插入所有元素（这里需要记录插入的数据，实际实现中可能需要其他方式）
-        // 注意：这是一个简化的实现，实际中可能需要维护插入历史
-        warn_log!("布隆过滤器扩容从 {} 到 {} 元素", self.len(), new_element_count);
+    /// 扩容布隆过滤器：以当前元素数量的两倍为容量重新创建，并插入 `keys`。
+    ///
+    /// 布隆过滤器无法从位图中恢复已插入的元素，调用者必须通过 `keys`
+    /// 提供所有仍然有效的元素，否则扩容后会出现漏判
+    pub fn resize<I, K>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        let mut new_filter = self.resized_empty();
+        for key in keys {
+            new_filter.insert(key.as_ref());
+        }
 
         *self = new_filter;
     }
 
+    /// 创建一个容量为当前元素数量两倍、误判率相同的空过滤器
+    pub(crate) fn resized_empty(&self) -> BloomFilter {
+        let new_element_count = (self.len() as usize * 2).max(1024);
+        debug_log!("布隆过滤器扩容从 {} 到 {} 元素", self.len(), new_element_count);
+        Self::new(new_element_count, self.target_fpp)
+    }
+
     /// 清空布隆过滤器
     pub fn clear(&mut self) {
-        self.bitmap.fill(0);
+        self.bitmap.fill(CacheLine::default());
         self.element_count.store(0, Ordering::Relaxed);
     }
 
     /// 获取位图大小（字节）
     pub fn size_in_bytes(&self) -> usize {
-        self.bitmap.len() * 8
+        self.bitmap.len() * std::mem::size_of::<CacheLine>()
     }
 
     /// 获取统计信息
@@ -303,6 +332,28 @@ impl TieredBloomFilter {
         FilterResult::DefinitelyNotExist
     }
 
+    /// 批量检查多个数据是否存在，结果与逐个调用 `contains` 相同，按输入的顺序返回。
+    /// 每层只获取一次读锁，已经在较热的层中找到的数据不再检查较冷的层
+    pub fn contains_batch(&self, keys: &[&[u8]]) -> Vec<FilterResult> {
+        let mut results = vec![FilterResult::DefinitelyNotExist; keys.len()];
+
+        let tiers = [
+            (&self.hot, FilterResult::MayExistHot),
+            (&self.warm, FilterResult::MayExistWarm),
+            (&self.cold, FilterResult::MayExistCold),
+        ];
+        for (filter, found) in tiers {
+            let filter = filter.inner.read();
+            for (key, result) in keys.iter().zip(&mut results) {
+                if *result == FilterResult::DefinitelyNotExist && filter.contains(key) {
+                    *result = found.clone();
+                }
+            }
+        }
+
+        results
+    }
+
     /// 获取统计信息
     pub fn stats(&self) -> TieredBloomFilterStats {
         TieredBloomFilterStats {
@@ -406,6 +457,41 @@ mod tests {
         assert_eq!(tiered.contains(b"no_key"), FilterResult::DefinitelyNotExist);
     }
 
+    #[test]
+    fn test_tiered_bloom_filter_contains_batch() {
+        let tiered = TieredBloomFilter::new(1000);
+
+        let keys: Vec<String> = (0..300).map(|i| format!("key_{}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            let tier = match i % 3 {
+                0 => FilterTier::Hot,
+                1 => FilterTier::Warm,
+                _ => FilterTier::Cold,
+            };
+            tiered.insert(key.as_bytes(), tier);
+        }
+
+        // 插入过的键和没有插入过的键交替出现
+        let missing: Vec<String> = (0..300).map(|i| format!("missing_{}", i)).collect();
+        let batch: Vec<&[u8]> = keys
+            .iter()
+            .zip(&missing)
+            .flat_map(|(key, missing)| [key.as_bytes(), missing.as_bytes()])
+            .collect();
+
+        let results = tiered.contains_batch(&batch);
+        assert_eq!(results.len(), batch.len());
+        for (key, result) in batch.iter().zip(&results) {
+            assert_eq!(*result, tiered.contains(key));
+        }
+        assert!(results.contains(&FilterResult::DefinitelyNotExist));
+        assert_eq!(results[0], FilterResult::MayExistHot);
+        assert_eq!(results[2], FilterResult::MayExistWarm);
+        assert_eq!(results[4], FilterResult::MayExistCold);
+
+        assert!(tiered.contains_batch(&[]).is_empty());
+    }
+
     #[test]
     fn test_bloom_filter_stats() {
         let mut filter = BloomFilter::new(1000, 0.01);
@@ -419,4 +505,65 @@ mod tests {
         assert!(stats.current_fpp < 0.02); // 应该很低
         assert!(stats.size_in_bytes > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_measured_fill_ratio() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        assert_eq!(filter.bitmap.as_ptr() as usize % 64, 0);
+        assert_eq!(filter.measured_fill_ratio(), 0.0);
+
+        // 手动计算插入的元素对应的所有位
+        let mut set_bits = std::collections::HashSet::new();
+        for i in 0..500 {
+            let key = format!("key_{}", i);
+            filter.insert(key.as_bytes());
+            for hash in filter.compute_hashes(key.as_bytes()) {
+                set_bits.insert(hash % filter.bit_count as u64);
+            }
+        }
+
+        let expected = set_bits.len() as f64 / filter.bit_count as f64;
+        assert_eq!(filter.measured_fill_ratio(), expected);
+        assert_eq!(
+            filter.estimated_fpp_from_fill(),
+            expected.powi(filter.hash_count as i32)
+        );
+        // 没有重复插入时两种估算接近
+        let analytic = filter.current_false_positive_rate();
+        assert!((filter.estimated_fpp_from_fill() - analytic).abs() < analytic, "{}", analytic);
+
+        filter.clear();
+        assert_eq!(filter.measured_fill_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_fill_estimate_ignores_repeated_inserts() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        for _ in 0..1000 {
+            filter.insert(b"same");
+        }
+
+        // 按插入次数估算时过滤器已经饱和，实际只有一个元素的位被置位
+        assert!(filter.current_false_positive_rate() > 0.5);
+        assert!(filter.estimated_fpp_from_fill() < 1e-6);
+        let expected = filter.hash_count as f64 / filter.bit_count as f64;
+        assert!(filter.measured_fill_ratio() <= expected);
+    }
+
+    #[test]
+    fn test_bloom_filter_resize() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        let keys: Vec<String> = (0..2000).map(|i| format!("key_{}", i)).collect();
+
+        for key in &keys {
+            filter.insert(key.as_bytes());
+        }
+        assert!(filter.needs_resize());
+
+        filter.resize(&keys);
+
+        assert!(!filter.needs_resize());
+        assert_eq!(filter.len(), 2000);
+        assert!(keys.iter().all(|key| filter.contains(key.as_bytes())));
+    }
+}
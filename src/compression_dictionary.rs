@@ -0,0 +1,426 @@
+//! 叶子节点的zstd压缩字典
+//!
+//! 叶子节点各自独立压缩，100到300字节的小值之间没有共享的上下文，
+//! zstd几乎无法压缩它们。配置 `Config::compression_dictionary` 后，
+//! 叶子节点使用共享的zstd字典压缩：
+//!
+//! - `CompressionDictionary::Static` 直接使用给定的字典
+//! - `CompressionDictionary::Train` 在序列化叶子节点时采样其中的值，
+//!   采样达到 `sample_bytes` 后训练一次字典，之后写入的叶子节点使用该字典
+//!
+//! 每个字典有一个编号，在任何使用它的叶子节点写入之前持久化到数据库目录的
+//! `dictionaries` 子目录中。字典文件的格式为：
+//!
+//! ```text
+//! [kind: u8][crc32: u32 LE][dictionary]
+//! ```
+//!
+//! 使用字典压缩的叶子节点以标记字节和字典编号开头，没有标记的叶子节点是
+//! 不使用字典的普通zstd帧：
+//!
+//! ```text
+//! [0xFE][dictionary_id: u32 LE][zstd frame]
+//! ```
+//!
+//! 打开数据库时加载所有字典，因此更换或去掉字典配置后，旧的叶子节点
+//! 仍然可以读取，多代字典可以共存。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::{Mutex, RwLock};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::config::CompressionDictionary;
+use crate::{info_log, platform_utils, warn_log};
+
+/// 使用字典压缩的叶子节点的标记字节。zstd帧以 `0x28` 开头，
+/// 增量序列化的叶子节点以 `0xFF` 开头，按值压缩的叶子节点以 `0xFD` 开头
+pub(crate) const DICTIONARY_MARKER: u8 = 0xFE;
+
+const DICTIONARY_DIR: &str = "dictionaries";
+const KIND_STATIC: u8 = 0;
+const KIND_TRAINED: u8 = 1;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// 使用某个字典（或不使用字典）写入的叶子节点的压缩统计，
+/// 从本次打开数据库开始计数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DictionaryStats {
+    /// 字典编号，`None` 表示不使用字典压缩的叶子节点
+    pub dictionary_id: Option<u32>,
+    /// 字典的大小（字节）
+    pub dictionary_bytes: usize,
+    /// 写入的叶子节点数
+    pub leaves_written: u64,
+    /// 压缩前的字节数
+    pub uncompressed_bytes: u64,
+    /// 压缩后的字节数
+    pub compressed_bytes: u64,
+}
+
+impl DictionaryStats {
+    /// 压缩比，即压缩前与压缩后的字节数之比。没有写入任何叶子节点时为0
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 0.0;
+        }
+        self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    leaves_written: PortableAtomicU64,
+    uncompressed_bytes: PortableAtomicU64,
+    compressed_bytes: PortableAtomicU64,
+}
+
+impl Counters {
+    fn record(&self, uncompressed: usize, compressed: usize) {
+        self.leaves_written.fetch_add(1, Ordering::Relaxed);
+        self.uncompressed_bytes
+            .fetch_add(uncompressed as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+
+    fn stats(&self, dictionary_id: Option<u32>, dictionary_bytes: usize) -> DictionaryStats {
+        DictionaryStats {
+            dictionary_id,
+            dictionary_bytes,
+            leaves_written: self.leaves_written.load(Ordering::Relaxed),
+            uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Dictionary {
+    id: u32,
+    kind: u8,
+    bytes: Vec<u8>,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+    counters: Counters,
+}
+
+impl Dictionary {
+    fn new(id: u32, kind: u8, bytes: Vec<u8>, level: i32) -> Dictionary {
+        Dictionary {
+            id,
+            kind,
+            encoder: EncoderDictionary::copy(&bytes, level),
+            decoder: DecoderDictionary::copy(&bytes),
+            bytes,
+            counters: Counters::default(),
+        }
+    }
+}
+
+/// 训练字典前采样的值
+struct Sampler {
+    sample_bytes: usize,
+    max_dict_size: usize,
+    data: Vec<u8>,
+    sizes: Vec<usize>,
+}
+
+/// 一个数据库的所有压缩字典
+pub(crate) struct CompressionDictionaries {
+    directory: PathBuf,
+    level: i32,
+    dictionaries: RwLock<BTreeMap<u32, Arc<Dictionary>>>,
+    /// 新写入的叶子节点使用的字典
+    current: RwLock<Option<Arc<Dictionary>>>,
+    sampler: Mutex<Option<Sampler>>,
+    sampling: AtomicBool,
+    plain: Counters,
+}
+
+impl fmt::Debug for CompressionDictionaries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionDictionaries")
+            .field("dictionaries", &self.dictionaries.read().keys().collect::<Vec<_>>())
+            .field("current", &self.current.read().as_ref().map(|d| d.id))
+            .field("sampling", &self.sampling.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl CompressionDictionaries {
+    /// 加载数据库目录中的所有字典，并根据配置选择新写入的叶子节点使用的字典。
+    /// 调用方必须已经持有数据库目录的锁
+    pub(crate) fn open(
+        path: &Path,
+        mode: Option<&CompressionDictionary>,
+        level: i32,
+    ) -> io::Result<CompressionDictionaries> {
+        let directory = path.join(DICTIONARY_DIR);
+        let dictionaries = load_dictionaries(&directory, level)?;
+
+        let ret = CompressionDictionaries {
+            directory,
+            level,
+            dictionaries: RwLock::new(dictionaries),
+            current: RwLock::new(None),
+            sampler: Mutex::new(None),
+            sampling: AtomicBool::new(false),
+            plain: Counters::default(),
+        };
+
+        match mode {
+            None => {}
+            Some(CompressionDictionary::Static(bytes)) => {
+                let existing = ret
+                    .dictionaries
+                    .read()
+                    .values()
+                    .find(|d| d.kind == KIND_STATIC && &d.bytes == bytes)
+                    .cloned();
+                let dictionary = match existing {
+                    Some(dictionary) => dictionary,
+                    None => ret.persist(KIND_STATIC, bytes.clone())?,
+                };
+                *ret.current.write() = Some(dictionary);
+            }
+            Some(CompressionDictionary::Train { sample_bytes, max_dict_size }) => {
+                let trained = ret
+                    .dictionaries
+                    .read()
+                    .values()
+                    .rev()
+                    .find(|d| d.kind == KIND_TRAINED)
+                    .cloned();
+                match trained {
+                    Some(dictionary) => *ret.current.write() = Some(dictionary),
+                    None => {
+                        *ret.sampler.lock() = Some(Sampler {
+                            sample_bytes: *sample_bytes,
+                            max_dict_size: *max_dict_size,
+                            data: Vec::with_capacity(*sample_bytes),
+                            sizes: vec![],
+                        });
+                        ret.sampling.store(true, Ordering::Release);
+                    }
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// 压缩一个序列化后的叶子节点
+    pub(crate) fn compress(&self, raw: &[u8], zstd_compression_level: i32) -> Vec<u8> {
+        let current = self.current.read().clone();
+
+        let Some(dictionary) = current else {
+            let ret = zstd::stream::encode_all(raw, zstd_compression_level).unwrap();
+            self.plain.record(raw.len(), ret.len());
+            return ret;
+        };
+
+        let mut ret = Vec::with_capacity(raw.len() / 2 + 5);
+        ret.push(DICTIONARY_MARKER);
+        ret.extend_from_slice(&dictionary.id.to_le_bytes());
+
+        let mut zstd_enc = zstd::stream::Encoder::with_prepared_dictionary(
+            &mut ret,
+            &dictionary.encoder,
+        )
+        .unwrap();
+        zstd_enc.write_all(raw).unwrap();
+        zstd_enc.finish().unwrap();
+
+        dictionary.counters.record(raw.len(), ret.len());
+        ret
+    }
+
+    /// 解压一个由 `compress` 压缩的叶子节点
+    pub(crate) fn decompress(&self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        if buf.first() != Some(&DICTIONARY_MARKER) {
+            return zstd::stream::decode_all(buf);
+        }
+
+        if buf.len() < 5 {
+            return Err(invalid_data(format!(
+                "使用字典压缩的叶子节点被截断，只有 {} 字节",
+                buf.len()
+            )));
+        }
+        let id = u32::from_le_bytes(buf[1..5].try_into().unwrap());
+        let dictionary = self.dictionaries.read().get(&id).cloned().ok_or_else(|| {
+            invalid_data(format!("叶子节点使用的压缩字典 {} 不存在", id))
+        })?;
+
+        let mut decoder = zstd::stream::read::Decoder::with_prepared_dictionary(
+            &buf[5..],
+            &dictionary.decoder,
+        )?;
+        let mut ret = vec![];
+        decoder.read_to_end(&mut ret)?;
+        Ok(ret)
+    }
+
+    /// 在训练字典之前采样叶子节点中的值，采样足够后训练字典
+    pub(crate) fn sample<'a>(&self, values: impl Iterator<Item = &'a [u8]>) {
+        if !self.sampling.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut sampler_opt = self.sampler.lock();
+        let Some(sampler) = sampler_opt.as_mut() else {
+            return;
+        };
+
+        for value in values {
+            if value.is_empty() {
+                continue;
+            }
+            sampler.data.extend_from_slice(value);
+            sampler.sizes.push(value.len());
+            if sampler.data.len() >= sampler.sample_bytes {
+                break;
+            }
+        }
+
+        if sampler.data.len() < sampler.sample_bytes {
+            return;
+        }
+
+        // 字典只训练一次
+        let sampler = sampler_opt.take().unwrap();
+        self.sampling.store(false, Ordering::Release);
+        drop(sampler_opt);
+
+        self.train(sampler);
+    }
+
+    fn train(&self, sampler: Sampler) {
+        let trained = zstd::dict::from_continuous(
+            &sampler.data,
+            &sampler.sizes,
+            sampler.max_dict_size,
+        )
+        .and_then(|bytes| self.persist(KIND_TRAINED, bytes));
+
+        match trained {
+            Ok(dictionary) => {
+                info_log!(
+                    "从 {} 个采样值训练了 {} 字节的压缩字典 {}",
+                    sampler.sizes.len(),
+                    dictionary.bytes.len(),
+                    dictionary.id
+                );
+                *self.current.write() = Some(dictionary);
+            }
+            Err(e) => {
+                warn_log!(
+                    "无法从 {} 个采样值训练压缩字典，将继续不使用字典压缩: {:?}",
+                    sampler.sizes.len(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// 分配一个新的编号并持久化字典
+    fn persist(&self, kind: u8, bytes: Vec<u8>) -> io::Result<Arc<Dictionary>> {
+        let mut dictionaries = self.dictionaries.write();
+        let id = dictionaries.keys().next_back().map_or(1, |id| id + 1);
+
+        fs::create_dir_all(&self.directory)?;
+
+        let mut contents = Vec::with_capacity(bytes.len() + 5);
+        contents.push(kind);
+        contents.extend_from_slice(&crc32fast::hash(&bytes).to_le_bytes());
+        contents.extend_from_slice(&bytes);
+
+        let tmp_path = self.directory.join(format!("{:08}.dict.tmp", id));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, dictionary_path(&self.directory, id))?;
+        platform_utils::sync_directory(&self.directory)?;
+
+        let dictionary = Arc::new(Dictionary::new(id, kind, bytes, self.level));
+        dictionaries.insert(id, dictionary.clone());
+        Ok(dictionary)
+    }
+
+    /// 不使用字典和每个字典的压缩统计
+    pub(crate) fn stats(&self) -> Vec<DictionaryStats> {
+        let mut ret = vec![self.plain.stats(None, 0)];
+        for dictionary in self.dictionaries.read().values() {
+            ret.push(
+                dictionary
+                    .counters
+                    .stats(Some(dictionary.id), dictionary.bytes.len()),
+            );
+        }
+        ret
+    }
+}
+
+fn dictionary_path(directory: &Path, id: u32) -> PathBuf {
+    directory.join(format!("{:08}.dict", id))
+}
+
+fn load_dictionaries(
+    directory: &Path,
+    level: i32,
+) -> io::Result<BTreeMap<u32, Arc<Dictionary>>> {
+    let mut ret = BTreeMap::new();
+
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ret),
+        Err(e) => return Err(e),
+    };
+
+    for entry_res in entries {
+        let entry = entry_res?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+
+        if name.ends_with(".dict.tmp") {
+            // 持久化过程中崩溃留下的字典，不可能有叶子节点使用它
+            warn_log!("删除未完成持久化的压缩字典 {:?}", entry.path());
+            fs::remove_file(entry.path())?;
+            continue;
+        }
+
+        let Some(id) = name.strip_suffix(".dict").and_then(|id| id.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let contents = fs::read(entry.path())?;
+        if contents.len() < 5 {
+            return Err(invalid_data(format!("压缩字典 {} 的文件被截断", id)));
+        }
+        let kind = contents[0];
+        let crc = u32::from_le_bytes(contents[1..5].try_into().unwrap());
+        let bytes = contents[5..].to_vec();
+        if crc32fast::hash(&bytes) != crc {
+            return Err(invalid_data(format!("压缩字典 {} 校验失败", id)));
+        }
+
+        ret.insert(id, Arc::new(Dictionary::new(id, kind, bytes, level)));
+    }
+
+    Ok(ret)
+}
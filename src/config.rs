@@ -1,11 +1,13 @@
+use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use fault_injection::{annotate, fallible};
 use tempdir::TempDir;
 
-use crate::{Db, smart_flush::SmartFlushConfig};
+use crate::{CorruptionError, Db, FlushDebugReport, atomic_worker::AtomicWorkerConfig, op_journal::OpJournalConfig, platform_utils::ThreadPriority, smart_flush::SmartFlushConfig};
 
 /// 压缩算法枚举
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -87,6 +89,299 @@ impl CompressionAlgorithm {
     }
 }
 
+/// 叶子节点的zstd压缩字典，适合由许多相似的小值组成的工作负载
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionDictionary {
+    /// 在序列化叶子节点时采样其中的值，采样达到 `sample_bytes` 字节后
+    /// 训练一个最大为 `max_dict_size` 字节的字典，之后写入的叶子节点使用它。
+    /// 已经训练过字典的数据库在重新打开时直接使用最新训练的字典
+    Train {
+        /// 训练前采样的值的总字节数
+        sample_bytes: usize,
+        /// 字典的最大字节数
+        max_dict_size: usize,
+    },
+    /// 使用给定的字典，例如事先用 `zstd --train` 训练的字典
+    Static(Vec<u8>),
+}
+
+/// 叶子节点分裂点选择策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitBias {
+    /// 最左侧叶子节点在开头附近分裂，最右侧叶子节点在末尾附近分裂，其余在中间分裂
+    #[default]
+    Auto,
+    /// 总是在中间分裂
+    Middle,
+    /// 总是在末尾附近分裂，右侧叶子节点几乎为空，适合以追加为主的工作负载
+    Last,
+}
+
+/// 叶子节点缓存的准入策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdmissionPolicy {
+    /// 读取的叶子节点总是进入缓存
+    #[default]
+    Always,
+    /// TinyLFU风格的准入过滤：用一个小的频率草图估计每个叶子节点最近的访问频率，
+    /// 缓存已满时，未缓存的叶子节点只有在估计频率超过它将替换的叶子节点时
+    /// 才进入缓存，否则直接从磁盘读取而不缓存。一次性的全量扫描因此不会挤出
+    /// 反复访问的热点数据。只影响 `CachePolicy::Normal` 的读取，写入总是需要
+    /// 把叶子节点载入缓存
+    TinyLfu,
+}
+
+/// 读取堆文件时的校验和检查方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumMode {
+    /// 不检查校验和，恢复时也不校验堆文件。损坏的数据会在反序列化时
+    /// 以普通的io错误的形式出现
+    Off,
+    /// 检查校验和，不匹配时返回 `CorruptionError`。恢复时校验堆文件（参见
+    /// `Config::verify_slots_on_open`）发现损坏的对象会导致打开数据库失败
+    #[default]
+    Verify,
+    /// 检查校验和，并隔离损坏的对象：之后对它的读取直接返回 `CorruptionError`
+    /// 而不再读取磁盘，直到它被重新写入或删除。恢复时校验堆文件发现损坏的对象
+    /// 会被隔离，数据库仍然可以打开
+    VerifyAndQuarantine,
+}
+
+/// 读取时发现对象的校验和不匹配时的处理方式，参见 `Config::on_corruption`
+#[derive(Clone, Default)]
+pub enum CorruptionPolicy {
+    /// 返回 `CorruptionError`，数据库的其他部分仍然可以使用
+    #[default]
+    Error,
+    /// 立即panic，不让调用方有机会使用损坏的数据
+    Panic,
+    /// 以 `CorruptionError` 调用回调，然后返回它。回调可以记录或报告损坏，
+    /// 也可以自己panic或终止进程
+    Callback(Arc<dyn Fn(&CorruptionError) + Send + Sync>),
+}
+
+impl fmt::Debug for CorruptionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorruptionPolicy::Error => f.write_str("Error"),
+            CorruptionPolicy::Panic => f.write_str("Panic"),
+            CorruptionPolicy::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// 写入堆文件的对象使用的校验和算法
+///
+/// 算法记录在每个对象中，读取时使用对象写入时的算法，因此更改这个选项后
+/// 已有的数据仍然可以读取，新的算法只用于之后写入的对象。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumKind {
+    /// 不写入校验和，读取时无法发现损坏。适用于ECC内存和带校验和的文件系统
+    None,
+    /// CRC32，4字节
+    #[default]
+    Crc32,
+    /// XXH64，8字节，比CRC32更能发现多位错误
+    XxHash64,
+}
+
+/// `Config::auto_compact_on_open` 触发的堆压缩的运行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutoCompact {
+    /// 在打开数据库的过程中完成压缩，`Config::open` 在压缩结束后才返回，
+    /// 进度通过 `recovery_progress_callback` 以 `RecoveryPhase::Compacting` 报告
+    #[default]
+    Blocking,
+    /// 打开数据库后立即在后台线程中压缩，`Config::open` 不等待它完成。
+    /// 数据库关闭时压缩在当前一轮结束后停止
+    Background,
+}
+
+/// 单个树的选项：叶子节点分裂/合并参数覆盖，未设置的项使用 `Config` 中的值，
+/// 叶子节点的值去重，以及只写一次模式。
+///
+/// 分裂/合并参数和值去重不会持久化，每次打开数据库后需要通过
+/// `Db::open_tree_with_options` 重新设置。`write_once` 在创建树时持久化，
+/// 之后不能更改。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeOptions {
+    /// 覆盖 `Config::leaf_split_threshold`
+    pub leaf_split_threshold: Option<usize>,
+    /// 覆盖 `Config::leaf_merge_threshold`
+    pub leaf_merge_threshold: Option<usize>,
+    /// 覆盖 `Config::split_bias`
+    pub split_bias: Option<SplitBias>,
+    /// 覆盖 `Config::truncate_split_keys`
+    pub truncate_split_keys: Option<bool>,
+    /// 序列化叶子节点时对叶子节点内相同的值去重：每个不同的值只存储一次，
+    /// 条目通过下标引用它。适合值来自一个很小的集合的树。
+    /// 只影响写入磁盘的格式，读取时还原为普通的条目，没有启用时写入的叶子节点
+    /// 同样可以读取
+    pub dedup_values: bool,
+    /// 参与去重的值的最小字节数，更小的值原样存储以避免引用的开销。
+    /// 默认为 `None`，即 `DEFAULT_DEDUP_MIN_VALUE_SIZE`
+    pub dedup_min_value_size: Option<usize>,
+    /// 只写一次模式，适合审计日志等只追加的数据：已存在的键不能被覆盖
+    /// （返回 `KeyAlreadyExists`），`remove`、`clear`、`pop_*`、包含删除或覆盖的
+    /// 批次以及 `Db::drop_tree` 都会返回错误，只能通过 `Tree::force` 和
+    /// `Db::force_drop_tree` 进行管理操作。从 `None` 到 `Some` 的比较并交换仍然允许。
+    ///
+    /// 此标志在创建树时持久化。用与之不同的 `write_once` 通过
+    /// `Db::open_tree_with_options` 打开已存在的树会返回错误
+    pub write_once: bool,
+}
+
+impl TreeOptions {
+    /// 返回不覆盖任何参数的 `TreeOptions`
+    pub fn new() -> TreeOptions {
+        TreeOptions::default()
+    }
+
+    /// 设置该树的叶子节点分裂阈值（构建器）
+    pub fn leaf_split_threshold(mut self, threshold: usize) -> TreeOptions {
+        self.leaf_split_threshold = Some(threshold);
+        self
+    }
+
+    /// 设置该树的叶子节点合并阈值（构建器）
+    pub fn leaf_merge_threshold(mut self, threshold: usize) -> TreeOptions {
+        self.leaf_merge_threshold = Some(threshold);
+        self
+    }
+
+    /// 设置该树的分裂点选择策略（构建器）
+    pub fn split_bias(mut self, split_bias: SplitBias) -> TreeOptions {
+        self.split_bias = Some(split_bias);
+        self
+    }
+
+    /// 设置该树是否缩短分裂键（构建器）
+    pub fn truncate_split_keys(mut self, truncate_split_keys: bool) -> TreeOptions {
+        self.truncate_split_keys = Some(truncate_split_keys);
+        self
+    }
+
+    /// 设置该树是否对叶子节点内相同的值去重（构建器）
+    pub fn dedup_values(mut self, dedup_values: bool) -> TreeOptions {
+        self.dedup_values = dedup_values;
+        self
+    }
+
+    /// 设置参与去重的值的最小字节数（构建器）
+    pub fn dedup_min_value_size(mut self, size: usize) -> TreeOptions {
+        self.dedup_min_value_size = Some(size);
+        self
+    }
+
+    /// 设置该树是否为只写一次模式（构建器）
+    pub fn write_once(mut self, write_once: bool) -> TreeOptions {
+        self.write_once = write_once;
+        self
+    }
+}
+
+/// `TreeOptions::dedup_min_value_size` 的默认值
+pub const DEFAULT_DEDUP_MIN_VALUE_SIZE: usize = 64;
+
+/// 校验叶子节点分裂/合并阈值，合并阈值必须不超过分裂阈值的四分之一，
+/// 以保证分裂后的叶子节点不会立即被合并，合并后的叶子节点也不会立即分裂。
+pub(crate) fn validate_flush_io_rate_limit(limit: Option<u64>) -> io::Result<()> {
+    if limit == Some(0) {
+        return Err(annotate!(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "flush_io_rate_limit 必须是正数"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_leaf_thresholds<const LEAF_FANOUT: usize>(
+    split_threshold: Option<usize>,
+    merge_threshold: Option<usize>,
+) -> io::Result<()> {
+    let split_threshold = split_threshold.unwrap_or(LEAF_FANOUT);
+
+    if split_threshold < 3 || split_threshold > LEAF_FANOUT {
+        return Err(annotate!(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "叶子节点分裂阈值 {} 必须在 3 到 LEAF_FANOUT ({}) 之间",
+                split_threshold, LEAF_FANOUT
+            )
+        )));
+    }
+
+    if let Some(merge_threshold) = merge_threshold
+        && merge_threshold > split_threshold / 4
+    {
+        return Err(annotate!(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "叶子节点合并阈值 {} 必须不超过分裂阈值 {} 的四分之一，以避免分裂和合并反复交替",
+                merge_threshold, split_threshold
+            )
+        )));
+    }
+
+    Ok(())
+}
+
+/// 打开数据库的阶段
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryPhase {
+    /// 校验堆文件中的对象
+    #[default]
+    Verifying,
+    /// 碎片率超过 `Config::auto_compact_on_open` 时压缩堆文件
+    Compacting,
+}
+
+/// 打开数据库时的恢复进度，通过 `Config::recovery_progress_callback` 定期报告
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// 当前所处的阶段，每个阶段的计数器分别从0开始
+    pub phase: RecoveryPhase,
+    /// 校验阶段为已校验的对象数，压缩阶段为已移动的对象数
+    pub objects_scanned: u64,
+    /// 校验阶段为需要校验的对象总数（根据恢复的元数据估计），
+    /// 压缩阶段为开始时需要移动的对象数，对象在压缩期间被写入时可能被超过
+    pub objects_total: u64,
+    /// 校验阶段为已从堆文件中读取的字节数，压缩阶段为已写入堆文件的字节数
+    pub bytes_processed: u64,
+}
+
+/// 恢复进度回调
+#[derive(Clone)]
+pub struct RecoveryProgressCallback(
+    pub(crate) Arc<dyn Fn(RecoveryProgress) + Send + Sync>,
+);
+
+impl fmt::Debug for RecoveryProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecoveryProgressCallback")
+    }
+}
+
+/// flush看门狗回调
+#[derive(Clone)]
+pub struct FlushWatchdogCallback(
+    pub(crate) Arc<dyn Fn(&FlushDebugReport) + Send + Sync>,
+);
+
+impl fmt::Debug for FlushWatchdogCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FlushWatchdogCallback")
+    }
+}
+
+/// 默认的恢复线程数：CPU核心数的一半，至少为1
+fn default_recovery_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get() / 2)
+        .unwrap_or(1)
+        .max(1)
+}
+
 macro_rules! builder {
     ($(($name:ident, $t:ty, $desc:expr)),*) => {
         $(
@@ -113,6 +408,12 @@ pub struct Config {
     pub zstd_compression_level: i32,
     /// 压缩算法选择。默认根据编译特性自动选择
     pub compression_algorithm: CompressionAlgorithm,
+    /// 配置了压缩算法时，小于此字节数的值不单独压缩而是原样存储，
+    /// 避免压缩帧的开销使很小的值反而变大。默认为64
+    pub value_compression_threshold: usize,
+    /// 叶子节点的zstd压缩字典。默认为 `None`，即每个叶子节点独立压缩。
+    /// 使用过的字典会持久化，去掉或更换字典后旧的数据仍然可以读取
+    pub compression_dictionary: Option<CompressionDictionary>,
     /// 这只为通过 `Config::tmp` 创建的对象设置为 `Some`，
     /// 并且在最后一个Arc删除时将删除存储目录
     pub tempdir_deleter: Option<Arc<TempDir>>,
@@ -123,12 +424,131 @@ pub struct Config {
     pub max_inline_value_threshold: usize,
     /// 增量序列化阈值（字节）。超过此大小的leaf节点将使用增量序列化
     pub incremental_serialization_threshold: usize,
-    /// 异步flush线程数。默认为2
+    /// 异步flush线程数。默认为2。目前没有使用，并行flush见 `flush_threads`
     pub flush_thread_count: usize,
     /// 缓存预热策略
     pub cache_warmup_strategy: CacheWarmupStrategy,
     /// 智能flush策略配置
     pub smart_flush_config: SmartFlushConfig,
+    /// 叶子节点分裂阈值（条目数），叶子节点的条目数达到此值时分裂。
+    /// 默认为 `None`，即叶子节点写满 `LEAF_FANOUT` 个条目时分裂
+    pub leaf_split_threshold: Option<usize>,
+    /// 叶子节点合并阈值（条目数），删除后叶子节点与其右侧兄弟节点的条目总数
+    /// 不超过此值时进行合并。默认为 `None`，即仅在叶子节点为空时合并
+    pub leaf_merge_threshold: Option<usize>,
+    /// 叶子节点分裂点选择策略
+    pub split_bias: SplitBias,
+    /// 叶子节点分裂时，以大于左侧最后一个键、不大于右侧第一个键的最短字节串
+    /// 作为右侧叶子节点的低键，而不是右侧第一个完整的键。低键同时是内存索引和
+    /// 元数据存储中的键，键有很长的公共前缀时可以显著缩小它们。
+    /// 两种低键可以在同一个数据库中共存，更改这个选项只影响之后的分裂。
+    /// 默认为 `true`
+    pub truncate_split_keys: bool,
+    /// 将后台flusher和布隆过滤器维护线程绑定到指定的CPU核心。默认为 `None`，
+    /// 即不绑定。在不支持的平台上仅输出警告
+    pub flusher_thread_affinity: Option<usize>,
+    /// 将后台flusher和布隆过滤器维护线程，以及并行flush和预读的线程池绑定到
+    /// 一组CPU核心，设置时优先于 `flusher_thread_affinity`。默认为 `None`，即不绑定
+    pub flusher_cpu_affinity: Option<Vec<usize>>,
+    /// 后台flusher线程的名称。默认为 `None`，即使用内置名称 `melange-flush`
+    pub flusher_thread_name: Option<String>,
+    /// 后台flusher和布隆过滤器维护线程，以及并行flush和预读的线程池的优先级。
+    /// 默认为 `None`，即不修改。权限不足时仅输出警告
+    pub flusher_thread_priority: Option<ThreadPriority>,
+    /// 恢复时并行校验堆文件的线程数。默认为CPU核心数的一半
+    pub recovery_threads: usize,
+    /// 打开数据库时总是读取并校验所有叶子节点。默认为 `false`，即只在上次没有
+    /// 正常关闭（进程崩溃或断电）时校验，正常关闭后打开不读取堆文件，
+    /// 损坏在第一次读取时才被发现
+    pub verify_slots_on_open: bool,
+    /// 读取堆文件时的校验和检查方式。默认为 `ChecksumMode::Verify`
+    pub checksum_mode: ChecksumMode,
+    /// 写入堆文件的对象使用的校验和算法。默认为 `ChecksumKind::Crc32`
+    pub checksum: ChecksumKind,
+    /// 读取对象时校验和不匹配的处理方式，只在 `checksum_mode` 不为
+    /// `ChecksumMode::Off` 时有效。恢复时发现的损坏由 `checksum_mode` 决定，
+    /// 隔离的对象之后的读取也不再触发。默认为 `CorruptionPolicy::Error`
+    pub on_corruption: CorruptionPolicy,
+    /// 恢复进度回调，在打开数据库的过程中被定期调用
+    pub recovery_progress_callback: Option<RecoveryProgressCallback>,
+    /// 叶子节点缓存的准入策略。默认为 `AdmissionPolicy::Always`
+    pub cache_admission: AdmissionPolicy,
+    /// 布隆过滤器的初始设计容量（元素数）。默认为1000000
+    pub bloom_filter_capacity: usize,
+    /// 预计写入的条目数。大于 `bloom_filter_capacity` 时布隆过滤器以此为初始容量，
+    /// 已知规模的批量导入不会使它的误判率上升而被 `bloom_auto_resize` 反复重建。
+    /// 树的索引是按节点分裂增长的B+树，不需要预先分配。计数器的数量见
+    /// `AtomicWorkerConfig::expected_counters`。默认为0，即没有提示
+    pub expected_entries: usize,
+    /// 启动一个后台维护线程，在布隆过滤器的误判率超过目标时，
+    /// 以更大的容量从所有树的有效键重建它。默认为 `false`
+    pub bloom_auto_resize: bool,
+    /// 后台维护线程检查布隆过滤器的间隔（毫秒）。默认为60000
+    pub bloom_resize_check_interval_ms: usize,
+    /// 刷新时元数据存储中的失效条目数超过有效条目数的此倍数时，在后台压缩
+    /// 元数据存储。默认为 `None`，即只在元数据日志达到内部的大小阈值时压缩
+    pub metadata_auto_compact_ratio: Option<f64>,
+    /// flush写入堆文件和元数据存储的速率上限（字节/秒）。每次flush写入后，
+    /// 如果写入的字节数按此速率需要更长的时间，则等待剩余的时间再返回。
+    /// 默认为 `None`，即不限制
+    pub flush_io_rate_limit: Option<u64>,
+    /// 诊断用的操作日志，记录树上每个成功的修改操作，见 `op_journal` 模块。
+    /// 默认为 `None`，即不记录
+    pub op_journal: Option<OpJournalConfig>,
+    /// 大于此大小（字节）的值单独存储在数据库目录的 `blobs` 子目录中，叶子节点中
+    /// 只保留引用，使扫描键和小值时不需要读取大值，见 `blob_store` 模块。
+    /// 默认为 `None`，即所有的值都存储在叶子节点中
+    pub inline_value_threshold: Option<usize>,
+    /// 一次flush运行超过此时间时，输出包含 `Db::debug_flush_state` 报告的错误日志
+    /// 并调用 `flush_watchdog_callback`。每次flush最多报告一次。
+    /// 默认为 `None`，即不检查
+    pub flush_watchdog: Option<Duration>,
+    /// flush看门狗发现超时的flush时调用的回调，在看门狗线程中被调用
+    pub flush_watchdog_callback: Option<FlushWatchdogCallback>,
+    /// 合并并发的 `Tree::flush` 和 `Db::flush_all` 调用：在另一个调用的flush
+    /// 运行期间到达的调用等待它完成，已经被它覆盖的调用直接返回，其余的调用
+    /// 只再进行一次flush。默认为 `true`
+    pub coalesce_flushes: bool,
+    /// `HybridOperationsManager` 的原子操作Worker的配置：计数器数量的上限和
+    /// 内存中计数器映射的哈希算法，见 `atomic_worker::AtomicWorkerConfig`
+    pub atomic_worker: AtomicWorkerConfig,
+    /// 启动一个后台线程，每隔这么久推进一次内存回收的epoch，释放被替换的
+    /// 索引节点，见 `Db::collect_garbage`。默认为 `None`，即只在操作中推进
+    pub gc_interval: Option<Duration>,
+    /// 仅在Windows上有效：以 `FILE_FLAG_WRITE_THROUGH` 打开堆的slab文件，写入直接
+    /// 到达磁盘而不停留在系统的写缓存中。slot大小是扇区对齐大小整数倍的slab文件
+    /// 还使用 `FILE_FLAG_NO_BUFFERING` 绕过系统缓存，读写这些文件时使用对齐的缓冲区。
+    /// 在其他平台上被忽略。默认为 `false`
+    pub windows_write_through: bool,
+    /// `Tree::pin` 固定的叶子节点最多占用的缓存百分比，超过时 `Tree::pin`
+    /// 返回 `io::ErrorKind::QuotaExceeded`，避免固定的键挤占其他数据的缓存。默认为10
+    pub max_pinned_cache_percent: u8,
+    /// 打开数据库时，如果堆文件的碎片率（slab文件中没有被存活对象占用的字节的比例）
+    /// 超过此值，就把对象移动到每个slab文件前部的空闲slot中并截断文件。
+    /// 每一步都和flush时的碎片整理一样可以安全地崩溃。必须在0.0到1.0之间（不含1.0）。
+    /// 没有只读模式，保持为 `None` 即可跳过。默认为 `None`，即不检查
+    pub auto_compact_on_open: Option<f64>,
+    /// `auto_compact_on_open` 触发的压缩的运行方式。默认为 `AutoCompact::Blocking`
+    pub auto_compact: AutoCompact,
+    /// `Tree::iter`、`Tree::range` 和 `Tree::scan_prefix` 正向扫描时在后台预读的
+    /// 叶子节点数，见 `IterOptions::read_ahead_leaves`。`*_with` 方法使用
+    /// `IterOptions` 中的值。默认为0，即不预读
+    pub read_ahead_leaves: usize,
+    /// 最近多少次写入了数据的flush所替换的叶子节点保留在堆文件中，使这些flush之前的
+    /// epoch可以通过 `Db::view_at_epoch` 读取。保留的叶子节点占用磁盘空间，
+    /// 直到更新的flush使它们超出这个数量，或者压缩堆文件。默认为0，即只能读取当前的状态
+    pub retained_flush_epochs: usize,
+    /// 每次flush序列化脏叶子节点的线程数。大于1时启动一个这么多线程的线程池，
+    /// 从共享的队列中取出这次flush的脏对象并行序列化，之后仍然作为一个批次
+    /// 写入堆文件，因此每个epoch仍然原子地持久化，各个flush也仍然按顺序进行。
+    /// 必须至少为1。默认为1，即在执行flush的线程中依次序列化
+    pub flush_threads: usize,
+    /// 打开数据库时在数据库目录中预留的磁盘空间（字节）。flush遇到文件系统已满时
+    /// 释放这些空间使这次flush可以完成，数据库随即进入只读降级状态，直到空间被释放，
+    /// 参见 `Db::try_resume_writes`。为0时不预留，空间不足时仍然进入只读降级状态，
+    /// 但当时正在进行的flush失败。默认在Linux上为16MiB，由 `posix_fallocate`
+    /// 分配而不写入数据；其他平台上只能写入数据来分配，默认为0
+    pub disk_full_headroom_bytes: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -152,6 +572,8 @@ impl Default for Config {
             entry_cache_percent: 20,
             zstd_compression_level: 3,
             compression_algorithm: CompressionAlgorithm::default(),
+            value_compression_threshold: 64,
+            compression_dictionary: None,
             tempdir_deleter: None,
             target_heap_file_fill_ratio: 0.9,
             max_inline_value_threshold: 4096,
@@ -159,6 +581,42 @@ impl Default for Config {
             flush_thread_count: 2,
             cache_warmup_strategy: CacheWarmupStrategy::Recent,
             smart_flush_config: SmartFlushConfig::default(),
+            leaf_split_threshold: None,
+            leaf_merge_threshold: None,
+            split_bias: SplitBias::default(),
+            truncate_split_keys: true,
+            flusher_thread_affinity: None,
+            flusher_cpu_affinity: None,
+            flusher_thread_name: None,
+            flusher_thread_priority: None,
+            recovery_threads: default_recovery_threads(),
+            verify_slots_on_open: false,
+            checksum_mode: ChecksumMode::default(),
+            checksum: ChecksumKind::default(),
+            on_corruption: CorruptionPolicy::default(),
+            recovery_progress_callback: None,
+            cache_admission: AdmissionPolicy::default(),
+            bloom_filter_capacity: 1_000_000,
+            expected_entries: 0,
+            bloom_auto_resize: false,
+            bloom_resize_check_interval_ms: 60_000,
+            metadata_auto_compact_ratio: None,
+            flush_io_rate_limit: None,
+            op_journal: None,
+            inline_value_threshold: None,
+            flush_watchdog: None,
+            flush_watchdog_callback: None,
+            coalesce_flushes: true,
+            atomic_worker: AtomicWorkerConfig::default(),
+            gc_interval: None,
+            windows_write_through: false,
+            max_pinned_cache_percent: 10,
+            auto_compact_on_open: None,
+            auto_compact: AutoCompact::default(),
+            read_ahead_leaves: 0,
+            retained_flush_epochs: 0,
+            flush_threads: 1,
+            disk_full_headroom_bytes: if cfg!(target_os = "linux") { 16 * 1024 * 1024 } else { 0 },
         }
     }
 }
@@ -181,23 +639,100 @@ impl Config {
         })
     }
 
+    /// 启用诊断用的操作日志，见 `op_journal` 模块
+    pub fn op_journal(mut self, op_journal: OpJournalConfig) -> Config {
+        self.op_journal = Some(op_journal);
+        self
+    }
+
+    /// 将大于 `threshold` 字节的值单独存储，见 `inline_value_threshold` 字段
+    pub fn inline_value_threshold(mut self, threshold: usize) -> Config {
+        self.inline_value_threshold = Some(threshold);
+        self
+    }
+
     /// 设置数据库的路径（构建器）
     pub fn path<P: AsRef<Path>>(mut self, path: P) -> Config {
         self.path = path.as_ref().to_path_buf();
         self
     }
 
+    /// 设置叶子节点分裂阈值（条目数）（构建器）
+    pub fn leaf_split_threshold(mut self, threshold: usize) -> Config {
+        self.leaf_split_threshold = Some(threshold);
+        self
+    }
+
+    /// 设置叶子节点合并阈值（条目数）（构建器）。
+    /// 必须不超过分裂阈值的四分之一，在打开数据库时校验
+    pub fn leaf_merge_threshold(mut self, threshold: usize) -> Config {
+        self.leaf_merge_threshold = Some(threshold);
+        self
+    }
+
+    /// 设置恢复进度回调（构建器）。回调会在恢复线程中被调用，
+    /// 每次调用报告的进度单调递增
+    pub fn recovery_progress_callback(
+        mut self,
+        callback: Arc<dyn Fn(RecoveryProgress) + Send + Sync>,
+    ) -> Config {
+        self.recovery_progress_callback =
+            Some(RecoveryProgressCallback(callback));
+        self
+    }
+
+    /// 设置flush看门狗回调（构建器），只在设置了 `flush_watchdog` 时被调用
+    pub fn flush_watchdog_callback(
+        mut self,
+        callback: Arc<dyn Fn(&FlushDebugReport) + Send + Sync>,
+    ) -> Config {
+        self.flush_watchdog_callback = Some(FlushWatchdogCallback(callback));
+        self
+    }
+
     builder!(
         (flush_every_ms, Option<usize>, "启动一个后台线程，每隔几毫秒将数据刷新到磁盘。默认为每200ms一次。"),
         (cache_capacity_bytes, usize, "缓存大小（字节）。默认为512mb。"),
         (entry_cache_percent, u8, "分配给扫描抗性入口缓存的缓存百分比。"),
         (zstd_compression_level, i32, "将数据写入磁盘时使用的zstd压缩级别。默认为3。"),
         (compression_algorithm, CompressionAlgorithm, "压缩算法选择。默认根据编译特性自动选择。"),
+        (value_compression_threshold, usize, "配置了压缩算法时，小于此字节数的值不单独压缩而是原样存储。默认为64。"),
+        (compression_dictionary, Option<CompressionDictionary>, "叶子节点的zstd压缩字典。默认为 `None`，即每个叶子节点独立压缩。"),
         (target_heap_file_fill_ratio, f32, "0.0到1.0之间的浮点数，控制文件中可以存在多少碎片，然后GC尝试重新压缩它。"),
         (max_inline_value_threshold, usize, "大于此可配置值的值将作为单独的blob存储。"),
         (incremental_serialization_threshold, usize, "增量序列化阈值（字节）。超过此大小的leaf节点将使用增量序列化。"),
         (flush_thread_count, usize, "异步flush线程数。默认为2。"),
-        (cache_warmup_strategy, CacheWarmupStrategy, "缓存预热策略。")
+        (cache_warmup_strategy, CacheWarmupStrategy, "缓存预热策略。"),
+        (split_bias, SplitBias, "叶子节点分裂点选择策略。"),
+        (truncate_split_keys, bool, "叶子节点分裂时以最短的分隔字节串作为右侧叶子节点的低键。默认为 `true`。"),
+        (flusher_thread_affinity, Option<usize>, "将后台flusher和布隆过滤器维护线程绑定到指定的CPU核心，在不支持的平台上仅输出警告。"),
+        (flusher_cpu_affinity, Option<Vec<usize>>, "将后台flusher和布隆过滤器维护线程绑定到一组CPU核心，优先于 `flusher_thread_affinity`，在不支持的平台上仅输出警告。"),
+        (flusher_thread_name, Option<String>, "后台flusher线程的名称。"),
+        (flusher_thread_priority, Option<ThreadPriority>, "后台flusher和布隆过滤器维护线程的优先级，权限不足或平台不支持时仅输出警告。"),
+        (recovery_threads, usize, "恢复时并行校验堆文件的线程数。默认为CPU核心数的一半。"),
+        (verify_slots_on_open, bool, "打开数据库时总是校验所有叶子节点，而不只是在上次没有正常关闭时。默认为 `false`。"),
+        (checksum_mode, ChecksumMode, "读取堆文件时的校验和检查方式。默认为 `ChecksumMode::Verify`。"),
+        (checksum, ChecksumKind, "写入堆文件的对象使用的校验和算法。已有的对象仍然按照写入时的算法校验。默认为 `ChecksumKind::Crc32`。"),
+        (on_corruption, CorruptionPolicy, "读取对象时校验和不匹配的处理方式：返回错误、panic或调用回调。默认为 `CorruptionPolicy::Error`。"),
+        (cache_admission, AdmissionPolicy, "叶子节点缓存的准入策略。默认为 `AdmissionPolicy::Always`。"),
+        (bloom_filter_capacity, usize, "布隆过滤器的初始设计容量（元素数）。默认为1000000。"),
+        (expected_entries, usize, "预计写入的条目数，大于 `bloom_filter_capacity` 时作为布隆过滤器的初始容量。默认为0，即没有提示。"),
+        (bloom_auto_resize, bool, "启动一个后台维护线程，在布隆过滤器的误判率超过目标时，以更大的容量从所有树的有效键重建它。默认为 `false`。"),
+        (bloom_resize_check_interval_ms, usize, "后台维护线程检查布隆过滤器的间隔（毫秒）。默认为60000。"),
+        (metadata_auto_compact_ratio, Option<f64>, "刷新时元数据存储中的失效条目数超过有效条目数的此倍数时，在后台压缩元数据存储。必须为正数。默认为 `None`。"),
+        (flush_io_rate_limit, Option<u64>, "flush写入的速率上限（字节/秒）。必须为正数。默认为 `None`，即不限制。"),
+        (flush_watchdog, Option<Duration>, "一次flush运行超过此时间时输出错误日志并调用 `flush_watchdog_callback`。必须为正数。默认为 `None`。"),
+        (coalesce_flushes, bool, "合并并发的 `Tree::flush` 和 `Db::flush_all` 调用。默认为 `true`。"),
+        (atomic_worker, AtomicWorkerConfig, "原子操作Worker的计数器数量上限和哈希算法。默认不限制计数器数量。"),
+        (gc_interval, Option<Duration>, "后台内存回收线程推进epoch的间隔。必须为正数。默认为 `None`，即不启动。"),
+        (windows_write_through, bool, "仅在Windows上有效：以 `FILE_FLAG_WRITE_THROUGH` 打开堆文件，扇区对齐的slab文件还使用 `FILE_FLAG_NO_BUFFERING`。默认为 `false`。"),
+        (max_pinned_cache_percent, u8, "`Tree::pin` 固定的叶子节点最多占用的缓存百分比，不能超过100。默认为10。"),
+        (auto_compact_on_open, Option<f64>, "打开数据库时堆文件的碎片率超过此值则压缩堆文件，必须在0.0到1.0之间（不含1.0）。默认为 `None`，即不检查。"),
+        (auto_compact, AutoCompact, "`auto_compact_on_open` 触发的压缩在打开过程中完成还是在后台运行。默认为 `AutoCompact::Blocking`。"),
+        (read_ahead_leaves, usize, "`Tree::iter`、`Tree::range` 和 `Tree::scan_prefix` 正向扫描时在后台预读的叶子节点数。默认为0，即不预读。"),
+        (retained_flush_epochs, usize, "保留最近多少次flush替换的叶子节点，供 `Db::view_at_epoch` 读取之前的epoch。默认为0，即只能读取当前的状态。"),
+        (flush_threads, usize, "每次flush并行序列化脏叶子节点的线程数，必须至少为1。默认为1。"),
+        (disk_full_headroom_bytes, u64, "打开数据库时预留的磁盘空间，在文件系统已满时释放，使正在进行的flush可以完成。默认在Linux上为16MiB，其他平台上为0。")
     );
 
     pub fn open<const LEAF_FANOUT: usize>(
@@ -209,6 +744,59 @@ impl Config {
                 "Db的LEAF_FANOUT const泛型必须为3或更大。"
             )));
         }
+        validate_leaf_thresholds::<LEAF_FANOUT>(
+            self.leaf_split_threshold,
+            self.leaf_merge_threshold,
+        )?;
+        if let Some(ratio) = self.metadata_auto_compact_ratio
+            && !(ratio > 0.0 && ratio.is_finite())
+        {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("metadata_auto_compact_ratio 必须是正数，实际为 {}", ratio)
+            )));
+        }
+        self.smart_flush_config.validate()?;
+        validate_flush_io_rate_limit(self.flush_io_rate_limit)?;
+        if self.flush_watchdog == Some(Duration::ZERO) {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "flush_watchdog 必须是正数"
+            )));
+        }
+        if self.gc_interval == Some(Duration::ZERO) {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gc_interval 必须是正数"
+            )));
+        }
+        if self.flush_threads == 0 {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "flush_threads 必须至少为1"
+            )));
+        }
+        if self.max_pinned_cache_percent > 100 {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "max_pinned_cache_percent 不能超过100，实际为 {}",
+                    self.max_pinned_cache_percent
+                )
+            )));
+        }
+        if let Some(ratio) = self.auto_compact_on_open
+            && !(0.0..1.0).contains(&ratio)
+        {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("auto_compact_on_open 必须在0.0到1.0之间（不含1.0），实际为 {}", ratio)
+            )));
+        }
+        if let Some(op_journal) = &self.op_journal {
+            op_journal.validate()?;
+        }
+        self.atomic_worker.validate()?;
         Db::open_with_config(self)
     }
 }
\ No newline at end of file
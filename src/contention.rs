@@ -0,0 +1,156 @@
+//! 比较并交换的竞争统计和有界重试
+//!
+//! `Tree::update_and_fetch`、`Tree::fetch_and_update`、`Tree::pop_first` 等辅助
+//! 方法先读取当前值再比较并交换，期间其他线程修改了这个键时交换失败并重试，
+//! `IndexedTree` 的守卫批次也是如此。每次这样的失败记为一次竞争：
+//!
+//! - 计入树的 `TreeStats::cas_conflicts`。直接调用 `Tree::compare_and_swap` 时
+//!   无法区分调用者的期望值是过期了还是本来就不对，失败计入
+//!   `TreeStats::cas_mismatches`
+//! - 按键计入热点键统计，`Tree::contention_report` 返回竞争最多的键。最多跟踪
+//!   `TRACKED_KEYS` 个键，已满时替换计数最小的键并继承它的计数（Space-Saving
+//!   算法），因此计数是上界，但真正的热点键不会被挤出。统计只保留键的哈希、
+//!   长度和前 `KEY_PREFIX_LEN` 个字节
+//! - 辅助方法的 `*_with_retry` 版本按 `RetryPolicy` 在重试之前等待，超出重试
+//!   次数时返回 `ContentionExceeded`，而不是无限重试
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::sync::atomic::Ordering;
+
+use inline_array::InlineArray;
+use parking_lot::Mutex;
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{Backoff, ContentionExceeded};
+
+/// 热点键统计最多跟踪的键数
+const TRACKED_KEYS: usize = 128;
+
+/// `ContendedKey::key_prefix` 保留的字节数
+const KEY_PREFIX_LEN: usize = 16;
+
+/// 基于比较并交换的操作遇到竞争时的重试方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// 第一次尝试之后最多重试的次数
+    pub max_retries: usize,
+    /// 每次重试之前的等待
+    pub backoff: Backoff,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy { max_retries: 100, backoff: Backoff::default() }
+    }
+}
+
+/// `Tree::contention_report` 中的一个键
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContendedKey {
+    /// 完整的键的哈希
+    pub key_hash: u64,
+    /// 键的前16个字节
+    pub key_prefix: InlineArray,
+    /// 完整的键的长度
+    pub key_len: usize,
+    /// 自上次重置以来的竞争次数，是上界，参见模块文档
+    pub conflicts: u64,
+}
+
+/// 一个树的竞争统计，由树的所有句柄共享
+#[derive(Debug, Default)]
+pub(crate) struct ContentionTracker {
+    conflicts: PortableAtomicU64,
+    mismatches: PortableAtomicU64,
+    keys: Mutex<HashMap<u64, ContendedKey>>,
+}
+
+impl ContentionTracker {
+    pub(crate) fn conflicts(&self) -> u64 {
+        self.conflicts.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn mismatches(&self) -> u64 {
+        self.mismatches.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_mismatch(&self) {
+        self.mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_conflict(&self, key: &[u8]) {
+        self.conflicts.fetch_add(1, Ordering::Relaxed);
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let key_hash = hasher.finish();
+
+        let mut keys = self.keys.lock();
+        if let Some(tracked) = keys.get_mut(&key_hash) {
+            tracked.conflicts += 1;
+            return;
+        }
+
+        let mut conflicts = 1;
+        if keys.len() >= TRACKED_KEYS {
+            let (&evicted, min) = keys
+                .iter()
+                .min_by_key(|(_, tracked)| tracked.conflicts)
+                .unwrap();
+            conflicts += min.conflicts;
+            keys.remove(&evicted);
+        }
+        keys.insert(
+            key_hash,
+            ContendedKey {
+                key_hash,
+                key_prefix: InlineArray::from(&key[..key.len().min(KEY_PREFIX_LEN)]),
+                key_len: key.len(),
+                conflicts,
+            },
+        );
+    }
+
+    /// 竞争最多的 `top_n` 个键，按竞争次数从多到少排序
+    pub(crate) fn report(&self, top_n: usize) -> Vec<ContendedKey> {
+        let mut report: Vec<ContendedKey> = self.keys.lock().values().cloned().collect();
+        report.sort_by(|a, b| b.conflicts.cmp(&a.conflicts).then(a.key_hash.cmp(&b.key_hash)));
+        report.truncate(top_n);
+        report
+    }
+
+    pub(crate) fn reset_report(&self) {
+        self.keys.lock().clear();
+    }
+}
+
+/// 一次重试操作遇到的竞争
+pub(crate) struct Retries<'a> {
+    tracker: &'a ContentionTracker,
+    policy: Option<RetryPolicy>,
+    conflicts: usize,
+}
+
+impl<'a> Retries<'a> {
+    /// `policy` 为 `None` 时不等待，也不限制重试次数
+    pub(crate) fn new(tracker: &'a ContentionTracker, policy: Option<RetryPolicy>) -> Retries<'a> {
+        Retries { tracker, policy, conflicts: 0 }
+    }
+
+    /// 记录 `key` 上的一次竞争，然后按策略等待。超出重试次数时返回 `ContentionExceeded`
+    pub(crate) fn conflict(&mut self, key: &[u8]) -> io::Result<()> {
+        self.tracker.record_conflict(key);
+        self.conflicts += 1;
+
+        let Some(policy) = &self.policy else {
+            return Ok(());
+        };
+        if self.conflicts > policy.max_retries {
+            return Err(ContentionExceeded { key: key.into(), attempts: self.conflicts }.into());
+        }
+        policy.backoff.wait(u32::try_from(self.conflicts - 1).unwrap_or(u32::MAX));
+        Ok(())
+    }
+}
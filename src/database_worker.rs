@@ -2,19 +2,229 @@
 //!
 //! 专门处理所有数据库操作，避免与原子操作Worker产生EBR冲突
 
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 use std::io;
 
 use crossbeam_queue::SegQueue;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLockReadGuard};
 
-use crate::{debug_log, trace_log, warn_log, error_log, info_log, InlineArray};
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{debug_log, trace_log, warn_log, Batch, InlineArray};
 use crate::db::Db;
+use crate::tree::SnapshotIter;
+
+/// 批量写入中每个键及其之前的值
+type PreviousValues = Vec<(InlineArray, Option<InlineArray>)>;
+
+type GetResponder = std::sync::mpsc::Sender<io::Result<Option<InlineArray>>>;
+
+type ScanResponder = std::sync::mpsc::Sender<io::Result<Vec<(Vec<u8>, Vec<u8>)>>>;
+
+/// 前缀扫描每次处理的最大条目数。处理完一批后扫描被放回队列末尾，
+/// 之前排队的操作得以执行
+const SCAN_CHUNK_SIZE: usize = 1024;
+
+/// 启用读取优先时，有等待中的写入操作的情况下最多连续处理的读取操作数
+const READ_BURST: usize = 16;
+
+/// 原子计数器在数据库中的键前缀
+pub(crate) const COUNTER_KEY_PREFIX: &[u8] = b"__atomic_counter__:";
+
+/// 带版本的计数器值格式：1字节版本号 + 8字节小端序u64。
+/// 旧版本直接存储8字节小端序u64，预热时仍然可以读取
+const COUNTER_FORMAT_V1: u8 = 1;
+
+/// 浮点计数器的值格式：1字节版本号 + 8字节小端序IEEE 754 f64。
+/// 与整数计数器使用相同的键，一个名称只能是其中一种计数器
+const COUNTER_FORMAT_F64: u8 = 2;
+
+/// 计数器的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CounterKind {
+    /// 通过 `increment` 等操作访问的u64计数器
+    U64,
+    /// 通过 `add_f64` 等操作访问的f64计数器
+    F64,
+}
+
+/// 以另一种类型的操作访问已存在的计数器时返回的错误，例如对u64计数器调用
+/// `add_f64`。
+///
+/// 以 `io::ErrorKind::InvalidInput` 的 `io::Error` 的形式返回，
+/// 可以通过 `CounterTypeMismatch::from_io_error` 取出。计数器没有被修改
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CounterTypeMismatch {
+    pub counter_name: String,
+    /// 操作要求的类型
+    pub expected: CounterKind,
+    /// 计数器实际的类型
+    pub found: CounterKind,
+}
+
+impl CounterTypeMismatch {
+    /// 如果 `error` 是由计数器类型不匹配引起的，返回对应的 `CounterTypeMismatch`
+    pub fn from_io_error(error: &io::Error) -> Option<&CounterTypeMismatch> {
+        error.get_ref()?.downcast_ref::<CounterTypeMismatch>()
+    }
+}
+
+impl fmt::Display for CounterTypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "counter {:?} is a {:?} counter, not a {:?} counter",
+            self.counter_name, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for CounterTypeMismatch {}
+
+impl From<CounterTypeMismatch> for io::Error {
+    fn from(error: CounterTypeMismatch) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+/// 一个计数器的值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CounterValue {
+    U64(u64),
+    F64(f64),
+}
+
+impl fmt::Display for CounterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterValue::U64(value) => value.fmt(f),
+            CounterValue::F64(value) => value.fmt(f),
+        }
+    }
+}
+
+/// 预热计数器时无法加载的条目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreloadIssue {
+    /// 完整的键，包括 `__atomic_counter__:` 前缀
+    pub key: Vec<u8>,
+    /// 无法加载的原因
+    pub reason: PreloadIssueReason,
+    /// 值的字节数
+    pub raw_len: usize,
+}
+
+/// 计数器条目无法加载的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreloadIssueReason {
+    /// 计数器名称不是有效的UTF-8
+    NonUtf8Name,
+    /// 值既不是旧版的8字节格式，也不是带版本的9字节格式
+    InvalidLength,
+    /// 值带有未知的格式版本号
+    UnknownVersion(u8),
+}
+
+/// 预热计数器的结果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CounterPreloadReport {
+    /// 成功加载的计数器
+    pub counters: Vec<(String, u64)>,
+    /// 成功加载的浮点计数器
+    pub float_counters: Vec<(String, f64)>,
+    /// 仍以旧版8字节格式存储的计数器名称，
+    /// 可以通过 `HybridOperationsManager::upgrade_counter_format` 升级
+    pub legacy: Vec<String>,
+    /// 被跳过的条目
+    pub issues: Vec<PreloadIssue>,
+}
+
+/// 数据库操作Worker的读取统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseWorkerStats {
+    /// 提交的获取请求数
+    pub get_requests: u64,
+    /// 实际执行的数据库读取数
+    pub reads: u64,
+    /// 合并到其他请求的读取中的获取请求数
+    pub coalesced_gets: u64,
+}
+
+/// 相同键的并发获取请求的合并状态（single-flight）
+///
+/// 启用合并时，提交获取请求的线程如果发现同一个键已有尚未开始执行的读取，
+/// 就只登记自己的响应通道，等待该读取的结果。Worker在开始读取之前取走所有
+/// 登记的响应通道，之后到达的请求会发起新的读取，因此每个请求得到的值
+/// 都不早于它提交的时刻
+#[derive(Debug, Default)]
+struct GetCoalescing {
+    enabled: AtomicBool,
+    in_flight: Mutex<HashMap<Vec<u8>, Vec<GetResponder>>>,
+    get_requests: PortableAtomicU64,
+    reads: PortableAtomicU64,
+    coalesced_gets: PortableAtomicU64,
+}
+
+/// 读取优先的调度状态
+///
+/// 启用后，获取、前缀扫描和检查键是否存在的操作进入单独的读取队列，Worker
+/// 优先处理读取队列，读取不必排在大量写入之后。有等待中的写入时，每连续处理
+/// `READ_BURST` 个读取就处理一个写入，写入不会被持续的读取饿死。
+///
+/// 同一个线程的操作总是等待前一个操作完成后才提交，调度顺序不影响它读到
+/// 自己写入的值。原子操作Worker异步发送的计数器持久化指令仍在写入队列中，
+/// 之后提交的读取可能先于它们执行
+#[derive(Debug, Default)]
+struct ReadPriority {
+    enabled: AtomicBool,
+    reads: SegQueue<DatabaseOperation>,
+}
+
+impl ReadPriority {
+    /// 把操作放入它所属的队列
+    fn push(&self, queue: &SegQueue<DatabaseOperation>, operation: DatabaseOperation) {
+        if operation.is_read() && self.enabled.load(Ordering::Acquire) {
+            self.reads.push(operation);
+        } else {
+            queue.push(operation);
+        }
+    }
+
+    /// 取出下一个要处理的操作，`reads_in_row` 记录连续处理的读取数
+    fn pop(&self, queue: &SegQueue<DatabaseOperation>, reads_in_row: &mut usize) -> Option<DatabaseOperation> {
+        if *reads_in_row < READ_BURST
+            && let Some(operation) = self.reads.pop()
+        {
+            *reads_in_row += 1;
+            return Some(operation);
+        }
+        *reads_in_row = 0;
+        queue.pop().or_else(|| self.reads.pop())
+    }
+}
+
+/// 分批执行的前缀扫描
+///
+/// 扫描读取的是开始时刻的快照，分批执行不影响结果的一致性。两批之间不持有
+/// EBR guard或叶子节点的锁
+pub(crate) struct PrefixScan {
+    iter: SnapshotIter<1024>,
+    items: Vec<(Vec<u8>, Vec<u8>)>,
+    response_tx: ScanResponder,
+}
+
+impl fmt::Debug for PrefixScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixScan").field("items", &self.items.len()).finish()
+    }
+}
 
 /// 数据库操作类型
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub(crate) enum DatabaseOperation {
     /// 插入数据
     Insert {
@@ -25,28 +235,42 @@ pub(crate) enum DatabaseOperation {
     /// 获取数据
     Get {
         key: Vec<u8>,
-        response_tx: std::sync::mpsc::Sender<io::Result<Option<InlineArray>>>,
+        response_tx: GetResponder,
+    },
+    /// 获取数据，结果发送给所有登记在合并状态中的请求
+    CoalescedGet {
+        key: Vec<u8>,
     },
-    /// 原子计数器持久化
+    /// 原子计数器持久化，返回之前持久化的值
     PersistCounter {
         counter_name: String,
-        value: u64,
-        response_tx: std::sync::mpsc::Sender<io::Result<()>>,
+        value: CounterValue,
+        response_tx: std::sync::mpsc::Sender<io::Result<Option<CounterValue>>>,
     },
     /// 预热计数器
     PreloadCounters {
-        response_tx: std::sync::mpsc::Sender<io::Result<Vec<(String, u64)>>>,
+        response_tx: std::sync::mpsc::Sender<io::Result<CounterPreloadReport>>,
     },
     /// 扫描前缀
     ScanPrefix {
         prefix: Vec<u8>,
-        response_tx: std::sync::mpsc::Sender<io::Result<Vec<(Vec<u8>, Vec<u8>)>>>,
+        response_tx: ScanResponder,
+    },
+    /// 继续执行尚未完成的前缀扫描
+    ContinueScan {
+        scan: Box<PrefixScan>,
     },
     /// 删除数据
     Remove {
         key: Vec<u8>,
         response_tx: std::sync::mpsc::Sender<io::Result<Option<InlineArray>>>,
     },
+    /// 批量写入，`return_previous` 为true时按键顺序返回之前的值
+    ApplyBatch {
+        batch: Batch,
+        return_previous: bool,
+        response_tx: std::sync::mpsc::Sender<io::Result<PreviousValues>>,
+    },
     /// 检查键是否存在
     ContainsKey {
         key: Vec<u8>,
@@ -74,6 +298,47 @@ pub(crate) enum DatabaseOperation {
     },
 }
 
+impl DatabaseOperation {
+    /// 启用读取优先时优先处理的操作
+    fn is_read(&self) -> bool {
+        matches!(
+            self,
+            DatabaseOperation::Get { .. }
+                | DatabaseOperation::CoalescedGet { .. }
+                | DatabaseOperation::ScanPrefix { .. }
+                | DatabaseOperation::ContinueScan { .. }
+                | DatabaseOperation::ContainsKey { .. }
+        )
+    }
+}
+
+/// Worker处理每个操作时持有读锁，持有写锁时Worker暂停（测试用）。
+/// 只在启用 `for-internal-testing-only` 特性时存在，否则处理操作时不加锁
+#[derive(Clone, Default)]
+pub(crate) struct PauseLock {
+    #[cfg(feature = "for-internal-testing-only")]
+    lock: Arc<parking_lot::RwLock<()>>,
+}
+
+impl PauseLock {
+    /// 处理一个操作期间持有，Worker被暂停时等待
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn hold(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        Some(self.lock.read())
+    }
+
+    #[cfg(not(feature = "for-internal-testing-only"))]
+    pub(crate) fn hold(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        None
+    }
+
+    /// 暂停Worker，直到返回的guard被释放。正在处理的操作会先完成
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn pause(&self) -> parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()> {
+        self.lock.write_arc()
+    }
+}
+
 /// 数据库操作Worker
 ///
 /// 专门处理所有数据库操作，与原子操作完全解耦
@@ -86,6 +351,16 @@ pub(crate) struct DatabaseWorker {
 
     /// 关闭信号
     shutdown_tx: Option<std::sync::mpsc::Sender<()>>,
+
+    /// 获取请求的合并状态和读取统计
+    coalescing: Arc<GetCoalescing>,
+
+    /// 读取优先的调度状态和读取队列
+    read_priority: Arc<ReadPriority>,
+
+    /// 见 `PauseLock`
+    #[cfg(feature = "for-internal-testing-only")]
+    pause: PauseLock,
 }
 
 impl DatabaseWorker {
@@ -98,17 +373,37 @@ impl DatabaseWorker {
         let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
 
         let worker_queue = operation_queue.clone();
-
-        let worker_handle = thread::spawn(move || {
-            debug_log!("数据库操作Worker线程启动");
-            Self::worker_loop(worker_queue, db, shutdown_rx);
-            debug_log!("数据库操作Worker线程退出");
-        });
+        let coalescing = Arc::new(GetCoalescing::default());
+        let worker_coalescing = coalescing.clone();
+        let read_priority = Arc::new(ReadPriority::default());
+        let worker_read_priority = read_priority.clone();
+        let pause = PauseLock::default();
+        let worker_pause = pause.clone();
+
+        let worker_handle = thread::Builder::new()
+            .name("melange-db-worker".into())
+            .spawn(move || {
+                debug_log!("数据库操作Worker线程启动");
+                Self::worker_loop(
+                    worker_queue,
+                    db,
+                    worker_coalescing,
+                    worker_read_priority,
+                    worker_pause,
+                    shutdown_rx,
+                );
+                debug_log!("数据库操作Worker线程退出");
+            })
+            .expect("无法创建数据库操作Worker线程");
 
         Self {
             operation_queue,
             worker_handle: Some(worker_handle),
             shutdown_tx: Some(shutdown_tx),
+            coalescing,
+            read_priority,
+            #[cfg(feature = "for-internal-testing-only")]
+            pause,
         }
     }
 
@@ -116,6 +411,9 @@ impl DatabaseWorker {
     fn worker_loop(
         operation_queue: Arc<SegQueue<DatabaseOperation>>,
         db: Arc<Db<1024>>,
+        coalescing: Arc<GetCoalescing>,
+        read_priority: Arc<ReadPriority>,
+        pause: PauseLock,
         shutdown_rx: std::sync::mpsc::Receiver<()>,
     ) {
         // 智能休眠参数
@@ -125,11 +423,16 @@ impl DatabaseWorker {
 
         let mut idle_count = 0;               // 连续空闲次数
         let mut current_sleep_us = BASE_SLEEP_US;
+        let mut reads_in_row = 0;               // 连续处理的读取数
 
         loop {
             // 检查关闭信号
             match shutdown_rx.try_recv() {
                 Ok(_) | Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                    // 退出前处理已提交的操作，避免丢失原子操作Worker发送的持久化指令
+                    while let Some(operation) = read_priority.pop(&operation_queue, &mut reads_in_row) {
+                        Self::handle_operation(&db, &operation_queue, &coalescing, &read_priority, operation);
+                    }
                     debug_log!("收到关闭信号，DatabaseWorker退出");
                     break;
                 }
@@ -139,8 +442,9 @@ impl DatabaseWorker {
             }
 
             // 处理操作队列
-            if let Some(operation) = operation_queue.pop() {
-                Self::handle_operation(&db, operation);
+            if let Some(operation) = read_priority.pop(&operation_queue, &mut reads_in_row) {
+                let _pause = pause.hold();
+                Self::handle_operation(&db, &operation_queue, &coalescing, &read_priority, operation);
                 // 有操作时重置空闲计数和休眠时间
                 idle_count = 0;
                 current_sleep_us = BASE_SLEEP_US;
@@ -164,62 +468,71 @@ impl DatabaseWorker {
     }
 
     /// 处理单个数据库操作
-    fn handle_operation(db: &Db<1024>, operation: DatabaseOperation) {
+    fn handle_operation(
+        db: &Db<1024>,
+        queue: &SegQueue<DatabaseOperation>,
+        coalescing: &GetCoalescing,
+        read_priority: &ReadPriority,
+        operation: DatabaseOperation,
+    ) {
         match operation {
             DatabaseOperation::Insert { key, value, response_tx } => {
                 let result = db.insert(&key, &*value);
                 let _ = response_tx.send(result);
             }
             DatabaseOperation::Get { key, response_tx } => {
+                coalescing.reads.fetch_add(1, Ordering::Relaxed);
                 let result = db.get(&key);
                 let _ = response_tx.send(result);
             }
+            DatabaseOperation::CoalescedGet { key } => {
+                // 先取走所有登记的请求再读取，之后到达的请求会发起新的读取
+                let waiters = coalescing.in_flight.lock().remove(&key).unwrap_or_default();
+                coalescing.reads.fetch_add(1, Ordering::Relaxed);
+                let result = db.get(&key);
+                trace_log!(op = "coalesced_get", key_len = key.len(), waiters = waiters.len(); "合并读取 {:?}，共 {} 个请求", key, waiters.len());
+                for response_tx in waiters {
+                    let response = match &result {
+                        Ok(value) => Ok(value.clone()),
+                        Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                    };
+                    let _ = response_tx.send(response);
+                }
+            }
             DatabaseOperation::PersistCounter { counter_name, value, response_tx } => {
-                trace_log!("持久化计数器: {} = {}", counter_name, value);
-                let key = format!("__atomic_counter__:{}", counter_name);
-                let result = db.insert(key.as_bytes(), &value.to_le_bytes()).map(|_| ());
+                trace_log!(op = "persist_counter", counter = counter_name.as_str(), value = value.to_string(); "持久化计数器: {} = {}", counter_name, value);
+                let key = counter_key(&counter_name);
+                let result = db
+                    .insert(key, &encode_counter_value(value)[..])
+                    .map(|previous| previous.and_then(|bytes| decode_counter(&bytes)));
                 let _ = response_tx.send(result);
             }
             DatabaseOperation::PreloadCounters { response_tx } => {
-                debug_log!("开始预热计数器...");
-                let mut counters = Vec::new();
-
-                let prefix = b"__atomic_counter__:";
-                for item_res in db.scan_prefix(prefix) {
-                    if let Ok((key_bytes, value_bytes)) = item_res {
-                        let key_bytes = &*key_bytes;
-                        let value_bytes = &*value_bytes;
-
-                        if let Ok(key_str) = std::str::from_utf8(key_bytes) {
-                            if let Some(counter_name) = key_str.strip_prefix("__atomic_counter__:") {
-                                if value_bytes.len() >= 8 {
-                                    let mut arr = [0u8; 8];
-                                    arr.copy_from_slice(&value_bytes[..8]);
-                                    let value = u64::from_le_bytes(arr);
-                                    counters.push((counter_name.to_string(), value));
-                                }
-                            }
-                        }
-                    }
-                }
-
-                debug_log!("预热完成，加载了 {} 个计数器", counters.len());
-                let _ = response_tx.send(Ok(counters));
+                let _ = response_tx.send(load_counters(db));
             }
             DatabaseOperation::ScanPrefix { prefix, response_tx } => {
-                let result = db.scan_prefix(&prefix)
-                    .collect::<io::Result<Vec<_>>>()
-                    .map(|items| {
-                        items.into_iter()
-                            .map(|(key, value)| (key.to_vec(), value.to_vec()))
-                            .collect()
-                    });
-                let _ = response_tx.send(result);
+                let scan = PrefixScan {
+                    iter: db.snapshot_scan_prefix(&prefix),
+                    items: vec![],
+                    response_tx,
+                };
+                Self::continue_scan(queue, read_priority, Box::new(scan));
+            }
+            DatabaseOperation::ContinueScan { scan } => {
+                Self::continue_scan(queue, read_priority, scan);
             }
             DatabaseOperation::Remove { key, response_tx } => {
                 let result = db.remove(&key);
                 let _ = response_tx.send(result);
             }
+            DatabaseOperation::ApplyBatch { batch, return_previous, response_tx } => {
+                let result = if return_previous {
+                    db.apply_batch_returning(batch)
+                } else {
+                    db.apply_batch(batch).map(|_| vec![])
+                };
+                let _ = response_tx.send(result);
+            }
             DatabaseOperation::ContainsKey { key, response_tx } => {
                 let result = db.contains_key(&key);
                 let _ = response_tx.send(result);
@@ -247,8 +560,42 @@ impl DatabaseWorker {
         }
     }
 
+    /// 执行前缀扫描的下一批，扫描未完成时把它放回队列末尾
+    fn continue_scan(
+        queue: &SegQueue<DatabaseOperation>,
+        read_priority: &ReadPriority,
+        mut scan: Box<PrefixScan>,
+    ) {
+        for _ in 0..SCAN_CHUNK_SIZE {
+            match scan.iter.next() {
+                Some(Ok((key, value))) => scan.items.push((key.to_vec(), value.to_vec())),
+                Some(Err(e)) => {
+                    let _ = scan.response_tx.send(Err(e));
+                    return;
+                }
+                None => {
+                    let _ = scan.response_tx.send(Ok(std::mem::take(&mut scan.items)));
+                    return;
+                }
+            }
+        }
+
+        trace_log!(op = "scan_prefix", items = scan.items.len(); "前缀扫描已读取 {} 条，放回队列", scan.items.len());
+        read_priority.push(queue, DatabaseOperation::ContinueScan { scan });
+    }
+
     /// 提交插入操作
     pub(crate) fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> io::Result<Option<InlineArray>> {
+        self.insert_with_timeout(key, value, None)
+    }
+
+    /// 提交插入操作，最多等待 `timeout`，`None` 表示一直等待
+    pub(crate) fn insert_with_timeout(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<InlineArray>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = DatabaseOperation::Insert {
@@ -257,31 +604,52 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
-        response_rx.recv().unwrap_or_else(|_| {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
-        })
+        wait_response(&response_rx, timeout)
     }
 
-    /// 提交获取操作
+    /// 提交获取操作。启用请求合并时，与同一个键尚未开始执行的读取共享结果
     pub(crate) fn get(&self, key: Vec<u8>) -> io::Result<Option<InlineArray>> {
+        self.get_with_timeout(key, None)
+    }
+
+    /// 提交获取操作，最多等待 `timeout`，`None` 表示一直等待
+    pub(crate) fn get_with_timeout(
+        &self,
+        key: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<InlineArray>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
+        self.coalescing.get_requests.fetch_add(1, Ordering::Relaxed);
 
-        let operation = DatabaseOperation::Get {
-            key,
-            response_tx,
-        };
+        if self.coalescing.enabled.load(Ordering::Acquire) {
+            let mut in_flight = self.coalescing.in_flight.lock();
+            if let Some(waiters) = in_flight.get_mut(&key) {
+                waiters.push(response_tx);
+                self.coalescing.coalesced_gets.fetch_add(1, Ordering::Relaxed);
+            } else {
+                in_flight.insert(key.clone(), vec![response_tx]);
+                self.submit(DatabaseOperation::CoalescedGet { key });
+            }
+        } else {
+            let operation = DatabaseOperation::Get {
+                key,
+                response_tx,
+            };
 
-        self.operation_queue.push(operation);
+            self.submit(operation);
+        }
 
-        response_rx.recv().unwrap_or_else(|_| {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
-        })
+        wait_response(&response_rx, timeout)
     }
 
-    /// 提交原子计数器持久化操作
-    pub(crate) fn persist_counter(&self, counter_name: String, value: u64) -> io::Result<()> {
+    /// 提交原子计数器持久化操作，返回之前持久化的值
+    pub(crate) fn persist_counter(
+        &self,
+        counter_name: String,
+        value: CounterValue,
+    ) -> io::Result<Option<CounterValue>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = DatabaseOperation::PersistCounter {
@@ -290,7 +658,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -298,22 +666,33 @@ impl DatabaseWorker {
     }
 
     /// 提交预热计数器操作
-    pub(crate) fn preload_counters(&self) -> io::Result<Vec<(String, u64)>> {
+    pub(crate) fn preload_counters(&self) -> io::Result<CounterPreloadReport> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = DatabaseOperation::PreloadCounters {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
         })
     }
 
-    /// 提交扫描前缀操作
+    /// 提交扫描前缀操作。结果是提交后某一时刻的一致快照，
+    /// 扫描分批执行，期间其他操作不会被阻塞
     pub(crate) fn scan_prefix(&self, prefix: Vec<u8>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.scan_prefix_with_timeout(prefix, None)
+    }
+
+    /// 提交扫描前缀操作，最多等待 `timeout`，`None` 表示一直等待。
+    /// 超时后扫描仍会执行完，结果被丢弃
+    pub(crate) fn scan_prefix_with_timeout(
+        &self,
+        prefix: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = DatabaseOperation::ScanPrefix {
@@ -321,15 +700,22 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
-        response_rx.recv().unwrap_or_else(|_| {
-            Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
-        })
+        wait_response(&response_rx, timeout)
     }
 
     /// 提交删除操作
     pub(crate) fn remove(&self, key: Vec<u8>) -> io::Result<Option<InlineArray>> {
+        self.remove_with_timeout(key, None)
+    }
+
+    /// 提交删除操作，最多等待 `timeout`，`None` 表示一直等待
+    pub(crate) fn remove_with_timeout(
+        &self,
+        key: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<InlineArray>> {
         let (response_tx, response_rx) = std::sync::mpsc::channel();
 
         let operation = DatabaseOperation::Remove {
@@ -337,7 +723,38 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
+
+        wait_response(&response_rx, timeout)
+    }
+
+    /// 提交批量写入操作
+    pub(crate) fn apply_batch(&self, batch: Batch) -> io::Result<()> {
+        self.submit_batch(batch, false).map(|_| ())
+    }
+
+    /// 提交批量写入操作，按键顺序返回每个键之前的值
+    pub(crate) fn apply_batch_returning(
+        &self,
+        batch: Batch,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        self.submit_batch(batch, true)
+    }
+
+    fn submit_batch(
+        &self,
+        batch: Batch,
+        return_previous: bool,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        let (response_tx, response_rx) = std::sync::mpsc::channel();
+
+        let operation = DatabaseOperation::ApplyBatch {
+            batch,
+            return_previous,
+            response_tx,
+        };
+
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -353,7 +770,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -368,7 +785,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -383,7 +800,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -398,7 +815,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -413,7 +830,7 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
@@ -428,19 +845,157 @@ impl DatabaseWorker {
             response_tx,
         };
 
-        self.operation_queue.push(operation);
+        self.submit(operation);
 
         response_rx.recv().unwrap_or_else(|_| {
             Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"))
         })
     }
 
+    /// 启用或禁用相同键的并发获取请求的合并
+    pub(crate) fn set_get_coalescing(&self, enabled: bool) {
+        self.coalescing.enabled.store(enabled, Ordering::Release);
+    }
+
+    /// 启用或禁用读取优先的调度
+    pub(crate) fn set_read_priority(&self, enabled: bool) {
+        self.read_priority.enabled.store(enabled, Ordering::Release);
+    }
+
+    /// 把操作放入它所属的队列
+    fn submit(&self, operation: DatabaseOperation) {
+        self.read_priority.push(&self.operation_queue, operation);
+    }
+
+    /// 获取请求和实际读取的统计
+    pub(crate) fn stats(&self) -> DatabaseWorkerStats {
+        DatabaseWorkerStats {
+            get_requests: self.coalescing.get_requests.load(Ordering::Relaxed),
+            reads: self.coalescing.reads.load(Ordering::Relaxed),
+            coalesced_gets: self.coalescing.coalesced_gets.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 暂停Worker，直到返回的guard被释放。正在处理的操作会先完成（测试用）
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn pause(&self) -> parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()> {
+        self.pause.pause()
+    }
+
     /// 获取操作队列引用（供其他Worker使用）
     pub(crate) fn operation_queue(&self) -> &Arc<SegQueue<DatabaseOperation>> {
         &self.operation_queue
     }
 }
 
+/// 等待Worker的响应，`timeout` 为 `None` 时一直等待，超时返回 `TimedOut`
+fn wait_response<T>(
+    response_rx: &std::sync::mpsc::Receiver<io::Result<T>>,
+    timeout: Option<Duration>,
+) -> io::Result<T> {
+    let disconnected = || Err(io::Error::new(io::ErrorKind::BrokenPipe, "DatabaseWorker连接断开"));
+    let Some(timeout) = timeout else {
+        return response_rx.recv().unwrap_or_else(|_| disconnected());
+    };
+
+    match response_rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("等待数据库Worker超时（{:?}）", timeout),
+        )),
+        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => disconnected(),
+    }
+}
+
+pub(crate) fn counter_key(counter_name: &str) -> Vec<u8> {
+    [COUNTER_KEY_PREFIX, counter_name.as_bytes()].concat()
+}
+
+/// 以带版本的格式编码计数器值
+pub(crate) fn encode_counter(value: u64) -> [u8; 9] {
+    encode_counter_value(CounterValue::U64(value))
+}
+
+/// 以带版本的格式编码整数或浮点计数器的值
+pub(crate) fn encode_counter_value(value: CounterValue) -> [u8; 9] {
+    let (version, bytes) = match value {
+        CounterValue::U64(value) => (COUNTER_FORMAT_V1, value.to_le_bytes()),
+        CounterValue::F64(value) => (COUNTER_FORMAT_F64, value.to_le_bytes()),
+    };
+    let mut ret = [version; 9];
+    ret[1..].copy_from_slice(&bytes);
+    ret
+}
+
+/// 解码持久化的计数器值，返回值以及是否为旧版的8字节格式
+fn parse_counter(bytes: &[u8]) -> Result<(CounterValue, bool), PreloadIssueReason> {
+    match bytes.len() {
+        8 => Ok((CounterValue::U64(u64::from_le_bytes(bytes.try_into().unwrap())), true)),
+        9 if bytes[0] == COUNTER_FORMAT_V1 => {
+            Ok((CounterValue::U64(u64::from_le_bytes(bytes[1..].try_into().unwrap())), false))
+        }
+        9 if bytes[0] == COUNTER_FORMAT_F64 => {
+            Ok((CounterValue::F64(f64::from_le_bytes(bytes[1..].try_into().unwrap())), false))
+        }
+        9 => Err(PreloadIssueReason::UnknownVersion(bytes[0])),
+        _ => Err(PreloadIssueReason::InvalidLength),
+    }
+}
+
+/// 解码持久化的计数器值，格式无效时返回 `None`
+pub(crate) fn decode_counter(bytes: &[u8]) -> Option<CounterValue> {
+    parse_counter(bytes).ok().map(|(value, _legacy)| value)
+}
+
+/// 从数据库中读取所有计数器。格式无效的条目不会被加载，
+/// 而是记录在报告中并输出警告
+pub(crate) fn load_counters(db: &Db<1024>) -> io::Result<CounterPreloadReport> {
+    debug_log!("开始预热计数器...");
+    let mut report = CounterPreloadReport::default();
+
+    for item_res in db.scan_prefix(COUNTER_KEY_PREFIX) {
+        let (key, value) = item_res?;
+        let name_bytes = &key[COUNTER_KEY_PREFIX.len()..];
+
+        let parsed = std::str::from_utf8(name_bytes)
+            .map_err(|_| PreloadIssueReason::NonUtf8Name)
+            .and_then(|name| parse_counter(&value).map(|parsed| (name, parsed)));
+
+        match parsed {
+            Ok((name, (CounterValue::U64(counter), legacy))) => {
+                if legacy {
+                    report.legacy.push(name.to_string());
+                }
+                report.counters.push((name.to_string(), counter));
+            }
+            Ok((name, (CounterValue::F64(counter), _legacy))) => {
+                report.float_counters.push((name.to_string(), counter));
+            }
+            Err(reason) => {
+                warn_log!(
+                    "跳过无效的计数器条目 {:?}: {:?}，值长度 {} 字节",
+                    String::from_utf8_lossy(&key),
+                    reason,
+                    value.len()
+                );
+                report.issues.push(PreloadIssue {
+                    key: key.to_vec(),
+                    reason,
+                    raw_len: value.len(),
+                });
+            }
+        }
+    }
+
+    debug_log!(
+        "预热完成，加载了 {} 个计数器，跳过 {} 个条目",
+        report.counters.len() + report.float_counters.len(),
+        report.issues.len()
+    );
+    Ok(report)
+}
+
 impl Drop for DatabaseWorker {
     fn drop(&mut self) {
         debug_log!("开始关闭数据库操作Worker");
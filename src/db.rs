@@ -1,13 +1,20 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::io;
-use std::sync::{Arc, mpsc};
+use std::path::Path;
+use std::sync::{Arc, Weak, mpsc};
 use std::time::{Duration, Instant};
 
 use parking_lot::Mutex;
 
 use crate::*;
-use crate::{debug_log, trace_log, warn_log, error_log, info_log, smart_flush::{SmartFlushScheduler, SmartFlushConfig}};
+use crate::backup::{BackupWriter, CollectionManifest, RestoreChain};
+use crate::flush_debug::FlusherAliveGuard;
+use crate::flush_group::FlushGroup;
+use crate::atomic_worker::AtomicWorkerConfig;
+use crate::hybrid_operations_manager::SharedWorkers;
+use crate::sequence::SequenceRegistry;
+use crate::{debug_log, warn_log, error_log, info_log, smart_flush::{AdaptiveFlushStats, SmartFlushScheduler, SmartFlushConfig}};
 
 /// melange_db - 高性能嵌入式数据库
 ///
@@ -34,6 +41,19 @@ pub struct Db<const LEAF_FANOUT: usize = 1024> {
     collection_name_mapping: Tree<LEAF_FANOUT>,
     default_tree: Tree<LEAF_FANOUT>,
     was_recovered: bool,
+    recovery_report: Option<RecoveryReport>,
+    // 最后一个 `Db` 被释放时断开，通知布隆过滤器维护线程退出
+    _bloom_maintenance_shutdown: Option<Arc<mpsc::Sender<()>>>,
+    // 最后一个 `Db` 被释放时断开，通知flush看门狗线程退出
+    _flush_watchdog_shutdown: Option<Arc<mpsc::Sender<()>>>,
+    // 最后一个 `Db` 被释放时通知内存回收线程退出并等待它结束
+    _gc_shutdown: Option<Arc<ThreadShutdown>>,
+    // 最后一个 `Db` 被释放时通知后台堆压缩线程退出并等待它结束
+    _compaction_shutdown: Option<Arc<ThreadShutdown>>,
+    // 同一个数据库上的所有 `HybridOperationsManager` 共享的Worker
+    pub(crate) shared_workers: Arc<SharedWorkers>,
+    // 按名称共享的序列状态，同名的 `Sequence` 从同一个范围分配
+    sequences: Arc<SequenceRegistry>,
 }
 
 impl<const LEAF_FANOUT: usize> std::ops::Deref for Db<LEAF_FANOUT> {
@@ -76,20 +96,173 @@ impl<const LEAF_FANOUT: usize> fmt::Debug for Db<LEAF_FANOUT> {
 unsafe impl<const LEAF_FANOUT: usize> Send for Db<LEAF_FANOUT> {}
 unsafe impl<const LEAF_FANOUT: usize> Sync for Db<LEAF_FANOUT> {}
 
+/// `Db::disk_usage` 返回的磁盘占用明细
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskUsageReport {
+    /// 数据库目录的总字节数，与 `Db::size_on_disk` 相同
+    pub total_bytes: u64,
+    /// 每个非空slab文件的大小
+    pub slab_files: Vec<SlabFileUsage>,
+    /// 元数据存储的字节数
+    pub metadata_bytes: u64,
+    /// 每个树的占用，第一项是默认树
+    pub trees: Vec<TreeDiskUsage>,
+}
+
+/// `Db::space_amplification_report` 返回的空间放大的分子和分母
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceAmplification {
+    /// 数据库目录的总字节数，与 `Db::size_on_disk` 相同
+    pub disk_bytes: u64,
+    /// 所有树中有效条目的键和值的字节数之和
+    pub logical_bytes: u64,
+}
+
+impl SpaceAmplification {
+    /// `disk_bytes / logical_bytes`。没有有效条目时，目录为空则返回 `0.0`，
+    /// 否则返回 `f64::INFINITY`
+    pub fn ratio(&self) -> f64 {
+        if self.logical_bytes == 0 {
+            if self.disk_bytes == 0 { 0.0 } else { f64::INFINITY }
+        } else {
+            self.disk_bytes as f64 / self.logical_bytes as f64
+        }
+    }
+}
+
+/// `Db::flush_async` 返回的句柄，在调用之前完成的所有写入都写入磁盘后完成
+#[derive(Clone)]
+pub struct FlushHandle<const LEAF_FANOUT: usize = 1024> {
+    cache: ObjectCache<LEAF_FANOUT>,
+    epoch: u64,
+}
+
+impl<const LEAF_FANOUT: usize> fmt::Debug for FlushHandle<LEAF_FANOUT> {
+    fn fmt(&self, w: &mut fmt::Formatter<'_>) -> fmt::Result {
+        w.debug_struct("FlushHandle")
+            .field("epoch", &self.epoch)
+            .field("complete", &self.is_complete())
+            .finish()
+    }
+}
+
+impl<const LEAF_FANOUT: usize> FlushHandle<LEAF_FANOUT> {
+    /// 请求的flush是否已经完成，不会阻塞
+    pub fn is_complete(&self) -> bool {
+        self.cache.max_flushed_epoch() >= self.epoch
+    }
+
+    /// 阻塞直到请求的flush完成。flush失败时返回数据库记录的错误
+    pub fn wait(&self) -> io::Result<()> {
+        // 定期检查全局错误，避免在flush失败后永远等待
+        while !self.cache.wait_for_flushed_epoch(self.epoch, Duration::from_millis(100)) {
+            self.cache.check_error()?;
+        }
+        Ok(())
+    }
+}
+
+/// 一个slab文件的大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabFileUsage {
+    /// 该文件中每个slot的字节数
+    pub slot_size: u64,
+    /// 文件的字节数
+    pub file_bytes: u64,
+    /// 文件实际占用的磁盘空间。越过文件末尾的写入留下的未写入区域不占用空间
+    /// （在Windows上slab文件为此被标记为稀疏文件），因此可能小于 `file_bytes`
+    pub allocated_bytes: u64,
+    /// 该文件是否绕过系统缓存，见 `Config::windows_write_through`
+    pub unbuffered: bool,
+}
+
+/// 一个树的磁盘占用
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeDiskUsage {
+    /// 树的名称，默认树为 `None`
+    pub name: Option<InlineArray>,
+    /// 该树的叶子节点序列化后的字节数，与 `Tree::estimated_disk_bytes` 相同
+    pub leaf_bytes: u64,
+    /// 加上按比例分摊的共享结构后的估算字节数
+    pub estimated_bytes: u64,
+}
+
+fn dir_size(path: &std::path::Path) -> io::Result<u64> {
+    use std::fs::read_dir;
+
+    fn recurse(mut dir: std::fs::ReadDir) -> io::Result<u64> {
+        dir.try_fold(0, |acc, file| {
+            let file = file?;
+            // 预留空间不是数据库的数据
+            if file.file_name() == crate::disk_space::HEADROOM_FILE {
+                return Ok(acc);
+            }
+            let size = match file.metadata()? {
+                data if data.is_dir() => recurse(read_dir(file.path())?)?,
+                data => data.len(),
+            };
+            Ok(acc + size)
+        })
+    }
+
+    recurse(read_dir(path)?)
+}
+
+/// flusher和布隆过滤器维护线程绑定的CPU核心：`flusher_cpu_affinity`
+/// 优先，否则为 `flusher_thread_affinity` 的单个核心
+fn flusher_cores(config: &Config) -> Option<Vec<usize>> {
+    match (&config.flusher_cpu_affinity, config.flusher_thread_affinity) {
+        (Some(cores), _) => Some(cores.clone()),
+        (None, Some(core)) => Some(vec![core]),
+        (None, None) => None,
+    }
+}
+
+/// 线程池的 `start_handler`：在名为 `thread` 的线程池的每个线程内应用
+/// flusher线程的CPU亲和性和优先级，失败时仅输出警告
+pub(crate) fn flusher_pool_start_handler(
+    config: &Config,
+    thread: &'static str,
+) -> impl Fn(usize) + Send + Sync + 'static {
+    let cores = flusher_cores(config);
+    let priority = config.flusher_thread_priority;
+    move |_| platform_utils::configure_current_thread(thread, cores.as_deref(), priority)
+}
+
+/// 在flusher线程内应用配置的CPU亲和性和优先级，失败时仅输出警告
+fn configure_flusher_thread(config: &Config) {
+    platform_utils::configure_current_thread(
+        "flusher",
+        flusher_cores(config).as_deref(),
+        config.flusher_thread_priority,
+    );
+}
+
 fn flusher<const LEAF_FANOUT: usize>(
     cache: ObjectCache<LEAF_FANOUT>,
-    shutdown_signal: mpsc::Receiver<mpsc::Sender<()>>,
+    shutdown_signal: mpsc::Receiver<FlusherSignal>,
     flush_every_ms: usize,
+    alive: FlusherAliveGuard,
 ) {
     let interval = Duration::from_millis(flush_every_ms as _);
     let mut last_flush_duration = Duration::default();
+    let mut waiting_for_space = false;
 
+    // 返回flush是否成功。文件系统已满时数据库进入只读降级状态，这次flush的
+    // 修改保留在缓存中，恢复写入之后重试
     let flush = || {
         let flush_res_res = std::panic::catch_unwind(|| cache.flush());
         match flush_res_res {
             Ok(Ok(_)) => {
                 // 不中止。
-                return;
+                return true;
+            }
+            Ok(Err(flush_failure)) if crate::disk_space::is_full(&flush_failure) => {
+                warn_log!(
+                    "Db flusher 在刷新时文件系统已满，恢复写入之后重试: {:?}",
+                    flush_failure
+                );
+                return false;
             }
             Ok(Err(flush_failure)) => {
                 error_log!(
@@ -112,12 +285,17 @@ fn flusher<const LEAF_FANOUT: usize>(
     };
 
     loop {
+        cache.flusher_status().beat();
+
         let recv_timeout = interval
             .saturating_sub(last_flush_duration)
             .max(Duration::from_millis(1));
-        if let Ok(shutdown_sender) = shutdown_signal.recv_timeout(recv_timeout)
+        // 超时或收到 `FlusherSignal::Flush` 时都执行一次常规flush
+        if let Ok(FlusherSignal::Shutdown(shutdown_sender)) =
+            shutdown_signal.recv_timeout(recv_timeout)
         {
-            flush();
+            let flushed = flush();
+            cache.mark_clean_shutdown();
 
             // 这可能是不必要的，但如果引入了会触发它的严重错误，
             // 它将避免问题
@@ -125,9 +303,14 @@ fn flusher<const LEAF_FANOUT: usize>(
                 "系统已关闭".to_string(),
             ));
 
-            assert!(cache.is_clean());
+            if flushed {
+                assert!(cache.is_clean());
+            } else {
+                error_log!("Db flusher 关闭时文件系统已满，最后一次flush之后的修改丢失");
+            }
 
             drop(cache);
+            drop(alive);
 
             if let Err(e) = shutdown_sender.send(()) {
                 error_log!(
@@ -140,14 +323,129 @@ fn flusher<const LEAF_FANOUT: usize>(
             return;
         }
 
-        let before_flush = Instant::now();
+        if !waiting_for_space {
+            let before_flush = Instant::now();
+
+            waiting_for_space = !flush();
+
+            last_flush_duration = before_flush.elapsed();
+        }
+
+        // 文件系统已满时检查空间是否已经释放，释放之后重试失败的flush
+        match cache.try_resume_writes_periodically() {
+            Ok(writable) => waiting_for_space &= !writable,
+            Err(e) => {
+                warn_log!("Db flusher 尝试恢复写入失败: {:?}", e);
+            }
+        }
+    }
+}
+
+/// 布隆过滤器维护线程。只持有树的弱引用，不阻止数据库关闭
+fn bloom_maintenance<const LEAF_FANOUT: usize>(
+    trees: Weak<Mutex<HashMap<CollectionId, Tree<LEAF_FANOUT>>>>,
+    shutdown_signal: mpsc::Receiver<()>,
+    interval: Duration,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) =
+        shutdown_signal.recv_timeout(interval)
+    {
+        let Some(trees) = trees.upgrade() else {
+            break;
+        };
+        let trees: Vec<Tree<LEAF_FANOUT>> = trees.lock().values().cloned().collect();
+
+        match Tree::resize_shared_bloom_filter_if_needed(&trees) {
+            Ok(true) => {
+                debug_log!("布隆过滤器误判率过高，已重建");
+            }
+            Ok(false) => {}
+            Err(e) => {
+                warn_log!("重建布隆过滤器失败: {:?}", e);
+            }
+        }
+    }
+
+    debug_log!("布隆过滤器维护线程退出");
+}
+
+/// 释放时通知后台线程退出并等待它结束。内存回收线程可能正持有升级后的树，
+/// 堆压缩线程持有缓存，等待它们结束保证最后一个 `Db` 被释放后数据库已经关闭
+struct ThreadShutdown {
+    name: &'static str,
+    sender: Option<mpsc::Sender<()>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for ThreadShutdown {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            error_log!("{}线程异常退出", self.name);
+        }
+    }
+}
 
-        flush();
+/// 启动后台堆压缩线程，见 `AutoCompact::Background`。线程在压缩完成或者
+/// 数据库关闭时退出，关闭时当前一轮flush仍然会完成
+fn spawn_background_compaction<const LEAF_FANOUT: usize>(
+    cache: ObjectCache<LEAF_FANOUT>,
+) -> io::Result<ThreadShutdown> {
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>();
+
+    let spawn_res = std::thread::Builder::new()
+        .name("melange-compact".into())
+        .spawn(move || {
+            let should_stop =
+                || matches!(shutdown_rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+            match cache.compact_heap(&should_stop, &mut |_| {}) {
+                Ok(moved) => {
+                    debug_log!("后台堆压缩完成，移动了 {} 个对象", moved);
+                }
+                Err(e) => {
+                    error_log!("后台堆压缩失败: {:?}", e);
+                    cache.set_error(&e);
+                }
+            }
+        });
 
-        last_flush_duration = before_flush.elapsed();
+    match spawn_res {
+        Ok(handle) => Ok(ThreadShutdown {
+            name: "堆压缩",
+            sender: Some(shutdown_tx),
+            handle: Some(handle),
+        }),
+        Err(e) => Err(io::Error::other(format!(
+            "无法为 melange_db 数据库生成堆压缩线程: {:?}",
+            e
+        ))),
     }
 }
 
+/// 内存回收线程，定期推进数据库内部登记的树的epoch，见 `Db::collect_garbage`。
+/// 只持有树的弱引用，不阻止数据库关闭
+fn garbage_collector<const LEAF_FANOUT: usize>(
+    trees: Weak<Mutex<HashMap<CollectionId, Tree<LEAF_FANOUT>>>>,
+    shutdown_signal: mpsc::Receiver<()>,
+    interval: Duration,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) =
+        shutdown_signal.recv_timeout(interval)
+    {
+        let Some(trees) = trees.upgrade() else {
+            break;
+        };
+        // the registered handles are only touched with the lock held
+        for tree in trees.lock().values() {
+            tree.collect_garbage();
+        }
+    }
+
+    debug_log!("内存回收线程退出");
+}
+
 impl<const LEAF_FANOUT: usize> Drop for Db<LEAF_FANOUT> {
     fn drop(&mut self) {
         if self.config.flush_every_ms.is_none() {
@@ -161,42 +459,68 @@ impl<const LEAF_FANOUT: usize> Drop for Db<LEAF_FANOUT> {
 }
 
 impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
-    #[cfg(feature = "for-internal-testing-only")]
-    fn validate(&self) -> io::Result<()> {
-        // 对于每个树，遍历索引，读取节点并断言低键匹配
-        // 并断言这是我们第一次看到节点 ID
+    /// 检查数据库的结构一致性：读取每个树的所有叶子节点，
+    /// 校验叶子节点的低键与索引一致、相邻叶子节点首尾相接、
+    /// 每个树只有一个没有上界的叶子节点，并且不同的树之间没有共享节点。
+    ///
+    /// 会将所有叶子节点读入缓存，适合在测试或维护时调用。
+    pub fn check(&self) -> io::Result<()> {
+        fn inconsistency(msg: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }
 
         let mut ever_seen = std::collections::HashSet::new();
         let before = std::time::Instant::now();
 
-        
-        for (_cid, tree) in self.trees.lock().iter() {
+        for (cid, tree) in self.trees.lock().iter() {
             let mut hi_none_count = 0;
             let mut last_hi = None;
             for (low, node) in tree.index.iter() {
                 // 确保我们没有在树之间重用 object_id
-                assert!(ever_seen.insert(node.object_id));
+                if !ever_seen.insert(node.object_id) {
+                    return Err(inconsistency(format!(
+                        "节点 {:?} 被多个叶子节点引用",
+                        node.object_id
+                    )));
+                }
 
                 let (read_low, node_mu, read_node) =
                     tree.page_in(&low, self.cache.current_flush_epoch())?;
+                let leaf = node_mu.leaf.as_ref().unwrap();
+
+                if read_node.object_id != node.object_id
+                    || leaf.lo != low
+                    || read_low != low
+                {
+                    return Err(inconsistency(format!(
+                        "集合 {:?} 中低键为 {:?} 的叶子节点与索引不一致",
+                        cid, low
+                    )));
+                }
 
-                assert_eq!(read_node.object_id, node.object_id);
-                assert_eq!(node_mu.leaf.as_ref().unwrap().lo, low);
-                assert_eq!(read_low, low);
-
-                if let Some(hi) = &last_hi {
-                    assert_eq!(hi, &node_mu.leaf.as_ref().unwrap().lo);
+                if let Some(hi) = &last_hi
+                    && hi != &leaf.lo
+                {
+                    return Err(inconsistency(format!(
+                        "集合 {:?} 中上一个叶子节点的上界 {:?} 与低键 {:?} 不相接",
+                        cid, hi, leaf.lo
+                    )));
                 }
 
-                if let Some(hi) = &node_mu.leaf.as_ref().unwrap().hi {
+                if let Some(hi) = &leaf.hi {
                     last_hi = Some(hi.clone());
                 } else {
-                    assert_eq!(hi_none_count, 0);
                     hi_none_count += 1;
                 }
             }
+
             // 每个树应该只有一个叶子节点没有最大 hi 键
-            assert_eq!(hi_none_count, 1);
+            if hi_none_count != 1 {
+                return Err(inconsistency(format!(
+                    "集合 {:?} 中有 {} 个没有上界的叶子节点",
+                    cid, hi_none_count
+                )));
+            }
         }
 
         debug_log!(
@@ -208,25 +532,180 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
         Ok(())
     }
 
+    /// 缓存的统计，以及创建了 `HybridOperationsManager` 之后原子计数器的统计
     pub fn stats(&self) -> Stats {
-        Stats { cache: self.cache.stats() }
+        Stats { cache: self.cache.stats(), counters: self.shared_workers.counter_stats() }
+    }
+
+    /// 推进基于epoch的内存回收（EBR），释放已经不再被任何读取者使用的索引节点。
+    ///
+    /// 树的索引被修改时，被替换的节点先放入执行修改的句柄（`Db`、每个 `Tree`
+    /// 及它们的克隆各是一个句柄）的本地缓冲区，等所有句柄都越过节点退休时的
+    /// epoch后才释放。句柄每执行128次操作推进一次epoch，很少被使用的句柄因此会
+    /// 推迟它自己退休的节点的回收。
+    ///
+    /// 迭代器（包括 `Tree::snapshot_iter`）只在每次调用 `next` 期间固定epoch，
+    /// 长时间持有迭代器不会阻止epoch推进。快照迭代器另外保存创建之后被修改的
+    /// 条目的旧内容，这部分内存在迭代器被释放时立即归还，不需要这个方法。
+    ///
+    /// 这个方法推进 `Db` 自己的句柄和数据库内部登记的每个树的epoch，释放它们
+    /// 退休的节点以及已释放的句柄遗留的垃圾。其他线程持有的句柄退休的节点在
+    /// 它们之后的操作中释放，也可以对那个句柄调用 `Tree::collect_garbage`；
+    /// 每个句柄还可能保留不超过128个尚未装满一个缓冲区的退休节点。
+    /// `Config::gc_interval` 启动一个定期执行同样工作的后台线程
+    pub fn collect_garbage(&self) {
+        self.default_tree.collect_garbage();
+        self.collection_name_mapping.collect_garbage();
+        self.cache.collect_garbage();
+        for tree in self.trees.lock().values() {
+            tree.collect_garbage();
+        }
+    }
+
+    pub(crate) fn atomic_worker_config(&self) -> AtomicWorkerConfig {
+        self.config.atomic_worker
     }
 
+    /// 返回数据库目录中所有文件的总大小，不包括 `Config::disk_full_headroom_bytes`
+    /// 预留的空间
     pub fn size_on_disk(&self) -> io::Result<u64> {
-        use std::fs::read_dir;
-
-        fn recurse(mut dir: std::fs::ReadDir) -> io::Result<u64> {
-            dir.try_fold(0, |acc, file| {
-                let file = file?;
-                let size = match file.metadata()? {
-                    data if data.is_dir() => recurse(read_dir(file.path())?)?,
-                    data => data.len(),
-                };
-                Ok(acc + size)
-            })
+        dir_size(&self.cache.config.path)
+    }
+
+    /// 返回数据库能否写入。flush时文件系统已满，数据库进入
+    /// `WriteState::ReadOnlyDegraded`：之后的写入以 `DiskFull`
+    /// （`io::ErrorKind::StorageFull`）失败，读取和迭代照常进行，
+    /// 已经写入缓存的数据仍然可以flush。后台flusher在空间释放后自动恢复写入，
+    /// 参见 `Db::try_resume_writes`
+    pub fn write_state(&self) -> WriteState {
+        self.cache.write_state()
+    }
+
+    /// 只读降级状态下立即检查文件系统是否已有可用空间：重新预留
+    /// `Config::disk_full_headroom_bytes` 字节，成功时恢复写入。
+    /// 返回之后能否写入，空间仍然不足时返回 `Ok(false)`。
+    /// 没有后台flusher（`flush_every_ms` 为 `None`）时只能这样恢复写入
+    pub fn try_resume_writes(&self) -> io::Result<bool> {
+        self.cache.try_resume_writes()
+    }
+
+    /// 返回堆文件的碎片率，即slab文件中没有被存活对象占用的字节的比例，
+    /// 在0.0到1.0之间。`Config::auto_compact_on_open` 与这个值比较
+    pub fn heap_fragmentation(&self) -> io::Result<f64> {
+        self.cache.heap_fragmentation()
+    }
+
+    /// 返回磁盘占用的明细：总字节数、每个slab文件和元数据存储的大小，
+    /// 以及每个树的估算占用。
+    ///
+    /// 每个树的叶子节点字节数在叶子节点写入和释放时增量维护，是精确的；
+    /// slot填充、空闲空间、元数据存储以及名称映射树等共享结构的占用
+    /// 按叶子节点字节数的比例分摊到各个树，是估算的。
+    /// 除了读取文件大小以外，开销与树的数量成正比。
+    pub fn disk_usage(&self) -> io::Result<DiskUsageReport> {
+        let heap = self.cache.heap();
+
+        let total_bytes = self.size_on_disk()?;
+        let metadata_bytes = dir_size(&heap.metadata_path())?;
+        let slab_files = heap.slab_file_usage()?;
+
+        let collection_bytes = heap.collection_bytes();
+        let leaf_bytes_of =
+            |id: CollectionId| collection_bytes.get(&id).copied().unwrap_or(0);
+
+        let mut trees = vec![TreeDiskUsage {
+            name: None,
+            leaf_bytes: leaf_bytes_of(DEFAULT_COLLECTION_ID),
+            estimated_bytes: 0,
+        }];
+        for kv_res in self.collection_name_mapping.iter() {
+            let (name, collection_id_buf) = kv_res?;
+            let collection_id = decode_collection_entry(&collection_id_buf).0;
+            trees.push(TreeDiskUsage {
+                name: Some(name),
+                leaf_bytes: leaf_bytes_of(collection_id),
+                estimated_bytes: 0,
+            });
+        }
+
+        let attributed: u64 = trees.iter().map(|tree| tree.leaf_bytes).sum();
+        let shared = total_bytes.saturating_sub(attributed);
+        for tree in &mut trees {
+            let share = if attributed == 0 {
+                0
+            } else {
+                (u128::from(shared) * u128::from(tree.leaf_bytes)
+                    / u128::from(attributed)) as u64
+            };
+            tree.estimated_bytes = tree.leaf_bytes + share;
+        }
+
+        Ok(DiskUsageReport { total_bytes, slab_files, metadata_bytes, trees })
+    }
+
+    /// 按键的顺序返回名为 `name` 的树的每个叶子节点的物理布局，供分析叶子节点的
+    /// 数量、大小、压缩率和填充率的外部工具使用，参见 `Tree::leaf_layout`。
+    /// 不是稳定的API。树不存在时返回 `NotFound`
+    #[doc(hidden)]
+    pub fn leaf_layout<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+    ) -> io::Result<impl Iterator<Item = LeafInfo>> {
+        let name = name.as_ref();
+        let Some(tree) = self.existing_tree(&self.trees.lock(), name)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("树 {:?} 不存在", String::from_utf8_lossy(name)),
+            ));
+        };
+        tree.leaf_layout()
+    }
+
+    /// 返回磁盘字节数与所有有效条目的键和值的字节数之比，
+    /// 见 `Db::space_amplification_report`
+    pub fn space_amplification(&self) -> io::Result<f64> {
+        Ok(self.space_amplification_report()?.ratio())
+    }
+
+    /// 返回空间放大的分子和分母，用于容量规划。比值明显大于1说明磁盘上有
+    /// 碎片、尚未回收的空闲slot或者尚未压缩的元数据日志。
+    ///
+    /// 逻辑字节数包括尚未flush的写入，而磁盘字节数只反映已经flush的数据，
+    /// 因此应在flush之后测量。需要读取所有树的所有条目，开销与数据量成正比
+    pub fn space_amplification_report(&self) -> io::Result<SpaceAmplification> {
+        let disk_bytes = self.size_on_disk()?;
+
+        let trees: Vec<Tree<LEAF_FANOUT>> = self
+            .trees
+            .lock()
+            .iter()
+            .filter(|(id, _)| **id != NAME_MAPPING_COLLECTION_ID)
+            .map(|(_, tree)| tree.clone())
+            .collect();
+
+        let mut logical_bytes = 0;
+        for tree in trees {
+            for kv_res in tree.iter() {
+                let (key, value) = kv_res?;
+                logical_bytes += (key.len() + value.len()) as u64;
+            }
         }
 
-        recurse(read_dir(&self.cache.config.path)?)
+        Ok(SpaceAmplification { disk_bytes, logical_bytes })
+    }
+
+    /// 返回元数据存储的维护统计：有效条目数、已被覆盖或释放而可以丢弃的
+    /// 失效条目数、快照和日志文件的总字节数，以及最近一次压缩的时间
+    pub fn metadata_stats(&self) -> io::Result<MetadataStats> {
+        self.cache.heap().metadata_stats()
+    }
+
+    /// 立即压缩元数据存储：把当前的元数据日志合并为只包含有效条目的新快照，
+    /// 并等待快照写入完成。快照先写入临时文件再原子地重命名，
+    /// 中途崩溃时恢复使用旧的快照和日志或者新的快照。
+    /// 可以与写入和刷新并发调用
+    pub fn compact_metadata(&self) -> io::Result<()> {
+        self.cache.heap().compact_metadata()
     }
 
     /// 如果数据库是从之前的进程恢复的，则返回 `true`。
@@ -238,15 +717,310 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
         self.was_recovered
     }
 
+    /// 如果数据库是从之前的进程恢复的，返回恢复过程的详细信息：
+    /// 恢复的对象数量、重放的元数据日志和批次、因未完成的flush而被截断的写入，
+    /// 以及恢复到的日志序列号。新建的数据库返回 `None`。
+    ///
+    /// 可以通过 `RecoveryReport::is_clean` 判断上一个进程是否在flush的中途退出
+    pub fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.recovery_report
+    }
+
+    /// 返回数据库创建时的格式：`LEAF_FANOUT`、存储格式版本、压缩算法和创建它的
+    /// melange_db版本。不打开数据库时可以使用 `melange_db::inspect` 读取
+    pub fn format_info(&self) -> FormatInfo {
+        self.cache.heap().format_info()
+    }
+
+    /// 返回叶子节点压缩的统计：第一项是不使用字典压缩的叶子节点，之后每个已加载
+    /// 或新训练的字典一项，可以通过 `DictionaryStats::compression_ratio` 比较
+    /// 各代字典的压缩效果。统计从本次打开数据库开始计数
+    pub fn compression_stats(&self) -> Vec<DictionaryStats> {
+        self.cache.dictionaries().stats()
+    }
+
+    /// 返回当前被隔离的对象及其损坏的位置，按集合ID和对象ID排序。
+    ///
+    /// 只有 `ChecksumMode::VerifyAndQuarantine` 会隔离对象：恢复时或读取时校验和
+    /// 不匹配的对象被隔离，对它的读取直接返回 `CorruptionError`，直到它被重新写入
+    /// 或删除。校验和失败的次数和最近一次的详情记录在 `Db::stats` 的
+    /// `cache.heap.corruption_events` 和 `cache.heap.last_corruption` 中
+    pub fn quarantined_objects(&self) -> Vec<CorruptionError> {
+        self.cache.heap().quarantined_objects()
+    }
+
+    /// 检查布隆过滤器的预计误判率，超过目标时以更大的容量从所有树的
+    /// 有效键重建它。返回是否进行了重建。
+    ///
+    /// 启用 `Config::bloom_auto_resize` 时，后台维护线程会定期调用此方法
+    pub fn resize_bloom_filter_if_needed(&self) -> io::Result<bool> {
+        let trees: Vec<Tree<LEAF_FANOUT>> =
+            self.trees.lock().values().cloned().collect();
+        Tree::resize_shared_bloom_filter_if_needed(&trees)
+    }
+
+    /// 返回智能flusher当前使用的配置
+    pub fn smart_flush_config(&self) -> SmartFlushConfig {
+        self.cache.smart_flush_config()
+    }
+
+    /// 返回默认调度的写放大自适应状态，参见
+    /// `SmartFlushConfig::target_write_amplification`
+    pub fn adaptive_flush_stats(&self) -> AdaptiveFlushStats {
+        self.cache.get_write_stats().adaptive_flush_stats()
+    }
+
+    /// 在数据库打开期间替换智能flusher的配置，与 `Config::open` 一样检查配置，
+    /// 无效时返回 `InvalidInput` 且不做任何修改。
+    ///
+    /// 新配置作为一个整体替换旧配置，flusher在下一次计算flush间隔时开始使用它。
+    /// 打开时没有启动智能flusher（`flush_every_ms` 为 `None` 或
+    /// `smart_flush_config.enabled` 为 `false`）时返回 `Unsupported`
+    pub fn update_smart_flush_config(&self, config: SmartFlushConfig) -> io::Result<()> {
+        config.validate()?;
+        if self.cache.config.flush_every_ms.is_none()
+            || !self.cache.config.smart_flush_config.enabled
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "数据库打开时没有启动智能flusher，无法更新它的配置",
+            ));
+        }
+        self.cache.set_smart_flush_config(config);
+        Ok(())
+    }
+
+    /// 在数据库打开期间改变缓存容量，与打开时一样，小于256的值按256处理。
+    ///
+    /// 缩小容量时立即淘汰超出新容量的叶子节点。尚未flush的叶子节点在下一次
+    /// flush之后才被淘汰，因此内存占用在下一次flush后才降到新容量以下。
+    /// 当前的容量可以通过 `Db::stats` 的 `cache.cache_capacity_bytes` 查看
+    pub fn set_cache_capacity_bytes(&self, cache_capacity_bytes: usize) {
+        self.cache.set_cache_capacity_bytes(cache_capacity_bytes);
+    }
+
+    /// 缓存中的叶子节点当前占用的内存（字节），需要遍历所有叶子节点
+    pub fn cache_resident_bytes(&self) -> usize {
+        self.cache.resident_bytes()
+    }
+
+    /// 按子系统分别返回数据库占用的内存：缓存的叶子节点、索引、布隆过滤器
+    /// 和计数器映射，用于把进程内存的增长归因到具体的子系统。需要遍历所有
+    /// 叶子节点和计数器
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            object_cache_bytes: self.cache.resident_bytes(),
+            index_bytes: self.cache.index_bytes(),
+            bloom_filter_bytes: self.cache.bloom_filter_bytes(),
+            counter_bytes: self.shared_workers.counter_memory_bytes(),
+        }
+    }
+
+    /// 包含 `Tree::pin` 固定的键的叶子节点在缓存中占用的内存（字节），
+    /// 需要遍历所有叶子节点。上限见 `Config::max_pinned_cache_percent`
+    pub fn pinned_bytes(&self) -> usize {
+        self.cache.pinned_bytes()
+    }
+
+    /// 使之后每次从堆文件读取对象都额外等待 `latency`，模拟较慢的存储设备。
+    /// 用于测试预读等隐藏读取延迟的功能
+    #[cfg(feature = "for-internal-testing-only")]
+    #[doc(hidden)]
+    pub fn set_read_latency_for_testing(&self, latency: Duration) {
+        self.cache.heap().set_read_latency_for_testing(latency);
+    }
+
+    /// 使之后的flush中每个对象的序列化都额外等待 `latency`，模拟序列化代价较高的
+    /// 叶子节点。用于测试 `Config::flush_threads`
+    #[cfg(feature = "for-internal-testing-only")]
+    #[doc(hidden)]
+    pub fn set_serialize_latency_for_testing(&self, latency: Duration) {
+        self.cache.set_serialize_latency_for_testing(latency);
+    }
+
+    /// 翻转堆文件中 `address` 处的槽的一个字节，之后从磁盘读取存放在那里的对象时
+    /// 校验和不匹配。`address` 来自 `Tree::debug_entries`。用于测试
+    /// `Config::on_corruption`。存放在那里的叶子节点如果在缓存中会被换出，
+    /// 下一次读取它时从磁盘读取
+    #[cfg(feature = "for-internal-testing-only")]
+    #[doc(hidden)]
+    pub fn corrupt_slot_for_testing(&self, address: SlabAddress) -> io::Result<()> {
+        self.cache.corrupt_slot_for_testing(address)
+    }
+
+    /// 在数据库打开期间改变 `Config::flush_io_rate_limit`，从下一次flush开始生效。
+    /// `Some(0)` 返回 `InvalidInput`
+    pub fn set_flush_io_rate_limit(&self, limit: Option<u64>) -> io::Result<()> {
+        crate::config::validate_flush_io_rate_limit(limit)?;
+        self.cache.set_flush_io_rate_limit(limit);
+        Ok(())
+    }
+
+    /// 把操作日志中的所有记录按从旧到新的顺序写入 `writer`，返回写入的记录数。
+    /// 写入的内容可以用 `OpJournalReader` 读取。
+    ///
+    /// 没有设置 `Config::op_journal` 时返回 `Unsupported`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::op_journal::{OpJournalConfig, OpJournalReader, OpKind};
+    ///
+    /// let config = melange_db::Config::tmp()?
+    ///     .op_journal(OpJournalConfig { max_bytes: 1 << 20, redact_values: false });
+    /// let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"k", vec![1])?;
+    ///
+    /// let mut dump = vec![];
+    /// assert_eq!(db.dump_op_journal(&mut dump)?, 1);
+    /// let entries = OpJournalReader::new(&dump[..])?.collect::<std::io::Result<Vec<_>>>()?;
+    /// assert_eq!(entries[0].op, OpKind::Insert);
+    /// assert_eq!(entries[0].key, b"k");
+    /// # Ok(()) }
+    /// ```
+    pub fn dump_op_journal<W: io::Write>(&self, writer: W) -> io::Result<u64> {
+        match self.cache.op_journal() {
+            Some(journal) => journal.dump(writer),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "数据库打开时没有设置 op_journal",
+            )),
+        }
+    }
+
+    /// 键太大、无法放入操作日志的一个分段而没有被记录的操作数
+    pub fn op_journal_dropped(&self) -> u64 {
+        self.cache.op_journal().map_or(0, |journal| journal.dropped())
+    }
+
+    /// 返回正在运行的flush在等待什么，用于调试不返回的 `flush`：当前的flush
+    /// epoch和正在运行的flush所处的阶段、每个集合中的脏对象数、仍然持有的
+    /// flush epoch guard，以及后台flusher线程是否在运行和它最后一次心跳的时间。
+    ///
+    /// 在调试构建中启用 `epoch-guard-backtraces` 特性时，报告中还包含每个未释放
+    /// 的guard创建时的调用栈
+    pub fn debug_flush_state(&self) -> FlushDebugReport {
+        let mut report = self.cache.debug_flush_state();
+
+        // 不使用 `iter`：迭代器持有树的克隆，没有后台flusher时释放克隆会flush，
+        // 在flush卡住时阻塞。读取名称失败时只报告集合ID
+        let mut names: HashMap<u64, InlineArray> = HashMap::new();
+        let _ = self.collection_name_mapping.backup_leaves(
+            |_object_id, _dirty| true,
+            |_object_id, entries| {
+                for (name, collection_id_buf) in entries.unwrap_or_default() {
+                    let collection_id = decode_collection_entry(&collection_id_buf).0;
+                    names.insert(collection_id.0, InlineArray::from(name));
+                }
+                Ok(())
+            },
+        );
+        for dirty in &mut report.dirty_collections {
+            dirty.name = names.remove(&dirty.collection_id);
+        }
+
+        report
+    }
+
+    /// 在一次flush中写入所有树的脏数据。
+    ///
+    /// 所有树共用同一个对象缓存和flush epoch，因此一次flush就覆盖了所有树在这次
+    /// 调用之前完成的写入。关闭数据库前依次对每个树调用 `Tree::flush` 会为每个树
+    /// 各进行一次flush，调用一次 `flush_all` 代替它们即可。在 `flush_all` 运行
+    /// 期间调用的 `Tree::flush` 等待它完成而不是另外开始一次flush，见
+    /// `Config::coalesce_flushes`。
+    pub fn flush_all(&self) -> io::Result<FlushStats> {
+        self.cache.flush_coalesced()
+    }
+
+    /// 原子地将多个批次分别应用到这个数据库的多个树。
+    ///
+    /// 先锁定所有树中受影响的叶子节点并检查所有批次的守卫条件（见
+    /// `Batch::guard`），任何一个条件不满足时什么都不写入。所有写入在同一个
+    /// flush epoch中完成，读取者要么看到全部写入，要么一个都看不到，崩溃后也
+    /// 只会恢复全部写入或者什么都不恢复。每个树最多只能有一个批次，所有树必须
+    /// 属于这个数据库，否则返回 `InvalidInput`。
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// let accounts = db.open_tree("accounts")?;
+    /// let log = db.open_tree("log")?;
+    ///
+    /// let mut debit = melange_db::Batch::default();
+    /// debit.insert(b"alice", b"90".as_slice());
+    /// let mut entry = melange_db::Batch::default();
+    /// entry.insert(b"0001", b"alice -10".as_slice());
+    ///
+    /// db.apply_batches(vec![(&accounts, debit), (&log, entry)])?;
+    /// assert_eq!(&*log.get(b"0001")?.unwrap(), b"alice -10");
+    /// # Ok(()) }
+    /// ```
+    pub fn apply_batches(&self, batches: Vec<(&Tree<LEAF_FANOUT>, Batch)>) -> io::Result<()> {
+        self.apply_multi_tree_batch(batches)
+    }
+
+    /// 请求一次flush后立即返回，而不是像 `Tree::flush` 一样阻塞调用者。
+    /// 返回的 `FlushHandle` 可以通过 `is_complete` 轮询，或者通过 `wait`
+    /// 等待在这次调用之前完成的所有写入都写入磁盘。
+    ///
+    /// 有flusher线程时由它立即执行这次flush，否则（`flush_every_ms` 为
+    /// `None`）在一个新线程中执行
+    pub fn flush_async(&self) -> FlushHandle<LEAF_FANOUT> {
+        // flush会向前推进当前的flush epoch并写入它之前的所有数据，
+        // 所以当前epoch被标记为已flush时，之前的写入都已经持久化
+        let handle = FlushHandle {
+            cache: self.cache.clone(),
+            epoch: self.cache.current_flush_epoch().get(),
+        };
+
+        let signal_res = self
+            ._shutdown_dropper
+            .shutdown_sender
+            .lock()
+            .send(FlusherSignal::Flush);
+
+        if signal_res.is_err() {
+            let cache = self.cache.clone();
+            let spawn_res = std::thread::Builder::new()
+                .name("melange-flush-async".into())
+                .spawn(move || {
+                    if let Err(e) = cache.flush() {
+                        error_log!("异步flush失败: {:?}", e);
+                        cache.set_error(&e);
+                    }
+                });
+
+            if let Err(e) = spawn_res {
+                let e = io::Error::other(format!(
+                    "无法为 melange_db 数据库生成异步flush线程: {:?}",
+                    e
+                ));
+                error_log!("{}", e);
+                self.cache.set_error(&e);
+            }
+        }
+
+        handle
+    }
+
     pub fn open_with_config(config: &Config) -> io::Result<Db<LEAF_FANOUT>> {
         let (shutdown_tx, shutdown_rx) = mpsc::channel();
 
-        let (cache, indices, was_recovered) = ObjectCache::recover(config)?;
+        let (cache, indices, recovery_report) = ObjectCache::recover(config)?;
+        let was_recovered = recovery_report.is_some();
 
         let _shutdown_dropper = Arc::new(ShutdownDropper {
             shutdown_sender: Mutex::new(shutdown_tx),
             cache: Mutex::new(cache.clone()),
         });
+        // 之后打开失败返回时先释放接收端，`ShutdownDropper` 发送关闭信号失败后
+        // 自行flush，而不是一直等待还没有启动的flusher线程确认
+        let shutdown_rx = shutdown_rx;
 
         let mut allocated_collection_ids = fnv::FnvHashSet::default();
 
@@ -275,11 +1049,19 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
 
         let default_tree = trees.get(&DEFAULT_COLLECTION_ID).unwrap().clone();
 
+        let mut named_collection_ids = fnv::FnvHashSet::default();
+        let mut write_once_collection_ids = vec![];
         for kv_res in collection_name_mapping.iter() {
-            let (_collection_name, collection_id_buf) = kv_res.unwrap();
-            let collection_id = CollectionId(u64::from_le_bytes(
-                collection_id_buf.as_ref().try_into().unwrap(),
-            ));
+            let (collection_name, collection_id_buf) = kv_res?;
+            let (collection_id, flags) =
+                decode_collection_entry(&collection_id_buf);
+            named_collection_ids.insert(collection_id);
+            if let Some(journal) = cache.op_journal() {
+                journal.register_tree(collection_id, &collection_name);
+            }
+            if flags & TREE_FLAG_WRITE_ONCE != 0 {
+                write_once_collection_ids.push(collection_id);
+            }
 
             if trees.contains_key(&collection_id) {
                 continue;
@@ -311,12 +1093,33 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
             trees.insert(collection_id, tree);
         }
 
+        for collection_id in write_once_collection_ids {
+            trees.get(&collection_id).unwrap().set_write_once();
+        }
+
+        // 旧版本的 drop_tree 只移除名称而保留叶子节点，这里释放这些没有名称的树
+        let orphaned: Vec<CollectionId> = trees
+            .keys()
+            .filter(|id| {
+                **id != NAME_MAPPING_COLLECTION_ID
+                    && **id != DEFAULT_COLLECTION_ID
+                    && !named_collection_ids.contains(*id)
+            })
+            .copied()
+            .collect();
+        for collection_id in orphaned {
+            warn_log!("释放已删除的树遗留的叶子节点，集合ID {:?}", collection_id);
+            let tree = trees.remove(&collection_id).unwrap();
+            tree.delete_all_leaves()?;
+            allocated_collection_ids.remove(&collection_id.0);
+        }
+
         let collection_id_allocator =
             Arc::new(Allocator::from_allocated(&allocated_collection_ids));
 
         assert_eq!(collection_name_mapping.len()? + 2, trees.len());
 
-        let ret = Db {
+        let mut ret = Db {
             config: config.clone(),
             cache: cache.clone(),
             default_tree,
@@ -325,19 +1128,135 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
             trees: Arc::new(Mutex::new(trees)),
             _shutdown_dropper,
             was_recovered,
+            recovery_report,
+            _bloom_maintenance_shutdown: None,
+            _flush_watchdog_shutdown: None,
+            _gc_shutdown: None,
+            _compaction_shutdown: None,
+            shared_workers: Arc::default(),
+            sequences: Arc::default(),
         };
+        // `ret` 也持有 `ShutdownDropper`，之后打开失败返回时同样先释放接收端
+        let shutdown_rx = shutdown_rx;
+        if config.bloom_auto_resize {
+            let (shutdown_tx, shutdown_rx) = mpsc::channel();
+            let trees = Arc::downgrade(&ret.trees);
+            let interval =
+                Duration::from_millis(config.bloom_resize_check_interval_ms as u64);
+
+            let cores = flusher_cores(config);
+            let priority = config.flusher_thread_priority;
+
+            let spawn_res = std::thread::Builder::new()
+                .name("melange-bloom".into())
+                .spawn(move || {
+                    platform_utils::configure_current_thread(
+                        "布隆过滤器维护",
+                        cores.as_deref(),
+                        priority,
+                    );
+                    bloom_maintenance(trees, shutdown_rx, interval)
+                });
+
+            if let Err(e) = spawn_res {
+                return Err(io::Error::other(format!(
+                    "无法为 melange_db 数据库生成布隆过滤器维护线程: {:?}",
+                    e
+                )));
+            }
+            ret._bloom_maintenance_shutdown = Some(Arc::new(shutdown_tx));
+        }
+
+        if let Some(limit) = config.flush_watchdog {
+            let (shutdown_tx, shutdown_rx) = mpsc::channel();
+            let cache = cache.clone();
+
+            let spawn_res = std::thread::Builder::new()
+                .name("melange-flush-watchdog".into())
+                .spawn(move || flush_debug::watchdog(cache, shutdown_rx, limit));
+
+            if let Err(e) = spawn_res {
+                return Err(io::Error::other(format!(
+                    "无法为 melange_db 数据库生成flush看门狗线程: {:?}",
+                    e
+                )));
+            }
+            ret._flush_watchdog_shutdown = Some(Arc::new(shutdown_tx));
+        }
 
+        if let Some(interval) = config.gc_interval {
+            let (shutdown_tx, shutdown_rx) = mpsc::channel();
+            let trees = Arc::downgrade(&ret.trees);
+
+            let spawn_res = std::thread::Builder::new()
+                .name("melange-gc".into())
+                .spawn(move || garbage_collector(trees, shutdown_rx, interval));
+
+            let handle = match spawn_res {
+                Ok(handle) => handle,
+                Err(e) => {
+                    return Err(io::Error::other(format!(
+                        "无法为 melange_db 数据库生成内存回收线程: {:?}",
+                        e
+                    )));
+                }
+            };
+            ret._gc_shutdown = Some(Arc::new(ThreadShutdown {
+                name: "内存回收",
+                sender: Some(shutdown_tx),
+                handle: Some(handle),
+            }));
+        }
+
+        if let Some(max_fragmentation) = config.auto_compact_on_open {
+            let fragmentation = cache.heap_fragmentation()?;
+            if fragmentation > max_fragmentation {
+                debug_log!(
+                    "堆文件碎片率 {:.3} 超过 {}，开始压缩",
+                    fragmentation,
+                    max_fragmentation
+                );
+                match config.auto_compact {
+                    AutoCompact::Blocking => {
+                        let callback = config.recovery_progress_callback.clone();
+                        cache.compact_heap(&|| false, &mut |progress| {
+                            if let Some(callback) = &callback {
+                                (callback.0)(progress);
+                            }
+                        })?;
+                    }
+                    AutoCompact::Background => {
+                        ret._compaction_shutdown =
+                            Some(Arc::new(spawn_background_compaction(cache.clone())?));
+                    }
+                }
+            }
+        }
+
+        // 检查会把所有叶子节点读入缓存，之后再换出它们，
+        // 使打开后的缓存与不启用这个特性时一样是空的。
+        // 被隔离的叶子节点不能读取，有被隔离的对象时不检查
         #[cfg(feature = "for-internal-testing-only")]
-        ret.validate()?;
+        if ret.quarantined_objects().is_empty() {
+            ret.check()?;
+            ret.cache.page_out_for_testing(|_| true);
+        }
 
         if let Some(flush_every_ms) = ret.cache.config.flush_every_ms {
             let smart_config = ret.cache.config.smart_flush_config.clone();
+            let thread_config = ret.cache.config.clone();
+            let thread_name = thread_config.flusher_thread_name.clone();
+            // 在启动线程之前标记，打开后立即生成的报告中flusher就是运行的
+            let alive = cache.flusher_status().clone().start();
 
             if smart_config.enabled {
                 // 使用智能flusher
                 let spawn_res = std::thread::Builder::new()
-                    .name("melange_db_smart_flusher".into())
-                    .spawn(move || smart_flusher(cache, shutdown_rx, smart_config));
+                    .name(thread_name.unwrap_or_else(|| "melange-flush".into()))
+                    .spawn(move || {
+                        configure_flusher_thread(&thread_config);
+                        smart_flusher(cache, shutdown_rx, alive)
+                    });
 
                 if let Err(e) = spawn_res {
                     return Err(io::Error::other(format!(
@@ -349,8 +1268,11 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
             } else {
                 // 使用传统固定间隔flusher
                 let spawn_res = std::thread::Builder::new()
-                    .name("melange_db_flusher".into())
-                    .spawn(move || flusher(cache, shutdown_rx, flush_every_ms));
+                    .name(thread_name.unwrap_or_else(|| "melange-flush".into()))
+                    .spawn(move || {
+                        configure_flusher_thread(&thread_config);
+                        flusher(cache, shutdown_rx, flush_every_ms, alive)
+                    });
 
                 if let Err(e) = spawn_res {
                     return Err(io::Error::other(format!(
@@ -419,9 +1341,7 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
 
         for kv_res in self.collection_name_mapping.iter() {
             let (collection_name, collection_id_buf) = kv_res.unwrap();
-            let collection_id = CollectionId(u64::from_le_bytes(
-                collection_id_buf.as_ref().try_into().unwrap(),
-            ));
+            let collection_id = decode_collection_entry(&collection_id_buf).0;
             let tree = trees.get(&collection_id).unwrap().clone();
 
             ret.push((
@@ -513,56 +1433,578 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
         }
     }
 
+    /// 当前的flush epoch。之后的一次flush完成时，在这个epoch和之前的epoch中
+    /// 完成的写入都已经写入磁盘，这时的状态可以通过 `Db::view_at_epoch` 读取。
+    ///
+    /// epoch只在当前打开的数据库中有意义，重新打开数据库后从头开始计数
+    pub fn current_epoch(&self) -> FlushEpoch {
+        self.cache.current_flush_epoch()
+    }
+
+    /// 返回这个数据库在 `epoch` 的flush完成时的只读视图，用于调试。`epoch` 来自
+    /// `Db::current_epoch`，还没有被flush时先执行一次flush，晚于当前epoch时返回
+    /// `io::ErrorKind::InvalidInput`。
+    ///
+    /// 视图包含那时存在的所有树和条目。创建视图时不读取叶子节点，每个树的叶子节点
+    /// 在第一次访问这个树时从堆文件读取。
+    ///
+    /// 只有最近 `Config::retained_flush_epochs` 次flush之前的状态可以读取，
+    /// 更早的epoch，以及压缩堆文件之前的epoch返回 `io::ErrorKind::NotFound`。
+    /// 视图创建之后epoch超出这个范围时，访问尚未读取的树也返回
+    /// `io::ErrorKind::NotFound`。
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let dir = tempfile::tempdir()?;
+    /// let db: melange_db::Db<1024> =
+    ///     melange_db::Config::new().path(dir.path()).retained_flush_epochs(8).open()?;
+    ///
+    /// db.insert(b"k", b"v1".as_slice())?;
+    /// let epoch = db.current_epoch();
+    /// db.flush()?;
+    ///
+    /// db.insert(b"k", b"v2".as_slice())?;
+    /// db.flush()?;
+    ///
+    /// let view = db.view_at_epoch(epoch)?;
+    /// assert_eq!(&*view.get(b"k")?.unwrap(), b"v1");
+    /// assert_eq!(&*db.get(b"k")?.unwrap(), b"v2");
+    /// # Ok(()) }
+    /// ```
+    pub fn view_at_epoch(&self, epoch: FlushEpoch) -> io::Result<EpochView<LEAF_FANOUT>> {
+        EpochView::new(self.cache.clone(), epoch)
+    }
+
+    /// 创建一个全量备份，写入不存在或为空的目录 `dest`，返回之后传给
+    /// `Db::backup_incremental` 的 `BackupEpoch`。
+    ///
+    /// 备份前会先flush。备份期间并发的写入可能只有一部分被包含在这个备份中，
+    /// 它们一定会被包含在之后的增量备份中。只备份树的内容，不备份 `TreeOptions`
+    pub fn backup<P: AsRef<Path>>(&self, dest: P) -> io::Result<BackupEpoch> {
+        self.backup_inner(dest.as_ref(), None)
+    }
+
+    /// 创建一个增量备份，只写入自 `since` 对应的备份以来被修改过的叶子节点，
+    /// 返回下一次增量备份使用的 `BackupEpoch`。
+    ///
+    /// 修改记录只保存在内存中：`since` 来自数据库重新打开之前时，
+    /// 增量备份包含所有叶子节点，但仍然可以作为备份链的一部分恢复。
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let dir = tempfile::tempdir()?;
+    /// # let db: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+    /// db.insert(b"a", b"1".as_slice())?;
+    /// let epoch = db.backup(dir.path().join("full"))?;
+    ///
+    /// db.insert(b"b", b"2".as_slice())?;
+    /// db.backup_incremental(dir.path().join("delta_1"), epoch)?;
+    ///
+    /// let restored: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+    /// restored.restore_incremental(dir.path().join("full"), &[dir.path().join("delta_1")])?;
+    /// assert_eq!(restored.checksum()?, db.checksum()?);
+    /// # Ok(()) }
+    /// ```
+    pub fn backup_incremental<P: AsRef<Path>>(
+        &self,
+        dest: P,
+        since: BackupEpoch,
+    ) -> io::Result<BackupEpoch> {
+        self.backup_inner(dest.as_ref(), Some(since))
+    }
+
+    fn backup_inner(&self, dest: &Path, since: Option<BackupEpoch>) -> io::Result<BackupEpoch> {
+        self.flush()?;
+
+        let epoch = self.cache.backup_epoch();
+        let written_since = self.cache.write_tracker().written_since(since, epoch)?;
+
+        let mut collections = vec![(None, self.default_tree.clone())];
+        {
+            let trees = self.trees.lock();
+            for kv_res in self.collection_name_mapping.iter() {
+                let (name, collection_id_buf) = kv_res?;
+                let collection_id = decode_collection_entry(&collection_id_buf).0;
+                if let Some(tree) = trees.get(&collection_id) {
+                    collections.push((Some(name.to_vec()), tree.clone()));
+                }
+            }
+        }
+
+        let mut writer = BackupWriter::create(dest)?;
+        for (name, tree) in collections {
+            let mut live_objects = vec![];
+            tree.backup_leaves(
+                |object_id, dirty| dirty || written_since(object_id),
+                |object_id, entries| {
+                    live_objects.push(*object_id);
+                    match entries {
+                        Some(entries) => writer.write_object(object_id, entries),
+                        None => Ok(()),
+                    }
+                },
+            )?;
+            writer.add_collection(CollectionManifest { name, live_objects });
+        }
+        writer.finish(epoch, since)?;
+
+        info_log!("备份到 {:?} 完成，基于 {:?}", dest, since);
+        Ok(epoch)
+    }
+
+    /// 从全量备份 `base` 和依次基于它的增量备份 `deltas` 恢复数据，
+    /// 写入当前数据库中的同名树，通常用于一个新创建的空数据库。
+    ///
+    /// 备份链不连续（例如缺少一个增量备份）或备份损坏时返回错误
+    pub fn restore_incremental<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        base: P,
+        deltas: &[Q],
+    ) -> io::Result<()> {
+        let deltas: Vec<&Path> = deltas.iter().map(AsRef::as_ref).collect();
+        let chain = RestoreChain::open(base.as_ref(), &deltas)?;
+
+        let trees = chain
+            .collections()
+            .iter()
+            .map(|collection| match &collection.name {
+                Some(name) => self.open_tree(name),
+                None => Ok(self.default_tree.clone()),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        chain.for_each_object(|index, entries| {
+            let mut batch = Batch::default();
+            for (k, v) in entries {
+                batch.insert(k, v);
+            }
+            trees[index].apply_batch(batch)
+        })?;
+
+        self.flush()?;
+        Ok(())
+    }
+
     pub fn contains_tree<V: AsRef<[u8]>>(&self, name: V) -> io::Result<bool> {
         Ok(self.collection_name_mapping.get(name.as_ref())?.is_some())
     }
 
+    /// 删除一个树及其所有数据，返回树是否存在。
+    ///
+    /// 树的所有叶子节点在同一个flush epoch中被释放，之后树的集合ID会被回收，
+    /// 供之后创建的树使用。释放叶子节点之前，仍然持有的该树的句柄和迭代器被标记为
+    /// 已删除，之后的操作返回 `TreeDropped`，不会访问被释放的叶子节点或使用同一个
+    /// 集合ID的新树。
+    ///
+    /// 只写一次的树（参见 `TreeOptions::write_once`）不能通过此方法删除，
+    /// 需要使用 `Db::force_drop_tree`
     pub fn drop_tree<V: AsRef<[u8]>>(&self, name: V) -> io::Result<bool> {
-        let name_ref = name.as_ref();
-        let trees = self.trees.lock();
+        self.drop_tree_inner(name.as_ref(), false)
+    }
+
+    /// 与 `drop_tree` 相同，但也会删除只写一次的树。仅供管理工具使用
+    pub fn force_drop_tree<V: AsRef<[u8]>>(&self, name: V) -> io::Result<bool> {
+        self.drop_tree_inner(name.as_ref(), true)
+    }
 
-        let tree = if let Some(collection_id_buf) =
+    fn drop_tree_inner(&self, name_ref: &[u8], force: bool) -> io::Result<bool> {
+        let mut trees = self.trees.lock();
+
+        let collection_id = if let Some(collection_id_buf) =
             self.collection_name_mapping.get(name_ref)?
         {
-            let collection_id = CollectionId(u64::from_le_bytes(
-                collection_id_buf.as_ref().try_into().unwrap(),
-            ));
-
-            trees.get(&collection_id).unwrap()
+            decode_collection_entry(&collection_id_buf).0
         } else {
             return Ok(false);
         };
 
-        tree.clear()?;
+        let tree = trees.get(&collection_id).unwrap().clone();
+
+        if tree.is_write_once() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "树 {:?} 是只写一次的，只能通过 Db::force_drop_tree 删除",
+                    String::from_utf8_lossy(name_ref)
+                )
+            ));
+        }
+
+        // NB: the leaves are deleted before the name mapping is removed, so
+        // the removal can only become durable together with the deletion
+        tree.delete_all_leaves()?;
 
         self.collection_name_mapping.remove(name_ref)?;
+        trees.remove(&collection_id);
+        self.cache.set_value_dedup(collection_id, None);
+        self.cache.unpin_collection(collection_id);
+
+        // 在所有可能仍在使用该ID的线程离开当前epoch之后回收
+        let mut guard = self.cache.heap_object_id_pin();
+        guard.defer_drop(DeferredFree {
+            allocator: self.collection_id_allocator.clone(),
+            freed_slot: collection_id.0,
+        });
 
         Ok(true)
     }
 
+    /// 创建名为 `dest` 的新树，它是名为 `src` 的树的写时复制的克隆，返回它的句柄。
+    ///
+    /// `dest` 的内容是 `src` 在克隆时刻的一致快照，`dest` 沿用 `src` 的只写一次
+    /// 模式。已经持久化的叶子节点不被复制，`dest` 与 `src` 共享堆中的同一个对象，
+    /// 因此克隆很快并且不占用额外的磁盘空间。之后任意一个树修改共享的叶子节点时
+    /// 会把新的版本写到别处，另一个树不受影响；删除任意一个树只释放另一个树不再
+    /// 引用的对象。共享的堆槽位数量见
+    /// `Db::stats` 中的 `heap.allocator.heap_slots_shared`。
+    ///
+    /// 克隆与名称在同一次flush中持久化，崩溃后要么不存在 `dest`，要么 `dest` 是
+    /// 完整的克隆。
+    ///
+    /// 单独存储的值（参见 `Config::inline_value_threshold`）没有引用计数，启用时
+    /// 退回到分批的流式复制：复制期间对 `src` 的写入既不被阻塞，也不出现在
+    /// `dest` 中，复制中途失败时 `dest` 被删除。
+    ///
+    /// `src` 不存在时返回 `NotFound`，`dest` 已存在时返回 `AlreadyExists`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// let fixture = db.open_tree("fixture")?;
+    /// fixture.insert(b"k", b"v".as_slice())?;
+    ///
+    /// let run = db.clone_tree("fixture", "run_1")?;
+    /// run.insert(b"k", b"changed".as_slice())?;
+    ///
+    /// assert_eq!(fixture.get(b"k")?.as_deref(), Some(&b"v"[..]));
+    /// # Ok(()) }
+    /// ```
+    pub fn clone_tree<S: AsRef<[u8]>, D: AsRef<[u8]>>(
+        &self,
+        src: S,
+        dest: D,
+    ) -> io::Result<Tree<LEAF_FANOUT>> {
+        let (src, dest) = (src.as_ref(), dest.as_ref());
+
+        let mut trees = self.trees.lock();
+        let Some(src_tree) = self.existing_tree(&trees, src)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("树 {:?} 不存在", String::from_utf8_lossy(src)),
+            ));
+        };
+        if self.existing_tree(&trees, dest)?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("树 {:?} 已存在", String::from_utf8_lossy(dest)),
+            ));
+        }
+
+        if self.cache.blobs().is_enabled() {
+            let dest_tree = self.create_tree_locked(&mut trees, dest, src_tree.is_write_once())?;
+            drop(trees);
+            self.copy_tree(&src_tree, &dest_tree, src, dest)?;
+            return Ok(dest_tree);
+        }
+
+        let collection_id =
+            CollectionId(self.collection_id_allocator.try_allocate()?);
+        let dest_tree = Tree::new(
+            collection_id,
+            self.cache.clone(),
+            Index::default(),
+            self._shutdown_dropper.clone(),
+        );
+        if let Err(e) = src_tree.share_leaves(&dest_tree) {
+            self.collection_id_allocator.free(collection_id.0);
+            return Err(e);
+        }
+
+        // NB: the leaves of the clone are installed before its name, so a
+        // crash leaves either the complete clone or leaves without a name,
+        // which are freed when the database is opened again
+        self.register_tree_locked(
+            &mut trees,
+            dest,
+            collection_id,
+            &dest_tree,
+            src_tree.is_write_once(),
+        )?;
+
+        Ok(dest_tree)
+    }
+
+    /// `clone_tree` 在启用单独存储的值时使用的分批流式复制
+    fn copy_tree(
+        &self,
+        src_tree: &Tree<LEAF_FANOUT>,
+        dest_tree: &Tree<LEAF_FANOUT>,
+        src: &[u8],
+        dest: &[u8],
+    ) -> io::Result<()> {
+        let copy = || -> io::Result<()> {
+            let mut batch = Batch::default();
+            let mut batch_len = 0;
+            for kv_res in src_tree.snapshot_iter() {
+                let (key, value) = kv_res?;
+                batch.insert(key, value);
+                batch_len += 1;
+                if batch_len >= CLONE_TREE_CHUNK {
+                    dest_tree.apply_batch(std::mem::take(&mut batch))?;
+                    batch_len = 0;
+                }
+            }
+            dest_tree.apply_batch(batch)
+        };
+
+        if let Err(e) = copy() {
+            warn_log!("复制树 {:?} 失败，删除未完成的 {:?}: {:?}", src, dest, e);
+            self.force_drop_tree(dest)?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// 把 `src` 中位于 `range` 的条目按键的顺序复制到 `dst`，返回复制的统计。
+    ///
+    /// 从 `src` 流式读取，每 `CLONE_TREE_CHUNK` 个条目作为一个批次写入 `dst`，
+    /// 不必逐条插入。`dst` 在一个批次的键的范围内没有条目时整个批次直接写入，
+    /// 否则先读取这些键的当前值，按 `policy` 处理已存在的键：
+    ///
+    /// - `ConflictPolicy::Skip` 保留 `dst` 的值
+    /// - `ConflictPolicy::Overwrite` 用 `src` 的值替换
+    /// - `ConflictPolicy::Error` 写入冲突的键之前的条目，然后返回
+    ///   `KeyAlreadyExists`（`io::ErrorKind::AlreadyExists`）
+    ///
+    /// 每个批次是原子的，但整个复制不是：复制期间对 `src` 的写入可能出现在
+    /// `dst` 中，也可能不出现。中断的复制可以从返回的
+    /// `CopyStats::last_copied_key` 之后（`Bound::Excluded`）继续，
+    /// 以 `ConflictPolicy::Error` 失败时可以从冲突的键开始继续。
+    ///
+    /// `src` 和 `dst` 必须属于同一个数据库并且不是同一个树，否则返回 `InvalidInput`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// use melange_db::ConflictPolicy;
+    ///
+    /// let day = db.open_tree("events_2024_01_01")?;
+    /// day.insert(b"0001", b"login".as_slice())?;
+    /// day.insert(b"0002", b"logout".as_slice())?;
+    /// let archive = db.open_tree("archive")?;
+    /// archive.insert(b"0002", b"kept".as_slice())?;
+    ///
+    /// let stats = db.copy_tree_range::<&[u8], _>(&day, &archive, .., ConflictPolicy::Skip)?;
+    /// assert_eq!((stats.copied, stats.skipped), (1, 1));
+    /// assert_eq!(stats.last_copied_key.as_deref(), Some(&b"0002"[..]));
+    /// assert_eq!(&*archive.get(b"0002")?.unwrap(), b"kept");
+    /// # Ok(()) }
+    /// ```
+    pub fn copy_tree_range<K, R>(
+        &self,
+        src: &Tree<LEAF_FANOUT>,
+        dst: &Tree<LEAF_FANOUT>,
+        range: R,
+        policy: ConflictPolicy,
+    ) -> io::Result<CopyStats>
+    where
+        K: AsRef<[u8]>,
+        R: std::ops::RangeBounds<K>,
+    {
+        src.copy_range_into(dst, range, policy, CLONE_TREE_CHUNK)
+    }
+
+    /// 把名为 `old` 的树改名为 `new`。
+    ///
+    /// 只修改名称映射，树的内容和已经打开的句柄不受影响。旧名称的删除和新名称的
+    /// 插入是同一个批量操作，崩溃后树只会以其中一个名称存在。
+    ///
+    /// `old` 不存在时返回 `NotFound`，`new` 已存在时返回 `AlreadyExists`
+    pub fn rename_tree<O: AsRef<[u8]>, N: AsRef<[u8]>>(&self, old: O, new: N) -> io::Result<()> {
+        let (old, new) = (old.as_ref(), new.as_ref());
+
+        let _trees = self.trees.lock();
+        let Some(entry) = self.collection_name_mapping.get(old)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("树 {:?} 不存在", String::from_utf8_lossy(old)),
+            ));
+        };
+        if self.collection_name_mapping.get(new)?.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("树 {:?} 已存在", String::from_utf8_lossy(new)),
+            ));
+        }
+
+        let mut batch = Batch::default();
+        batch.remove(old);
+        batch.insert(new, entry.clone());
+        self.collection_name_mapping.apply_batch(batch)?;
+
+        if let Some(journal) = self.cache.op_journal() {
+            journal.register_tree(decode_collection_entry(&entry).0, new);
+        }
+
+        Ok(())
+    }
+
+    /// 当前分配的最大集合ID（树的内部编号），没有分配任何ID时返回 `None`。
+    /// 删除的树的ID会被回收，因此反复创建和删除树不会使其持续增长
+    pub fn max_collection_id(&self) -> Option<u64> {
+        self.collection_id_allocator.max_allocated()
+    }
+
+    /// 与 `open_tree` 相同，但使用 `options` 覆盖该树的叶子节点分裂/合并参数。
+    ///
+    /// 如果树已经打开，新参数会作用于该树的所有句柄。
+    /// 这些参数不会持久化，重新打开数据库后需要再次设置。
+    ///
+    /// 新建的树使用 `options.write_once` 并将其持久化。如果已存在的树的只写一次
+    /// 模式与 `options.write_once` 不同，返回 `io::ErrorKind::InvalidInput` 错误
+    /// 而不改变树的语义
+    pub fn open_tree_with_options<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+        options: TreeOptions,
+    ) -> io::Result<Tree<LEAF_FANOUT>> {
+        // 在创建树之前校验，避免因参数无效而留下新建的空树
+        crate::config::validate_leaf_thresholds::<LEAF_FANOUT>(
+            options.leaf_split_threshold.or(self.config.leaf_split_threshold),
+            options.leaf_merge_threshold.or(self.config.leaf_merge_threshold),
+        )?;
+
+        let tree = self.open_tree_inner(name.as_ref(), options.write_once)?;
+        if tree.is_write_once() != options.write_once {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "树 {:?} 的只写一次模式为 {}，不能以 write_once = {} 打开",
+                    String::from_utf8_lossy(name.as_ref()),
+                    tree.is_write_once(),
+                    options.write_once
+                )
+            ));
+        }
+        tree.apply_options(&options)?;
+        Ok(tree)
+    }
+
     /// 打开或创建一个新的磁盘支持的 [`Tree`]，具有自己的键空间，
     /// 可通过提供的标识符从 `Db` 访问。
+    ///
+    /// 已存在的树保持其创建时的只写一次模式，新建的树不是只写一次的
     pub fn open_tree<V: AsRef<[u8]>>(
         &self,
         name: V,
     ) -> io::Result<Tree<LEAF_FANOUT>> {
-        let name_ref = name.as_ref();
-        let mut trees = self.trees.lock();
+        self.open_tree_inner(name.as_ref(), false)
+    }
 
-        if let Some(collection_id_buf) =
-            self.collection_name_mapping.get(name_ref)?
+    /// 与 `open_tree` 相同，并把树加入名为 `group` 的flush组。
+    ///
+    /// 每个flush组按自己的 `config` 调度flush，组内的树的写入只计入该组的
+    /// 累积字节数和写入速率，不影响默认调度和其他组，可以为不同的租户设置
+    /// 不同的持久化延迟。组不存在时以 `config` 创建，已存在时用 `config`
+    /// 替换它的配置。树已经属于另一个组时改为属于 `group`。
+    ///
+    /// 所有组共用同一个flush epoch，一次flush会写入所有组的脏数据，因此一个组的
+    /// 写入最迟在它自己的调度要求的时刻被持久化，也可能更早。组的成员关系不会
+    /// 持久化，重新打开数据库后需要再次设置。
+    ///
+    /// `config` 与 `Config::open` 一样被检查，无效时返回 `InvalidInput`；打开时
+    /// 没有启动智能flusher时返回 `Unsupported`。各组的统计可以通过
+    /// `Db::flush_group_stats` 查看
+    pub fn open_tree_in_group<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+        group: &str,
+        config: SmartFlushConfig,
+    ) -> io::Result<Tree<LEAF_FANOUT>> {
+        config.validate()?;
+        if self.cache.config.flush_every_ms.is_none()
+            || !self.cache.config.smart_flush_config.enabled
         {
-            let collection_id = CollectionId(u64::from_le_bytes(
-                collection_id_buf.as_ref().try_into().unwrap(),
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "数据库打开时没有启动智能flusher，无法使用flush组",
             ));
+        }
+        let tree = self.open_tree(name)?;
+        tree.set_flush_group(self.cache.flush_groups().get_or_create(group, config));
+        Ok(tree)
+    }
+
+    /// 返回所有flush组的统计，按组的创建顺序排列
+    pub fn flush_group_stats(&self) -> Vec<FlushGroupStats> {
+        self.cache.flush_groups().stats()
+    }
+
+    /// 打开或创建名为 `name` 的持久化严格单调序列，每次持久化上界时预留
+    /// 1024个ID。同名的序列共享同一个范围，参见 [`Sequence`]
+    pub fn sequence<V: AsRef<[u8]>>(&self, name: V) -> io::Result<Sequence<LEAF_FANOUT>> {
+        self.sequence_with_batch_size(name, crate::sequence::DEFAULT_SEQUENCE_BATCH_SIZE)
+    }
+
+    /// 与 `sequence` 相同，每次持久化上界时预留 `batch_size` 个ID。批次越大
+    /// 持久化写入越少，崩溃后跳过的ID越多。序列已经打开时，新的批次大小作用于
+    /// 它的所有句柄，从下一次持久化上界开始生效。批次大小不会持久化，
+    /// `batch_size` 为0时返回 `InvalidInput` 错误
+    pub fn sequence_with_batch_size<V: AsRef<[u8]>>(
+        &self,
+        name: V,
+        batch_size: u64,
+    ) -> io::Result<Sequence<LEAF_FANOUT>> {
+        Sequence::open(&self.default_tree, &self.sequences, name.as_ref(), batch_size)
+    }
 
-            let tree = trees.get(&collection_id).unwrap();
+    /// 打开已存在的树，或以给定的只写一次模式创建新的树
+    fn open_tree_inner(
+        &self,
+        name_ref: &[u8],
+        write_once: bool,
+    ) -> io::Result<Tree<LEAF_FANOUT>> {
+        let mut trees = self.trees.lock();
 
-            return Ok(tree.clone());
+        if let Some(tree) = self.existing_tree(&trees, name_ref)? {
+            return Ok(tree);
         }
 
+        self.create_tree_locked(&mut trees, name_ref, write_once)
+    }
+
+    fn existing_tree(
+        &self,
+        trees: &HashMap<CollectionId, Tree<LEAF_FANOUT>>,
+        name_ref: &[u8],
+    ) -> io::Result<Option<Tree<LEAF_FANOUT>>> {
+        let Some(collection_id_buf) = self.collection_name_mapping.get(name_ref)? else {
+            return Ok(None);
+        };
+        let collection_id = decode_collection_entry(&collection_id_buf).0;
+        Ok(Some(trees.get(&collection_id).unwrap().clone()))
+    }
+
+    /// 创建新的树，调用方持有 `self.trees` 的锁并已确认名称不存在
+    fn create_tree_locked(
+        &self,
+        trees: &mut HashMap<CollectionId, Tree<LEAF_FANOUT>>,
+        name_ref: &[u8],
+        write_once: bool,
+    ) -> io::Result<Tree<LEAF_FANOUT>> {
         let collection_id =
-            CollectionId(self.collection_id_allocator.allocate());
+            CollectionId(self.collection_id_allocator.try_allocate()?);
 
         let initial_low_key = InlineArray::default();
 
@@ -579,30 +2021,85 @@ impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
             self._shutdown_dropper.clone(),
         );
 
+        self.register_tree_locked(trees, name_ref, collection_id, &tree, write_once)?;
+
+        Ok(tree)
+    }
+
+    /// 以 `name_ref` 为名称记录新建的树 `tree`，调用方持有 `self.trees` 的锁
+    fn register_tree_locked(
+        &self,
+        trees: &mut HashMap<CollectionId, Tree<LEAF_FANOUT>>,
+        name_ref: &[u8],
+        collection_id: CollectionId,
+        tree: &Tree<LEAF_FANOUT>,
+        write_once: bool,
+    ) -> io::Result<()> {
+        let flags = if write_once {
+            tree.set_write_once();
+            TREE_FLAG_WRITE_ONCE
+        } else {
+            0
+        };
+        if let Some(journal) = self.cache.op_journal() {
+            journal.register_tree(collection_id, name_ref);
+        }
         self.collection_name_mapping
-            .insert(name_ref, &collection_id.0.to_le_bytes())?;
+            .insert(name_ref, encode_collection_entry(collection_id, flags))?;
 
         trees.insert(collection_id, tree.clone());
 
-        Ok(tree)
+        Ok(())
     }
 }
 
+/// `Db::clone_tree` 和 `Db::copy_tree_range` 每次写入目标树的条目数
+const CLONE_TREE_CHUNK: usize = 1024;
+
+/// 名称映射中表示树是只写一次的标志位
+const TREE_FLAG_WRITE_ONCE: u8 = 1;
+
+/// 名称映射中每个树的值为8字节的小端集合ID，带有标志的树之后再追加一个
+/// 标志字节。没有标志的树保持旧的8字节格式
+fn encode_collection_entry(collection_id: CollectionId, flags: u8) -> Vec<u8> {
+    let mut buf = collection_id.0.to_le_bytes().to_vec();
+    if flags != 0 {
+        buf.push(flags);
+    }
+    buf
+}
+
+pub(crate) fn decode_collection_entry(buf: &[u8]) -> (CollectionId, u8) {
+    let collection_id =
+        CollectionId(u64::from_le_bytes(buf[..8].try_into().unwrap()));
+    let flags = buf.get(8).copied().unwrap_or(0);
+    (collection_id, flags)
+}
+
 /// 智能flusher线程函数
 fn smart_flusher<const LEAF_FANOUT: usize>(
     cache: ObjectCache<LEAF_FANOUT>,
-    shutdown_signal: mpsc::Receiver<mpsc::Sender<()>>,
-    config: SmartFlushConfig,
+    shutdown_signal: mpsc::Receiver<FlusherSignal>,
+    alive: FlusherAliveGuard,
 ) {
-    let mut scheduler = SmartFlushScheduler::new(config);
-    let stats = scheduler.get_stats();
+    // 使用树记录写入的统计，使累积字节阈值和写入速率反映实际的写入
+    let mut scheduler =
+        SmartFlushScheduler::with_stats(cache.smart_flush_config(), cache.get_write_stats());
+    let mut waiting_for_space = false;
 
+    // 文件系统已满时返回 `None`，这次flush的修改保留在缓存中，恢复写入之后重试
     let flush = || {
         let flush_res_res = std::panic::catch_unwind(|| cache.flush());
         match flush_res_res {
-            Ok(Ok(_)) => {
-                scheduler.notify_flush_completed();
-                return;
+            Ok(Ok(flush_stats)) => {
+                return Some(flush_stats);
+            }
+            Ok(Err(flush_failure)) if crate::disk_space::is_full(&flush_failure) => {
+                warn_log!(
+                    "智能Db flusher 在刷新时文件系统已满，恢复写入之后重试: {:?}",
+                    flush_failure
+                );
+                return None;
             }
             Ok(Err(flush_failure)) => {
                 error_log!(
@@ -625,18 +2122,75 @@ fn smart_flusher<const LEAF_FANOUT: usize>(
     };
 
     loop {
-        let next_delay = scheduler.calculate_next_flush_delay();
+        cache.flusher_status().beat();
+
+        // 每个周期读取一次配置，`Db::update_smart_flush_config` 的修改在这里生效
+        scheduler.update_config(cache.smart_flush_config());
+        let default_delay = scheduler.calculate_next_flush_delay();
+
+        // 等到默认调度和所有flush组中最早的一个需要flush的时刻
+        let groups: Vec<(Arc<FlushGroup>, Duration)> = cache
+            .flush_groups()
+            .all()
+            .into_iter()
+            .map(|group| {
+                let delay = group.next_flush_delay();
+                (group, delay)
+            })
+            .collect();
+        let next_delay = groups
+            .iter()
+            .map(|(_, delay)| *delay)
+            .fold(default_delay, Duration::min);
+
+        // 一次flush写入所有组的数据，但只有到期的调度重置间隔计时
+        let flush = || {
+            // 这次flush之前记录的写入，之后的写入计入下一次flush
+            let logical_bytes = (scheduler.get_stats().get_accumulated_bytes()
+                + groups.iter().map(|(group, _)| group.accumulated_bytes()).sum::<usize>())
+                as u64;
+
+            let Some(flush_stats) = flush() else {
+                return false;
+            };
+
+            let disk_bytes = flush_stats.write_batch.heap_bytes_written
+                + flush_stats.write_batch.metadata_bytes_written;
+            scheduler.record_flush_amplification(disk_bytes, logical_bytes);
+            for (group, _) in &groups {
+                group.record_flush_amplification(disk_bytes, logical_bytes);
+            }
 
-        if let Ok(shutdown_sender) = shutdown_signal.recv_timeout(next_delay) {
-            flush();
+            if default_delay <= next_delay {
+                scheduler.notify_flush_completed();
+            } else {
+                scheduler.get_stats().reset_accumulated_bytes();
+            }
+            for (group, delay) in &groups {
+                group.flush_completed(*delay <= next_delay);
+            }
+            true
+        };
+
+        // 超时或收到 `FlusherSignal::Flush` 时都执行一次常规flush
+        if let Ok(FlusherSignal::Shutdown(shutdown_sender)) =
+            shutdown_signal.recv_timeout(next_delay)
+        {
+            let flushed = flush();
+            cache.mark_clean_shutdown();
 
             cache.set_error(&io::Error::other(
                 "系统已关闭".to_string(),
             ));
 
-            assert!(cache.is_clean());
+            if flushed {
+                assert!(cache.is_clean());
+            } else {
+                error_log!("智能Db flusher 关闭时文件系统已满，最后一次flush之后的修改丢失");
+            }
 
             drop(cache);
+            drop(alive);
 
             if let Err(e) = shutdown_sender.send(()) {
                 error_log!(
@@ -649,11 +2203,21 @@ fn smart_flusher<const LEAF_FANOUT: usize>(
             return;
         }
 
-        let before_flush = Instant::now();
-        flush();
-        let flush_duration = before_flush.elapsed();
+        if !waiting_for_space {
+            let before_flush = Instant::now();
+            waiting_for_space = !flush();
+            let flush_duration = before_flush.elapsed();
 
-        debug_log!("智能flush完成，耗时: {:?}", flush_duration);
+            debug_log!("智能flush完成，耗时: {:?}", flush_duration);
+        }
+
+        // 文件系统已满时检查空间是否已经释放，释放之后重试失败的flush
+        match cache.try_resume_writes_periodically() {
+            Ok(writable) => waiting_for_space &= !writable,
+            Err(e) => {
+                warn_log!("智能Db flusher 尝试恢复写入失败: {:?}", e);
+            }
+        }
     }
 }
 
@@ -0,0 +1,263 @@
+//! 磁盘空间不足时的只读降级
+//!
+//! flush写入堆文件或元数据日志时文件系统已满（`io::ErrorKind::StorageFull`），
+//! 原本会设置全局错误，之后包括读取在内的所有操作都失败。现在：
+//!
+//! - 打开数据库时在数据库目录中预留 `Config::disk_full_headroom_bytes` 字节的
+//!   `HEADROOM_FILE`，之前打开时留下的大小正确的文件直接使用。第一次遇到空间
+//!   不足时删除它，并重试失败的写入一次，使正在进行的flush可以完成
+//! - 数据库随即进入 `WriteState::ReadOnlyDegraded`：写入以 `DiskFull` 失败，
+//!   读取、迭代和flush已经写入缓存的数据照常进行，flush时不再整理碎片
+//! - 后台flusher每隔 `PROBE_INTERVAL` 尝试重新预留空间，成功后恢复写入；
+//!   `Db::try_resume_writes` 立即尝试一次
+//!
+//! 没有预留空间（非Linux平台的默认值）或者预留空间用完之后flush仍然空间不足时，
+//! 这次flush的修改保留在缓存中，数据库保持降级状态，后台flusher等到恢复写入
+//! 之后重试，不设置全局错误
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::DiskFull;
+use crate::{info_log, warn_log};
+
+/// 预留空间的文件名，位于数据库目录中。不计入 `Db::size_on_disk`
+pub(crate) const HEADROOM_FILE: &str = "headroom";
+
+/// 不预留空间时，尝试恢复写入所分配的探测文件的大小
+const PROBE_BYTES: u64 = 1024 * 1024;
+
+/// 后台flusher尝试恢复写入的最小间隔
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 数据库能否写入，参见 `Db::write_state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteState {
+    /// 正常读写
+    Writable,
+    /// 文件系统已满，写入以 `DiskFull` 失败，读取照常进行
+    ReadOnlyDegraded,
+}
+
+/// 空间是否不足
+pub(crate) fn is_full(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::StorageFull
+}
+
+/// 一个数据库的预留空间和降级状态，由堆持有
+#[derive(Debug)]
+pub(crate) struct DiskSpace {
+    directory: PathBuf,
+    headroom_bytes: u64,
+    // 持有预留空间时为 `true`
+    reserved: Mutex<bool>,
+    degraded: AtomicBool,
+    last_probe: Mutex<Option<Instant>>,
+}
+
+impl DiskSpace {
+    /// 预留 `headroom_bytes` 字节。空间已经不足时以降级状态打开
+    pub(crate) fn open(directory: &Path, headroom_bytes: u64) -> io::Result<DiskSpace> {
+        let space = DiskSpace {
+            directory: directory.to_owned(),
+            headroom_bytes,
+            reserved: Mutex::new(false),
+            degraded: AtomicBool::new(false),
+            last_probe: Mutex::new(None),
+        };
+
+        if headroom_bytes == 0 {
+            // 之前以预留空间打开时留下的文件
+            match fs::remove_file(space.headroom_path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => return Ok(space),
+            }
+        }
+
+        match space.reserve() {
+            Ok(()) => Ok(space),
+            Err(e) if is_full(&e) => {
+                warn_log!("打开数据库时无法预留 {} 字节的空间，以只读降级状态打开", headroom_bytes);
+                space.degraded.store(true, Ordering::Release);
+                Ok(space)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn headroom_path(&self) -> PathBuf {
+        self.directory.join(HEADROOM_FILE)
+    }
+
+    pub(crate) fn write_state(&self) -> WriteState {
+        if self.degraded.load(Ordering::Acquire) {
+            WriteState::ReadOnlyDegraded
+        } else {
+            WriteState::Writable
+        }
+    }
+
+    /// 降级状态下以 `DiskFull` 拒绝写入
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        match self.write_state() {
+            WriteState::Writable => Ok(()),
+            WriteState::ReadOnlyDegraded => {
+                Err(DiskFull { path: self.directory.clone() }.into())
+            }
+        }
+    }
+
+    /// 执行 `write`。空间不足时进入降级状态，释放预留空间，然后重试一次。
+    /// `write` 必须可以安全地重复执行
+    pub(crate) fn retry_when_full<T>(&self, mut write: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        match write() {
+            Err(e) if is_full(&e) => {
+                self.enter_degraded();
+                write()
+            }
+            res => res,
+        }
+    }
+
+    /// 进入降级状态并释放预留空间，在空间不足导致写入失败时调用
+    pub(crate) fn enter_degraded(&self) {
+        if !self.degraded.swap(true, Ordering::AcqRel) {
+            warn_log!("{:?} 所在的文件系统已满，数据库进入只读降级状态", self.directory);
+        }
+
+        let mut reserved = self.reserved.lock();
+        if *reserved {
+            if let Err(e) = fs::remove_file(self.headroom_path()) {
+                warn_log!("删除预留空间的文件失败: {:?}", e);
+            }
+            *reserved = false;
+        }
+    }
+
+    /// 降级状态下尝试重新预留空间，成功时恢复写入。返回能否写入
+    pub(crate) fn try_resume(&self) -> io::Result<bool> {
+        if !self.degraded.load(Ordering::Acquire) {
+            return Ok(true);
+        }
+        *self.last_probe.lock() = Some(Instant::now());
+
+        let res = if self.headroom_bytes == 0 {
+            self.probe()
+        } else {
+            self.reserve()
+        };
+        match res {
+            Ok(()) => {
+                self.degraded.store(false, Ordering::Release);
+                info_log!("{:?} 所在的文件系统已有可用空间，恢复写入", self.directory);
+                Ok(true)
+            }
+            Err(e) if is_full(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 距离上一次尝试超过 `PROBE_INTERVAL` 时调用 `try_resume`
+    pub(crate) fn try_resume_periodically(&self) -> io::Result<bool> {
+        if !self.degraded.load(Ordering::Acquire) {
+            return Ok(true);
+        }
+        let probed_recently = self
+            .last_probe
+            .lock()
+            .is_some_and(|last_probe| last_probe.elapsed() < PROBE_INTERVAL);
+        if probed_recently {
+            return Ok(false);
+        }
+        self.try_resume()
+    }
+
+    fn reserve(&self) -> io::Result<()> {
+        let mut reserved = self.reserved.lock();
+        if *reserved {
+            return Ok(());
+        }
+        let path = self.headroom_path();
+        // 之前打开时预留的文件大小正确时直接使用，不必每次打开都重新分配
+        if is_allocated(&path, self.headroom_bytes)? {
+            *reserved = true;
+            return Ok(());
+        }
+        if let Err(e) = allocate(&path, self.headroom_bytes) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        *reserved = true;
+        Ok(())
+    }
+
+    /// 分配后立即删除一个探测文件
+    fn probe(&self) -> io::Result<()> {
+        let path = self.directory.join(format!("{}.probe", HEADROOM_FILE));
+        let res = allocate(&path, PROBE_BYTES);
+        let _ = fs::remove_file(&path);
+        res
+    }
+}
+
+/// `path` 是否是已经分配了 `len` 字节磁盘空间的文件
+fn is_allocated(path: &Path, len: u64) -> io::Result<bool> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.is_file() && metadata.len() == len && allocated_bytes(&metadata) >= len),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // `blocks` 总是以512字节为单位
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// 创建 `path` 并为它分配 `len` 字节的磁盘空间
+fn allocate(path: &Path, len: u64) -> io::Result<()> {
+    let file = fs::OpenOptions::new().create(true).truncate(true).write(true).open(path)?;
+    allocate_file(&file, len)?;
+    file.sync_all()
+}
+
+#[cfg(target_os = "linux")]
+fn allocate_file(file: &fs::File, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = libc::off_t::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "预留空间过大"))?;
+    // 不写入数据就分配空间，不支持的文件系统上由libc写入
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate_file(mut file: &fs::File, len: u64) -> io::Result<()> {
+    use std::io::Write;
+
+    // 稀疏文件不占用空间，只能写入数据
+    let zeros = vec![0_u8; 1024 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    Ok(())
+}
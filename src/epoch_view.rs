@@ -0,0 +1,162 @@
+//! 读取之前的flush epoch的状态
+//!
+//! 每次flush写入的叶子节点替换的堆文件slot在之后的
+//! `Config::retained_flush_epochs` 次flush中保持分配，堆同时记录每次flush之前
+//! 这些对象的位置。从堆中当前的对象位置开始，按从新到旧的顺序撤销晚于目标epoch
+//! 的flush，就得到目标epoch的flush完成时所有对象的位置：
+//!
+//! - 这些记录只保存在打开数据库的进程的内存中，因此视图从打开的 `Db` 创建，
+//!   数据库关闭之后之前的epoch都不能再读取
+//! - 创建视图时只计算对象的位置，每个树的叶子节点在第一次访问这个树时才读取，
+//!   之后保存在视图中。被删除的树和没有名称的树不包含在内
+//! - 被替换的值如果单独存储为blob，在对应的叶子节点不再被保留之后才删除
+//! - 之后的flush使目标epoch超出保留的范围，或者压缩堆文件时，这些slot可能被
+//!   重新使用，尚未读取的树返回 `io::ErrorKind::NotFound`
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::db::decode_collection_entry;
+use crate::leaf::Leaf;
+use crate::object_cache::ObjectCache;
+use crate::{
+    CollectionId, FlushEpoch, InlineArray, ObjectId, SlabAddress,
+    DEFAULT_COLLECTION_ID, NAME_MAPPING_COLLECTION_ID,
+};
+
+/// 一个树在视图的epoch的叶子节点，以低键为键
+type Leaves<const LEAF_FANOUT: usize> = BTreeMap<InlineArray, Box<Leaf<LEAF_FANOUT>>>;
+
+/// 数据库在之前的一个flush epoch的flush完成时的只读视图，见 `Db::view_at_epoch`
+pub struct EpochView<const LEAF_FANOUT: usize = 1024> {
+    cache: ObjectCache<LEAF_FANOUT>,
+    epoch: FlushEpoch,
+    // 每个集合在这个epoch的对象及其位置
+    objects: HashMap<CollectionId, Vec<(CollectionId, ObjectId, SlabAddress)>>,
+    // 已经读取的集合
+    loaded: Mutex<HashMap<CollectionId, Arc<Leaves<LEAF_FANOUT>>>>,
+}
+
+/// `EpochView` 中的一个树
+pub struct EpochTree<const LEAF_FANOUT: usize = 1024> {
+    cache: ObjectCache<LEAF_FANOUT>,
+    leaves: Arc<Leaves<LEAF_FANOUT>>,
+}
+
+impl<const LEAF_FANOUT: usize> EpochView<LEAF_FANOUT> {
+    pub(crate) fn new(
+        cache: ObjectCache<LEAF_FANOUT>,
+        epoch: FlushEpoch,
+    ) -> io::Result<EpochView<LEAF_FANOUT>> {
+        let current = cache.current_flush_epoch();
+        if epoch > current {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} 晚于当前的 {:?}", epoch, current),
+            ));
+        }
+        if cache.max_flushed_epoch() < epoch.get() {
+            // 目标epoch的写入还没有全部写入堆文件
+            cache.flush()?;
+        }
+
+        let mut objects: HashMap<_, Vec<_>> = HashMap::new();
+        for object in cache.heap().objects_at_epoch(epoch)? {
+            objects.entry(object.0).or_default().push(object);
+        }
+
+        Ok(EpochView { cache, epoch, objects, loaded: Mutex::default() })
+    }
+
+    /// 视图对应的epoch
+    pub fn epoch(&self) -> FlushEpoch {
+        self.epoch
+    }
+
+    /// 读取集合在这个epoch的叶子节点，已经读取过时直接返回
+    fn leaves(&self, collection_id: CollectionId) -> io::Result<Arc<Leaves<LEAF_FANOUT>>> {
+        let mut loaded = self.loaded.lock();
+        if let Some(leaves) = loaded.get(&collection_id) {
+            return Ok(leaves.clone());
+        }
+
+        let mut leaves = Leaves::new();
+        let objects = self.objects.get(&collection_id).map_or(&[][..], Vec::as_slice);
+        self.cache.heap().read_objects_at_epoch(self.epoch, objects, |_, bytes| {
+            let leaf: Box<Leaf<LEAF_FANOUT>> =
+                Leaf::deserialize(&bytes, self.cache.dictionaries(), self.cache.blobs())?;
+            if leaf.deleted.is_none() {
+                leaves.insert(leaf.lo.clone(), leaf);
+            }
+            Ok(())
+        })?;
+
+        let leaves = Arc::new(leaves);
+        loaded.insert(collection_id, leaves.clone());
+        Ok(leaves)
+    }
+
+    /// 这个epoch存在的树的名称和集合ID
+    fn names(&self) -> io::Result<Vec<(InlineArray, CollectionId)>> {
+        let mut names = vec![];
+        for leaf in self.leaves(NAME_MAPPING_COLLECTION_ID)?.values() {
+            for kv_res in leaf.iter(self.cache.blobs()) {
+                let (name, collection_id_buf) = kv_res?;
+                names.push((name, decode_collection_entry(&collection_id_buf).0));
+            }
+        }
+        Ok(names)
+    }
+
+    /// 这个epoch存在的所有树的名称，不包括默认树
+    pub fn tree_names(&self) -> io::Result<Vec<InlineArray>> {
+        Ok(self.names()?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// 默认树在这个epoch的状态
+    pub fn default_tree(&self) -> io::Result<EpochTree<LEAF_FANOUT>> {
+        self.tree_of(DEFAULT_COLLECTION_ID)
+    }
+
+    /// 名为 `name` 的树在这个epoch的状态，这时不存在的树返回 `None`
+    pub fn tree<V: AsRef<[u8]>>(&self, name: V) -> io::Result<Option<EpochTree<LEAF_FANOUT>>> {
+        let name = name.as_ref();
+        match self.names()?.into_iter().find(|(other, _)| &**other == name) {
+            Some((_, collection_id)) => self.tree_of(collection_id).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn tree_of(&self, collection_id: CollectionId) -> io::Result<EpochTree<LEAF_FANOUT>> {
+        Ok(EpochTree { cache: self.cache.clone(), leaves: self.leaves(collection_id)? })
+    }
+
+    /// 读取默认树中的键，见 `EpochTree::get`
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.default_tree()?.get(key)
+    }
+}
+
+impl<const LEAF_FANOUT: usize> EpochTree<LEAF_FANOUT> {
+    /// 读取键在视图的epoch的值
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        let key = key.as_ref();
+        let Some((_, leaf)) = self
+            .leaves
+            .range::<[u8], _>((Bound::Unbounded, Bound::Included(key)))
+            .next_back()
+        else {
+            return Ok(None);
+        };
+        leaf.get(key, self.cache.blobs())
+    }
+
+    /// 按键的顺序返回视图的epoch的所有条目
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(InlineArray, InlineArray)>> + '_ {
+        self.leaves.values().flat_map(|leaf| leaf.iter(self.cache.blobs()))
+    }
+}
@@ -0,0 +1,296 @@
+//! 按原因分类的错误
+//!
+//! 公开的API返回 `io::Result`，具体的原因以结构化的错误（例如 `QuotaExceeded`、
+//! `CorruptionError`）的形式放在 `io::Error` 中。`MelangeError::from` 把一个
+//! `io::Error` 分类为对应的变体，取出其中的结构化错误，不需要匹配错误信息；
+//! 转换不丢失任何信息，`io::Error::from` 可以把 `MelangeError` 还原为等价的
+//! `io::Error`。
+
+use std::fmt;
+use std::io;
+
+use crate::{
+    BatchGuardError, CompareAndSwapError, ContentionExceeded, CorruptionError,
+    DatabaseLocked, DiskFull, KeyAlreadyExists, LeafFanoutMismatch, QuotaExceeded, TreeDropped,
+};
+use crate::atomic_worker::CounterLimitExceeded;
+use crate::database_worker::CounterTypeMismatch;
+
+/// 返回 `MelangeError` 的 `Result`
+pub type MelangeResult<T> = Result<T, MelangeError>;
+
+/// melange_db 的错误，按原因分类，参见模块文档
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use melange_db::{MelangeError, TreeOptions};
+///
+/// # let db: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+/// let tree = db.open_tree_with_options(
+///     "ledger",
+///     TreeOptions { write_once: true, ..TreeOptions::default() },
+/// )?;
+/// tree.insert(b"k", b"v")?;
+///
+/// match tree.insert(b"k", b"w").map_err(MelangeError::from) {
+///     Err(MelangeError::KeyAlreadyExists(error)) => assert_eq!(&*error.key, b"k"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// # Ok(()) }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MelangeError {
+    /// 当前配置或平台不支持该操作（`io::ErrorKind::Unsupported`）
+    Unsupported(io::Error),
+    /// 参数或配置无效（`io::ErrorKind::InvalidInput`）
+    InvalidInput(io::Error),
+    /// 不允许的修改，例如删除只写一次的树中的键（`io::ErrorKind::PermissionDenied`）
+    PermissionDenied(io::Error),
+    /// 比较并交换的当前值与期望的值不同
+    CompareAndSwap(CompareAndSwapError),
+    /// 条件批次的守卫条件不成立
+    BatchGuard(BatchGuardError),
+    /// 在只写一次的树中写入已存在的键
+    KeyAlreadyExists(KeyAlreadyExists),
+    /// 超出树的写入速率限制
+    QuotaExceeded(QuotaExceeded),
+    /// 基于比较并交换的操作在竞争中超出重试次数
+    ContentionExceeded(ContentionExceeded),
+    /// 从磁盘读取的对象的校验和不匹配
+    Corruption(CorruptionError),
+    /// 以与创建时不同的 `LEAF_FANOUT` 打开数据库
+    LeafFanoutMismatch(LeafFanoutMismatch),
+    /// 数据库目录已被另一个 `Db` 打开
+    DatabaseLocked(DatabaseLocked),
+    /// 以另一种类型的操作访问已存在的原子计数器
+    CounterTypeMismatch(CounterTypeMismatch),
+    /// 创建原子计数器会超过计数器数量的上限
+    CounterLimitExceeded(CounterLimitExceeded),
+    /// 数据库因文件系统已满处于只读降级状态，拒绝写入
+    DiskFull(DiskFull),
+    /// 访问已被 `Db::drop_tree` 删除的树
+    TreeDropped(TreeDropped),
+    /// 磁盘空间不足（`io::ErrorKind::StorageFull`）
+    StorageFull(io::Error),
+    /// 其他IO错误
+    Io(io::Error),
+}
+
+impl MelangeError {
+    /// 与这个错误等价的 `io::Error` 的 `ErrorKind`
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            MelangeError::Unsupported(_) | MelangeError::LeafFanoutMismatch(_) => {
+                io::ErrorKind::Unsupported
+            }
+            MelangeError::InvalidInput(_) | MelangeError::CounterTypeMismatch(_) => {
+                io::ErrorKind::InvalidInput
+            }
+            MelangeError::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+            MelangeError::CompareAndSwap(_) | MelangeError::BatchGuard(_) => {
+                io::ErrorKind::Other
+            }
+            MelangeError::KeyAlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            MelangeError::QuotaExceeded(_) | MelangeError::CounterLimitExceeded(_) => {
+                io::ErrorKind::QuotaExceeded
+            }
+            MelangeError::Corruption(_) => io::ErrorKind::InvalidData,
+            MelangeError::DatabaseLocked(_) => io::ErrorKind::WouldBlock,
+            MelangeError::ContentionExceeded(_) => io::ErrorKind::ResourceBusy,
+            MelangeError::DiskFull(_) | MelangeError::StorageFull(_) => {
+                io::ErrorKind::StorageFull
+            }
+            MelangeError::TreeDropped(_) => io::ErrorKind::NotFound,
+            MelangeError::Io(error) => error.kind(),
+        }
+    }
+
+    /// 稍后以相同的参数重试是否可能成功：比较并交换冲突、守卫条件不成立、
+    /// 超出写入速率限制、竞争超出重试次数、数据库被锁定、文件系统已满而只读降级
+    /// （释放空间之后），以及被中断或超时的IO
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            MelangeError::CompareAndSwap(_)
+            | MelangeError::BatchGuard(_)
+            | MelangeError::QuotaExceeded(_)
+            | MelangeError::ContentionExceeded(_)
+            | MelangeError::DatabaseLocked(_)
+            | MelangeError::DiskFull(_) => true,
+            MelangeError::Io(error) => matches!(
+                error.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// 如果 `error` 中是 `T`，取出它，否则原样返回 `error`
+fn take<T: std::error::Error + Send + Sync + 'static>(error: io::Error) -> Result<T, io::Error> {
+    if error.get_ref().is_none_or(|inner| !inner.is::<T>()) {
+        return Err(error);
+    }
+    Ok(*error.into_inner().unwrap().downcast::<T>().unwrap())
+}
+
+impl From<io::Error> for MelangeError {
+    fn from(error: io::Error) -> MelangeError {
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::CompareAndSwap(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::BatchGuard(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::KeyAlreadyExists(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::QuotaExceeded(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::ContentionExceeded(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::Corruption(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::LeafFanoutMismatch(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::DatabaseLocked(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::CounterTypeMismatch(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::CounterLimitExceeded(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::DiskFull(inner),
+            Err(error) => error,
+        };
+        let error = match take(error) {
+            Ok(inner) => return MelangeError::TreeDropped(inner),
+            Err(error) => error,
+        };
+
+        match error.kind() {
+            io::ErrorKind::Unsupported => MelangeError::Unsupported(error),
+            io::ErrorKind::InvalidInput => MelangeError::InvalidInput(error),
+            io::ErrorKind::PermissionDenied => MelangeError::PermissionDenied(error),
+            io::ErrorKind::StorageFull => MelangeError::StorageFull(error),
+            _ => MelangeError::Io(error),
+        }
+    }
+}
+
+impl From<MelangeError> for io::Error {
+    fn from(error: MelangeError) -> io::Error {
+        match error {
+            MelangeError::Unsupported(error)
+            | MelangeError::InvalidInput(error)
+            | MelangeError::PermissionDenied(error)
+            | MelangeError::StorageFull(error)
+            | MelangeError::Io(error) => error,
+            MelangeError::CompareAndSwap(error) => io::Error::other(error),
+            MelangeError::BatchGuard(error) => io::Error::other(error),
+            MelangeError::KeyAlreadyExists(error) => error.into(),
+            MelangeError::QuotaExceeded(error) => error.into(),
+            MelangeError::ContentionExceeded(error) => error.into(),
+            MelangeError::Corruption(error) => error.into(),
+            MelangeError::LeafFanoutMismatch(error) => error.into(),
+            MelangeError::DatabaseLocked(error) => error.into(),
+            MelangeError::CounterTypeMismatch(error) => error.into(),
+            MelangeError::CounterLimitExceeded(error) => error.into(),
+            MelangeError::DiskFull(error) => error.into(),
+            MelangeError::TreeDropped(error) => error.into(),
+        }
+    }
+}
+
+impl From<CompareAndSwapError> for MelangeError {
+    fn from(error: CompareAndSwapError) -> MelangeError {
+        MelangeError::CompareAndSwap(error)
+    }
+}
+
+impl fmt::Display for MelangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MelangeError::Unsupported(error)
+            | MelangeError::InvalidInput(error)
+            | MelangeError::PermissionDenied(error)
+            | MelangeError::StorageFull(error)
+            | MelangeError::Io(error) => error.fmt(f),
+            MelangeError::CompareAndSwap(error) => error.fmt(f),
+            MelangeError::BatchGuard(error) => error.fmt(f),
+            MelangeError::KeyAlreadyExists(error) => error.fmt(f),
+            MelangeError::QuotaExceeded(error) => error.fmt(f),
+            MelangeError::ContentionExceeded(error) => error.fmt(f),
+            MelangeError::Corruption(error) => error.fmt(f),
+            MelangeError::LeafFanoutMismatch(error) => error.fmt(f),
+            MelangeError::DatabaseLocked(error) => error.fmt(f),
+            MelangeError::CounterTypeMismatch(error) => error.fmt(f),
+            MelangeError::CounterLimitExceeded(error) => error.fmt(f),
+            MelangeError::DiskFull(error) => error.fmt(f),
+            MelangeError::TreeDropped(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MelangeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MelangeError::Unsupported(error)
+            | MelangeError::InvalidInput(error)
+            | MelangeError::PermissionDenied(error)
+            | MelangeError::StorageFull(error)
+            | MelangeError::Io(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_keeps_kind_and_payload() {
+        let corruption = CorruptionError {
+            collection_id: 1,
+            object_id: 2,
+            slab_id: 3,
+            slot_size: 64,
+            slot: 4,
+        };
+        let error = MelangeError::from(io::Error::from(corruption));
+        assert!(matches!(error, MelangeError::Corruption(inner) if inner == corruption));
+
+        let error: io::Error = MelangeError::from(io::Error::from(corruption)).into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(CorruptionError::from_io_error(&error), Some(&corruption));
+
+        let full = MelangeError::from(io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(full.kind(), io::ErrorKind::StorageFull);
+        assert!(matches!(full, MelangeError::StorageFull(_)));
+        assert!(!full.is_retryable());
+
+        // 没有结构化错误的 `Other` 错误保持为 `Io`
+        let other = MelangeError::from(io::Error::other("boom"));
+        assert!(matches!(&other, MelangeError::Io(e) if e.to_string() == "boom"));
+    }
+}
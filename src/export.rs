@@ -0,0 +1,490 @@
+//! 带校验和的导出文件
+//!
+//! `Db::export_to` 把 `Db::export` 的内容写入一个可以离线校验的归档，
+//! `verify` 不需要打开数据库就能逐条检查归档，`Db::import_from` 把归档导入
+//! 数据库。所有整数都按小端序编码，归档由以下部分依次组成：
+//!
+//! - 文件头：8字节的 `MAGIC`、`u32` 格式版本、`u8` 整个文件的哈希算法，
+//!   以及前面13个字节的CRC32
+//! - 记录：每条记录以9字节的记录头开始，包括 `u8` 类型、两个 `u32` 长度
+//!   `a_len` 和 `b_len`，之后是记录头的CRC32。记录头之后是 `a_len` 和 `b_len`
+//!   字节的两段内容，最后是记录头和两段内容的CRC32。集合记录的两段内容是
+//!   集合类型和名称，之后直到下一条集合记录的键值记录都属于这个集合；键值记录的
+//!   两段内容是键和值
+//! - 文件尾：类型为 `FOOTER` 的记录，第二段内容是 `u64` 记录数和 `u64` 哈希值，
+//!   哈希值覆盖文件尾之前的所有字节
+//!
+//! 记录头单独校验，长度被损坏时不会把后面的数据误读为内容。
+//! 整个文件的哈希默认为CRC32，启用 `export-xxh3` 特性后使用XXH3-64。
+
+#[cfg(feature = "export-xxh3")]
+use std::hash::Hasher as _;
+use std::io::{self, Read, Write};
+
+use fault_injection::annotate;
+
+use crate::Db;
+
+/// 导出文件开头的魔数
+pub const MAGIC: [u8; 8] = *b"MELANGEX";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LEN: u64 = 8 + 4 + 1 + 4;
+const RECORD_HEADER_LEN: usize = 1 + 4 + 4;
+
+const COLLECTION: u8 = 1;
+const ENTRY: u8 = 2;
+const FOOTER: u8 = 0xFF;
+
+/// 导出文件的哈希算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// CRC32，扩展为 `u64`
+    Crc32,
+    /// XXH3-64，写入和校验都需要启用 `export-xxh3` 特性
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    /// 当前构建写入新的导出文件时使用的算法
+    pub const fn default_for_build() -> HashAlgorithm {
+        if cfg!(feature = "export-xxh3") {
+            HashAlgorithm::Xxh3
+        } else {
+            HashAlgorithm::Crc32
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            HashAlgorithm::Crc32 => 1,
+            HashAlgorithm::Xxh3 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<HashAlgorithm> {
+        match id {
+            1 => Some(HashAlgorithm::Crc32),
+            2 => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    fn hasher(self) -> io::Result<FileHasher> {
+        match self {
+            HashAlgorithm::Crc32 => Ok(FileHasher::Crc32(crc32fast::Hasher::new())),
+            #[cfg(feature = "export-xxh3")]
+            HashAlgorithm::Xxh3 => Ok(FileHasher::Xxh3(Box::new(twox_hash::XxHash3_64::new()))),
+            #[cfg(not(feature = "export-xxh3"))]
+            HashAlgorithm::Xxh3 => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "导出文件使用XXH3哈希，需要启用 export-xxh3 特性",
+            )),
+        }
+    }
+}
+
+enum FileHasher {
+    Crc32(crc32fast::Hasher),
+    #[cfg(feature = "export-xxh3")]
+    Xxh3(Box<twox_hash::XxHash3_64>),
+}
+
+impl FileHasher {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            FileHasher::Crc32(hasher) => hasher.update(bytes),
+            #[cfg(feature = "export-xxh3")]
+            FileHasher::Xxh3(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            FileHasher::Crc32(hasher) => u64::from(hasher.clone().finalize()),
+            #[cfg(feature = "export-xxh3")]
+            FileHasher::Xxh3(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// 一个集合及其键值对的数量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionCount {
+    /// 集合类型，树为 `b"tree"`
+    pub collection_type: Vec<u8>,
+    /// 集合名称
+    pub name: Vec<u8>,
+    /// 键值对的数量
+    pub entries: u64,
+}
+
+/// `Db::export_to` 写入的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportReport {
+    /// 集合记录和键值记录的总数
+    pub records: u64,
+    /// 按写入顺序排列的集合
+    pub collections: Vec<CollectionCount>,
+    /// 写入的字节数
+    pub bytes: u64,
+    /// 整个文件的哈希算法
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// `verify` 的结果
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VerifyReport {
+    /// 校验通过的集合记录和键值记录的总数
+    pub records: u64,
+    /// 校验通过的集合，按在文件中的顺序排列
+    pub collections: Vec<CollectionCount>,
+    /// 读取的字节数
+    pub bytes_read: u64,
+    /// 文件头中的哈希算法，文件头无效时为 `None`
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// 第一个校验失败的位置（文件头、记录或文件尾开始的字节偏移），
+    /// 之后的内容没有被检查
+    pub first_corrupt_offset: Option<u64>,
+    /// 文件在一个完整的记录之前结束时，该记录开始的字节偏移
+    pub truncated_at: Option<u64>,
+    /// 文件尾存在且它的记录数和哈希值与文件内容一致
+    pub footer_valid: bool,
+}
+
+impl VerifyReport {
+    /// 整个文件完整且没有损坏
+    pub fn is_valid(&self) -> bool {
+        self.footer_valid && self.first_corrupt_offset.is_none() && self.truncated_at.is_none()
+    }
+}
+
+/// 向 `writer` 写入一个导出文件，`export` 为 `Db::export` 的返回值
+fn write<W: Write>(
+    writer: W,
+    export: Vec<(Vec<u8>, Vec<u8>, impl Iterator<Item = Vec<Vec<u8>>>)>,
+    hash_algorithm: HashAlgorithm,
+) -> io::Result<ExportReport> {
+    let mut writer = HashingWriter {
+        inner: io::BufWriter::new(writer),
+        hasher: hash_algorithm.hasher()?,
+        bytes: 0,
+    };
+
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header.push(hash_algorithm.id());
+    header.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+    writer.write_all(&header)?;
+
+    let mut records: u64 = 0;
+    let mut collections = vec![];
+    for (collection_type, name, entries) in export {
+        write_record(&mut writer, COLLECTION, &collection_type, &name)?;
+        records += 1;
+        let mut count = CollectionCount { collection_type, name, entries: 0 };
+        for kv in entries {
+            let [key, value] = &kv[..] else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("导出的条目应为键和值两项，实际为 {} 项", kv.len()),
+                ));
+            };
+            write_record(&mut writer, ENTRY, key, value)?;
+            count.entries += 1;
+            records += 1;
+        }
+        collections.push(count);
+    }
+
+    let mut footer = Vec::with_capacity(16);
+    footer.extend_from_slice(&records.to_le_bytes());
+    footer.extend_from_slice(&writer.hasher.finish().to_le_bytes());
+    write_record(&mut writer, FOOTER, &[], &footer)?;
+    writer.inner.flush()?;
+
+    Ok(ExportReport { records, collections, bytes: writer.bytes, hash_algorithm })
+}
+
+fn write_record<W: Write>(writer: &mut W, tag: u8, a: &[u8], b: &[u8]) -> io::Result<()> {
+    let length = |bytes: &[u8]| {
+        u32::try_from(bytes.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "导出的键、值或集合名称超过4GB")
+        })
+    };
+    let mut header = [0; RECORD_HEADER_LEN];
+    header[0] = tag;
+    header[1..5].copy_from_slice(&length(a)?.to_le_bytes());
+    header[5..9].copy_from_slice(&length(b)?.to_le_bytes());
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&header);
+    crc.update(a);
+    crc.update(b);
+
+    writer.write_all(&header)?;
+    writer.write_all(&crc32fast::hash(&header).to_le_bytes())?;
+    writer.write_all(a)?;
+    writer.write_all(b)?;
+    writer.write_all(&crc.finalize().to_le_bytes())
+}
+
+struct HashingWriter<W: Write> {
+    inner: io::BufWriter<W>,
+    hasher: FileHasher,
+    bytes: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 读取导出文件时遇到的一项内容
+enum Item {
+    Collection(Vec<u8>, Vec<u8>),
+    Entry(Vec<u8>, Vec<u8>),
+}
+
+/// 按记录读取导出文件并校验，校验失败后停止
+struct ArchiveReader<R: Read> {
+    inner: R,
+    hasher: Option<FileHasher>,
+    offset: u64,
+    report: VerifyReport,
+    done: bool,
+}
+
+/// 读取 `len` 字节，文件提前结束时返回 `None`
+fn read_exact_or_eof<R: Read>(reader: &mut R, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    Ok((buf.len() == len).then_some(buf))
+}
+
+impl<R: Read> ArchiveReader<R> {
+    fn new(inner: R) -> ArchiveReader<R> {
+        ArchiveReader { inner, hasher: None, offset: 0, report: VerifyReport::default(), done: false }
+    }
+
+    /// 读取 `len` 字节并计入文件哈希
+    fn read(&mut self, len: usize) -> io::Result<Option<Vec<u8>>> {
+        let buf = read_exact_or_eof(&mut self.inner, len)?;
+        if let (Some(buf), Some(hasher)) = (&buf, &mut self.hasher) {
+            hasher.update(buf);
+        }
+        Ok(buf)
+    }
+
+    fn corrupt(&mut self, at: u64) -> io::Result<Option<Item>> {
+        self.report.first_corrupt_offset = Some(at);
+        self.done = true;
+        Ok(None)
+    }
+
+    fn truncated(&mut self, at: u64) -> io::Result<Option<Item>> {
+        self.report.truncated_at = Some(at);
+        self.done = true;
+        Ok(None)
+    }
+
+    fn read_header(&mut self) -> io::Result<Option<Item>> {
+        let Some(header) = read_exact_or_eof(&mut self.inner, HEADER_LEN as usize)? else {
+            return self.truncated(0);
+        };
+        let (fields, crc) = header.split_at(HEADER_LEN as usize - 4);
+        if fields[..8] != MAGIC
+            || crc32fast::hash(fields) != u32::from_le_bytes(crc.try_into().unwrap())
+        {
+            return self.corrupt(0);
+        }
+        let version = u32::from_le_bytes(fields[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("未知的导出文件格式版本 {}", version),
+            ));
+        }
+        let Some(hash_algorithm) = HashAlgorithm::from_id(fields[12]) else {
+            return self.corrupt(0);
+        };
+        self.report.hash_algorithm = Some(hash_algorithm);
+        let mut hasher = hash_algorithm.hasher()?;
+        hasher.update(&header);
+        self.hasher = Some(hasher);
+        self.offset = HEADER_LEN;
+        Ok(None)
+    }
+
+    /// 读取下一个集合或键值对，文件结束、校验失败或读到文件尾后返回 `None`
+    fn next_item(&mut self) -> io::Result<Option<Item>> {
+        if self.done {
+            return Ok(None);
+        }
+        if self.hasher.is_none() {
+            self.read_header()?;
+            if self.done {
+                return Ok(None);
+            }
+        }
+
+        let start = self.offset;
+        // 文件尾的哈希不包括文件尾本身
+        let hash_before_record = self.hasher.as_ref().unwrap().finish();
+
+        let Some(header) = self.read(RECORD_HEADER_LEN + 4)? else {
+            return self.truncated(start);
+        };
+        let (fields, header_crc) = header.split_at(RECORD_HEADER_LEN);
+        if crc32fast::hash(fields) != u32::from_le_bytes(header_crc.try_into().unwrap()) {
+            return self.corrupt(start);
+        }
+        let tag = fields[0];
+        let a_len = u32::from_le_bytes(fields[1..5].try_into().unwrap()) as usize;
+        let b_len = u32::from_le_bytes(fields[5..9].try_into().unwrap()) as usize;
+        if !matches!(tag, COLLECTION | ENTRY | FOOTER) || (tag == FOOTER && (a_len, b_len) != (0, 16)) {
+            return self.corrupt(start);
+        }
+
+        let (Some(a), Some(b), Some(crc)) = (self.read(a_len)?, self.read(b_len)?, self.read(4)?)
+        else {
+            return self.truncated(start);
+        };
+        let mut expected = crc32fast::Hasher::new();
+        expected.update(fields);
+        expected.update(&a);
+        expected.update(&b);
+        if expected.finalize() != u32::from_le_bytes(crc[..].try_into().unwrap()) {
+            return self.corrupt(start);
+        }
+        self.offset += (RECORD_HEADER_LEN + 4 + a_len + b_len + 4) as u64;
+        self.report.bytes_read = self.offset;
+
+        match tag {
+            COLLECTION => {
+                self.report.collections.push(CollectionCount {
+                    collection_type: a.clone(),
+                    name: b.clone(),
+                    entries: 0,
+                });
+                self.report.records += 1;
+                Ok(Some(Item::Collection(a, b)))
+            }
+            ENTRY => {
+                let Some(collection) = self.report.collections.last_mut() else {
+                    // 第一个集合记录之前的键值记录
+                    return self.corrupt(start);
+                };
+                collection.entries += 1;
+                self.report.records += 1;
+                Ok(Some(Item::Entry(a, b)))
+            }
+            _ => {
+                let records = u64::from_le_bytes(b[..8].try_into().unwrap());
+                let hash = u64::from_le_bytes(b[8..].try_into().unwrap());
+                if records != self.report.records || hash != hash_before_record {
+                    return self.corrupt(start);
+                }
+                // 文件尾之后不应再有内容
+                let mut trailing = [0; 1];
+                if self.inner.read(&mut trailing)? != 0 {
+                    return self.corrupt(self.offset);
+                }
+                self.report.footer_valid = true;
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// 逐条校验从 `reader` 读取的导出文件，不需要打开数据库。
+///
+/// 检查文件头、每条记录的CRC32和文件尾的记录数和哈希值。遇到损坏或提前结束的
+/// 文件时不返回错误，而是在 `VerifyReport` 中记录第一个损坏的位置或文件
+/// 结束的位置，以及在那之前校验通过的集合和键值对的数量。只有读取本身失败、
+/// 格式版本未知，或文件使用当前构建不支持的哈希算法时才返回错误
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let db: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+/// db.open_tree("users")?.insert(b"k", b"v")?;
+/// let mut archive = vec![];
+/// db.export_to(&mut archive)?;
+///
+/// let report = melange_db::export::verify(&archive[..])?;
+/// assert!(report.is_valid());
+/// assert_eq!(report.collections[0].entries, 1);
+///
+/// // 翻转值中的一个比特：值之后是4字节的CRC32和33字节的文件尾
+/// let value_offset = archive.len() - 33 - 4 - 1;
+/// archive[value_offset] ^= 1;
+/// let report = melange_db::export::verify(&archive[..])?;
+/// assert!(!report.is_valid());
+/// assert!(report.first_corrupt_offset.is_some());
+/// # Ok(()) }
+/// ```
+pub fn verify<R: Read>(reader: R) -> io::Result<VerifyReport> {
+    let mut archive = ArchiveReader::new(reader);
+    while archive.next_item()?.is_some() {}
+    Ok(archive.report)
+}
+
+impl<const LEAF_FANOUT: usize> Db<LEAF_FANOUT> {
+    /// 把 `Db::export` 的内容写入 `writer`，格式见 `melange_db::export` 模块。
+    /// 写入的文件可以用 `export::verify` 离线校验，用 `Db::import_from` 导入
+    pub fn export_to<W: Write>(&self, writer: W) -> io::Result<ExportReport> {
+        write(writer, self.export(), HashAlgorithm::default_for_build())
+    }
+
+    /// 导入 `Db::export_to` 写入的文件，与 `Db::import` 一样要求其中的键在数据库
+    /// 中不存在。
+    ///
+    /// 每条记录在写入前校验，损坏或提前结束时返回 `InvalidData`，错误之前的
+    /// 记录已经被写入，因此应先用 `export::verify` 检查文件，或导入空的数据库
+    pub fn import_from<R: Read>(&self, reader: R) -> io::Result<VerifyReport> {
+        let mut archive = ArchiveReader::new(reader);
+        let mut tree = None;
+        while let Some(item) = archive.next_item()? {
+            match item {
+                Item::Collection(collection_type, name) => {
+                    if collection_type != b"tree" {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("未知集合类型 {:?}", collection_type),
+                        ));
+                    }
+                    tree = Some(self.open_tree(name)?);
+                }
+                Item::Entry(key, value) => {
+                    let tree = tree.as_ref().unwrap();
+                    if tree.insert(&key, value)?.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("导入会覆盖已存在的键 {:?}", key),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let report = archive.report;
+        if !report.is_valid() {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("导出文件无效: {:?}", report),
+            )));
+        }
+        Ok(report)
+    }
+}
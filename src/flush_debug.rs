@@ -0,0 +1,208 @@
+//! 调试卡住的flush
+//!
+//! `Db::debug_flush_state` 返回当前flush的进度、各个集合中的脏对象数、
+//! 仍然持有的flush epoch guard以及后台flusher线程的心跳。设置
+//! `Config::flush_watchdog` 后，一个后台看门狗线程在flush运行超时的时候
+//! 输出这个报告。
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use inline_array::InlineArray;
+use parking_lot::Mutex;
+
+use crate::flush_epoch::EpochGuards;
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{ObjectCache, debug_log, error_log};
+
+/// 一次flush所处的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushStage {
+    /// 等待上一次flush完成
+    WaitingForPreviousFlush,
+    /// 等待被封闭的epoch中所有的flush epoch guard被释放
+    WaitingForQuiescence,
+    /// 序列化脏对象
+    Serializing,
+    /// 写入堆文件和元数据存储
+    Writing,
+    /// 写入完成后的缓存逐出和速率限制
+    Finishing,
+}
+
+/// 一次正在运行的flush
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushInProgress {
+    /// 这次flush写入的epoch
+    pub epoch: u64,
+    pub stage: FlushStage,
+    /// 从flush开始到生成报告时经过的时间
+    pub elapsed: Duration,
+}
+
+/// 一个集合中尚未flush的对象
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyCollection {
+    pub collection_id: u64,
+    /// 树的名称，默认树为 `None`。看门狗生成的报告中总是 `None`
+    pub name: Option<InlineArray>,
+    pub dirty_objects: usize,
+}
+
+/// `Db::debug_flush_state` 返回的flush状态
+#[derive(Debug, Clone)]
+pub struct FlushDebugReport {
+    /// 写入操作当前进入的flush epoch
+    pub current_epoch: u64,
+    /// 已经完整写入磁盘的最大epoch
+    pub flushed_epoch: u64,
+    /// 已经开始写入的最大epoch
+    pub flushing_epoch: u64,
+    /// 正在运行的flush，最早开始的在前
+    pub flushes_in_progress: Vec<FlushInProgress>,
+    /// 每个有脏对象的集合中的脏对象数
+    pub dirty_collections: Vec<DirtyCollection>,
+    /// 最早的脏对象的年龄，从它被写入的epoch开始时计算，因此是一个上限
+    pub oldest_dirty_age: Option<Duration>,
+    /// 当前epoch中的guard，之后是最近被封闭的epoch中仍未释放的guard
+    pub outstanding_guards: Vec<EpochGuards>,
+    /// 后台flusher线程是否在运行
+    pub flusher_alive: bool,
+    /// 后台flusher线程最后一次心跳的时间
+    pub flusher_heartbeat: Option<SystemTime>,
+}
+
+/// 正在运行的flush的阶段，由 `ObjectCache::flush` 更新
+#[derive(Debug, Default)]
+pub(crate) struct FlushProgress {
+    flushes: Mutex<BTreeMap<u64, (FlushStage, Instant)>>,
+}
+
+impl FlushProgress {
+    pub(crate) fn start(&self, epoch: u64) -> FlushProgressGuard<'_> {
+        self.flushes
+            .lock()
+            .insert(epoch, (FlushStage::WaitingForPreviousFlush, Instant::now()));
+        FlushProgressGuard { progress: self, epoch }
+    }
+
+    pub(crate) fn in_progress(&self) -> Vec<FlushInProgress> {
+        self.flushes
+            .lock()
+            .iter()
+            .map(|(epoch, (stage, started))| FlushInProgress {
+                epoch: *epoch,
+                stage: *stage,
+                elapsed: started.elapsed(),
+            })
+            .collect()
+    }
+}
+
+/// 在flush返回（包括出错返回）时将它从 `FlushProgress` 中移除
+pub(crate) struct FlushProgressGuard<'a> {
+    progress: &'a FlushProgress,
+    epoch: u64,
+}
+
+impl FlushProgressGuard<'_> {
+    pub(crate) fn set_stage(&self, stage: FlushStage) {
+        if let Some(entry) = self.progress.flushes.lock().get_mut(&self.epoch) {
+            entry.0 = stage;
+        }
+    }
+}
+
+impl Drop for FlushProgressGuard<'_> {
+    fn drop(&mut self) {
+        self.progress.flushes.lock().remove(&self.epoch);
+    }
+}
+
+/// 后台flusher线程的存活状态和心跳
+#[derive(Debug, Default)]
+pub(crate) struct FlusherStatus {
+    alive: AtomicBool,
+    /// 最后一次心跳的UNIX时间（毫秒），0表示还没有心跳
+    heartbeat_ms: PortableAtomicU64,
+}
+
+impl FlusherStatus {
+    /// 在启动flusher线程时调用，返回的guard在线程退出时将其标记为不再运行
+    pub(crate) fn start(self: Arc<Self>) -> FlusherAliveGuard {
+        self.alive.store(true, Ordering::Release);
+        self.beat();
+        FlusherAliveGuard { status: self }
+    }
+
+    /// 在flusher线程的每次循环中调用
+    pub(crate) fn beat(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.heartbeat_ms.store(now.max(1), Ordering::Release);
+    }
+
+    pub(crate) fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn last_heartbeat(&self) -> Option<SystemTime> {
+        match self.heartbeat_ms.load(Ordering::Acquire) {
+            0 => None,
+            ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+}
+
+pub(crate) struct FlusherAliveGuard {
+    status: Arc<FlusherStatus>,
+}
+
+impl Drop for FlusherAliveGuard {
+    fn drop(&mut self) {
+        self.status.alive.store(false, Ordering::Release);
+    }
+}
+
+/// flush看门狗线程，检查正在运行的flush是否超过 `limit`。每次flush只报告
+/// 一次，在 `shutdown_signal` 的发送端被释放时退出
+pub(crate) fn watchdog<const LEAF_FANOUT: usize>(
+    cache: ObjectCache<LEAF_FANOUT>,
+    shutdown_signal: mpsc::Receiver<()>,
+    limit: Duration,
+) {
+    let interval = (limit / 4).clamp(Duration::from_millis(1), Duration::from_millis(100));
+    let mut reported_epoch = 0;
+
+    while let Err(mpsc::RecvTimeoutError::Timeout) =
+        shutdown_signal.recv_timeout(interval)
+    {
+        let overdue = cache
+            .flush_progress()
+            .in_progress()
+            .into_iter()
+            .find(|flush| flush.elapsed > limit && flush.epoch > reported_epoch);
+        let Some(overdue) = overdue else {
+            continue;
+        };
+        reported_epoch = overdue.epoch;
+
+        let report = cache.debug_flush_state();
+        error_log!(
+            "flush epoch {} 已运行 {:?}，超过看门狗限制 {:?}: {:#?}",
+            overdue.epoch,
+            overdue.elapsed,
+            limit,
+            report
+        );
+        if let Some(callback) = &cache.config.flush_watchdog_callback {
+            (callback.0)(&report);
+        }
+    }
+
+    debug_log!("flush看门狗线程退出");
+}
@@ -1,13 +1,18 @@
+use std::collections::VecDeque;
 use std::num::NonZeroU64;
-use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicPtr, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
+use crate::platform_utils::{self, ThreadPriority};
 
 const SEAL_BIT: u64 = 1 << 63;
 const SEAL_MASK: u64 = u64::MAX - SEAL_BIT;
 const MIN_EPOCH: u64 = 2;
+/// 为调试报告保留的最近被封闭的epoch的数量
+const RETAINED_SEALED_EPOCHS: usize = 16;
 
 /// 优化的flush调度器
 /// 支持批量flush、优先级调度和自适应间隔
@@ -87,6 +92,10 @@ pub struct FlushConfig {
     pub batch_size_threshold: usize,
     /// 自适应调整因子
     pub adaptive_factor: f64,
+    /// 将工作线程绑定到一组CPU核心，默认不绑定
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// 工作线程的优先级，默认不修改
+    pub thread_priority: Option<ThreadPriority>,
 }
 
 impl Default for FlushConfig {
@@ -97,6 +106,8 @@ impl Default for FlushConfig {
             high_priority_interval: Duration::from_millis(50),
             batch_size_threshold: 8,
             adaptive_factor: 0.1,
+            cpu_affinity: None,
+            thread_priority: None,
         }
     }
 }
@@ -104,13 +115,13 @@ impl Default for FlushConfig {
 /// flush统计信息
 #[derive(Debug, Default)]
 pub struct FlushStats {
-    pub total_flushes: AtomicU64,
-    pub successful_flushes: AtomicU64,
-    pub failed_flushes: AtomicU64,
-    pub average_flush_time: AtomicU64,
-    pub max_flush_time: AtomicU64,
-    pub batch_flushes: AtomicU64,
-    pub adaptive_intervals: AtomicU64,
+    pub total_flushes: PortableAtomicU64,
+    pub successful_flushes: PortableAtomicU64,
+    pub failed_flushes: PortableAtomicU64,
+    pub average_flush_time: PortableAtomicU64,
+    pub max_flush_time: PortableAtomicU64,
+    pub batch_flushes: PortableAtomicU64,
+    pub adaptive_intervals: PortableAtomicU64,
 }
 
 impl OptimizedFlushScheduler {
@@ -140,8 +151,13 @@ impl OptimizedFlushScheduler {
             let running = self.running.clone();
 
             let handle = std::thread::Builder::new()
-                .name(format!("melange_flush_worker_{}", thread_id))
+                .name(format!("melange-flush-{}", thread_id))
                 .spawn(move || {
+                    platform_utils::configure_current_thread(
+                        "flush工作",
+                        config.cpu_affinity.as_deref(),
+                        config.thread_priority,
+                    );
                     worker_loop(receiver, stats, config, running);
                 })
                 .expect("Failed to create flush worker thread");
@@ -216,13 +232,13 @@ impl OptimizedFlushScheduler {
     /// 获取统计信息
     pub fn get_stats(&self) -> FlushStats {
         FlushStats {
-            total_flushes: AtomicU64::new(self.stats.total_flushes.load(Ordering::Relaxed)),
-            successful_flushes: AtomicU64::new(self.stats.successful_flushes.load(Ordering::Relaxed)),
-            failed_flushes: AtomicU64::new(self.stats.failed_flushes.load(Ordering::Relaxed)),
-            average_flush_time: AtomicU64::new(self.stats.average_flush_time.load(Ordering::Relaxed)),
-            max_flush_time: AtomicU64::new(self.stats.max_flush_time.load(Ordering::Relaxed)),
-            batch_flushes: AtomicU64::new(self.stats.batch_flushes.load(Ordering::Relaxed)),
-            adaptive_intervals: AtomicU64::new(self.stats.adaptive_intervals.load(Ordering::Relaxed)),
+            total_flushes: PortableAtomicU64::new(self.stats.total_flushes.load(Ordering::Relaxed)),
+            successful_flushes: PortableAtomicU64::new(self.stats.successful_flushes.load(Ordering::Relaxed)),
+            failed_flushes: PortableAtomicU64::new(self.stats.failed_flushes.load(Ordering::Relaxed)),
+            average_flush_time: PortableAtomicU64::new(self.stats.average_flush_time.load(Ordering::Relaxed)),
+            max_flush_time: PortableAtomicU64::new(self.stats.max_flush_time.load(Ordering::Relaxed)),
+            batch_flushes: PortableAtomicU64::new(self.stats.batch_flushes.load(Ordering::Relaxed)),
+            adaptive_intervals: PortableAtomicU64::new(self.stats.adaptive_intervals.load(Ordering::Relaxed)),
         }
     }
 
@@ -403,6 +419,7 @@ fn perform_batch_flush(tasks: Vec<BatchFlushTask>) -> Vec<std::io::Result<()>> {
 }
 
 // 原始的FlushEpoch相关代码保持不变
+/// 写入所属的flush epoch，每次flush向前推进一次，见 `Db::current_epoch`
 #[derive(
     Debug,
     Clone,
@@ -437,8 +454,12 @@ impl concurrent_map::Minimum for FlushEpoch {
 
 #[derive(Debug)]
 pub(crate) struct FlushInvariants {
-    max_flushed_epoch: AtomicU64,
-    max_flushing_epoch: AtomicU64,
+    max_flushed_epoch: PortableAtomicU64,
+    max_flushing_epoch: PortableAtomicU64,
+    /// Notified whenever `max_flushed_epoch` advances, for
+    /// `wait_for_flushed_epoch`.
+    flushed_mu: Mutex<()>,
+    flushed_cv: Condvar,
 }
 
 impl Default for FlushInvariants {
@@ -446,15 +467,41 @@ impl Default for FlushInvariants {
         FlushInvariants {
             max_flushed_epoch: (MIN_EPOCH - 1).into(),
             max_flushing_epoch: (MIN_EPOCH - 1).into(),
+            flushed_mu: Mutex::new(()),
+            flushed_cv: Condvar::new(),
         }
     }
 }
 
 impl FlushInvariants {
+    pub(crate) fn max_flushed_epoch(&self) -> u64 {
+        self.max_flushed_epoch.load(Ordering::SeqCst)
+    }
+
     pub(crate) fn mark_flushed_epoch(&self, epoch: FlushEpoch) {
+        let mu = self.flushed_mu.lock().unwrap();
         let last = self.max_flushed_epoch.swap(epoch.get(), Ordering::SeqCst);
+        drop(mu);
+        self.flushed_cv.notify_all();
 
-        assert_eq!(last + 1, epoch.get());
+        // the objects of a failed flush are written by the next flush, so
+        // a failed epoch is flushed along with the one after it
+        assert!(last < epoch.get());
+    }
+
+    /// Blocks until `epoch` has been completely flushed or `timeout`
+    /// elapses, returning whether it was flushed.
+    pub(crate) fn wait_for_flushed_epoch(&self, epoch: u64, timeout: Duration) -> bool {
+        let mu = self.flushed_mu.lock().unwrap();
+        let (_mu, _) = self
+            .flushed_cv
+            .wait_timeout_while(mu, timeout, |_| self.max_flushed_epoch() < epoch)
+            .unwrap();
+        self.max_flushed_epoch() >= epoch
+    }
+
+    pub(crate) fn max_flushing_epoch(&self) -> u64 {
+        self.max_flushing_epoch.load(Ordering::SeqCst)
     }
 
     pub(crate) fn mark_flushing_epoch(&self, epoch: FlushEpoch) {
@@ -516,10 +563,17 @@ impl Completion {
 pub struct FlushEpochGuard<'a> {
     tracker: &'a EpochTracker,
     previously_sealed: bool,
+    #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+    backtrace_id: Option<u64>,
 }
 
 impl Drop for FlushEpochGuard<'_> {
     fn drop(&mut self) {
+        #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+        if let Some(id) = self.backtrace_id {
+            self.tracker.backtraces.lock().unwrap().remove(&id);
+        }
+
         let rc = self.tracker.rc.fetch_sub(1, Ordering::SeqCst) - 1;
         if rc & SEAL_MASK == 0 && (rc & SEAL_BIT) == SEAL_BIT {
             crate::debug_delay();
@@ -539,22 +593,85 @@ impl FlushEpochGuard<'_> {
 #[derive(Debug)]
 pub(crate) struct EpochTracker {
     epoch: FlushEpoch,
-    rc: AtomicU64,
+    rc: PortableAtomicU64,
     vacancy_notifier: Completion,
     previous_flush_complete: Completion,
+    started: Instant,
+    /// Creation backtraces of the guards that are still checked in,
+    /// keyed by a per-guard id.
+    #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+    backtraces: Mutex<std::collections::HashMap<u64, std::backtrace::Backtrace>>,
+}
+
+impl EpochTracker {
+    fn new(epoch: FlushEpoch, previous_flush_complete: Completion) -> EpochTracker {
+        EpochTracker {
+            epoch,
+            rc: PortableAtomicU64::new(0),
+            vacancy_notifier: Completion::new(epoch),
+            previous_flush_complete,
+            started: Instant::now(),
+            #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+            backtraces: Mutex::default(),
+        }
+    }
+
+    fn guards(&self) -> EpochGuards {
+        let rc = self.rc.load(Ordering::SeqCst);
+
+        #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+        let backtraces = self
+            .backtraces
+            .lock()
+            .unwrap()
+            .values()
+            .map(|backtrace| backtrace.to_string())
+            .collect();
+        #[cfg(not(all(feature = "epoch-guard-backtraces", debug_assertions)))]
+        let backtraces = vec![];
+
+        EpochGuards {
+            epoch: self.epoch.get(),
+            sealed: rc & SEAL_BIT == SEAL_BIT,
+            count: rc & SEAL_MASK,
+            age: self.started.elapsed(),
+            backtraces,
+        }
+    }
+}
+
+/// The flush epoch guards that are checked into one epoch, reported by
+/// `Db::debug_flush_state`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochGuards {
+    pub epoch: u64,
+    /// Whether a flush has sealed this epoch and is waiting for the
+    /// remaining guards to be dropped.
+    pub sealed: bool,
+    pub count: u64,
+    /// Time since the epoch became the active epoch.
+    pub age: Duration,
+    /// Where each outstanding guard was created. Only collected in debug
+    /// builds with the `epoch-guard-backtraces` feature.
+    pub backtraces: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct FlushEpochTracker {
-    active_ebr: ebr::Ebr<Box<EpochTracker>, 16, 16>,
+    active_ebr: ebr::Ebr<Arc<EpochTracker>, 16, 16>,
     inner: Arc<FlushEpochInner>,
 }
 
 #[derive(Debug)]
 pub(crate) struct FlushEpochInner {
-    counter: AtomicU64,
+    counter: PortableAtomicU64,
     roll_mu: Mutex<()>,
     current_active: AtomicPtr<EpochTracker>,
+    /// The most recently sealed epochs, oldest first, kept so that guards
+    /// holding up a flush can be reported.
+    sealed: Mutex<VecDeque<Arc<EpochTracker>>>,
+    #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+    next_guard_id: PortableAtomicU64,
 }
 
 impl Drop for FlushEpochInner {
@@ -564,7 +681,7 @@ impl Drop for FlushEpochInner {
             self.current_active.swap(std::ptr::null_mut(), Ordering::SeqCst);
         if !old_ptr.is_null() {
             //let old: &EpochTracker = &*old_ptr;
-            unsafe { drop(Box::from_raw(old_ptr)) }
+            unsafe { drop(Arc::from_raw(old_ptr)) }
         }
         drop(vacancy_mu);
     }
@@ -573,14 +690,10 @@ impl Drop for FlushEpochInner {
 impl Default for FlushEpochTracker {
     fn default() -> FlushEpochTracker {
         let last = Completion::new(FlushEpoch(NonZeroU64::new(1).unwrap()));
-        let current_active_ptr = Box::into_raw(Box::new(EpochTracker {
-            epoch: FlushEpoch(NonZeroU64::new(MIN_EPOCH).unwrap()),
-            rc: AtomicU64::new(0),
-            vacancy_notifier: Completion::new(FlushEpoch(
-                NonZeroU64::new(MIN_EPOCH).unwrap(),
-            )),
-            previous_flush_complete: last.clone(),
-        }));
+        let current_active_ptr = Arc::into_raw(Arc::new(EpochTracker::new(
+            FlushEpoch(NonZeroU64::new(MIN_EPOCH).unwrap()),
+            last.clone(),
+        ))) as *mut EpochTracker;
 
         last.mark_complete();
 
@@ -588,9 +701,12 @@ impl Default for FlushEpochTracker {
 
         FlushEpochTracker {
             inner: Arc::new(FlushEpochInner {
-                counter: AtomicU64::new(2),
+                counter: PortableAtomicU64::new(2),
                 roll_mu: Mutex::new(()),
                 current_active,
+                sealed: Mutex::default(),
+                #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+                next_guard_id: PortableAtomicU64::new(0),
             }),
             active_ebr: ebr::Ebr::default(),
         }
@@ -598,6 +714,14 @@ impl Default for FlushEpochTracker {
 }
 
 impl FlushEpochTracker {
+    /// Advances the epoch of this handle's retired trackers, see
+    /// `ObjectCache::collect_garbage`.
+    pub(crate) fn collect_garbage(&self) {
+        for _ in 0..crate::EBR_EPOCH_ADVANCES {
+            self.active_ebr.manually_advance_epoch();
+        }
+    }
+
     /// Returns the epoch notifier for the previous epoch.
     /// Intended to be passed to a flusher that can eventually
     /// notify the flush-requesting thread.
@@ -615,12 +739,10 @@ impl FlushEpochTracker {
 
         let forward_flush_notifier = Completion::new(flush_through_epoch);
 
-        let new_active = Box::into_raw(Box::new(EpochTracker {
-            epoch: new_epoch,
-            rc: AtomicU64::new(0),
-            vacancy_notifier: Completion::new(new_epoch),
-            previous_flush_complete: forward_flush_notifier.clone(),
-        }));
+        let new_active = Arc::into_raw(Arc::new(EpochTracker::new(
+            new_epoch,
+            forward_flush_notifier.clone(),
+        ))) as *mut EpochTracker;
 
         let old_ptr =
             self.inner.current_active.swap(new_active, Ordering::SeqCst);
@@ -640,11 +762,23 @@ impl FlushEpochTracker {
 
             // mark_complete_inner called via drop in a uniform way
             //println!("dropping flush epoch guard for epoch {flush_through}");
-            drop(FlushEpochGuard { tracker: old, previously_sealed: true });
+            drop(FlushEpochGuard {
+                tracker: old,
+                previously_sealed: true,
+                #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+                backtrace_id: None,
+            });
 
             (old.previous_flush_complete.clone(), old.vacancy_notifier.clone())
         };
-        tracker_guard.defer_drop(unsafe { Box::from_raw(old_ptr) });
+        let old = unsafe { Arc::from_raw(old_ptr) };
+        let mut sealed = self.inner.sealed.lock().unwrap();
+        if sealed.len() == RETAINED_SEALED_EPOCHS {
+            sealed.pop_front();
+        }
+        sealed.push_back(old.clone());
+        drop(sealed);
+        tracker_guard.defer_drop(old);
         drop(vacancy_mu);
         (last_flush_complete_notifier, vacancy_notifier, forward_flush_notifier)
     }
@@ -659,7 +793,13 @@ impl FlushEpochTracker {
 
             let previously_sealed = rc & SEAL_BIT == SEAL_BIT;
 
-            let guard = FlushEpochGuard { tracker, previously_sealed };
+            #[allow(unused_mut)]
+            let mut guard = FlushEpochGuard {
+                tracker,
+                previously_sealed,
+                #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+                backtrace_id: None,
+            };
 
             if previously_sealed {
                 // the epoch is already closed, so we must drop the rc
@@ -667,6 +807,16 @@ impl FlushEpochTracker {
                 // Drop impl.
                 drop(guard);
             } else {
+                #[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+                {
+                    let id = self.inner.next_guard_id.fetch_add(1, Ordering::Relaxed);
+                    tracker
+                        .backtraces
+                        .lock()
+                        .unwrap()
+                        .insert(id, std::backtrace::Backtrace::force_capture());
+                    guard.backtrace_id = Some(id);
+                }
                 return guard;
             }
         }
@@ -681,6 +831,50 @@ impl FlushEpochTracker {
 
         FlushEpoch(NonZeroU64::new(current).unwrap())
     }
+
+    /// The guards checked into the active epoch, followed by those still
+    /// checked into recently sealed epochs, newest first.
+    pub(crate) fn outstanding_guards(&self) -> Vec<EpochGuards> {
+        let _tracker_guard = self.active_ebr.pin();
+        let vacancy_mu = self.inner.roll_mu.lock().unwrap();
+
+        let active: &EpochTracker =
+            unsafe { &*self.inner.current_active.load(Ordering::SeqCst) };
+        let mut ret = vec![active.guards()];
+
+        for tracker in self.inner.sealed.lock().unwrap().iter().rev() {
+            let guards = tracker.guards();
+            if guards.count > 0 {
+                ret.push(guards);
+            }
+        }
+        drop(vacancy_mu);
+
+        ret
+    }
+
+    /// When `epoch` became the active epoch, if it is the active epoch or
+    /// one of the recently sealed ones.
+    pub(crate) fn epoch_started(&self, epoch: FlushEpoch) -> Option<Instant> {
+        let _tracker_guard = self.active_ebr.pin();
+        let vacancy_mu = self.inner.roll_mu.lock().unwrap();
+
+        let active: &EpochTracker =
+            unsafe { &*self.inner.current_active.load(Ordering::SeqCst) };
+        let ret = if active.epoch == epoch {
+            Some(active.started)
+        } else {
+            let sealed = self.inner.sealed.lock().unwrap();
+            match sealed.iter().find(|tracker| tracker.epoch == epoch) {
+                Some(tracker) => Some(tracker.started),
+                // older than every retained epoch
+                None => sealed.front().map(|tracker| tracker.started),
+            }
+        };
+        drop(vacancy_mu);
+
+        ret
+    }
 }
 
 #[test]
@@ -713,7 +907,7 @@ fn concurrent_flush_epoch_burn_in_inner() {
 
     let barrier = std::sync::Arc::new(std::sync::Barrier::new(N_THREADS * 2 + 1));
 
-    let pt = pagetable::PageTable::<AtomicU64>::default();
+    let pt = pagetable::PageTable::<PortableAtomicU64>::default();
 
     let rolls = || {
         let fa = fa.clone();
@@ -0,0 +1,178 @@
+//! 按树分组的flush调度
+//!
+//! 每个flush组有自己的 `SmartFlushConfig` 和写入负载统计，属于它的树的写入
+//! 只计入该组的统计，智能flusher按每个组自己的间隔和累积字节阈值安排flush，
+//! 写入频繁的租户不会缩短其他租户的flush间隔，反之亦然。没有加入任何组的树
+//! 使用 `Config::smart_flush_config` 对应的默认调度。
+//!
+//! 所有树共用同一个flush epoch，一次flush会写入所有组的脏叶子节点，因此一个组
+//! 的写入最迟在它自己的调度要求的时刻被持久化，也可能因为其他组的flush而更早
+//! 被持久化。其他组触发的flush只清零一个组的累积字节数，不重置它的间隔计时，
+//! 每个组仍然按自己的节奏请求flush。
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::smart_flush::{SmartFlushConfig, SmartFlushScheduler, WriteLoadStats};
+
+/// 一个flush组的统计，由 `Db::flush_group_stats` 返回
+#[derive(Debug, Clone)]
+pub struct FlushGroupStats {
+    /// 组的名称
+    pub name: String,
+    /// 组当前使用的配置
+    pub config: SmartFlushConfig,
+    /// 由这个组的调度触发的flush次数
+    pub flushes: u64,
+    /// 组内的树记录的写入字节数
+    pub bytes_written: u64,
+    /// 距离这个组上一次触发flush的时间，尚未触发过时为 `None`
+    pub since_last_flush: Option<Duration>,
+}
+
+/// 一个flush组
+pub(crate) struct FlushGroup {
+    name: String,
+    scheduler: RwLock<SmartFlushScheduler>,
+    stats: Arc<WriteLoadStats>,
+    flushes: PortableAtomicU64,
+    bytes_written: PortableAtomicU64,
+    last_flush: RwLock<Option<Instant>>,
+}
+
+impl FlushGroup {
+    fn new(name: &str, config: SmartFlushConfig) -> FlushGroup {
+        let stats = Arc::new(WriteLoadStats::new());
+        FlushGroup {
+            name: name.to_string(),
+            scheduler: RwLock::new(SmartFlushScheduler::with_stats(config, stats.clone())),
+            stats,
+            flushes: PortableAtomicU64::new(0),
+            bytes_written: PortableAtomicU64::new(0),
+            last_flush: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn record_write(&self, bytes_written: usize) {
+        self.stats.record_write(bytes_written);
+        self.bytes_written.fetch_add(bytes_written as u64, Ordering::Relaxed);
+    }
+
+    /// 按组的配置计算距离下一次flush的时间
+    pub(crate) fn next_flush_delay(&self) -> Duration {
+        self.scheduler.read().calculate_next_flush_delay()
+    }
+
+    /// 组内尚未flush的写入字节数
+    pub(crate) fn accumulated_bytes(&self) -> usize {
+        self.stats.get_accumulated_bytes()
+    }
+
+    /// 按组的配置根据一次flush的写放大调整间隔，参见
+    /// `SmartFlushScheduler::record_flush_amplification`
+    pub(crate) fn record_flush_amplification(&self, disk_bytes: u64, logical_bytes: u64) {
+        self.scheduler.read().record_flush_amplification(disk_bytes, logical_bytes);
+    }
+
+    /// 一次flush完成后调用，`requested` 表示这次flush是否由这个组的调度触发
+    pub(crate) fn flush_completed(&self, requested: bool) {
+        if requested {
+            self.scheduler.read().notify_flush_completed();
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            *self.last_flush.write() = Some(Instant::now());
+        } else {
+            self.stats.reset_accumulated_bytes();
+        }
+    }
+
+    fn stats(&self, config: SmartFlushConfig) -> FlushGroupStats {
+        FlushGroupStats {
+            name: self.name.clone(),
+            config,
+            flushes: self.flushes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            since_last_flush: self.last_flush.read().map(|at| at.elapsed()),
+        }
+    }
+}
+
+/// 数据库的所有flush组，按创建顺序排列
+#[derive(Default)]
+pub(crate) struct FlushGroups {
+    groups: RwLock<Vec<(SmartFlushConfig, Arc<FlushGroup>)>>,
+}
+
+impl FlushGroups {
+    /// 返回名为 `name` 的组，组已存在时用 `config` 替换它的配置
+    pub(crate) fn get_or_create(&self, name: &str, config: SmartFlushConfig) -> Arc<FlushGroup> {
+        let mut groups = self.groups.write();
+        if let Some((existing, group)) = groups.iter_mut().find(|(_, group)| group.name == name) {
+            group.scheduler.write().update_config(config.clone());
+            *existing = config;
+            return group.clone();
+        }
+        let group = Arc::new(FlushGroup::new(name, config.clone()));
+        groups.push((config, group.clone()));
+        group
+    }
+
+    pub(crate) fn all(&self) -> Vec<Arc<FlushGroup>> {
+        self.groups.read().iter().map(|(_, group)| group.clone()).collect()
+    }
+
+    pub(crate) fn stats(&self) -> Vec<FlushGroupStats> {
+        self.groups
+            .read()
+            .iter()
+            .map(|(config, group)| group.stats(config.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(base_interval_ms: usize) -> SmartFlushConfig {
+        SmartFlushConfig {
+            base_interval_ms,
+            min_interval_ms: base_interval_ms / 2,
+            max_interval_ms: base_interval_ms * 2,
+            ..SmartFlushConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_get_or_create_replaces_config() {
+        let groups = FlushGroups::default();
+        let first = groups.get_or_create("a", config(100));
+        let second = groups.get_or_create("a", config(1000));
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let stats = groups.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].config.base_interval_ms, 1000);
+        assert!(first.next_flush_delay() > Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_other_groups_flush_only_resets_bytes() {
+        let groups = FlushGroups::default();
+        let group = groups.get_or_create("a", config(100));
+        group.record_write(10);
+
+        group.flush_completed(false);
+        let stats = &groups.stats()[0];
+        assert_eq!((stats.flushes, stats.bytes_written), (0, 10));
+        assert!(stats.since_last_flush.is_none());
+
+        group.flush_completed(true);
+        let stats = &groups.stats()[0];
+        assert_eq!(stats.flushes, 1);
+        assert!(stats.since_last_flush.is_some());
+    }
+}
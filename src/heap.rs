@@ -1,24 +1,51 @@
+use std::collections::VecDeque;
 use std::fmt;
 use std::fs;
 use std::io::{self, Read};
 use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering, fence};
+use std::sync::atomic::{
+    AtomicBool, AtomicPtr, AtomicUsize, Ordering, fence,
+};
 use std::time::{Duration, Instant};
 
 use ebr::{Ebr, Guard};
 use fault_injection::{annotate, fallible, maybe};
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use fs2::FileExt as _;
 use parking_lot::{Mutex, RwLock};
 use rayon::prelude::*;
 
+use crate::disk_space::DiskSpace;
+use crate::metadata_store::{MetadataMaintenance, MetadataStats};
 use crate::object_location_mapper::{AllocatorStats, ObjectLocationMapper};
-use crate::{CollectionId, Config, DeferredFree, MetadataStore, ObjectId};
+use crate::{
+    ChecksumKind, ChecksumMode, CollectionId, CompressionAlgorithm, Config, CorruptionError,
+    CorruptionPolicy, DatabaseLocked, DeferredFree, FlushEpoch, LeafFanoutMismatch,
+    MetadataStore, ObjectId, RecoveryPhase, RecoveryProgress, SlabFileUsage,
+    EBR_EPOCH_ADVANCES,
+};
 
 const WARN: &str = "DO_NOT_PUT_YOUR_FILES_HERE";
+/// Written after the final flush of a clean shutdown and removed at the
+/// start of recovery, so it only exists while the database is closed and
+/// was closed cleanly. It records the stored size of every object, which
+/// lets recovery skip reading the slabs.
+const CLEAN_SHUTDOWN: &str = "clean_shutdown";
+const SETTINGS_COOKIE: &str = "durability_cookie";
+/// The version of the on-disk storage format written by this crate.
+/// Databases with a newer format version are refused at open.
+///
+/// Version 2 records the checksum algorithm in every heap slot.
+pub(crate) const FORMAT_VERSION: u32 = 2;
+/// The first format version whose heap slots end with a checksum kind byte.
+/// Older databases store a CRC32 in the last 4 bytes of every slot.
+const TAGGED_SLOTS_FORMAT_VERSION: u32 = 2;
+/// The checksum kind byte plus the longest checksum.
+const MAX_CHECKSUM_TRAILER: usize = 9;
 pub(crate) const N_SLABS: usize = 78;
 const FILE_TARGET_FILL_RATIO: u64 = 80;
 const FILE_RESIZE_MARGIN: u64 = 115;
@@ -125,6 +152,11 @@ pub struct HeapStats {
     pub write_batch_max: WriteBatchStats,
     pub write_batch_sum: WriteBatchStats,
     pub truncated_file_bytes: u64,
+    /// The number of objects that failed checksum verification, including
+    /// those found while recovering.
+    pub corruption_events: u64,
+    /// The most recent checksum failure.
+    pub last_corruption: Option<CorruptionError>,
 }
 
 impl WriteBatchStats {
@@ -183,21 +215,77 @@ impl WriteBatchStats {
 }
 
 const fn overhead_for_size(size: usize) -> usize {
-    if size + 5 <= u8::MAX as usize {
-        // crc32 + 1 byte frame
-        5
-    } else if size + 6 <= u16::MAX as usize {
-        // crc32 + 2 byte frame
-        6
-    } else if size + 8 <= u32::MAX as usize {
-        // crc32 + 4 byte frame
-        8
+    if size + 1 + MAX_CHECKSUM_TRAILER <= u8::MAX as usize {
+        // checksum trailer + 1 byte frame
+        1 + MAX_CHECKSUM_TRAILER
+    } else if size + 2 + MAX_CHECKSUM_TRAILER <= u16::MAX as usize {
+        // checksum trailer + 2 byte frame
+        2 + MAX_CHECKSUM_TRAILER
+    } else if size + 4 + MAX_CHECKSUM_TRAILER <= u32::MAX as usize {
+        // checksum trailer + 4 byte frame
+        4 + MAX_CHECKSUM_TRAILER
     } else {
-        // crc32 + 8 byte frame
-        12
+        // checksum trailer + 8 byte frame
+        8 + MAX_CHECKSUM_TRAILER
     }
 }
 
+const fn checksum_len(kind: ChecksumKind) -> usize {
+    match kind {
+        ChecksumKind::None => 0,
+        ChecksumKind::Crc32 => 4,
+        ChecksumKind::XxHash64 => 8,
+    }
+}
+
+const fn checksum_tag(kind: ChecksumKind) -> u8 {
+    match kind {
+        ChecksumKind::None => 0,
+        ChecksumKind::Crc32 => 1,
+        ChecksumKind::XxHash64 => 2,
+    }
+}
+
+const fn checksum_kind_for_tag(tag: u8) -> Option<ChecksumKind> {
+    match tag {
+        0 => Some(ChecksumKind::None),
+        1 => Some(ChecksumKind::Crc32),
+        2 => Some(ChecksumKind::XxHash64),
+        _ => None,
+    }
+}
+
+/// Computes the checksum of the slot bytes in front of it. In tagged slots
+/// the kind byte after the checksum is covered as well, so that a flipped
+/// kind byte is caught unless it turns into `ChecksumKind::None`. Only the
+/// first `checksum_len(kind)` bytes of the result are used.
+fn slot_checksum(kind: ChecksumKind, body: &[u8], tag: Option<u8>) -> [u8; 8] {
+    let mut checksum = [0; 8];
+    match kind {
+        ChecksumKind::None => {}
+        ChecksumKind::Crc32 => {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(body);
+            if let Some(tag) = tag {
+                hasher.update(&[tag]);
+            }
+            checksum[..4]
+                .copy_from_slice(&(hasher.finalize() ^ 0xAF).to_le_bytes());
+        }
+        ChecksumKind::XxHash64 => {
+            use std::hash::Hasher as _;
+
+            let mut hasher = twox_hash::XxHash64::with_seed(0);
+            hasher.write(body);
+            if let Some(tag) = tag {
+                hasher.write(&[tag]);
+            }
+            checksum.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+    }
+    checksum
+}
+
 fn slab_for_size(size: usize) -> u8 {
     let total_size = size + overhead_for_size(size);
     for (idx, slab_size) in SLAB_SIZES.iter().enumerate() {
@@ -221,34 +309,162 @@ pub struct HeapRecovery {
     pub heap: Heap,
     pub recovered_nodes: Vec<ObjectRecovery>,
     pub was_recovered: bool,
+    pub report: RecoveryReport,
+}
+
+/// What opening an existing database had to do, returned by
+/// `Db::recovery_report`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The number of live objects (tree leaves) recovered.
+    pub objects_recovered: u64,
+    /// The number of trees that had at least one recovered leaf.
+    pub collections_recovered: u64,
+    /// Whether the database was closed cleanly. Recovery then trusts the
+    /// slabs and does not read them, unless `Config::verify_slots_on_open`
+    /// is set.
+    pub clean_shutdown: bool,
+    /// The number of slab bytes read while verifying the recovered objects,
+    /// or 0 if they were not verified.
+    pub bytes_verified: u64,
+    /// The number of recovered objects that failed checksum verification
+    /// and were quarantined under `ChecksumMode::VerifyAndQuarantine`. An
+    /// object whose slot ends past the end of its slab file counts as
+    /// failing verification.
+    pub objects_quarantined: u64,
+    /// The number of slab file bytes after the last slot referenced by the
+    /// recovered metadata. They hold freed objects and the objects of a
+    /// flush that was interrupted before publishing them in the metadata,
+    /// possibly only partially written. They are never read, and later
+    /// writes reuse them.
+    pub unreferenced_trailing_bytes: u64,
+    /// Whether a metadata snapshot was found.
+    pub snapshot_recovered: bool,
+    /// The number of metadata logs replayed on top of the snapshot.
+    pub logs_replayed: u64,
+    /// The number of metadata batches, one per completed flush, replayed
+    /// from those logs.
+    pub batches_replayed: u64,
+    /// The number of logs that ended in a torn write. The flush that was
+    /// writing it had not completed, so it is rolled back.
+    pub torn_writes_truncated: u64,
+    /// The number of bytes discarded after the last intact batch of each log.
+    pub torn_bytes_truncated: u64,
+    /// The number of incomplete snapshot rewrites that were discarded.
+    pub incomplete_snapshots_removed: u64,
+    /// The metadata log sequence number that the database was recovered to.
+    pub recovered_to_lsn: u64,
+    /// How long recovery took.
+    pub duration: Duration,
+}
+
+impl RecoveryReport {
+    /// Returns `true` if recovery did not have to discard anything left
+    /// behind by an interrupted flush or snapshot.
+    pub fn is_clean(&self) -> bool {
+        self.torn_writes_truncated == 0 && self.incomplete_snapshots_removed == 0
+    }
+}
+
+/// The creation-time format of a database, returned by `Db::format_info`
+/// and by `melange_db::inspect`, which reads it without opening the
+/// database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatInfo {
+    /// The `LEAF_FANOUT` const generic the database was created with. It
+    /// must be used for every later open.
+    pub leaf_fanout: usize,
+    /// The version of the on-disk storage format.
+    pub format_version: u32,
+    /// The `Config::compression_algorithm` the database was created with,
+    /// or `None` for databases created before it was recorded.
+    pub compression_algorithm: Option<CompressionAlgorithm>,
+    /// The version of melange_db that created the database, or `None` for
+    /// databases created before it was recorded.
+    pub created_by_crate_version: Option<String>,
+}
+
+/// Reads the format of the database at `path` without opening it.
+pub(crate) fn inspect(path: &Path) -> io::Result<FormatInfo> {
+    match fs::read(path.join(SETTINGS_COOKIE)) {
+        Ok(bytes) => Ok(PersistentSettings::deserialize(&bytes)?.format_info()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no melange_db database found at {:?}", path),
+        )),
+        Err(e) => Err(e),
+    }
 }
 
 enum PersistentSettings {
-    V1 { leaf_fanout: u64 },
+    V1 {
+        leaf_fanout: u64,
+    },
+    V2 {
+        leaf_fanout: u64,
+        format_version: u32,
+        compression_algorithm: CompressionAlgorithm,
+        crate_version: String,
+    },
 }
 
 impl PersistentSettings {
+    fn current(leaf_fanout: usize, config: &Config) -> PersistentSettings {
+        PersistentSettings::V2 {
+            leaf_fanout: leaf_fanout as u64,
+            format_version: FORMAT_VERSION,
+            compression_algorithm: config.compression_algorithm,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
     // NB: should only be called with a directory lock already exclusively acquired
+    //
+    // Returns the settings the database was created with.
     fn verify_or_store<P: AsRef<Path>>(
-        &self,
+        self,
         path: P,
         _directory_lock: &std::fs::File,
-    ) -> io::Result<()> {
-        let settings_path = path.as_ref().join("durability_cookie");
+    ) -> io::Result<PersistentSettings> {
+        let settings_path = path.as_ref().join(SETTINGS_COOKIE);
 
         match std::fs::read(&settings_path) {
             Ok(previous_bytes) => {
                 let previous =
                     PersistentSettings::deserialize(&previous_bytes)?;
-                self.check_compatibility(&previous)
+                self.check_compatibility(&previous)?;
+                Ok(previous)
             }
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                std::fs::write(settings_path, self.serialize())
+                std::fs::write(settings_path, self.serialize())?;
+                Ok(self)
             }
             Err(e) => Err(e),
         }
     }
 
+    fn format_info(&self) -> FormatInfo {
+        match self {
+            PersistentSettings::V1 { leaf_fanout } => FormatInfo {
+                leaf_fanout: *leaf_fanout as usize,
+                format_version: 1,
+                compression_algorithm: None,
+                created_by_crate_version: None,
+            },
+            PersistentSettings::V2 {
+                leaf_fanout,
+                format_version,
+                compression_algorithm,
+                crate_version,
+            } => FormatInfo {
+                leaf_fanout: *leaf_fanout as usize,
+                format_version: *format_version,
+                compression_algorithm: Some(*compression_algorithm),
+                created_by_crate_version: Some(crate_version.clone()),
+            },
+        }
+    }
+
     fn deserialize(buf: &[u8]) -> io::Result<PersistentSettings> {
         let mut cursor = buf;
         let mut buf = [0_u8; 64];
@@ -266,11 +482,45 @@ impl PersistentSettings {
             ));
         }
 
+        let leaf_fanout = u64::from_le_bytes(buf[2..10].try_into().unwrap());
+
         match version {
-            1 => {
-                let leaf_fanout =
-                    u64::from_le_bytes(buf[2..10].try_into().unwrap());
-                Ok(PersistentSettings::V1 { leaf_fanout })
+            1 => Ok(PersistentSettings::V1 { leaf_fanout }),
+            2 => {
+                let format_version =
+                    u32::from_le_bytes(buf[10..14].try_into().unwrap());
+                let compression_algorithm = match buf[14] {
+                    0 => CompressionAlgorithm::None,
+                    1 => CompressionAlgorithm::Zstd,
+                    2 => CompressionAlgorithm::Lz4,
+                    other => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "encountered unknown compression algorithm {} in settings cookie",
+                                other
+                            ),
+                        ));
+                    }
+                };
+                let crate_version_len = buf[15] as usize;
+                let crate_version = buf
+                    .get(16..16 + crate_version_len)
+                    .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "encountered invalid crate version in settings cookie",
+                        )
+                    })?
+                    .to_string();
+
+                Ok(PersistentSettings::V2 {
+                    leaf_fanout,
+                    format_version,
+                    compression_algorithm,
+                    crate_version,
+                })
             }
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
@@ -281,28 +531,36 @@ impl PersistentSettings {
 
     fn check_compatibility(
         &self,
-        other: &PersistentSettings,
+        previous: &PersistentSettings,
     ) -> io::Result<()> {
-        use PersistentSettings::*;
-
-        match (self, other) {
-            (V1 { leaf_fanout: lf1 }, V1 { leaf_fanout: lf2 }) => {
-                if lf1 != lf2 {
-                    Err(io::Error::new(
-                        io::ErrorKind::Unsupported,
-                        format!(
-                            "melange_db was already opened with a LEAF_FANOUT const generic of {}, \
-                                and this may not be changed after initial creation. Please use \
-                                Db::import / Db::export to migrate, if you wish to change the \
-                                system's format.",
-                            lf2
-                        ),
-                    ))
-                } else {
-                    Ok(())
-                }
+        let opened = self.format_info();
+        let created = previous.format_info();
+
+        if created.leaf_fanout != opened.leaf_fanout {
+            return Err(LeafFanoutMismatch {
+                created_with: created.leaf_fanout,
+                opened_with: opened.leaf_fanout,
             }
+            .into());
         }
+
+        if created.format_version > opened.format_version {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "database uses storage format version {}{}, but this version of \
+                        melange_db only supports versions up to {}",
+                    created.format_version,
+                    created
+                        .created_by_crate_version
+                        .map(|v| format!(" (created by melange_db {})", v))
+                        .unwrap_or_default(),
+                    opened.format_version
+                ),
+            ));
+        }
+
+        Ok(())
     }
 
     fn serialize(&self) -> Vec<u8> {
@@ -318,10 +576,35 @@ impl PersistentSettings {
 
                 buf.extend_from_slice(&leaf_fanout.to_le_bytes());
             }
+            PersistentSettings::V2 {
+                leaf_fanout,
+                format_version,
+                compression_algorithm,
+                crate_version,
+            } => {
+                // LEAF_FANOUT: 8 bytes LE, format version: 4 bytes LE,
+                // compression algorithm: 1 byte, then the crate version
+                // prefixed by its 1 byte length
+                let version: [u8; 2] = 2_u16.to_le_bytes();
+                buf.extend_from_slice(&version);
+
+                buf.extend_from_slice(&leaf_fanout.to_le_bytes());
+                buf.extend_from_slice(&format_version.to_le_bytes());
+                buf.push(match compression_algorithm {
+                    CompressionAlgorithm::None => 0,
+                    CompressionAlgorithm::Zstd => 1,
+                    CompressionAlgorithm::Lz4 => 2,
+                });
+
+                let crate_version = &crate_version.as_bytes()
+                    [..crate_version.len().min(60 - buf.len() - 1)];
+                buf.push(crate_version.len() as u8);
+                buf.extend_from_slice(crate_version);
+            }
         }
 
         // zero-pad the buffer
-        assert!(buf.len() < 60);
+        assert!(buf.len() <= 60);
         buf.resize(60, 0);
 
         let hash: u32 = crc32fast::hash(&buf) ^ 0xAF;
@@ -335,8 +618,10 @@ impl PersistentSettings {
     }
 }
 
+/// The physical location of a stored object: a slot in one of the slab
+/// files. Exposed for diagnostics only, see `Tree::debug_entries`.
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub(crate) struct SlabAddress {
+pub struct SlabAddress {
     slab_id: u8,
     slab_slot: [u8; 7],
 }
@@ -353,11 +638,20 @@ impl SlabAddress {
         }
     }
 
+    /// The index of the slab file, which is named after it.
     #[inline]
     pub const fn slab(&self) -> u8 {
         self.slab_id
     }
 
+    /// The size of every slot in the slab file.
+    #[inline]
+    pub const fn slot_size(&self) -> usize {
+        SLAB_SIZES[self.slab_id as usize]
+    }
+
+    /// The index of the slot, at byte offset `slot * slot_size` of the
+    /// slab file.
     #[inline]
     pub const fn slot(&self) -> u64 {
         u64::from_be_bytes([
@@ -403,19 +697,43 @@ impl From<SlabAddress> for NonZeroU64 {
 #[cfg(unix)]
 mod sys_io {
     use std::io;
-    use std::os::unix::fs::FileExt;
+    use std::os::unix::fs::{FileExt, MetadataExt};
 
     use super::*;
 
+    /// Opens a slab file. `Config::windows_write_through` has no effect
+    /// here, so slab files are never unbuffered.
+    pub(super) fn open_slab(
+        path: &Path,
+        _slot_size: usize,
+        _write_through: bool,
+    ) -> io::Result<(fs::File, bool)> {
+        let mut options = fs::OpenOptions::new();
+        options.create(true).read(true).write(true);
+        Ok((options.open(path)?, false))
+    }
+
+    pub(super) fn sync(file: &fs::File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    /// Bytes of disk space allocated to the file, which is less than its
+    /// length when it has holes.
+    pub(super) fn allocated_len(file: &fs::File) -> io::Result<u64> {
+        Ok(file.metadata()?.blocks() * 512)
+    }
+
     pub(super) fn read_exact_at(
         file: &fs::File,
         buf: &mut [u8],
         offset: u64,
+        _unbuffered: bool,
     ) -> io::Result<()> {
         match maybe!(file.read_exact_at(buf, offset)) {
             Ok(r) => Ok(r),
+            // a torn slot at the end of the file, see `Slab::read_slot`
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(e),
             Err(e) => {
-                // FIXME BUG 3: failed to read 64 bytes at offset 192 from file with len 192
                 println!(
                     "failed to read {} bytes at offset {} from file with len {}",
                     buf.len(),
@@ -432,6 +750,7 @@ mod sys_io {
         file: &fs::File,
         buf: &[u8],
         offset: u64,
+        _unbuffered: bool,
     ) -> io::Result<()> {
         maybe!(file.write_all_at(buf, offset))
     }
@@ -439,11 +758,192 @@ mod sys_io {
 
 #[cfg(windows)]
 mod sys_io {
-    use std::os::windows::fs::FileExt;
+    use std::alloc::{Layout, alloc_zeroed, dealloc, handle_alloc_error};
+    use std::ffi::c_void;
+    use std::os::windows::fs::{FileExt, OpenOptionsExt};
+    use std::os::windows::io::AsRawHandle;
+    use std::ptr::{self, NonNull};
 
     use super::*;
 
+    const FILE_FLAG_WRITE_THROUGH: u32 = 0x8000_0000;
+    const FILE_FLAG_NO_BUFFERING: u32 = 0x2000_0000;
+    const FSCTL_SET_SPARSE: u32 = 0x0009_00c4;
+    const FILE_STANDARD_INFO_CLASS: i32 = 1;
+
+    /// Offsets, lengths and buffer addresses of unbuffered I/O must be
+    /// multiples of the volume sector size. 4096 covers both 512 byte and
+    /// 4K native sectors.
+    const SECTOR_ALIGNMENT: usize = 4096;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct FileStandardInfo {
+        allocation_size: i64,
+        end_of_file: i64,
+        number_of_links: u32,
+        delete_pending: u8,
+        directory: u8,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn DeviceIoControl(
+            device: *mut c_void,
+            control_code: u32,
+            in_buffer: *const c_void,
+            in_buffer_size: u32,
+            out_buffer: *mut c_void,
+            out_buffer_size: u32,
+            bytes_returned: *mut u32,
+            overlapped: *mut c_void,
+        ) -> i32;
+        fn FlushFileBuffers(file: *mut c_void) -> i32;
+        fn GetFileInformationByHandleEx(
+            file: *mut c_void,
+            class: i32,
+            info: *mut c_void,
+            info_size: u32,
+        ) -> i32;
+    }
+
+    /// Opens a slab file and marks it sparse, so that the ranges that
+    /// truncation and writes past the end of the file leave behind are not
+    /// allocated. With `write_through`, writes bypass the write cache, and
+    /// slab files whose slots are sector aligned also bypass the system
+    /// cache entirely, which is reported by the returned flag.
+    pub(super) fn open_slab(
+        path: &Path,
+        slot_size: usize,
+        write_through: bool,
+    ) -> io::Result<(fs::File, bool)> {
+        let unbuffered = write_through && slot_size.is_multiple_of(SECTOR_ALIGNMENT);
+
+        let mut options = fs::OpenOptions::new();
+        options.create(true).read(true).write(true);
+        if write_through {
+            let mut flags = FILE_FLAG_WRITE_THROUGH;
+            if unbuffered {
+                flags |= FILE_FLAG_NO_BUFFERING;
+            }
+            options.custom_flags(flags);
+        }
+        let file = options.open(path)?;
+
+        let mut bytes_returned = 0;
+        let res = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle(),
+                FSCTL_SET_SPARSE,
+                ptr::null(),
+                0,
+                ptr::null_mut(),
+                0,
+                &mut bytes_returned,
+                ptr::null_mut(),
+            )
+        };
+        if res == 0 {
+            // FAT volumes have no sparse files, which only costs space
+            warn_log!(
+                "failed to mark slab file {:?} as sparse: {:?}",
+                path,
+                io::Error::last_os_error()
+            );
+        }
+
+        Ok((file, unbuffered))
+    }
+
+    pub(super) fn sync(file: &fs::File) -> io::Result<()> {
+        if unsafe { FlushFileBuffers(file.as_raw_handle()) } == 0 {
+            return Err(annotate!(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// Bytes of disk space allocated to the file, which is less than its
+    /// length when it is sparse and has unwritten ranges.
+    pub(super) fn allocated_len(file: &fs::File) -> io::Result<u64> {
+        let mut info = FileStandardInfo::default();
+        let res = unsafe {
+            GetFileInformationByHandleEx(
+                file.as_raw_handle(),
+                FILE_STANDARD_INFO_CLASS,
+                (&mut info as *mut FileStandardInfo).cast(),
+                std::mem::size_of::<FileStandardInfo>() as u32,
+            )
+        };
+        if res == 0 {
+            return Err(annotate!(io::Error::last_os_error()));
+        }
+        Ok(u64::try_from(info.allocation_size).unwrap_or(0))
+    }
+
+    /// A zeroed, sector aligned buffer for unbuffered I/O.
+    struct AlignedBuffer {
+        ptr: NonNull<u8>,
+        layout: Layout,
+    }
+
+    impl AlignedBuffer {
+        fn zeroed(len: usize) -> AlignedBuffer {
+            assert!(len > 0 && len.is_multiple_of(SECTOR_ALIGNMENT));
+            let layout = Layout::from_size_align(len, SECTOR_ALIGNMENT).unwrap();
+            let ptr = NonNull::new(unsafe { alloc_zeroed(layout) })
+                .unwrap_or_else(|| handle_alloc_error(layout));
+            AlignedBuffer { ptr, layout }
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+        }
+
+        fn as_mut_slice(&mut self) -> &mut [u8] {
+            unsafe {
+                std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size())
+            }
+        }
+    }
+
+    impl Drop for AlignedBuffer {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+        }
+    }
+
     pub(super) fn read_exact_at(
+        file: &fs::File,
+        buf: &mut [u8],
+        offset: u64,
+        unbuffered: bool,
+    ) -> io::Result<()> {
+        if unbuffered {
+            let mut aligned = AlignedBuffer::zeroed(buf.len());
+            read_exact_at_inner(file, aligned.as_mut_slice(), offset)?;
+            buf.copy_from_slice(aligned.as_slice());
+            Ok(())
+        } else {
+            read_exact_at_inner(file, buf, offset)
+        }
+    }
+
+    pub(super) fn write_all_at(
+        file: &fs::File,
+        buf: &[u8],
+        offset: u64,
+        unbuffered: bool,
+    ) -> io::Result<()> {
+        if unbuffered {
+            let mut aligned = AlignedBuffer::zeroed(buf.len());
+            aligned.as_mut_slice().copy_from_slice(buf);
+            write_all_at_inner(file, aligned.as_slice(), offset)
+        } else {
+            write_all_at_inner(file, buf, offset)
+        }
+    }
+
+    fn read_exact_at_inner(
         file: &fs::File,
         mut buf: &mut [u8],
         mut offset: u64,
@@ -470,7 +970,7 @@ mod sys_io {
         }
     }
 
-    pub(super) fn write_all_at(
+    fn write_all_at_inner(
         file: &fs::File,
         mut buf: &[u8],
         mut offset: u64,
@@ -495,106 +995,172 @@ mod sys_io {
     }
 }
 
+enum SlotRead {
+    Valid(Vec<u8>),
+    ChecksumMismatch,
+}
+
 #[derive(Debug)]
 struct Slab {
     file: fs::File,
     slot_size: usize,
-    max_live_slot_since_last_truncation: AtomicU64,
+    max_live_slot_since_last_truncation: PortableAtomicU64,
+    /// Whether slots end with a checksum kind byte, see `Slab::write`
+    tagged: bool,
+    /// Whether the file bypasses the system cache, which requires sector
+    /// aligned buffers, see `Config::windows_write_through`
+    unbuffered: bool,
 }
 
 impl Slab {
     fn sync(&self) -> io::Result<()> {
-        self.file.sync_all()
+        sys_io::sync(&self.file)
     }
 
     fn read(
         &self,
         slot: u64,
+        verify: bool,
         _guard: &mut Guard<'_, DeferredFree, 16, 16>,
-    ) -> io::Result<Vec<u8>> {
+    ) -> io::Result<SlotRead> {
+        self.read_slot(slot, verify)
+    }
+
+    fn read_slot(&self, slot: u64, verify: bool) -> io::Result<SlotRead> {
         trace_log!("reading from slot {} in slab {}", slot, self.slot_size);
 
         let mut data = vec![0u8; self.slot_size];
 
         let whence = self.slot_size as u64 * slot;
 
-        maybe!(sys_io::read_exact_at(&self.file, &mut data, whence))?;
+        let read_res = maybe!(sys_io::read_exact_at(
+            &self.file,
+            &mut data,
+            whence,
+            self.unbuffered,
+        ));
+        match read_res {
+            Ok(()) => {}
+            // the write of a slot at the end of the file was interrupted
+            // after extending the file only partially
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(SlotRead::ChecksumMismatch);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let (kind, tag) = if self.tagged {
+            let tag = data[self.slot_size - 1];
+            match checksum_kind_for_tag(tag) {
+                Some(kind) => (kind, Some(tag)),
+                // the kind byte itself is damaged
+                None => return Ok(SlotRead::ChecksumMismatch),
+            }
+        } else {
+            (ChecksumKind::Crc32, None)
+        };
+        let body_end = self.body_end(kind);
 
-        let hash_actual: [u8; 4] =
-            (crc32fast::hash(&data[..self.slot_size - 4]) ^ 0xAF).to_le_bytes();
-        let hash_expected = &data[self.slot_size - 4..];
+        if verify {
+            let hash_len = checksum_len(kind);
+            let hash_actual = slot_checksum(kind, &data[..body_end], tag);
+            let hash_expected = &data[body_end..body_end + hash_len];
 
-        if hash_expected != hash_actual {
-            return Err(annotate!(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "crc mismatch - data corruption detected"
-            )));
+            if hash_expected != &hash_actual[..hash_len] {
+                return Ok(SlotRead::ChecksumMismatch);
+            }
         }
 
         let len: usize = if self.slot_size <= u8::MAX as usize {
-            // crc32 + 1 byte frame
-            usize::from(data[self.slot_size - 5])
+            // 1 byte frame
+            usize::from(data[body_end - 1])
         } else if self.slot_size <= u16::MAX as usize {
-            // crc32 + 2 byte frame
+            // 2 byte frame
             let mut size_bytes: [u8; 2] = [0; 2];
-            size_bytes
-                .copy_from_slice(&data[self.slot_size - 6..self.slot_size - 4]);
+            size_bytes.copy_from_slice(&data[body_end - 2..body_end]);
             usize::from(u16::from_le_bytes(size_bytes))
         } else if self.slot_size <= u32::MAX as usize {
-            // crc32 + 4 byte frame
+            // 4 byte frame
             let mut size_bytes: [u8; 4] = [0; 4];
-            size_bytes
-                .copy_from_slice(&data[self.slot_size - 8..self.slot_size - 4]);
+            size_bytes.copy_from_slice(&data[body_end - 4..body_end]);
             usize::try_from(u32::from_le_bytes(size_bytes)).unwrap()
         } else {
-            // crc32 + 8 byte frame
+            // 8 byte frame
             let mut size_bytes: [u8; 8] = [0; 8];
-            size_bytes.copy_from_slice(
-                &data[self.slot_size - 12..self.slot_size - 4],
-            );
+            size_bytes.copy_from_slice(&data[body_end - 8..body_end]);
             usize::try_from(u64::from_le_bytes(size_bytes)).unwrap()
         };
 
         data.truncate(len);
 
-        Ok(data)
+        Ok(SlotRead::Valid(data))
     }
 
-    fn write(&self, slot: u64, mut data: Vec<u8>) -> io::Result<()> {
-        let len = data.len();
+    /// The end of the length frame, where the checksum trailer starts.
+    fn body_end(&self, kind: ChecksumKind) -> usize {
+        if self.tagged {
+            self.slot_size - checksum_len(kind) - 1
+        } else {
+            self.slot_size - 4
+        }
+    }
 
-        assert!(len + overhead_for_size(data.len()) <= self.slot_size);
+    /// Writes `object` to `slot`. A slot holds the data, zero padding, the
+    /// length of the data and a checksum trailer. In tagged slots the
+    /// trailer is the checksum of the chosen kind followed by the kind byte,
+    /// otherwise it is always a 4 byte CRC32. A full filesystem releases the
+    /// headroom held by `space` and the write is retried once.
+    fn write(
+        &self,
+        slot: u64,
+        object: &[u8],
+        checksum: ChecksumKind,
+        space: &DiskSpace,
+    ) -> io::Result<()> {
+        let len = object.len();
+
+        assert!(len + overhead_for_size(len) <= self.slot_size);
+        assert!(self.tagged || checksum == ChecksumKind::Crc32);
 
+        // framed in a copy, so that the objects of a failed batch can be
+        // written again by the flush that retries it
+        let mut data = Vec::with_capacity(self.slot_size);
+        data.extend_from_slice(object);
         data.resize(self.slot_size, 0);
 
+        let body_end = self.body_end(checksum);
+
         if self.slot_size <= u8::MAX as usize {
-            // crc32 + 1 byte frame
-            data[self.slot_size - 5] = u8::try_from(len).unwrap();
+            // 1 byte frame
+            data[body_end - 1] = u8::try_from(len).unwrap();
         } else if self.slot_size <= u16::MAX as usize {
-            // crc32 + 2 byte frame
+            // 2 byte frame
             let size_bytes: [u8; 2] = u16::try_from(len).unwrap().to_le_bytes();
-            data[self.slot_size - 6..self.slot_size - 4]
-                .copy_from_slice(&size_bytes);
+            data[body_end - 2..body_end].copy_from_slice(&size_bytes);
         } else if self.slot_size <= u32::MAX as usize {
-            // crc32 + 4 byte frame
+            // 4 byte frame
             let size_bytes: [u8; 4] = u32::try_from(len).unwrap().to_le_bytes();
-            data[self.slot_size - 8..self.slot_size - 4]
-                .copy_from_slice(&size_bytes);
+            data[body_end - 4..body_end].copy_from_slice(&size_bytes);
         } else {
-            // crc32 + 8 byte frame
+            // 8 byte frame
             let size_bytes: [u8; 8] = u64::try_from(len).unwrap().to_le_bytes();
-            data[self.slot_size - 12..self.slot_size - 4]
-                .copy_from_slice(&size_bytes);
+            data[body_end - 8..body_end].copy_from_slice(&size_bytes);
         }
 
-        let hash: [u8; 4] =
-            (crc32fast::hash(&data[..self.slot_size - 4]) ^ 0xAF).to_le_bytes();
-        data[self.slot_size - 4..].copy_from_slice(&hash);
+        let tag = self.tagged.then_some(checksum_tag(checksum));
+        let hash_len = checksum_len(checksum);
+        let hash = slot_checksum(checksum, &data[..body_end], tag);
+        data[body_end..body_end + hash_len].copy_from_slice(&hash[..hash_len]);
+        if let Some(tag) = tag {
+            data[self.slot_size - 1] = tag;
+        }
 
         let whence = self.slot_size as u64 * slot;
 
         trace_log!("writing to slot {} in slab {}", slot, self.slot_size);
-        sys_io::write_all_at(&self.file, &data, whence)
+        space.retry_when_full(|| {
+            sys_io::write_all_at(&self.file, &data, whence, self.unbuffered)
+        })
     }
 }
 
@@ -636,6 +1202,14 @@ pub enum Update {
         object_id: ObjectId,
         collection_id: CollectionId,
     },
+    /// Records the location that `Heap::share` mapped the object to,
+    /// without writing it.
+    Share {
+        object_id: ObjectId,
+        collection_id: CollectionId,
+        low_key: InlineArray,
+        location: NonZeroU64,
+    },
 }
 
 impl Update {
@@ -643,7 +1217,8 @@ impl Update {
     pub(crate) fn object_id(&self) -> ObjectId {
         match self {
             Update::Store { object_id, .. }
-            | Update::Free { object_id, .. } => *object_id,
+            | Update::Free { object_id, .. }
+            | Update::Share { object_id, .. } => *object_id,
         }
     }
 }
@@ -671,6 +1246,25 @@ impl UpdateMetadata {
     }
 }
 
+/// The locations that one write batch replaced. While the batch is
+/// retained its vacated slots stay allocated, so the state before it can
+/// still be read.
+struct RetainedBatch {
+    epoch: FlushEpoch,
+    // where each object of the batch was stored before it, `None` if the
+    // batch stored it for the first time
+    previous: Vec<(ObjectId, Option<(CollectionId, SlabAddress)>)>,
+    vacated: Vec<DeferredFree>,
+}
+
+/// The most recent write batches, see `Config::retained_flush_epochs`.
+struct EpochHistory {
+    batches: VecDeque<RetainedBatch>,
+    // the oldest epoch whose state can be reconstructed, which is the
+    // epoch of the last batch that was no longer retained
+    horizon: FlushEpoch,
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 struct WriteBatchStatTracker {
     sum: WriteBatchStats,
@@ -683,12 +1277,29 @@ pub struct Heap {
     slabs: Arc<[Slab; N_SLABS]>,
     table: ObjectLocationMapper,
     metadata_store: Arc<Mutex<MetadataStore>>,
+    metadata_maintenance: MetadataMaintenance,
+    metadata_auto_compact_ratio: Option<f64>,
     free_ebr: Ebr<DeferredFree, 16, 16>,
     global_error: Arc<AtomicPtr<(io::ErrorKind, String)>>,
     #[allow(unused)]
     directory_lock: Arc<fs::File>,
     stats: Arc<RwLock<WriteBatchStatTracker>>,
-    truncated_file_bytes: Arc<AtomicU64>,
+    truncated_file_bytes: Arc<PortableAtomicU64>,
+    checksum_mode: ChecksumMode,
+    checksum: ChecksumKind,
+    corruption_policy: CorruptionPolicy,
+    corruption_events: Arc<PortableAtomicU64>,
+    last_corruption: Arc<Mutex<Option<CorruptionError>>>,
+    format_info: Arc<FormatInfo>,
+    // nanoseconds added to every object read, see
+    // `Db::set_read_latency_for_testing`
+    #[cfg(feature = "for-internal-testing-only")]
+    read_latency_for_testing: Arc<PortableAtomicU64>,
+    // held while the table is updated after a batch, so that the history
+    // is always consistent with the table
+    epoch_history: Arc<Mutex<EpochHistory>>,
+    retained_flush_epochs: usize,
+    disk_space: Arc<DiskSpace>,
 }
 
 impl fmt::Debug for Heap {
@@ -700,11 +1311,277 @@ impl fmt::Debug for Heap {
     }
 }
 
+/// (slot, object id, collection id) of a recovered object
+type RecoveredSlot = (u64, ObjectId, CollectionId);
+
+/// Reads every slot referenced by the recovered metadata and verifies its
+/// checksum, so that corruption left behind by an unclean shutdown is
+/// detected while opening rather than on first access. Slabs are independent
+/// files, so the slots are split into chunks that `Config::recovery_threads`
+/// workers pull from a shared counter. Progress is reported through
+/// `Config::recovery_progress_callback` after every chunk.
+///
+/// Only called after an unclean shutdown or when
+/// `Config::verify_slots_on_open` is set.
+///
+/// With `ChecksumMode::Off` the slots are still read to learn their sizes,
+/// but checksums are not compared. With `ChecksumMode::Verify` the first
+/// mismatch fails recovery, and with `ChecksumMode::VerifyAndQuarantine`
+/// every mismatching object is returned so that it can be quarantined.
+///
+/// Returns the size of the object stored at each verified location, which
+/// seeds the per-collection disk usage accounting.
+fn verify_recovered_slots(
+    slabs: &[Slab],
+    recovered_metadata: &[UpdateMetadata],
+    config: &Config,
+) -> io::Result<(FnvHashMap<u64, u64>, Vec<CorruptionError>)> {
+    const SLOTS_PER_CHUNK: usize = 256;
+
+    let verify = config.checksum_mode != ChecksumMode::Off;
+
+    let mut slots_per_slab: Vec<Vec<RecoveredSlot>> = vec![vec![]; N_SLABS];
+    for update_metadata in recovered_metadata {
+        if let UpdateMetadata::Store {
+            location, object_id, collection_id, ..
+        } = update_metadata
+        {
+            let slab_address = SlabAddress::from(*location);
+            slots_per_slab[usize::from(slab_address.slab())].push((
+                slab_address.slot(),
+                *object_id,
+                *collection_id,
+            ));
+        }
+    }
+
+    let mut chunks: Vec<(usize, &[RecoveredSlot])> = vec![];
+    for slots in &mut slots_per_slab {
+        slots.sort_unstable();
+    }
+    for (slab_id, slots) in slots_per_slab.iter().enumerate() {
+        for chunk in slots.chunks(SLOTS_PER_CHUNK) {
+            chunks.push((slab_id, chunk));
+        }
+    }
+
+    let objects_total = recovered_metadata.len() as u64;
+    let objects_scanned = PortableAtomicU64::new(0);
+    let bytes_processed = PortableAtomicU64::new(0);
+    let next_chunk = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let sizes: Mutex<FnvHashMap<u64, u64>> = Mutex::default();
+    let corrupted: Mutex<Vec<CorruptionError>> = Mutex::default();
+
+    // progress is read and reported under a mutex so that consecutive
+    // callback invocations never observe decreasing counters
+    let report_mu = Mutex::new(());
+    let report = || {
+        if let Some(callback) = &config.recovery_progress_callback {
+            let _report_guard = report_mu.lock();
+            (callback.0)(RecoveryProgress {
+                phase: RecoveryPhase::Verifying,
+                objects_scanned: objects_scanned.load(Ordering::Acquire),
+                objects_total,
+                bytes_processed: bytes_processed.load(Ordering::Acquire),
+            });
+        }
+    };
+
+    let worker = || -> io::Result<()> {
+        while !failed.load(Ordering::Acquire) {
+            let chunk_index = next_chunk.fetch_add(1, Ordering::AcqRel);
+            let Some((slab_id, slots)) = chunks.get(chunk_index) else {
+                return Ok(());
+            };
+            let slab = &slabs[*slab_id];
+            let mut chunk_sizes = Vec::with_capacity(slots.len());
+
+            for (slot, object_id, collection_id) in *slots {
+                let slab_address = SlabAddress::from_slab_slot(
+                    u8::try_from(*slab_id).unwrap(),
+                    *slot,
+                );
+                match slab.read_slot(*slot, verify) {
+                    Ok(SlotRead::Valid(data)) => {
+                        let location: NonZeroU64 = slab_address.into();
+                        chunk_sizes.push((location.get(), data.len() as u64));
+                    }
+                    Ok(SlotRead::ChecksumMismatch) => {
+                        let error = corruption_error(
+                            *object_id,
+                            *collection_id,
+                            slab_address,
+                        );
+                        if config.checksum_mode
+                            != ChecksumMode::VerifyAndQuarantine
+                        {
+                            failed.store(true, Ordering::Release);
+                            return Err(io::Error::from(error));
+                        }
+                        warn_log!("quarantining corrupted object during recovery: {}", error);
+                        corrupted.lock().push(error);
+                    }
+                    Err(e) => {
+                        failed.store(true, Ordering::Release);
+                        return Err(annotate!(io::Error::new(
+                            e.kind(),
+                            format!(
+                                "recovery verification of slot {} in the {} byte slab failed: {}",
+                                slot, slab.slot_size, e
+                            )
+                        )));
+                    }
+                }
+            }
+            sizes.lock().extend(chunk_sizes);
+
+            objects_scanned.fetch_add(slots.len() as u64, Ordering::AcqRel);
+            bytes_processed.fetch_add(
+                (slots.len() * slab.slot_size) as u64,
+                Ordering::AcqRel,
+            );
+            report();
+        }
+
+        Ok(())
+    };
+
+    report();
+
+    if chunks.is_empty() {
+        return Ok((FnvHashMap::default(), vec![]));
+    }
+
+    let n_threads = config.recovery_threads.clamp(1, chunks.len());
+    let before = Instant::now();
+
+    std::thread::scope(|scope| {
+        let mut handles = vec![];
+        for _ in 1..n_threads {
+            let spawn_res = std::thread::Builder::new()
+                .name("melange-recover".into())
+                .spawn_scoped(scope, worker);
+
+            match spawn_res {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    warn_log!(
+                        "unable to spawn recovery thread, continuing with {} threads: {:?}",
+                        handles.len() + 1,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+
+        // the current thread participates in the scan as well
+        let mut ret = worker();
+
+        for handle in handles {
+            let thread_ret = handle.join().expect("recovery thread panicked");
+            if ret.is_ok() {
+                ret = thread_ret;
+            }
+        }
+
+        ret
+    })?;
+
+    debug_log!(
+        "verified {} recovered objects in {} slabs using {} threads in {:?}",
+        objects_total,
+        slots_per_slab.iter().filter(|s| !s.is_empty()).count(),
+        n_threads,
+        before.elapsed()
+    );
+
+    Ok((sizes.into_inner(), corrupted.into_inner()))
+}
+
+/// Sums the bytes of each slab file after its last slot that the recovered
+/// metadata references.
+fn unreferenced_trailing_bytes(
+    slabs: &[Slab],
+    recovered_metadata: &[UpdateMetadata],
+) -> io::Result<u64> {
+    let mut referenced_len = [0_u64; N_SLABS];
+    for update_metadata in recovered_metadata {
+        if let UpdateMetadata::Store { location, .. } = update_metadata {
+            let slab_address = SlabAddress::from(*location);
+            let slab_id = usize::from(slab_address.slab());
+            let end = (slab_address.slot() + 1) * slabs[slab_id].slot_size as u64;
+            referenced_len[slab_id] = referenced_len[slab_id].max(end);
+        }
+    }
+
+    let mut trailing = 0;
+    for (slab, referenced_len) in slabs.iter().zip(referenced_len) {
+        let file_len = fallible!(slab.file.metadata()).len();
+        trailing += file_len.saturating_sub(referenced_len);
+    }
+    Ok(trailing)
+}
+
+fn corruption_error(
+    object_id: ObjectId,
+    collection_id: CollectionId,
+    slab_address: SlabAddress,
+) -> CorruptionError {
+    CorruptionError {
+        collection_id: collection_id.0,
+        object_id: *object_id,
+        slab_id: slab_address.slab(),
+        slot_size: slab_address.slot_size(),
+        slot: slab_address.slot(),
+    }
+}
+
+/// Reads and removes the `CLEAN_SHUTDOWN` marker, returning the object sizes
+/// it recorded, or `None` after an unclean shutdown. The marker is removed
+/// durably before returning, so a crash while the database is open is
+/// detected by the next recovery.
+fn take_clean_shutdown_sizes(
+    path: &Path,
+) -> io::Result<Option<FnvHashMap<u64, u64>>> {
+    let marker_path = path.join(CLEAN_SHUTDOWN);
+    let bytes = match fs::read(&marker_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(annotate!(e)),
+    };
+    fallible!(fs::remove_file(&marker_path));
+    maybe!(crate::platform_utils::sync_directory(path))?;
+
+    // (location, size) pairs followed by a crc32 of them
+    let Some(entries_len) = bytes.len().checked_sub(4) else {
+        warn_log!("ignoring truncated clean shutdown marker at {:?}", path);
+        return Ok(None);
+    };
+    let (entries, crc) = bytes.split_at(entries_len);
+    if entries.len() % 16 != 0 || crc32fast::hash(entries).to_le_bytes() != crc {
+        warn_log!("ignoring corrupt clean shutdown marker at {:?}", path);
+        return Ok(None);
+    }
+
+    let sizes = entries
+        .chunks_exact(16)
+        .map(|entry| {
+            let location = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let size = u64::from_le_bytes(entry[8..].try_into().unwrap());
+            (location, size)
+        })
+        .collect();
+    Ok(Some(sizes))
+}
+
 impl Heap {
     pub fn recover(
         leaf_fanout: usize,
         config: &Config,
     ) -> io::Result<HeapRecovery> {
+        let before_recovery = Instant::now();
         let path = &config.path;
         trace_log!("recovering Heap at {:?}", path);
         let slabs_dir = path.join("slabs");
@@ -747,7 +1624,12 @@ impl Heap {
 
         let directory_lock = fallible!(file_lock_opts.open(&lock_file_path));
 
-        fallible!(directory_lock.try_lock_exclusive());
+        if let Err(e) = directory_lock.try_lock_exclusive() {
+            if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                return Err(DatabaseLocked { path: path.to_path_buf() }.into());
+            }
+            return Err(annotate!(e));
+        }
 
         // 在Windows上，我们只同步锁文件，而不是目录
         #[cfg(unix)]
@@ -762,30 +1644,93 @@ impl Heap {
             maybe!(directory_lock.sync_all())?;
         }
 
-        let persistent_settings =
-            PersistentSettings::V1 { leaf_fanout: leaf_fanout as u64 };
+        let format_info = PersistentSettings::current(leaf_fanout, config)
+            .verify_or_store(path, &directory_lock)?
+            .format_info();
+
+        let disk_space =
+            maybe!(DiskSpace::open(path, config.disk_full_headroom_bytes))?;
 
-        persistent_settings.verify_or_store(path, &directory_lock)?;
+        let clean_shutdown_sizes = take_clean_shutdown_sizes(path)?;
+        let clean_shutdown = clean_shutdown_sizes.is_some();
+
+        let (metadata_store, recovered_metadata, metadata_stats) =
+            MetadataStore::recover_with_stats(path.join("metadata"))?;
+
+        let tagged = format_info.format_version >= TAGGED_SLOTS_FORMAT_VERSION;
+        let checksum = if tagged || config.checksum == ChecksumKind::Crc32 {
+            config.checksum
+        } else {
+            warn_log!(
+                "database at {:?} uses storage format {}, which only supports CRC32 checksums; ignoring Config::checksum {:?}",
+                path,
+                format_info.format_version,
+                config.checksum
+            );
+            ChecksumKind::Crc32
+        };
 
-        let (metadata_store, recovered_metadata) =
-            MetadataStore::recover(path.join("metadata"))?;
+        let mut slabs = vec![];
+        for slot_size in &SLAB_SIZES {
+            let slab_path = slabs_dir.join(format!("{}", slot_size));
+
+            let (file, unbuffered) = fallible!(sys_io::open_slab(
+                &slab_path,
+                *slot_size,
+                config.windows_write_through
+            ));
+
+            slabs.push(Slab {
+                slot_size: *slot_size,
+                file,
+                max_live_slot_since_last_truncation: PortableAtomicU64::new(0),
+                tagged,
+                unbuffered,
+            })
+        }
+
+        // after a clean shutdown every slot was fully written and synced
+        // before it was referenced, so reading them all again only finds
+        // corruption that happened while the database was closed
+        let verify_slots = config.verify_slots_on_open || !clean_shutdown;
+        let (recovered_sizes, corrupted) = if verify_slots {
+            verify_recovered_slots(&slabs, &recovered_metadata, config)?
+        } else {
+            (clean_shutdown_sizes.unwrap_or_default(), vec![])
+        };
+        let unreferenced_trailing_bytes =
+            unreferenced_trailing_bytes(&slabs, &recovered_metadata)?;
 
         let table = ObjectLocationMapper::new(
             &recovered_metadata,
+            &recovered_sizes,
             config.target_heap_file_fill_ratio,
         );
 
+        let objects_quarantined = corrupted.len() as u64;
+        let last_corruption = corrupted.last().copied();
+        for error in corrupted {
+            table.quarantine(error);
+        }
+
         let mut recovered_nodes =
             Vec::<ObjectRecovery>::with_capacity(recovered_metadata.len());
+        let mut recovered_collections = FnvHashSet::default();
+        let mut bytes_verified = 0;
 
         for update_metadata in recovered_metadata {
             match update_metadata {
                 UpdateMetadata::Store {
                     object_id,
                     collection_id,
-                    location: _,
+                    location,
                     low_key,
                 } => {
+                    recovered_collections.insert(collection_id);
+                    if verify_slots {
+                        bytes_verified +=
+                            SlabAddress::from(location).slot_size() as u64;
+                    }
                     recovered_nodes.push(ObjectRecovery {
                         object_id,
                         collection_id,
@@ -798,40 +1743,60 @@ impl Heap {
             }
         }
 
-        let mut slabs = vec![];
-        let mut slab_opts = fs::OpenOptions::new();
-        slab_opts.create(true).read(true).write(true);
-        for slot_size in &SLAB_SIZES {
-            let slab_path = slabs_dir.join(format!("{}", slot_size));
-
-            let file = fallible!(slab_opts.open(slab_path));
-
-            slabs.push(Slab {
-                slot_size: *slot_size,
-                file,
-                max_live_slot_since_last_truncation: AtomicU64::new(0),
-            })
-        }
-
         // 跨平台的目录同步处理
         maybe!(crate::platform_utils::sync_directory(&slabs_dir))?;
 
         debug_log!("recovery of Heap at {:?} complete", path);
 
+        let report = RecoveryReport {
+            objects_recovered: recovered_nodes.len() as u64,
+            collections_recovered: recovered_collections.len() as u64,
+            clean_shutdown,
+            bytes_verified,
+            objects_quarantined,
+            unreferenced_trailing_bytes,
+            snapshot_recovered: metadata_stats.snapshot_recovered,
+            logs_replayed: metadata_stats.logs_replayed,
+            batches_replayed: metadata_stats.batches_replayed,
+            torn_writes_truncated: metadata_stats.torn_writes_truncated,
+            torn_bytes_truncated: metadata_stats.torn_bytes_truncated,
+            incomplete_snapshots_removed: metadata_stats
+                .incomplete_snapshots_removed,
+            recovered_to_lsn: metadata_stats.recovered_to_lsn,
+            duration: before_recovery.elapsed(),
+        };
+
         Ok(HeapRecovery {
             heap: Heap {
                 slabs: Arc::new(slabs.try_into().unwrap()),
                 path: path.into(),
                 table,
                 global_error: metadata_store.get_global_error_arc(),
+                metadata_maintenance: metadata_store.maintenance(),
+                metadata_auto_compact_ratio: config.metadata_auto_compact_ratio,
                 metadata_store: Arc::new(Mutex::new(metadata_store)),
                 directory_lock: Arc::new(directory_lock),
                 free_ebr: Ebr::default(),
                 truncated_file_bytes: Arc::default(),
                 stats: Arc::default(),
+                checksum_mode: config.checksum_mode,
+                checksum,
+                corruption_policy: config.on_corruption.clone(),
+                corruption_events: Arc::new(PortableAtomicU64::new(objects_quarantined)),
+                last_corruption: Arc::new(Mutex::new(last_corruption)),
+                format_info: Arc::new(format_info),
+                #[cfg(feature = "for-internal-testing-only")]
+                read_latency_for_testing: Arc::default(),
+                epoch_history: Arc::new(Mutex::new(EpochHistory {
+                    batches: VecDeque::new(),
+                    horizon: FlushEpoch::MIN,
+                })),
+                retained_flush_epochs: config.retained_flush_epochs,
+                disk_space: Arc::new(disk_space),
             },
             recovered_nodes,
             was_recovered,
+            report,
         })
     }
 
@@ -841,6 +1806,32 @@ impl Heap {
         self.global_error.clone()
     }
 
+    /// Records the stored size of every object in the `CLEAN_SHUTDOWN`
+    /// marker, so that the next recovery does not have to read the slabs.
+    /// Called after the final flush of a shutdown. Nothing may be written
+    /// to the heap afterwards, because the marker claims that every object
+    /// referenced by the metadata is intact.
+    pub(crate) fn mark_clean_shutdown(&self) -> io::Result<()> {
+        self.check_error()?;
+
+        let sizes = self.table.stored_sizes();
+        let mut bytes = Vec::with_capacity(sizes.len() * 16 + 4);
+        for (location, size) in sizes {
+            bytes.extend_from_slice(&location.to_le_bytes());
+            bytes.extend_from_slice(&size.to_le_bytes());
+        }
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        let tmp_path = self.path.join(format!("{}.tmp", CLEAN_SHUTDOWN));
+        let mut file = fallible!(fs::File::create(&tmp_path));
+        fallible!(io::Write::write_all(&mut file, &bytes));
+        fallible!(file.sync_all());
+        drop(file);
+        fallible!(fs::rename(&tmp_path, self.path.join(CLEAN_SHUTDOWN)));
+        maybe!(crate::platform_utils::sync_directory(&self.path))
+    }
+
     fn check_error(&self) -> io::Result<()> {
         let err_ptr: *const (io::ErrorKind, String) =
             self.global_error.load(Ordering::Acquire);
@@ -872,21 +1863,138 @@ impl Heap {
             allocator: self.table.stats(),
             write_batch_max: stats.max,
             write_batch_sum: stats.sum,
+            corruption_events: self.corruption_events.load(Ordering::Acquire),
+            last_corruption: *self.last_corruption.lock(),
         }
     }
 
+    /// Returns the format the database was created with.
+    pub fn format_info(&self) -> FormatInfo {
+        (*self.format_info).clone()
+    }
+
+    /// The headroom and read-only state of the database directory.
+    pub(crate) fn disk_space(&self) -> &DiskSpace {
+        &self.disk_space
+    }
+
+    /// Returns the objects that are currently quarantined because their
+    /// stored copy failed checksum verification.
+    pub fn quarantined_objects(&self) -> Vec<CorruptionError> {
+        self.table.quarantined_objects()
+    }
+
+    /// Makes every following object read take at least `latency` longer,
+    /// to simulate slow storage in tests.
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn set_read_latency_for_testing(&self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.read_latency_for_testing.store(nanos, Ordering::Relaxed);
+    }
+
+    /// The slot that `object_id` is currently stored in, if any.
+    pub(crate) fn location(&self, object_id: ObjectId) -> Option<SlabAddress> {
+        self.table.get_location_for_object(object_id)
+    }
+
+    /// Reads the slot at `address` directly, without going through the
+    /// object table, for diagnostics. The slot may have been freed or
+    /// reused since `address` was obtained. A slot that fails checksum
+    /// verification returns an error of kind `io::ErrorKind::InvalidData`
+    /// and is not quarantined.
+    pub fn read_slab_address(&self, address: SlabAddress) -> io::Result<Vec<u8>> {
+        self.check_error()?;
+
+        let Some(slab) = self.slabs.get(usize::from(address.slab_id)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} does not name a slab", address),
+            ));
+        };
+
+        let mut guard = self.free_ebr.pin();
+        let verify = self.checksum_mode != ChecksumMode::Off;
+        match slab.read(address.slot(), verify, &mut guard)? {
+            SlotRead::Valid(bytes) => Ok(bytes),
+            SlotRead::ChecksumMismatch => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the slot at {:?} failed checksum verification", address),
+            )),
+        }
+    }
+
+    /// Handles a checksum mismatch found while reading an object according
+    /// to `Config::on_corruption`, before the error is returned.
+    fn apply_corruption_policy(&self, error: &CorruptionError) {
+        match &self.corruption_policy {
+            CorruptionPolicy::Error => {}
+            CorruptionPolicy::Panic => panic!("{}", error),
+            CorruptionPolicy::Callback(callback) => callback(error),
+        }
+    }
+
+    /// Flips a byte in the slot at `address`, so that reading the object
+    /// stored there fails checksum verification. See
+    /// `Db::corrupt_slot_for_testing`.
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn corrupt_slot_for_testing(&self, address: SlabAddress) -> io::Result<()> {
+        let Some(slab) = self.slabs.get(usize::from(address.slab_id)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} does not name a slab", address),
+            ));
+        };
+
+        let whence = slab.slot_size as u64 * address.slot();
+        let mut data = vec![0_u8; slab.slot_size];
+        sys_io::read_exact_at(&slab.file, &mut data, whence, slab.unbuffered)?;
+        data[0] ^= 0xFF;
+        sys_io::write_all_at(&slab.file, &data, whence, slab.unbuffered)
+    }
+
     pub fn read(&self, object_id: ObjectId) -> Option<io::Result<Vec<u8>>> {
         if let Err(e) = self.check_error() {
             return Some(Err(e));
         }
 
+        if let Some(error) = self.table.quarantined(object_id) {
+            return Some(Err(io::Error::from(error)));
+        }
+
+        #[cfg(feature = "for-internal-testing-only")]
+        {
+            let latency = self.read_latency_for_testing.load(Ordering::Relaxed);
+            if latency > 0 {
+                std::thread::sleep(Duration::from_nanos(latency));
+            }
+        }
+
         let mut guard = self.free_ebr.pin();
         let slab_address = self.table.get_location_for_object(object_id)?;
 
         let slab = &self.slabs[usize::from(slab_address.slab_id)];
 
-        match slab.read(slab_address.slot(), &mut guard) {
-            Ok(bytes) => Some(Ok(bytes)),
+        let verify = self.checksum_mode != ChecksumMode::Off;
+        match slab.read(slab_address.slot(), verify, &mut guard) {
+            Ok(SlotRead::Valid(bytes)) => Some(Ok(bytes)),
+            Ok(SlotRead::ChecksumMismatch) => {
+                // a corrupted object does not make the rest of the heap
+                // unusable, so unlike other read failures this does not set
+                // the global error
+                let error = corruption_error(
+                    object_id,
+                    self.table.collection_for_object(object_id),
+                    slab_address,
+                );
+                error_log!("{}", error);
+                self.corruption_events.fetch_add(1, Ordering::AcqRel);
+                *self.last_corruption.lock() = Some(error);
+                if self.checksum_mode == ChecksumMode::VerifyAndQuarantine {
+                    self.table.quarantine(error);
+                }
+                self.apply_corruption_policy(&error);
+                Some(Err(io::Error::from(error)))
+            }
             Err(e) => {
                 let annotated = annotate!(e);
                 self.set_error(&annotated);
@@ -895,9 +2003,18 @@ impl Heap {
         }
     }
 
+    /// Writes the objects of the flush of `epoch` and makes the batch
+    /// durable. The slots that the batch vacates are retained for
+    /// `Config::retained_flush_epochs` later batches, see
+    /// `Heap::objects_at_epoch`.
+    ///
+    /// A batch that fails to be written is not referenced by the metadata,
+    /// and the slots it wrote to are freed again, so the same batch can be
+    /// passed to a later call.
     pub fn write_batch(
         &self,
-        batch: Vec<Update>,
+        batch: &[Update],
+        epoch: FlushEpoch,
     ) -> io::Result<WriteBatchStats> {
         self.check_error()?;
         let metadata_store = self.metadata_store.try_lock()
@@ -906,12 +2023,14 @@ impl Heap {
 
         let slabs = &self.slabs;
         let table = &self.table;
+        let checksum = self.checksum;
+        let disk_space = &*self.disk_space;
 
-        let heap_bytes_written = AtomicU64::new(0);
-        let heap_files_used_0_to_63 = AtomicU64::new(0);
-        let heap_files_used_64_to_127 = AtomicU64::new(0);
+        let heap_bytes_written = PortableAtomicU64::new(0);
+        let heap_files_used_0_to_63 = PortableAtomicU64::new(0);
+        let heap_files_used_64_to_127 = PortableAtomicU64::new(0);
 
-        let map_closure = |update: Update| match update {
+        let map_closure = |update: &Update| match update {
             Update::Store { object_id, collection_id, low_key, data } => {
                 let data_len = data.len();
                 let slab_id = slab_for_size(data_len);
@@ -920,7 +2039,7 @@ impl Heap {
                 let new_location_nzu: NonZeroU64 = new_location.into();
 
                 let complete_durability_pipeline =
-                    maybe!(slab.write(new_location.slot(), data));
+                    maybe!(slab.write(new_location.slot(), data, checksum, disk_space));
 
                 if let Err(e) = complete_durability_pipeline {
                     // can immediately free slot as the
@@ -943,27 +2062,48 @@ impl Heap {
                         .fetch_or(slab_bit, Ordering::Release);
                 }
 
-                Ok(UpdateMetadata::Store {
-                    object_id,
-                    collection_id,
-                    low_key,
+                let metadata = UpdateMetadata::Store {
+                    object_id: *object_id,
+                    collection_id: *collection_id,
+                    low_key: low_key.clone(),
                     location: new_location_nzu,
-                })
+                };
+                Ok((metadata, data_len as u64))
             }
-            Update::Free { object_id, collection_id } => {
-                Ok(UpdateMetadata::Free { object_id, collection_id })
+            Update::Free { object_id, collection_id } => Ok((
+                UpdateMetadata::Free {
+                    object_id: *object_id,
+                    collection_id: *collection_id,
+                },
+                0,
+            )),
+            Update::Share { object_id, collection_id, low_key, location } => {
+                let metadata = UpdateMetadata::Store {
+                    object_id: *object_id,
+                    collection_id: *collection_id,
+                    low_key: low_key.clone(),
+                    location: *location,
+                };
+                Ok((metadata, table.object_size(*object_id)))
             }
         };
 
         let before_heap_write = Instant::now();
 
-        let metadata_batch_res: io::Result<Vec<UpdateMetadata>> =
-            batch.into_par_iter().map(map_closure).collect();
+        let metadata_batch_res: Vec<io::Result<(UpdateMetadata, u64)>> =
+            batch.par_iter().map(map_closure).collect();
 
         let before_heap_sync = Instant::now();
 
+        // Every object is written to a slot that no durable metadata
+        // references, because replaced slots are only freed after the
+        // metadata that replaces them is durable. The objects are synced
+        // before the metadata referencing them is written, so an object
+        // whose write is interrupted, even by an abort, is never referenced
+        // and recovery does not read it.
         fence(Ordering::SeqCst);
 
+        let mut sync_res = Ok(());
         for slab_id in 0..N_SLABS {
             let dirty = if slab_id < 64 {
                 let slab_bit = 0b1 << slab_id;
@@ -978,7 +2118,10 @@ impl Heap {
             };
 
             if dirty {
-                self.slabs[slab_id].sync()?;
+                sync_res = self.slabs[slab_id].sync();
+                if sync_res.is_err() {
+                    break;
+                }
             }
         }
 
@@ -986,55 +2129,136 @@ impl Heap {
 
         let heap_write_latency = before_heap_write.elapsed();
 
-        let metadata_batch = match metadata_batch_res {
-            Ok(mut mb) => {
-                // TODO evaluate impact : cost ratio of this sort
-                mb.par_sort_unstable();
-                mb
-            }
-            Err(e) => {
-                self.set_error(&e);
-                return Err(e);
+        // the slots written by a batch that fails are not referenced by any
+        // metadata, and the flush that retries the batch writes it again
+        let written: Vec<SlabAddress> = batch
+            .iter()
+            .zip(&metadata_batch_res)
+            .filter_map(|(update, res)| match (update, res) {
+                (Update::Store { .. }, Ok((UpdateMetadata::Store { location, .. }, _))) => {
+                    Some(SlabAddress::from(*location))
+                }
+                _ => None,
+            })
+            .collect();
+        let free_written = || {
+            for slab_address in &written {
+                table.free_slab_slot(*slab_address);
             }
         };
 
-        // make metadata durable
+        let metadata_batch_res: io::Result<Vec<(UpdateMetadata, u64)>> =
+            sync_res.and_then(|()| metadata_batch_res.into_iter().collect());
+
+        let (metadata_batch, object_sizes): (Vec<UpdateMetadata>, Vec<u64>) =
+            match metadata_batch_res {
+                Ok(mut mb) => {
+                    // TODO evaluate impact : cost ratio of this sort
+                    mb.par_sort_unstable();
+                    mb.into_iter().unzip()
+                }
+                Err(e) => {
+                    free_written();
+                    return Err(e);
+                }
+            };
+
+        // make metadata durable. A metadata write that cannot be rolled back
+        // sets the global error in the metadata store
         let before_metadata_write = Instant::now();
         let metadata_bytes_written =
-            match metadata_store.write_batch(&metadata_batch) {
+            match self
+                .disk_space
+                .retry_when_full(|| metadata_store.write_batch(&metadata_batch))
+            {
                 Ok(metadata_bytes_written) => metadata_bytes_written,
                 Err(e) => {
-                    self.set_error(&e);
+                    free_written();
                     return Err(e);
                 }
             };
         let metadata_write_latency = before_metadata_write.elapsed();
 
+        if let Some(ratio) = self.metadata_auto_compact_ratio
+            && let Err(e) =
+                self.metadata_maintenance.compact_if_dead_ratio_exceeds(ratio)
+        {
+            warn_log!("failed to start metadata store compaction: {e:?}");
+        }
+
+        let mut history = self.epoch_history.lock();
+        let retain = self.retained_flush_epochs > 0;
+        let mut retained = RetainedBatch {
+            epoch,
+            previous: vec![],
+            vacated: vec![],
+        };
+
         // reclaim previous disk locations for future writes
-        for update_metadata in metadata_batch {
+        for (update_metadata, size) in metadata_batch.into_iter().zip(object_sizes) {
+            if retain {
+                let object_id = update_metadata.object_id();
+                let new_location = match &update_metadata {
+                    UpdateMetadata::Store { location, .. } => Some(*location),
+                    UpdateMetadata::Free { .. } => None,
+                };
+                // a shared object is already mapped to its new location by
+                // `Heap::share`, and was not stored before
+                let previous = self
+                    .table
+                    .get_location_for_object(object_id)
+                    .filter(|location| Some(NonZeroU64::from(*location)) != new_location)
+                    .map(|location| (self.table.collection_for_object(object_id), location));
+                retained.previous.push((object_id, previous));
+            }
+
             let last_address_opt = match update_metadata {
-                UpdateMetadata::Store { object_id, location, .. } => {
-                    self.table.insert(object_id, SlabAddress::from(location))
-                }
-                UpdateMetadata::Free { object_id, .. } => {
+                UpdateMetadata::Store {
+                    object_id, collection_id, location, ..
+                } => self.table.insert(
+                    object_id,
+                    collection_id,
+                    SlabAddress::from(location),
+                    size,
+                ),
+                UpdateMetadata::Free { object_id, collection_id } => {
                     guard.defer_drop(DeferredFree {
                         allocator: self.table.clone_object_id_allocator_arc(),
                         freed_slot: object_id.0.get(),
                     });
-                    self.table.remove(object_id)
+                    self.table.remove(object_id, collection_id)
                 }
             };
 
             if let Some(last_address) = last_address_opt {
-                guard.defer_drop(DeferredFree {
+                let vacated = DeferredFree {
                     allocator: self
                         .table
                         .clone_slab_allocator_arc(last_address.slab_id),
                     freed_slot: last_address.slot(),
-                });
+                };
+                if retain {
+                    retained.vacated.push(vacated);
+                } else {
+                    guard.defer_drop(vacated);
+                }
             }
         }
 
+        if retain {
+            history.batches.push_back(retained);
+            while history.batches.len() > self.retained_flush_epochs {
+                let evicted = history.batches.pop_front().unwrap();
+                history.horizon = evicted.epoch;
+                for vacated in evicted.vacated {
+                    guard.defer_drop(vacated);
+                }
+            }
+        } else {
+            history.horizon = epoch;
+        }
+        drop(history);
+
         // truncate files that are now too fragmented
         let before_truncate = Instant::now();
         let mut truncated_files = 0;
@@ -1120,15 +2344,268 @@ impl Heap {
         Ok(stats)
     }
 
+    /// The oldest epoch that `objects_at_epoch` can still locate.
+    pub(crate) fn epoch_horizon(&self) -> FlushEpoch {
+        self.epoch_history.lock().horizon
+    }
+
+    /// Frees the slots of every retained batch, so that compaction can move
+    /// objects into them and truncate the files behind them. Only the
+    /// current state can be read afterwards.
+    pub(crate) fn release_epoch_history(&self) {
+        let mut guard = self.free_ebr.pin();
+        let mut history = self.epoch_history.lock();
+        while let Some(evicted) = history.batches.pop_front() {
+            history.horizon = evicted.epoch;
+            for vacated in evicted.vacated {
+                guard.defer_drop(vacated);
+            }
+        }
+    }
+
+    /// Fails with `ErrorKind::NotFound` if a batch after `epoch` is no
+    /// longer retained, so the state after `epoch` can't be read anymore.
+    fn check_epoch_retained(history: &EpochHistory, epoch: FlushEpoch) -> io::Result<()> {
+        if epoch < history.horizon {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{:?} has been reclaimed, the oldest retained epoch is {:?}",
+                    epoch, history.horizon
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Where every object was stored in the state after the batch of
+    /// `epoch` and every batch before it, in collection id order, without
+    /// reading any of them. Fails with `ErrorKind::NotFound` if a batch
+    /// after `epoch` is no longer retained.
+    pub(crate) fn objects_at_epoch(
+        &self,
+        epoch: FlushEpoch,
+    ) -> io::Result<Vec<(CollectionId, ObjectId, SlabAddress)>> {
+        self.check_error()?;
+
+        let mut objects = {
+            let history = self.epoch_history.lock();
+            Self::check_epoch_retained(&history, epoch)?;
+
+            let mut objects = self.table.stored_objects();
+            for batch in history.batches.iter().rev() {
+                if batch.epoch <= epoch {
+                    break;
+                }
+                for (object_id, previous) in &batch.previous {
+                    match previous {
+                        Some(previous) => objects.insert(*object_id, *previous),
+                        None => objects.remove(object_id),
+                    };
+                }
+            }
+
+            objects
+                .into_iter()
+                .map(|(object_id, (collection_id, location))| {
+                    (collection_id, object_id, location)
+                })
+                .collect::<Vec<_>>()
+        };
+        objects.sort_unstable_by_key(|(collection_id, object_id, _)| {
+            (*collection_id, *object_id)
+        });
+
+        Ok(objects)
+    }
+
+    /// Calls `f` with the collection and the stored bytes of each of
+    /// `objects`, which `objects_at_epoch` returned for `epoch`. Fails with
+    /// `ErrorKind::NotFound` if a batch after `epoch` has stopped being
+    /// retained since then, because their slots may have been reused.
+    ///
+    /// Slots that are vacated while this runs are only freed after it
+    /// returns, so a slow `f` delays the reuse of heap space.
+    pub(crate) fn read_objects_at_epoch(
+        &self,
+        epoch: FlushEpoch,
+        objects: &[(CollectionId, ObjectId, SlabAddress)],
+        mut f: impl FnMut(CollectionId, Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.check_error()?;
+        // pinned before checking the horizon, so that the slots of batches
+        // that stop being retained afterwards stay allocated until we're done
+        let mut guard = self.free_ebr.pin();
+        Self::check_epoch_retained(&self.epoch_history.lock(), epoch)?;
+
+        let verify = self.checksum_mode != ChecksumMode::Off;
+        for &(collection_id, object_id, location) in objects {
+            let slab = &self.slabs[usize::from(location.slab())];
+            match slab.read(location.slot(), verify, &mut guard)? {
+                SlotRead::Valid(bytes) => f(collection_id, bytes)?,
+                SlotRead::ChecksumMismatch => {
+                    let error = corruption_error(object_id, collection_id, location);
+                    self.apply_corruption_policy(&error);
+                    return Err(io::Error::from(error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn metadata_stats(&self) -> io::Result<MetadataStats> {
+        self.metadata_maintenance.stats()
+    }
+
+    /// Rewrites the metadata store so that it only contains live entries.
+    /// Safe to call while another thread is writing a batch.
+    pub(crate) fn compact_metadata(&self) -> io::Result<()> {
+        self.check_error()?;
+        self.metadata_maintenance.compact()
+    }
+
     pub fn heap_object_id_pin(&self) -> ebr::Guard<'_, DeferredFree, 16, 16> {
         self.free_ebr.pin()
     }
 
+    /// Advances the epoch of this handle's deferred frees, see
+    /// `ObjectCache::collect_garbage`.
+    pub(crate) fn collect_garbage(&self) {
+        for _ in 0..EBR_EPOCH_ADVANCES {
+            self.free_ebr.manually_advance_epoch();
+        }
+    }
+
     pub fn allocate_object_id(&self) -> ObjectId {
         self.table.allocate_object_id()
     }
 
+    /// Makes `object_id` readable from the slot that `source` is currently
+    /// stored in, so that both objects share one copy until either of them
+    /// is rewritten or freed. The sharing becomes durable when an
+    /// `Update::Share` for `object_id` is written. Returns `None` if
+    /// `source` is not stored.
+    pub(crate) fn share(
+        &self,
+        source: ObjectId,
+        object_id: ObjectId,
+        collection_id: CollectionId,
+    ) -> Option<SlabAddress> {
+        self.table.share(source, object_id, collection_id)
+    }
+
+    /// Unmaps an object that `Heap::share` mapped and that was never
+    /// written, freeing its id and, if no other object references it any
+    /// more, its slot.
+    pub(crate) fn unshare(&self, object_id: ObjectId, collection_id: CollectionId) {
+        let mut guard = self.free_ebr.pin();
+        if let Some(vacated) = self.table.remove(object_id, collection_id) {
+            let vacated = DeferredFree {
+                allocator: self.table.clone_slab_allocator_arc(vacated.slab_id),
+                freed_slot: vacated.slot(),
+            };
+            let mut history = self.epoch_history.lock();
+            match history.batches.back_mut() {
+                Some(batch) => batch.vacated.push(vacated),
+                None => guard.defer_drop(vacated),
+            }
+        }
+        guard.defer_drop(DeferredFree {
+            allocator: self.table.clone_object_id_allocator_arc(),
+            freed_slot: object_id.0.get(),
+        });
+    }
+
     pub(crate) fn objects_to_defrag(&self) -> FnvHashSet<ObjectId> {
         self.table.objects_to_defrag()
     }
+
+    /// The objects to move so that every slab is completely filled.
+    pub(crate) fn objects_to_compact(&self) -> FnvHashSet<ObjectId> {
+        self.table.objects_to_defrag_to(1.0)
+    }
+
+    /// The fraction of slab file bytes that are not taken up by an
+    /// allocated slot, between 0.0 and 1.0.
+    pub(crate) fn fragmentation(&self) -> io::Result<f64> {
+        let allocated_slots = self.table.allocated_slots_per_slab();
+
+        let mut file_bytes = 0;
+        let mut live_bytes = 0;
+        for (slab, allocated) in self.slabs.iter().zip(allocated_slots) {
+            let len = slab.file.metadata()?.len();
+            file_bytes += len;
+            live_bytes += (allocated * slab.slot_size as u64).min(len);
+        }
+
+        if file_bytes == 0 {
+            return Ok(0.0);
+        }
+        Ok(1.0 - live_bytes as f64 / file_bytes as f64)
+    }
+
+    /// Truncates every slab file after its highest allocated slot, and
+    /// returns the number of bytes removed. Slots are only freed after
+    /// the metadata no longer references them, so this is as crash safe
+    /// as the truncation in `write_batch`.
+    ///
+    /// This must only be called while flushes are serialized, because
+    /// `write_batch` must not observe the metadata store as locked.
+    pub(crate) fn truncate_to_live(&self) -> io::Result<u64> {
+        let _metadata_store = self.metadata_store.lock();
+
+        let max_allocated: FnvHashMap<usize, u64> =
+            self.table.get_max_allocated_per_slab().into_iter().collect();
+
+        let mut truncated_bytes = 0;
+        for (i, slab) in self.slabs.iter().enumerate() {
+            let len = slab.file.metadata()?.len();
+            let live_len = max_allocated
+                .get(&i)
+                .map_or(0, |max| (max + 1) * slab.slot_size as u64);
+
+            if live_len < len && cfg!(not(feature = "monotonic-behavior")) {
+                slab.file.set_len(live_len)?;
+                slab.max_live_slot_since_last_truncation
+                    .store(max_allocated.get(&i).copied().unwrap_or(0), Ordering::SeqCst);
+                truncated_bytes += len - live_len;
+            }
+        }
+
+        self.truncated_file_bytes.fetch_add(truncated_bytes, Ordering::Release);
+
+        Ok(truncated_bytes)
+    }
+
+    /// Bytes of live objects stored for each collection, maintained
+    /// incrementally as batches are written.
+    pub(crate) fn collection_bytes(&self) -> FnvHashMap<CollectionId, u64> {
+        self.table.collection_bytes()
+    }
+
+    pub(crate) fn bytes_for_collection(&self, collection_id: CollectionId) -> u64 {
+        self.table.bytes_for_collection(collection_id)
+    }
+
+    /// Returns the usage of every non-empty slab file.
+    pub(crate) fn slab_file_usage(&self) -> io::Result<Vec<SlabFileUsage>> {
+        let mut ret = vec![];
+        for slab in self.slabs.iter() {
+            let len = slab.file.metadata()?.len();
+            if len > 0 {
+                ret.push(SlabFileUsage {
+                    slot_size: slab.slot_size as u64,
+                    file_bytes: len,
+                    allocated_bytes: sys_io::allocated_len(&slab.file)?,
+                    unbuffered: slab.unbuffered,
+                });
+            }
+        }
+        Ok(ret)
+    }
+
+    pub(crate) fn metadata_path(&self) -> PathBuf {
+        self.path.join("metadata")
+    }
 }
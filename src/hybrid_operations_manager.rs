@@ -4,16 +4,93 @@
 //! - 普通数据库操作：直接访问，零额外开销
 //! - 原子计数器操作：通过统一架构，保证并发安全
 
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::io;
+use std::time::{Duration, Instant};
 
-use crate::{debug_log, trace_log, warn_log, error_log, info_log, InlineArray};
+use parking_lot::Mutex;
+
+use crate::{debug_log, trace_log, warn_log, Batch, InlineArray, Tree};
 use crate::db::Db;
+use crate::portable_atomic::PortableAtomicU64;
 use super::atomic_worker::AtomicWorker;
-use super::database_worker::DatabaseWorker;
+
+pub use super::atomic_worker::{
+    AtomicWorkerConfig, CounterEviction, CounterKeyHashing, CounterLimitExceeded, CounterStats,
+    RoundingMode,
+};
+use super::database_worker::{
+    encode_counter, load_counters, counter_key, DatabaseWorker,
+};
+
+pub use super::database_worker::{
+    CounterKind, CounterPreloadReport, CounterTypeMismatch, DatabaseWorkerStats, PreloadIssue,
+    PreloadIssueReason,
+};
+
+/// 慢操作日志中的请求ID，在进程内唯一
+static NEXT_REQUEST_ID: PortableAtomicU64 = PortableAtomicU64::new(1);
+
+/// 同一个数据库上的所有管理器共享的Worker
+///
+/// 每个数据库只有一个原子操作Worker和一份内存中的计数器，
+/// 否则不同管理器的递增互相不可见，并且两个Worker会交替持久化各自的旧值。
+/// 数据库Worker在仍有管理器使用它时共享，最后一个使用者释放后关闭
+#[derive(Default)]
+pub(crate) struct SharedWorkers {
+    atomic_worker: OnceLock<Arc<AtomicWorker>>,
+    database_worker: Mutex<Weak<DatabaseWorker>>,
+}
+
+impl SharedWorkers {
+    fn atomic_worker(&self, db: &Db<1024>) -> Arc<AtomicWorker> {
+        self.atomic_worker
+            .get_or_init(|| {
+                Arc::new(AtomicWorker::new(None, Tree::clone(db), db.atomic_worker_config()))
+            })
+            .clone()
+    }
+
+    pub(crate) fn counter_stats(&self) -> Option<CounterStats> {
+        self.atomic_worker.get().map(|atomic_worker| atomic_worker.counter_stats())
+    }
+
+    pub(crate) fn counter_memory_bytes(&self) -> usize {
+        self.atomic_worker.get().map_or(0, |atomic_worker| atomic_worker.counter_memory_bytes())
+    }
+
+    /// 获取共享的数据库Worker，没有时创建一个并让原子操作Worker向它发送持久化指令
+    fn acquire_database_worker(&self, db: &Arc<Db<1024>>) -> Arc<DatabaseWorker> {
+        let mut database_worker = self.database_worker.lock();
+        if let Some(existing) = database_worker.upgrade() {
+            return existing;
+        }
+
+        let created = Arc::new(DatabaseWorker::new(db.clone()));
+        self.atomic_worker(db)
+            .set_db_queue(Some(created.operation_queue().clone()));
+        *database_worker = Arc::downgrade(&created);
+        created
+    }
+
+    /// 释放一个管理器持有的数据库Worker，最后一个使用者释放时停止持久化计数器
+    fn release_database_worker(&self, released: Arc<DatabaseWorker>) {
+        let _database_worker = self.database_worker.lock();
+        if Arc::strong_count(&released) == 1
+            && let Some(atomic_worker) = self.atomic_worker.get()
+        {
+            atomic_worker.set_db_queue(None);
+        }
+        drop(released);
+    }
+}
 
 /// 混合操作管理器
 ///
+/// 同一个数据库（包括它的克隆）上创建的所有管理器共享同一个原子操作Worker
+/// 和同一份内存中的计数器，通过任何一个管理器的递增对其他管理器立即可见。
+///
 /// 智能选择最优路径：
 /// - 原子操作 → AtomicWorker（保证并发安全）
 /// - 普通操作 → 直接访问（零开销）
@@ -26,34 +103,49 @@ pub struct HybridOperationsManager {
 
     /// 数据库操作Worker（仅用于特殊场景）
     database_worker: Option<Arc<DatabaseWorker>>,
+
+    /// 是否合并数据库Worker中相同键的并发获取请求
+    coalesce_gets: AtomicBool,
+
+    /// 数据库Worker是否优先处理读取操作
+    prioritize_reads: AtomicBool,
+
+    /// 记录为慢操作的耗时阈值（纳秒），`u64::MAX` 表示不记录
+    slow_op_threshold_nanos: PortableAtomicU64,
 }
 
 impl HybridOperationsManager {
-    /// 创建新的混合操作管理器
+    /// 创建新的混合操作管理器，与同一个数据库上的其他管理器共享原子操作Worker
     pub fn new(db: Arc<Db<1024>>) -> Self {
         debug_log!("创建混合操作管理器");
 
-        // 创建原子操作Worker（不需要数据库Worker队列）
-        let atomic_worker = Arc::new(AtomicWorker::new(None));
+        let atomic_worker = db.shared_workers.atomic_worker(&db);
 
         Self {
             db,
             atomic_worker,
             database_worker: None,
+            coalesce_gets: AtomicBool::new(false),
+            prioritize_reads: AtomicBool::new(false),
+            slow_op_threshold_nanos: PortableAtomicU64::new(u64::MAX),
         }
     }
 
-    /// 创建带数据库Worker的管理器（特殊场景使用）
+    /// 创建带数据库Worker的管理器（特殊场景使用）。
+    /// 同一个数据库上启用了数据库Worker模式的管理器共享同一个数据库Worker
     pub fn new_with_db_worker(db: Arc<Db<1024>>) -> Self {
         debug_log!("创建混合操作管理器（含数据库Worker）");
 
-        let database_worker = Arc::new(DatabaseWorker::new(db.clone()));
-        let atomic_worker = Arc::new(AtomicWorker::new(Some(database_worker.operation_queue().clone())));
+        let database_worker = db.shared_workers.acquire_database_worker(&db);
+        let atomic_worker = db.shared_workers.atomic_worker(&db);
 
         Self {
             db,
             atomic_worker,
             database_worker: Some(database_worker),
+            coalesce_gets: AtomicBool::new(false),
+            prioritize_reads: AtomicBool::new(false),
+            slow_op_threshold_nanos: PortableAtomicU64::new(u64::MAX),
         }
     }
 
@@ -61,161 +153,408 @@ impl HybridOperationsManager {
 
     /// 原子递增操作
     pub fn increment(&self, counter_name: String, delta: u64) -> io::Result<u64> {
-        trace_log!("执行原子递增: {} + {}", counter_name, delta);
-        self.atomic_worker.increment(counter_name, delta)
+        trace_log!(op = "increment", counter = counter_name.as_str(), delta = delta; "执行原子递增: {} + {}", counter_name, delta);
+        self.observe("increment", counter_name.len(), || {
+            self.atomic_worker.increment(counter_name, delta)
+        })
+    }
+
+    /// 有上限的原子递增操作，例如用于限流：递增后不超过 `max` 时返回新的值，
+    /// 否则不修改计数器并返回 `None`。只有值发生变化时才持久化
+    pub fn increment_bounded(&self, counter_name: String, delta: u64, max: u64) -> io::Result<Option<u64>> {
+        trace_log!(op = "increment_bounded", counter = counter_name.as_str(), delta = delta, max = max; "执行有上限的原子递增: {} + {} (max: {})", counter_name, delta, max);
+        self.observe("increment_bounded", counter_name.len(), || {
+            self.atomic_worker.increment_bounded(counter_name, delta, max)
+        })
     }
 
     /// 原子递减操作
     pub fn decrement(&self, counter_name: String, delta: u64) -> io::Result<u64> {
-        trace_log!("执行原子递减: {} - {}", counter_name, delta);
-        self.atomic_worker.decrement(counter_name, delta)
+        trace_log!(op = "decrement", counter = counter_name.as_str(), delta = delta; "执行原子递减: {} - {}", counter_name, delta);
+        self.observe("decrement", counter_name.len(), || {
+            self.atomic_worker.decrement(counter_name, delta)
+        })
     }
 
     /// 原子乘法操作
     pub fn multiply(&self, counter_name: String, factor: u64) -> io::Result<u64> {
-        trace_log!("执行原子乘法: {} * {}", counter_name, factor);
-        self.atomic_worker.multiply(counter_name, factor)
+        trace_log!(op = "multiply", counter = counter_name.as_str(), factor = factor; "执行原子乘法: {} * {}", counter_name, factor);
+        self.observe("multiply", counter_name.len(), || {
+            self.atomic_worker.multiply(counter_name, factor)
+        })
     }
 
-    /// 原子除法操作
+    /// 原子除法操作，结果向下舍入
     pub fn divide(&self, counter_name: String, divisor: u64) -> io::Result<u64> {
-        trace_log!("执行原子除法: {} / {}", counter_name, divisor);
-        self.atomic_worker.divide(counter_name, divisor)
+        self.divide_with(counter_name, divisor, RoundingMode::Down)
+    }
+
+    /// 原子除法操作，按 `rounding` 舍入。除数为零时返回 `InvalidInput`
+    pub fn divide_with(&self, counter_name: String, divisor: u64, rounding: RoundingMode) -> io::Result<u64> {
+        trace_log!(op = "divide", counter = counter_name.as_str(), divisor = divisor; "执行原子除法: {} / {}", counter_name, divisor);
+        self.observe("divide", counter_name.len(), || {
+            self.atomic_worker.scale(counter_name, 1, divisor, rounding)
+        })
     }
 
-    /// 原子百分比操作
+    /// 原子百分比操作，结果向下舍入
     pub fn percentage(&self, counter_name: String, percentage: u64) -> io::Result<u64> {
-        trace_log!("执行原子百分比: {} * {}%", counter_name, percentage);
-        self.atomic_worker.percentage(counter_name, percentage)
+        self.percentage_with(counter_name, percentage, RoundingMode::Down)
+    }
+
+    /// 原子百分比操作，按 `rounding` 舍入。`percentage` 超过100时返回 `InvalidInput`
+    pub fn percentage_with(&self, counter_name: String, percentage: u64, rounding: RoundingMode) -> io::Result<u64> {
+        trace_log!(op = "percentage", counter = counter_name.as_str(), percentage = percentage; "执行原子百分比: {} * {}%", counter_name, percentage);
+        if percentage > 100 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "百分比值不能超过100"));
+        }
+        self.observe("percentage", counter_name.len(), || {
+            self.atomic_worker.scale(counter_name, percentage, 100, rounding)
+        })
+    }
+
+    /// 原子万分比（基点）操作，计数器变为 `value * basis_points / 10000`，
+    /// 按 `rounding` 舍入。`basis_points` 超过10000时返回 `InvalidInput`
+    pub fn basis_points(&self, counter_name: String, basis_points: u64, rounding: RoundingMode) -> io::Result<u64> {
+        trace_log!(op = "basis_points", counter = counter_name.as_str(), basis_points = basis_points; "执行原子万分比: {} * {}‱", counter_name, basis_points);
+        if basis_points > 10_000 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "基点值不能超过10000"));
+        }
+        self.observe("basis_points", counter_name.len(), || {
+            self.atomic_worker.scale(counter_name, basis_points, 10_000, rounding)
+        })
+    }
+
+    /// 原子按比例缩放操作，计数器变为 `value * numerator / denominator`，按 `rounding`
+    /// 舍入。中间结果使用128位整数计算，不会溢出；结果超过 `u64::MAX` 时与
+    /// `multiply` 一样设为 `u64::MAX`。分母为零时返回 `InvalidInput`
+    pub fn scale(
+        &self,
+        counter_name: String,
+        numerator: u64,
+        denominator: u64,
+        rounding: RoundingMode,
+    ) -> io::Result<u64> {
+        trace_log!(op = "scale", counter = counter_name.as_str(), numerator = numerator, denominator = denominator; "执行原子缩放: {} * {} / {}", counter_name, numerator, denominator);
+        self.observe("scale", counter_name.len(), || {
+            self.atomic_worker.scale(counter_name, numerator, denominator, rounding)
+        })
     }
 
     /// 原子比较和交换操作
     pub fn compare_and_swap(&self, counter_name: String, expected: u64, new_value: u64) -> io::Result<bool> {
-        trace_log!("执行原子比较和交换: {} (expected: {}, new: {})", counter_name, expected, new_value);
-        self.atomic_worker.compare_and_swap(counter_name, expected, new_value)
+        trace_log!(op = "compare_and_swap", counter = counter_name.as_str(), expected = expected, new_value = new_value; "执行原子比较和交换: {} (expected: {}, new: {})", counter_name, expected, new_value);
+        self.observe("compare_and_swap", counter_name.len(), || {
+            self.atomic_worker.compare_and_swap(counter_name, expected, new_value)
+        })
+    }
+
+    /// 多个计数器的原子比较和交换：`ops` 中的每一项是
+    /// `(counter_name, expected, new_value)`，所有计数器的值都等于期望值时才写入
+    /// 全部新值并返回 `true`，否则不修改任何计数器并返回 `false`。
+    /// 不存在的计数器按0比较，同一个计数器出现多次时返回 `InvalidInput` 错误
+    pub fn multi_compare_and_swap(&self, ops: Vec<(String, u64, u64)>) -> io::Result<bool> {
+        trace_log!(op = "multi_compare_and_swap", counters = ops.len(); "执行多个计数器的原子比较和交换: {} 个计数器", ops.len());
+        let key_len = ops.iter().map(|(name, _, _)| name.len()).sum();
+        self.observe("multi_compare_and_swap", key_len, || {
+            self.atomic_worker.multi_compare_and_swap(ops)
+        })
     }
 
     /// 获取计数器值
     pub fn get(&self, counter_name: String) -> io::Result<Option<u64>> {
-        trace_log!("执行获取计数器: {}", counter_name);
-        self.atomic_worker.get(counter_name)
+        trace_log!(op = "get_counter", counter = counter_name.as_str(); "执行获取计数器: {}", counter_name);
+        self.observe("get_counter", counter_name.len(), || self.atomic_worker.get(counter_name))
+    }
+
+    /// 获取计数器值，最多等待 `timeout`。
+    ///
+    /// 原子操作Worker在 `timeout` 内没有响应时返回 `ErrorKind::TimedOut`，
+    /// 不会无限期阻塞调用方
+    pub fn get_timeout(&self, counter_name: String, timeout: Duration) -> io::Result<Option<u64>> {
+        trace_log!(op = "get_counter", counter = counter_name.as_str(); "执行获取计数器: {}，超时 {:?}", counter_name, timeout);
+        self.observe("get_counter", counter_name.len(), || {
+            self.atomic_worker.get_with_timeout(counter_name, Some(timeout))
+        })
     }
 
     /// 重置计数器
     pub fn reset(&self, counter_name: String, new_value: u64) -> io::Result<()> {
-        trace_log!("执行重置计数器: {} = {}", counter_name, new_value);
-        self.atomic_worker.reset(counter_name, new_value)
+        trace_log!(op = "reset", counter = counter_name.as_str(), new_value = new_value; "执行重置计数器: {} = {}", counter_name, new_value);
+        self.observe("reset", counter_name.len(), || {
+            self.atomic_worker.reset(counter_name, new_value)
+        })
+    }
+
+    /// 浮点计数器的原子累加，返回累加后的值。不存在的计数器从0开始。
+    ///
+    /// 浮点计数器与u64计数器共用名称空间：对已存在的u64计数器调用时返回
+    /// `CounterTypeMismatch`，反之亦然。`delta` 为NaN时返回 `InvalidInput`，
+    /// 结果为无穷大时饱和到 `f64::MAX` 或 `f64::MIN` 并输出警告。
+    /// 值以小端序IEEE 754格式持久化，通过 `preload_counters` 加载
+    pub fn add_f64(&self, counter_name: String, delta: f64) -> io::Result<f64> {
+        trace_log!(op = "add_f64", counter = counter_name.as_str(), delta = delta; "执行浮点累加: {} + {}", counter_name, delta);
+        self.observe("add_f64", counter_name.len(), || {
+            self.atomic_worker.add_f64(counter_name, delta)
+        })
     }
 
-    /// 预热原子计数器
+    /// 获取浮点计数器的值，对u64计数器调用时返回 `CounterTypeMismatch`
+    pub fn get_f64(&self, counter_name: String) -> io::Result<Option<f64>> {
+        trace_log!(op = "get_f64", counter = counter_name.as_str(); "执行获取浮点计数器: {}", counter_name);
+        self.observe("get_f64", counter_name.len(), || self.atomic_worker.get_f64(counter_name))
+    }
+
+    /// 重置浮点计数器，规则与 `add_f64` 相同
+    pub fn reset_f64(&self, counter_name: String, new_value: f64) -> io::Result<()> {
+        trace_log!(op = "reset_f64", counter = counter_name.as_str(), new_value = new_value; "执行重置浮点计数器: {} = {}", counter_name, new_value);
+        self.observe("reset_f64", counter_name.len(), || {
+            self.atomic_worker.reset_f64(counter_name, new_value)
+        })
+    }
+
+    /// 预热原子计数器，返回加载的计数器数量。
+    /// 已在内存中的计数器（例如由共享Worker的其他管理器更新过的）保持内存中的值
     pub fn preload_counters(&self) -> io::Result<usize> {
-        debug_log!("预热原子计数器");
+        self.preload_counters_report()
+            .map(|report| report.counters.len() + report.float_counters.len())
+    }
 
-        // 直接从数据库加载计数器
-        let mut counters = Vec::new();
-        let prefix = b"__atomic_counter__:";
-
-        for item_res in self.db.scan_prefix(prefix) {
-            if let Ok((key_bytes, value_bytes)) = item_res {
-                let key_bytes = &*key_bytes;
-                let value_bytes = &*value_bytes;
-
-                if let Ok(key_str) = std::str::from_utf8(key_bytes) {
-                    if let Some(counter_name) = key_str.strip_prefix("__atomic_counter__:") {
-                        if value_bytes.len() >= 8 {
-                            let mut arr = [0u8; 8];
-                            arr.copy_from_slice(&value_bytes[..8]);
-                            let value = u64::from_le_bytes(arr);
-                            counters.push((counter_name.to_string(), value));
-                        }
-                    }
-                }
-            }
-        }
+    /// 预热原子计数器，并返回加载的计数器、仍为旧格式的计数器以及被跳过的条目。
+    ///
+    /// 值必须是带版本的9字节格式或旧版的8字节格式，
+    /// 其他长度或未知版本的条目不会被加载，并以警告级别记录日志
+    pub fn preload_counters_report(&self) -> io::Result<CounterPreloadReport> {
+        debug_log!("预热原子计数器");
 
-        let count = counters.len();
+        let report = if let Some(db_worker) = &self.database_worker {
+            db_worker.preload_counters()?
+        } else {
+            load_counters(&self.db)?
+        };
 
         // 加载到原子操作Worker
-        for (name, value) in counters {
-            self.atomic_worker.load_counter(name.clone(), value);
+        for (name, value) in &report.counters {
+            self.atomic_worker.load_counter(name.clone(), *value);
             trace_log!("预热计数器: {} = {}", name, value);
         }
+        for (name, value) in &report.float_counters {
+            self.atomic_worker.load_float_counter(name.clone(), *value);
+            trace_log!("预热浮点计数器: {} = {}", name, value);
+        }
+
+        Ok(report)
+    }
+
+    /// 将以旧版8字节格式存储的计数器改写为带版本的格式，返回改写的数量。
+    ///
+    /// 使用比较并交换改写每个计数器，期间被并发更新的计数器已经是新格式，会被跳过
+    pub fn upgrade_counter_format(&self) -> io::Result<usize> {
+        let report = load_counters(&self.db)?;
+        let mut upgraded = 0;
+
+        for name in &report.legacy {
+            let key = counter_key(name);
+            let Some(current) = self.db.get(&key)? else {
+                continue;
+            };
+            if current.len() != 8 {
+                continue;
+            }
+
+            let value = u64::from_le_bytes(current.as_ref().try_into().unwrap());
+            let cas_res = self.db.compare_and_swap(
+                &key,
+                Some(current),
+                Some(&encode_counter(value)[..]),
+            )?;
+            if cas_res.is_ok() {
+                upgraded += 1;
+            }
+        }
 
-        Ok(count)
+        debug_log!("已将 {} 个计数器升级为带版本的格式", upgraded);
+        Ok(upgraded)
     }
 
     // ========== 普通数据库操作：直接访问 ==========
 
-    /// 执行数据库插入操作（直接访问）
+    /// 执行数据库插入操作（直接访问），返回该键之前的值。
+    /// 直接访问和数据库Worker模式的返回值一致
     pub fn insert(&self, key: &[u8], value: &[u8]) -> io::Result<Option<InlineArray>> {
-        trace_log!("直接数据库插入: {:?}", key);
+        trace_log!(op = "insert", key_len = key.len(), value_len = value.len(); "直接数据库插入: {:?}", key);
 
         // 使用DatabaseWorker以避免EBR冲突
-        if let Some(db_worker) = &self.database_worker {
-            // 启用DatabaseWorker模式时通过Worker避免EBR冲突
-            db_worker.insert(key.to_vec(), value.to_vec())
-        } else {
-            // 默认场景：直接访问，零开销（单线程安全）
-            self.db.insert(key, value)
-        }
+        self.observe("insert", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                // 启用DatabaseWorker模式时通过Worker避免EBR冲突
+                db_worker.insert(key.to_vec(), value.to_vec())
+            } else {
+                // 默认场景：直接访问，零开销（单线程安全）
+                self.db.insert(key, value)
+            }
+        })
     }
 
     /// 执行数据库获取操作（直接访问）
     pub fn get_data(&self, key: &[u8]) -> io::Result<Option<InlineArray>> {
-        trace_log!("直接数据库获取: {:?}", key);
+        trace_log!(op = "get", key_len = key.len(); "直接数据库获取: {:?}", key);
 
-        if let Some(db_worker) = &self.database_worker {
-            db_worker.get(key.to_vec())
-        } else {
-            self.db.get(key)
-        }
+        self.observe("get", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.get(key.to_vec())
+            } else {
+                self.db.get(key)
+            }
+        })
+    }
+
+    /// 执行数据库插入操作，最多等待 `timeout`。
+    ///
+    /// 数据库Worker模式下Worker在 `timeout` 内没有响应时返回 `ErrorKind::TimedOut`，
+    /// 此时插入仍在队列中，之后可能被执行。直接访问模式下与 `insert` 相同
+    pub fn insert_timeout(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Option<InlineArray>> {
+        self.observe("insert", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.insert_with_timeout(key.to_vec(), value.to_vec(), Some(timeout))
+            } else {
+                self.db.insert(key, value)
+            }
+        })
+    }
+
+    /// 执行数据库获取操作，最多等待 `timeout`。
+    ///
+    /// 数据库Worker模式下Worker在 `timeout` 内没有响应时返回 `ErrorKind::TimedOut`。
+    /// 直接访问模式下与 `get_data` 相同
+    pub fn get_data_timeout(&self, key: &[u8], timeout: Duration) -> io::Result<Option<InlineArray>> {
+        self.observe("get", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.get_with_timeout(key.to_vec(), Some(timeout))
+            } else {
+                self.db.get(key)
+            }
+        })
+    }
+
+    /// 扫描前缀操作，最多等待 `timeout`。
+    ///
+    /// 数据库Worker模式下扫描在 `timeout` 内没有完成时返回 `ErrorKind::TimedOut`。
+    /// 直接访问模式下与 `scan_prefix` 相同
+    pub fn scan_prefix_timeout(
+        &self,
+        prefix: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let Some(db_worker) = &self.database_worker else {
+            return self.scan_prefix(prefix);
+        };
+        self.observe("scan_prefix", prefix.len(), || {
+            db_worker.scan_prefix_with_timeout(prefix.to_vec(), Some(timeout))
+        })
+    }
+
+    /// 执行数据库删除操作，最多等待 `timeout`。
+    ///
+    /// 数据库Worker模式下Worker在 `timeout` 内没有响应时返回 `ErrorKind::TimedOut`，
+    /// 此时删除仍在队列中，之后可能被执行。直接访问模式下与 `remove` 相同
+    pub fn remove_timeout(&self, key: &[u8], timeout: Duration) -> io::Result<Option<InlineArray>> {
+        self.observe("remove", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.remove_with_timeout(key.to_vec(), Some(timeout))
+            } else {
+                self.db.remove(key)
+            }
+        })
     }
 
     /// 扫描前缀操作
+    ///
+    /// 启用DatabaseWorker时，扫描读取提交时刻的快照并在Worker中分批执行，
+    /// 大范围的扫描不会阻塞之后提交的其他操作
     pub fn scan_prefix(&self, prefix: &[u8]) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
-        trace_log!("扫描前缀: {:?}", prefix);
+        trace_log!(op = "scan_prefix", prefix_len = prefix.len(); "扫描前缀: {:?}", prefix);
 
         // 使用DatabaseWorker以避免EBR冲突
+        self.observe("scan_prefix", prefix.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                // 启用DatabaseWorker模式时通过Worker避免EBR冲突
+                db_worker.scan_prefix(prefix.to_vec())
+            } else {
+                // 默认场景：直接访问（单线程安全）
+                self.db.scan_prefix(prefix)
+                    .collect::<io::Result<Vec<_>>>()
+                    .map(|items| {
+                        items.into_iter()
+                            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                            .collect()
+                    })
+            }
+        })
+    }
+
+    /// 执行数据库删除操作（直接访问），返回被删除的值。
+    /// 直接访问和数据库Worker模式的返回值一致
+    pub fn remove(&self, key: &[u8]) -> io::Result<Option<InlineArray>> {
+        trace_log!(op = "remove", key_len = key.len(); "直接数据库删除: {:?}", key);
+
+        self.observe("remove", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.remove(key.to_vec())
+            } else {
+                self.db.remove(key)
+            }
+        })
+    }
+
+    /// 原子地应用批量写入。启用数据库Worker模式时通过数据库Worker执行，否则直接访问
+    pub fn apply_batch(&self, batch: Batch) -> io::Result<()> {
+        trace_log!(op = "apply_batch", keys = batch.writes.len(); "批量写入: {} 个键", batch.writes.len());
+
         if let Some(db_worker) = &self.database_worker {
-            // 启用DatabaseWorker模式时通过Worker避免EBR冲突
-            db_worker.scan_prefix(prefix.to_vec())
+            db_worker.apply_batch(batch)
         } else {
-            // 默认场景：直接访问（单线程安全）
-            self.db.scan_prefix(prefix)
-                .collect::<io::Result<Vec<_>>>()
-                .map(|items| {
-                    items.into_iter()
-                        .map(|(key, value)| (key.to_vec(), value.to_vec()))
-                        .collect()
-                })
+            self.db.apply_batch(batch)
         }
     }
 
-    /// 执行数据库删除操作（直接访问）
-    pub fn remove(&self, key: &[u8]) -> io::Result<Option<InlineArray>> {
-        trace_log!("直接数据库删除: {:?}", key);
+    /// 原子地应用批量写入，并按键顺序返回每个键之前的值。与 `apply_batch` 一样，
+    /// 启用数据库Worker模式时通过数据库Worker执行，否则直接访问。
+    /// 之前的值为 `None` 表示该键是新建的。需要克隆所有写入的键和旧值
+    pub fn apply_batch_returning(
+        &self,
+        batch: Batch,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        trace_log!(op = "apply_batch_returning", keys = batch.writes.len(); "批量写入并返回旧值: {} 个键", batch.writes.len());
 
         if let Some(db_worker) = &self.database_worker {
-            db_worker.remove(key.to_vec())
+            db_worker.apply_batch_returning(batch)
         } else {
-            self.db.remove(key)
+            self.db.apply_batch_returning(batch)
         }
     }
 
     /// 检查键是否存在（直接访问）
     pub fn contains_key(&self, key: &[u8]) -> io::Result<bool> {
-        trace_log!("直接检查键存在: {:?}", key);
+        trace_log!(op = "contains_key", key_len = key.len(); "直接检查键存在: {:?}", key);
 
-        if let Some(db_worker) = &self.database_worker {
-            db_worker.contains_key(key.to_vec())
-        } else {
-            self.db.contains_key(key)
-        }
+        self.observe("contains_key", key.len(), || {
+            if let Some(db_worker) = &self.database_worker {
+                db_worker.contains_key(key.to_vec())
+            } else {
+                self.db.contains_key(key)
+            }
+        })
     }
 
     /// 清空所有数据（直接访问）
     pub fn clear(&self) -> io::Result<()> {
-        trace_log!("直接清空数据库");
+        trace_log!(op = "clear"; "直接清空数据库");
 
         if let Some(db_worker) = &self.database_worker {
             db_worker.clear()
@@ -268,27 +607,129 @@ impl HybridOperationsManager {
         }
     }
 
-    /// 启用数据库Worker模式（特殊场景）
+    /// 启用数据库Worker模式（特殊场景）。内存中的计数器保持不变，
+    /// 之后的原子操作通过数据库Worker持久化
     pub fn enable_database_worker_mode(&mut self) {
         if self.database_worker.is_none() {
             debug_log!("启用数据库Worker模式");
-            self.database_worker = Some(Arc::new(DatabaseWorker::new(self.db.clone())));
-
-            // 重新创建AtomicWorker，连接到DatabaseWorker
-            self.atomic_worker = Arc::new(AtomicWorker::new(
-                Some(self.database_worker.as_ref().unwrap().operation_queue().clone())
-            ));
+            let database_worker = self.db.shared_workers.acquire_database_worker(&self.db);
+            if self.coalesce_gets.load(Ordering::Acquire) {
+                database_worker.set_get_coalescing(true);
+            }
+            if self.prioritize_reads.load(Ordering::Acquire) {
+                database_worker.set_read_priority(true);
+            }
+            self.database_worker = Some(database_worker);
         }
     }
 
-    /// 禁用数据库Worker模式（默认高性能模式）
+    /// 禁用数据库Worker模式（默认高性能模式）。同一个数据库上没有其他管理器
+    /// 使用数据库Worker时，原子操作不再持久化
     pub fn disable_database_worker_mode(&mut self) {
-        if self.database_worker.is_some() {
+        if let Some(database_worker) = self.database_worker.take() {
             debug_log!("禁用数据库Worker模式，切换到直接访问");
-            self.database_worker = None;
+            self.db.shared_workers.release_database_worker(database_worker);
+        }
+    }
+
+    /// 启用或禁用数据库Worker模式下 `get_data` 的请求合并（默认禁用）。
+    ///
+    /// 启用后，多个线程并发获取同一个键时只执行一次读取，所有请求共享结果。
+    /// 每个请求得到的值不早于它提交的时刻。直接访问模式下没有效果。
+    /// 数据库Worker由同一个数据库上的管理器共享，设置对所有使用它的管理器生效
+    pub fn set_get_coalescing(&self, enabled: bool) {
+        self.coalesce_gets.store(enabled, Ordering::Release);
+        if let Some(db_worker) = &self.database_worker {
+            db_worker.set_get_coalescing(enabled);
+        }
+    }
+
+    /// 启用或禁用数据库Worker的读取优先调度（默认禁用，按提交顺序处理）。
+    ///
+    /// 启用后，`get_data`、`scan_prefix` 和 `contains_key` 排在等待中的写入操作
+    /// 之前执行，大量写入时读取的延迟不会随写入队列的长度增长。有等待中的写入时
+    /// 每连续处理16个读取就处理一个写入。原子计数器异步的持久化指令也是写入，
+    /// 读取计数器的键可能读到较早持久化的值。直接访问模式下没有效果。
+    /// 数据库Worker由同一个数据库上的管理器共享，设置对所有使用它的管理器生效
+    pub fn set_read_priority(&self, enabled: bool) {
+        self.prioritize_reads.store(enabled, Ordering::Release);
+        if let Some(db_worker) = &self.database_worker {
+            db_worker.set_read_priority(enabled);
+        }
+    }
+
+    /// 设置慢操作的耗时阈值（默认不记录，`Duration::MAX` 关闭）。
+    ///
+    /// 计数器操作和按键的数据操作从提交到得到结果（包括与Worker之间的往返）
+    /// 超过 `threshold` 时，以警告级别记录操作类型、键或计数器名称的长度、
+    /// 耗时和一个在进程内唯一的请求ID，用于定位偶发的长尾延迟。
+    /// 启用 `tracing` 特性时它们作为 `slow_op`、`key_len`、`elapsed_us` 和
+    /// `request_id` 字段输出。只对这个管理器生效，克隆的管理器继承当前的设置
+    pub fn set_slow_op_threshold(&self, threshold: Duration) {
+        let nanos = u64::try_from(threshold.as_nanos()).unwrap_or(u64::MAX);
+        self.slow_op_threshold_nanos.store(nanos, Ordering::Release);
+    }
 
-            // 重新创建AtomicWorker，不连接DatabaseWorker
-            self.atomic_worker = Arc::new(AtomicWorker::new(None));
+    /// 执行 `f`，耗时超过慢操作阈值时记录日志
+    fn observe<T>(&self, op: &'static str, key_len: usize, f: impl FnOnce() -> T) -> T {
+        let threshold_nanos = self.slow_op_threshold_nanos.load(Ordering::Acquire);
+        if threshold_nanos == u64::MAX {
+            return f();
+        }
+
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let res = f();
+        let elapsed = start.elapsed();
+        if elapsed.as_nanos() > u128::from(threshold_nanos) {
+            warn_log!(
+                slow_op = op,
+                request_id = request_id,
+                key_len = key_len,
+                elapsed_us = elapsed.as_micros() as u64;
+                "慢操作 #{}: {} (键长度 {}) 耗时 {:?}", request_id, op, key_len, elapsed
+            );
+        }
+        res
+    }
+
+    /// 设置内存中常驻的原子计数器的上限，`None` 表示不限制（默认）。
+    ///
+    /// 超过上限时，最久未访问的计数器在持久化最新的值之后被移出内存，
+    /// 下次访问时透明地从磁盘重新加载。设置上限后，不在内存中的计数器
+    /// 总是先从磁盘加载，因此不需要预热。原子操作Worker由同一个数据库上的
+    /// 管理器共享，设置对所有管理器生效，并在下一次原子操作时开始生效
+    pub fn set_max_resident_counters(&self, max_resident: Option<usize>) {
+        self.atomic_worker.set_max_resident_counters(max_resident);
+    }
+
+    /// 当前内存中的原子计数器数量
+    pub fn resident_counter_count(&self) -> usize {
+        self.atomic_worker.resident_counter_count()
+    }
+
+    /// 原子计数器的总数：内存中的，加上被移出内存或尚未加载、只在磁盘上的。
+    /// 也包含在 `Db::stats` 中。
+    ///
+    /// `Config::atomic_worker` 设置了 `max_counters` 时，按
+    /// `CounterEviction::RejectNew` 处理的上限与这个数量比较
+    pub fn counter_cardinality(&self) -> usize {
+        self.atomic_worker.counter_stats().cardinality
+    }
+
+    /// 数据库Worker的获取请求和实际读取的统计，未启用数据库Worker模式时返回 `None`
+    pub fn database_worker_stats(&self) -> Option<DatabaseWorkerStats> {
+        self.database_worker.as_ref().map(|db_worker| db_worker.stats())
+    }
+
+    /// 暂停原子操作Worker和数据库Worker（如果启用），直到返回的guard被释放。
+    /// 用于测试Worker无响应时的行为
+    #[cfg(feature = "for-internal-testing-only")]
+    #[doc(hidden)]
+    pub fn pause_workers_for_testing(&self) -> WorkerPause {
+        WorkerPause {
+            _atomic: self.atomic_worker.pause(),
+            _database: self.database_worker.as_ref().map(|db_worker| db_worker.pause()),
         }
     }
 
@@ -301,4 +742,36 @@ impl HybridOperationsManager {
     pub fn db(&self) -> &Db<1024> {
         &self.db
     }
-}
\ No newline at end of file
+}
+
+/// `HybridOperationsManager::pause_workers_for_testing` 返回的guard，
+/// 释放后Worker继续处理操作
+#[cfg(feature = "for-internal-testing-only")]
+#[doc(hidden)]
+pub struct WorkerPause {
+    _atomic: parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()>,
+    _database: Option<parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, ()>>,
+}
+
+impl Clone for HybridOperationsManager {
+    /// 克隆的管理器共享原子操作Worker，并在原管理器启用了数据库Worker模式时
+    /// 共享同一个数据库Worker
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            atomic_worker: self.atomic_worker.clone(),
+            database_worker: self.database_worker.clone(),
+            coalesce_gets: AtomicBool::new(self.coalesce_gets.load(Ordering::Acquire)),
+            prioritize_reads: AtomicBool::new(self.prioritize_reads.load(Ordering::Acquire)),
+            slow_op_threshold_nanos: PortableAtomicU64::new(
+                self.slow_op_threshold_nanos.load(Ordering::Acquire),
+            ),
+        }
+    }
+}
+
+impl Drop for HybridOperationsManager {
+    fn drop(&mut self) {
+        self.disable_database_worker_mode();
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::BTreeSet;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::io;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crossbeam_queue::SegQueue;
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
 use fnv::FnvHashSet;
 use parking_lot::Mutex;
@@ -20,8 +22,8 @@ pub struct Allocator {
     ///
     /// A lock free queue of recently freed ids which uses when there is contention on `free_and_pending`.
     free_queue: SegQueue<u64>,
-    allocation_counter: AtomicU64,
-    free_counter: AtomicU64,
+    allocation_counter: PortableAtomicU64,
+    free_counter: PortableAtomicU64,
 }
 
 impl Allocator {
@@ -100,7 +102,25 @@ impl Allocator {
         }
     }
 
+    /// The number of ids that are currently allocated.
+    pub fn allocated_count(&self) -> u64 {
+        let mut free_and_tip = self.free_and_pending.lock();
+        while let Some(free_id) = self.free_queue.pop() {
+            free_and_tip.free_set.insert(free_id);
+        }
+
+        compact(&mut free_and_tip);
+
+        free_and_tip.next_to_allocate - free_and_tip.free_set.len() as u64
+    }
+
     pub fn allocate(&self) -> u64 {
+        self.try_allocate().unwrap()
+    }
+
+    /// Like `allocate`, but returns an error instead of panicking when every
+    /// id has been handed out.
+    pub fn try_allocate(&self) -> io::Result<u64> {
         self.allocation_counter.fetch_add(1, Ordering::Relaxed);
         let mut free_and_tip = self.free_and_pending.lock();
         while let Some(free_id) = self.free_queue.pop() {
@@ -112,11 +132,16 @@ impl Allocator {
         let pop_attempt = free_and_tip.free_set.pop_first();
 
         if let Some(id) = pop_attempt {
-            id
+            Ok(id)
+        } else if free_and_tip.next_to_allocate == u64::MAX {
+            Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "id space exhausted: all 2^64 - 1 ids are allocated",
+            ))
         } else {
             let ret = free_and_tip.next_to_allocate;
             free_and_tip.next_to_allocate += 1;
-            ret
+            Ok(ret)
         }
     }
 
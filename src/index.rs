@@ -0,0 +1,243 @@
+//! 二级索引
+//!
+//! `IndexedTree` 将一个基础树和一个索引树绑定在一起：每次通过它写入基础树时，
+//! 用户提供的提取函数从旧值和新值计算索引键，基础树的写入和索引树的增删通过
+//! `Db::apply_batches` 在同一个原子批次中完成，因此两个树永远不会出现只写入了
+//! 一半的状态，崩溃后也是如此。
+//!
+//! 索引树中每个条目的键是 `[索引键长度（4字节大端序）][索引键][基础树的键]`，
+//! 值为空。长度前缀使不同长度的索引键互不混淆，同一个索引键可以对应多个基础键。
+//!
+//! 直接写入基础树或索引树会绕过索引维护，之后需要调用 `IndexedTree::rebuild`。
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use inline_array::InlineArray;
+use parking_lot::RwLock;
+
+use crate::{Batch, BatchGuardError, RetryPolicy, Tree};
+
+/// `IndexedTree::rebuild` 每次写入索引树的条目数
+const REBUILD_CHUNK: usize = 1024;
+
+type Extract = dyn Fn(&[u8], &[u8]) -> Vec<Vec<u8>> + Send + Sync;
+
+/// `IndexedTree::rebuild` 的进度，每写入一批索引条目后报告一次
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildProgress {
+    /// 已扫描的基础树条目数
+    pub entries_scanned: u64,
+    /// 已写入的索引条目数
+    pub index_entries_written: u64,
+}
+
+/// 自动维护一个二级索引的树
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// use melange_db::index::IndexedTree;
+///
+/// // 值的格式为 "邮箱,名字"，按邮箱建立索引
+/// let users = IndexedTree::new(
+///     db.open_tree("users")?,
+///     db.open_tree("users_by_email")?,
+///     |_key, value| vec![value.split(|b| *b == b',').next().unwrap().to_vec()],
+/// );
+///
+/// users.insert(b"user:1", b"alice@example.com,Alice".as_slice())?;
+/// users.insert(b"user:2", b"bob@example.com,Bob".as_slice())?;
+/// users.insert(b"user:1", b"alice@example.org,Alice".as_slice())?;
+///
+/// let found = users.get_by_index(b"alice@example.org")?;
+/// assert_eq!(&*found[0].0, b"user:1");
+/// assert!(users.get_by_index(b"alice@example.com")?.is_empty());
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct IndexedTree<const LEAF_FANOUT: usize = 1024> {
+    base: Tree<LEAF_FANOUT>,
+    index: Tree<LEAF_FANOUT>,
+    extract: Arc<Extract>,
+    // writes hold it shared, `rebuild` exclusively
+    rebuild_lock: Arc<RwLock<()>>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl<const LEAF_FANOUT: usize> fmt::Debug for IndexedTree<LEAF_FANOUT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexedTree").finish_non_exhaustive()
+    }
+}
+
+/// 索引条目的键：长度前缀、索引键、基础键
+fn entry_key(index_key: &[u8], key: &[u8]) -> InlineArray {
+    let mut entry = entry_prefix(index_key);
+    entry.extend_from_slice(key);
+    entry.into()
+}
+
+fn entry_prefix(index_key: &[u8]) -> Vec<u8> {
+    let len = u32::try_from(index_key.len()).expect("索引键的长度超过u32");
+    let mut prefix = Vec::with_capacity(4 + index_key.len());
+    prefix.extend_from_slice(&len.to_be_bytes());
+    prefix.extend_from_slice(index_key);
+    prefix
+}
+
+impl<const LEAF_FANOUT: usize> IndexedTree<LEAF_FANOUT> {
+    /// 创建一个索引包装。`base` 和 `index` 必须是同一个数据库中的两个不同的树。
+    /// `extract` 从基础树的键和值计算这个条目的索引键，可以返回任意个索引键，
+    /// 重复的索引键只记录一次
+    pub fn new<F>(base: Tree<LEAF_FANOUT>, index: Tree<LEAF_FANOUT>, extract: F) -> IndexedTree<LEAF_FANOUT>
+    where
+        F: Fn(&[u8], &[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        IndexedTree {
+            base,
+            index,
+            extract: Arc::new(extract),
+            rebuild_lock: Arc::default(),
+            retry_policy: None,
+        }
+    }
+
+    /// 写入的守卫条件因并发写入同一个键而失败时按 `policy` 等待后重试，超出重试次数时
+    /// 返回 `ContentionExceeded`。默认不等待，一直重试到成功。
+    /// 每次失败都计入基础树的 `TreeStats::cas_conflicts`
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> IndexedTree<LEAF_FANOUT> {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// 基础树
+    pub fn base(&self) -> &Tree<LEAF_FANOUT> {
+        &self.base
+    }
+
+    /// 索引树
+    pub fn index(&self) -> &Tree<LEAF_FANOUT> {
+        &self.index
+    }
+
+    fn index_keys(&self, key: &[u8], value: Option<&[u8]>) -> BTreeSet<Vec<u8>> {
+        value.map(|value| (self.extract)(key, value).into_iter().collect()).unwrap_or_default()
+    }
+
+    /// 见 [`Tree::get`]
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.base.get(key)
+    }
+
+    /// 写入基础树，同时删除旧值不再对应的索引条目并添加新值的索引条目。
+    /// 返回旧值
+    pub fn insert<K, V>(&self, key: K, value: V) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        self.write(key.as_ref(), Some(value.into()))
+    }
+
+    /// 从基础树删除，同时删除它的索引条目。返回旧值
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.write(key.as_ref(), None)
+    }
+
+    fn write(&self, key: &[u8], value: Option<InlineArray>) -> io::Result<Option<InlineArray>> {
+        let _rebuild_guard = self.rebuild_lock.read();
+        let new_index_keys = self.index_keys(key, value.as_deref());
+        let mut retries = self.base.retries(self.retry_policy);
+
+        loop {
+            let old = self.base.get(key)?;
+            let old_index_keys = self.index_keys(key, old.as_deref());
+
+            // the guard makes the batch fail if the key changed after the
+            // old index keys were computed from it
+            let mut base_batch = Batch::default();
+            base_batch.guard(key, old.as_ref());
+            match &value {
+                Some(value) => base_batch.insert(key, value.clone()),
+                None => base_batch.remove(key),
+            }
+
+            let mut index_batch = Batch::default();
+            for stale in old_index_keys.difference(&new_index_keys) {
+                index_batch.remove(entry_key(stale, key));
+            }
+            for added in new_index_keys.difference(&old_index_keys) {
+                index_batch.insert(entry_key(added, key), InlineArray::default());
+            }
+
+            let batches = vec![(&self.base, base_batch), (&self.index, index_batch)];
+            match self.base.apply_multi_tree_batch(batches) {
+                Ok(()) => return Ok(old),
+                Err(e) if BatchGuardError::from_io_error(&e).is_some() => retries.conflict(key)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 返回索引键为 `index_key` 的所有基础树条目，按基础树的键排序。
+    ///
+    /// 索引条目和基础树的值分别读取，读取期间被并发修改为不再对应 `index_key`
+    /// 的条目不会被返回
+    pub fn get_by_index<I: AsRef<[u8]>>(&self, index_key: I) -> io::Result<Vec<(InlineArray, InlineArray)>> {
+        let index_key = index_key.as_ref();
+        let prefix = entry_prefix(index_key);
+
+        let mut ret = vec![];
+        for entry_res in self.index.scan_prefix(&prefix).keys() {
+            let entry = entry_res?;
+            let key = &entry[prefix.len()..];
+            let Some(value) = self.base.get(key)? else {
+                continue;
+            };
+            if (self.extract)(key, &value).iter().any(|k| k[..] == *index_key) {
+                ret.push((InlineArray::from(key), value));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// 清空索引树，然后扫描基础树重新生成所有索引条目。每写入一批条目后
+    /// 调用一次 `progress`，返回最终的进度。
+    ///
+    /// 重建期间通过这个 `IndexedTree`（及其克隆）的写入会等待重建完成。重建
+    /// 不是原子的，中途失败或崩溃后需要再次调用
+    pub fn rebuild<F: FnMut(RebuildProgress)>(&self, mut progress: F) -> io::Result<RebuildProgress> {
+        let _rebuild_guard = self.rebuild_lock.write();
+
+        self.index.clear()?;
+
+        let mut report = RebuildProgress::default();
+        let mut batch = Batch::default();
+        let mut batch_len = 0;
+        for kv_res in self.base.iter() {
+            let (key, value) = kv_res?;
+            report.entries_scanned += 1;
+            for index_key in self.index_keys(&key, Some(&value)) {
+                batch.insert(entry_key(&index_key, &key), InlineArray::default());
+                batch_len += 1;
+            }
+            if batch_len >= REBUILD_CHUNK {
+                self.index.apply_batch(std::mem::take(&mut batch))?;
+                report.index_entries_written += batch_len as u64;
+                batch_len = 0;
+                progress(report);
+            }
+        }
+        self.index.apply_batch(batch)?;
+        report.index_entries_written += batch_len as u64;
+        progress(report);
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,177 @@
+//! 整数键编码
+//!
+//! 树中的键按字节的字典序排序。直接使用 `u64::to_le_bytes` 或
+//! 原生字节序作为键时，字典序与数值顺序不一致，范围扫描会静默地返回错误的结果。
+//! 本模块提供的类型将整数编码为8字节的大端序表示，保证字节序与数值顺序一致：
+//!
+//! - `BigEndianU64` 直接使用大端序
+//! - `BigEndianI64` 在大端序的基础上翻转符号位，使负数排在正数之前。
+//!   注意zig-zag编码（0, -1, 1, -2, ...）并不保持数值顺序，因此这里不使用它
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> std::io::Result<()> {
+//! use melange_db::{BigEndianI64, Config};
+//!
+//! let db = Config::tmp()?.open::<1024>()?;
+//!
+//! for ts in [30_i64, -5, 10, 0] {
+//!     db.insert(BigEndianI64(ts).to_bytes(), b"event".as_slice())?;
+//! }
+//!
+//! // 范围扫描 [-5, 10)
+//! let keys: Vec<i64> = db
+//!     .range(BigEndianI64(-5).to_bytes()..BigEndianI64(10).to_bytes())
+//!     .keys()
+//!     .map(|k| BigEndianI64::try_from(k?).map(|k| k.0))
+//!     .collect::<std::io::Result<_>>()?;
+//!
+//! assert_eq!(keys, vec![-5, 0]);
+//! # Ok(()) }
+//! ```
+
+use std::io;
+
+use crate::InlineArray;
+
+/// 按数值顺序排序的 `u64` 键，编码为8字节大端序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BigEndianU64(pub u64);
+
+/// 按数值顺序排序的 `i64` 键，编码为翻转符号位后的8字节大端序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BigEndianI64(pub i64);
+
+impl BigEndianU64 {
+    /// 编码后的字节长度
+    pub const ENCODED_LEN: usize = 8;
+
+    /// 编码为字节，字节的字典序与数值顺序一致
+    pub const fn to_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// 从 `to_bytes` 的结果解码
+    pub const fn from_bytes(bytes: [u8; 8]) -> BigEndianU64 {
+        BigEndianU64(u64::from_be_bytes(bytes))
+    }
+}
+
+impl BigEndianI64 {
+    /// 编码后的字节长度
+    pub const ENCODED_LEN: usize = 8;
+
+    /// 编码为字节，字节的字典序与数值顺序一致
+    pub const fn to_bytes(self) -> [u8; 8] {
+        ((self.0 as u64) ^ (1 << 63)).to_be_bytes()
+    }
+
+    /// 从 `to_bytes` 的结果解码
+    pub const fn from_bytes(bytes: [u8; 8]) -> BigEndianI64 {
+        BigEndianI64((u64::from_be_bytes(bytes) ^ (1 << 63)) as i64)
+    }
+}
+
+/// 取出8字节的编码，长度不符时返回 `InvalidData` 错误
+fn encoded_bytes(bytes: &[u8], type_name: &str) -> io::Result<[u8; 8]> {
+    bytes.try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} 的编码必须为8字节，实际为 {} 字节", type_name, bytes.len()),
+        )
+    })
+}
+
+macro_rules! impl_integer_key {
+    ($name:ident, $int:ty) => {
+        impl From<$int> for $name {
+            fn from(i: $int) -> $name {
+                $name(i)
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(key: $name) -> $int {
+                key.0
+            }
+        }
+
+        impl From<$name> for InlineArray {
+            fn from(key: $name) -> InlineArray {
+                InlineArray::from(&key.to_bytes()[..])
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = io::Error;
+
+            fn try_from(bytes: &[u8]) -> io::Result<$name> {
+                Ok($name::from_bytes(encoded_bytes(bytes, stringify!($name))?))
+            }
+        }
+
+        impl TryFrom<&InlineArray> for $name {
+            type Error = io::Error;
+
+            fn try_from(bytes: &InlineArray) -> io::Result<$name> {
+                $name::try_from(bytes.as_ref())
+            }
+        }
+
+        impl TryFrom<InlineArray> for $name {
+            type Error = io::Error;
+
+            fn try_from(bytes: InlineArray) -> io::Result<$name> {
+                $name::try_from(bytes.as_ref())
+            }
+        }
+    };
+}
+
+impl_integer_key!(BigEndianU64, u64);
+impl_integer_key!(BigEndianI64, i64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u64_roundtrip_and_order() {
+        let values = [0, 1, 255, 256, u32::MAX as u64, u64::MAX - 1, u64::MAX];
+
+        for window in values.windows(2) {
+            let a = BigEndianU64(window[0]).to_bytes();
+            let b = BigEndianU64(window[1]).to_bytes();
+            assert!(a < b, "{:?} >= {:?}", window[0], window[1]);
+        }
+
+        for v in values {
+            let encoded = InlineArray::from(BigEndianU64(v));
+            assert_eq!(BigEndianU64::try_from(&encoded).unwrap().0, v);
+        }
+    }
+
+    #[test]
+    fn test_i64_roundtrip_and_order() {
+        let values = [i64::MIN, i64::MIN + 1, -256, -1, 0, 1, 256, i64::MAX];
+
+        for window in values.windows(2) {
+            let a = BigEndianI64(window[0]).to_bytes();
+            let b = BigEndianI64(window[1]).to_bytes();
+            assert!(a < b, "{:?} >= {:?}", window[0], window[1]);
+        }
+
+        for v in values {
+            let encoded = InlineArray::from(BigEndianI64(v));
+            assert_eq!(BigEndianI64::try_from(encoded).unwrap().0, v);
+        }
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let err = BigEndianU64::try_from(&b"short"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(BigEndianI64::try_from(&[0u8; 9][..]).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! 复合键编码
+//!
+//! `KeyEncoder` 将多个组成部分编码为一个键，编码结果的字节字典序与
+//! 组成部分按元组比较的顺序一致，因此可以直接用于范围扫描和前缀扫描。
+//!
+//! 每个组成部分以一个类型标记字节开头：
+//!
+//! - 字符串和字节串：内容中的 `0x00` 转义为 `0x00 0xFF`，并以 `0x00 0x01` 结尾。
+//!   转义保证了较短的字符串排在以它为前缀的较长字符串之前
+//! - `u64`：8字节大端序，见 `BigEndianU64`
+//! - `i64`：翻转符号位后的8字节大端序，见 `BigEndianI64`
+//!
+//! 类型标记使编码可以自描述，`KeyDecoder` 在类型不匹配时返回错误，
+//! `prefix` 可以在不知道键结构的情况下截取前N个组成部分。
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> std::io::Result<()> {
+//! use melange_db::Config;
+//! use melange_db::keys::{KeyDecoder, KeyEncoder, prefix};
+//!
+//! let db = Config::tmp()?.open::<1024>()?;
+//!
+//! for (user_id, ts) in [(7, 300), (7, -20), (12, 5), (7, 1000)] {
+//!     let key = KeyEncoder::new().str("user").u64(user_id).i64(ts).finish();
+//!     db.insert(key, b"event".as_slice())?;
+//! }
+//!
+//! // 扫描用户7的所有事件，按时间戳排序
+//! let user_prefix = KeyEncoder::new().str("user").u64(7).finish();
+//! let mut timestamps = vec![];
+//! for key in db.scan_prefix(&user_prefix).keys() {
+//!     let key = key?;
+//!     assert_eq!(prefix(&key, 2)?, &user_prefix[..]);
+//!
+//!     let mut decoder = KeyDecoder::new(&key);
+//!     assert_eq!(decoder.str()?, "user");
+//!     assert_eq!(decoder.u64()?, 7);
+//!     timestamps.push(decoder.i64()?);
+//!     decoder.finish()?;
+//! }
+//!
+//! assert_eq!(timestamps, vec![-20, 300, 1000]);
+//! # Ok(()) }
+//! ```
+
+use std::io;
+
+use crate::{BigEndianI64, BigEndianU64};
+
+const TAG_BYTES: u8 = 0x01;
+const TAG_STR: u8 = 0x02;
+const TAG_U64: u8 = 0x10;
+const TAG_I64: u8 = 0x11;
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// 复合键编码器
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyEncoder {
+    buf: Vec<u8>,
+}
+
+impl KeyEncoder {
+    /// 创建一个空的编码器
+    pub fn new() -> KeyEncoder {
+        KeyEncoder::default()
+    }
+
+    /// 追加一个字符串组成部分
+    pub fn str(self, s: &str) -> KeyEncoder {
+        self.escaped(TAG_STR, s.as_bytes())
+    }
+
+    /// 追加一个字节串组成部分
+    pub fn bytes(self, bytes: &[u8]) -> KeyEncoder {
+        self.escaped(TAG_BYTES, bytes)
+    }
+
+    /// 追加一个 `u64` 组成部分
+    pub fn u64(mut self, i: u64) -> KeyEncoder {
+        self.buf.push(TAG_U64);
+        self.buf.extend_from_slice(&BigEndianU64(i).to_bytes());
+        self
+    }
+
+    /// 追加一个 `i64` 组成部分
+    pub fn i64(mut self, i: i64) -> KeyEncoder {
+        self.buf.push(TAG_I64);
+        self.buf.extend_from_slice(&BigEndianI64(i).to_bytes());
+        self
+    }
+
+    /// 返回编码后的键
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn escaped(mut self, tag: u8, bytes: &[u8]) -> KeyEncoder {
+        self.buf.reserve(bytes.len() + 3);
+        self.buf.push(tag);
+        for byte in bytes {
+            self.buf.push(*byte);
+            if *byte == ESCAPE {
+                self.buf.push(ESCAPED_ZERO);
+            }
+        }
+        self.buf.push(ESCAPE);
+        self.buf.push(TERMINATOR);
+        self
+    }
+}
+
+/// 复合键解码器，按编码时的顺序依次读取各个组成部分
+#[derive(Debug, Clone)]
+pub struct KeyDecoder<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> KeyDecoder<'a> {
+    /// 为一个由 `KeyEncoder` 编码的键创建解码器
+    pub fn new(key: &'a [u8]) -> KeyDecoder<'a> {
+        KeyDecoder { remaining: key }
+    }
+
+    /// 是否已读取所有组成部分
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
+    /// 读取一个字符串组成部分
+    pub fn str(&mut self) -> io::Result<String> {
+        let bytes = self.escaped(TAG_STR)?;
+        String::from_utf8(bytes)
+            .map_err(|e| invalid_data(format!("字符串组成部分不是有效的UTF-8: {}", e)))
+    }
+
+    /// 读取一个字节串组成部分
+    pub fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        self.escaped(TAG_BYTES)
+    }
+
+    /// 读取一个 `u64` 组成部分
+    pub fn u64(&mut self) -> io::Result<u64> {
+        self.fixed(TAG_U64).map(|bytes| BigEndianU64::from_bytes(bytes).0)
+    }
+
+    /// 读取一个 `i64` 组成部分
+    pub fn i64(&mut self) -> io::Result<i64> {
+        self.fixed(TAG_I64).map(|bytes| BigEndianI64::from_bytes(bytes).0)
+    }
+
+    /// 确认所有组成部分都已读取
+    pub fn finish(self) -> io::Result<()> {
+        if self.remaining.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_data(format!(
+                "键中还有 {} 个字节未被解码",
+                self.remaining.len()
+            )))
+        }
+    }
+
+    fn tag(&mut self, expected: u8) -> io::Result<()> {
+        match self.remaining.split_first() {
+            Some((tag, rest)) if *tag == expected => {
+                self.remaining = rest;
+                Ok(())
+            }
+            Some((tag, _)) => Err(invalid_data(format!(
+                "组成部分类型不匹配: 期望 {:#04x}, 实际 {:#04x}",
+                expected, tag
+            ))),
+            None => Err(invalid_data("键中没有更多的组成部分".into())),
+        }
+    }
+
+    fn fixed(&mut self, tag: u8) -> io::Result<[u8; 8]> {
+        self.tag(tag)?;
+        if self.remaining.len() < 8 {
+            return Err(invalid_data("整数组成部分被截断".into()));
+        }
+        let (bytes, rest) = self.remaining.split_at(8);
+        self.remaining = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn escaped(&mut self, tag: u8) -> io::Result<Vec<u8>> {
+        self.tag(tag)?;
+        let (bytes, consumed) = unescape(self.remaining)?;
+        self.remaining = &self.remaining[consumed..];
+        Ok(bytes)
+    }
+}
+
+/// 解码一个转义的组成部分，返回内容和消耗的字节数（包括结尾标记）
+fn unescape(encoded: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    let mut ret = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] != ESCAPE {
+            ret.push(encoded[i]);
+            i += 1;
+            continue;
+        }
+
+        match encoded.get(i + 1) {
+            Some(&ESCAPED_ZERO) => ret.push(ESCAPE),
+            Some(&TERMINATOR) => return Ok((ret, i + 2)),
+            _ => return Err(invalid_data("无效的字符串转义序列".into())),
+        }
+        i += 2;
+    }
+
+    Err(invalid_data("字符串组成部分缺少结尾标记".into()))
+}
+
+/// 截取键的前 `components` 个组成部分，结果可以直接传给 `Tree::scan_prefix`。
+/// 键的组成部分少于 `components` 个时返回错误
+pub fn prefix(key: &[u8], components: usize) -> io::Result<&[u8]> {
+    let mut offset = 0;
+    for _ in 0..components {
+        let tag = *key.get(offset).ok_or_else(|| {
+            invalid_data(format!("键的组成部分少于 {} 个", components))
+        })?;
+        offset += 1;
+
+        match tag {
+            TAG_BYTES | TAG_STR => offset += unescape(&key[offset..])?.1,
+            TAG_U64 | TAG_I64 => {
+                if key.len() < offset + 8 {
+                    return Err(invalid_data("整数组成部分被截断".into()));
+                }
+                offset += 8;
+            }
+            other => {
+                return Err(invalid_data(format!(
+                    "未知的组成部分类型 {:#04x}",
+                    other
+                )));
+            }
+        }
+    }
+
+    Ok(&key[..offset])
+}
@@ -1,5 +1,9 @@
+use std::cmp::Ordering;
+
 use crate::*;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
+use crate::blob_store::{BLOB_MARKER, BlobStore};
+use crate::compression_dictionary::CompressionDictionaries;
 
 /// 增量序列化变更跟踪结构
 /// 用于跟踪leaf节点自上次完整序列化以来的变更
@@ -72,6 +76,103 @@ impl IncrementalChanges {
     }
 }
 
+/// 按值压缩的叶子节点的标记字节，之后是未压缩的序列化叶子节点。值已经单独
+/// 压缩过，整个叶子节点不再压缩一次
+const VALUE_COMPRESSED_MARKER: u8 = 0xFD;
+
+/// 按值压缩的叶子节点中一个值的存储方式
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum StoredValue {
+    Uncompressed(InlineArray),
+    Zstd(Vec<u8>),
+    Lz4(Vec<u8>),
+}
+
+impl StoredValue {
+    fn compress(
+        value: &InlineArray,
+        algorithm: CompressionAlgorithm,
+        threshold: usize,
+        zstd_compression_level: i32,
+    ) -> StoredValue {
+        if value.len() < threshold {
+            return StoredValue::Uncompressed(value.clone());
+        }
+
+        let stored = match algorithm {
+            CompressionAlgorithm::Zstd => StoredValue::Zstd(
+                zstd::stream::encode_all(value.as_ref(), zstd_compression_level).unwrap(),
+            ),
+            CompressionAlgorithm::Lz4 => {
+                StoredValue::Lz4(lz4_flex::compress_prepend_size(value))
+            }
+            CompressionAlgorithm::None => {
+                return StoredValue::Uncompressed(value.clone());
+            }
+        };
+
+        // 压缩后没有变小的值原样存储
+        match &stored {
+            StoredValue::Zstd(bytes) | StoredValue::Lz4(bytes) if bytes.len() < value.len() => {
+                stored
+            }
+            _ => StoredValue::Uncompressed(value.clone()),
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        !matches!(self, StoredValue::Uncompressed(_))
+    }
+
+    fn decompress(self) -> std::io::Result<InlineArray> {
+        match self {
+            StoredValue::Uncompressed(value) => Ok(value),
+            StoredValue::Zstd(bytes) => Ok(zstd::stream::decode_all(bytes.as_slice())?.into()),
+            StoredValue::Lz4(bytes) => lz4_flex::decompress_size_prepended(&bytes)
+                .map(InlineArray::from)
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("无法解压lz4压缩的值: {}", e),
+                    )
+                }),
+        }
+    }
+}
+
+/// 按值压缩的叶子节点的序列化格式
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ValueCompressedLeaf {
+    lo: InlineArray,
+    hi: Option<InlineArray>,
+    prefix_length: usize,
+    mutation_count: u64,
+    entries: Vec<(InlineArray, StoredValue)>,
+}
+
+/// 值去重的叶子节点的标记字节，之后是一个完整序列化的叶子节点帧
+const VALUE_DEDUP_MARKER: u8 = 0xFC;
+
+/// 值去重的叶子节点中一个条目的值
+#[derive(serde::Serialize, serde::Deserialize)]
+enum DedupValue {
+    /// 小于最小去重大小的值，原样存储
+    Inline(StoredValue),
+    /// 引用 `ValueDedupLeaf::shared` 中的值
+    Shared(u32),
+}
+
+/// 值去重的叶子节点的序列化格式：每个不同的值在 `shared` 中只存储一次
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ValueDedupLeaf {
+    lo: InlineArray,
+    hi: Option<InlineArray>,
+    prefix_length: usize,
+    mutation_count: u64,
+    shared: Vec<StoredValue>,
+    entries: Vec<(InlineArray, DedupValue)>,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct Leaf<const LEAF_FANOUT: usize> {
     pub lo: InlineArray,
@@ -97,6 +198,13 @@ pub(crate) struct Leaf<const LEAF_FANOUT: usize> {
     /// 是否启用增量序列化
     #[serde(skip)]
     pub incremental_serialization_enabled: bool,
+    /// 自从叶子节点被加载以来写入的键（完整的键）所在的flush epoch，不持久化
+    #[serde(skip)]
+    write_epochs: BTreeMap<InlineArray, FlushEpoch>,
+    /// 叶子节点从磁盘加载时的flush epoch，之前写入的键的epoch不超过它。
+    /// `None` 表示未知
+    #[serde(skip)]
+    pub loaded_epoch: Option<FlushEpoch>,
 }
 
 impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
@@ -117,6 +225,8 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
             incremental_changes: None,
             last_serialized_version: 0,
             incremental_serialization_enabled: false,
+            write_epochs: BTreeMap::new(),
+            loaded_epoch: None,
         }
     }
 
@@ -160,41 +270,165 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
         &self.lo[..self.prefix_length]
     }
 
-    pub(crate) fn get(&self, key: &[u8]) -> Option<&InlineArray> {
+    fn get_stored(&self, key: &[u8]) -> Option<&InlineArray> {
         assert!(self.deleted.is_none());
         assert!(key.starts_with(self.prefix()));
         let prefixed_key = &key[self.prefix_length..];
-        self.data.get(prefixed_key)
+        let index = SimdComparator::binary_search_by_index(
+            self.data.len(),
+            |i| &self.data.get_index(i).unwrap().0,
+            prefixed_key,
+        )
+        .ok()?;
+        self.data.get_index(index).map(|(_, value)| value)
+    }
+
+    /// 在条目中查找 `key`，`Ok` 为键相等的条目，`Err` 为第一个键大于它的条目。
+    /// `key` 可以在叶子节点的范围之外
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let prefix = self.prefix();
+        match key.strip_prefix(prefix) {
+            Some(prefixed_key) => SimdComparator::binary_search_by_index(
+                self.data.len(),
+                |i| &self.data.get_index(i).unwrap().0,
+                prefixed_key,
+            ),
+            // 不以前缀开头的键小于或大于所有条目
+            None if key < prefix => Err(0),
+            None => Err(self.data.len()),
+        }
+    }
+
+    /// 第 `index` 个条目的完整键和叶子节点中存储的值
+    fn stored_at(&self, index: usize) -> Option<(InlineArray, InlineArray)> {
+        let (k, v) = self.data.get_index(index)?;
+        let prefix = self.prefix();
+        let mut unshifted_key = Vec::with_capacity(prefix.len() + k.len());
+        unshifted_key.extend_from_slice(prefix);
+        unshifted_key.extend_from_slice(k);
+        Some((unshifted_key.into(), v.clone()))
+    }
+
+    /// 键大于等于 `key` 的第一个条目，返回完整的键和叶子节点中存储的值
+    pub(crate) fn ceiling_stored(&self, key: &[u8]) -> Option<(InlineArray, InlineArray)> {
+        let (Ok(index) | Err(index)) = self.search(key);
+        self.stored_at(index)
+    }
+
+    /// 键小于等于 `key` 的最后一个条目，返回完整的键和叶子节点中存储的值
+    pub(crate) fn floor_stored(&self, key: &[u8]) -> Option<(InlineArray, InlineArray)> {
+        let index = match self.search(key) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        self.stored_at(index)
+    }
+
+    /// 读取键的值，单独存储的值从 `blobs` 读取
+    pub(crate) fn get(
+        &self,
+        key: &[u8],
+        blobs: &BlobStore,
+    ) -> std::io::Result<Option<InlineArray>> {
+        self.get_stored(key).map(|stored| blobs.load(stored)).transpose()
+    }
+
+    /// 读取键的值和它在磁盘上占用的字节数，参见 `BlobStore::stored_len`
+    pub(crate) fn get_with_stored_len(
+        &self,
+        key: &[u8],
+        blobs: &BlobStore,
+    ) -> std::io::Result<Option<(InlineArray, usize)>> {
+        self.get_stored(key)
+            .map(|stored| Ok((blobs.load(stored)?, blobs.stored_len(stored))))
+            .transpose()
+    }
+
+    /// 以借用的值调用 `f`，内联的值不被复制
+    pub(crate) fn get_with<R>(
+        &self,
+        key: &[u8],
+        blobs: &BlobStore,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> std::io::Result<Option<R>> {
+        self.get_stored(key).map(|stored| blobs.with_value(stored, f)).transpose()
+    }
+
+    /// 叶子节点中是否有这个键，不读取值
+    pub(crate) fn contains_key(&self, key: &[u8]) -> bool {
+        self.get_stored(key).is_some()
     }
 
+    /// 键的值在叶子节点中占用的字节数，单独存储的值只计算引用的大小
+    pub(crate) fn stored_value_len(&self, key: &[u8]) -> Option<usize> {
+        self.get_stored(key).map(|stored| stored.len())
+    }
+
+    /// 键最后一次被写入的flush epoch的上界。自从叶子节点被加载以来写入的键
+    /// 是准确的，其余的键是加载时的epoch，未知时为 `current_epoch`
+    pub(crate) fn write_epoch(&self, key: &[u8], current_epoch: FlushEpoch) -> FlushEpoch {
+        self.write_epochs
+            .get(key)
+            .copied()
+            .or(self.loaded_epoch)
+            .unwrap_or(current_epoch)
+    }
+
+    /// 插入键值对并返回旧的值，值被修改时记录 `epoch` 为键的写入epoch。
+    /// 大于 `Config::inline_value_threshold` 的值写入单独的blob，被覆盖的
+    /// blob在包含这次修改的flush完成后删除
     pub(crate) fn insert(
         &mut self,
         key: InlineArray,
         value: InlineArray,
-    ) -> Option<InlineArray> {
+        epoch: FlushEpoch,
+        blobs: &BlobStore,
+    ) -> std::io::Result<Option<InlineArray>> {
         assert!(self.deleted.is_none());
         assert!(key.starts_with(self.prefix()));
-        let prefixed_key = key[self.prefix_length..].into();
 
-        let old_value = self.data.insert(prefixed_key, value.clone());
+        // 先读取旧的值，读取失败时叶子节点不被修改
+        let old_value = self.get(&key, blobs)?;
+        if old_value.as_ref() == Some(&value) {
+            // 调用方不会把未改变的叶子节点标记为脏，保留已经持久化的引用
+            return Ok(old_value);
+        }
+        let stored = blobs.store(value)?;
+
+        let prefixed_key = key[self.prefix_length..].into();
+        if let Some(old_stored) = self.data.insert(prefixed_key, stored.clone()) {
+            blobs.release(&old_stored);
+        }
 
         // 跟踪增量变更
         if self.incremental_serialization_enabled {
             if let Some(changes) = &mut self.incremental_changes {
-                changes.add_insert(key, value);
+                changes.add_insert(key.clone(), stored);
             }
         }
 
-        old_value
+        self.write_epochs.insert(key, epoch);
+
+        Ok(old_value)
     }
 
-    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<InlineArray> {
+    pub(crate) fn remove(
+        &mut self,
+        key: &[u8],
+        blobs: &BlobStore,
+    ) -> std::io::Result<Option<InlineArray>> {
         assert!(self.deleted.is_none());
         let prefix = self.prefix();
         assert!(key.starts_with(prefix));
-        let partial_key = &key[self.prefix_length..];
 
-        let old_value = self.data.remove(partial_key);
+        let old_value = self.get(key, blobs)?;
+
+        let partial_key = &key[self.prefix_length..];
+        if let Some(old_stored) = self.data.remove(partial_key) {
+            blobs.release(&old_stored);
+        }
+        self.write_epochs.remove(key);
 
         // 跟踪增量变更
         if self.incremental_serialization_enabled {
@@ -203,11 +437,26 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
             }
         }
 
-        old_value
+        Ok(old_value)
+    }
+
+    /// 释放叶子节点中所有单独存储的值，用于清除或删除整个叶子节点
+    pub(crate) fn release_values(&self, blobs: &BlobStore) {
+        for (_k, stored) in self.data.iter() {
+            blobs.release(stored);
+        }
     }
 
     pub(crate) fn merge_from(&mut self, other: &mut Self) {
-        assert!(self.is_empty());
+        self.write_epochs.append(&mut other.write_epochs);
+        self.loaded_epoch = match (self.loaded_epoch, other.loaded_epoch) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+
+        if !self.is_empty() {
+            return self.merge_non_empty_from(other);
+        }
 
         self.hi = other.hi.clone();
 
@@ -251,15 +500,138 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
 
         #[cfg(feature = "for-internal-testing-only")]
         assert_eq!(
-            self.iter().collect::<Vec<_>>(),
-            other.iter().collect::<Vec<_>>(),
+            self.iter_stored().collect::<Vec<_>>(),
+            other.iter_stored().collect::<Vec<_>>(),
             "self: {:#?} \n other: {:#?}\n",
             self,
             other
         );
     }
 
-    pub(crate) fn iter(
+    /// Merges the right sibling into a leaf that still holds items, which
+    /// requires re-keying our own items under the (possibly shorter) prefix
+    /// shared with the sibling's high key.
+    fn merge_non_empty_from(&mut self, other: &mut Self) {
+        let original_len = self.data.len() + other.data.len();
+        assert!(original_len <= LEAF_FANOUT);
+
+        let items: Vec<(InlineArray, InlineArray)> =
+            self.iter_stored().chain(other.iter_stored()).collect();
+
+        self.hi = other.hi.clone();
+
+        self.prefix_length = if let Some(hi) = &self.hi {
+            self.lo.iter().zip(hi.iter()).take_while(|(l, r)| l == r).count()
+        } else {
+            0
+        };
+
+        self.data = stack_map::StackMap::new();
+        for (k, v) in items {
+            self.data.insert(k[self.prefix_length..].into(), v);
+        }
+
+        assert_eq!(self.data.len(), original_len);
+
+        self.set_in_memory_size();
+    }
+
+    pub(crate) const fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// 所有完整的键和叶子节点中存储的值的字节数，单独存储的值只计算引用
+    pub(crate) fn content_bytes(&self) -> usize {
+        self.data
+            .iter()
+            .map(|(k, v)| self.prefix_length + k.len() + v.len())
+            .sum()
+    }
+
+    /// 叶子节点中是否有以 `prefix` 开头的键，不访问值
+    pub(crate) fn contains_prefix(&self, prefix: &[u8]) -> bool {
+        let leaf_prefix = self.prefix();
+        if leaf_prefix.starts_with(prefix) {
+            // 所有键都以 `prefix` 开头
+            return !self.is_empty();
+        }
+
+        let Some(relative) = prefix.strip_prefix(leaf_prefix) else {
+            return false;
+        };
+
+        self.data
+            .iter()
+            .find(|(k, _)| &**k >= relative)
+            .is_some_and(|(k, _)| k.starts_with(relative))
+    }
+
+    /// 查找按升序排列（可以重复）的多个键的值，结果与 `keys` 一一对应。
+    /// 键与条目归并查找，每次用 `SimdComparator::batch_compare` 比较一个键
+    /// 与接下来的若干个条目
+    pub(crate) fn get_sorted(
+        &self,
+        keys: &[&[u8]],
+        blobs: &BlobStore,
+    ) -> std::io::Result<Vec<Option<InlineArray>>> {
+        const WINDOW: usize = 8;
+
+        assert!(self.deleted.is_none());
+        let prefix = self.prefix();
+        let entries: Vec<&[u8]> = self.data.iter().map(|(k, _)| &**k).collect();
+
+        let mut cursor = 0;
+        keys.iter()
+            .map(|key| {
+                assert!(key.starts_with(prefix));
+                let key = &key[self.prefix_length..];
+                while cursor < entries.len() {
+                    let window = &entries[cursor..(cursor + WINDOW).min(entries.len())];
+                    let orderings = SimdComparator::batch_compare(key, window);
+                    match orderings.iter().position(|o| *o != Ordering::Greater) {
+                        Some(position) => {
+                            cursor += position;
+                            if orderings[position] != Ordering::Equal {
+                                return Ok(None);
+                            }
+                            return blobs.load(&self.data.get_index(cursor).unwrap().1).map(Some);
+                        }
+                        None => cursor += window.len(),
+                    }
+                }
+                Ok(None)
+            })
+            .collect()
+    }
+
+    /// 依次以借用的完整键和值调用 `f`。完整键在 `key_buf` 中拼接，
+    /// 不为每个条目分配内存
+    pub(crate) fn for_each_borrowed(
+        &self,
+        key_buf: &mut Vec<u8>,
+        blobs: &BlobStore,
+        mut f: impl FnMut(&[u8], &[u8]),
+    ) -> std::io::Result<()> {
+        let prefix = self.prefix();
+        for (k, v) in self.data.iter() {
+            key_buf.clear();
+            key_buf.extend_from_slice(prefix);
+            key_buf.extend_from_slice(k);
+            blobs.with_value(v, |v| f(key_buf, v))?;
+        }
+        Ok(())
+    }
+
+    /// 依次返回完整的键和值，单独存储的值从 `blobs` 读取
+    pub(crate) fn iter<'a>(
+        &'a self,
+        blobs: &'a BlobStore,
+    ) -> impl Iterator<Item = std::io::Result<(InlineArray, InlineArray)>> + 'a {
+        self.iter_stored().map(|(k, stored)| Ok((k, blobs.load(&stored)?)))
+    }
+
+    /// 依次返回完整的键和叶子节点中存储的值，不读取单独存储的值
+    pub(crate) fn iter_stored(
         &self,
     ) -> impl Iterator<Item = (InlineArray, InlineArray)> {
         let prefix = self.prefix();
@@ -271,27 +643,145 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
         })
     }
 
-    /// 序列化leaf节点，支持增量序列化
-    pub(crate) fn serialize(&self, zstd_compression_level: i32) -> Vec<u8> {
-        if self.should_use_incremental_serialization() {
+    /// 序列化leaf节点，支持增量序列化，以及所属的树启用了值去重时的去重序列化。
+    /// 值带有单独存储的标记时在前面加上 `BLOB_MARKER`
+    pub(crate) fn serialize(
+        &self,
+        cache: &ObjectCache<LEAF_FANOUT>,
+        collection_id: CollectionId,
+    ) -> Vec<u8> {
+        let zstd_compression_level = cache.config.zstd_compression_level;
+        let serialized = if self.should_use_incremental_serialization() {
             self.serialize_incremental(zstd_compression_level)
+        } else if let Some(min_value_size) = cache.value_dedup_min_size(collection_id) {
+            self.serialize_value_dedup(cache, min_value_size)
+        } else if cache.config.compression_algorithm == CompressionAlgorithm::None {
+            self.serialize_full(zstd_compression_level, cache.dictionaries())
         } else {
-            self.serialize_full(zstd_compression_level)
+            self.serialize_value_compressed(cache)
+        };
+
+        if !cache.blobs().is_enabled() {
+            return serialized;
         }
+        let mut ret = Vec::with_capacity(serialized.len() + 1);
+        ret.push(BLOB_MARKER);
+        ret.extend_from_slice(&serialized);
+        ret
     }
 
-    /// 完整序列化
-    fn serialize_full(&self, zstd_compression_level: i32) -> Vec<u8> {
-        let mut ret = vec![];
+    /// 完整序列化，配置了压缩字典时使用当前的字典压缩
+    fn serialize_full(
+        &self,
+        zstd_compression_level: i32,
+        dictionaries: &CompressionDictionaries,
+    ) -> Vec<u8> {
+        dictionaries.sample(self.data.iter().map(|(_k, v)| v.as_ref()));
 
-        let mut zstd_enc =
-            zstd::stream::Encoder::new(&mut ret, zstd_compression_level)
-                .unwrap();
+        let raw = bincode::serde::encode_to_vec(self, bincode::config::standard()).unwrap();
 
-        bincode::serde::encode_into_std_write(self, &mut zstd_enc, bincode::config::standard()).unwrap();
+        dictionaries.compress(&raw, zstd_compression_level)
+    }
 
-        zstd_enc.finish().unwrap();
+    /// 按值压缩的完整序列化：不小于 `Config::value_compression_threshold` 的值
+    /// 使用配置的压缩算法单独压缩，其余的值原样存储，每个值记录自己的存储方式。
+    /// 每个叶子节点只压缩一次，所以序列化的结果不再经过叶子节点级别的zstd压缩
+    fn serialize_value_compressed(&self, cache: &ObjectCache<LEAF_FANOUT>) -> Vec<u8> {
+        let config = &cache.config;
+
+        let mut compressed = 0;
+        let entries: Vec<(InlineArray, StoredValue)> = self
+            .data
+            .iter()
+            .map(|(k, v)| {
+                let stored = StoredValue::compress(
+                    v,
+                    config.compression_algorithm,
+                    config.value_compression_threshold,
+                    config.zstd_compression_level,
+                );
+                if stored.is_compressed() {
+                    compressed += 1;
+                }
+                (k.clone(), stored)
+            })
+            .collect();
+
+        cache.record_stored_values(compressed, entries.len() as u64 - compressed);
+
+        let stored_leaf = ValueCompressedLeaf {
+            lo: self.lo.clone(),
+            hi: self.hi.clone(),
+            prefix_length: self.prefix_length,
+            mutation_count: self.mutation_count,
+            entries,
+        };
+        let mut ret = vec![VALUE_COMPRESSED_MARKER];
+        bincode::serde::encode_into_std_write(&stored_leaf, &mut ret, bincode::config::standard())
+            .unwrap();
+        ret
+    }
+
+    /// 值去重的完整序列化：不小于 `min_value_size` 的值按内容（哈希后逐字节比较）
+    /// 去重，每个不同的值只存储一次，并按 `serialize_value_compressed` 的规则压缩。
+    /// 序列化的是叶子节点的当前内容，共享同一个值的条目之一被修改后，
+    /// 下一次序列化时它自然引用新的值
+    fn serialize_value_dedup(
+        &self,
+        cache: &ObjectCache<LEAF_FANOUT>,
+        min_value_size: usize,
+    ) -> Vec<u8> {
+        let config = &cache.config;
+        let dictionaries = cache.dictionaries();
+
+        dictionaries.sample(self.data.iter().map(|(_k, v)| v.as_ref()));
+
+        let mut compressed = 0;
+        let mut stored_values = 0;
+        let mut compress = |value: &InlineArray| {
+            let stored = StoredValue::compress(
+                value,
+                config.compression_algorithm,
+                config.value_compression_threshold,
+                config.zstd_compression_level,
+            );
+            if stored.is_compressed() {
+                compressed += 1;
+            }
+            stored_values += 1;
+            stored
+        };
+
+        let mut indices: fnv::FnvHashMap<&[u8], u32> = Default::default();
+        let mut shared = vec![];
+        let mut entries = Vec::with_capacity(self.data.len());
+        for (k, v) in self.data.iter() {
+            let value = if v.len() < min_value_size {
+                DedupValue::Inline(compress(v))
+            } else {
+                let index = *indices.entry(v.as_ref()).or_insert_with(|| {
+                    shared.push(compress(v));
+                    shared.len() as u32 - 1
+                });
+                DedupValue::Shared(index)
+            };
+            entries.push((k.clone(), value));
+        }
+
+        cache.record_stored_values(compressed, stored_values - compressed);
+
+        let stored_leaf = ValueDedupLeaf {
+            lo: self.lo.clone(),
+            hi: self.hi.clone(),
+            prefix_length: self.prefix_length,
+            mutation_count: self.mutation_count,
+            shared,
+            entries,
+        };
+        let raw = bincode::serde::encode_to_vec(&stored_leaf, bincode::config::standard()).unwrap();
 
+        let mut ret = vec![VALUE_DEDUP_MARKER];
+        ret.extend_from_slice(&dictionaries.compress(&raw, config.zstd_compression_level));
         ret
     }
 
@@ -320,22 +810,71 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
         ret
     }
 
-    /// 反序列化leaf节点，自动检测增量序列化
+    /// 反序列化leaf节点，自动检测增量序列化和使用的压缩字典。值是否带有单独存储的
+    /// 标记与当前配置不同时（添加或去掉了 `Config::inline_value_threshold`），
+    /// 转换为当前配置的形式
     pub(crate) fn deserialize(
         buf: &[u8],
+        dictionaries: &CompressionDictionaries,
+        blobs: &BlobStore,
+    ) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
+        let (tagged, buf) = match buf.split_first() {
+            Some((&BLOB_MARKER, rest)) => (true, rest),
+            _ => (false, buf),
+        };
+
+        let mut leaf = Self::deserialize_untagged(buf, dictionaries)?;
+
+        if tagged && !blobs.is_enabled() {
+            leaf.map_values(|stored| blobs.untag(stored))?;
+        } else if !tagged && blobs.is_enabled() {
+            leaf.map_values(|value| Ok(BlobStore::tag_inline(value)))?;
+        }
+
+        Ok(leaf)
+    }
+
+    fn map_values(
+        &mut self,
+        mut f: impl FnMut(&InlineArray) -> std::io::Result<InlineArray>,
+    ) -> std::io::Result<()> {
+        for (k, v) in std::mem::take(&mut self.data).iter() {
+            self.data.insert(k.clone(), f(v)?);
+        }
+        if let Some(changes) = &mut self.incremental_changes {
+            for v in &mut changes.modified_values {
+                *v = f(v)?;
+            }
+        }
+        self.set_in_memory_size();
+        Ok(())
+    }
+
+    fn deserialize_untagged(
+        buf: &[u8],
+        dictionaries: &CompressionDictionaries,
     ) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
         if buf.len() > 0 && buf[0] == 0xFF {
             // 增量序列化数据
             Self::deserialize_incremental(buf)
+        } else if buf.first() == Some(&VALUE_COMPRESSED_MARKER) {
+            // 按值压缩的完整序列化数据
+            Self::deserialize_value_compressed(&buf[1..])
+        } else if buf.first() == Some(&VALUE_DEDUP_MARKER) {
+            // 值去重的完整序列化数据
+            Self::deserialize_value_dedup(&buf[1..], dictionaries)
         } else {
             // 完整序列化数据
-            Self::deserialize_full(buf)
+            Self::deserialize_full(buf, dictionaries)
         }
     }
 
     /// 反序列化完整数据
-    fn deserialize_full(buf: &[u8]) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
-        let zstd_decoded = zstd::stream::decode_all(buf).unwrap();
+    fn deserialize_full(
+        buf: &[u8],
+        dictionaries: &CompressionDictionaries,
+    ) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
+        let zstd_decoded = dictionaries.decompress(buf)?;
         let (mut leaf, _): (Box<Leaf<LEAF_FANOUT>>, usize) =
             bincode::serde::decode_from_slice(&zstd_decoded, bincode::config::standard()).unwrap();
 
@@ -345,6 +884,63 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
         Ok(leaf)
     }
 
+    /// 反序列化按值压缩的完整数据
+    fn deserialize_value_compressed(buf: &[u8]) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
+        let (stored_leaf, _): (ValueCompressedLeaf, usize) =
+            bincode::serde::decode_from_slice(buf, bincode::config::standard()).unwrap();
+
+        let mut leaf = Box::new(Leaf::empty());
+        leaf.lo = stored_leaf.lo;
+        leaf.hi = stored_leaf.hi;
+        leaf.prefix_length = stored_leaf.prefix_length;
+        leaf.mutation_count = stored_leaf.mutation_count;
+        for (k, stored) in stored_leaf.entries {
+            leaf.data.insert(k, stored.decompress()?);
+        }
+        leaf.set_in_memory_size();
+
+        Ok(leaf)
+    }
+
+    /// 反序列化值去重的完整数据，每个引用共享值的条目得到该值的一个副本
+    fn deserialize_value_dedup(
+        buf: &[u8],
+        dictionaries: &CompressionDictionaries,
+    ) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
+        let decoded = dictionaries.decompress(buf)?;
+        let (stored_leaf, _): (ValueDedupLeaf, usize) =
+            bincode::serde::decode_from_slice(&decoded, bincode::config::standard()).unwrap();
+
+        let shared = stored_leaf
+            .shared
+            .into_iter()
+            .map(StoredValue::decompress)
+            .collect::<std::io::Result<Vec<InlineArray>>>()?;
+
+        let mut leaf = Box::new(Leaf::empty());
+        leaf.lo = stored_leaf.lo;
+        leaf.hi = stored_leaf.hi;
+        leaf.prefix_length = stored_leaf.prefix_length;
+        leaf.mutation_count = stored_leaf.mutation_count;
+        for (k, value) in stored_leaf.entries {
+            let value = match value {
+                DedupValue::Inline(stored) => stored.decompress()?,
+                DedupValue::Shared(index) => {
+                    shared.get(index as usize).cloned().ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("去重的叶子节点引用了不存在的值 {}", index),
+                        )
+                    })?
+                }
+            };
+            leaf.data.insert(k, value);
+        }
+        leaf.set_in_memory_size();
+
+        Ok(leaf)
+    }
+
     /// 反序列化增量数据
     fn deserialize_incremental(buf: &[u8]) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>> {
         let zstd_decoded = zstd::stream::decode_all(&buf[1..]).unwrap();
@@ -402,22 +998,28 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
         new_epoch: FlushEpoch,
         allocator: &ObjectCache<LEAF_FANOUT>,
         collection_id: CollectionId,
+        split_threshold: usize,
+        split_bias: SplitBias,
+        truncate_split_key: bool,
     ) -> Option<(InlineArray, Object<LEAF_FANOUT>)> {
-        if self.data.is_full() {
+        if self.data.is_full() || self.data.len() >= split_threshold {
             let original_len = self.data.len();
 
             let old_prefix_len = self.prefix_length;
             // split
-            let split_offset = if self.lo.is_empty() {
-                // split left-most shard almost at the beginning for
-                // optimizing downward-growing workloads
-                1
-            } else if self.hi.is_none() {
-                // split right-most shard almost at the end for
-                // optimizing upward-growing workloads
-                self.data.len() - 2
-            } else {
-                self.data.len() / 2
+            let split_offset = match split_bias {
+                SplitBias::Auto if self.lo.is_empty() => {
+                    // split left-most shard almost at the beginning for
+                    // optimizing downward-growing workloads
+                    1
+                }
+                SplitBias::Auto if self.hi.is_none() => {
+                    // split right-most shard almost at the end for
+                    // optimizing upward-growing workloads
+                    self.data.len() - 2
+                }
+                SplitBias::Auto | SplitBias::Middle => self.data.len() / 2,
+                SplitBias::Last => self.data.len() - 2,
             };
 
             let data = self.data.split_off(split_offset);
@@ -425,14 +1027,19 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
             let left_max = &self.data.last().unwrap().0;
             let right_min = &data.first().unwrap().0;
 
-            // suffix truncation attempts to shrink the split key
-            // so that shorter keys bubble up into the index
-            let splitpoint_length = right_min
-                .iter()
-                .zip(left_max.iter())
-                .take_while(|(a, b)| a == b)
-                .count()
-                + 1;
+            // suffix truncation shrinks the split key to the shortest
+            // prefix of right_min that is greater than left_max, so that
+            // shorter keys bubble up into the index
+            let splitpoint_length = if truncate_split_key {
+                right_min
+                    .iter()
+                    .zip(left_max.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count()
+                    + 1
+            } else {
+                right_min.len()
+            };
 
             let mut split_vec =
                 Vec::with_capacity(self.prefix_length + splitpoint_length);
@@ -464,6 +1071,8 @@ impl<const LEAF_FANOUT: usize> Leaf<LEAF_FANOUT> {
                 incremental_changes: None,
                 last_serialized_version: 0,
                 incremental_serialization_enabled: self.incremental_serialization_enabled,
+                write_epochs: self.write_epochs.split_off(&split_key),
+                loaded_epoch: self.loaded_epoch,
             };
 
             // 如果启用增量序列化，为新leaf也启用
@@ -13,22 +13,49 @@
 pub mod block_cache;
 pub mod bloom_filter;
 pub mod smart_flush;
+mod admission;
+mod backup;
+mod batch_spill;
+mod blob_store;
+mod compression_dictionary;
 mod config;
+mod contention;
 mod db;
+mod disk_space;
+mod epoch_view;
+mod error;
+pub mod export;
+mod flush_debug;
 mod flush_epoch;
+mod flush_group;
 mod heap;
 mod id_allocator;
+pub mod index;
+pub mod key_encoding;
+pub mod keys;
 mod leaf;
 mod logging;
 mod metadata_store;
+#[cfg(feature = "sled-import")]
+pub mod migrate;
 mod object_cache;
 mod object_location_mapper;
+pub mod op_journal;
 pub mod platform_utils;
+mod portable_atomic;
+mod quota;
+mod read_ahead;
+mod scan_arena;
 pub mod simd_optimized;
+mod scoped;
+mod sequence;
+mod snapshot;
 pub mod atomic_worker;
 pub mod database_worker;
 pub mod hybrid_operations_manager;
 mod tree;
+mod tree_writer;
+pub mod ttl;
 
 #[cfg(any(
     feature = "testing-shred-allocator",
@@ -38,24 +65,80 @@ mod tree;
 pub mod alloc;
 
 
+/// 随机让出线程，放大并发代码中的竞争窗口
+///
+/// 默认在调试构建（包括集成测试）和启用 `chaos-testing` 特性时开启。需要可复现
+/// 的计时时，设置环境变量 `MELANGE_DEBUG_DELAY=0` 关闭。未启用 `chaos-testing`
+/// 的release构建不包含这段代码。
 #[inline]
 fn debug_delay() {
-    #[cfg(debug_assertions)]
-    {
-        let rand =
-            std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos();
-
-        if rand % 128 > 100 {
-            for _ in 0..rand % 16 {
-                std::thread::yield_now();
-            }
+    #[cfg(any(debug_assertions, feature = "chaos-testing"))]
+    chaos_delay(debug_delay_enabled());
+}
+
+#[cfg(any(debug_assertions, feature = "chaos-testing"))]
+fn debug_delay_enabled() -> bool {
+    static ENABLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *ENABLED.get_or_init(|| match std::env::var("MELANGE_DEBUG_DELAY") {
+        Ok(value) => value.trim() != "0",
+        Err(_) => true,
+    })
+}
+
+/// 返回让出线程的次数
+#[cfg(any(debug_assertions, feature = "chaos-testing"))]
+fn chaos_delay(enabled: bool) -> u128 {
+    if !enabled {
+        return 0;
+    }
+
+    let rand = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+
+    if rand % 128 > 100 {
+        for _ in 0..rand % 16 {
+            std::thread::yield_now();
         }
+        rand % 16
+    } else {
+        0
     }
 }
 
-pub use crate::config::{Config, CacheWarmupStrategy, CompressionAlgorithm};
-pub use crate::db::Db;
-pub use crate::tree::{Batch, Iter, Tree};
+pub use crate::config::{
+    AdmissionPolicy, AutoCompact, Config, CacheWarmupStrategy, ChecksumKind, ChecksumMode, CompressionAlgorithm,
+    CompressionDictionary, CorruptionPolicy, RecoveryPhase, RecoveryProgress, SplitBias, TreeOptions,
+    DEFAULT_DEDUP_MIN_VALUE_SIZE,
+};
+pub use crate::backup::BackupEpoch;
+pub use crate::compression_dictionary::DictionaryStats;
+pub use crate::contention::{ContendedKey, RetryPolicy};
+pub use crate::db::{
+    Db, DiskUsageReport, FlushHandle, SlabFileUsage, SpaceAmplification, TreeDiskUsage,
+};
+pub use crate::disk_space::WriteState;
+pub use crate::epoch_view::{EpochTree, EpochView};
+pub use crate::error::{MelangeError, MelangeResult};
+pub use crate::flush_debug::{
+    DirtyCollection, FlushDebugReport, FlushInProgress, FlushStage,
+};
+pub use crate::flush_epoch::{EpochGuards, FlushEpoch};
+pub use crate::flush_group::FlushGroupStats;
+pub use crate::quota::{QuotaPolicy, QuotaUsage, TreeQuota};
+pub use crate::heap::{FormatInfo, RecoveryReport};
+pub use crate::key_encoding::{BigEndianI64, BigEndianU64};
+pub use crate::metadata_store::MetadataStats;
+pub use crate::scan_arena::ScanArena;
+pub use crate::scoped::{ScopedIter, ScopedTree};
+pub use crate::sequence::Sequence;
+pub use crate::tree_writer::{TreeWriter, TreeWriterOptions};
+pub use crate::platform_utils::ThreadPriority;
+pub use crate::tree::{
+    ArenaScan, Backoff, Batch, BloomReadStats, CachePolicy, ConflictPolicy, CopyStats,
+    EntryMetadata, GetOptions, Iter, IterOptions, ScanFilter, SnapshotIter, Tree, TreeStats,
+};
+#[doc(hidden)]
+pub use crate::tree::LeafInfo;
 
 // 内部优化实现细节，不应暴露给用户
 #[doc(hidden)]
@@ -70,6 +153,12 @@ const NAME_MAPPING_COLLECTION_ID: CollectionId = CollectionId(0);
 const DEFAULT_COLLECTION_ID: CollectionId = CollectionId(1);
 const INDEX_FANOUT: usize = 64;
 const EBR_LOCAL_GC_BUFFER_SIZE: usize = 128;
+// the `ebr` default: a `ConcurrentMap` handle advances the global epoch
+// once every this many pins
+const EBR_BUMP_EPOCH_OPS: usize = 128;
+// garbage sealed in epoch `e` is freed once every handle has moved past
+// `e + 2`, and orphaned bags are picked up on the advance after that
+const EBR_EPOCH_ADVANCES: usize = 3;
 
 use std::collections::BTreeMap;
 use std::num::NonZeroU64;
@@ -79,10 +168,10 @@ use std::sync::Arc;
 use parking_lot::RwLock;
 
 use crate::flush_epoch::{
-    FlushEpoch, FlushEpochGuard, FlushEpochTracker, FlushInvariants,
+    FlushEpochGuard, FlushEpochTracker, FlushInvariants,
 };
 use crate::heap::{
-    HeapStats, ObjectRecovery, SlabAddress, Update, WriteBatchStats,
+    HeapStats, ObjectRecovery, Update, WriteBatchStats,
 };
 use crate::id_allocator::{Allocator, DeferredFree};
 use crate::leaf::Leaf;
@@ -90,7 +179,7 @@ use crate::leaf::Leaf;
 // 这些是公开的，以便在外部二进制文件中进行崩溃测试
 // 它们被隐藏是因为没有关于其API稳定性或功能的保证
 #[doc(hidden)]
-pub use crate::heap::{Heap, HeapRecovery};
+pub use crate::heap::{Heap, HeapRecovery, SlabAddress};
 #[doc(hidden)]
 pub use crate::metadata_store::MetadataStore;
 #[doc(hidden)]
@@ -99,10 +188,20 @@ pub use crate::object_cache::{CacheStats, Dirty, FlushStats, ObjectCache};
 /// 使用默认配置在指定路径打开一个 `Db`
 /// 这将在指定路径创建一个新的存储目录（如果它不存在）
 /// 您可以使用 `Db::was_recovered` 方法来确定数据库是否从之前的实例中恢复
+/// `Db::recovery_report` 返回恢复过程的详细信息
 pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Db> {
     Config::new().path(path).open()
 }
 
+/// 读取指定路径的数据库创建时的格式，而不打开数据库
+///
+/// 不需要 `LEAF_FANOUT` const 泛型参数，也不获取数据库的锁，可以在决定如何打开
+/// 数据库之前检查它，例如用 `FormatInfo::leaf_fanout` 选择 `Config::open` 的泛型参数。
+/// 路径下没有数据库时返回 `io::ErrorKind::NotFound`
+pub fn inspect<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<FormatInfo> {
+    crate::heap::inspect(path.as_ref())
+}
+
 /// 清理指定路径的数据库锁文件
 ///
 /// 这个函数会清理指定路径下的所有锁文件，包括：
@@ -158,6 +257,31 @@ pub fn cleanup_lock_files<P: AsRef<std::path::Path>>(path: P) -> std::io::Result
 #[derive(Debug, Copy, Clone)]
 pub struct Stats {
     pub cache: CacheStats,
+    /// 原子计数器的统计，数据库上还没有创建过 `HybridOperationsManager` 时为 `None`
+    pub counters: Option<crate::atomic_worker::CounterStats>,
+}
+
+/// `Db::memory_stats` 返回的各个子系统占用的内存（字节）。
+///
+/// 由当前的数据结构计算，不依赖全局分配器，因此不包括分配器本身的开销，
+/// 索引和计数器映射的值是估算的
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// 缓存中的叶子节点，与 `Db::cache_resident_bytes` 相同
+    pub object_cache_bytes: usize,
+    /// 每个树从低键到叶子节点的索引和对象ID索引，不包括叶子节点的内容
+    pub index_bytes: usize,
+    /// 布隆过滤器的位图，重建期间包括正在构建的新过滤器
+    pub bloom_filter_bytes: usize,
+    /// `HybridOperationsManager` 的内存中的计数器映射
+    pub counter_bytes: usize,
+}
+
+impl MemoryStats {
+    /// 所有子系统的总和
+    pub fn total_bytes(&self) -> usize {
+        self.object_cache_bytes + self.index_bytes + self.bloom_filter_bytes + self.counter_bytes
+    }
 }
 
 /// 比较并交换结果
@@ -203,6 +327,330 @@ impl std::fmt::Display for CompareAndSwapError {
 
 impl std::error::Error for CompareAndSwapError {}
 
+/// 条件批次的守卫条件不成立时返回的错误。
+///
+/// `Tree::apply_batch` 以 `io::Error` 的形式返回它，
+/// 可以通过 `BatchGuardError::from_io_error` 取出。
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BatchGuardError {
+    /// 第一个（按键顺序）未满足的守卫条件的键
+    pub key: InlineArray,
+    /// 守卫条件期望的值
+    pub expected: Option<InlineArray>,
+    /// 该键的当前值
+    pub current: Option<InlineArray>,
+}
+
+impl BatchGuardError {
+    /// 如果 `error` 是由未满足的批次守卫条件引起的，返回对应的 `BatchGuardError`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&BatchGuardError> {
+        error.get_ref()?.downcast_ref::<BatchGuardError>()
+    }
+}
+
+impl std::fmt::Display for BatchGuardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Batch guard on key {:?} did not hold", self.key)
+    }
+}
+
+impl std::error::Error for BatchGuardError {}
+
+/// 在只写一次的树中写入已存在的键时返回的错误，参见 `TreeOptions::write_once`。
+/// `Db::copy_tree_range` 以 `ConflictPolicy::Error` 遇到目标树中已存在的键时也返回它。
+///
+/// 以 `io::ErrorKind::AlreadyExists` 的 `io::Error` 的形式返回，
+/// 可以通过 `KeyAlreadyExists::from_io_error` 取出。
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyAlreadyExists {
+    /// 已存在的键（批次中按键顺序的第一个）
+    pub key: InlineArray,
+}
+
+impl KeyAlreadyExists {
+    /// 如果 `error` 是由写入只写一次的树中已存在的键引起的，返回对应的 `KeyAlreadyExists`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&KeyAlreadyExists> {
+        error.get_ref()?.downcast_ref::<KeyAlreadyExists>()
+    }
+}
+
+impl std::fmt::Display for KeyAlreadyExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key {:?} already exists", self.key)
+    }
+}
+
+impl std::error::Error for KeyAlreadyExists {}
+
+impl From<KeyAlreadyExists> for std::io::Error {
+    fn from(error: KeyAlreadyExists) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::AlreadyExists, error)
+    }
+}
+
+/// 写入超出树的 `TreeQuota::max_write_ops_per_sec` 且策略为
+/// `QuotaPolicy::Reject` 时返回的错误，参见 `Tree::set_quota`。
+///
+/// 以 `io::ErrorKind::QuotaExceeded` 的 `io::Error` 的形式返回，
+/// 可以通过 `QuotaExceeded::from_io_error` 取出。被拒绝的写入没有修改树
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QuotaExceeded {
+    /// 树的写入速率限制
+    pub max_write_ops_per_sec: u32,
+    /// 令牌桶预计在多长时间后有足够的令牌
+    pub retry_after: std::time::Duration,
+}
+
+impl QuotaExceeded {
+    /// 如果 `error` 是由超出树的写入速率限制引起的，返回对应的 `QuotaExceeded`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&QuotaExceeded> {
+        error.get_ref()?.downcast_ref::<QuotaExceeded>()
+    }
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "write exceeds the tree's quota of {} writes per second, retry after {:?}",
+            self.max_write_ops_per_sec, self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+impl From<QuotaExceeded> for std::io::Error {
+    fn from(error: QuotaExceeded) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::QuotaExceeded, error)
+    }
+}
+
+/// 基于比较并交换的操作在竞争中超出 `RetryPolicy::max_retries` 时返回的错误，
+/// 参见 `Tree::update_and_fetch_with_retry`。
+///
+/// 以 `io::ErrorKind::ResourceBusy` 的 `io::Error` 的形式返回，
+/// 可以通过 `ContentionExceeded::from_io_error` 取出。放弃的操作没有修改树
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentionExceeded {
+    /// 竞争的键
+    pub key: InlineArray,
+    /// 因竞争而失败的尝试次数
+    pub attempts: usize,
+}
+
+impl ContentionExceeded {
+    /// 如果 `error` 是由超出重试次数的竞争引起的，返回对应的 `ContentionExceeded`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&ContentionExceeded> {
+        error.get_ref()?.downcast_ref::<ContentionExceeded>()
+    }
+}
+
+impl std::fmt::Display for ContentionExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "compare and swap on key {:?} gave up after {} conflicting attempts",
+            self.key, self.attempts
+        )
+    }
+}
+
+impl std::error::Error for ContentionExceeded {}
+
+impl From<ContentionExceeded> for std::io::Error {
+    fn from(error: ContentionExceeded) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::ResourceBusy, error)
+    }
+}
+
+/// 文件系统已满使数据库进入 `WriteState::ReadOnlyDegraded` 之后，写入返回的错误，
+/// 参见 `Db::try_resume_writes`。
+///
+/// 以 `io::ErrorKind::StorageFull` 的 `io::Error` 的形式返回，
+/// 可以通过 `DiskFull::from_io_error` 取出。被拒绝的写入没有修改树
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DiskFull {
+    /// 数据库的目录
+    pub path: std::path::PathBuf,
+}
+
+impl DiskFull {
+    /// 如果 `error` 是由数据库处于只读降级状态引起的，返回对应的 `DiskFull`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&DiskFull> {
+        error.get_ref()?.downcast_ref::<DiskFull>()
+    }
+}
+
+impl std::fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the filesystem holding {:?} is full, writes are rejected until space is freed",
+            self.path
+        )
+    }
+}
+
+impl std::error::Error for DiskFull {}
+
+impl From<DiskFull> for std::io::Error {
+    fn from(error: DiskFull) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::StorageFull, error)
+    }
+}
+
+/// `Db::drop_tree` 删除一个树之后，这个树仍然存在的句柄和迭代器的操作返回的错误。
+///
+/// 以 `io::ErrorKind::NotFound` 的 `io::Error` 的形式返回，
+/// 可以通过 `TreeDropped::from_io_error` 取出。之后以同一个名称打开的树是一个
+/// 新的空树，旧的句柄不会访问它
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreeDropped {
+    /// 被删除的树的集合ID，可能已经被之后创建的树重新使用
+    pub collection_id: u64,
+}
+
+impl TreeDropped {
+    /// 如果 `error` 是由访问已删除的树引起的，返回对应的 `TreeDropped`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&TreeDropped> {
+        error.get_ref()?.downcast_ref::<TreeDropped>()
+    }
+}
+
+impl std::fmt::Display for TreeDropped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the tree with collection id {} has been dropped", self.collection_id)
+    }
+}
+
+impl std::error::Error for TreeDropped {}
+
+impl From<TreeDropped> for std::io::Error {
+    fn from(error: TreeDropped) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::NotFound, error)
+    }
+}
+
+/// 从堆文件读取的对象的校验和不匹配时返回的错误，说明损坏的位置。
+///
+/// 以 `io::ErrorKind::InvalidData` 的 `io::Error` 的形式返回，
+/// 可以通过 `CorruptionError::from_io_error` 取出。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CorruptionError {
+    /// 对象所属的树的集合ID
+    pub collection_id: u64,
+    /// 损坏的对象的ID
+    pub object_id: u64,
+    /// 对象所在的slab文件的编号
+    pub slab_id: u8,
+    /// 对象所在的slab文件的槽大小（字节），即 `slabs` 目录下的文件名
+    pub slot_size: usize,
+    /// 对象在slab文件中的槽号，对象从文件的 `slot * slot_size` 字节处开始
+    pub slot: u64,
+}
+
+impl CorruptionError {
+    /// 如果 `error` 是由校验和不匹配引起的，返回对应的 `CorruptionError`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&CorruptionError> {
+        error.get_ref()?.downcast_ref::<CorruptionError>()
+    }
+}
+
+impl std::fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "checksum mismatch for object {} of collection {} in slot {} of the {} byte slab",
+            self.object_id, self.collection_id, self.slot, self.slot_size
+        )
+    }
+}
+
+impl std::error::Error for CorruptionError {}
+
+impl From<CorruptionError> for std::io::Error {
+    fn from(error: CorruptionError) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// 以与创建时不同的 `LEAF_FANOUT` 打开数据库时返回的错误。
+///
+/// 以 `io::ErrorKind::Unsupported` 的 `io::Error` 的形式返回，
+/// 可以通过 `LeafFanoutMismatch::from_io_error` 取出。
+/// 可以先用 `inspect` 读取数据库创建时的 `LEAF_FANOUT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LeafFanoutMismatch {
+    /// 数据库创建时的 `LEAF_FANOUT`
+    pub created_with: usize,
+    /// 本次打开时使用的 `LEAF_FANOUT`
+    pub opened_with: usize,
+}
+
+impl LeafFanoutMismatch {
+    /// 如果 `error` 是由 `LEAF_FANOUT` 不匹配引起的，返回对应的 `LeafFanoutMismatch`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&LeafFanoutMismatch> {
+        error.get_ref()?.downcast_ref::<LeafFanoutMismatch>()
+    }
+}
+
+impl std::fmt::Display for LeafFanoutMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "database was created with LEAF_FANOUT={}, opened with {}. The LEAF_FANOUT \
+                may not be changed after creation; use Db::export / Db::import to migrate",
+            self.created_with, self.opened_with
+        )
+    }
+}
+
+impl std::error::Error for LeafFanoutMismatch {}
+
+impl From<LeafFanoutMismatch> for std::io::Error {
+    fn from(error: LeafFanoutMismatch) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Unsupported, error)
+    }
+}
+
+/// 数据库目录已被另一个 `Db`（可能在另一个进程中）打开时返回的错误。
+///
+/// 打开数据库时对目录中的 `.lock` 文件加排他的建议锁（Unix上为 `flock`，
+/// Windows上为 `LockFileEx`），持有它的 `Db` 及其所有克隆被释放后锁随之释放。
+/// 以 `io::ErrorKind::WouldBlock` 的 `io::Error` 的形式返回，
+/// 可以通过 `DatabaseLocked::from_io_error` 取出。
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DatabaseLocked {
+    /// 数据库目录
+    pub path: std::path::PathBuf,
+}
+
+impl DatabaseLocked {
+    /// 如果 `error` 是由数据库目录已被锁定引起的，返回对应的 `DatabaseLocked`
+    pub fn from_io_error(error: &std::io::Error) -> Option<&DatabaseLocked> {
+        error.get_ref()?.downcast_ref::<DatabaseLocked>()
+    }
+}
+
+impl std::fmt::Display for DatabaseLocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "database at {:?} is already opened by another Db, possibly in another process",
+            self.path
+        )
+    }
+}
+
+impl std::error::Error for DatabaseLocked {}
+
+impl From<DatabaseLocked> for std::io::Error {
+    fn from(error: DatabaseLocked) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::WouldBlock, error)
+    }
+}
+
 #[derive(
     Debug,
     Clone,
@@ -289,13 +737,19 @@ impl<const LEAF_FANOUT: usize> PartialEq for Object<LEAF_FANOUT> {
     }
 }
 
+/// 发送给 flusher 线程的信号
+enum FlusherSignal {
+    /// 立即执行一次flush，由 `Db::flush_async` 发送
+    Flush,
+    /// flush剩余的数据后退出，并通过这个通道向请求者确认
+    Shutdown(std::sync::mpsc::Sender<()>),
+}
+
 /// 存储在 `Db` 和 `Tree` 的 Arc 中，
 /// 所以当最后一个"高级"结构被删除时，
 /// flusher 线程被清理
 struct ShutdownDropper<const LEAF_FANOUT: usize> {
-    shutdown_sender: parking_lot::Mutex<
-        std::sync::mpsc::Sender<std::sync::mpsc::Sender<()>>,
-    >,
+    shutdown_sender: parking_lot::Mutex<std::sync::mpsc::Sender<FlusherSignal>>,
     cache: parking_lot::Mutex<object_cache::ObjectCache<LEAF_FANOUT>>,
 }
 
@@ -303,7 +757,7 @@ impl<const LEAF_FANOUT: usize> Drop for ShutdownDropper<LEAF_FANOUT> {
     fn drop(&mut self) {
         let (tx, rx) = std::sync::mpsc::channel();
         debug_log!("sending shutdown signal to flusher");
-        if self.shutdown_sender.lock().send(tx).is_ok() {
+        if self.shutdown_sender.lock().send(FlusherSignal::Shutdown(tx)).is_ok() {
             if let Err(e) = rx.recv() {
                 error_log!("failed to shut down flusher thread: {:?}", e);
             } else {
@@ -320,6 +774,10 @@ impl<const LEAF_FANOUT: usize> Drop for ShutdownDropper<LEAF_FANOUT> {
                     e
                 );
                 cache.set_error(&e);
+            } else {
+                cache.mark_clean_shutdown();
+                // 与flusher线程关闭时一样，之后的写入不能使记录失效
+                cache.set_error(&std::io::Error::other("系统已关闭"));
             }
         }
     }
@@ -348,3 +806,30 @@ const fn _assert_public_types_send_sync() {
     _assert_send_sync::<CompareAndSwapSuccess>();
     _assert_send_sync::<CompareAndSwapError>();
 }
+
+#[cfg(all(test, any(debug_assertions, feature = "chaos-testing")))]
+mod tests {
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    use super::chaos_delay;
+
+    #[test]
+    fn test_disabled_debug_delay_is_noop() {
+        let start = Instant::now();
+        let mut yields = 0;
+        for _ in 0..1_000_000 {
+            yields += chaos_delay(black_box(false));
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(yields, 0);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_enabled_debug_delay_yields() {
+        let yields: u128 = (0..100_000).map(|_| chaos_delay(black_box(true))).sum();
+        assert!(yields > 0);
+    }
+}
@@ -1,52 +1,111 @@
 //! 高性能日志模块
 //!
-//! 使用rat_logger日志库，由调用者负责初始化
+//! 默认使用rat_logger日志库，启用 `tracing` 特性时改为通过 `tracing` 输出，
+//! 可以与应用的订阅者和span关联。两种情况下都由调用者负责初始化，
 //! 库本身不进行日志初始化，保持配置灵活性
 
 /// 调试级别日志 - 仅在debug模式下编译
+///
+/// 可以在格式化参数之前用 `;` 分隔给出结构化字段，例如
+/// `debug_log!(op = "flush", objects = n; "flush完成: {} 个对象", n)`。
+/// 启用 `tracing` 特性时通过 `tracing` 输出（在release模式下同样保留，
+/// 由订阅者按级别过滤），字段作为事件的字段；
+/// 否则通过rat_logger输出格式化的消息，字段被忽略
 #[macro_export]
 macro_rules! debug_log {
+    ($($field:ident = $value:expr),+ ; $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($field = $value),+, $($arg)+);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
+        rat_logger::debug!($($arg)+);
+    };
     ($($arg:tt)*) => {
-        #[cfg(debug_assertions)]
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($arg)*);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
         rat_logger::debug!($($arg)*);
 
-        #[cfg(not(debug_assertions))]
+        #[cfg(not(any(debug_assertions, feature = "tracing")))]
         {
             // release模式下完全零成本
         }
     };
 }
 
-/// 追踪级别日志 - 仅在debug模式下编译
+/// 追踪级别日志 - 仅在debug模式下编译，结构化字段的用法同 `debug_log!`
 #[macro_export]
 macro_rules! trace_log {
+    ($($field:ident = $value:expr),+ ; $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::trace!($($field = $value),+, $($arg)+);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
+        rat_logger::trace!($($arg)+);
+    };
     ($($arg:tt)*) => {
-        #[cfg(debug_assertions)]
+        #[cfg(feature = "tracing")]
+        ::tracing::trace!($($arg)*);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
         rat_logger::trace!($($arg)*);
     };
 }
 
-/// 信息级别日志 - 轻量级，仅在必要时使用
+/// 信息级别日志 - 轻量级，仅在必要时使用，结构化字段的用法同 `debug_log!`
 #[macro_export]
 macro_rules! info_log {
+    ($($field:ident = $value:expr),+ ; $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($field = $value),+, $($arg)+);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
+        rat_logger::info!($($arg)+);
+    };
     ($($arg:tt)*) => {
-        #[cfg(debug_assertions)]
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($arg)*);
+
+        #[cfg(all(debug_assertions, not(feature = "tracing")))]
         rat_logger::info!($($arg)*);
     };
 }
 
-/// 警告级别日志 - 始终保留但优化
+/// 警告级别日志 - 始终保留但优化，结构化字段的用法同 `debug_log!`
 #[macro_export]
 macro_rules! warn_log {
+    ($($field:ident = $value:expr),+ ; $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($field = $value),+, $($arg)+);
+
+        #[cfg(not(feature = "tracing"))]
+        rat_logger::warn!($($arg)+);
+    };
     ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($arg)*);
+
+        #[cfg(not(feature = "tracing"))]
         rat_logger::warn!($($arg)*);
     };
 }
 
-/// 错误级别日志 - 始终保留
+/// 错误级别日志 - 始终保留，结构化字段的用法同 `debug_log!`
 #[macro_export]
 macro_rules! error_log {
+    ($($field:ident = $value:expr),+ ; $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::error!($($field = $value),+, $($arg)+);
+
+        #[cfg(not(feature = "tracing"))]
+        rat_logger::error!($($arg)+);
+    };
     ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::error!($($arg)*);
+
+        #[cfg(not(feature = "tracing"))]
         rat_logger::error!($($arg)*);
     };
 }
@@ -59,7 +118,7 @@ macro_rules! perf_trace {
         #[cfg(all(debug_assertions, feature = "perf-trace"))]
         {
             use std::time::Instant;
-            static _COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+            static _COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
             let start = Instant::now();
             let result = { $($arg)* };
             let elapsed = start.elapsed();
@@ -1,17 +1,19 @@
 use std::collections::BTreeSet;
 use std::fs;
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, Write};
 use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
 use std::sync::{
     Arc,
-    atomic::{AtomicPtr, AtomicU64, Ordering},
+    atomic::{AtomicPtr, Ordering},
 };
+use std::time::SystemTime;
 
 use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
 use fault_injection::{annotate, fallible, maybe};
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use inline_array::InlineArray;
 use parking_lot::Mutex;
 use rayon::prelude::*;
@@ -53,30 +55,106 @@ struct MetadataRecovery {
     recovered: Vec<UpdateMetadata>,
     id_for_next_log: u64,
     snapshot_size: u64,
+    stats: MetadataRecoveryStats,
+}
+
+/// What recovering the metadata store had to do, surfaced through
+/// `Db::recovery_report`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MetadataRecoveryStats {
+    pub(crate) snapshot_recovered: bool,
+    pub(crate) logs_replayed: u64,
+    pub(crate) batches_replayed: u64,
+    pub(crate) torn_writes_truncated: u64,
+    pub(crate) torn_bytes_truncated: u64,
+    pub(crate) incomplete_snapshots_removed: u64,
+    pub(crate) recovered_to_lsn: u64,
+}
+
+/// The batches recovered from a single log.
+struct RecoveredLog {
+    data: FnvHashMap<ObjectId, UpdateMetadata>,
+    batches: u64,
+    // bytes after the last intact batch, left behind by a torn write
+    torn_bytes: u64,
+}
+
+/// Maintenance statistics of the metadata store, returned by
+/// `Db::metadata_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataStats {
+    /// The number of objects whose latest metadata entry stores a location.
+    pub live_entries: u64,
+    /// Entries in the snapshot and logs that are superseded by a later
+    /// entry for the same object, or that record a freed object. Compaction
+    /// discards them.
+    pub dead_entries: u64,
+    /// The total size of the snapshot and log files.
+    pub file_bytes: u64,
+    /// When the store last finished writing a snapshot, including the one
+    /// written while recovering.
+    pub last_compaction: Option<SystemTime>,
+}
+
+/// Counts maintained by the writer and the compactor, from which
+/// `MetadataStats` is derived.
+#[derive(Default)]
+struct Accounting {
+    live: Mutex<FnvHashSet<ObjectId>>,
+    snapshot_entries: PortableAtomicU64,
+    // entries in logs that have not been compacted yet, including the active one
+    log_entries: PortableAtomicU64,
+    // logs handed to the compactor that it has not finished with
+    pending_compactions: PortableAtomicU64,
+    last_compaction: Mutex<Option<SystemTime>>,
 }
 
 struct LogAndStats {
     file: fs::File,
     bytes_written: u64,
+    entries: u64,
     log_sequence_number: u64,
 }
 
 enum WorkerMessage {
     Shutdown(Sender<()>),
-    LogReadyToCompact { log_and_stats: LogAndStats },
+    LogReadyToCompact {
+        log_and_stats: LogAndStats,
+        // notified once the log has been folded into a new snapshot
+        compacted: Option<Sender<io::Result<()>>>,
+    },
+}
+
+struct PendingCompaction {
+    log_sequence_number: u64,
+    entries: u64,
+    compacted: Option<Sender<io::Result<()>>>,
+}
+
+impl PendingCompaction {
+    fn new(
+        log_and_stats: LogAndStats,
+        compacted: Option<Sender<io::Result<()>>>,
+    ) -> PendingCompaction {
+        PendingCompaction {
+            log_sequence_number: log_and_stats.log_sequence_number,
+            entries: log_and_stats.entries,
+            compacted,
+        }
+    }
 }
 
 fn get_compactions(
     rx: &mut Receiver<WorkerMessage>,
-) -> Result<Vec<u64>, Option<Sender<()>>> {
+) -> Result<Vec<PendingCompaction>, Option<Sender<()>>> {
     let mut ret = vec![];
 
     match rx.recv() {
         Ok(WorkerMessage::Shutdown(tx)) => {
             return Err(Some(tx));
         }
-        Ok(WorkerMessage::LogReadyToCompact { log_and_stats }) => {
-            ret.push(log_and_stats.log_sequence_number);
+        Ok(WorkerMessage::LogReadyToCompact { log_and_stats, compacted }) => {
+            ret.push(PendingCompaction::new(log_and_stats, compacted));
         }
         Err(e) => {
             error_log!(
@@ -93,8 +171,8 @@ fn get_compactions(
                 tx.send(()).unwrap();
                 return Err(Some(tx));
             }
-            Ok(WorkerMessage::LogReadyToCompact { log_and_stats }) => {
-                ret.push(log_and_stats.log_sequence_number);
+            Ok(WorkerMessage::LogReadyToCompact { log_and_stats, compacted }) => {
+                ret.push(PendingCompaction::new(log_and_stats, compacted));
             }
             Err(_timeout) => return Ok(ret),
         }
@@ -106,6 +184,10 @@ fn worker(
     mut last_snapshot_lsn: u64,
     inner: Inner,
 ) {
+    // logs that could not be compacted because the filesystem was full,
+    // compacted along with the next log
+    let mut deferred: Vec<PendingCompaction> = vec![];
+
     loop {
         if let Err(error) = check_error(&inner.global_error) {
             drop(inner);
@@ -119,22 +201,51 @@ fn worker(
         }
 
         match get_compactions(&mut rx) {
-            Ok(log_ids) => {
-                assert_eq!(log_ids[0], last_snapshot_lsn + 1);
+            Ok(mut compactions) => {
+                if !deferred.is_empty() {
+                    deferred.append(&mut compactions);
+                    compactions = std::mem::take(&mut deferred);
+                }
+
+                assert_eq!(
+                    compactions[0].log_sequence_number,
+                    last_snapshot_lsn + 1
+                );
 
                 let write_res = read_snapshot_and_apply_logs(
                     &inner.storage_directory,
-                    log_ids.into_iter().collect(),
+                    compactions
+                        .iter()
+                        .map(|compaction| compaction.log_sequence_number)
+                        .collect(),
                     Some(last_snapshot_lsn),
                     &inner.directory_lock,
                 );
                 match write_res {
+                    Err(e) if crate::disk_space::is_full(&e) => {
+                        warn_log!(
+                            "log compactor could not write a snapshot because the filesystem is full, retrying with the next log"
+                        );
+                        for mut compaction in compactions {
+                            if let Some(compacted) = compaction.compacted.take() {
+                                let _ = compacted
+                                    .send(Err(io::Error::new(e.kind(), e.to_string())));
+                            }
+                            deferred.push(compaction);
+                        }
+                    }
                     Err(e) => {
                         set_error(&inner.global_error, &e);
                         error_log!(
                             "log compactor thread encountered error: {:?} - setting global fatal error and shutting down compactions",
                             e
                         );
+                        for compaction in compactions {
+                            if let Some(compacted) = compaction.compacted {
+                                let _ = compacted
+                                    .send(Err(io::Error::new(e.kind(), e.to_string())));
+                            }
+                        }
                         return;
                     }
                     Ok(recovery) => {
@@ -143,6 +254,31 @@ fn worker(
                             .store(recovery.snapshot_size, Ordering::SeqCst);
                         last_snapshot_lsn =
                             recovery.id_for_next_log.checked_sub(1).unwrap();
+
+                        let accounting = &inner.accounting;
+                        let compacted_entries: u64 = compactions
+                            .iter()
+                            .map(|compaction| compaction.entries)
+                            .sum();
+                        accounting.snapshot_entries.store(
+                            recovery.recovered.len() as u64,
+                            Ordering::Release,
+                        );
+                        accounting
+                            .log_entries
+                            .fetch_sub(compacted_entries, Ordering::AcqRel);
+                        accounting.pending_compactions.fetch_sub(
+                            compactions.len() as u64,
+                            Ordering::AcqRel,
+                        );
+                        *accounting.last_compaction.lock() =
+                            Some(SystemTime::now());
+
+                        for compaction in compactions {
+                            if let Some(compacted) = compaction.compacted {
+                                let _ = compacted.send(Ok(()));
+                            }
+                        }
                     }
                 }
             }
@@ -206,10 +342,143 @@ fn check_error(
 struct Inner {
     global_error: Arc<AtomicPtr<(io::ErrorKind, String)>>,
     active_log: Arc<Mutex<LogAndStats>>,
-    snapshot_size: Arc<AtomicU64>,
+    snapshot_size: Arc<PortableAtomicU64>,
     storage_directory: PathBuf,
     directory_lock: Arc<fs::File>,
     worker_outbox: Sender<WorkerMessage>,
+    accounting: Arc<Accounting>,
+}
+
+/// A handle for inspecting and compacting the store without the
+/// `MetadataStore` itself, which the heap only locks while writing a batch.
+/// It does not hold an `Inner`, so it does not keep the global error alive.
+#[derive(Clone)]
+pub(crate) struct MetadataMaintenance {
+    global_error: Arc<AtomicPtr<(io::ErrorKind, String)>>,
+    active_log: Arc<Mutex<LogAndStats>>,
+    storage_directory: PathBuf,
+    worker_outbox: Sender<WorkerMessage>,
+    accounting: Arc<Accounting>,
+}
+
+impl MetadataMaintenance {
+    pub(crate) fn stats(&self) -> io::Result<MetadataStats> {
+        let accounting = &self.accounting;
+        let live_entries = accounting.live.lock().len() as u64;
+        let entries = accounting.snapshot_entries.load(Ordering::Acquire)
+            + accounting.log_entries.load(Ordering::Acquire);
+
+        // logs and snapshots may be removed by the compactor while we look
+        let mut file_bytes = 0;
+        for dir_entry_res in fallible!(fs::read_dir(&self.storage_directory)) {
+            let dir_entry = fallible!(dir_entry_res);
+            let name = dir_entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.starts_with(LOG_PREFIX) && !name.starts_with(SNAPSHOT_PREFIX)
+            {
+                continue;
+            }
+            match dir_entry.metadata() {
+                Ok(metadata) => file_bytes += metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(annotate!(e)),
+            }
+        }
+
+        Ok(MetadataStats {
+            live_entries,
+            dead_entries: entries.saturating_sub(live_entries),
+            file_bytes,
+            last_compaction: *accounting.last_compaction.lock(),
+        })
+    }
+
+    /// Folds the active log into a new snapshot that only contains live
+    /// entries, and waits for the snapshot to be written. The snapshot is
+    /// written to a temporary file and renamed into place, as for every
+    /// other compaction, so a crash leaves either the old snapshot and logs
+    /// or the new snapshot.
+    pub(crate) fn compact(&self) -> io::Result<()> {
+        check_error(&self.global_error)?;
+
+        let (tx, rx) = bounded(1);
+        {
+            let mut log = self.active_log.lock();
+            self.rotate_log(&mut log, Some(tx))?;
+        }
+
+        match rx.recv() {
+            Ok(result) => result,
+            Err(_) => {
+                check_error(&self.global_error)?;
+                Err(io::Error::other("metadata store has been shut down"))
+            }
+        }
+    }
+
+    /// Starts a compaction without waiting for it if dead entries exceed
+    /// `ratio` times the live entries and no compaction is already pending.
+    pub(crate) fn compact_if_dead_ratio_exceeds(
+        &self,
+        ratio: f64,
+    ) -> io::Result<()> {
+        if self.accounting.pending_compactions.load(Ordering::Acquire) > 0 {
+            return Ok(());
+        }
+
+        let stats = self.stats()?;
+        if stats.dead_entries == 0
+            || (stats.dead_entries as f64) <= stats.live_entries as f64 * ratio
+        {
+            return Ok(());
+        }
+
+        debug_log!(
+            "compacting metadata store with {} dead and {} live entries",
+            stats.dead_entries,
+            stats.live_entries
+        );
+        let mut log = self.active_log.lock();
+        self.rotate_log(&mut log, None)
+    }
+
+    /// Replaces the active log with a new one and hands the previous log to
+    /// the compactor.
+    fn rotate_log(
+        &self,
+        log: &mut LogAndStats,
+        compacted: Option<Sender<io::Result<()>>>,
+    ) -> io::Result<()> {
+        let next_offset = log.log_sequence_number + 1;
+        let next_path = log_path(&self.storage_directory, next_offset);
+
+        // open new log
+        let mut next_log_file_opts = fs::OpenOptions::new();
+        next_log_file_opts.create(true).read(true).write(true);
+
+        let next_log_file = maybe!(next_log_file_opts.open(next_path))?;
+
+        let next_log_and_stats = LogAndStats {
+            file: next_log_file,
+            log_sequence_number: next_offset,
+            bytes_written: 0,
+            entries: 0,
+        };
+
+        // replace log
+        let old_log_and_stats = std::mem::replace(log, next_log_and_stats);
+
+        // send to snapshot writer
+        self.accounting.pending_compactions.fetch_add(1, Ordering::AcqRel);
+        self.worker_outbox
+            .send(WorkerMessage::LogReadyToCompact {
+                log_and_stats: old_log_and_stats,
+                compacted,
+            })
+            .map_err(|_| {
+                io::Error::other("unable to send log to compact to worker")
+            })
+    }
 }
 
 impl Drop for Inner {
@@ -258,6 +527,16 @@ impl MetadataStore {
         set_error(&self.inner.global_error, error);
     }
 
+    pub(crate) fn maintenance(&self) -> MetadataMaintenance {
+        MetadataMaintenance {
+            global_error: self.inner.global_error.clone(),
+            active_log: self.inner.active_log.clone(),
+            storage_directory: self.inner.storage_directory.clone(),
+            worker_outbox: self.inner.worker_outbox.clone(),
+            accounting: self.inner.accounting.clone(),
+        }
+    }
+
     /// Returns the writer handle `MetadataStore`, a sorted array of metadata, and a sorted array
     /// of free keys.
     pub fn recover<P: AsRef<Path>>(
@@ -268,6 +547,16 @@ impl MetadataStore {
         // Metadata - node id, value, user data
         Vec<UpdateMetadata>,
     )> {
+        let (store, recovered, _stats) =
+            MetadataStore::recover_with_stats(storage_directory)?;
+        Ok((store, recovered))
+    }
+
+    /// Like `recover`, but also returns what recovery had to do.
+    pub(crate) fn recover_with_stats<P: AsRef<Path>>(
+        storage_directory: P,
+    ) -> io::Result<(MetadataStore, Vec<UpdateMetadata>, MetadataRecoveryStats)>
+    {
         use fs2::FileExt;
 
         // TODO NOCOMMIT
@@ -309,9 +598,23 @@ impl MetadataStore {
         let recovery =
             MetadataStore::recover_inner(&storage_directory, &directory_lock)?;
 
+        let accounting = Accounting {
+            live: Mutex::new(
+                recovery
+                    .recovered
+                    .iter()
+                    .map(|update_metadata| update_metadata.object_id())
+                    .collect(),
+            ),
+            snapshot_entries: PortableAtomicU64::new(recovery.recovered.len() as u64),
+            last_compaction: Mutex::new(Some(SystemTime::now())),
+            ..Accounting::default()
+        };
+
         let new_log = LogAndStats {
             log_sequence_number: recovery.id_for_next_log,
             bytes_written: 0,
+            entries: 0,
             file: fallible!(fs::File::create(log_path(
                 path,
                 recovery.id_for_next_log
@@ -327,12 +630,13 @@ impl MetadataStore {
             global_error: Default::default(),
             active_log: Arc::new(Mutex::new(new_log)),
             worker_outbox: tx,
+            accounting: Arc::new(accounting),
         };
 
         let worker_inner = inner.clone();
 
         let spawn_res = std::thread::Builder::new()
-            .name("melange_db_flusher".into())
+            .name("melange-metadata".into())
             .spawn(move || {
                 worker(
                     rx,
@@ -348,7 +652,11 @@ impl MetadataStore {
             )));
         }
 
-        Ok((MetadataStore { inner, is_shut_down: false }, recovery.recovered))
+        Ok((
+            MetadataStore { inner, is_shut_down: false },
+            recovery.recovered,
+            recovery.stats,
+        ))
     }
 
     /// Returns the recovered mappings, the id for the next log file, the highest allocated object id, and the set of free ids
@@ -360,14 +668,19 @@ impl MetadataStore {
 
         debug_log!("opening MetadataStore at {:?}", path);
 
-        let (log_ids, snapshot_id_opt) = enumerate_logs_and_snapshot(path)?;
+        let (log_ids, snapshot_id_opt, incomplete_snapshots_removed) =
+            enumerate_logs_and_snapshot(path)?;
 
-        read_snapshot_and_apply_logs(
+        let mut recovery = read_snapshot_and_apply_logs(
             path,
             log_ids,
             snapshot_id_opt,
             directory_lock,
-        )
+        )?;
+        recovery.stats.incomplete_snapshots_removed =
+            incomplete_snapshots_removed;
+
+        Ok(recovery)
     }
 
     /// Write a batch of metadata. `None` for the second half of the outer tuple represents a
@@ -379,58 +692,50 @@ impl MetadataStore {
         let ret = batch_bytes.len() as u64;
 
         let mut log = self.inner.active_log.lock();
-
-        if let Err(e) = maybe!(log.file.write_all(&batch_bytes)) {
-            self.set_error(&e);
+        let start = maybe!(log.file.stream_position())?;
+
+        let write_res = maybe!(log.file.write_all(&batch_bytes))
+            .and_then(|_| maybe!(log.file.sync_all()))
+            .and_then(|_| self.inner.directory_lock.sync_all());
+
+        if let Err(e) = write_res {
+            // a full filesystem leaves the log as it was, so that the batch
+            // can be written again once space has been released
+            let rolled_back = crate::disk_space::is_full(&e)
+                && log.file.set_len(start).is_ok()
+                && log.file.seek(io::SeekFrom::Start(start)).is_ok();
+            if !rolled_back {
+                self.set_error(&e);
+            }
             return Err(e);
         }
 
-        if let Err(e) = maybe!(log.file.sync_all())
-            .and_then(|_| self.inner.directory_lock.sync_all())
+        log.bytes_written += batch_bytes.len() as u64;
+        log.entries += batch.len() as u64;
+
+        let accounting = &self.inner.accounting;
+        accounting.log_entries.fetch_add(batch.len() as u64, Ordering::AcqRel);
         {
-            self.set_error(&e);
-            return Err(e);
+            let mut live = accounting.live.lock();
+            for update_metadata in batch {
+                match update_metadata {
+                    UpdateMetadata::Store { object_id, .. } => {
+                        live.insert(*object_id);
+                    }
+                    UpdateMetadata::Free { object_id, .. } => {
+                        live.remove(object_id);
+                    }
+                }
+            }
         }
 
-        log.bytes_written += batch_bytes.len() as u64;
-
         if log.bytes_written
             > self.inner.snapshot_size.load(Ordering::Acquire).max(64 * 1024)
         {
-            let next_offset = log.log_sequence_number + 1;
-            let next_path =
-                log_path(&self.inner.storage_directory, next_offset);
-
-            // open new log
-            let mut next_log_file_opts = fs::OpenOptions::new();
-            next_log_file_opts.create(true).read(true).write(true);
-
-            let next_log_file = match maybe!(next_log_file_opts.open(next_path))
-            {
-                Ok(nlf) => nlf,
-                Err(e) => {
-                    self.set_error(&e);
-                    return Err(e);
-                }
-            };
-
-            let next_log_and_stats = LogAndStats {
-                file: next_log_file,
-                log_sequence_number: next_offset,
-                bytes_written: 0,
-            };
-
-            // replace log
-            let old_log_and_stats =
-                std::mem::replace(&mut *log, next_log_and_stats);
-
-            // send to snapshot writer
-            self.inner
-                .worker_outbox
-                .send(WorkerMessage::LogReadyToCompact {
-                    log_and_stats: old_log_and_stats,
-                })
-                .expect("unable to send log to compact to worker");
+            if let Err(e) = self.maintenance().rotate_log(&mut log, None) {
+                self.set_error(&e);
+                return Err(e);
+            }
         }
 
         Ok(ret)
@@ -620,28 +925,40 @@ fn read_frame(
     Ok(ret)
 }
 
-// returns the deduplicated data in this log, along with an optional offset where a
-// final torn write occurred.
-fn read_log(
-    directory_path: &Path,
-    lsn: u64,
-) -> io::Result<FnvHashMap<ObjectId, UpdateMetadata>> {
+// returns the deduplicated data in this log, along with the number of bytes
+// after the last intact batch where a final torn write occurred.
+fn read_log(directory_path: &Path, lsn: u64) -> io::Result<RecoveredLog> {
     trace_log!("reading log {lsn}");
     let mut ret = FnvHashMap::default();
 
     let mut file = fallible!(fs::File::open(log_path(directory_path, lsn)));
+    let file_len = fallible!(file.metadata()).len();
 
     let mut reusable_frame_buffer: Vec<u8> = vec![];
+    let mut batches = 0;
+    let mut intact_len = 0;
 
     while let Ok(frame) = read_frame(&mut file, &mut reusable_frame_buffer) {
+        batches += 1;
+        intact_len += reusable_frame_buffer.len() as u64;
         for update_metadata in frame {
             ret.insert(update_metadata.object_id(), update_metadata);
         }
     }
 
+    let torn_bytes = file_len.saturating_sub(intact_len);
+    if torn_bytes > 0 {
+        warn_log!(
+            "discarding {} bytes of a torn write at offset {} of log {}",
+            torn_bytes,
+            intact_len,
+            lsn
+        );
+    }
+
     trace_log!("recovered {} items in log {}", ret.len(), lsn);
 
-    Ok(ret)
+    Ok(RecoveredLog { data: ret, batches, torn_bytes })
 }
 
 /// returns the data from the snapshot as well as the size of the snapshot
@@ -679,11 +996,14 @@ fn snapshot_path(directory_path: &Path, id: u64, temporary: bool) -> PathBuf {
     }
 }
 
+// returns the log ids, the snapshot id, and the number of incomplete
+// snapshot rewrites that were removed
 fn enumerate_logs_and_snapshot(
     directory_path: &Path,
-) -> io::Result<(BTreeSet<u64>, Option<u64>)> {
+) -> io::Result<(BTreeSet<u64>, Option<u64>, u64)> {
     let mut logs = BTreeSet::new();
     let mut snapshot: Option<u64> = None;
+    let mut incomplete_snapshots_removed = 0;
 
     for dir_entry_res in fallible!(fs::read_dir(directory_path)) {
         let dir_entry = fallible!(dir_entry_res);
@@ -700,6 +1020,7 @@ fn enumerate_logs_and_snapshot(
         if file_name.ends_with(TMP_SUFFIX) {
             warn_log!("removing incomplete snapshot rewrite {file_name:?}");
             fallible!(fs::remove_file(directory_path.join(file_name)));
+            incomplete_snapshots_removed += 1;
         } else if file_name.starts_with(LOG_PREFIX) {
             let start = LOG_PREFIX.len() + 1;
             let stop = start + 16;
@@ -751,7 +1072,7 @@ fn enumerate_logs_and_snapshot(
     }
     logs.retain(|l| *l > snap_id);
 
-    Ok((logs, snapshot))
+    Ok((logs, snapshot, incomplete_snapshots_removed))
 }
 
 fn read_snapshot_and_apply_logs(
@@ -774,9 +1095,7 @@ fn read_snapshot_and_apply_logs(
 
     let mut max_log_id = snapshot_id_opt.unwrap_or(0);
 
-    let log_data_res: io::Result<
-        Vec<(u64, FnvHashMap<ObjectId, UpdateMetadata>)>,
-    > = (&log_ids) //.iter().collect::<Vec<_>>())
+    let log_data_res: io::Result<Vec<(u64, RecoveredLog)>> = (&log_ids) //.iter().collect::<Vec<_>>())
         .into_par_iter()
         .map(move |log_id| {
             if let Some(snapshot_id) = snapshot_id_opt {
@@ -794,10 +1113,22 @@ fn read_snapshot_and_apply_logs(
 
     trace_log!("recovered snapshot contains {recovered:?}");
 
+    let mut stats = MetadataRecoveryStats {
+        snapshot_recovered: snapshot_id_opt.is_some(),
+        ..MetadataRecoveryStats::default()
+    };
+
     for (log_id, log_datum) in log_data_res? {
         max_log_id = max_log_id.max(log_id);
 
-        for (object_id, update_metadata) in log_datum {
+        stats.logs_replayed += 1;
+        stats.batches_replayed += log_datum.batches;
+        if log_datum.torn_bytes > 0 {
+            stats.torn_writes_truncated += 1;
+            stats.torn_bytes_truncated += log_datum.torn_bytes;
+        }
+
+        for (object_id, update_metadata) in log_datum.data {
             if matches!(update_metadata, UpdateMetadata::Store { .. }) {
                 recovered.insert(object_id, update_metadata);
             } else {
@@ -828,10 +1159,19 @@ fn read_snapshot_and_apply_logs(
     let mut snapshot_file =
         fallible!(snapshot_file_opts.open(&new_snapshot_tmp_path));
 
-    fallible!(snapshot_file.write_all(&new_snapshot_data));
+    let write_res = snapshot_file
+        .write_all(&new_snapshot_data)
+        .and_then(|_| snapshot_file.sync_all());
     drop(new_snapshot_data);
 
-    fallible!(snapshot_file.sync_all());
+    if let Err(e) = write_res {
+        // a full filesystem is retried by the worker, don't keep the
+        // partial snapshot occupying space until then
+        if crate::disk_space::is_full(&e) {
+            let _ = fs::remove_file(&new_snapshot_tmp_path);
+        }
+        return Err(annotate!(e));
+    }
 
     let new_snapshot_path = snapshot_path(path, max_log_id, false);
     trace_log!("renaming written snapshot to {new_snapshot_path:?}");
@@ -848,9 +1188,12 @@ fn read_snapshot_and_apply_logs(
         fallible!(fs::remove_file(old_snapshot_path));
     }
 
+    stats.recovered_to_lsn = max_log_id;
+
     Ok(MetadataRecovery {
         recovered,
         id_for_next_log: max_log_id + 1,
         snapshot_size,
+        stats,
     })
 }
@@ -0,0 +1,204 @@
+//! sled 数据库迁移工具
+//!
+//! 需要启用 `sled-import` 特性。通过 sled 0.34 打开已有的数据库，
+//! 枚举其中所有的树并按名称在 melange_db 中重建，然后按键的顺序批量写入。
+//! sled 的默认树会被写入 melange_db 的默认树。
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use crate::{Batch, Config, Db, debug_log, info_log, warn_log};
+
+/// sled 默认树的名称
+const SLED_DEFAULT_TREE_NAME: &[u8] = b"__sled__default";
+
+/// 迁移选项
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    /// 树重命名映射：sled 中的树名 -> melange_db 中的树名
+    pub tree_renames: HashMap<Vec<u8>, Vec<u8>>,
+    /// 仅统计条目数和字节数，不写入 melange_db
+    pub dry_run: bool,
+    /// 允许的最大键长度（字节），超过的键会被跳过并记录在报告中
+    pub max_key_len: usize,
+    /// 每个批次写入的条目数
+    pub batch_size: usize,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        Self {
+            tree_renames: HashMap::new(),
+            dry_run: false,
+            max_key_len: u16::MAX as usize,
+            batch_size: 1024,
+        }
+    }
+}
+
+/// 单个树的迁移结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeMigrationReport {
+    /// sled 中的树名
+    pub source_name: Vec<u8>,
+    /// melange_db 中的树名，`None` 表示默认树
+    pub target_name: Option<Vec<u8>>,
+    /// 已迁移（或在 dry-run 模式下将被迁移）的条目数
+    pub items: u64,
+    /// 已迁移的键和值的总字节数
+    pub bytes: u64,
+    /// 被跳过的键
+    pub skipped_keys: Vec<Vec<u8>>,
+}
+
+/// 迁移报告
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// 是否为 dry-run
+    pub dry_run: bool,
+    /// 每个树的迁移结果
+    pub trees: Vec<TreeMigrationReport>,
+}
+
+impl MigrationReport {
+    /// 所有树迁移的条目总数
+    pub fn total_items(&self) -> u64 {
+        self.trees.iter().map(|t| t.items).sum()
+    }
+
+    /// 所有树迁移的总字节数
+    pub fn total_bytes(&self) -> u64 {
+        self.trees.iter().map(|t| t.bytes).sum()
+    }
+
+    /// 所有树跳过的键总数
+    pub fn total_skipped(&self) -> usize {
+        self.trees.iter().map(|t| t.skipped_keys.len()).sum()
+    }
+}
+
+/// 将位于 `sled_path` 的 sled 0.34 数据库迁移到由 `config` 指定的 melange_db 数据库。
+///
+/// sled 数据库只会被读取，不会写入任何数据，`sled_path` 不是sled数据库时返回
+/// `NotFound` 错误。dry-run 模式下不会打开
+/// melange_db 数据库，只统计每个树的条目数和字节数。
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use melange_db::migrate::{MigrationOptions, from_sled};
+///
+/// let report = from_sled(
+///     "old_sled_db",
+///     melange_db::Config::new().path("new_melange_db"),
+///     MigrationOptions::default(),
+/// )?;
+///
+/// println!("迁移了 {} 条记录", report.total_items());
+/// # Ok(()) }
+/// ```
+pub fn from_sled<P: AsRef<Path>>(
+    sled_path: P,
+    config: Config,
+    options: MigrationOptions,
+) -> io::Result<MigrationReport> {
+    // sled在路径不存在时会创建一个空的数据库，先检查它的配置文件
+    let sled_path = sled_path.as_ref();
+    if !sled_path.join("conf").is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} 不是一个sled数据库", sled_path.display()),
+        ));
+    }
+    let sled_db = sled::Config::new().path(sled_path).open()?;
+
+    let db: Option<Db<1024>> =
+        if options.dry_run { None } else { Some(config.open()?) };
+
+    let mut report =
+        MigrationReport { dry_run: options.dry_run, trees: vec![] };
+
+    for source_name in sled_db.tree_names() {
+        let source_name = source_name.to_vec();
+        let sled_tree = sled_db.open_tree(&source_name)?;
+
+        let target_name = match options.tree_renames.get(&source_name) {
+            Some(renamed) => Some(renamed.clone()),
+            None if source_name == SLED_DEFAULT_TREE_NAME => None,
+            None => Some(source_name.clone()),
+        };
+
+        let target_tree = match (&db, &target_name) {
+            (Some(db), Some(name)) => Some(db.open_tree(name)?),
+            (Some(db), None) => Some((**db).clone()),
+            (None, _) => None,
+        };
+
+        let mut tree_report = TreeMigrationReport {
+            source_name,
+            target_name,
+            ..TreeMigrationReport::default()
+        };
+
+        let mut batch = Batch::default();
+        let mut batch_len = 0;
+
+        for kv_res in sled_tree.iter() {
+            let (k, v) = kv_res?;
+
+            if k.len() > options.max_key_len {
+                warn_log!(
+                    "跳过长度为 {} 的键，超过最大键长度 {}",
+                    k.len(),
+                    options.max_key_len
+                );
+                tree_report.skipped_keys.push(k.to_vec());
+                continue;
+            }
+
+            tree_report.items += 1;
+            tree_report.bytes += (k.len() + v.len()) as u64;
+
+            if let Some(tree) = &target_tree {
+                batch.insert(&*k, &*v);
+                batch_len += 1;
+
+                if batch_len >= options.batch_size {
+                    tree.apply_batch(std::mem::take(&mut batch))?;
+                    batch_len = 0;
+                }
+            }
+        }
+
+        if let Some(tree) = &target_tree
+            && batch_len > 0
+        {
+            tree.apply_batch(batch)?;
+        }
+
+        debug_log!(
+            "已迁移树 {:?}: {} 条记录, {} 字节, 跳过 {} 个键",
+            String::from_utf8_lossy(&tree_report.source_name),
+            tree_report.items,
+            tree_report.bytes,
+            tree_report.skipped_keys.len()
+        );
+
+        report.trees.push(tree_report);
+    }
+
+    if let Some(db) = db {
+        db.flush()?;
+    }
+
+    info_log!(
+        "sled 迁移完成: {} 个树, {} 条记录, 跳过 {} 个键",
+        report.trees.len(),
+        report.total_items(),
+        report.total_skipped()
+    );
+
+    Ok(report)
+}
@@ -1,16 +1,27 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::io;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
-use crate::{debug_log, trace_log, warn_log, error_log, info_log, smart_flush::WriteLoadStats};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{debug_log, trace_log, error_log, smart_flush::{SmartFlushConfig, WriteLoadStats}};
+use crate::admission::TinyLfu;
+use crate::backup::{BackupEpoch, WriteTracker};
+use crate::flush_group::FlushGroups;
+use crate::blob_store::BlobStore;
+use crate::flush_debug::{
+    DirtyCollection, FlushDebugReport, FlushProgress, FlushStage, FlusherStatus,
+};
+use crate::op_journal::OpJournal;
+use crate::compression_dictionary::CompressionDictionaries;
 use std::time::{Duration, Instant};
 
 use cache_advisor::CacheAdvisor;
 use concurrent_map::{ConcurrentMap, Minimum};
 use fault_injection::annotate;
 use inline_array::InlineArray;
-use parking_lot::RwLock;
+use parking_lot::{Condvar, Mutex, RwLock};
+use rayon::prelude::*;
 
 use crate::*;
 
@@ -19,6 +30,19 @@ pub struct CacheStats {
     pub cache_hits: u64,
     pub cache_misses: u64,
     pub cache_hit_ratio: f32,
+    /// Leaf reads that did not promote the leaf in the cache because of a
+    /// non-default `CachePolicy`.
+    pub cache_bypassed_reads: u64,
+    /// Leaf reads that were served from disk without caching the leaf
+    /// because `AdmissionPolicy::TinyLfu` estimated it to be accessed less
+    /// often than the leaves it would have displaced.
+    pub cache_admission_rejections: u64,
+    /// Leaves that were paged into the cache ahead of a scan by
+    /// `IterOptions::read_ahead_leaves`.
+    pub read_ahead_leaves: u64,
+    /// The number of times the bloom filter was rebuilt at a larger
+    /// capacity because its false positive rate degraded.
+    pub bloom_filter_resizes: u64,
     pub max_read_io_latency_us: u64,
     pub sum_read_io_latency_us: u64,
     pub deserialization_latency_max_us: u64,
@@ -26,8 +50,26 @@ pub struct CacheStats {
     pub heap: HeapStats,
     pub flush_max: FlushStats,
     pub flush_sum: FlushStats,
+    /// The number of flushes that were run.
+    pub flushes: u64,
+    /// The number of `Tree::flush` and `Db::flush_all` calls that returned
+    /// without running a flush of their own because a concurrent call's
+    /// flush already covered their writes.
+    pub flushes_coalesced: u64,
     pub compacted_heap_slots: u64,
     pub tree_leaves_merged: u64,
+    /// The number of values that were individually compressed with the
+    /// configured `CompressionAlgorithm` when their leaf was written.
+    pub values_compressed: u64,
+    /// The number of values that were written uncompressed because they
+    /// were below `Config::value_compression_threshold` or did not shrink.
+    pub values_stored_uncompressed: u64,
+    /// The cache capacity currently in effect, which may have been changed
+    /// by `Db::set_cache_capacity_bytes` since the database was opened.
+    pub cache_capacity_bytes: usize,
+    /// The flush IO rate limit currently in effect, which may have been
+    /// changed by `Db::set_flush_io_rate_limit`.
+    pub flush_io_rate_limit: Option<u64>,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -38,6 +80,9 @@ pub struct FlushStats {
     pub compute_defrag_latency: Duration,
     pub storage_latency: Duration,
     pub post_write_eviction_latency: Duration,
+    /// Time spent waiting after the write to stay under
+    /// `Config::flush_io_rate_limit`.
+    pub rate_limit_latency: Duration,
     pub objects_flushed: u64,
     pub write_batch: WriteBatchStats,
 }
@@ -63,6 +108,9 @@ impl FlushStats {
             post_write_eviction_latency: self
                 .post_write_eviction_latency
                 .add(other.post_write_eviction_latency),
+            rate_limit_latency: self
+                .rate_limit_latency
+                .add(other.rate_limit_latency),
             objects_flushed: self.objects_flushed.add(other.objects_flushed),
             write_batch: self.write_batch.sum(&other.write_batch),
         }
@@ -85,6 +133,9 @@ impl FlushStats {
             post_write_eviction_latency: self
                 .post_write_eviction_latency
                 .max(other.post_write_eviction_latency),
+            rate_limit_latency: self
+                .rate_limit_latency
+                .max(other.rate_limit_latency),
             objects_flushed: self.objects_flushed.max(other.objects_flushed),
             write_batch: self.write_batch.max(&other.write_batch),
         }
@@ -109,12 +160,22 @@ pub enum Dirty<const LEAF_FANOUT: usize> {
         object_id: ObjectId,
         collection_id: CollectionId,
     },
+    /// An object of a cloned tree that shares the stored copy of an object
+    /// of the source tree, see `Tree::share_leaves`. A write to the object
+    /// in the same epoch replaces this with `NotYetSerialized`.
+    Shared {
+        object_id: ObjectId,
+        collection_id: CollectionId,
+        low_key: InlineArray,
+        location: NonZeroU64,
+    },
 }
 
 impl<const LEAF_FANOUT: usize> Dirty<LEAF_FANOUT> {
     pub fn is_final_state(&self) -> bool {
         match self {
             Dirty::NotYetSerialized { .. } => false,
+            Dirty::Shared { .. } => false,
             Dirty::CooperativelySerialized { .. } => true,
             Dirty::MergedAndDeleted { .. } => true,
         }
@@ -128,14 +189,75 @@ struct FlushStatTracker {
     max: FlushStats,
 }
 
+/// Lets concurrent callers of `ObjectCache::flush_coalesced` share flushes.
+#[derive(Debug, Default)]
+struct FlushCoalescer {
+    state: Mutex<CoalescerState>,
+    done: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct CoalescerState {
+    /// Whether a caller is currently running a flush on behalf of the
+    /// others.
+    leader_active: bool,
+    /// Every write in this epoch or an earlier one has been flushed by a
+    /// coalesced flush.
+    flushed_through: u64,
+    coalesced: u64,
+}
+
+/// Clears `leader_active` and wakes the waiting callers when the leader's
+/// flush returns or panics.
+struct CoalescerLeader<'a> {
+    coalescer: &'a FlushCoalescer,
+    covered: Option<u64>,
+}
+
+impl Drop for CoalescerLeader<'_> {
+    fn drop(&mut self) {
+        let mut state = self.coalescer.state.lock();
+        state.leader_active = false;
+        if let Some(covered) = self.covered {
+            state.flushed_through = state.flushed_through.max(covered);
+        }
+        drop(state);
+        self.coalescer.done.notify_all();
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct ReadStatTracker {
-    pub cache_hits: AtomicU64,
-    pub cache_misses: AtomicU64,
-    pub max_read_io_latency_us: AtomicU64,
-    pub sum_read_io_latency_us: AtomicU64,
-    pub max_deserialization_latency_us: AtomicU64,
-    pub sum_deserialization_latency_us: AtomicU64,
+    pub cache_hits: PortableAtomicU64,
+    pub cache_misses: PortableAtomicU64,
+    pub cache_bypassed_reads: PortableAtomicU64,
+    pub cache_admission_rejections: PortableAtomicU64,
+    pub read_ahead_leaves: PortableAtomicU64,
+    pub max_read_io_latency_us: PortableAtomicU64,
+    pub sum_read_io_latency_us: PortableAtomicU64,
+    pub max_deserialization_latency_us: PortableAtomicU64,
+    pub sum_deserialization_latency_us: PortableAtomicU64,
+}
+
+/// The number of accesses `CacheAdvisor` buffers before applying them.
+const ADVISOR_QUEUE_ITEMS: usize = 32;
+
+/// The maximum number of flushes `compact_heap` runs. Objects that are
+/// written to while they are being moved can take more than one round.
+const MAX_COMPACTION_ROUNDS: usize = 8;
+
+/// The settings that can be changed while the database is open. Each one
+/// is replaced as a whole, so readers observe either the old or the new
+/// value, never a mix of the two.
+#[derive(Debug)]
+pub(crate) struct RuntimeConfig {
+    smart_flush: RwLock<SmartFlushConfig>,
+    flush_io_rate_limit: RwLock<Option<u64>>,
+    cache_capacity_bytes: AtomicUsize,
+    /// The advisor that every `ObjectCache` clone should be using, and a
+    /// generation that is bumped whenever the cache is resized so that
+    /// clones know to pick up the new advisor.
+    cache_advisor: RwLock<(u64, CacheAdvisor)>,
 }
 
 pub struct ObjectCache<const LEAF_FANOUT: usize> {
@@ -148,19 +270,74 @@ pub struct ObjectCache<const LEAF_FANOUT: usize> {
         EBR_LOCAL_GC_BUFFER_SIZE,
     >,
     heap: Heap,
-    cache_advisor: RwLock<CacheAdvisor>,
+    cache_advisor: RwLock<(u64, CacheAdvisor)>,
+    runtime: Arc<RuntimeConfig>,
     flush_epoch: FlushEpochTracker,
     dirty: ConcurrentMap<(FlushEpoch, ObjectId), Dirty<LEAF_FANOUT>, 4>,
-    compacted_heap_slots: Arc<AtomicU64>,
-    pub(super) tree_leaves_merged: Arc<AtomicU64>,
+    compacted_heap_slots: Arc<PortableAtomicU64>,
+    /// Set while `compact_heap` runs, so that flushes move every object
+    /// after the first free slot and truncate the slab files.
+    compacting: Arc<AtomicBool>,
+    pub(super) tree_leaves_merged: Arc<PortableAtomicU64>,
+    values_compressed: Arc<PortableAtomicU64>,
+    values_stored_uncompressed: Arc<PortableAtomicU64>,
         invariants: Arc<FlushInvariants>,
     flush_stats: Arc<RwLock<FlushStatTracker>>,
     pub(super) read_stats: Arc<ReadStatTracker>,
     // 优化组件
-    bloom_filter: Arc<RwLock<BloomFilter>>,
+    bloom_filter: Arc<RwLock<BloomFilterState>>,
+    bloom_filter_resizes: Arc<PortableAtomicU64>,
     block_cache: Arc<CacheManager>,
     // 智能flush统计
     write_stats: Arc<WriteLoadStats>,
+    dictionaries: Arc<CompressionDictionaries>,
+    /// The minimum value size to deduplicate for each collection that has
+    /// `TreeOptions::dedup_values` enabled.
+    value_dedup: Arc<RwLock<HashMap<CollectionId, usize>>>,
+    /// The keys pinned with `Tree::pin` in each collection. Leaves holding
+    /// any of them are never paged out.
+    pinned_keys: Arc<RwLock<HashMap<CollectionId, BTreeSet<InlineArray>>>>,
+    /// The admission filter, if `Config::cache_admission` is
+    /// `AdmissionPolicy::TinyLfu`.
+    admission: Option<Arc<Mutex<TinyLfu>>>,
+    /// The flush epoch each object was last written in, for incremental
+    /// backups.
+    write_tracker: Arc<WriteTracker>,
+    /// The flush groups created by `Db::open_tree_in_group`.
+    flush_groups: Arc<FlushGroups>,
+    /// The diagnostic journal, if `Config::op_journal` is set.
+    op_journal: Option<Arc<OpJournal>>,
+    /// Values stored outside of leaves, see `Config::inline_value_threshold`.
+    blobs: Arc<BlobStore>,
+    /// The stage of each running flush, for `Db::debug_flush_state`.
+    flush_progress: Arc<FlushProgress>,
+    /// Liveness of the background flusher thread, if there is one.
+    flusher_status: Arc<FlusherStatus>,
+    /// Shares flushes between concurrent `Tree::flush` calls.
+    flush_coalescer: Arc<FlushCoalescer>,
+    /// The blobs released by each flush whose replaced leaves the heap
+    /// still retains, see `Config::retained_flush_epochs`.
+    retained_blobs: Arc<Mutex<RetainedBlobs>>,
+    /// The threads that serialize the dirty objects of each flush, if
+    /// `Config::flush_threads` is above 1.
+    flush_pool: Option<Arc<rayon::ThreadPool>>,
+    /// The threads that read leaves ahead of range scans, started on the
+    /// first scan that reads ahead.
+    read_ahead_pool: Arc<OnceLock<rayon::ThreadPool>>,
+    // `Db::set_serialize_latency_for_testing`
+    #[cfg(feature = "for-internal-testing-only")]
+    serialize_latency_for_testing: Arc<PortableAtomicU64>,
+}
+
+/// The ids of the blobs released by the flush of each epoch.
+type RetainedBlobs = VecDeque<(FlushEpoch, Vec<u64>)>;
+
+/// The bloom filter consulted by reads, and the larger filter that
+/// replaces it while a resize is in progress.
+#[derive(Debug)]
+struct BloomFilterState {
+    current: BloomFilter,
+    rebuilding: Option<BloomFilter>,
 }
 
 impl<const LEAF_FANOUT: usize> std::panic::RefUnwindSafe
@@ -176,16 +353,37 @@ impl<const LEAF_FANOUT: usize> Clone for ObjectCache<LEAF_FANOUT> {
             object_id_index: self.object_id_index.clone(),
             heap: self.heap.clone(),
             cache_advisor: RwLock::new(self.cache_advisor.read().clone()),
+            runtime: self.runtime.clone(),
             flush_epoch: self.flush_epoch.clone(),
             dirty: self.dirty.clone(),
             compacted_heap_slots: self.compacted_heap_slots.clone(),
+            compacting: self.compacting.clone(),
             tree_leaves_merged: self.tree_leaves_merged.clone(),
+            values_compressed: self.values_compressed.clone(),
+            values_stored_uncompressed: self.values_stored_uncompressed.clone(),
                         invariants: self.invariants.clone(),
             flush_stats: self.flush_stats.clone(),
             read_stats: self.read_stats.clone(),
             bloom_filter: self.bloom_filter.clone(),
+            bloom_filter_resizes: self.bloom_filter_resizes.clone(),
             block_cache: self.block_cache.clone(),
             write_stats: self.write_stats.clone(),
+            dictionaries: self.dictionaries.clone(),
+            value_dedup: self.value_dedup.clone(),
+            pinned_keys: self.pinned_keys.clone(),
+            admission: self.admission.clone(),
+            write_tracker: self.write_tracker.clone(),
+            flush_groups: self.flush_groups.clone(),
+            op_journal: self.op_journal.clone(),
+            blobs: self.blobs.clone(),
+            flush_progress: self.flush_progress.clone(),
+            flusher_status: self.flusher_status.clone(),
+            flush_coalescer: self.flush_coalescer.clone(),
+            retained_blobs: self.retained_blobs.clone(),
+            flush_pool: self.flush_pool.clone(),
+            read_ahead_pool: self.read_ahead_pool.clone(),
+            #[cfg(feature = "for-internal-testing-only")]
+            serialize_latency_for_testing: self.serialize_latency_for_testing.clone(),
         }
     }
 }
@@ -195,16 +393,17 @@ unsafe impl<const LEAF_FANOUT: usize> Send for ObjectCache<LEAF_FANOUT> {}
 unsafe impl<const LEAF_FANOUT: usize> Sync for ObjectCache<LEAF_FANOUT> {}
 
 impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
-    /// Returns the recovered ObjectCache, the tree indexes, and a bool signifying whether the system
-    /// was recovered or not
+    /// Returns the recovered ObjectCache, the tree indexes, and a report of
+    /// what recovery did if the system was recovered from a previous
+    /// instance
     pub fn recover(
         config: &Config,
     ) -> io::Result<(
         ObjectCache<LEAF_FANOUT>,
         HashMap<CollectionId, Index<LEAF_FANOUT>>,
-        bool,
+        Option<RecoveryReport>,
     )> {
-        let HeapRecovery { heap, recovered_nodes, was_recovered } =
+        let HeapRecovery { heap, recovered_nodes, was_recovered, report } =
             Heap::recover(LEAF_FANOUT, config)?;
 
         let (object_id_index, indices) = initialize(&recovered_nodes, &heap);
@@ -233,7 +432,13 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         }
 
         // 初始化优化组件
-        let bloom_filter = Arc::new(RwLock::new(BloomFilter::new(1_000_000, 0.01)));
+        let bloom_filter = Arc::new(RwLock::new(BloomFilterState {
+            current: BloomFilter::new(
+                config.bloom_filter_capacity.max(config.expected_entries).max(1),
+                0.01,
+            ),
+            rebuilding: None,
+        }));
         let block_cache_config = CacheConfig {
             max_size: config.cache_capacity_bytes / 4, // 使用25%的缓存容量
             block_size: 4096,
@@ -242,43 +447,248 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         };
         let block_cache = Arc::new(CacheManager::new(block_cache_config));
         let write_stats = Arc::new(WriteLoadStats::new());
+        let dictionaries = Arc::new(CompressionDictionaries::open(
+            &config.path,
+            config.compression_dictionary.as_ref(),
+            config.zstd_compression_level,
+        )?);
+
+        let cache_advisor = (
+            0,
+            CacheAdvisor::new(
+                config.cache_capacity_bytes.max(256),
+                config.entry_cache_percent.min(80),
+            ),
+        );
+        let runtime = Arc::new(RuntimeConfig {
+            smart_flush: RwLock::new(config.smart_flush_config.clone()),
+            flush_io_rate_limit: RwLock::new(config.flush_io_rate_limit),
+            cache_capacity_bytes: AtomicUsize::new(
+                config.cache_capacity_bytes.max(256),
+            ),
+            cache_advisor: RwLock::new(cache_advisor.clone()),
+        });
 
         let pc = ObjectCache {
             config: config.clone(),
             object_id_index,
-            cache_advisor: RwLock::new(CacheAdvisor::new(
-                config.cache_capacity_bytes.max(256),
-                config.entry_cache_percent.min(80),
-            )),
+            cache_advisor: RwLock::new(cache_advisor),
+            runtime,
             global_error: heap.get_global_error_arc(),
             heap,
             dirty: Default::default(),
             flush_epoch: Default::default(),
                         compacted_heap_slots: Arc::default(),
+            compacting: Arc::default(),
             tree_leaves_merged: Arc::default(),
+            values_compressed: Arc::default(),
+            values_stored_uncompressed: Arc::default(),
             invariants: Arc::default(),
             flush_stats: Arc::default(),
             read_stats: Arc::default(),
             bloom_filter,
+            bloom_filter_resizes: Arc::default(),
             block_cache,
             write_stats,
+            dictionaries,
+            value_dedup: Arc::default(),
+            pinned_keys: Arc::default(),
+            write_tracker: Arc::default(),
+            flush_groups: Arc::default(),
+            op_journal: match config.op_journal {
+                Some(op_journal) => Some(Arc::new(OpJournal::open(&config.path, op_journal)?)),
+                None => None,
+            },
+            blobs: Arc::new(BlobStore::open(&config.path, config.inline_value_threshold)?),
+            flush_progress: Arc::default(),
+            flusher_status: Arc::default(),
+            flush_coalescer: Arc::default(),
+            retained_blobs: Arc::default(),
+            flush_pool: if config.flush_threads > 1 {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(config.flush_threads)
+                    .thread_name(|i| format!("melange-flush-{}", i))
+                    .start_handler(crate::db::flusher_pool_start_handler(config, "flush"))
+                    .build()
+                    .map_err(io::Error::other)?;
+                Some(Arc::new(pool))
+            } else {
+                None
+            },
+            read_ahead_pool: Arc::default(),
+            #[cfg(feature = "for-internal-testing-only")]
+            serialize_latency_for_testing: Arc::default(),
+            admission: match config.cache_admission {
+                AdmissionPolicy::Always => None,
+                AdmissionPolicy::TinyLfu => Some(Arc::new(Mutex::new(
+                    TinyLfu::new(
+                        config.cache_capacity_bytes.max(256),
+                        config.entry_cache_percent.min(80),
+                    ),
+                ))),
+            },
+        };
+
+        Ok((pc, indices, was_recovered.then_some(report)))
+    }
+
+    /// Records how the values of a leaf in the value-compressed format were stored.
+    pub(crate) fn record_stored_values(&self, compressed: u64, uncompressed: u64) {
+        self.values_compressed.fetch_add(compressed, Ordering::Release);
+        self.values_stored_uncompressed
+            .fetch_add(uncompressed, Ordering::Release);
+    }
+
+    pub(crate) fn dictionaries(&self) -> &CompressionDictionaries {
+        &self.dictionaries
+    }
+
+    pub(crate) fn read_ahead_pool(&self) -> &rayon::ThreadPool {
+        self.read_ahead_pool.get_or_init(|| crate::read_ahead::build_pool(&self.config))
+    }
+
+    pub(crate) fn blobs(&self) -> &BlobStore {
+        &self.blobs
+    }
+
+    /// Enables value deduplication for the leaves of a collection, for
+    /// values of at least `min_value_size` bytes, or disables it with `None`.
+    pub(crate) fn set_value_dedup(
+        &self,
+        collection_id: CollectionId,
+        min_value_size: Option<usize>,
+    ) {
+        let mut value_dedup = self.value_dedup.write();
+        match min_value_size {
+            Some(min_value_size) => {
+                value_dedup.insert(collection_id, min_value_size);
+            }
+            None => {
+                value_dedup.remove(&collection_id);
+            }
+        }
+    }
+
+    /// Returns the minimum value size to deduplicate in the leaves of a
+    /// collection, if value deduplication is enabled for it.
+    pub(crate) fn value_dedup_min_size(
+        &self,
+        collection_id: CollectionId,
+    ) -> Option<usize> {
+        self.value_dedup.read().get(&collection_id).copied()
+    }
+
+    /// Pins `key` of a collection, so that the leaf holding it is not paged
+    /// out. Returns `false` if it was already pinned.
+    pub(crate) fn pin(&self, collection_id: CollectionId, key: &[u8]) -> bool {
+        self.pinned_keys
+            .write()
+            .entry(collection_id)
+            .or_default()
+            .insert(InlineArray::from(key))
+    }
+
+    /// Unpins `key` of a collection. Returns `false` if it was not pinned.
+    pub(crate) fn unpin(&self, collection_id: CollectionId, key: &[u8]) -> bool {
+        let mut pinned_keys = self.pinned_keys.write();
+        let Some(keys) = pinned_keys.get_mut(&collection_id) else {
+            return false;
+        };
+        let removed = keys.remove(key);
+        if keys.is_empty() {
+            pinned_keys.remove(&collection_id);
+        }
+        removed
+    }
+
+    /// Forgets the pinned keys of a dropped collection.
+    pub(crate) fn unpin_collection(&self, collection_id: CollectionId) {
+        self.pinned_keys.write().remove(&collection_id);
+    }
+
+    /// Returns `true` if the given leaf of a collection holds a pinned key.
+    pub(crate) fn is_pinned(
+        &self,
+        collection_id: CollectionId,
+        leaf: &Leaf<LEAF_FANOUT>,
+    ) -> bool {
+        let pinned_keys = self.pinned_keys.read();
+        let Some(keys) = pinned_keys.get(&collection_id) else {
+            return false;
         };
+        keys.range(leaf.lo.clone()..).next().is_some_and(|key| {
+            leaf.hi.as_ref().is_none_or(|hi| key < hi)
+        })
+    }
+
+    /// The in-memory size of the cached leaves that hold a pinned key.
+    pub(crate) fn pinned_bytes(&self) -> usize {
+        if self.pinned_keys.read().is_empty() {
+            return 0;
+        }
+        self.object_id_index
+            .iter()
+            .filter_map(|(_, node)| {
+                let read = node.inner.read();
+                let leaf = read.leaf.as_ref()?;
+                (leaf.deleted.is_none() && self.is_pinned(node.collection_id, leaf))
+                    .then_some(leaf.in_memory_size)
+            })
+            .sum()
+    }
+
+    /// The most that `Tree::pin` may pin, `Config::max_pinned_cache_percent`
+    /// of the current cache capacity.
+    pub(crate) fn max_pinned_bytes(&self) -> usize {
+        let percent = self.config.max_pinned_cache_percent.min(100) as usize;
+        self.cache_capacity_bytes() / 100 * percent
+    }
 
-        Ok((pc, indices, was_recovered))
+    /// Returns `true` if reads of uncached leaves have to be admitted with
+    /// `admit` before paging them in.
+    pub(crate) fn uses_admission_filter(&self) -> bool {
+        self.admission.is_some()
+    }
+
+    /// Decides whether an uncached leaf that is about to be read should be
+    /// paged into the cache.
+    pub(crate) fn admit(&self, object_id: ObjectId) -> bool {
+        match &self.admission {
+            Some(admission) => admission.lock().admit(*object_id),
+            None => true,
+        }
     }
 
     pub fn is_clean(&self) -> bool {
         self.dirty.is_empty()
     }
 
+    /// 关闭前最后一次flush成功之后调用，记录数据库已正常关闭，
+    /// 下次打开时不必读取并校验所有叶子节点。之后不能再写入堆文件
+    pub(crate) fn mark_clean_shutdown(&self) {
+        if !self.is_clean() {
+            return;
+        }
+        if let Err(e) = self.heap.mark_clean_shutdown() {
+            warn_log!("无法记录数据库已正常关闭，下次打开时将校验所有叶子节点: {:?}", e);
+        }
+    }
+
     pub fn read(&self, object_id: ObjectId) -> Option<io::Result<Vec<u8>>> {
         match self.heap.read(object_id) {
             Some(Ok(buf)) => Some(Ok(buf)),
+            Some(Err(e)) if CorruptionError::from_io_error(&e).is_some() => {
+                Some(Err(e))
+            }
             Some(Err(e)) => Some(Err(annotate!(e))),
             None => None,
         }
     }
 
+    pub(crate) fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
     pub fn stats(&self) -> CacheStats {
         let flush_stats = { *self.flush_stats.read() };
         let cache_hits = self.read_stats.cache_hits.load(Ordering::Acquire);
@@ -290,13 +700,36 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             cache_hits,
             cache_misses,
             cache_hit_ratio,
+            cache_bypassed_reads: self
+                .read_stats
+                .cache_bypassed_reads
+                .load(Ordering::Acquire),
+            cache_admission_rejections: self
+                .read_stats
+                .cache_admission_rejections
+                .load(Ordering::Acquire),
+            read_ahead_leaves: self
+                .read_stats
+                .read_ahead_leaves
+                .load(Ordering::Acquire),
+            bloom_filter_resizes: self
+                .bloom_filter_resizes
+                .load(Ordering::Acquire),
             compacted_heap_slots: self
                 .compacted_heap_slots
                 .load(Ordering::Acquire),
             tree_leaves_merged: self.tree_leaves_merged.load(Ordering::Acquire),
+            values_compressed: self.values_compressed.load(Ordering::Acquire),
+            values_stored_uncompressed: self
+                .values_stored_uncompressed
+                .load(Ordering::Acquire),
+            cache_capacity_bytes: self.cache_capacity_bytes(),
+            flush_io_rate_limit: self.flush_io_rate_limit(),
             heap: self.heap.stats(),
             flush_max: flush_stats.max,
             flush_sum: flush_stats.sum,
+            flushes: flush_stats.count,
+            flushes_coalesced: self.flush_coalescer.state.lock().coalesced,
             deserialization_latency_max_us: self
                 .read_stats
                 .max_deserialization_latency_us
@@ -318,11 +751,78 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
 
     // 优化组件访问方法
     pub fn bloom_filter_contains(&self, key: &[u8]) -> bool {
-        self.bloom_filter.read().contains(key)
+        self.bloom_filter.read().current.contains(key)
     }
 
     pub fn bloom_filter_insert(&self, key: &[u8]) {
-        self.bloom_filter.write().insert(key);
+        let mut state = self.bloom_filter.write();
+        state.current.insert(key);
+        if let Some(rebuilding) = &mut state.rebuilding {
+            rebuilding.insert(key);
+        }
+    }
+
+    pub(crate) fn bloom_filter_needs_resize(&self) -> bool {
+        self.bloom_filter.read().current.needs_resize()
+    }
+
+    /// Replaces the bloom filter with a larger one containing the keys
+    /// produced by `for_each_live_key`. Keys inserted concurrently while
+    /// the new filter is being built are added to both filters, so the
+    /// new filter has no false negatives for keys inserted through
+    /// `bloom_filter_insert` once it is installed.
+    pub(crate) fn rebuild_bloom_filter<F>(
+        &self,
+        for_each_live_key: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(&mut dyn FnMut(&[u8])) -> io::Result<()>,
+    {
+        const KEYS_PER_LOCK: usize = 1024;
+
+        {
+            let mut state = self.bloom_filter.write();
+            if state.rebuilding.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "布隆过滤器正在被重建",
+                ));
+            }
+            let new_filter = state.current.resized_empty();
+            state.rebuilding = Some(new_filter);
+        }
+
+        // insert live keys in chunks to avoid taking the lock per key
+        let mut pending: Vec<InlineArray> = Vec::with_capacity(KEYS_PER_LOCK);
+        let flush_pending = |pending: &mut Vec<InlineArray>| {
+            let mut state = self.bloom_filter.write();
+            let rebuilding = state.rebuilding.as_mut().unwrap();
+            for key in pending.drain(..) {
+                rebuilding.insert(&key);
+            }
+        };
+
+        let res = for_each_live_key(&mut |key| {
+            pending.push(key.into());
+            if pending.len() >= KEYS_PER_LOCK {
+                flush_pending(&mut pending);
+            }
+        });
+        flush_pending(&mut pending);
+
+        let mut state = self.bloom_filter.write();
+        let rebuilt = state.rebuilding.take().unwrap();
+        res?;
+
+        debug_log!(
+            "布隆过滤器重建完成: {} 个元素, 预计误判率 {:.4}",
+            rebuilt.len(),
+            rebuilt.current_false_positive_rate()
+        );
+        state.current = rebuilt;
+        self.bloom_filter_resizes.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
     }
 
     pub fn get_block_cache_stats(&self) -> block_cache::CacheStats {
@@ -337,6 +837,102 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         self.write_stats.clone()
     }
 
+    pub(crate) fn smart_flush_config(&self) -> SmartFlushConfig {
+        self.runtime.smart_flush.read().clone()
+    }
+
+    pub(crate) fn set_smart_flush_config(&self, config: SmartFlushConfig) {
+        *self.runtime.smart_flush.write() = config;
+    }
+
+    pub(crate) fn flush_io_rate_limit(&self) -> Option<u64> {
+        *self.runtime.flush_io_rate_limit.read()
+    }
+
+    pub(crate) fn set_flush_io_rate_limit(&self, limit: Option<u64>) {
+        *self.runtime.flush_io_rate_limit.write() = limit;
+    }
+
+    pub(crate) fn cache_capacity_bytes(&self) -> usize {
+        self.runtime.cache_capacity_bytes.load(Ordering::Acquire)
+    }
+
+    /// The in-memory size of every leaf that is currently cached.
+    pub(crate) fn resident_bytes(&self) -> usize {
+        self.object_id_index
+            .iter()
+            .filter_map(|(_, node)| {
+                node.inner.read().leaf.as_ref().map(|leaf| leaf.in_memory_size)
+            })
+            .sum()
+    }
+
+    /// An estimate of the memory used by the in-memory indexes: the
+    /// per-tree index from low key to leaf and the object id index, with
+    /// the leaf handles they share. Cached leaves are not included, and
+    /// neither are the internal nodes of the maps.
+    pub(crate) fn index_bytes(&self) -> usize {
+        let per_leaf = 2 * size_of::<Object<LEAF_FANOUT>>()
+            + size_of::<ObjectId>()
+            + size_of::<InlineArray>()
+            + size_of::<RwLock<CacheBox<LEAF_FANOUT>>>()
+            + 2 * size_of::<usize>();
+        self.object_id_index
+            .iter()
+            .map(|(_, node)| per_leaf + node.low_key.len())
+            .sum()
+    }
+
+    /// The size of the bloom filter bitmaps, including the one being
+    /// rebuilt, if any.
+    pub(crate) fn bloom_filter_bytes(&self) -> usize {
+        let state = self.bloom_filter.read();
+        state.current.size_in_bytes()
+            + state.rebuilding.as_ref().map_or(0, BloomFilter::size_in_bytes)
+    }
+
+    /// Replaces the cache advisor with one of the new capacity, and
+    /// evicts whatever the new advisor does not have room for. Dirty
+    /// leaves are paged out after their next flush.
+    pub(crate) fn set_cache_capacity_bytes(&self, capacity: usize) {
+        if capacity < 256 {
+            debug_log!(
+                "cache capacity set to under 256 bytes, so we will use \
+                the minimum of 256 bytes instead"
+            );
+        }
+        let capacity = capacity.max(256);
+        let entry_cache_percent = self.config.entry_cache_percent.min(80);
+
+        // hold the shared advisor's lock while re-registering resident
+        // leaves, so that concurrent resizes are applied one at a time
+        let mut shared = self.runtime.cache_advisor.write();
+        let mut advisor = CacheAdvisor::new(capacity, entry_cache_percent);
+        let mut resident = vec![];
+        for (object_id, node) in self.object_id_index.iter() {
+            if let Some(leaf) = node.inner.read().leaf.as_ref() {
+                resident.push((*object_id, leaf.in_memory_size));
+            }
+        }
+        // the advisor buffers accesses locally until it has 32 of them,
+        // so repeat accesses until the buffer is drained, leaving nothing
+        // behind in the advisor that clones will copy
+        let accesses = resident.len().next_multiple_of(ADVISOR_QUEUE_ITEMS);
+        let mut to_evict = vec![];
+        for (object_id, size) in resident.iter().cycle().take(accesses) {
+            to_evict.extend(advisor.accessed(*object_id, *size));
+        }
+        *shared = (shared.0 + 1, advisor);
+        self.runtime.cache_capacity_bytes.store(capacity, Ordering::Release);
+        drop(shared);
+
+        if let Some(admission) = &self.admission {
+            admission.lock().resize(capacity, entry_cache_percent);
+        }
+
+        self.evict(None, &to_evict);
+    }
+
     pub fn check_error(&self) -> io::Result<()> {
         let err_ptr: *const (io::ErrorKind, String) =
             self.global_error.load(Ordering::Acquire);
@@ -408,6 +1004,147 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         self.flush_epoch.current_flush_epoch()
     }
 
+    /// See `Heap::share`.
+    pub(crate) fn share_object(
+        &self,
+        source: ObjectId,
+        object_id: ObjectId,
+        collection_id: CollectionId,
+    ) -> Option<NonZeroU64> {
+        self.heap.share(source, object_id, collection_id).map(NonZeroU64::from)
+    }
+
+    /// The highest flush epoch that has been completely flushed.
+    pub(crate) fn max_flushed_epoch(&self) -> u64 {
+        self.invariants.max_flushed_epoch()
+    }
+
+    /// Blocks until `epoch` has been completely flushed or `timeout`
+    /// elapses, returning whether it was flushed.
+    pub(crate) fn wait_for_flushed_epoch(&self, epoch: u64, timeout: Duration) -> bool {
+        self.invariants.wait_for_flushed_epoch(epoch, timeout)
+    }
+
+    /// The backup epoch covering every flush completed so far.
+    pub(crate) fn backup_epoch(&self) -> BackupEpoch {
+        self.write_tracker.epoch(self.invariants.max_flushed_epoch())
+    }
+
+    pub(crate) fn write_tracker(&self) -> &WriteTracker {
+        &self.write_tracker
+    }
+
+    pub(crate) fn flush_groups(&self) -> &FlushGroups {
+        &self.flush_groups
+    }
+
+    pub(crate) fn op_journal(&self) -> Option<&OpJournal> {
+        self.op_journal.as_deref()
+    }
+
+    pub(crate) fn flush_progress(&self) -> &FlushProgress {
+        &self.flush_progress
+    }
+
+    pub(crate) fn flusher_status(&self) -> &Arc<FlusherStatus> {
+        &self.flusher_status
+    }
+
+    /// Reports what running flushes are waiting for. Collection names are
+    /// left for the caller to fill in.
+    pub(crate) fn debug_flush_state(&self) -> FlushDebugReport {
+        let mut dirty_objects: BTreeMap<CollectionId, usize> = BTreeMap::new();
+        for (_key, dirty) in self.dirty.iter() {
+            let collection_id = match dirty {
+                Dirty::NotYetSerialized { collection_id, .. }
+                | Dirty::CooperativelySerialized { collection_id, .. }
+                | Dirty::MergedAndDeleted { collection_id, .. }
+                | Dirty::Shared { collection_id, .. } => collection_id,
+            };
+            *dirty_objects.entry(collection_id).or_default() += 1;
+        }
+
+        FlushDebugReport {
+            current_epoch: self.current_flush_epoch().get(),
+            flushed_epoch: self.invariants.max_flushed_epoch(),
+            flushing_epoch: self.invariants.max_flushing_epoch(),
+            flushes_in_progress: self.flush_progress.in_progress(),
+            dirty_collections: dirty_objects
+                .into_iter()
+                .map(|(collection_id, dirty_objects)| DirtyCollection {
+                    collection_id: collection_id.0,
+                    name: None,
+                    dirty_objects,
+                })
+                .collect(),
+            oldest_dirty_age: self
+                .dirty
+                .first()
+                .and_then(|((epoch, _object_id), _dirty)| self.flush_epoch.epoch_started(epoch))
+                .map(|started| started.elapsed()),
+            outstanding_guards: self.flush_epoch.outstanding_guards(),
+            flusher_alive: self.flusher_status.is_alive(),
+            flusher_heartbeat: self.flusher_status.last_heartbeat(),
+        }
+    }
+
+    /// Returns `true` if both handles belong to the same database.
+    pub fn is_same_cache(&self, other: &ObjectCache<LEAF_FANOUT>) -> bool {
+        Arc::ptr_eq(&self.global_error, &other.global_error)
+    }
+
+    /// Rejects writes to databases whose filesystem is full.
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        self.heap.disk_space().check_writable()
+    }
+
+    pub(crate) fn write_state(&self) -> WriteState {
+        self.heap.disk_space().write_state()
+    }
+
+    pub(crate) fn try_resume_writes(&self) -> io::Result<bool> {
+        self.heap.disk_space().try_resume()
+    }
+
+    /// Called by the background flusher after every flush, tries to resume
+    /// writes at most once per `disk_space::PROBE_INTERVAL`.
+    pub(crate) fn try_resume_writes_periodically(&self) -> io::Result<bool> {
+        self.heap.disk_space().try_resume_periodically()
+    }
+
+    /// Makes the serialization of every object in the following flushes
+    /// take at least `latency` longer, to simulate expensive leaves in
+    /// tests.
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn set_serialize_latency_for_testing(&self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.serialize_latency_for_testing.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Flips a byte in the slot at `address` and pages out the leaf stored
+    /// there, so that its next read fails checksum verification. See
+    /// `Db::corrupt_slot_for_testing`.
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn corrupt_slot_for_testing(&self, address: SlabAddress) -> io::Result<()> {
+        self.heap.corrupt_slot_for_testing(address)?;
+        self.page_out_for_testing(|object_id| self.heap.location(object_id) == Some(address));
+        Ok(())
+    }
+
+    /// Pages out the clean, unpinned leaves that are stored in the heap and
+    /// whose object ids match `filter`, so that their next access reads
+    /// them from disk.
+    #[cfg(feature = "for-internal-testing-only")]
+    pub(crate) fn page_out_for_testing(&self, filter: impl Fn(ObjectId) -> bool) {
+        let to_page_out: Vec<(u64, usize)> = self
+            .object_id_index
+            .iter()
+            .filter(|(object_id, _)| self.heap.location(*object_id).is_some() && filter(*object_id))
+            .map(|(object_id, _)| (*object_id, 0))
+            .collect();
+        self.evict(None, &to_page_out);
+    }
+
     pub fn check_into_flush_epoch(&self) -> FlushEpochGuard {
         self.flush_epoch.check_in()
     }
@@ -454,7 +1191,56 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         #[allow(unused)] flush_epoch: FlushEpoch,
     ) -> io::Result<()> {
         let mut ca = self.cache_advisor.write();
-        let to_evict = ca.accessed_reuse_buffer(*accessed_object_id, size);
+        {
+            // pick up the advisor of a resized cache
+            let shared = self.runtime.cache_advisor.read();
+            if ca.0 != shared.0 {
+                *ca = shared.clone();
+            }
+        }
+        let to_evict = ca.1.accessed_reuse_buffer(*accessed_object_id, size);
+        if let Some(admission) = &self.admission {
+            admission.lock().record_access(*accessed_object_id, size);
+        }
+
+        self.evict(Some(accessed_object_id), to_evict);
+
+        Ok(())
+    }
+
+    /// Pages out the given leaves outside of the cache advisor's choices,
+    /// for keeping a tree within its `TreeQuota::max_cache_bytes`. Dirty
+    /// leaves are paged out after their next flush. The advisor is told
+    /// that the leaves paged out right away no longer take up room, so the
+    /// space goes to other trees.
+    pub(crate) fn page_out(&self, object_ids: &[ObjectId]) -> io::Result<()> {
+        let to_evict: Vec<(u64, usize)> =
+            object_ids.iter().map(|object_id| (**object_id, 0)).collect();
+        self.evict(None, &to_evict);
+
+        let flush_epoch = self.current_flush_epoch();
+        for object_id in object_ids {
+            let is_paged_out = self
+                .object_id_index
+                .get(object_id)
+                .is_some_and(|node| node.inner.read().leaf.is_none());
+            if is_paged_out {
+                self.mark_access_and_evict(*object_id, 0, flush_epoch)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pages out the leaves chosen by the cache advisor, or marks them to
+    /// be paged out after they are flushed if they are dirty.
+    fn evict(
+        &self,
+        accessed_object_id: Option<ObjectId>,
+        to_evict: &[(u64, usize)],
+    ) {
+        // the admission filter is not locked while leaves are locked below,
+        // because readers consult it while holding a leaf's read lock
+        let mut evicted = vec![];
         let mut not_found = 0;
         for (node_to_evict, _rough_size) in to_evict {
             let object_id =
@@ -464,7 +1250,7 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
                     unreachable!("object ID must never have been 0");
                 };
 
-            if accessed_object_id == object_id {
+            if accessed_object_id == Some(object_id) {
                 // TODO our own object was evicted, so
                 // set page out after current epoch (or just page out if clean?)
                 continue;
@@ -486,6 +1272,12 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
                 continue;
             }
             let leaf: &mut Leaf<LEAF_FANOUT> = write.leaf.as_mut().unwrap();
+            if self.is_pinned(node.collection_id, leaf) {
+                continue;
+            }
+            if self.admission.is_some() {
+                evicted.push(object_id);
+            }
 
             if let Some(dirty_epoch) = leaf.dirty_flush_epoch {
                 // We can't page out this leaf until it has been
@@ -501,23 +1293,298 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             }
         }
 
+        if let Some(admission) = &self.admission
+            && !evicted.is_empty()
+        {
+            let mut admission = admission.lock();
+            for object_id in evicted {
+                admission.record_eviction(*object_id);
+            }
+        }
+
         if not_found > 0 {
             trace_log!(
                 "during cache eviction, did not find {} nodes that we were trying to evict",
                 not_found
             );
         }
-
-        Ok(())
     }
 
     pub fn heap_object_id_pin(&self) -> ebr::Guard<'_, DeferredFree, 16, 16> {
         self.heap.heap_object_id_pin()
     }
 
+    /// Advances the EBR epochs of this handle's maps, reclaiming the nodes
+    /// they retired and the garbage orphaned by dropped handles. Garbage
+    /// retired through other handles is freed by their own later
+    /// operations once the epoch has moved past it, and each handle keeps
+    /// a partially filled bag of up to `EBR_LOCAL_GC_BUFFER_SIZE` retired
+    /// items until it fills up or the handle is dropped.
+    pub(crate) fn collect_garbage(&self) {
+        reclaim_retired(&self.object_id_index);
+        reclaim_retired(&self.dirty);
+        self.heap.collect_garbage();
+        self.flush_epoch.collect_garbage();
+    }
+
+    /// Flushes every write that completed before this call, sharing the
+    /// work with concurrent callers.
+    ///
+    /// Callers that arrive while another caller's flush is running wait
+    /// for it, and then either return because it covered their writes or
+    /// elect one of them to run a single flush for all of them. A caller
+    /// whose writes were covered by someone else's flush gets an empty
+    /// `FlushStats`. With `Config::coalesce_flushes` disabled this is the
+    /// same as `flush`.
+    pub fn flush_coalesced(&self) -> io::Result<FlushStats> {
+        if !self.config.coalesce_flushes {
+            return self.flush();
+        }
+
+        // every write that completed before this call is in this epoch or
+        // an earlier one
+        let needed = self.current_flush_epoch().get();
+
+        let coalescer = &*self.flush_coalescer;
+        let mut state = coalescer.state.lock();
+        loop {
+            if state.flushed_through >= needed {
+                state.coalesced += 1;
+                return Ok(FlushStats::default());
+            }
+            if !state.leader_active {
+                break;
+            }
+            coalescer.done.wait(&mut state);
+        }
+        state.leader_active = true;
+        drop(state);
+
+        let mut leader = CoalescerLeader { coalescer, covered: None };
+
+        // the flush seals the epoch that is current when it starts, which
+        // is at least this one
+        let covered = self.current_flush_epoch().get();
+        let ret = self.flush()?;
+        leader.covered = Some(covered);
+
+        Ok(ret)
+    }
+
+    /// Removes the blobs released by the flush of `epoch` and earlier
+    /// flushes once the heap no longer retains the leaves that reference
+    /// them, see `Config::retained_flush_epochs`.
+    fn remove_released_blobs(&self, epoch: FlushEpoch, released: Vec<u64>) {
+        if self.config.retained_flush_epochs == 0 {
+            self.blobs.remove_released(released);
+            return;
+        }
+
+        let horizon = self.heap.epoch_horizon();
+        let mut retained = self.retained_blobs.lock();
+        retained.push_back((epoch, released));
+
+        let mut removable = vec![];
+        while retained.front().is_some_and(|(epoch, _)| *epoch <= horizon) {
+            removable.extend(retained.pop_front().unwrap().1);
+        }
+        drop(retained);
+
+        self.blobs.remove_released(removable);
+    }
+
+    /// Puts the objects of a flush that failed to be written back into the
+    /// dirty map, so that the next flush writes them along with its own
+    /// epoch. The leaves were already serialized and are no longer marked
+    /// dirty, so their serialized form is kept. Only the first
+    /// `dirty_updates` updates came from the dirty map; the rest rewrite
+    /// fragmented objects and are recomputed by the next flush.
+    fn retry_in_next_flush(
+        &self,
+        write_batch: Vec<Update>,
+        dirty_updates: usize,
+        flush_through_epoch: FlushEpoch,
+        released_blobs: Vec<u64>,
+    ) {
+        for update in write_batch.into_iter().take(dirty_updates) {
+            let object_id = update.object_id();
+            let dirty = match update {
+                Update::Store { object_id, collection_id, low_key, data } => {
+                    Dirty::CooperativelySerialized {
+                        object_id,
+                        collection_id,
+                        low_key,
+                        data: Arc::new(data),
+                        mutation_count: 0,
+                    }
+                }
+                Update::Free { object_id, collection_id } => {
+                    Dirty::MergedAndDeleted { object_id, collection_id }
+                }
+                Update::Share { object_id, collection_id, low_key, location } => {
+                    Dirty::Shared { object_id, collection_id, low_key, location }
+                }
+            };
+            self.dirty.insert((flush_through_epoch, object_id), dirty);
+        }
+
+        self.blobs.restore_released(released_blobs);
+    }
+
+    /// Turns an object that the flush of `flush_through_epoch` is
+    /// responsible for into its heap update, along with its node if it is
+    /// to be paged out after the flush. The objects of a flush are passed
+    /// here concurrently when `Config::flush_threads` is above 1, but each
+    /// object only once.
+    fn serialize_dirty(
+        &self,
+        dirty_object_id: ObjectId,
+        dirty_value: Dirty<LEAF_FANOUT>,
+        flush_through_epoch: FlushEpoch,
+    ) -> (Update, Option<Object<LEAF_FANOUT>>) {
+        #[cfg(feature = "for-internal-testing-only")]
+        {
+            let latency = self.serialize_latency_for_testing.load(Ordering::Relaxed);
+            if latency > 0 {
+                std::thread::sleep(Duration::from_nanos(latency));
+            }
+        }
+
+        match dirty_value {
+            Dirty::MergedAndDeleted { object_id, collection_id } => {
+                assert_eq!(object_id, dirty_object_id);
+
+                trace_log!(
+                    "MergedAndDeleted for {:?}, adding None to write_batch",
+                    object_id
+                );
+                (Update::Free { object_id, collection_id }, None)
+            }
+            Dirty::Shared { object_id, collection_id, low_key, location } => {
+                assert_eq!(object_id, dirty_object_id);
+                let update =
+                    Update::Share { object_id, collection_id, low_key, location };
+                (update, None)
+            }
+            Dirty::CooperativelySerialized {
+                object_id: _,
+                collection_id,
+                low_key,
+                mutation_count: _,
+                mut data,
+            } => {
+                Arc::make_mut(&mut data);
+                let data = Arc::into_inner(data).unwrap();
+                let update = Update::Store {
+                    object_id: dirty_object_id,
+                    collection_id,
+                    low_key,
+                    data,
+                };
+                (update, None)
+            }
+            Dirty::NotYetSerialized { low_key, collection_id, node } => {
+                assert_eq!(low_key, node.low_key);
+                assert_eq!(
+                    dirty_object_id, node.object_id,
+                    "mismatched node ID for NotYetSerialized with low key {:?}",
+                    low_key
+                );
+                let mut lock = node.inner.write();
+
+                let leaf_ref: &mut Leaf<LEAF_FANOUT> = if let Some(
+                    lock_ref,
+                ) =
+                    lock.leaf.as_mut()
+                {
+                    lock_ref
+                } else {
+                    panic!(
+                        "failed to get lock for node that was NotYetSerialized, low key {:?} id {:?}",
+                        low_key, node.object_id
+                    );
+                };
+
+                assert_eq!(leaf_ref.lo, low_key);
+
+                let data = if leaf_ref.dirty_flush_epoch
+                    == Some(flush_through_epoch)
+                {
+                    if let Some(deleted_at) = leaf_ref.deleted {
+                        assert!(deleted_at > flush_through_epoch);
+                    }
+
+                    leaf_ref.max_unflushed_epoch =
+                        leaf_ref.dirty_flush_epoch.take();
+
+                    leaf_ref.serialize(self, collection_id)
+                } else {
+                    // Here we expect that there was a benign data race and that another thread
+                    // mutated the leaf after encountering it being dirty for our epoch, after
+                    // storing a CooperativelySerialized in the dirty map.
+                    let dirty_value_2_opt =
+                        self.dirty.remove(&(flush_through_epoch, dirty_object_id));
+
+                    if let Some(Dirty::CooperativelySerialized {
+                        low_key: low_key_2,
+                        mutation_count: _,
+                        mut data,
+                        collection_id: ci2,
+                        object_id: ni2,
+                    }) = dirty_value_2_opt
+                    {
+                        assert_eq!(node.object_id, ni2);
+                        assert_eq!(node.object_id, dirty_object_id);
+                        assert_eq!(low_key, low_key_2);
+                        assert_eq!(node.low_key, low_key);
+                        assert_eq!(collection_id, ci2);
+                        Arc::make_mut(&mut data);
+
+                        Arc::into_inner(data).unwrap()
+                    } else {
+                        error_log!(
+                            "violation of flush responsibility for second read \
+                            of expected cooperative serialization. leaf in question's \
+                            dirty_flush_epoch is {:?}, our expected key was {:?}. node.deleted: {:?}",
+                            leaf_ref.dirty_flush_epoch,
+                            (flush_through_epoch, dirty_object_id),
+                            leaf_ref.deleted,
+                        );
+                        unreachable!(
+                            "a leaf was expected to be cooperatively serialized but it was not available. \
+                            violation of flush responsibility for second read \
+                            of expected cooperative serialization. leaf in question's \
+                            dirty_flush_epoch is {:?}, our expected key was {:?}. node.deleted: {:?}",
+                            leaf_ref.dirty_flush_epoch,
+                            (flush_through_epoch, dirty_object_id),
+                            leaf_ref.deleted,
+                        );
+                    }
+                };
+
+                // page_out_on_flush is set to false
+                // on page-in due to serde(skip)
+                let evict = (leaf_ref.page_out_on_flush == Some(flush_through_epoch))
+                    .then(|| node.clone());
+
+                let update = Update::Store {
+                    object_id: dirty_object_id,
+                    collection_id,
+                    low_key,
+                    data,
+                };
+                (update, evict)
+            }
+        }
+    }
+
     pub fn flush(&self) -> io::Result<FlushStats> {
         let mut write_batch = vec![];
 
+        // taken before the epoch advances, so every blob released so far was
+        // released by a write that this flush persists
+        let released_blobs = self.blobs.take_released();
+
         trace_log!("advancing epoch");
         let (
             previous_flush_complete_notifier,
@@ -525,6 +1592,8 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             forward_flush_notifier,
         ) = self.flush_epoch.roll_epoch_forward();
 
+        let progress = self.flush_progress.start(this_vacant_notifier.epoch().get());
+
         let before_previous_block = Instant::now();
 
         trace_log!(
@@ -536,6 +1605,8 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
 
         let pre_block_on_previous_flush = before_previous_block.elapsed();
 
+        progress.set_stage(FlushStage::WaitingForQuiescence);
+
         let before_current_quiescence = Instant::now();
 
         trace_log!(
@@ -553,14 +1624,32 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
 
         self.invariants.mark_flushing_epoch(flush_through_epoch);
 
-        let mut objects_to_defrag = self.heap.objects_to_defrag();
+        progress.set_stage(FlushStage::Serializing);
 
-        let flush_boundary = (flush_through_epoch.increment(), ObjectId::MIN);
+        let compacting = self.compacting.load(Ordering::Acquire);
+        let mut objects_to_defrag = if compacting {
+            self.heap.objects_to_compact()
+        } else {
+            self.heap.objects_to_defrag()
+        };
+        if self.heap.disk_space().write_state() == WriteState::ReadOnlyDegraded {
+            // rewriting fragmented objects needs space that a full
+            // filesystem doesn't have
+            objects_to_defrag.clear();
+        }
 
-        let mut evict_after_flush = vec![];
+        let flush_boundary = (flush_through_epoch.increment(), ObjectId::MIN);
 
         let before_serialization = Instant::now();
 
+        // NB: the dirty map is drained before any object is serialized, so
+        // that the objects can be serialized in any order. The epoch is
+        // still written as a single batch below.
+        //
+        // Objects of an earlier epoch were left behind by a failed flush,
+        // and are superseded by a later write to the same object.
+        let mut dirty_objects = vec![];
+        let mut dirty_object_indices: HashMap<ObjectId, usize> = HashMap::new();
         for ((dirty_epoch, dirty_object_id), dirty_value_initial_read) in
             self.dirty.range(..flush_boundary)
         {
@@ -579,137 +1668,36 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             // while taking ownership of the value
             drop(dirty_value_initial_read);
 
-            assert_eq!(dirty_epoch, flush_through_epoch);
-
-            match dirty_value {
-                Dirty::MergedAndDeleted { object_id, collection_id } => {
-                    assert_eq!(object_id, dirty_object_id);
-
-                    trace_log!(
-                        "MergedAndDeleted for {:?}, adding None to write_batch",
-                        object_id
-                    );
-                    write_batch.push(Update::Free { object_id, collection_id });
-
-                    
-                }
-                Dirty::CooperativelySerialized {
-                    object_id: _,
-                    collection_id,
-                    low_key,
-                    mutation_count: _,
-                    mut data,
-                } => {
-                    Arc::make_mut(&mut data);
-                    let data = Arc::into_inner(data).unwrap();
-                    write_batch.push(Update::Store {
-                        object_id: dirty_object_id,
-                        collection_id,
-                        low_key,
-                        data,
-                    });
-
-                    
-                }
-                Dirty::NotYetSerialized { low_key, collection_id, node } => {
-                    assert_eq!(low_key, node.low_key);
-                    assert_eq!(
-                        dirty_object_id, node.object_id,
-                        "mismatched node ID for NotYetSerialized with low key {:?}",
-                        low_key
-                    );
-                    let mut lock = node.inner.write();
-
-                    let leaf_ref: &mut Leaf<LEAF_FANOUT> = if let Some(
-                        lock_ref,
-                    ) =
-                        lock.leaf.as_mut()
-                    {
-                        lock_ref
-                    } else {
-                        
-                        panic!(
-                            "failed to get lock for node that was NotYetSerialized, low key {:?} id {:?}",
-                            low_key, node.object_id
-                        );
-                    };
-
-                    assert_eq!(leaf_ref.lo, low_key);
-
-                    let data = if leaf_ref.dirty_flush_epoch
-                        == Some(flush_through_epoch)
-                    {
-                        if let Some(deleted_at) = leaf_ref.deleted {
-                                                        assert!(deleted_at > flush_through_epoch);
-                        }
-
-                        leaf_ref.max_unflushed_epoch =
-                            leaf_ref.dirty_flush_epoch.take();
+            assert!(
+                dirty_epoch == flush_through_epoch
+                    || !matches!(dirty_value, Dirty::NotYetSerialized { .. })
+            );
 
-                        
+            if let Some(index) = dirty_object_indices.get(&dirty_object_id) {
+                dirty_objects[*index] = (dirty_object_id, dirty_value);
+            } else {
+                dirty_object_indices.insert(dirty_object_id, dirty_objects.len());
+                dirty_objects.push((dirty_object_id, dirty_value));
+            }
+        }
 
-                        leaf_ref.serialize(self.config.zstd_compression_level)
-                    } else {
-                        // Here we expect that there was a benign data race and that another thread
-                        // mutated the leaf after encountering it being dirty for our epoch, after
-                        // storing a CooperativelySerialized in the dirty map.
-                        let dirty_value_2_opt =
-                            self.dirty.remove(&(dirty_epoch, dirty_object_id));
-
-                        if let Some(Dirty::CooperativelySerialized {
-                            low_key: low_key_2,
-                            mutation_count: _,
-                            mut data,
-                            collection_id: ci2,
-                            object_id: ni2,
-                        }) = dirty_value_2_opt
-                        {
-                            assert_eq!(node.object_id, ni2);
-                            assert_eq!(node.object_id, dirty_object_id);
-                            assert_eq!(low_key, low_key_2);
-                            assert_eq!(node.low_key, low_key);
-                            assert_eq!(collection_id, ci2);
-                            Arc::make_mut(&mut data);
-
-                            
-
-                            Arc::into_inner(data).unwrap()
-                        } else {
-                            error_log!(
-                                "violation of flush responsibility for second read \
-                                of expected cooperative serialization. leaf in question's \
-                                dirty_flush_epoch is {:?}, our expected key was {:?}. node.deleted: {:?}",
-                                leaf_ref.dirty_flush_epoch,
-                                (dirty_epoch, dirty_object_id),
-                                leaf_ref.deleted,
-                            );
-                                                        unreachable!(
-                                "a leaf was expected to be cooperatively serialized but it was not available. \
-                                violation of flush responsibility for second read \
-                                of expected cooperative serialization. leaf in question's \
-                                dirty_flush_epoch is {:?}, our expected key was {:?}. node.deleted: {:?}",
-                                leaf_ref.dirty_flush_epoch,
-                                (dirty_epoch, dirty_object_id),
-                                leaf_ref.deleted,
-                            );
-                        }
-                    };
-
-                    write_batch.push(Update::Store {
-                        object_id: dirty_object_id,
-                        collection_id,
-                        low_key,
-                        data,
-                    });
+        let serialize = |(dirty_object_id, dirty_value)| {
+            self.serialize_dirty(dirty_object_id, dirty_value, flush_through_epoch)
+        };
+        let serialized: Vec<(Update, Option<Object<LEAF_FANOUT>>)> =
+            match &self.flush_pool {
+                Some(pool) if dirty_objects.len() > 1 => pool.install(|| {
+                    dirty_objects.into_par_iter().map(serialize).collect()
+                }),
+                _ => dirty_objects.into_iter().map(serialize).collect(),
+            };
 
-                    if leaf_ref.page_out_on_flush == Some(flush_through_epoch) {
-                        // page_out_on_flush is set to false
-                        // on page-in due to serde(skip)
-                        evict_after_flush.push(node.clone());
-                    }
-                }
-            }
+        let mut evict_after_flush = vec![];
+        for (update, evict) in serialized {
+            write_batch.push(update);
+            evict_after_flush.extend(evict);
         }
+        let dirty_updates = write_batch.len();
 
         if !objects_to_defrag.is_empty() {
             debug_log!(
@@ -781,6 +1769,8 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
 
         let serialization_latency = before_serialization.elapsed();
 
+        progress.set_stage(FlushStage::Writing);
+
         let before_storage = Instant::now();
 
         let objects_flushed = write_batch.len() as u64;
@@ -789,17 +1779,59 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
         let write_batch_object_ids: Vec<ObjectId> =
             write_batch.iter().map(Update::object_id).collect();
 
+        let stored_object_ids: Vec<ObjectId> = write_batch
+            .iter()
+            .filter(|update| matches!(update, Update::Store { .. } | Update::Share { .. }))
+            .map(Update::object_id)
+            .collect();
+
         let write_batch_stats = if objects_flushed > 0 {
-            let write_batch_stats = self.heap.write_batch(write_batch)?;
-            trace_log!(
-                "marking {flush_through_epoch:?} as flushed - \
-                {objects_flushed} objects written, {write_batch_stats:?}",
-            );
-            write_batch_stats
+            // leaves in this batch may reference blobs written since the
+            // last flush, which must be durable before the metadata is
+            let write_res = self.blobs.sync().and_then(|()| {
+                self.heap.write_batch(&write_batch, flush_through_epoch)
+            });
+            match write_res {
+                Ok(write_batch_stats) => {
+                    trace_log!(
+                        "marking {flush_through_epoch:?} as flushed - \
+                        {objects_flushed} objects written, {write_batch_stats:?}",
+                    );
+                    write_batch_stats
+                }
+                Err(e) => {
+                    if disk_space::is_full(&e) {
+                        self.heap.disk_space().enter_degraded();
+                    }
+                    self.retry_in_next_flush(
+                        write_batch,
+                        dirty_updates,
+                        flush_through_epoch,
+                        released_blobs,
+                    );
+                    forward_flush_notifier.mark_complete();
+                    return Err(e);
+                }
+            }
         } else {
             WriteBatchStats::default()
         };
 
+        if compacting {
+            // retained slots would keep the files from being truncated
+            self.heap.release_epoch_history();
+        }
+
+        self.remove_released_blobs(flush_through_epoch, released_blobs);
+
+        if compacting {
+            // the epoch is already written, so a failed truncation only
+            // leaves the files longer than they need to be
+            if let Err(e) = self.heap.truncate_to_live() {
+                error_log!("failed to truncate slab files while compacting: {e:?}");
+            }
+        }
+
         let storage_latency = before_storage.elapsed();
 
         trace_log!(
@@ -807,10 +1839,16 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             flush_through_epoch
         );
 
+        // recorded before the epoch is marked as flushed, so a backup that
+        // observes the epoch as flushed also observes its writes
+        self.write_tracker.record(&stored_object_ids, flush_through_epoch);
+
         self.invariants.mark_flushed_epoch(flush_through_epoch);
 
         forward_flush_notifier.mark_complete();
 
+        progress.set_stage(FlushStage::Finishing);
+
         let before_eviction = Instant::now();
 
         for node_to_evict in evict_after_flush {
@@ -820,21 +1858,38 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             let mut lock = node_to_evict.inner.write();
             let leaf = lock.leaf.as_mut().unwrap();
 
-            if let Some(dirty_epoch) = leaf.dirty_flush_epoch {
-                if dirty_epoch != flush_through_epoch {
-                    continue;
-                }
-            } else {
+            // serialization above cleared the dirty epoch, so a leaf that
+            // is dirty again was written to after it was serialized
+            if leaf.dirty_flush_epoch.is_some() {
                 continue;
             }
 
-            
+            // the leaf may have been pinned since it was chosen
+            if self.is_pinned(node_to_evict.collection_id, leaf) {
+                continue;
+            }
 
             lock.leaf = None;
         }
 
         let post_write_eviction_latency = before_eviction.elapsed();
 
+        // the epoch is already durable, so waiting here delays only the
+        // next flush and not the writers waiting for this one
+        let rate_limit_latency = match self.flush_io_rate_limit() {
+            Some(limit) => {
+                let bytes_written = write_batch_stats.heap_bytes_written
+                    + write_batch_stats.metadata_bytes_written;
+                let allowed = Duration::from_secs_f64(
+                    bytes_written as f64 / limit.max(1) as f64,
+                );
+                let wait = allowed.saturating_sub(storage_latency);
+                std::thread::sleep(wait);
+                wait
+            }
+            None => Duration::ZERO,
+        };
+
         // kick forward the low level epoch-based reclamation systems
         // because this operation can cause a lot of garbage to build
         // up, and this speeds up its reclamation.
@@ -847,6 +1902,7 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
             serialization_latency,
             storage_latency,
             post_write_eviction_latency,
+            rate_limit_latency,
             objects_flushed,
             write_batch: write_batch_stats,
             compute_defrag_latency,
@@ -861,6 +1917,84 @@ impl<const LEAF_FANOUT: usize> ObjectCache<LEAF_FANOUT> {
 
         Ok(ret)
     }
+
+    /// The fraction of slab file bytes not used by a live object.
+    pub(crate) fn heap_fragmentation(&self) -> io::Result<f64> {
+        self.heap.fragmentation()
+    }
+
+    /// Moves objects into the lowest free slots of their slab and
+    /// truncates the slab files behind them, in up to
+    /// `MAX_COMPACTION_ROUNDS` flushes. Each flush is crash safe in the
+    /// same way as the regular defragmentation it performs: an object is
+    /// only read from its new slot after the metadata pointing to it is
+    /// durable, and its old slot is freed only after that.
+    ///
+    /// `progress` is called after every round with the objects moved and
+    /// heap bytes written so far. Returns the number of objects moved.
+    pub(crate) fn compact_heap(
+        &self,
+        should_stop: &dyn Fn() -> bool,
+        progress: &mut dyn FnMut(RecoveryProgress),
+    ) -> io::Result<u64> {
+        self.compacting.store(true, Ordering::Release);
+        let ret = self.compact_heap_rounds(should_stop, progress);
+        self.compacting.store(false, Ordering::Release);
+        ret
+    }
+
+    fn compact_heap_rounds(
+        &self,
+        should_stop: &dyn Fn() -> bool,
+        progress: &mut dyn FnMut(RecoveryProgress),
+    ) -> io::Result<u64> {
+        let objects_total = self.heap.objects_to_compact().len() as u64;
+        let mut objects_moved = 0;
+        let mut bytes_written = 0;
+
+        for _ in 0..MAX_COMPACTION_ROUNDS {
+            if should_stop() {
+                break;
+            }
+
+            // frees the slots that the previous round moved objects out
+            // of, so that this round can truncate behind them
+            self.heap.collect_garbage();
+
+            let before = self.compacted_heap_slots.load(Ordering::Acquire);
+            let stats = self.flush()?;
+            let moved =
+                self.compacted_heap_slots.load(Ordering::Acquire) - before;
+
+            objects_moved += moved;
+            bytes_written += stats.write_batch.heap_bytes_written;
+            progress(RecoveryProgress {
+                phase: RecoveryPhase::Compacting,
+                objects_scanned: objects_moved,
+                objects_total,
+                bytes_processed: bytes_written,
+            });
+
+            if moved == 0 {
+                break;
+            }
+        }
+
+        Ok(objects_moved)
+    }
+}
+
+/// Pins `map` often enough for its handle to advance the global epoch
+/// `EBR_EPOCH_ADVANCES` times, which frees what the handle retired.
+pub(crate) fn reclaim_retired<K, V, const FANOUT: usize, const LOCAL_GC_BUFFER_SIZE: usize>(
+    map: &ConcurrentMap<K, V, FANOUT, LOCAL_GC_BUFFER_SIZE>,
+) where
+    K: 'static + Clone + Minimum + Ord + Send + Sync,
+    V: 'static + Clone + Send + Sync,
+{
+    for _ in 0..EBR_EPOCH_ADVANCES * EBR_BUMP_EPOCH_OPS {
+        map.contains_key(&K::MIN);
+    }
 }
 
 fn initialize<const LEAF_FANOUT: usize>(
@@ -1,12 +1,14 @@
 use std::num::NonZeroU64;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 use pagetable::PageTable;
+use parking_lot::Mutex;
 
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{
-    Allocator, ObjectId,
+    Allocator, CollectionId, CorruptionError, ObjectId,
     heap::{N_SLABS, SlabAddress, UpdateMetadata},
 };
 
@@ -16,11 +18,14 @@ pub struct AllocatorStats {
     pub objects_freed: u64,
     pub heap_slots_allocated: u64,
     pub heap_slots_freed: u64,
+    /// The number of heap slots referenced by more than one object, see
+    /// `Db::clone_tree`.
+    pub heap_slots_shared: u64,
 }
 
 #[derive(Default)]
 struct SlabTenancy {
-    slot_to_object_id: PageTable<AtomicU64>,
+    slot_to_object_id: PageTable<PortableAtomicU64>,
     slot_allocator: Arc<Allocator>,
 }
 
@@ -57,19 +62,46 @@ impl SlabTenancy {
 
 #[derive(Clone)]
 pub(crate) struct ObjectLocationMapper {
-    object_id_to_location: PageTable<AtomicU64>,
+    object_id_to_location: PageTable<PortableAtomicU64>,
+    // the stored (pre-framing) size of each object, used to maintain
+    // collection_bytes incrementally as objects are rewritten or freed
+    object_id_to_size: PageTable<PortableAtomicU64>,
+    collection_bytes: Arc<Mutex<FnvHashMap<CollectionId, u64>>>,
+    object_id_to_collection: PageTable<PortableAtomicU64>,
+    // objects whose stored copy failed checksum verification, until they
+    // are rewritten or removed. The count lets the write path skip the
+    // mutex when nothing is quarantined.
+    quarantined: Arc<Mutex<FnvHashMap<ObjectId, CorruptionError>>>,
+    quarantined_count: Arc<AtomicUsize>,
+    // the objects referencing each slot that is referenced by more than
+    // one object, because a cloned tree shares it with its source. A
+    // shared slot is only freed when its last object vacates it.
+    shared_slots: Arc<Mutex<FnvHashMap<u64, Vec<ObjectId>>>>,
+    shared_count: Arc<AtomicUsize>,
     slab_tenancies: Arc<[SlabTenancy; N_SLABS]>,
     object_id_allocator: Arc<Allocator>,
     target_fill_ratio: f32,
 }
 
 impl ObjectLocationMapper {
+    /// `recovered_sizes` maps each recovered location to the size of the
+    /// object stored there, as observed while verifying the slots or as
+    /// recorded at the last clean shutdown. Locations missing from it are
+    /// accounted at their slot size.
     pub(crate) fn new(
         recovered_metadata: &[UpdateMetadata],
+        recovered_sizes: &FnvHashMap<u64, u64>,
         target_fill_ratio: f32,
     ) -> ObjectLocationMapper {
         let mut ret = ObjectLocationMapper {
             object_id_to_location: PageTable::default(),
+            object_id_to_size: PageTable::default(),
+            collection_bytes: Arc::default(),
+            object_id_to_collection: PageTable::default(),
+            quarantined: Arc::default(),
+            quarantined_count: Arc::default(),
+            shared_slots: Arc::default(),
+            shared_count: Arc::default(),
             slab_tenancies: Arc::new(core::array::from_fn(|_| {
                 SlabTenancy::default()
             })),
@@ -78,6 +110,8 @@ impl ObjectLocationMapper {
         };
 
         let mut object_ids: FnvHashSet<u64> = Default::default();
+        let mut objects_per_location: FnvHashMap<u64, Vec<ObjectId>> =
+            Default::default();
         let mut slots_per_slab: [FnvHashSet<u64>; N_SLABS] =
             core::array::from_fn(|_| Default::default());
 
@@ -85,15 +119,23 @@ impl ObjectLocationMapper {
             match update_metadata {
                 UpdateMetadata::Store {
                     object_id,
-                    collection_id: _,
+                    collection_id,
                     location,
                     low_key: _,
                 } => {
                     object_ids.insert(**object_id);
+                    objects_per_location
+                        .entry(location.get())
+                        .or_default()
+                        .push(*object_id);
                     let slab_address = SlabAddress::from(*location);
                     slots_per_slab[slab_address.slab() as usize]
                         .insert(slab_address.slot());
-                    ret.insert(*object_id, slab_address);
+                    let size = recovered_sizes
+                        .get(&location.get())
+                        .copied()
+                        .unwrap_or_else(|| slab_address.slot_size() as u64);
+                    ret.insert(*object_id, *collection_id, slab_address, size);
                 }
                 UpdateMetadata::Free { .. } => {
                     unreachable!()
@@ -104,6 +146,10 @@ impl ObjectLocationMapper {
         ret.object_id_allocator =
             Arc::new(Allocator::from_allocated(&object_ids));
 
+        objects_per_location.retain(|_, objects| objects.len() > 1);
+        ret.shared_count = Arc::new(AtomicUsize::new(objects_per_location.len()));
+        ret.shared_slots = Arc::new(Mutex::new(objects_per_location));
+
         let slabs = Arc::get_mut(&mut ret.slab_tenancies).unwrap();
 
         for i in 0..N_SLABS {
@@ -115,6 +161,13 @@ impl ObjectLocationMapper {
         ret
     }
 
+    /// The number of allocated slots of each slab.
+    pub(crate) fn allocated_slots_per_slab(&self) -> [u64; N_SLABS] {
+        core::array::from_fn(|i| {
+            self.slab_tenancies[i].slot_allocator.allocated_count()
+        })
+    }
+
     pub(crate) fn get_max_allocated_per_slab(&self) -> Vec<(usize, u64)> {
         let mut ret = vec![];
 
@@ -146,6 +199,7 @@ impl ObjectLocationMapper {
             objects_freed,
             heap_slots_allocated,
             heap_slots_freed,
+            heap_slots_shared: self.shared_count.load(Ordering::Acquire) as u64,
         }
     }
 
@@ -195,6 +249,102 @@ impl ObjectLocationMapper {
         Some(SlabAddress::from(nzu))
     }
 
+    /// The location and stored size of every stored object, in the form
+    /// that `ObjectLocationMapper::new` accepts as `recovered_sizes`.
+    pub(crate) fn stored_sizes(&self) -> FnvHashMap<u64, u64> {
+        let Some(max_allocated) = self.object_id_allocator.max_allocated() else {
+            return FnvHashMap::default();
+        };
+
+        (1..=max_allocated)
+            .filter_map(ObjectId::new)
+            .filter_map(|object_id| {
+                let location = self.get_location_for_object(object_id)?;
+                Some((NonZeroU64::from(location).get(), self.object_size(object_id)))
+            })
+            .collect()
+    }
+
+    /// The collection and location of every stored object.
+    pub(crate) fn stored_objects(
+        &self,
+    ) -> FnvHashMap<ObjectId, (CollectionId, SlabAddress)> {
+        let Some(max_allocated) = self.object_id_allocator.max_allocated() else {
+            return FnvHashMap::default();
+        };
+
+        (1..=max_allocated)
+            .filter_map(ObjectId::new)
+            .filter_map(|object_id| {
+                let location = self.get_location_for_object(object_id)?;
+                Some((object_id, (self.collection_for_object(object_id), location)))
+            })
+            .collect()
+    }
+
+    /// The stored size of the object, or 0 if it is not stored.
+    pub(crate) fn object_size(&self, object_id: ObjectId) -> u64 {
+        self.object_id_to_size.get(*object_id).load(Ordering::Acquire)
+    }
+
+    /// Returns the number of bytes currently stored for each collection,
+    /// counting the serialized size of every live object.
+    pub(crate) fn collection_bytes(&self) -> FnvHashMap<CollectionId, u64> {
+        self.collection_bytes.lock().clone()
+    }
+
+    pub(crate) fn bytes_for_collection(&self, collection_id: CollectionId) -> u64 {
+        self.collection_bytes.lock().get(&collection_id).copied().unwrap_or(0)
+    }
+
+    /// Returns the collection that the object was last stored for.
+    pub(crate) fn collection_for_object(&self, object_id: ObjectId) -> CollectionId {
+        CollectionId(
+            self.object_id_to_collection.get(*object_id).load(Ordering::Acquire),
+        )
+    }
+
+    /// Marks an object as corrupt so that reads fail without touching the
+    /// disk. The mark is cleared when the object is rewritten or removed.
+    pub(crate) fn quarantine(&self, error: CorruptionError) {
+        let object_id = ObjectId::new(error.object_id).unwrap();
+        let mut quarantined = self.quarantined.lock();
+        quarantined.insert(object_id, error);
+        self.quarantined_count.store(quarantined.len(), Ordering::Release);
+    }
+
+    pub(crate) fn quarantined(&self, object_id: ObjectId) -> Option<CorruptionError> {
+        if self.quarantined_count.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        self.quarantined.lock().get(&object_id).copied()
+    }
+
+    pub(crate) fn quarantined_objects(&self) -> Vec<CorruptionError> {
+        let mut ret: Vec<CorruptionError> =
+            self.quarantined.lock().values().copied().collect();
+        ret.sort_unstable();
+        ret
+    }
+
+    fn lift_quarantine(&self, object_id: ObjectId) {
+        if self.quarantined_count.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut quarantined = self.quarantined.lock();
+        quarantined.remove(&object_id);
+        self.quarantined_count.store(quarantined.len(), Ordering::Release);
+    }
+
+    fn account(&self, collection_id: CollectionId, added: u64, removed: u64) {
+        let mut collection_bytes = self.collection_bytes.lock();
+        let bytes = collection_bytes.entry(collection_id).or_default();
+        *bytes = (*bytes + added).saturating_sub(removed);
+        if *bytes == 0 {
+            collection_bytes.remove(&collection_id);
+        }
+    }
+
     /// Returns the previous address for this object, if it is vacating one.
     ///
     /// # Panics
@@ -204,8 +354,18 @@ impl ObjectLocationMapper {
     pub(crate) fn insert(
         &self,
         object_id: ObjectId,
+        collection_id: CollectionId,
         new_location: SlabAddress,
+        size: u64,
     ) -> Option<SlabAddress> {
+        let last_size =
+            self.object_id_to_size.get(*object_id).swap(size, Ordering::Release);
+        self.account(collection_id, size, last_size);
+        self.object_id_to_collection
+            .get(*object_id)
+            .store(collection_id.0, Ordering::Release);
+        self.lift_quarantine(object_id);
+
         // insert into object_id_to_location
         let location_nzu: NonZeroU64 = new_location.into();
         let location_u64 = location_nzu.get();
@@ -215,9 +375,18 @@ impl ObjectLocationMapper {
             .get(*object_id)
             .swap(location_u64, Ordering::Release);
 
+        if last_u64 == location_u64 {
+            // the metadata of an object that `share` already mapped
+            return None;
+        }
+
         let last_address_opt = if let Some(nzu) = NonZeroU64::new(last_u64) {
             let last_address = SlabAddress::from(nzu);
-            Some(last_address)
+            if self.release_shared(last_address, object_id) {
+                None
+            } else {
+                Some(last_address)
+            }
         } else {
             None
         };
@@ -241,7 +410,16 @@ impl ObjectLocationMapper {
     /// # Panics
     ///
     /// Asserts that the object was actually stored in a location.
-    pub(crate) fn remove(&self, object_id: ObjectId) -> Option<SlabAddress> {
+    pub(crate) fn remove(
+        &self,
+        object_id: ObjectId,
+        collection_id: CollectionId,
+    ) -> Option<SlabAddress> {
+        let last_size =
+            self.object_id_to_size.get(*object_id).swap(0, Ordering::Release);
+        self.account(collection_id, 0, last_size);
+        self.lift_quarantine(object_id);
+
         let last_u64 = self
             .object_id_to_location
             .get(*object_id)
@@ -250,6 +428,10 @@ impl ObjectLocationMapper {
         if let Some(nzu) = NonZeroU64::new(last_u64) {
             let last_address = SlabAddress::from(nzu);
 
+            if self.release_shared(last_address, object_id) {
+                return None;
+            }
+
             let slab = last_address.slab();
             let slot = last_address.slot();
 
@@ -266,14 +448,84 @@ impl ObjectLocationMapper {
         }
     }
 
+    /// Maps `object_id` to the slot of `source`, which then stays allocated
+    /// until both objects have vacated it. Returns the shared location, or
+    /// `None` if `source` is not stored.
+    pub(crate) fn share(
+        &self,
+        source: ObjectId,
+        object_id: ObjectId,
+        collection_id: CollectionId,
+    ) -> Option<SlabAddress> {
+        let mut shared_slots = self.shared_slots.lock();
+
+        let location_u64 =
+            self.object_id_to_location.get(*source).load(Ordering::Acquire);
+        let location = SlabAddress::from(NonZeroU64::new(location_u64)?);
+        let size = self.object_id_to_size.get(*source).load(Ordering::Acquire);
+
+        shared_slots
+            .entry(location_u64)
+            .or_insert_with(|| vec![source])
+            .push(object_id);
+        self.shared_count.store(shared_slots.len(), Ordering::Release);
+
+        self.object_id_to_size.get(*object_id).store(size, Ordering::Release);
+        self.account(collection_id, size, 0);
+        self.object_id_to_collection
+            .get(*object_id)
+            .store(collection_id.0, Ordering::Release);
+        self.object_id_to_location
+            .get(*object_id)
+            .store(location_u64, Ordering::Release);
+
+        Some(location)
+    }
+
+    /// Called when `object_id` vacates `location`. Returns whether another
+    /// object still references the slot, in which case it must not be
+    /// freed.
+    ///
+    /// This always takes the mutex, even when nothing is shared, so that a
+    /// concurrent `share` either observes the vacated location before this
+    /// or the new location after it.
+    fn release_shared(&self, location: SlabAddress, object_id: ObjectId) -> bool {
+        let location_u64 = NonZeroU64::from(location).get();
+        let mut shared_slots = self.shared_slots.lock();
+        let Some(objects) = shared_slots.get_mut(&location_u64) else {
+            return false;
+        };
+
+        objects.retain(|other| *other != object_id);
+        if let [remaining] = objects[..] {
+            // the slot tenancy must name an object that is stored there,
+            // for defragmentation and for `remove`
+            self.slab_tenancies[usize::from(location.slab())]
+                .slot_to_object_id
+                .get(location.slot())
+                .store(*remaining, Ordering::Release);
+            shared_slots.remove(&location_u64);
+            self.shared_count.store(shared_slots.len(), Ordering::Release);
+        }
+
+        true
+    }
+
     pub(crate) fn objects_to_defrag(&self) -> FnvHashSet<ObjectId> {
+        self.objects_to_defrag_to(self.target_fill_ratio)
+    }
+
+    /// The objects to move so that each slab reaches `target_fill_ratio`.
+    pub(crate) fn objects_to_defrag_to(
+        &self,
+        target_fill_ratio: f32,
+    ) -> FnvHashSet<ObjectId> {
         let mut ret = FnvHashSet::default();
 
         for slab_id in 0..N_SLABS {
             let slab = &self.slab_tenancies[slab_id];
 
-            for (object_id, slot) in
-                slab.objects_to_defrag(self.target_fill_ratio)
+            for (object_id, slot) in slab.objects_to_defrag(target_fill_ratio)
             {
                 let sa = SlabAddress::from_slab_slot(
                     u8::try_from(slab_id).unwrap(),
@@ -0,0 +1,780 @@
+//! 诊断用的操作日志
+//!
+//! 设置 `Config::op_journal` 后，树上每个成功的公开修改操作（`insert`、
+//! `remove`、`compare_and_swap`、`apply_batch` 等）都会记录一条日志，
+//! 包括树名、操作类型、键、值（或者只记录值的哈希）、时间戳和线程编号，
+//! 用于重现导致数据错误的操作序列。
+//!
+//! 操作日志不是预写日志：记录先进入内存缓冲区，由后台线程定期写入数据库
+//! 目录下 `op_journal` 目录中的两个分段文件，不影响数据的读写路径。崩溃时
+//! 尚未写入的尾部会丢失，但文件中存在的记录一定是完整的。当前分段写满后清空
+//! 另一个分段并切换过去，因此日志最多占用 `OpJournalConfig::max_bytes` 字节，
+//! 保留最近的操作。
+//!
+//! 所有整数都按小端序编码。分段文件和 `Db::dump_op_journal` 的输出格式相同：
+//!
+//! - 文件头：8字节的 `MAGIC`、`u64` 序号，以及前面16个字节的CRC32。
+//!   序号较大的分段较新，转储文件的序号为0
+//! - 记录：`u32` 内容长度、内容的XXH3-64哈希（种子为0）的低32位，之后是
+//!   内容。内容依次为 `u64` 时间戳
+//!   （微秒）、`u64` 线程编号、`u8` 操作类型、`u64` 集合ID、`u8` 是否有树名、
+//!   可选的 `u32` 长度加树名、`u32` 长度加键，以及值：`u8` 标记之后，
+//!   哈希为 `u64` 哈希加 `u64` 原始长度，完整的值为 `u32` 长度加内容
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fault_injection::{annotate, fallible};
+use inline_array::InlineArray;
+use parking_lot::{Condvar, Mutex, RwLock};
+
+use crate::portable_atomic::PortableAtomicU64;
+use crate::{CollectionId, DEFAULT_COLLECTION_ID, NAME_MAPPING_COLLECTION_ID};
+use crate::{debug_log, trace_log, warn_log, error_log};
+
+/// 分段文件和转储文件开头的魔数
+pub const MAGIC: [u8; 8] = *b"MELJRNL1";
+/// `OpJournalConfig::max_bytes` 的最小值
+pub const MIN_MAX_BYTES: u64 = 4096;
+
+const HEADER_LEN: usize = 8 + 8 + 4;
+const RECORD_HEADER_LEN: usize = 4 + 4;
+const DIRECTORY: &str = "op_journal";
+const SEGMENTS: [&str; 2] = ["segment.0", "segment.1"];
+/// 后台线程写入缓冲区的间隔
+const WRITE_INTERVAL: Duration = Duration::from_millis(100);
+/// 缓冲区超过这个大小时立即唤醒后台线程
+const WAKEUP_BYTES: usize = 64 * 1024;
+
+const VALUE_ABSENT: u8 = 0;
+const VALUE_HASHED: u8 = 1;
+const VALUE_FULL: u8 = 2;
+
+/// `Config::op_journal` 的设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpJournalConfig {
+    /// 日志在磁盘上占用的最大字节数，平均分给两个分段，
+    /// 不能小于 `MIN_MAX_BYTES`
+    pub max_bytes: u64,
+    /// 只记录值的哈希和长度而不记录值本身
+    pub redact_values: bool,
+}
+
+impl OpJournalConfig {
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.max_bytes < MIN_MAX_BYTES {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "op_journal 的 max_bytes 不能小于 {}，实际为 {}",
+                    MIN_MAX_BYTES, self.max_bytes
+                )
+            )));
+        }
+        Ok(())
+    }
+
+    fn segment_capacity(&self) -> u64 {
+        self.max_bytes / 2
+    }
+}
+
+/// 被记录的操作类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpKind {
+    /// `insert` 或 `get_or_insert_with` 插入了值
+    Insert,
+    /// `remove`
+    Remove,
+    /// 成功的 `compare_and_swap`，包括通过它实现的 `update_and_fetch`、
+    /// `fetch_and_update` 和 `pop_*` 等操作。值为写入的新值
+    CompareAndSwap,
+    /// `apply_batch` 中的一次插入
+    BatchInsert,
+    /// `apply_batch` 中的一次删除
+    BatchRemove,
+}
+
+impl OpKind {
+    fn id(self) -> u8 {
+        match self {
+            OpKind::Insert => 1,
+            OpKind::Remove => 2,
+            OpKind::CompareAndSwap => 3,
+            OpKind::BatchInsert => 4,
+            OpKind::BatchRemove => 5,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<OpKind> {
+        match id {
+            1 => Some(OpKind::Insert),
+            2 => Some(OpKind::Remove),
+            3 => Some(OpKind::CompareAndSwap),
+            4 => Some(OpKind::BatchInsert),
+            5 => Some(OpKind::BatchRemove),
+            _ => None,
+        }
+    }
+}
+
+/// 日志中记录的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalValue {
+    /// 操作没有写入值，例如删除
+    Absent,
+    /// 设置了 `redact_values`，或者值太大无法放入一个分段
+    Hashed {
+        /// `hash_value` 计算的哈希
+        hash: u64,
+        /// 值的字节数
+        len: u64,
+    },
+    /// 完整的值
+    Full(Vec<u8>),
+}
+
+/// 日志中的一条记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpJournalEntry {
+    /// 记录时的Unix时间戳（微秒），Linux上精度为几毫秒
+    pub timestamp_us: u64,
+    /// 执行操作的线程编号，在进程内从1开始分配，同一个线程的编号不变
+    pub thread_id: u64,
+    /// 操作类型
+    pub op: OpKind,
+    /// 树的集合ID
+    pub collection_id: u64,
+    /// 树名，默认树为 `None`
+    pub tree: Option<Vec<u8>>,
+    /// 键
+    pub key: Vec<u8>,
+    /// 写入的值
+    pub value: JournalValue,
+}
+
+/// 日志记录值的哈希时使用的64位XXH3哈希（种子为0），可以用来比较日志中的值。
+/// 每次写入都要计算，逐字节的哈希在插入路径上的开销太大
+pub fn hash_value(value: &[u8]) -> u64 {
+    twox_hash::XxHash3_64::oneshot(value)
+}
+
+fn thread_id() -> u64 {
+    static NEXT_THREAD_ID: PortableAtomicU64 = PortableAtomicU64::new(1);
+    thread_local! {
+        static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    THREAD_ID.with(|id| *id)
+}
+
+/// 记录的时间戳（微秒）。Linux上使用粗粒度的时钟，精度为几毫秒，但读取它
+/// 只需要几纳秒，而每次写入都要读取一次
+fn timestamp_us() -> u64 {
+    #[cfg(target_os = "linux")]
+    {
+        let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: ts 是有效的可写timespec
+        if unsafe { libc::clock_gettime(libc::CLOCK_REALTIME_COARSE, &mut ts) } == 0 {
+            return ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000;
+        }
+    }
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+fn encode_header(seq: u64) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..8].copy_from_slice(&MAGIC);
+    header[8..16].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc32fast::hash(&header[..16]);
+    header[16..].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// 返回文件头中的序号，文件头无效时返回 `None`
+fn decode_header(header: &[u8]) -> Option<u64> {
+    if header.len() < HEADER_LEN || header[..8] != MAGIC {
+        return None;
+    }
+    let crc = u32::from_le_bytes(header[16..20].try_into().unwrap());
+    if crc != crc32fast::hash(&header[..16]) {
+        return None;
+    }
+    Some(u64::from_le_bytes(header[8..16].try_into().unwrap()))
+}
+
+/// 编码时借用的值，避免复制完整的值
+#[derive(Clone, Copy)]
+enum ValueRef<'a> {
+    Absent,
+    Hashed { hash: u64, len: u64 },
+    Full(&'a [u8]),
+}
+
+impl ValueRef<'_> {
+    fn encoded_len(&self) -> usize {
+        match self {
+            ValueRef::Absent => 1,
+            ValueRef::Hashed { .. } => 1 + 8 + 8,
+            ValueRef::Full(value) => 1 + 4 + value.len(),
+        }
+    }
+}
+
+/// 记录内容的校验和。每次写入都要计算，短输入上XXH3比CRC32快几倍
+fn record_checksum(payload: &[u8]) -> u32 {
+    hash_value(payload) as u32
+}
+
+/// 一条记录（包括记录头）编码后的长度
+fn record_len(tree: Option<&[u8]>, key: &[u8], value: ValueRef<'_>) -> usize {
+    RECORD_HEADER_LEN
+        + 8
+        + 8
+        + 1
+        + 8
+        + 1
+        + tree.map_or(0, |name| 4 + name.len())
+        + 4
+        + key.len()
+        + value.encoded_len()
+}
+
+/// 在 `buf` 中追加一条带有记录头的记录
+fn encode_record(
+    buf: &mut Vec<u8>,
+    timestamp_us: u64,
+    op: OpKind,
+    collection_id: u64,
+    tree: Option<&[u8]>,
+    key: &[u8],
+    value: ValueRef<'_>,
+) {
+    let start = buf.len();
+
+    // 定长的部分先在栈上拼好再一次追加
+    let mut fixed = [0; RECORD_HEADER_LEN + 8 + 8 + 1 + 8];
+    fixed[8..16].copy_from_slice(&timestamp_us.to_le_bytes());
+    fixed[16..24].copy_from_slice(&thread_id().to_le_bytes());
+    fixed[24] = op.id();
+    fixed[25..].copy_from_slice(&collection_id.to_le_bytes());
+    buf.extend_from_slice(&fixed);
+    match tree {
+        Some(name) => {
+            buf.push(1);
+            buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buf.extend_from_slice(name);
+        }
+        None => buf.push(0),
+    }
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    match value {
+        ValueRef::Absent => buf.push(VALUE_ABSENT),
+        ValueRef::Hashed { hash, len } => {
+            buf.push(VALUE_HASHED);
+            buf.extend_from_slice(&hash.to_le_bytes());
+            buf.extend_from_slice(&len.to_le_bytes());
+        }
+        ValueRef::Full(value) => {
+            buf.push(VALUE_FULL);
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(value);
+        }
+    }
+
+    let payload_len = (buf.len() - start - RECORD_HEADER_LEN) as u32;
+    let checksum = record_checksum(&buf[start + RECORD_HEADER_LEN..]);
+    buf[start..start + 4].copy_from_slice(&payload_len.to_le_bytes());
+    buf[start + 4..start + 8].copy_from_slice(&checksum.to_le_bytes());
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("操作日志记录无效: {}", msg))
+}
+
+fn decode_record(payload: &[u8]) -> io::Result<OpJournalEntry> {
+    struct Cursor<'a>(&'a [u8]);
+
+    impl<'a> Cursor<'a> {
+        fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+            if self.0.len() < len {
+                return Err(invalid_data("内容提前结束"));
+            }
+            let (head, tail) = self.0.split_at(len);
+            self.0 = tail;
+            Ok(head)
+        }
+
+        fn u8(&mut self) -> io::Result<u8> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn u32(&mut self) -> io::Result<u32> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn u64(&mut self) -> io::Result<u64> {
+            Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+        }
+
+        fn bytes(&mut self) -> io::Result<Vec<u8>> {
+            let len = self.u32()? as usize;
+            Ok(self.take(len)?.to_vec())
+        }
+    }
+
+    let mut cursor = Cursor(payload);
+    let timestamp_us = cursor.u64()?;
+    let thread_id = cursor.u64()?;
+    let op = OpKind::from_id(cursor.u8()?).ok_or_else(|| invalid_data("未知操作类型"))?;
+    let collection_id = cursor.u64()?;
+    let tree = match cursor.u8()? {
+        0 => None,
+        1 => Some(cursor.bytes()?),
+        _ => return Err(invalid_data("未知树名标记")),
+    };
+    let key = cursor.bytes()?;
+    let value = match cursor.u8()? {
+        VALUE_ABSENT => JournalValue::Absent,
+        VALUE_HASHED => JournalValue::Hashed { hash: cursor.u64()?, len: cursor.u64()? },
+        VALUE_FULL => JournalValue::Full(cursor.bytes()?),
+        _ => return Err(invalid_data("未知值标记")),
+    };
+    if !cursor.0.is_empty() {
+        return Err(invalid_data("内容之后有多余的字节"));
+    }
+
+    Ok(OpJournalEntry { timestamp_us, thread_id, op, collection_id, tree, key, value })
+}
+
+/// 返回 `buf` 开头的完整且校验通过的记录（包括记录头）的总长度
+fn valid_prefix_len(buf: &[u8]) -> usize {
+    let mut offset = 0;
+    while buf.len() - offset >= RECORD_HEADER_LEN {
+        let len = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(buf[offset + 4..offset + 8].try_into().unwrap());
+        let end = offset + RECORD_HEADER_LEN + len;
+        if end > buf.len() || record_checksum(&buf[offset + RECORD_HEADER_LEN..end]) != checksum {
+            break;
+        }
+        offset = end;
+    }
+    offset
+}
+
+/// 读取一个分段文件，返回序号和其中的有效记录。崩溃时可能留下不完整的尾部，
+/// 读取在第一条不完整或校验失败的记录处停止
+fn read_segment(path: &Path) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(annotate!(e)),
+    };
+    let Some(seq) = decode_header(&buf) else {
+        return Ok(None);
+    };
+    let mut records = buf[HEADER_LEN..].to_vec();
+    records.truncate(valid_prefix_len(&records));
+    Ok(Some((seq, records)))
+}
+
+/// 按从旧到新的顺序读取目录中的分段
+fn read_segments(directory: &Path) -> io::Result<Vec<(u64, Vec<u8>)>> {
+    let mut segments = vec![];
+    for name in SEGMENTS {
+        if let Some(segment) = read_segment(&directory.join(name))? {
+            segments.push(segment);
+        }
+    }
+    segments.sort_by_key(|(seq, _)| *seq);
+    Ok(segments)
+}
+
+/// 读取 `Db::dump_op_journal` 的输出。
+///
+/// 文件头在 `new` 中校验，每条记录在迭代时校验，记录损坏或提前结束时返回
+/// `InvalidData` 或 `UnexpectedEof` 错误，之后迭代结束
+pub struct OpJournalReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> OpJournalReader<R> {
+    /// 读取并校验文件头
+    pub fn new(mut reader: R) -> io::Result<OpJournalReader<R>> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if decode_header(&header).is_none() {
+            return Err(invalid_data("文件头无效"));
+        }
+        Ok(OpJournalReader { reader, done: false })
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<OpJournalEntry>> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        let mut read = 0;
+        while read < RECORD_HEADER_LEN {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let mut payload = vec![0; len];
+        self.reader.read_exact(&mut payload)?;
+        if record_checksum(&payload) != checksum {
+            return Err(invalid_data("校验和不匹配"));
+        }
+        decode_record(&payload).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for OpJournalReader<R> {
+    type Item = io::Result<OpJournalEntry>;
+
+    fn next(&mut self) -> Option<io::Result<OpJournalEntry>> {
+        if self.done {
+            return None;
+        }
+        let ret = self.read_entry().transpose();
+        if !matches!(ret, Some(Ok(_))) {
+            self.done = true;
+        }
+        ret
+    }
+}
+
+/// 不打开数据库，直接按从旧到新的顺序读取数据库目录 `path` 中的操作日志。
+/// 用于数据库无法打开时检查日志，进程崩溃时丢失的尾部被忽略
+pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<Vec<OpJournalEntry>> {
+    let mut entries = vec![];
+    for (_seq, records) in read_segments(&path.as_ref().join(DIRECTORY))? {
+        let mut offset = 0;
+        while offset < records.len() {
+            let len = u32::from_le_bytes(records[offset..offset + 4].try_into().unwrap()) as usize;
+            let start = offset + RECORD_HEADER_LEN;
+            entries.push(decode_record(&records[start..start + len])?);
+            offset = start + len;
+        }
+    }
+    Ok(entries)
+}
+
+/// 磁盘上的两个分段，只有一个是当前写入的分段
+struct Segments {
+    directory: PathBuf,
+    files: [File; 2],
+    active: usize,
+    seq: u64,
+    /// 当前分段的字节数，也是它的文件位置
+    len: u64,
+    capacity: u64,
+    /// 写入后清空的缓冲区，下次写入时与 `Shared::pending` 交换
+    spare: Vec<u8>,
+}
+
+impl Segments {
+    fn open(directory: PathBuf, capacity: u64) -> io::Result<Segments> {
+        fallible!(fs::create_dir_all(&directory));
+
+        let mut options = OpenOptions::new();
+        options.create(true).truncate(false).read(true).write(true);
+        let files = [
+            fallible!(options.open(directory.join(SEGMENTS[0]))),
+            fallible!(options.open(directory.join(SEGMENTS[1]))),
+        ];
+
+        let mut segments =
+            Segments { directory, files, active: 0, seq: 0, len: 0, capacity, spare: vec![] };
+
+        // 继续写入较新的分段，截断崩溃时留下的不完整尾部
+        let mut newest = None;
+        for (i, name) in SEGMENTS.iter().enumerate() {
+            if let Some((seq, records)) = read_segment(&segments.directory.join(name))?
+                && newest.is_none_or(|(newest_seq, _, _)| seq > newest_seq)
+            {
+                newest = Some((seq, i, records.len()));
+            }
+        }
+        match newest {
+            Some((seq, active, records_len)) => {
+                segments.active = active;
+                segments.seq = seq;
+                segments.len = (HEADER_LEN + records_len) as u64;
+                let file = &mut segments.files[active];
+                fallible!(file.set_len(segments.len));
+                fallible!(file.seek(SeekFrom::Start(segments.len)));
+            }
+            None => segments.start_segment(0, 1)?,
+        }
+
+        Ok(segments)
+    }
+
+    /// 清空 `index` 分段并开始以序号 `seq` 写入它
+    fn start_segment(&mut self, index: usize, seq: u64) -> io::Result<()> {
+        let file = &mut self.files[index];
+        fallible!(file.set_len(0));
+        fallible!(file.seek(SeekFrom::Start(0)));
+        fallible!(file.write_all(&encode_header(seq)));
+        self.active = index;
+        self.seq = seq;
+        self.len = HEADER_LEN as u64;
+        Ok(())
+    }
+
+    /// 写入 `buf` 中的记录，当前分段写满时切换到另一个分段
+    fn write_records(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.len + buf.len() as u64 <= self.capacity {
+            // 整个缓冲区都能放入当前分段，不需要逐条查找切换分段的位置
+            fallible!(self.files[self.active].write_all(buf));
+            self.len += buf.len() as u64;
+            return Ok(());
+        }
+
+        let mut chunk_start = 0;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let len = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap()) as u64;
+            let record_len = RECORD_HEADER_LEN as u64 + len;
+            let chunk_len = (offset - chunk_start) as u64;
+            if self.len + chunk_len + record_len > self.capacity {
+                fallible!(self.files[self.active].write_all(&buf[chunk_start..offset]));
+                self.len += chunk_len;
+                chunk_start = offset;
+                self.start_segment(1 - self.active, self.seq + 1)?;
+            }
+            offset += record_len as usize;
+        }
+        fallible!(self.files[self.active].write_all(&buf[chunk_start..]));
+        self.len += (buf.len() - chunk_start) as u64;
+        Ok(())
+    }
+}
+
+struct Shared {
+    config: OpJournalConfig,
+    /// 尚未写入分段的记录
+    pending: Mutex<Vec<u8>>,
+    wakeup: Condvar,
+    closed: AtomicBool,
+    segments: Mutex<Segments>,
+    /// 每个集合ID对应的树名，由 `Db` 在打开树时登记
+    names: RwLock<HashMap<u64, InlineArray>>,
+    /// 键太大、无法放入一个分段而被丢弃的记录数
+    dropped: PortableAtomicU64,
+}
+
+impl Shared {
+    /// 写入缓冲区中的记录。先锁住分段再取出缓冲区，使记录按顺序写入
+    fn write_pending(&self) -> io::Result<()> {
+        let mut segments = self.segments.lock();
+        // 换入上次写入后清空的缓冲区，记录时不需要重新分配
+        let mut buf = std::mem::take(&mut segments.spare);
+        std::mem::swap(&mut buf, &mut *self.pending.lock());
+        let res = if buf.is_empty() { Ok(()) } else { segments.write_records(&buf) };
+        if buf.capacity() <= 4 * WAKEUP_BYTES {
+            buf.clear();
+            segments.spare = buf;
+        }
+        res
+    }
+
+    /// 缓冲区达到这个大小时唤醒后台线程
+    fn wakeup_bytes(&self) -> usize {
+        WAKEUP_BYTES.min(self.config.segment_capacity() as usize / 2)
+    }
+}
+
+fn writer(shared: Arc<Shared>) {
+    loop {
+        {
+            let mut pending = shared.pending.lock();
+            if pending.len() < shared.wakeup_bytes() && !shared.closed.load(Ordering::Acquire) {
+                shared.wakeup.wait_for(&mut pending, WRITE_INTERVAL);
+            }
+        }
+
+        if let Err(e) = shared.write_pending() {
+            warn_log!("写入操作日志失败: {:?}", e);
+        }
+
+        if shared.closed.load(Ordering::Acquire) {
+            // 关闭之后不会再有新的记录，最后写入一次剩余的记录
+            if let Err(e) = shared.write_pending() {
+                warn_log!("写入操作日志失败: {:?}", e);
+            }
+            debug_log!("操作日志写入线程退出");
+            return;
+        }
+    }
+}
+
+/// 数据库的操作日志，由 `ObjectCache` 持有，最后一个持有者被释放时写入剩余的
+/// 记录并停止后台线程
+pub(crate) struct OpJournal {
+    shared: Arc<Shared>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for OpJournal {
+    fn fmt(&self, w: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        w.debug_struct("OpJournal").field("config", &self.shared.config).finish()
+    }
+}
+
+impl OpJournal {
+    pub(crate) fn open(db_path: &Path, config: OpJournalConfig) -> io::Result<OpJournal> {
+        config.validate()?;
+        let segments = Segments::open(db_path.join(DIRECTORY), config.segment_capacity())?;
+
+        let shared = Arc::new(Shared {
+            config,
+            pending: Mutex::new(vec![]),
+            wakeup: Condvar::new(),
+            closed: AtomicBool::new(false),
+            segments: Mutex::new(segments),
+            names: RwLock::default(),
+            dropped: PortableAtomicU64::new(0),
+        });
+
+        let thread_shared = shared.clone();
+        let writer = std::thread::Builder::new()
+            .name("melange-op-journal".into())
+            .spawn(move || writer(thread_shared));
+        let writer = match writer {
+            Ok(writer) => writer,
+            Err(e) => {
+                return Err(io::Error::other(format!(
+                    "无法为 melange_db 数据库生成操作日志写入线程: {:?}",
+                    e
+                )));
+            }
+        };
+
+        Ok(OpJournal { shared, writer: Some(writer) })
+    }
+
+    pub(crate) fn register_tree(&self, collection_id: CollectionId, name: &[u8]) {
+        self.shared.names.write().insert(collection_id.0, name.into());
+    }
+
+    /// 记录一个成功的操作，`value` 为操作写入的值
+    pub(crate) fn record(
+        &self,
+        collection_id: CollectionId,
+        op: OpKind,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) {
+        // 名称映射由 `Db` 内部修改，不是用户的操作
+        if collection_id == NAME_MAPPING_COLLECTION_ID {
+            return;
+        }
+
+        // 默认树没有名称，不需要读取名称表
+        let names = (collection_id != DEFAULT_COLLECTION_ID).then(|| self.shared.names.read());
+        let tree = names
+            .as_ref()
+            .and_then(|names| names.get(&collection_id.0))
+            .map(|name| &name[..]);
+
+        let hashed = |value: &[u8]| ValueRef::Hashed {
+            hash: hash_value(value),
+            len: value.len() as u64,
+        };
+        let mut value_ref = match value {
+            None => ValueRef::Absent,
+            Some(value) if self.shared.config.redact_values => hashed(value),
+            Some(value) => ValueRef::Full(value),
+        };
+
+        // 一条记录必须能放入一个空的分段，太大的值只记录哈希
+        let max_record_len = (self.shared.config.segment_capacity() as usize) - HEADER_LEN;
+        let mut len = record_len(tree, key, value_ref);
+        if let ValueRef::Full(value) = value_ref && len > max_record_len {
+            value_ref = hashed(value);
+            len = record_len(tree, key, value_ref);
+        }
+        if len > max_record_len {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+            trace_log!("操作日志记录太大，已丢弃");
+            return;
+        }
+
+        let timestamp_us = timestamp_us();
+
+        let mut pending = self.shared.pending.lock();
+        pending.reserve(len);
+        encode_record(&mut pending, timestamp_us, op, collection_id.0, tree, key, value_ref);
+        let pending_len = pending.len() as u64;
+        drop(pending);
+        drop(names);
+
+        if pending_len >= self.shared.config.max_bytes {
+            // 后台线程跟不上时由记录的线程自己写入，使缓冲区的大小有上限
+            if let Err(e) = self.shared.write_pending() {
+                warn_log!("写入操作日志失败: {:?}", e);
+            }
+        } else if pending_len >= self.shared.wakeup_bytes() as u64 {
+            self.shared.wakeup.notify_one();
+        }
+    }
+
+    /// 键太大、无法放入一个分段而被丢弃的记录数
+    pub(crate) fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 写入缓冲区中的记录，然后按从旧到新的顺序把所有记录写入 `writer`，
+    /// 返回写入的记录数
+    pub(crate) fn dump<W: Write>(&self, writer: W) -> io::Result<u64> {
+        let mut writer = io::BufWriter::new(writer);
+
+        // 持有分段的锁，使读取时后台线程不会切换分段
+        let mut segments = self.shared.segments.lock();
+        let pending = std::mem::take(&mut *self.shared.pending.lock());
+        segments.write_records(&pending)?;
+
+        writer.write_all(&encode_header(0))?;
+        let mut count = 0;
+        for (_seq, records) in read_segments(&segments.directory)? {
+            let mut offset = 0;
+            while offset < records.len() {
+                let len = u32::from_le_bytes(records[offset..offset + 4].try_into().unwrap());
+                offset += RECORD_HEADER_LEN + len as usize;
+                count += 1;
+            }
+            writer.write_all(&records)?;
+        }
+        drop(segments);
+
+        writer.flush()?;
+        Ok(count)
+    }
+}
+
+impl Drop for OpJournal {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.wakeup.notify_one();
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            error_log!("操作日志写入线程发生恐慌");
+        }
+    }
+}
@@ -7,6 +7,8 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::{self, Read, Seek};
 
+use crate::warn_log;
+
 #[cfg(unix)]
 use std::os::unix::fs::FileExt;
 
@@ -119,6 +121,284 @@ pub fn read_exact_at(file: &fs::File, mut buf: &mut [u8], offset: u64) -> std::i
     }
 }
 
+/// 后台线程的优先级
+///
+/// 各平台的优先级范围不同，前五个级别会被映射到平台的原生值：
+///
+/// | 级别 | Linux（nice值） | Windows（`SetThreadPriority`） | macOS |
+/// |------|-----------------|--------------------------------|-------|
+/// | `Lowest` | 19 | -2 | 后台 |
+/// | `BelowNormal` | 10 | -1 | 后台 |
+/// | `Normal` | 0 | 0 | 普通 |
+/// | `AboveNormal` | -10 | 1 | 不支持 |
+/// | `Highest` | -20 | 2 | 不支持 |
+///
+/// Linux上普通调度策略（`SCHED_OTHER`）的线程的 `pthread_setschedparam`
+/// 优先级只能为0，因此通过线程的nice值调整优先级。提高优先级通常需要
+/// `CAP_SYS_NICE` 权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPriority {
+    /// 最低优先级
+    Lowest,
+    /// 低于普通优先级
+    BelowNormal,
+    /// 普通优先级
+    Normal,
+    /// 高于普通优先级
+    AboveNormal,
+    /// 最高优先级
+    Highest,
+    /// 平台的原生优先级值，直接传给 `set_current_thread_priority`
+    Native(i32),
+}
+
+impl ThreadPriority {
+    /// 转换为当前平台的原生优先级值，当前平台不支持该级别时返回 `Unsupported` 错误
+    pub fn native_value(self) -> io::Result<i32> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let levels = [Some(19), Some(10), Some(0), Some(-10), Some(-20)];
+
+        #[cfg(windows)]
+        let levels = [Some(-2), Some(-1), Some(0), Some(1), Some(2)];
+
+        #[cfg(target_os = "macos")]
+        let levels = [
+            Some(libc::PRIO_DARWIN_BG),
+            Some(libc::PRIO_DARWIN_BG),
+            Some(0),
+            None,
+            None,
+        ];
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            windows,
+            target_os = "macos"
+        )))]
+        let levels: [Option<i32>; 5] = [None; 5];
+
+        let level = match self {
+            ThreadPriority::Lowest => levels[0],
+            ThreadPriority::BelowNormal => levels[1],
+            ThreadPriority::Normal => levels[2],
+            ThreadPriority::AboveNormal => levels[3],
+            ThreadPriority::Highest => levels[4],
+            ThreadPriority::Native(priority) => Some(priority),
+        };
+
+        level.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("当前平台不支持线程优先级 {:?}", self),
+            )
+        })
+    }
+}
+
+/// 将当前线程绑定到指定的CPU核心
+///
+/// 等价于只包含一个核心的 `pin_current_thread_to_cores`。
+pub fn pin_current_thread_to_core(core: usize) -> io::Result<()> {
+    pin_current_thread_to_cores(&[core])
+}
+
+/// 将当前线程绑定到一组CPU核心
+///
+/// 在Linux上使用 `sched_setaffinity`，在Windows上使用 `SetThreadAffinityMask`。
+/// macOS不支持将线程绑定到核心，只能通过 `THREAD_AFFINITY_POLICY` 提示调度器
+/// 将具有相同标记的线程放在共享缓存的核心上，这里以第一个核心作为标记，
+/// Apple Silicon上会返回错误。其他平台返回 `Unsupported` 错误。
+pub fn pin_current_thread_to_cores(cores: &[usize]) -> io::Result<()> {
+    if cores.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "CPU核心集合为空",
+        ));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if let Some(core) = cores.iter().find(|core| **core >= libc::CPU_SETSIZE as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU核心编号 {} 超出范围", core),
+            ));
+        }
+
+        // SAFETY: cpu_set_t 是普通数据，全零即为空集合；pid 为 0 表示调用线程
+        let ret = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for core in cores {
+                libc::CPU_SET(*core, &mut set);
+            }
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    {
+        if let Some(core) = cores.iter().find(|core| **core >= usize::BITS as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("CPU核心编号 {} 超出范围", core),
+            ));
+        }
+
+        let mask = cores.iter().fold(0_usize, |mask, core| mask | (1 << core));
+
+        // SAFETY: GetCurrentThread 返回的伪句柄始终有效
+        let previous_mask = unsafe {
+            windows_thread::SetThreadAffinityMask(windows_thread::GetCurrentThread(), mask)
+        };
+
+        if previous_mask == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // 标记0表示不设置亲和性，因此核心编号加1
+        let mut policy = libc::thread_affinity_policy_data_t {
+            affinity_tag: cores[0] as libc::integer_t + 1,
+        };
+
+        // SAFETY: pthread_mach_thread_np 返回的端口属于当前线程且无需释放，
+        // policy 在调用期间有效
+        let ret = unsafe {
+            libc::thread_policy_set(
+                libc::pthread_mach_thread_np(libc::pthread_self()),
+                libc::THREAD_AFFINITY_POLICY as libc::thread_policy_flavor_t,
+                &mut policy as *mut libc::thread_affinity_policy_data_t as libc::thread_policy_t,
+                libc::THREAD_AFFINITY_POLICY_COUNT,
+            )
+        };
+
+        if ret != libc::KERN_SUCCESS {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("thread_policy_set 失败: {}", ret),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        windows,
+        target_os = "macos"
+    )))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "当前平台不支持设置线程CPU亲和性",
+        ))
+    }
+}
+
+/// 设置当前线程的优先级
+///
+/// 在Linux上 `priority` 是线程的nice值（-20到19，越小优先级越高），
+/// 在Windows上是 `SetThreadPriority` 的优先级（-2到2，越大优先级越高），
+/// 在macOS上是 `setpriority(PRIO_DARWIN_THREAD, ..)` 的值（0或 `PRIO_DARWIN_BG`），
+/// 其他平台返回 `Unsupported` 错误。
+pub fn set_current_thread_priority(priority: i32) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // SAFETY: 在Linux上，以线程ID调用 setpriority 只影响该线程
+        let ret = unsafe {
+            let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+            libc::setpriority(libc::PRIO_PROCESS, tid, priority)
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    {
+        // SAFETY: GetCurrentThread 返回的伪句柄始终有效
+        let ok = unsafe {
+            windows_thread::SetThreadPriority(windows_thread::GetCurrentThread(), priority)
+        };
+
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // SAFETY: PRIO_DARWIN_THREAD 配合 who 为 0 只影响调用线程
+        let ret = unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, priority) };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        windows,
+        target_os = "macos"
+    )))]
+    {
+        let _ = priority;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "当前平台不支持设置线程优先级",
+        ))
+    }
+}
+
+/// 在后台线程内应用CPU亲和性和优先级，失败时（例如权限不足）仅输出警告
+pub(crate) fn configure_current_thread(
+    thread: &str,
+    cores: Option<&[usize]>,
+    priority: Option<ThreadPriority>,
+) {
+    if let Some(cores) = cores
+        && let Err(e) = pin_current_thread_to_cores(cores)
+    {
+        warn_log!("无法将 {} 线程绑定到CPU核心 {:?}: {:?}", thread, cores, e);
+    }
+
+    if let Some(priority) = priority
+        && let Err(e) = priority.native_value().and_then(set_current_thread_priority)
+    {
+        warn_log!("无法将 {} 线程优先级设置为 {:?}: {:?}", thread, priority, e);
+    }
+}
+
+#[cfg(windows)]
+mod windows_thread {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn GetCurrentThread() -> isize;
+        pub fn SetThreadAffinityMask(thread: isize, mask: usize) -> usize;
+        pub fn SetThreadPriority(thread: isize, priority: i32) -> i32;
+    }
+}
+
 /// 为示例程序准备数据库
 ///
 /// 自动清理并创建示例数据库目录。
@@ -0,0 +1,205 @@
+//! 在没有64位原子指令的平台上也能使用的 `u64` 原子类型
+//!
+//! 目标平台支持64位原子操作（`target_has_atomic = "64"`）时，`PortableAtomicU64`
+//! 就是 `std::sync::atomic::AtomicU64`，没有任何额外开销。否则（例如ARMv5、
+//! 32位PowerPC和MIPS）使用一个由互斥锁保护的实现，提供相同的方法和签名，
+//! 调用方不需要区分平台。带锁的实现忽略 `Ordering` 参数：每个操作都在锁内完成，
+//! 锁本身提供的顺序不弱于任何 `Ordering`。
+
+#[cfg(target_has_atomic = "64")]
+pub(crate) use std::sync::atomic::AtomicU64 as PortableAtomicU64;
+
+#[cfg(not(target_has_atomic = "64"))]
+pub(crate) use locked::LockedAtomicU64 as PortableAtomicU64;
+
+#[cfg(any(not(target_has_atomic = "64"), test))]
+#[cfg_attr(target_has_atomic = "64", allow(dead_code))]
+mod locked {
+    use std::fmt;
+    use std::sync::atomic::Ordering;
+
+    use parking_lot::Mutex;
+
+    /// 由互斥锁保护的 `u64`，方法与 `AtomicU64` 相同
+    #[derive(Default)]
+    pub struct LockedAtomicU64(Mutex<u64>);
+
+    impl LockedAtomicU64 {
+        pub const fn new(value: u64) -> LockedAtomicU64 {
+            LockedAtomicU64(Mutex::new(value))
+        }
+
+        pub fn get_mut(&mut self) -> &mut u64 {
+            self.0.get_mut()
+        }
+
+        pub fn into_inner(self) -> u64 {
+            self.0.into_inner()
+        }
+
+        pub fn load(&self, _order: Ordering) -> u64 {
+            *self.0.lock()
+        }
+
+        pub fn store(&self, value: u64, _order: Ordering) {
+            *self.0.lock() = value;
+        }
+
+        pub fn swap(&self, value: u64, _order: Ordering) -> u64 {
+            std::mem::replace(&mut *self.0.lock(), value)
+        }
+
+        pub fn compare_exchange(
+            &self,
+            current: u64,
+            new: u64,
+            _success: Ordering,
+            _failure: Ordering,
+        ) -> Result<u64, u64> {
+            let mut value = self.0.lock();
+            if *value == current {
+                *value = new;
+                Ok(current)
+            } else {
+                Err(*value)
+            }
+        }
+
+        pub fn compare_exchange_weak(
+            &self,
+            current: u64,
+            new: u64,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<u64, u64> {
+            self.compare_exchange(current, new, success, failure)
+        }
+
+        pub fn fetch_update<F>(
+            &self,
+            _set_order: Ordering,
+            _fetch_order: Ordering,
+            mut f: F,
+        ) -> Result<u64, u64>
+        where
+            F: FnMut(u64) -> Option<u64>,
+        {
+            let mut value = self.0.lock();
+            let previous = *value;
+            match f(previous) {
+                Some(new) => {
+                    *value = new;
+                    Ok(previous)
+                }
+                None => Err(previous),
+            }
+        }
+
+        fn modify(&self, f: impl FnOnce(u64) -> u64) -> u64 {
+            let mut value = self.0.lock();
+            let previous = *value;
+            *value = f(previous);
+            previous
+        }
+
+        pub fn fetch_add(&self, delta: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value.wrapping_add(delta))
+        }
+
+        pub fn fetch_sub(&self, delta: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value.wrapping_sub(delta))
+        }
+
+        pub fn fetch_and(&self, bits: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value & bits)
+        }
+
+        pub fn fetch_or(&self, bits: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value | bits)
+        }
+
+        pub fn fetch_xor(&self, bits: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value ^ bits)
+        }
+
+        pub fn fetch_max(&self, other: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value.max(other))
+        }
+
+        pub fn fetch_min(&self, other: u64, _order: Ordering) -> u64 {
+            self.modify(|value| value.min(other))
+        }
+    }
+
+    // 全零的 `parking_lot::Mutex` 是未加锁的，因此可以存放在按零初始化的 `PageTable` 中
+    impl pagetable::Zeroable for LockedAtomicU64 {}
+
+    impl From<u64> for LockedAtomicU64 {
+        fn from(value: u64) -> LockedAtomicU64 {
+            LockedAtomicU64::new(value)
+        }
+    }
+
+    impl fmt::Debug for LockedAtomicU64 {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(&*self.0.lock(), f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+    use std::thread;
+
+    use super::locked::LockedAtomicU64;
+
+    #[test]
+    fn test_locked_matches_atomic() {
+        let atomic = AtomicU64::new(5);
+        let locked = LockedAtomicU64::new(5);
+
+        assert_eq!(atomic.fetch_add(u64::MAX, SeqCst), locked.fetch_add(u64::MAX, SeqCst));
+        assert_eq!(atomic.fetch_sub(7, SeqCst), locked.fetch_sub(7, SeqCst));
+        assert_eq!(atomic.fetch_max(100, SeqCst), locked.fetch_max(100, SeqCst));
+        assert_eq!(atomic.fetch_min(3, SeqCst), locked.fetch_min(3, SeqCst));
+        assert_eq!(atomic.fetch_or(0b1100, SeqCst), locked.fetch_or(0b1100, SeqCst));
+        assert_eq!(atomic.fetch_and(0b0110, SeqCst), locked.fetch_and(0b0110, SeqCst));
+        assert_eq!(atomic.fetch_xor(0b1111, SeqCst), locked.fetch_xor(0b1111, SeqCst));
+        assert_eq!(atomic.swap(42, SeqCst), locked.swap(42, SeqCst));
+        assert_eq!(
+            atomic.compare_exchange(41, 0, SeqCst, SeqCst),
+            locked.compare_exchange(41, 0, SeqCst, SeqCst)
+        );
+        assert_eq!(
+            atomic.compare_exchange(42, 43, SeqCst, SeqCst),
+            locked.compare_exchange(42, 43, SeqCst, SeqCst)
+        );
+        let bounded = |value: u64| value.checked_add(10).filter(|new| *new <= 50);
+        assert_eq!(atomic.fetch_update(SeqCst, SeqCst, bounded), locked.fetch_update(SeqCst, SeqCst, bounded));
+        assert_eq!(atomic.fetch_update(SeqCst, SeqCst, bounded), locked.fetch_update(SeqCst, SeqCst, bounded));
+
+        locked.store(atomic.load(SeqCst), SeqCst);
+        assert_eq!(atomic.into_inner(), locked.into_inner());
+    }
+
+    #[test]
+    fn test_locked_concurrent_increments() {
+        let locked = Arc::new(LockedAtomicU64::default());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let locked = locked.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        locked.fetch_add(1, SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(locked.load(SeqCst), 80_000);
+    }
+}
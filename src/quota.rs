@@ -0,0 +1,317 @@
+//! 按树的写入配额
+//!
+//! 一个数据库中的多个树（例如每个租户一个树）共用flush带宽和缓存，一个树的
+//! 大量写入会挤占其他树的资源。`Tree::set_quota` 为单个树设置三种限制：
+//!
+//! - `max_write_ops_per_sec`：令牌桶限速，桶的容量是一秒的写入量。超出时按
+//!   `QuotaPolicy` 延迟写入，或以 `QuotaExceeded` 拒绝写入
+//! - `max_dirty_bytes`：树自上一次flush以来写入的字节数超过限制时，写入方先
+//!   执行一次flush，这期间只有这个树的写入方等待
+//! - `max_cache_bytes`：树在缓存中的叶子节点超过限制时，优先淘汰这个树自己的
+//!   叶子节点，腾出的空间留给其他树
+//!
+//! 脏字节数按写入的键和值的长度估算，在包含最早一次计入的写入的flush epoch
+//! 被flush后清零。缓存占用每 `CACHE_CHECK_INTERVAL` 次写入检查一次。
+//! 配额不会持久化，重新打开数据库后需要再次设置。
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::QuotaExceeded;
+use crate::portable_atomic::PortableAtomicU64;
+
+/// 每隔多少次写入检查一次树的缓存占用
+pub(crate) const CACHE_CHECK_INTERVAL: usize = 64;
+
+/// 超出 `TreeQuota::max_write_ops_per_sec` 的写入的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotaPolicy {
+    /// 等待到令牌桶中有足够的令牌后再写入
+    #[default]
+    Delay,
+    /// 立即以 `io::ErrorKind::QuotaExceeded` 的 `QuotaExceeded` 错误拒绝写入
+    Reject,
+}
+
+/// 一个树的配额，`None` 表示不限制，参见 `Tree::set_quota`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TreeQuota {
+    /// 自上一次flush以来最多写入的字节数（键和值的长度之和）
+    pub max_dirty_bytes: Option<usize>,
+    /// 每秒最多的写入次数，批次中的每个写入各计一次
+    pub max_write_ops_per_sec: Option<u32>,
+    /// 树在缓存中的叶子节点最多占用的内存（字节）
+    pub max_cache_bytes: Option<usize>,
+    /// 超出写入速率限制时的处理方式
+    pub policy: QuotaPolicy,
+}
+
+impl TreeQuota {
+    fn is_unlimited(&self) -> bool {
+        self.max_dirty_bytes.is_none()
+            && self.max_write_ops_per_sec.is_none()
+            && self.max_cache_bytes.is_none()
+    }
+
+    /// 检查配额是否有效：所有限制都必须大于0
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.max_dirty_bytes == Some(0)
+            || self.max_write_ops_per_sec == Some(0)
+            || self.max_cache_bytes == Some(0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("树的配额必须大于0，实际为 {:?}", self),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 一个树的配额和使用情况，由 `Tree::quota_usage` 返回
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QuotaUsage {
+    /// 当前的配额
+    pub quota: TreeQuota,
+    /// 自上一次flush以来写入的字节数（估算值）
+    pub dirty_bytes: usize,
+    /// 树在缓存中的叶子节点占用的内存（字节）
+    pub resident_bytes: usize,
+    /// 因写入速率限制而被延迟的写入次数
+    pub writes_delayed: u64,
+    /// 因写入速率限制而等待的总时间
+    pub write_delay: Duration,
+    /// 因写入速率限制而被拒绝的写入次数
+    pub writes_rejected: u64,
+    /// 因脏字节数超过限制而由写入方执行的flush次数
+    pub dirty_flushes: u64,
+    /// 因缓存占用超过限制而被淘汰的叶子节点数，脏叶子节点在下一次flush后才被淘汰
+    pub leaves_paged_out: u64,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// 一个树的配额状态，由树的所有句柄共享
+#[derive(Debug)]
+pub(crate) struct QuotaState {
+    quota: RwLock<TreeQuota>,
+    // 没有任何限制时写入路径只读取这个标志
+    enabled: AtomicBool,
+    bucket: Mutex<TokenBucket>,
+    // (最早一次计入的写入所在的flush epoch, 自那以后写入的字节数)
+    dirty: Mutex<(u64, usize)>,
+    // 同一时刻只有一个写入方为这个树执行flush，其他写入方在这里等待
+    dirty_flush: Mutex<()>,
+    writes_since_cache_check: AtomicUsize,
+    writes_delayed: PortableAtomicU64,
+    write_delay_nanos: PortableAtomicU64,
+    writes_rejected: PortableAtomicU64,
+    dirty_flushes: PortableAtomicU64,
+    leaves_paged_out: PortableAtomicU64,
+}
+
+impl Default for QuotaState {
+    fn default() -> QuotaState {
+        QuotaState {
+            quota: RwLock::default(),
+            enabled: AtomicBool::new(false),
+            bucket: Mutex::new(TokenBucket { tokens: 0.0, refilled_at: Instant::now() }),
+            dirty: Mutex::default(),
+            dirty_flush: Mutex::default(),
+            writes_since_cache_check: AtomicUsize::new(0),
+            writes_delayed: PortableAtomicU64::new(0),
+            write_delay_nanos: PortableAtomicU64::new(0),
+            writes_rejected: PortableAtomicU64::new(0),
+            dirty_flushes: PortableAtomicU64::new(0),
+            leaves_paged_out: PortableAtomicU64::new(0),
+        }
+    }
+}
+
+impl QuotaState {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn quota(&self) -> TreeQuota {
+        *self.quota.read()
+    }
+
+    /// 替换配额，令牌桶重新装满
+    pub(crate) fn set(&self, quota: TreeQuota) {
+        let mut current = self.quota.write();
+        *self.bucket.lock() = TokenBucket {
+            tokens: quota.max_write_ops_per_sec.map_or(0.0, f64::from),
+            refilled_at: Instant::now(),
+        };
+        *current = quota;
+        self.enabled.store(!quota.is_unlimited(), Ordering::Release);
+    }
+
+    /// 从令牌桶中取出 `ops` 个令牌，令牌不足时按配额的策略等待或返回错误
+    pub(crate) fn admit(&self, ops: usize) -> io::Result<()> {
+        let quota = self.quota();
+        let Some(rate) = quota.max_write_ops_per_sec else {
+            return Ok(());
+        };
+        let rate = f64::from(rate);
+        let ops = ops as f64;
+
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(rate);
+            bucket.refilled_at = now;
+
+            // 超过桶容量的批次在桶满时放行，之后的写入等待令牌补足
+            let needed = ops.min(rate);
+            if bucket.tokens >= needed {
+                bucket.tokens -= ops;
+                return Ok(());
+            }
+
+            let wait = Duration::from_secs_f64((needed - bucket.tokens) / rate);
+            if quota.policy == QuotaPolicy::Reject {
+                self.writes_rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(QuotaExceeded {
+                    max_write_ops_per_sec: rate as u32,
+                    retry_after: wait,
+                }
+                .into());
+            }
+            // 预先取出令牌，使并发的写入方依次排队而不是同时醒来
+            bucket.tokens -= ops;
+            wait
+        };
+
+        self.writes_delayed.fetch_add(1, Ordering::Relaxed);
+        self.write_delay_nanos.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        std::thread::sleep(wait);
+        Ok(())
+    }
+
+    /// 树自上一次flush以来写入的字节数
+    pub(crate) fn dirty_bytes(&self, max_flushed_epoch: u64) -> usize {
+        let dirty = self.dirty.lock();
+        if dirty.0 <= max_flushed_epoch { 0 } else { dirty.1 }
+    }
+
+    pub(crate) fn over_dirty_limit(&self, max_flushed_epoch: u64) -> bool {
+        self.quota()
+            .max_dirty_bytes
+            .is_some_and(|max| self.dirty_bytes(max_flushed_epoch) >= max)
+    }
+
+    /// 在执行flush之前获取，使同一个树的其他写入方等待flush完成
+    pub(crate) fn lock_dirty_flush(&self) -> parking_lot::MutexGuard<'_, ()> {
+        self.dirty_flush.lock()
+    }
+
+    pub(crate) fn record_dirty_flush(&self) {
+        self.dirty_flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录在 `flush_epoch` 中写入的 `bytes` 字节，并返回是否应该检查缓存占用
+    pub(crate) fn record_write(&self, bytes: usize, flush_epoch: u64, max_flushed_epoch: u64) -> bool {
+        {
+            let mut dirty = self.dirty.lock();
+            if dirty.0 <= max_flushed_epoch {
+                *dirty = (flush_epoch, 0);
+            }
+            dirty.1 += bytes;
+        }
+        self.quota().max_cache_bytes.is_some()
+            && self.writes_since_cache_check.fetch_add(1, Ordering::Relaxed) % CACHE_CHECK_INTERVAL
+                == CACHE_CHECK_INTERVAL - 1
+    }
+
+    pub(crate) fn record_paged_out(&self, leaves: usize) {
+        self.leaves_paged_out.fetch_add(leaves as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn usage(&self, max_flushed_epoch: u64, resident_bytes: usize) -> QuotaUsage {
+        QuotaUsage {
+            quota: self.quota(),
+            dirty_bytes: self.dirty_bytes(max_flushed_epoch),
+            resident_bytes,
+            writes_delayed: self.writes_delayed.load(Ordering::Relaxed),
+            write_delay: Duration::from_nanos(self.write_delay_nanos.load(Ordering::Relaxed)),
+            writes_rejected: self.writes_rejected.load(Ordering::Relaxed),
+            dirty_flushes: self.dirty_flushes.load(Ordering::Relaxed),
+            leaves_paged_out: self.leaves_paged_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited(max_write_ops_per_sec: u32, policy: QuotaPolicy) -> QuotaState {
+        let state = QuotaState::default();
+        state.set(TreeQuota {
+            max_write_ops_per_sec: Some(max_write_ops_per_sec),
+            policy,
+            ..TreeQuota::default()
+        });
+        state
+    }
+
+    #[test]
+    fn test_reject_after_burst() {
+        let state = rate_limited(10, QuotaPolicy::Reject);
+        for _ in 0..10 {
+            state.admit(1).unwrap();
+        }
+        let error = state.admit(1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::QuotaExceeded);
+        let exceeded = QuotaExceeded::from_io_error(&error).unwrap();
+        assert!(exceeded.retry_after <= Duration::from_millis(100), "{:?}", exceeded);
+        assert_eq!(state.usage(0, 0).writes_rejected, 1);
+    }
+
+    #[test]
+    fn test_delay_waits_for_tokens() {
+        let state = rate_limited(100, QuotaPolicy::Delay);
+        state.admit(100).unwrap();
+        let start = Instant::now();
+        state.admit(10).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(90), "{:?}", start.elapsed());
+        assert_eq!(state.usage(0, 0).writes_delayed, 1);
+    }
+
+    #[test]
+    fn test_dirty_bytes_reset_after_flush() {
+        let state = QuotaState::default();
+        state.set(TreeQuota { max_dirty_bytes: Some(100), ..TreeQuota::default() });
+
+        state.record_write(60, 2, 1);
+        state.record_write(60, 2, 1);
+        assert!(state.over_dirty_limit(1));
+        // 包含这些写入的epoch 2被flush之后清零
+        assert!(!state.over_dirty_limit(2));
+        state.record_write(10, 3, 2);
+        assert_eq!(state.dirty_bytes(2), 10);
+    }
+
+    #[test]
+    fn test_unlimited_quota_is_disabled() {
+        let state = QuotaState::default();
+        assert!(!state.is_enabled());
+        state.set(TreeQuota { max_cache_bytes: Some(1), ..TreeQuota::default() });
+        assert!(state.is_enabled());
+        state.set(TreeQuota::default());
+        assert!(!state.is_enabled());
+
+        let invalid = TreeQuota { max_write_ops_per_sec: Some(0), ..TreeQuota::default() };
+        assert_eq!(invalid.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,188 @@
+//! 范围扫描的预读
+//!
+//! 正向的 `Iter` 每进入一个新的叶子节点，就从内存中的索引找出接下来要访问的
+//! `IterOptions::read_ahead_leaves` 个叶子节点，在后台线程池中从堆文件读取其中
+//! 不在缓存中的叶子节点并放入缓存，使迭代器到达这些叶子节点时不必等待磁盘IO：
+//!
+//! - 只预读范围上界之前的叶子节点，由调用者在安排预读时限制
+//! - 预读的叶子节点按迭代器的 `CachePolicy` 进入缓存。预读的结果只能通过缓存
+//!   交给迭代器，因此 `CachePolicy::BypassCache` 的扫描不预读
+//! - 使用准入过滤器时，`CachePolicy::Normal` 的预读和普通的读取一样先经过准入
+//! - 迭代器被丢弃时取消尚未开始的读取，并等待正在进行的读取结束，
+//!   因此迭代器丢弃之后不会有后台线程继续持有数据库的堆文件
+//!
+//! 预读只是提示：叶子节点被其他线程锁定、已经被合并或者读取失败时直接跳过，
+//! 迭代器到达时自行读取并报告错误。
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use inline_array::InlineArray;
+use parking_lot::{Condvar, Mutex};
+
+use crate::leaf::Leaf;
+use crate::object_cache::ObjectCache;
+use crate::{CachePolicy, Config, Object, debug_log};
+
+/// 预读线程池的线程数。预读的耗时主要在等待IO上，因此不随CPU核心数变化
+const READ_AHEAD_THREADS: usize = 8;
+
+/// 创建数据库的预读线程池，线程使用flusher线程的CPU亲和性和优先级。
+/// 不使用rayon的全局线程池，因为丢弃迭代器时要等待预读结束，
+/// 而迭代器可能在全局线程池中被丢弃
+pub(crate) fn build_pool(config: &Config) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(READ_AHEAD_THREADS)
+        .thread_name(|i| format!("melange-read-ahead-{}", i))
+        .start_handler(crate::db::flusher_pool_start_handler(config, "预读"))
+        .build()
+        .expect("无法启动预读线程池")
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    cancelled: AtomicBool,
+    in_flight: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// 在任务结束时（包括panic）减少进行中的读取数
+struct InFlight(Arc<Shared>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut in_flight = self.0.in_flight.lock();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+/// 一个迭代器的预读状态
+#[derive(Debug)]
+pub(crate) struct ReadAhead {
+    leaves: usize,
+    policy: CachePolicy,
+    /// 最后一个已安排预读的叶子节点的低键
+    scheduled_until: Option<InlineArray>,
+    shared: Arc<Shared>,
+}
+
+impl ReadAhead {
+    pub(crate) fn new(leaves: usize, policy: CachePolicy) -> ReadAhead {
+        ReadAhead {
+            leaves: if policy == CachePolicy::BypassCache { 0 } else { leaves },
+            policy,
+            scheduled_until: None,
+            shared: Arc::default(),
+        }
+    }
+
+    /// 是否启用了预读
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.leaves > 0
+    }
+
+    /// 为 `upcoming` 的前 `read_ahead_leaves` 个叶子节点中尚未安排过的安排预读。
+    /// `upcoming` 按键的顺序给出迭代器接下来访问的叶子节点，调用者负责把它限制在
+    /// 范围上界之前
+    pub(crate) fn schedule<const LEAF_FANOUT: usize>(
+        &mut self,
+        cache: &ObjectCache<LEAF_FANOUT>,
+        upcoming: impl Iterator<Item = (InlineArray, Object<LEAF_FANOUT>)>,
+    ) {
+        for (low_key, node) in upcoming.take(self.leaves) {
+            if self.scheduled_until.as_ref().is_some_and(|until| *until >= low_key) {
+                continue;
+            }
+            self.scheduled_until = Some(low_key.clone());
+
+            // 已经在缓存中，或者正被其他线程使用
+            if node.inner.try_read().is_none_or(|cache_box| cache_box.leaf.is_some()) {
+                continue;
+            }
+
+            *self.shared.in_flight.lock() += 1;
+            let in_flight = InFlight(self.shared.clone());
+            let pool = cache.read_ahead_pool();
+            let cache = cache.clone();
+            let policy = self.policy;
+            pool.spawn(move || {
+                let in_flight = in_flight;
+                let cache = cache;
+                if in_flight.0.cancelled.load(Ordering::Acquire) {
+                    return;
+                }
+                if let Err(e) = read_leaf(&cache, policy, &low_key, &node) {
+                    debug_log!("预读低键为 {:?} 的叶子节点失败: {:?}", low_key, e);
+                }
+            });
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        self.shared.cancelled.store(true, Ordering::Release);
+        let mut in_flight = self.shared.in_flight.lock();
+        while *in_flight > 0 {
+            self.shared.idle.wait(&mut in_flight);
+        }
+    }
+}
+
+/// 把一个不在缓存中的叶子节点从堆文件读入缓存
+fn read_leaf<const LEAF_FANOUT: usize>(
+    cache: &ObjectCache<LEAF_FANOUT>,
+    policy: CachePolicy,
+    low_key: &InlineArray,
+    node: &Object<LEAF_FANOUT>,
+) -> io::Result<()> {
+    if policy == CachePolicy::Normal && !cache.admit(node.object_id) {
+        return Ok(());
+    }
+
+    let heap_pin = cache.heap_object_id_pin();
+
+    // 叶子节点在安排预读之后被合并，它的对象ID可能已经被释放
+    let is_current = cache
+        .object_id_index
+        .get(&node.object_id)
+        .is_some_and(|current| Arc::ptr_eq(&current.inner, &node.inner));
+    if !is_current {
+        return Ok(());
+    }
+
+    let Some(mut write) = node.inner.try_write() else {
+        return Ok(());
+    };
+    if write.leaf.is_some() {
+        return Ok(());
+    }
+
+    let Some(leaf_bytes) = cache.read(node.object_id) else {
+        return Ok(());
+    };
+    let leaf: Box<Leaf<LEAF_FANOUT>> =
+        Leaf::deserialize(&leaf_bytes?, cache.dictionaries(), cache.blobs())?;
+    if leaf.deleted.is_some() || leaf.lo != *low_key {
+        return Ok(());
+    }
+
+    let size = leaf.in_memory_size;
+    write.leaf = Some(leaf);
+    drop(write);
+    drop(heap_pin);
+
+    cache.read_stats.read_ahead_leaves.fetch_add(1, Ordering::Relaxed);
+
+    // 和 `Tree::page_in` 读入的叶子节点一样登记到缓存中，
+    // `CachePolicy::ReadDontPromote` 的叶子节点因此进入入口段
+    cache.mark_access_and_evict(
+        node.object_id,
+        size,
+        cache.current_flush_epoch(),
+    )
+}
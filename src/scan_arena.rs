@@ -0,0 +1,124 @@
+//! 短期扫描使用的内存池
+//!
+//! `Tree::scan_prefix_in` 将扫描到的键和值从加锁的叶子节点复制到调用者提供的
+//! `ScanArena` 中，返回借用内存池的切片，避免为每个条目单独分配内存。内存池按块
+//! 增长，`reset` 之后保留已经分配的块供下一次扫描复用。
+
+use std::cell::{Cell, UnsafeCell};
+
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 为扫描结果分配内存的内存池
+///
+/// 分配出的切片在调用 `reset` 或释放内存池之前一直有效。内存池不是线程安全的，
+/// 每个扫描线程使用自己的内存池。
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// db.insert(b"user_1", b"alice".as_slice())?;
+/// db.insert(b"user_2", b"bob".as_slice())?;
+///
+/// let mut arena = melange_db::ScanArena::new();
+/// let users: Vec<(&[u8], &[u8])> =
+///     db.scan_prefix_in(b"user_", &arena).collect::<std::io::Result<_>>()?;
+/// assert_eq!(users[1], (&b"user_2"[..], &b"bob"[..]));
+///
+/// drop(users);
+/// arena.reset();
+/// # Ok(()) }
+/// ```
+#[derive(Default)]
+pub struct ScanArena {
+    // Chunks are never resized or read through references other than the
+    // slices handed out by `alloc`, so pushing new chunks does not move or
+    // invalidate bytes that are already borrowed.
+    chunks: UnsafeCell<Vec<Vec<u8>>>,
+    current: Cell<usize>,
+    used: Cell<usize>,
+    allocated: Cell<usize>,
+}
+
+impl ScanArena {
+    pub fn new() -> ScanArena {
+        ScanArena::default()
+    }
+
+    /// 创建一个预先分配了 `capacity` 字节的内存池
+    pub fn with_capacity(capacity: usize) -> ScanArena {
+        let arena = ScanArena::default();
+        if capacity > 0 {
+            // SAFETY: nothing has been allocated from the arena yet
+            unsafe { &mut *arena.chunks.get() }.push(Vec::with_capacity(capacity));
+        }
+        arena
+    }
+
+    /// 将 `bytes` 复制到内存池中
+    pub fn alloc(&self, bytes: &[u8]) -> &[u8] {
+        if bytes.is_empty() {
+            return &[];
+        }
+
+        // SAFETY: the arena is not `Sync` and this is the only place that
+        // accesses the chunk list through a shared reference. Slices already
+        // handed out point into the chunks' heap buffers, which stay in place
+        // when the list itself grows.
+        let chunks = unsafe { &mut *self.chunks.get() };
+
+        let mut current = self.current.get();
+        let mut used = self.used.get();
+        while current < chunks.len() && chunks[current].capacity() - used < bytes.len() {
+            current += 1;
+            used = 0;
+        }
+        if current == chunks.len() {
+            let last = chunks.last().map_or(0, Vec::capacity);
+            let size = (last * 2).max(MIN_CHUNK_SIZE).max(bytes.len());
+            chunks.push(Vec::with_capacity(size));
+        }
+
+        // SAFETY: the loop above made sure `bytes` fits into the unused
+        // capacity of the chunk, which no handed out slice overlaps
+        let ret = unsafe {
+            let dst = chunks[current].as_mut_ptr().add(used);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len());
+            std::slice::from_raw_parts(dst, bytes.len())
+        };
+
+        self.current.set(current);
+        self.used.set(used + bytes.len());
+        self.allocated.set(self.allocated.get() + bytes.len());
+        ret
+    }
+
+    /// 自上次 `reset` 以来分配的字节数
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated.get()
+    }
+
+    /// 内存池持有的块的总大小
+    pub fn capacity(&self) -> usize {
+        // SAFETY: only reads the chunk capacities, `alloc` is not running
+        unsafe { &*self.chunks.get() }.iter().map(Vec::capacity).sum()
+    }
+
+    /// 使之前分配的切片失效，保留已经分配的块
+    pub fn reset(&mut self) {
+        self.current.set(0);
+        self.used.set(0);
+        self.allocated.set(0);
+    }
+}
+
+impl std::fmt::Debug for ScanArena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScanArena")
+            .field("allocated_bytes", &self.allocated_bytes())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
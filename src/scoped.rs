@@ -0,0 +1,304 @@
+//! 限定在一个键前缀之内的树视图
+//!
+//! `ScopedTree` 在写入时为键加上前缀，在读取和迭代时去掉前缀。作用域内的键
+//! 经过拼接后总是以前缀开头，迭代的范围也被限制在前缀之内，所以通过它无法
+//! 读取或修改前缀之外的键。
+//!
+//! 注意前缀只是字节前缀：作用域 `tenant1` 也包含 `tenant10` 的键。需要互不
+//! 重叠的作用域时，应以分隔符结尾，例如 `tenant1/`。
+
+use std::io;
+use std::ops::{Bound, RangeBounds};
+
+use inline_array::InlineArray;
+
+use crate::batch_spill::BatchSpill;
+use crate::{Batch, CompareAndSwapResult, Iter, Tree};
+
+/// 前缀与键拼接后不超过该长度时在栈上拼接，不分配内存
+const INLINE_KEY_LEN: usize = 64;
+
+/// 前缀与一个键拼接后的完整键
+enum ScopedKey {
+    Inline([u8; INLINE_KEY_LEN], usize),
+    Heap(Vec<u8>),
+}
+
+impl ScopedKey {
+    fn new(prefix: &[u8], key: &[u8]) -> ScopedKey {
+        let len = prefix.len() + key.len();
+        if len <= INLINE_KEY_LEN {
+            let mut buf = [0; INLINE_KEY_LEN];
+            buf[..prefix.len()].copy_from_slice(prefix);
+            buf[prefix.len()..len].copy_from_slice(key);
+            ScopedKey::Inline(buf, len)
+        } else {
+            let mut buf = Vec::with_capacity(len);
+            buf.extend_from_slice(prefix);
+            buf.extend_from_slice(key);
+            ScopedKey::Heap(buf)
+        }
+    }
+}
+
+impl AsRef<[u8]> for ScopedKey {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            ScopedKey::Inline(buf, len) => &buf[..*len],
+            ScopedKey::Heap(buf) => buf,
+        }
+    }
+}
+
+/// 大于所有以 `prefix` 开头的键的最小键，`prefix` 为空或全部是0xFF时不存在
+fn prefix_upper_bound(prefix: &[u8]) -> Option<InlineArray> {
+    let mut upper = prefix.to_vec();
+    while let Some(last) = upper.pop() {
+        if last < u8::MAX {
+            upper.push(last + 1);
+            return Some(upper.into());
+        }
+    }
+    None
+}
+
+/// 限定在一个键前缀之内的 `Tree` 句柄，由 [`Tree::scoped`] 创建
+///
+/// 所有方法的键都是相对于前缀的键，返回的键也已去掉前缀。
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// let tenant = db.scoped(b"tenant_a/");
+/// tenant.insert(b"user", b"alice".as_slice())?;
+///
+/// assert_eq!(&*db.get(b"tenant_a/user")?.unwrap(), b"alice");
+/// assert_eq!(&*tenant.iter().next().unwrap()?.0, b"user");
+/// assert!(db.scoped(b"tenant_b/").get(b"user")?.is_none());
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct ScopedTree<const LEAF_FANOUT: usize = 1024> {
+    tree: Tree<LEAF_FANOUT>,
+    prefix: InlineArray,
+}
+
+impl<const LEAF_FANOUT: usize> std::fmt::Debug for ScopedTree<LEAF_FANOUT> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScopedTree").field("prefix", &self.prefix).finish()
+    }
+}
+
+impl<const LEAF_FANOUT: usize> ScopedTree<LEAF_FANOUT> {
+    pub(crate) fn new(tree: Tree<LEAF_FANOUT>, prefix: &[u8]) -> ScopedTree<LEAF_FANOUT> {
+        ScopedTree { tree, prefix: prefix.into() }
+    }
+
+    fn key(&self, key: &[u8]) -> ScopedKey {
+        ScopedKey::new(&self.prefix, key)
+    }
+
+    /// 作用域的前缀
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// 在当前作用域之内再限定一个前缀
+    pub fn scoped<P: AsRef<[u8]>>(&self, prefix: P) -> ScopedTree<LEAF_FANOUT> {
+        ScopedTree::new(self.tree.clone(), self.key(prefix.as_ref()).as_ref())
+    }
+
+    /// 见 [`Tree::get`]
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.tree.get(self.key(key.as_ref()))
+    }
+
+    /// 见 [`Tree::contains_key`]
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> io::Result<bool> {
+        self.tree.contains_key(self.key(key.as_ref()))
+    }
+
+    /// 见 [`Tree::insert`]
+    pub fn insert<K, V>(&self, key: K, value: V) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        self.tree.insert(self.key(key.as_ref()), value)
+    }
+
+    /// 见 [`Tree::remove`]
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.tree.remove(self.key(key.as_ref()))
+    }
+
+    /// 见 [`Tree::compare_and_swap`]
+    pub fn compare_and_swap<K, OV, NV>(
+        &self,
+        key: K,
+        old: Option<OV>,
+        new: Option<NV>,
+    ) -> CompareAndSwapResult
+    where
+        K: AsRef<[u8]>,
+        OV: AsRef<[u8]>,
+        NV: Into<InlineArray>,
+    {
+        self.tree.compare_and_swap(self.key(key.as_ref()), old, new)
+    }
+
+    /// 见 [`Tree::update_and_fetch`]
+    pub fn update_and_fetch<K, V, F>(&self, key: K, f: F) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        self.tree.update_and_fetch(self.key(key.as_ref()), f)
+    }
+
+    /// 见 [`Tree::fetch_and_update`]
+    pub fn fetch_and_update<K, V, F>(&self, key: K, f: F) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        self.tree.fetch_and_update(self.key(key.as_ref()), f)
+    }
+
+    /// 原子地应用一个批次，批次和守卫中的键都是相对于前缀的键。见 [`Tree::apply_batch`]
+    pub fn apply_batch(&self, mut batch: Batch) -> io::Result<()> {
+        let mut scoped = Batch {
+            spill: batch.spill.as_ref().map(|spill| BatchSpill::new(spill.threshold)),
+            ..Batch::default()
+        };
+
+        let writes = std::mem::take(&mut batch.writes);
+        let mut add = |key: InlineArray, value: Option<InlineArray>| {
+            let key: InlineArray = self.key(&key).as_ref().into();
+            match value {
+                Some(value) => scoped.insert(key, value),
+                None => scoped.remove(key),
+            }
+        };
+        match batch.spill.take().filter(|spill| !spill.runs.is_empty()) {
+            Some(spill) => {
+                for write in spill.merged(writes)? {
+                    let (key, value) = write?;
+                    add(key, value);
+                }
+            }
+            None => {
+                for (key, value) in writes {
+                    add(key, value);
+                }
+            }
+        }
+
+        for (key, expected) in batch.guards {
+            scoped.guard(self.key(&key).as_ref(), expected);
+        }
+
+        self.tree.apply_batch(scoped)
+    }
+
+    /// 作用域内所有键值对的迭代器
+    pub fn iter(&self) -> ScopedIter<LEAF_FANOUT> {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// 作用域内一个范围的迭代器，范围的边界是相对于前缀的键，无界的一端被限制在
+    /// 前缀之内
+    pub fn range<K, R>(&self, range: R) -> ScopedIter<LEAF_FANOUT>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let full = |key: &K| -> InlineArray { self.key(key.as_ref()).as_ref().into() };
+
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included(full(key)),
+            Bound::Excluded(key) => Bound::Excluded(full(key)),
+            Bound::Unbounded => Bound::Included(self.prefix.clone()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(full(key)),
+            Bound::Excluded(key) => Bound::Excluded(full(key)),
+            Bound::Unbounded => match prefix_upper_bound(&self.prefix) {
+                Some(upper) => Bound::Excluded(upper),
+                None => Bound::Unbounded,
+            },
+        };
+
+        ScopedIter { inner: self.tree.range((start, end)), prefix_len: self.prefix.len() }
+    }
+
+    /// 作用域内以 `prefix` 开头的键值对的迭代器
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> ScopedIter<LEAF_FANOUT> {
+        ScopedIter {
+            inner: self.tree.scan_prefix(self.key(prefix.as_ref())),
+            prefix_len: self.prefix.len(),
+        }
+    }
+
+    /// 作用域内的第一个键值对
+    pub fn first(&self) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.iter().next().transpose()
+    }
+
+    /// 作用域内的最后一个键值对
+    pub fn last(&self) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.iter().next_back().transpose()
+    }
+
+    /// 作用域内的键值对数量，需要遍历整个作用域
+    pub fn len(&self) -> io::Result<usize> {
+        let mut len = 0;
+        for item in self.iter() {
+            item?;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    /// 作用域内是否没有任何键
+    pub fn is_empty(&self) -> io::Result<bool> {
+        self.tree.contains_prefix(&self.prefix).map(|contains| !contains)
+    }
+}
+
+/// `ScopedTree` 的迭代器，返回的键已去掉前缀
+pub struct ScopedIter<const LEAF_FANOUT: usize> {
+    inner: Iter<LEAF_FANOUT>,
+    prefix_len: usize,
+}
+
+impl<const LEAF_FANOUT: usize> ScopedIter<LEAF_FANOUT> {
+    fn strip(
+        &self,
+        item: io::Result<(InlineArray, InlineArray)>,
+    ) -> io::Result<(InlineArray, InlineArray)> {
+        let (key, value) = item?;
+        Ok((InlineArray::from(&key[self.prefix_len..]), value))
+    }
+}
+
+impl<const LEAF_FANOUT: usize> Iterator for ScopedIter<LEAF_FANOUT> {
+    type Item = io::Result<(InlineArray, InlineArray)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        Some(self.strip(item))
+    }
+}
+
+impl<const LEAF_FANOUT: usize> DoubleEndedIterator for ScopedIter<LEAF_FANOUT> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next_back()?;
+        Some(self.strip(item))
+    }
+}
@@ -0,0 +1,174 @@
+//! 持久化的严格单调序列
+//!
+//! `Sequence` 在内存中从一段预留的范围分配ID，并在默认树中持久化这段范围的
+//! 上界（高水位）。分配的ID达到持久化的上界之前，先把新的上界
+//! `当前值 + batch_size` 写入并flush，然后才交出超过旧上界的ID。因此交出的
+//! ID总是小于磁盘上的上界，崩溃后从持久化的上界继续分配，不会重复交出任何ID，
+//! 代价是每次重启最多跳过 `batch_size` 个ID，每个批次只有一次持久化写入。
+//!
+//! 上界以 `__sequence__:` 加序列名称为键存储在默认树中，与计数器一样
+//! 对默认树的迭代可见。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::Tree;
+
+/// 持久化序列上界的键前缀
+pub(crate) const SEQUENCE_KEY_PREFIX: &[u8] = b"__sequence__:";
+
+/// `Db::sequence` 使用的默认批次大小
+pub(crate) const DEFAULT_SEQUENCE_BATCH_SIZE: u64 = 1024;
+
+/// 同一个数据库中按名称共享的序列状态，同名的句柄从同一个范围分配
+pub(crate) type SequenceRegistry = Mutex<HashMap<Vec<u8>, Arc<Mutex<SequenceState>>>>;
+
+#[derive(Debug)]
+pub(crate) struct SequenceState {
+    /// 下一个交出的ID
+    next: u64,
+    /// 已持久化的上界，交出的ID都小于它
+    ceiling: u64,
+    batch_size: u64,
+}
+
+fn sequence_key(name: &[u8]) -> Vec<u8> {
+    [SEQUENCE_KEY_PREFIX, name].concat()
+}
+
+/// 持久化的严格单调序列，由 [`Db::sequence`](crate::Db::sequence) 创建，
+/// 参见模块文档
+///
+/// 句柄可以克隆并在线程之间共享，同一个数据库中同名的所有句柄共享同一个序列。
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// let sequence = db.sequence("orders")?;
+/// let first = sequence.next()?;
+/// assert_eq!(sequence.peek(), first + 1);
+/// assert!(sequence.next()? > first);
+///
+/// // 之后交出的ID不小于下限
+/// sequence.set_floor(1_000_000)?;
+/// assert_eq!(sequence.next()?, 1_000_000);
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct Sequence<const LEAF_FANOUT: usize = 1024> {
+    tree: Tree<LEAF_FANOUT>,
+    key: Vec<u8>,
+    state: Arc<Mutex<SequenceState>>,
+}
+
+impl<const LEAF_FANOUT: usize> fmt::Debug for Sequence<LEAF_FANOUT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequence")
+            .field("name", &String::from_utf8_lossy(&self.key[SEQUENCE_KEY_PREFIX.len()..]))
+            .field("state", &*self.state.lock())
+            .finish()
+    }
+}
+
+impl<const LEAF_FANOUT: usize> Sequence<LEAF_FANOUT> {
+    /// 打开名为 `name` 的序列。第一次打开时从 `tree` 中读取持久化的上界，
+    /// 从它开始分配；已经打开过时只更新批次大小
+    pub(crate) fn open(
+        tree: &Tree<LEAF_FANOUT>,
+        registry: &SequenceRegistry,
+        name: &[u8],
+        batch_size: u64,
+    ) -> io::Result<Sequence<LEAF_FANOUT>> {
+        if batch_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "序列的批次大小不能为0"));
+        }
+
+        let key = sequence_key(name);
+        let mut registry = registry.lock();
+        let state = match registry.get(name) {
+            Some(state) => {
+                state.lock().batch_size = batch_size;
+                state.clone()
+            }
+            None => {
+                let ceiling = match tree.get(&key)? {
+                    Some(bytes) => {
+                        let bytes: [u8; 8] = bytes.as_ref().try_into().map_err(|_| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("序列 {:?} 持久化的上界长度为 {}", String::from_utf8_lossy(name), bytes.len()),
+                            )
+                        })?;
+                        u64::from_be_bytes(bytes)
+                    }
+                    None => 0,
+                };
+                let state = Arc::new(Mutex::new(SequenceState { next: ceiling, ceiling, batch_size }));
+                registry.insert(name.to_vec(), state.clone());
+                state
+            }
+        };
+
+        Ok(Sequence { tree: tree.clone(), key, state })
+    }
+
+    /// 交出下一个ID，严格大于之前交出的所有ID，包括崩溃和重新打开之前交出的。
+    /// 当前批次用完时先持久化新的上界，持久化失败时返回错误且不交出ID
+    pub fn next(&self) -> io::Result<u64> {
+        let mut state = self.state.lock();
+        if state.next >= state.ceiling {
+            let ceiling = state.next.checked_add(state.batch_size).ok_or_else(|| {
+                io::Error::other(format!(
+                    "序列 {:?} 已耗尽",
+                    String::from_utf8_lossy(&self.key[SEQUENCE_KEY_PREFIX.len()..])
+                ))
+            })?;
+            self.persist_ceiling(ceiling)?;
+            state.ceiling = ceiling;
+        }
+        let id = state.next;
+        state.next += 1;
+        Ok(id)
+    }
+
+    /// 下一次 `next` 将交出的ID，不分配它
+    pub fn peek(&self) -> u64 {
+        self.state.lock().next
+    }
+
+    /// 保证之后交出的ID都不小于 `floor`。`floor` 超过已持久化的上界时先把它
+    /// 持久化为新的上界，因此下限在崩溃之后仍然有效。`floor` 不大于下一个ID时
+    /// 不做任何修改
+    pub fn set_floor(&self, floor: u64) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if floor <= state.next {
+            return Ok(());
+        }
+        if floor > state.ceiling {
+            self.persist_ceiling(floor)?;
+            state.ceiling = floor;
+        }
+        state.next = floor;
+        Ok(())
+    }
+
+    /// 每次持久化上界时预留的ID数量
+    pub fn batch_size(&self) -> u64 {
+        self.state.lock().batch_size
+    }
+
+    /// 写入新的上界并等待它被持久化。调用方持有状态锁，
+    /// 同一个序列的其他调用等待这次写入完成
+    fn persist_ceiling(&self, ceiling: u64) -> io::Result<()> {
+        self.tree.insert(&self.key, &ceiling.to_be_bytes()[..])?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
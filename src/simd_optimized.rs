@@ -125,13 +125,10 @@ impl SimdComparator {
 
             // 如果所有字节都相等，eq_mask将是全1
             if vminvq_u8(eq_mask) != 0xFF {
-                // 找到第一个不同的字节
-                let diff_mask = vceqq_u8(a_vec, b_vec);
-                let diff_mask_bits = vmovn_u16(vreinterpretq_u16_u8(diff_mask));
-                let diff_mask_64 = vget_lane_u64(vreinterpret_u64_u8(diff_mask_bits), 0);
-
-                // 找到第一个不同的字节位置
-                let first_diff = diff_mask_64.trailing_zeros() as usize;
+                // 在这16个字节中找到第一个不同的字节
+                let first_diff = (0..16)
+                    .find(|j| a[offset + j] != b[offset + j])
+                    .unwrap();
 
                 return a[offset + first_diff].cmp(&b[offset + first_diff]);
             }
@@ -165,8 +162,8 @@ impl SimdComparator {
 
             // 如果所有字节都相等，eq_mask_bits将是全1
             if eq_mask_bits != -1 {
-                // 找到第一个不同的字节位置
-                let first_diff = eq_mask_bits.trailing_zeros() as usize;
+                // 相等的字节对应的位为1，第一个为0的位是第一个不同的字节
+                let first_diff = (!eq_mask_bits).trailing_zeros() as usize;
                 return a[offset + first_diff].cmp(&b[offset + first_diff]);
             }
         }
@@ -199,8 +196,8 @@ impl SimdComparator {
 
             // 如果所有字节都相等，eq_mask_bits将是全1
             if eq_mask_bits != 0xFFFF {
-                // 找到第一个不同的字节位置
-                let first_diff = eq_mask_bits.trailing_zeros() as usize;
+                // 相等的字节对应的位为1，第一个为0的位是第一个不同的字节
+                let first_diff = (!eq_mask_bits).trailing_zeros() as usize;
                 return a[offset + first_diff].cmp(&b[offset + first_diff]);
             }
         }
@@ -265,6 +262,33 @@ impl SimdComparator {
 
     
 
+    /// 使用 `compare` 的二分查找，结果与 `slice::binary_search` 相同：找到时返回
+    /// `Ok(下标)`，否则返回 `Err(插入后仍保持有序的位置)`
+    pub fn binary_search(sorted_keys: &[&[u8]], target: &[u8]) -> Result<usize, usize> {
+        Self::binary_search_by_index(sorted_keys.len(), |i| sorted_keys[i], target)
+    }
+
+    /// 与 `binary_search` 相同，但通过 `key_at` 取得第 `i` 个键，
+    /// 不需要先把键收集到切片中
+    #[inline]
+    pub fn binary_search_by_index<'a>(
+        len: usize,
+        key_at: impl Fn(usize) -> &'a [u8],
+        target: &[u8],
+    ) -> Result<usize, usize> {
+        let mut low = 0;
+        let mut high = len;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match Self::compare(key_at(mid), target) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
     /// 批量key比较优化
     ///
     /// 在批量操作中预取数据以提高缓存命中率
@@ -456,4 +480,52 @@ mod tests {
 
         assert_eq!(results, expected);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_long_keys_match_slice_order() {
+        // 覆盖SIMD块内每个位置的第一个不同字节，以及块之后的剩余字节
+        for len in [17, 32, 33, 64, 70] {
+            for position in 0..len {
+                let a = vec![7u8; len];
+                let mut b = a.clone();
+                b[position] = 8;
+                assert_eq!(SimdComparator::compare(&a, &b), a.cmp(&b), "{} {}", len, position);
+                assert_eq!(SimdComparator::compare(&b, &a), b.cmp(&a), "{} {}", len, position);
+            }
+        }
+    }
+    #[test]
+    fn test_binary_search_matches_slice() {
+        use rand::Rng;
+
+        let mut rng = rand::rng();
+        for _ in 0..200 {
+            // 长度跨越小key路径和SIMD路径，字节取值较少使键共享较长的前缀
+            let mut keys: Vec<Vec<u8>> = (0..rng.random_range(0..300))
+                .map(|_| {
+                    let len = rng.random_range(0..48);
+                    (0..len).map(|_| rng.random_range(0..3)).collect()
+                })
+                .collect();
+            keys.sort();
+            keys.dedup();
+            let sorted: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+
+            let probes: Vec<Vec<u8>> = keys
+                .iter()
+                .cloned()
+                .chain((0..50).map(|_| {
+                    let len = rng.random_range(0..48);
+                    (0..len).map(|_| rng.random_range(0..4)).collect()
+                }))
+                .collect();
+            for probe in &probes {
+                assert_eq!(
+                    SimdComparator::binary_search(&sorted, probe),
+                    sorted.binary_search_by(|key| (*key).cmp(probe.as_slice())),
+                    "{:?}",
+                    probe
+                );
+            }
+        }
+    }
+}
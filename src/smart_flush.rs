@@ -1,7 +1,9 @@
+use std::io;
 use std::time::{Duration, Instant};
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use parking_lot::RwLock;
+use crate::portable_atomic::PortableAtomicU64;
 use crate::{debug_log};
 
 /// 智能flush策略配置
@@ -19,6 +21,45 @@ pub struct SmartFlushConfig {
     pub accumulated_bytes_threshold: usize,
     /// 是否启用自适应flush
     pub enabled: bool,
+    /// 写放大的目标，设置时启用写放大自适应。写放大是一次flush写入磁盘的字节数
+    /// 除以这次flush之前记录的写入字节数，反复小幅修改相同的键时每次flush都重写
+    /// 整个叶子节点，写放大很高。超过目标时flush间隔加倍，直到 `max_interval_ms`；
+    /// 低于目标的一半时逐步缩短回按写入速率计算的间隔。一次flush写入所有flush组的
+    /// 数据，写放大按整个数据库计算。为 `None`（默认）时不调整
+    pub target_write_amplification: Option<f64>,
+}
+
+impl SmartFlushConfig {
+    /// 检查配置是否有效：`min_interval_ms <= base_interval_ms <= max_interval_ms`，
+    /// 且两个阈值都大于0
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if !(self.min_interval_ms <= self.base_interval_ms
+            && self.base_interval_ms <= self.max_interval_ms)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "smart_flush_config 的间隔必须满足 min_interval_ms <= base_interval_ms <= max_interval_ms，实际为 {} / {} / {}",
+                    self.min_interval_ms, self.base_interval_ms, self.max_interval_ms
+                ),
+            ));
+        }
+        if self.write_rate_threshold == 0 || self.accumulated_bytes_threshold == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "smart_flush_config 的 write_rate_threshold 和 accumulated_bytes_threshold 必须大于0",
+            ));
+        }
+        if let Some(target) = self.target_write_amplification
+            && !(target.is_finite() && target > 0.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("smart_flush_config 的 target_write_amplification 必须是正数，实际为 {}", target),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Default for SmartFlushConfig {
@@ -30,6 +71,31 @@ impl Default for SmartFlushConfig {
             write_rate_threshold: 10000, // 10K ops/sec
             accumulated_bytes_threshold: 4 * 1024 * 1024, // 4MB
             enabled: true,
+            target_write_amplification: None,
+        }
+    }
+}
+
+/// 写放大自适应的状态，由 `Db::adaptive_flush_stats` 返回
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveFlushStats {
+    /// 最近一次有写入的flush的写放大，还没有这样的flush时为 `None`
+    pub write_amplification: Option<f64>,
+    /// 当前flush间隔相对于按写入速率计算的间隔的倍数，至少为1
+    pub interval_scale: f64,
+    /// 计入写放大的flush写入磁盘的字节数
+    pub disk_bytes_written: u64,
+    /// 计入写放大的flush之前记录的写入字节数
+    pub logical_bytes_written: u64,
+}
+
+impl Default for AdaptiveFlushStats {
+    fn default() -> Self {
+        Self {
+            write_amplification: None,
+            interval_scale: 1.0,
+            disk_bytes_written: 0,
+            logical_bytes_written: 0,
         }
     }
 }
@@ -39,28 +105,31 @@ impl Default for SmartFlushConfig {
 #[derive(Debug)]
 pub struct WriteLoadStats {
     /// 写入操作计数
-    write_count: AtomicU64,
+    write_count: PortableAtomicU64,
     /// 写入字节数
-    write_bytes: AtomicU64,
+    write_bytes: PortableAtomicU64,
     /// 上次统计时间
     last_stats_time: RwLock<Instant>,
     /// 当前写入速率（ops/sec）
-    current_write_rate: AtomicU64,
+    current_write_rate: PortableAtomicU64,
     /// 当前写入字节速率（bytes/sec）
-    current_byte_rate: AtomicU64,
+    current_byte_rate: PortableAtomicU64,
     /// 累积未flush的字节数
     accumulated_bytes: AtomicUsize,
+    /// 写放大自适应的状态
+    adaptive: RwLock<AdaptiveFlushStats>,
 }
 
 impl WriteLoadStats {
     pub fn new() -> Self {
         Self {
-            write_count: AtomicU64::new(0),
-            write_bytes: AtomicU64::new(0),
+            write_count: PortableAtomicU64::new(0),
+            write_bytes: PortableAtomicU64::new(0),
             last_stats_time: RwLock::new(Instant::now()),
-            current_write_rate: AtomicU64::new(0),
-            current_byte_rate: AtomicU64::new(0),
+            current_write_rate: PortableAtomicU64::new(0),
+            current_byte_rate: PortableAtomicU64::new(0),
             accumulated_bytes: AtomicUsize::new(0),
+            adaptive: RwLock::new(AdaptiveFlushStats::default()),
         }
     }
 
@@ -110,6 +179,11 @@ impl WriteLoadStats {
     pub fn reset_accumulated_bytes(&self) {
         self.accumulated_bytes.store(0, Ordering::Relaxed);
     }
+
+    /// 获取写放大自适应的状态
+    pub fn adaptive_flush_stats(&self) -> AdaptiveFlushStats {
+        *self.adaptive.read()
+    }
 }
 
 /// 智能flush调度器（内部实现细节）
@@ -122,9 +196,14 @@ pub struct SmartFlushScheduler {
 
 impl SmartFlushScheduler {
     pub fn new(config: SmartFlushConfig) -> Self {
+        Self::with_stats(config, Arc::new(WriteLoadStats::new()))
+    }
+
+    /// 使用已有的写入负载统计创建调度器
+    pub fn with_stats(config: SmartFlushConfig, stats: Arc<WriteLoadStats>) -> Self {
         Self {
             config,
-            stats: Arc::new(WriteLoadStats::new()),
+            stats,
             last_flush_time: RwLock::new(Instant::now()),
         }
     }
@@ -176,6 +255,12 @@ impl SmartFlushScheduler {
                       write_rate, interval_ms);
         }
 
+        // 策略3：写放大超过目标时延长间隔
+        if self.config.target_write_amplification.is_some() {
+            let scale = self.stats.adaptive.read().interval_scale;
+            interval_ms = ((interval_ms as f64 * scale) as usize).min(self.config.max_interval_ms);
+        }
+
         // 计算还需要等待的时间
         let remaining_interval = Duration::from_millis(interval_ms as u64);
 
@@ -192,6 +277,36 @@ impl SmartFlushScheduler {
         self.stats.reset_accumulated_bytes();
     }
 
+    /// 一次flush写入磁盘 `disk_bytes` 字节，这次flush之前记录了 `logical_bytes`
+    /// 字节的写入。启用写放大自适应时据此调整flush间隔的倍数
+    pub fn record_flush_amplification(&self, disk_bytes: u64, logical_bytes: u64) {
+        let Some(target) = self.config.target_write_amplification else {
+            return;
+        };
+        // 没有写入的flush（例如只整理碎片）不反映写放大
+        if logical_bytes == 0 {
+            return;
+        }
+
+        let amplification = disk_bytes as f64 / logical_bytes as f64;
+        let max_scale = (self.config.max_interval_ms as f64
+            / self.config.min_interval_ms.max(1) as f64)
+            .max(1.0);
+
+        let mut adaptive = self.stats.adaptive.write();
+        adaptive.write_amplification = Some(amplification);
+        adaptive.disk_bytes_written += disk_bytes;
+        adaptive.logical_bytes_written += logical_bytes;
+        if amplification > target {
+            adaptive.interval_scale = (adaptive.interval_scale * 2.0).min(max_scale);
+        } else if amplification < target / 2.0 {
+            adaptive.interval_scale = (adaptive.interval_scale / 2.0).max(1.0);
+        }
+
+        debug_log!("智能flush: 写放大{:.1}，目标{:.1}，间隔倍数{}",
+                  amplification, target, adaptive.interval_scale);
+    }
+
     /// 更新配置
     pub fn update_config(&mut self, config: SmartFlushConfig) {
         self.config = config;
@@ -231,6 +346,7 @@ mod tests {
             write_rate_threshold: 1000,
             accumulated_bytes_threshold: 1000,
             enabled: true,
+            target_write_amplification: None,
         };
 
         let scheduler = SmartFlushScheduler::new(config);
@@ -248,4 +364,59 @@ mod tests {
         let delay = scheduler.calculate_next_flush_delay();
         assert!(delay > Duration::from_millis(0));
     }
+
+    #[test]
+    fn test_validate_config() {
+        assert!(SmartFlushConfig::default().validate().is_ok());
+
+        let inverted = SmartFlushConfig {
+            min_interval_ms: 300,
+            base_interval_ms: 200,
+            ..SmartFlushConfig::default()
+        };
+        assert_eq!(inverted.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let zero_threshold = SmartFlushConfig {
+            accumulated_bytes_threshold: 0,
+            ..SmartFlushConfig::default()
+        };
+        assert_eq!(zero_threshold.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let negative_target = SmartFlushConfig {
+            target_write_amplification: Some(-1.0),
+            ..SmartFlushConfig::default()
+        };
+        assert_eq!(negative_target.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_write_amplification_scales_interval() {
+        let config = SmartFlushConfig {
+            base_interval_ms: 100,
+            min_interval_ms: 50,
+            max_interval_ms: 1000,
+            target_write_amplification: Some(4.0),
+            ..SmartFlushConfig::default()
+        };
+        let scheduler = SmartFlushScheduler::new(config);
+        let stats = scheduler.get_stats();
+
+        // 超过目标时加倍，不超过 max_interval_ms / min_interval_ms
+        for expected in [2.0, 4.0, 8.0, 16.0, 20.0, 20.0] {
+            scheduler.record_flush_amplification(4096, 10);
+            assert_eq!(stats.adaptive_flush_stats().interval_scale, expected);
+        }
+        assert!(scheduler.calculate_next_flush_delay() <= Duration::from_millis(1000));
+
+        // 没有写入的flush不计入
+        scheduler.record_flush_amplification(4096, 0);
+        assert_eq!(stats.adaptive_flush_stats().logical_bytes_written, 60);
+
+        // 在目标附近保持，低于目标的一半时减半
+        scheduler.record_flush_amplification(300, 100);
+        assert_eq!(stats.adaptive_flush_stats().interval_scale, 20.0);
+        scheduler.record_flush_amplification(100, 100);
+        assert_eq!(stats.adaptive_flush_stats().interval_scale, 10.0);
+        assert_eq!(stats.adaptive_flush_stats().write_amplification, Some(1.0));
+    }
 }
\ No newline at end of file
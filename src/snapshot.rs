@@ -0,0 +1,500 @@
+//! 快照迭代器的写时复制状态
+//!
+//! `Tree::snapshot_iter` 需要在并发写入的情况下产生创建时刻的一致视图。
+//! 每个活跃的快照记录迭代器当前的位置，以及在迭代器到达之前被修改的键范围中
+//! 修改前的内容：
+//!
+//! - 写入方在修改叶子节点之前（持有叶子节点的写锁时）调用
+//!   `SnapshotRegistry::preserve`，把该叶子节点在迭代器位置之后、尚未被保存过的
+//!   键范围的内容复制到每个活跃的快照中。第一次保存的内容就是快照创建时的内容，
+//!   因此之后对同一范围的修改不再复制
+//! - 迭代器读取叶子节点时（持有叶子节点的读锁），已保存的范围使用保存的内容，
+//!   其余范围直接使用叶子节点的当前内容，然后前进到叶子节点的上界并丢弃
+//!   上界之前保存的内容
+//!
+//! 写入方在持有叶子节点锁时检查是否存在活跃的快照，检查的时刻即为写入相对于
+//! 快照的线性化点：在快照注册之前完成检查的写入对快照可见，之后的写入不可见。
+//!
+//! `Tree::clear` 摘下的未缓存叶子节点不被读取，而是通过 `SnapshotState::detach`
+//! 以共享存储位置的对象id交给快照，迭代器到达时才从磁盘读取。
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::blob_store::BlobStore;
+use crate::heap::Heap;
+use crate::leaf::Leaf;
+use crate::{CollectionId, InlineArray, ObjectId};
+
+/// 键范围的上界，`None` 表示无上界
+type HighKey = Option<InlineArray>;
+
+fn is_below(key: &InlineArray, hi: &HighKey) -> bool {
+    match hi {
+        Some(hi) => key < hi,
+        None => true,
+    }
+}
+
+/// 被 `Tree::clear` 摘下而没有读取的叶子节点。`object_id` 通过 `Heap::share`
+/// 共享叶子节点的存储位置，最后一个引用被丢弃时释放
+#[derive(Debug)]
+pub(crate) struct DetachedLeaf {
+    pub(crate) object_id: ObjectId,
+    collection_id: CollectionId,
+    // `Heap` 的句柄不能在线程之间共享
+    heap: Mutex<Heap>,
+}
+
+impl DetachedLeaf {
+    pub(crate) fn new(object_id: ObjectId, collection_id: CollectionId, heap: Heap) -> Self {
+        DetachedLeaf { object_id, collection_id, heap: Mutex::new(heap) }
+    }
+}
+
+impl Drop for DetachedLeaf {
+    fn drop(&mut self) {
+        let heap = self.heap.get_mut().unwrap_or_else(|e| e.into_inner());
+        heap.unshare(self.object_id, self.collection_id);
+    }
+}
+
+/// 一个树的所有活跃快照
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotRegistry {
+    active: AtomicUsize,
+    snapshots: Mutex<Vec<Arc<SnapshotState>>>,
+}
+
+impl SnapshotRegistry {
+    /// 注册一个新的快照，快照从此刻开始不再观察到之后的写入
+    pub(crate) fn register(&self) -> Arc<SnapshotState> {
+        self.register_range(InlineArray::default(), None)
+    }
+
+    /// 注册一个只覆盖 `[lo, hi)` 的快照，写入方不会为这个范围之外的键保存内容
+    pub(crate) fn register_range(&self, lo: InlineArray, hi: HighKey) -> Arc<SnapshotState> {
+        let state = Arc::new(SnapshotState {
+            inner: Mutex::new(SnapshotInner { position: lo, end: hi, ..SnapshotInner::default() }),
+        });
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.push(state.clone());
+        self.active.store(snapshots.len(), Ordering::SeqCst);
+        state
+    }
+
+    /// 注销一个快照，之后的写入不再为它保存内容
+    pub(crate) fn unregister(&self, state: &Arc<SnapshotState>) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.retain(|other| !Arc::ptr_eq(other, state));
+        self.active.store(snapshots.len(), Ordering::SeqCst);
+    }
+
+    /// 在叶子节点被修改之前保存它的内容，调用方必须持有叶子节点的写锁。
+    /// 单独存储的值被读取后保存，因为被覆盖的blob在下一次flush后会被删除
+    pub(crate) fn preserve<const LEAF_FANOUT: usize>(
+        &self,
+        leaf: &Leaf<LEAF_FANOUT>,
+        blobs: &BlobStore,
+    ) -> std::io::Result<()> {
+        if self.active.load(Ordering::SeqCst) == 0 {
+            return Ok(());
+        }
+
+        let snapshots = self.snapshots.lock().unwrap();
+        for snapshot in snapshots.iter() {
+            snapshot.inner.lock().unwrap().preserve(leaf, blobs)?;
+        }
+        Ok(())
+    }
+
+    /// 当前所有活跃的快照。修改多个叶子节点的写入方在持有所有这些叶子节点的写锁时
+    /// 获取一次，并用 `SnapshotState::preserve` 保存每个叶子节点，获取的时刻即为
+    /// 整个修改的线性化点
+    pub(crate) fn active_snapshots(&self) -> Vec<Arc<SnapshotState>> {
+        if self.active.load(Ordering::SeqCst) == 0 {
+            return vec![];
+        }
+
+        self.snapshots.lock().unwrap().clone()
+    }
+}
+
+/// 一个活跃快照的状态
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotState {
+    inner: Mutex<SnapshotInner>,
+}
+
+impl SnapshotState {
+    /// 在叶子节点被修改之前为这个快照保存它的内容，调用方必须持有叶子节点的写锁
+    pub(crate) fn preserve<const LEAF_FANOUT: usize>(
+        &self,
+        leaf: &Leaf<LEAF_FANOUT>,
+        blobs: &BlobStore,
+    ) -> std::io::Result<()> {
+        self.inner.lock().unwrap().preserve(leaf, blobs)
+    }
+
+    /// 在 `[lo, hi)` 被修改之前为这个快照记录它的内容保存在摘下的叶子节点
+    /// `detached` 中，调用方必须持有该叶子节点的写锁
+    pub(crate) fn detach(&self, lo: &InlineArray, hi: &HighKey, detached: &Arc<DetachedLeaf>) {
+        self.inner.lock().unwrap().detach(lo, hi, detached)
+    }
+
+    /// 读取叶子节点中从 `start` 开始的快照内容，并将迭代器位置前进到叶子节点的上界。
+    /// 摘下的叶子节点通过 `load` 读取，读取时不持有快照的锁。
+    /// 调用方必须持有叶子节点的读锁
+    pub(crate) fn read_and_advance<const LEAF_FANOUT: usize>(
+        &self,
+        leaf: &Leaf<LEAF_FANOUT>,
+        start: &InlineArray,
+        blobs: &BlobStore,
+        load: impl Fn(ObjectId) -> std::io::Result<Box<Leaf<LEAF_FANOUT>>>,
+    ) -> std::io::Result<Vec<(InlineArray, InlineArray)>> {
+        // 只有迭代器自己会移除摘下的范围，因此读取期间它们不会改变
+        let detached = self.inner.lock().unwrap().detached_in(start, &leaf.hi);
+
+        let mut loaded: Vec<(ObjectId, Box<Leaf<LEAF_FANOUT>>)> = vec![];
+        let mut detached_entries = vec![];
+        for (lo, hi, detached) in detached {
+            if !loaded.iter().any(|(object_id, _)| *object_id == detached.object_id) {
+                loaded.push((detached.object_id, load(detached.object_id)?));
+            }
+            let (_, detached_leaf) = loaded
+                .iter()
+                .find(|(object_id, _)| *object_id == detached.object_id)
+                .unwrap();
+            for (k, stored) in detached_leaf.iter_stored() {
+                if &k >= start && k >= lo && is_below(&k, &hi) && is_below(&k, &leaf.hi) {
+                    detached_entries.push((k, blobs.load(&stored)?));
+                }
+            }
+        }
+
+        self.inner.lock().unwrap().read_and_advance(leaf, start, blobs, detached_entries)
+    }
+
+    /// 当前保存的键值对数量
+    #[cfg(test)]
+    fn preserved_len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SnapshotInner {
+    /// 迭代器已经读取了此位置之前的所有键
+    position: InlineArray,
+    /// 快照覆盖的范围的上界
+    end: HighKey,
+    /// 迭代器已经读取完所有叶子节点
+    finished: bool,
+    /// 已保存的互不相交的键范围，下界到上界
+    covered: BTreeMap<InlineArray, HighKey>,
+    /// 已保存范围内快照创建时的键值对
+    entries: BTreeMap<InlineArray, InlineArray>,
+    /// 已保存范围中内容保存在摘下的叶子节点中的范围，下界到上界和叶子节点
+    detached: BTreeMap<InlineArray, (HighKey, Arc<DetachedLeaf>)>,
+}
+
+impl SnapshotInner {
+    fn is_covered(&self, key: &InlineArray) -> bool {
+        match self.covered.range::<InlineArray, _>(..=key).next_back() {
+            Some((_lo, hi)) => is_below(key, hi),
+            None => false,
+        }
+    }
+
+    /// 返回 `[lo, hi)` 中尚未被保存的子范围
+    fn uncovered(&self, lo: &InlineArray, hi: &HighKey) -> Vec<(InlineArray, HighKey)> {
+        let mut gaps = vec![];
+        let mut cursor = lo.clone();
+
+        // 从包含 `lo` 的范围（如果有）开始按顺序遍历与 `[lo, hi)` 相交的范围
+        let first = self
+            .covered
+            .range::<InlineArray, _>(..=lo)
+            .next_back()
+            .map(|(covered_lo, _)| covered_lo.clone())
+            .unwrap_or_else(|| lo.clone());
+
+        for (covered_lo, covered_hi) in self.covered.range(first..) {
+            if !is_below(covered_lo, hi) {
+                break;
+            }
+            if !is_below(&cursor, covered_hi) {
+                continue;
+            }
+            if covered_lo > &cursor {
+                gaps.push((cursor.clone(), Some(covered_lo.clone())));
+            }
+            match covered_hi {
+                Some(covered_hi) => cursor = covered_hi.clone(),
+                None => return gaps,
+            }
+        }
+
+        if is_below(&cursor, hi) {
+            gaps.push((cursor, hi.clone()));
+        }
+
+        gaps
+    }
+
+    /// `[lo, hi)` 中迭代器尚未读取过、在快照范围之内并且尚未被保存的子范围
+    fn unpreserved(&self, lo: &InlineArray, hi: &HighKey) -> Vec<(InlineArray, HighKey)> {
+        if self.finished {
+            return vec![];
+        }
+
+        let lo = lo.clone().max(self.position.clone());
+        let hi = match (hi, &self.end) {
+            (Some(hi), Some(end)) => Some(hi.clone().min(end.clone())),
+            (hi, None) => hi.clone(),
+            (None, end) => end.clone(),
+        };
+        if !is_below(&lo, &hi) {
+            return vec![];
+        }
+
+        self.uncovered(&lo, &hi)
+    }
+
+    fn detach(&mut self, lo: &InlineArray, hi: &HighKey, detached: &Arc<DetachedLeaf>) {
+        for (gap_lo, gap_hi) in self.unpreserved(lo, hi) {
+            self.detached.insert(gap_lo.clone(), (gap_hi.clone(), detached.clone()));
+            self.covered.insert(gap_lo, gap_hi);
+        }
+    }
+
+    /// 与 `[start, hi)` 相交的摘下的范围
+    fn detached_in(
+        &self,
+        start: &InlineArray,
+        hi: &HighKey,
+    ) -> Vec<(InlineArray, HighKey, Arc<DetachedLeaf>)> {
+        self.detached
+            .iter()
+            .filter(|(lo, (detached_hi, _))| is_below(lo, hi) && is_below(start, detached_hi))
+            .map(|(lo, (detached_hi, detached))| (lo.clone(), detached_hi.clone(), detached.clone()))
+            .collect()
+    }
+
+    fn preserve<const LEAF_FANOUT: usize>(
+        &mut self,
+        leaf: &Leaf<LEAF_FANOUT>,
+        blobs: &BlobStore,
+    ) -> std::io::Result<()> {
+        let gaps = self.unpreserved(&leaf.lo, &leaf.hi);
+        if gaps.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries = vec![];
+        for (k, stored) in leaf.iter_stored() {
+            let in_gap = gaps
+                .iter()
+                .any(|(gap_lo, gap_hi)| &k >= gap_lo && is_below(&k, gap_hi));
+            if in_gap {
+                entries.push((k, blobs.load(&stored)?));
+            }
+        }
+
+        self.entries.extend(entries);
+        for (gap_lo, gap_hi) in gaps {
+            self.covered.insert(gap_lo, gap_hi);
+        }
+        Ok(())
+    }
+
+    fn read_and_advance<const LEAF_FANOUT: usize>(
+        &mut self,
+        leaf: &Leaf<LEAF_FANOUT>,
+        start: &InlineArray,
+        blobs: &BlobStore,
+        detached_entries: Vec<(InlineArray, InlineArray)>,
+    ) -> std::io::Result<Vec<(InlineArray, InlineArray)>> {
+        let mut ret: BTreeMap<InlineArray, InlineArray> =
+            detached_entries.into_iter().collect();
+        for (k, stored) in leaf.iter_stored() {
+            if &k >= start && !self.is_covered(&k) {
+                ret.insert(k, blobs.load(&stored)?);
+            }
+        }
+
+        let end = match &leaf.hi {
+            Some(hi) => Bound::Excluded(hi.clone()),
+            None => Bound::Unbounded,
+        };
+        for (k, v) in self.entries.range((Bound::Included(start.clone()), end)) {
+            ret.insert(k.clone(), v.clone());
+        }
+
+        self.advance(&leaf.hi);
+
+        Ok(ret.into_iter().collect())
+    }
+
+    /// 前进到 `hi` 并丢弃它之前保存的内容
+    fn advance(&mut self, hi: &HighKey) {
+        let Some(hi) = hi else {
+            self.finished = true;
+            self.covered.clear();
+            self.entries.clear();
+            self.detached.clear();
+            return;
+        };
+
+        self.position = hi.clone();
+        self.entries = self.entries.split_off(hi);
+
+        let mut remaining_detached = self.detached.split_off(hi);
+        if let Some((_lo, (detached_hi, detached))) = self.detached.pop_last()
+            && is_below(hi, &detached_hi)
+        {
+            remaining_detached.insert(hi.clone(), (detached_hi, detached));
+        }
+        self.detached = remaining_detached;
+
+        let mut remaining = self.covered.split_off(hi);
+        if let Some((_lo, covered_hi)) = self.covered.pop_last()
+            && is_below(hi, &covered_hi)
+        {
+            // 跨越 `hi` 的范围只保留 `hi` 之后的部分
+            remaining.insert(hi.clone(), covered_hi);
+        }
+        self.covered = remaining;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FlushEpoch;
+
+    fn leaf(
+        lo: &[u8],
+        hi: Option<&[u8]>,
+        entries: &[(&[u8], &[u8])],
+    ) -> Leaf<1024> {
+        let blobs = BlobStore::disabled();
+        let mut leaf = Leaf::empty();
+        leaf.lo = InlineArray::from(lo);
+        leaf.hi = hi.map(InlineArray::from);
+        for (k, v) in entries {
+            leaf.insert(InlineArray::from(*k), InlineArray::from(*v), FlushEpoch::MIN, &blobs).unwrap();
+        }
+        leaf
+    }
+
+    fn no_detached(_: ObjectId) -> std::io::Result<Box<Leaf<1024>>> {
+        unreachable!("没有摘下的叶子节点")
+    }
+
+    fn keys(entries: &[(InlineArray, InlineArray)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        entries.iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_first_preservation_wins() {
+        let blobs = BlobStore::disabled();
+        let registry = SnapshotRegistry::default();
+        let snapshot = registry.register();
+
+        let mut l = leaf(b"", None, &[(b"a", b"1"), (b"b", b"1")]);
+        registry.preserve(&l, &blobs).unwrap();
+
+        l.insert(InlineArray::from(&b"a"[..]), InlineArray::from(&b"2"[..]), FlushEpoch::MIN, &blobs).unwrap();
+        l.insert(InlineArray::from(&b"c"[..]), InlineArray::from(&b"2"[..]), FlushEpoch::MIN, &blobs).unwrap();
+        registry.preserve(&l, &blobs).unwrap();
+        assert_eq!(snapshot.preserved_len(), 2);
+
+        let read = snapshot.read_and_advance(&l, &InlineArray::default(), &blobs, no_detached).unwrap();
+        assert_eq!(
+            keys(&read),
+            vec![(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"1".to_vec())]
+        );
+
+        // 迭代结束后写入不再保存任何内容
+        registry.preserve(&l, &blobs).unwrap();
+        assert_eq!(snapshot.preserved_len(), 0);
+
+        registry.unregister(&snapshot);
+        assert_eq!(registry.active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_split_leaves_and_cursor() {
+        let blobs = BlobStore::disabled();
+        let registry = SnapshotRegistry::default();
+        let snapshot = registry.register();
+
+        // 修改前整个范围被保存
+        let mut whole = leaf(b"", None, &[(b"a", b"1"), (b"m", b"1"), (b"x", b"1")]);
+        registry.preserve(&whole, &blobs).unwrap();
+        whole.remove(&InlineArray::from(&b"m"[..]), &blobs).unwrap();
+
+        // 模拟分裂：左半部分 [, m)，右半部分 [m, )
+        let mut left = leaf(b"", Some(b"m"), &[(b"a", b"1")]);
+        let right = leaf(b"m", None, &[(b"x", b"1"), (b"y", b"2")]);
+
+        let read = snapshot.read_and_advance(&left, &InlineArray::default(), &blobs, no_detached).unwrap();
+        assert_eq!(keys(&read), vec![(b"a".to_vec(), b"1".to_vec())]);
+
+        // 迭代器已经越过的叶子节点不再保存
+        left.insert(InlineArray::from(&b"b"[..]), InlineArray::from(&b"2"[..]), FlushEpoch::MIN, &blobs).unwrap();
+        registry.preserve(&left, &blobs).unwrap();
+        assert_eq!(snapshot.preserved_len(), 2);
+
+        let read = snapshot.read_and_advance(&right, &InlineArray::from(&b"m"[..]), &blobs, no_detached).unwrap();
+        assert_eq!(
+            keys(&read),
+            vec![(b"m".to_vec(), b"1".to_vec()), (b"x".to_vec(), b"1".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_range_snapshot_preserves_only_its_range() {
+        let blobs = BlobStore::disabled();
+        let registry = SnapshotRegistry::default();
+        let snapshot = registry.register_range(
+            InlineArray::from(&b"c"[..]),
+            Some(InlineArray::from(&b"f"[..])),
+        );
+
+        // 只保存 [c, f) 中的键
+        let l = leaf(b"", None, &[(b"a", b"1"), (b"d", b"1"), (b"e", b"1"), (b"x", b"1")]);
+        registry.preserve(&l, &blobs).unwrap();
+        assert_eq!(snapshot.preserved_len(), 2);
+
+        // 完全在范围之外的叶子节点不被保存
+        registry.preserve(&leaf(b"f", None, &[(b"g", b"1")]), &blobs).unwrap();
+        registry.preserve(&leaf(b"", Some(b"c"), &[(b"b", b"1")]), &blobs).unwrap();
+        assert_eq!(snapshot.preserved_len(), 2);
+    }
+
+    #[test]
+    fn test_uncovered_gaps() {
+        let mut inner = SnapshotInner::default();
+        inner.covered.insert(InlineArray::from(&b"c"[..]), Some(InlineArray::from(&b"e"[..])));
+        inner.covered.insert(InlineArray::from(&b"g"[..]), Some(InlineArray::from(&b"h"[..])));
+
+        let gaps = inner.uncovered(&InlineArray::from(&b"d"[..]), &None);
+        assert_eq!(
+            gaps,
+            vec![
+                (InlineArray::from(&b"e"[..]), Some(InlineArray::from(&b"g"[..]))),
+                (InlineArray::from(&b"h"[..]), None),
+            ]
+        );
+
+        let gaps = inner.uncovered(
+            &InlineArray::from(&b"c"[..]),
+            &Some(InlineArray::from(&b"e"[..])),
+        );
+        assert!(gaps.is_empty());
+    }
+}
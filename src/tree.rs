@@ -7,8 +7,10 @@ use std::ops;
 use std::ops::Bound;
 use std::ops::RangeBounds;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use std::time::Instant;
+use std::sync::atomic::{
+    AtomicBool, AtomicU8, AtomicUsize, Ordering,
+};
+use std::time::{Duration, Instant};
 
 use concurrent_map::Minimum;
 use fault_injection::annotate;
@@ -18,9 +20,17 @@ use parking_lot::{
     lock_api::{ArcRwLockReadGuard, ArcRwLockWriteGuard},
 };
 
+use crate::portable_atomic::PortableAtomicU64;
 use crate::*;
 
 // 使用性能优化的日志宏
+use crate::batch_spill::BatchSpill;
+use crate::contention::{ContentionTracker, Retries};
+use crate::flush_group::FlushGroup;
+use crate::op_journal::OpKind;
+use crate::quota::QuotaState;
+use crate::read_ahead::ReadAhead;
+use crate::snapshot::{DetachedLeaf, SnapshotRegistry, SnapshotState};
 use crate::{debug_log, trace_log, warn_log, error_log, info_log};
 
 
@@ -42,9 +52,383 @@ pub struct Tree<const LEAF_FANOUT: usize = 1024> {
     collection_id: CollectionId,
     cache: ObjectCache<LEAF_FANOUT>,
     pub(crate) index: Index<LEAF_FANOUT>,
+    leaf_policy: Arc<LeafPolicy>,
+    bloom_counters: Arc<BloomReadCounters>,
+    snapshots: Arc<SnapshotRegistry>,
+    // persisted in the collection name mapping, shared by every handle
+    write_once: Arc<AtomicBool>,
+    // the flush group set by `Db::open_tree_in_group`, shared by every handle
+    flush_group: Arc<RwLock<Option<Arc<FlushGroup>>>>,
+    // set by `Tree::set_quota`, shared by every handle
+    quota: Arc<QuotaState>,
+    // bumped by `Tree::clear`, shared by every handle
+    clears: Arc<PortableAtomicU64>,
+    // set by `Db::drop_tree` before the leaves are freed, shared by every
+    // handle and iterator
+    dropped: Arc<AtomicBool>,
+    // compare and swap contention, shared by every handle
+    contention: Arc<ContentionTracker>,
+    // set on handles returned by `Tree::force`
+    force: bool,
     _shutdown_dropper: Arc<ShutdownDropper<LEAF_FANOUT>>,
 }
 
+/// Structural statistics for a single [`Tree`], counted since the
+/// database was opened.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TreeStats {
+    /// The number of times a leaf of this tree was split.
+    pub leaf_splits: u64,
+    /// The number of times a leaf of this tree was merged into its
+    /// left sibling.
+    pub leaf_merges: u64,
+    /// The number of compare and swaps inside `update_and_fetch`,
+    /// `fetch_and_update`, `pop_first`, `pop_last` and their variants, and
+    /// guarded batches of an `IndexedTree`, that failed because another
+    /// thread changed the key after it was read, forcing a retry.
+    pub cas_conflicts: u64,
+    /// The number of direct [`Tree::compare_and_swap`] calls that failed
+    /// because the current value did not match the expected one.
+    pub cas_mismatches: u64,
+}
+
+/// The physical layout of one leaf, returned by [`Tree::leaf_layout`].
+/// Not a stable API.
+#[doc(hidden)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafInfo {
+    /// The key the leaf is indexed under.
+    pub low_key: InlineArray,
+    /// The number of entries in the leaf.
+    pub entry_count: usize,
+    /// The size of the leaf's stored copy, after compression, or `None`
+    /// if the leaf was never flushed.
+    pub serialized_bytes: Option<usize>,
+    /// The bytes of the full keys and the values stored in the leaf.
+    /// Values stored as blobs only count their reference.
+    pub uncompressed_bytes: usize,
+    pub object_id: u64,
+    /// The slab file holding the stored copy, or `None` if the leaf was
+    /// never flushed.
+    pub slab_file: Option<u8>,
+    /// Whether the leaf was resident in the cache. The entries of a
+    /// resident leaf are counted from memory, including writes that were
+    /// not flushed yet, while `serialized_bytes` describes the last flush.
+    pub in_cache: bool,
+}
+
+/// Read-path statistics for the bloom filter consulted by [`Tree::get`],
+/// counted since the database was opened.
+///
+/// The filter is shared by every tree of a database and is not rebuilt
+/// when a database is recovered (only [`Config::bloom_auto_resize`]
+/// rebuilds it from the live keys), so `get` never trusts a negative
+/// answer from it. Instead, the leaf is always read and the filter's answer is
+/// compared with the result, which shows how many reads trusting the filter
+/// would save and how often it would be wrong.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BloomReadStats {
+    /// The number of `get` calls on this tree.
+    pub lookups: u64,
+    /// Lookups of absent keys that the filter reported as absent. Each one
+    /// is a leaf read that short-circuiting on the filter would avoid.
+    pub true_negatives: u64,
+    /// Lookups of present keys that the filter reported as possibly present.
+    pub confirmed_hits: u64,
+    /// Lookups of absent keys that the filter reported as possibly present.
+    pub false_positives: u64,
+    /// Lookups of present keys that the filter reported as absent, which
+    /// happens for keys recovered from disk or written without going
+    /// through `insert`. Short-circuiting would have returned a wrong
+    /// result for each of these.
+    pub false_negatives: u64,
+}
+
+impl BloomReadStats {
+    /// The observed false-positive rate: the fraction of lookups of absent
+    /// keys that the filter failed to rule out.
+    pub fn false_positive_rate(&self) -> f64 {
+        let absent = self.false_positives + self.true_negatives;
+        if absent == 0 {
+            0.0
+        } else {
+            self.false_positives as f64 / absent as f64
+        }
+    }
+}
+
+/// Controls how a read interacts with the leaf cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Leaves that are read are loaded into the cache and marked as
+    /// accessed, which may promote them over other cached leaves.
+    #[default]
+    Normal,
+    /// Leaves that are already cached are read without being marked as
+    /// accessed, and leaves that are not cached are read directly from disk
+    /// without being inserted into the cache.
+    BypassCache,
+    /// Leaves that are already cached are read without being marked as
+    /// accessed, and leaves that are not cached are loaded into the cache's
+    /// entry segment, where they can be evicted without displacing leaves
+    /// that have been accessed repeatedly. Until the cache has filled up for
+    /// the first time, new leaves are admitted directly into the main
+    /// segment, so use `BypassCache` for scans right after opening.
+    ReadDontPromote,
+}
+
+/// Options for [`Tree::iter_with`], [`Tree::range_with`] and
+/// [`Tree::scan_prefix_with`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IterOptions {
+    /// How the leaves visited by the iterator interact with the cache.
+    pub cache_policy: CachePolicy,
+    /// When a forward iterator moves into a new leaf, the number of
+    /// following leaves, up to the end of the range, to read from disk
+    /// into the cache in the background so that they are ready when the
+    /// iterator gets to them. Outstanding reads are cancelled when the
+    /// iterator is dropped. Ignored for `CachePolicy::BypassCache`, and
+    /// by `next_back`. `0` disables read-ahead.
+    ///
+    /// `Tree::iter`, `Tree::range` and `Tree::scan_prefix` use
+    /// `Config::read_ahead_leaves`.
+    pub read_ahead_leaves: usize,
+}
+
+/// Options for [`Tree::get_with`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GetOptions {
+    /// How the leaf holding the key interacts with the cache.
+    pub cache_policy: CachePolicy,
+}
+
+/// When and how large a value was last written, returned by
+/// [`Tree::get_with_metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// The flush epoch in which the value was last written, or a later
+    /// one. Exact for values written since their leaf was last loaded into
+    /// the cache; older values report the epoch in which the leaf was
+    /// loaded. A value that was not rewritten never reports a later epoch
+    /// while its leaf stays cached, so a value whose epoch is unchanged
+    /// has not been modified. Like [`Db::current_epoch`], epochs are only
+    /// meaningful while the database stays open.
+    pub write_epoch: FlushEpoch,
+    /// The bytes the value occupies when stored: its size in the leaf,
+    /// plus the length of its blob file when it is stored as a blob. Keys
+    /// and leaf compression are not counted.
+    pub stored_bytes: usize,
+}
+
+/// How long [`Tree::compare_and_swap_retry`] waits after a conflicting
+/// attempt before recomputing the value.
+///
+/// The wait doubles after every conflict, starting at `initial` and capped
+/// at `max`, and is randomized to between half and all of that so that
+/// contending threads spread out. Short waits yield the thread instead of
+/// sleeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The wait after the first conflict.
+    pub initial: Duration,
+    /// The longest wait between two attempts.
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial: Duration::from_micros(1),
+            max: Duration::from_millis(1),
+        }
+    }
+}
+
+impl Backoff {
+    /// Waits below this are spent yielding, as sleeping would overshoot them.
+    const YIELD_THRESHOLD: Duration = Duration::from_micros(50);
+
+    pub(crate) fn wait(&self, conflicts: u32) {
+        let ceiling = self
+            .initial
+            .saturating_mul(1 << conflicts.min(31))
+            .min(self.max);
+
+        let ceiling_ns = u64::try_from(ceiling.as_nanos()).unwrap_or(u64::MAX);
+        let rand = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos()
+            as u64;
+        let wait = Duration::from_nanos(
+            ceiling_ns / 2 + rand % (ceiling_ns / 2 + 1),
+        );
+
+        if wait < Self::YIELD_THRESHOLD {
+            let before = Instant::now();
+            while before.elapsed() < wait {
+                std::thread::yield_now();
+            }
+        } else {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// What [`Db::copy_tree_range`](crate::Db::copy_tree_range) does with a
+/// key that already exists in the destination tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the destination's value.
+    Skip,
+    /// Replace the destination's value with the source's.
+    Overwrite,
+    /// Stop the copy before the key and return a
+    /// [`KeyAlreadyExists`] error for it.
+    Error,
+}
+
+/// The result of [`Db::copy_tree_range`](crate::Db::copy_tree_range).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Entries written into the destination, including `overwritten`.
+    pub copied: usize,
+    /// Entries that replaced an existing destination value.
+    pub overwritten: usize,
+    /// Entries left out because the destination already had the key.
+    pub skipped: usize,
+    /// The last source key that was copied or skipped. A copy that was
+    /// interrupted resumes after it.
+    pub last_copied_key: Option<InlineArray>,
+}
+
+/// A leaf returned by `Tree::leaf_for_key_with_policy`: either a locked,
+/// cached leaf or a private copy read from disk that bypassed the cache.
+/// The private copy keeps the node's read lock, so the blobs that its
+/// values reference cannot be released while they are read.
+enum ReadLeaf<'a, const LEAF_FANOUT: usize> {
+    Cached(LeafReadGuard<'a, LEAF_FANOUT>),
+    Uncached(
+        Box<Leaf<LEAF_FANOUT>>,
+        ArcRwLockReadGuard<RawRwLock, CacheBox<LEAF_FANOUT>>,
+    ),
+}
+
+impl<const LEAF_FANOUT: usize> ReadLeaf<'_, LEAF_FANOUT> {
+    fn leaf(&self) -> &Leaf<LEAF_FANOUT> {
+        match self {
+            ReadLeaf::Cached(guard) => guard.leaf_read.leaf.as_ref().unwrap(),
+            ReadLeaf::Uncached(leaf, _read) => leaf,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BloomReadCounters {
+    lookups: PortableAtomicU64,
+    true_negatives: PortableAtomicU64,
+    confirmed_hits: PortableAtomicU64,
+    false_positives: PortableAtomicU64,
+    false_negatives: PortableAtomicU64,
+}
+
+impl BloomReadCounters {
+    fn record(&self, filter_contains: bool, present: bool) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let counter = match (filter_contains, present) {
+            (false, false) => &self.true_negatives,
+            (true, true) => &self.confirmed_hits,
+            (true, false) => &self.false_positives,
+            (false, true) => &self.false_negatives,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Sentinel stored in `LeafPolicy::merge_threshold` meaning that only
+/// empty leaves are merged.
+const MERGE_ONLY_EMPTY: usize = usize::MAX;
+
+/// Leaf split/merge tuning for a tree, shared by every handle to it so that
+/// `Db::open_tree_with_options` can adjust an already-open tree.
+#[derive(Debug)]
+struct LeafPolicy {
+    split_threshold: AtomicUsize,
+    merge_threshold: AtomicUsize,
+    split_bias: AtomicU8,
+    truncate_split_keys: AtomicBool,
+    splits: PortableAtomicU64,
+    merges: PortableAtomicU64,
+}
+
+impl LeafPolicy {
+    fn new(
+        split_threshold: usize,
+        merge_threshold: Option<usize>,
+        split_bias: SplitBias,
+        truncate_split_keys: bool,
+    ) -> LeafPolicy {
+        LeafPolicy {
+            split_threshold: AtomicUsize::new(split_threshold),
+            merge_threshold: AtomicUsize::new(
+                merge_threshold.unwrap_or(MERGE_ONLY_EMPTY),
+            ),
+            split_bias: AtomicU8::new(split_bias_to_u8(split_bias)),
+            truncate_split_keys: AtomicBool::new(truncate_split_keys),
+            splits: PortableAtomicU64::new(0),
+            merges: PortableAtomicU64::new(0),
+        }
+    }
+
+    fn split_threshold(&self) -> usize {
+        self.split_threshold.load(Ordering::Relaxed)
+    }
+
+    fn merge_threshold(&self) -> Option<usize> {
+        match self.merge_threshold.load(Ordering::Relaxed) {
+            MERGE_ONLY_EMPTY => None,
+            threshold => Some(threshold),
+        }
+    }
+
+    fn split_bias(&self) -> SplitBias {
+        match self.split_bias.load(Ordering::Relaxed) {
+            1 => SplitBias::Middle,
+            2 => SplitBias::Last,
+            _ => SplitBias::Auto,
+        }
+    }
+
+    fn truncate_split_keys(&self) -> bool {
+        self.truncate_split_keys.load(Ordering::Relaxed)
+    }
+}
+
+/// Returned by operations on a handle to a tree that was removed with
+/// `Db::drop_tree`, whose index no longer contains any leaves.
+fn write_once_removal_error(operation: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "{} is not allowed on a write-once tree, use Tree::force for administrative changes",
+            operation
+        ),
+    )
+}
+
+fn dropped_tree_error(collection_id: CollectionId) -> io::Error {
+    TreeDropped { collection_id: collection_id.0 }.into()
+}
+
+fn cleared_during_iteration_error() -> io::Error {
+    io::Error::other("the tree was cleared during iteration")
+}
+
+const fn split_bias_to_u8(split_bias: SplitBias) -> u8 {
+    match split_bias {
+        SplitBias::Auto => 0,
+        SplitBias::Middle => 1,
+        SplitBias::Last => 2,
+    }
+}
+
 impl<const LEAF_FANOUT: usize> Drop for Tree<LEAF_FANOUT> {
     fn drop(&mut self) {
         if self.cache.config.flush_every_ms.is_none() {
@@ -173,6 +557,65 @@ impl<const LEAF_FANOUT: usize> Drop for LeafWriteGuard<'_, LEAF_FANOUT> {
     }
 }
 
+#[cfg(debug_assertions)]
+thread_local! {
+    // trees with a leaf read-locked by a `get_in_place` closure running
+    // on this thread, identified by `Tree::closure_pin_id`
+    static CLOSURE_PINS: std::cell::RefCell<Vec<usize>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Marks a tree as having a leaf read-locked by a user closure on the
+/// current thread until dropped, so that calls back into the tree panic
+/// instead of deadlocking. Only tracked in debug builds.
+struct ClosurePin {
+    #[cfg(debug_assertions)]
+    tree: usize,
+}
+
+impl ClosurePin {
+    fn enter<const LEAF_FANOUT: usize>(tree: &Tree<LEAF_FANOUT>) -> ClosurePin {
+        #[cfg(debug_assertions)]
+        {
+            let tree = tree.closure_pin_id();
+            CLOSURE_PINS.with(|pins| pins.borrow_mut().push(tree));
+            ClosurePin { tree }
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            let _ = tree;
+            ClosurePin {}
+        }
+    }
+
+    fn check<const LEAF_FANOUT: usize>(tree: &Tree<LEAF_FANOUT>) {
+        #[cfg(debug_assertions)]
+        {
+            let tree = tree.closure_pin_id();
+            if CLOSURE_PINS.with(|pins| pins.borrow().contains(&tree)) {
+                panic!(
+                    "a `Tree::get_in_place` closure called back into the \
+                    tree whose leaf it holds read-locked, which can deadlock"
+                );
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = tree;
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for ClosurePin {
+    fn drop(&mut self) {
+        CLOSURE_PINS.with(|pins| {
+            let mut pins = pins.borrow_mut();
+            if let Some(position) = pins.iter().rposition(|tree| *tree == self.tree) {
+                pins.remove(position);
+            }
+        });
+    }
+}
+
 impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     pub(crate) fn new(
         collection_id: CollectionId,
@@ -180,133 +623,723 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         index: Index<LEAF_FANOUT>,
         _shutdown_dropper: Arc<ShutdownDropper<LEAF_FANOUT>>,
     ) -> Tree<LEAF_FANOUT> {
-        Tree { collection_id, cache, index, _shutdown_dropper }
+        let leaf_policy = Arc::new(LeafPolicy::new(
+            cache.config.leaf_split_threshold.unwrap_or(LEAF_FANOUT),
+            cache.config.leaf_merge_threshold,
+            cache.config.split_bias,
+            cache.config.truncate_split_keys,
+        ));
+        Tree {
+            collection_id,
+            cache,
+            index,
+            leaf_policy,
+            bloom_counters: Arc::default(),
+            snapshots: Arc::default(),
+            write_once: Arc::default(),
+            flush_group: Arc::default(),
+            quota: Arc::default(),
+            clears: Arc::default(),
+            dropped: Arc::default(),
+            contention: Arc::default(),
+            force: false,
+            _shutdown_dropper,
+        }
     }
 
-    // This is only pub for an extra assertion during testing.
-    #[doc(hidden)]
-    pub fn check_error(&self) -> io::Result<()> {
-        self.cache.check_error()
+    pub(crate) fn set_flush_group(&self, group: Arc<FlushGroup>) {
+        *self.flush_group.write() = Some(group);
     }
 
-    fn set_error(&self, error: &io::Error) {
-        self.cache.set_error(error)
+    pub(crate) fn set_write_once(&self) {
+        self.write_once.store(true, Ordering::Release);
     }
 
-    pub fn storage_stats(&self) -> Stats {
-        Stats { cache: self.cache.stats() }
+    /// Returns `true` if this tree was created with
+    /// [`TreeOptions::write_once`], so that existing keys can be neither
+    /// overwritten nor removed.
+    pub fn is_write_once(&self) -> bool {
+        self.write_once.load(Ordering::Acquire)
     }
 
-    /// Synchronously flushes all dirty IO buffers and calls
-    /// fsync. If this succeeds, it is guaranteed that all
-    /// previous writes will be recovered if the system
-    /// crashes. Returns the number of bytes flushed during
-    /// this call.
-    ///
-    /// Flushing can take quite a lot of time, and you should
-    /// measure the performance impact of using it on
-    /// realistic sustained workloads running on realistic
-    /// hardware.
-    ///
-    /// This is called automatically on drop of the last open Db
-    /// instance.
-    pub fn flush(&self) -> io::Result<FlushStats> {
-        self.cache.flush()
+    /// Returns a handle to this tree that is not bound by
+    /// [`TreeOptions::write_once`]: it can overwrite and remove keys and
+    /// clear the tree. This is reserved for administrative tooling, and
+    /// every other handle to the tree keeps enforcing the write-once rules.
+    pub fn force(&self) -> Tree<LEAF_FANOUT> {
+        let mut tree = self.clone();
+        tree.force = true;
+        tree
     }
 
-    pub(crate) fn page_in(
-        &self,
-        key: &[u8],
-        flush_epoch: FlushEpoch,
-    ) -> io::Result<(
-        InlineArray,
-        ArcRwLockWriteGuard<RawRwLock, CacheBox<LEAF_FANOUT>>,
-        Object<LEAF_FANOUT>,
-    )> {
-        let before_read_io = Instant::now();
-
-                let mut loops: u64 = 0;
-        let mut last_continue = "none";
-        let mut warned = false;
-
-        loop {
-            loops += 1;
+    fn enforces_write_once(&self) -> bool {
+        !self.force && self.is_write_once()
+    }
 
-            if loops > 10_000_000 && !warned {
-                warn_log!(
-                    "page_in spinning for a long time due to continue point {}",
-                    last_continue
-                );
-                warned = true;
+    /// Fails removals from a write-once tree.
+    fn check_write_once_removal(&self, operation: &str) -> io::Result<()> {
+        if self.enforces_write_once() {
+            Err(write_once_removal_error(operation))
+        } else {
+            Ok(())
+        }
+    }
 
-                #[cfg(feature = "for-internal-testing-only")]
-                assert!(
-                    loops <= 10_000_000,
-                    "stuck in loop at continue point: {}, search key: {:?}",
-                    last_continue,
-                    key,
-                );
-            }
+    /// Applies per-tree overrides of the leaf split/merge settings. Settings
+    /// that are not overridden fall back to the `Config` the database was
+    /// opened with.
+    pub(crate) fn apply_options(&self, options: &TreeOptions) -> io::Result<()> {
+        let split_threshold = options
+            .leaf_split_threshold
+            .or(self.cache.config.leaf_split_threshold);
+        let merge_threshold = options
+            .leaf_merge_threshold
+            .or(self.cache.config.leaf_merge_threshold);
+        let split_bias =
+            options.split_bias.unwrap_or(self.cache.config.split_bias);
+        let truncate_split_keys = options
+            .truncate_split_keys
+            .unwrap_or(self.cache.config.truncate_split_keys);
+
+        crate::config::validate_leaf_thresholds::<LEAF_FANOUT>(
+            split_threshold,
+            merge_threshold,
+        )?;
+
+        let policy = &self.leaf_policy;
+        policy
+            .split_threshold
+            .store(split_threshold.unwrap_or(LEAF_FANOUT), Ordering::Relaxed);
+        policy.merge_threshold.store(
+            merge_threshold.unwrap_or(MERGE_ONLY_EMPTY),
+            Ordering::Relaxed,
+        );
+        policy
+            .split_bias
+            .store(split_bias_to_u8(split_bias), Ordering::Relaxed);
+        policy
+            .truncate_split_keys
+            .store(truncate_split_keys, Ordering::Relaxed);
+
+        self.cache.set_value_dedup(
+            self.collection_id,
+            options.dedup_values.then(|| {
+                options
+                    .dedup_min_value_size
+                    .unwrap_or(crate::config::DEFAULT_DEDUP_MIN_VALUE_SIZE)
+            }),
+        );
 
-            let _heap_pin = self.cache.heap_object_id_pin();
+        Ok(())
+    }
 
-            let (low_key, node) = self.index.get_lte(key).unwrap();
-            if node.collection_id != self.collection_id {
-                trace_log!("retry due to mismatched collection id in page_in");
+    /// Limits how much of the database's write rate, flush bandwidth and
+    /// cache this tree may use, replacing any previous quota. See
+    /// [`TreeQuota`] for the individual limits, each of which is disabled
+    /// when `None`. The quota applies to every handle of this tree, takes
+    /// effect for the next write, and is not persisted.
+    ///
+    /// Writes over `max_write_ops_per_sec` are delayed or rejected with
+    /// [`QuotaExceeded`] according to `TreeQuota::policy`. When more than
+    /// `max_dirty_bytes` have been written since the last flush, the next
+    /// writer flushes the database before writing, and only this tree's
+    /// writers wait for it. The flush writes every tree's dirty data,
+    /// because all trees share one flush epoch. When this tree's leaves
+    /// take up more than `max_cache_bytes` of the cache, its own leaves are
+    /// paged out, clean ones first. This is checked every 64 writes.
+    ///
+    /// Returns `InvalidInput` if any limit is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::{QuotaExceeded, QuotaPolicy, TreeQuota};
+    ///
+    /// # let db: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+    /// let tenant = db.open_tree("tenant")?;
+    /// tenant.set_quota(TreeQuota {
+    ///     max_write_ops_per_sec: Some(2),
+    ///     policy: QuotaPolicy::Reject,
+    ///     ..TreeQuota::default()
+    /// })?;
+    ///
+    /// tenant.insert(b"a", b"1")?;
+    /// tenant.insert(b"b", b"2")?;
+    /// let error = tenant.insert(b"c", b"3").unwrap_err();
+    /// assert!(QuotaExceeded::from_io_error(&error).is_some());
+    /// assert_eq!(tenant.quota_usage().writes_rejected, 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn set_quota(&self, quota: TreeQuota) -> io::Result<()> {
+        quota.validate()?;
+        self.quota.set(quota);
+        Ok(())
+    }
 
-                hint::spin_loop();
+    /// Returns this tree's quota along with how much of it is in use and
+    /// how often it has been enforced since it was first set. Computing
+    /// the resident bytes walks every leaf of the tree.
+    pub fn quota_usage(&self) -> QuotaUsage {
+        self.quota.usage(self.cache.max_flushed_epoch(), self.resident_bytes())
+    }
 
-                last_continue = concat!(file!(), ':', line!());
+    /// The in-memory size of this tree's leaves that are in the cache.
+    fn resident_bytes(&self) -> usize {
+        self.index
+            .iter()
+            .filter_map(|(_, node)| {
+                node.inner.read().leaf.as_ref().map(|leaf| leaf.in_memory_size)
+            })
+            .sum()
+    }
 
-                continue;
+    /// Applies the write rate and dirty bytes limits of this tree's quota
+    /// before `ops` writes. Must be called before any leaf is locked.
+    fn check_quota(&self, ops: usize) -> io::Result<()> {
+        if !self.quota.is_enabled() {
+            return Ok(());
+        }
+        self.quota.admit(ops)?;
+
+        if self.quota.over_dirty_limit(self.cache.max_flushed_epoch()) {
+            let _flushing = self.quota.lock_dirty_flush();
+            // another writer of this tree may have flushed while we waited
+            if self.quota.over_dirty_limit(self.cache.max_flushed_epoch()) {
+                self.cache.flush()?;
+                self.quota.record_dirty_flush();
             }
+        }
+        Ok(())
+    }
 
-                        let mut write = node.inner.write_arc();
-            if write.leaf.is_none() {
-                self.cache
-                    .read_stats
-                    .cache_misses
-                    .fetch_add(1, Ordering::Relaxed);
+    /// Accounts `bytes` written against this tree's quota, and pages out
+    /// this tree's leaves if they take up more than `max_cache_bytes`.
+    /// Must be called after every leaf lock has been released.
+    fn record_quota_write(&self, bytes: usize) -> io::Result<()> {
+        if !self.quota.is_enabled() {
+            return Ok(());
+        }
+        let check_cache = self.quota.record_write(
+            bytes,
+            self.cache.current_flush_epoch().get(),
+            self.cache.max_flushed_epoch(),
+        );
+        if check_cache && let Some(max_cache_bytes) = self.quota.quota().max_cache_bytes {
+            self.enforce_cache_quota(max_cache_bytes)?;
+        }
+        Ok(())
+    }
 
-                let leaf_bytes =
-                    if let Some(read_res) = self.cache.read(node.object_id) {
-                        match read_res {
-                            Ok(buf) => buf,
-                            Err(e) => return Err(annotate!(e)),
-                        }
-                    } else {
-                        hint::spin_loop();
+    fn enforce_cache_quota(&self, max_cache_bytes: usize) -> io::Result<()> {
+        let mut resident = vec![];
+        for (_, node) in self.index.iter() {
+            let read = node.inner.read();
+            if let Some(leaf) = read.leaf.as_ref() {
+                let dirty = leaf.dirty_flush_epoch.is_some() || leaf.max_unflushed_epoch.is_some();
+                resident.push((dirty, leaf.in_memory_size, node.object_id));
+            }
+        }
 
-                        last_continue = concat!(file!(), ':', line!());
+        let mut excess = resident
+            .iter()
+            .map(|(_, size, _)| size)
+            .sum::<usize>()
+            .saturating_sub(max_cache_bytes);
+        if excess == 0 {
+            return Ok(());
+        }
 
-                        continue;
-                    };
+        // clean leaves can be paged out right away, dirty ones only after
+        // their next flush
+        resident.sort_by_key(|(dirty, _, _)| *dirty);
+        let mut to_page_out = vec![];
+        for (_, size, object_id) in resident {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(size);
+            to_page_out.push(object_id);
+        }
 
-                let read_io_latency_us =
-                    u64::try_from(before_read_io.elapsed().as_micros())
-                        .unwrap();
-                self.cache
-                    .read_stats
-                    .max_read_io_latency_us
-                    .fetch_max(read_io_latency_us, Ordering::Relaxed);
-                self.cache
-                    .read_stats
-                    .sum_read_io_latency_us
-                    .fetch_add(read_io_latency_us, Ordering::Relaxed);
+        self.cache.page_out(&to_page_out)?;
+        self.quota.record_paged_out(to_page_out.len());
+        Ok(())
+    }
 
-                let before_deserialization = Instant::now();
+    /// Keeps the leaf holding `key` in the cache until `unpin` is called
+    /// for it, regardless of eviction pressure. The key does not have to
+    /// exist. As the leaf splits or merges, whichever leaf holds the key is
+    /// the one that stays cached. Pins apply to every handle of this tree
+    /// and are not persisted.
+    ///
+    /// Pinned leaves take up cache capacity that the rest of the database
+    /// can not use, so pinning fails with `QuotaExceeded` if the pinned
+    /// leaves would take up more than `Config::max_pinned_cache_percent` of
+    /// the cache. See [`Db::pinned_bytes`]. A pinned leaf that grows may
+    /// exceed the limit, which is only checked here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let db: melange_db::Db<1024> = melange_db::Config::tmp()?.open()?;
+    /// let settings = db.open_tree("settings")?;
+    /// settings.insert(b"feature_flags", b"...")?;
+    ///
+    /// settings.pin(b"feature_flags")?;
+    /// assert!(db.pinned_bytes() > 0);
+    ///
+    /// settings.unpin(b"feature_flags");
+    /// assert_eq!(db.pinned_bytes(), 0);
+    /// # Ok(()) }
+    /// ```
+    pub fn pin<K: AsRef<[u8]>>(&self, key: K) -> io::Result<()> {
+        let key = key.as_ref();
+        // measured before locking the leaf, because it reads every leaf
+        let pinned_bytes = self.cache.pinned_bytes();
 
-                let leaf: Box<Leaf<LEAF_FANOUT>> =
-                    Leaf::deserialize(&leaf_bytes).unwrap();
+        let mut leaf_guard = self.leaf_for_key_mut(key)?;
+        let leaf = leaf_guard.leaf_write.leaf.as_mut().unwrap();
+        if !self.cache.is_pinned(self.collection_id, leaf) {
+            let max_pinned_bytes = self.cache.max_pinned_bytes();
+            if pinned_bytes + leaf.in_memory_size > max_pinned_bytes {
+                return Err(annotate!(io::Error::new(
+                    io::ErrorKind::QuotaExceeded,
+                    format!(
+                        "pinning a leaf of {} bytes would exceed the {} bytes \
+                        allowed by Config::max_pinned_cache_percent, {} bytes \
+                        are already pinned",
+                        leaf.in_memory_size, max_pinned_bytes, pinned_bytes
+                    )
+                )));
+            }
+        }
 
-                if leaf.lo != low_key {
-                    // TODO determine why this rare situation occurs and better
-                    // understand whether it is really benign.
-                    trace_log!("mismatch between object key and leaf low");
+        // the pin is registered while the leaf is locked, so an eviction
+        // either sees it or marked the leaf before we clear the mark
+        self.cache.pin(self.collection_id, key);
+        leaf.page_out_on_flush = None;
+        Ok(())
+    }
 
-                    hint::spin_loop();
+    /// Allows the leaf holding `key` to be evicted again, unless it holds
+    /// another pinned key. Returns `false` if `key` was not pinned.
+    pub fn unpin<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.cache.unpin(self.collection_id, key.as_ref())
+    }
 
-                    last_continue = concat!(file!(), ':', line!());
+    /// Returns the number of leaf splits and merges and of failed compare
+    /// and swaps of this tree since the database was opened.
+    pub fn tree_stats(&self) -> TreeStats {
+        TreeStats {
+            leaf_splits: self.leaf_policy.splits.load(Ordering::Relaxed),
+            leaf_merges: self.leaf_policy.merges.load(Ordering::Relaxed),
+            cas_conflicts: self.contention.conflicts(),
+            cas_mismatches: self.contention.mismatches(),
+        }
+    }
+
+    /// Returns up to `top_n` keys of this tree with the most compare and
+    /// swap conflicts (see [`TreeStats::cas_conflicts`]) since the last
+    /// [`Tree::reset_contention_report`], most contended first. Keys are
+    /// reported by hash, length and a short prefix only.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.update_and_fetch("counter", |_| Some(vec![1]))?;
+    ///
+    /// // a single thread never conflicts with itself
+    /// assert!(db.contention_report(10).is_empty());
+    /// # Ok(()) }
+    /// ```
+    pub fn contention_report(&self, top_n: usize) -> Vec<ContendedKey> {
+        self.contention.report(top_n)
+    }
+
+    /// Clears the per-key counts behind [`Tree::contention_report`]. The
+    /// totals in [`TreeStats`] are not reset.
+    pub fn reset_contention_report(&self) {
+        self.contention.reset_report();
+    }
+
+    /// Tracks the conflicts of one retrying operation on this tree.
+    pub(crate) fn retries(&self, policy: Option<RetryPolicy>) -> Retries<'_> {
+        Retries::new(&self.contention, policy)
+    }
+
+    /// Returns the total length of the low keys of this tree's leaves. Low
+    /// keys are the keys of the in-memory index and of the metadata store,
+    /// see [`Config::truncate_split_keys`].
+    pub fn index_key_bytes(&self) -> usize {
+        self.index.iter().map(|(low_key, _)| low_key.len()).sum()
+    }
+
+    /// Returns how well the bloom filter would have answered this tree's
+    /// `get` calls since the database was opened. See [`BloomReadStats`].
+    pub fn bloom_stats(&self) -> BloomReadStats {
+        let counters = &self.bloom_counters;
+        BloomReadStats {
+            lookups: counters.lookups.load(Ordering::Relaxed),
+            true_negatives: counters.true_negatives.load(Ordering::Relaxed),
+            confirmed_hits: counters.confirmed_hits.load(Ordering::Relaxed),
+            false_positives: counters.false_positives.load(Ordering::Relaxed),
+            false_negatives: counters.false_negatives.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of bytes this tree's leaves occupy in the heap
+    /// as of the last completed flush. This is the serialized (and possibly
+    /// compressed) size of each leaf, maintained incrementally as leaves are
+    /// written and freed, so it is cheap to call. It does not include slot
+    /// padding, free space or metadata shared with the other trees; see
+    /// [`Db::disk_usage`](crate::Db::disk_usage) for those.
+    pub fn estimated_disk_bytes(&self) -> u64 {
+        self.cache.heap().bytes_for_collection(self.collection_id)
+    }
+
+    /// Returns every key of this tree together with the heap slot of the
+    /// leaf holding it, for offline inspection tools that correlate keys
+    /// with the slab files. Not a stable API.
+    ///
+    /// Locations are those of the last flush: each leaf is read back from
+    /// its slot and the keys stored there are reported, so writes since the
+    /// last flush are not reflected and leaves that were never flushed are
+    /// left out. Values are not loaded, and values stored as blobs are not
+    /// in the leaf's slot. A flush running concurrently may move a leaf
+    /// after it was read.
+    #[doc(hidden)]
+    pub fn debug_entries(
+        &self,
+    ) -> io::Result<impl Iterator<Item = (InlineArray, SlabAddress)>> {
+        self.check_error()?;
+
+        let heap = self.cache.heap();
+        let mut entries = vec![];
+        for (_, node) in self.index.iter() {
+            let _heap_pin = self.cache.heap_object_id_pin();
+            let Some(address) = heap.location(node.object_id) else {
+                continue;
+            };
+            let bytes = heap.read_slab_address(address)?;
+            let leaf: Box<Leaf<LEAF_FANOUT>> = Leaf::deserialize(
+                &bytes,
+                self.cache.dictionaries(),
+                self.cache.blobs(),
+            )?;
+            if leaf.deleted.is_some() {
+                continue;
+            }
+            entries.extend(leaf.iter_stored().map(|(key, _)| (key, address)));
+        }
+        Ok(entries.into_iter())
+    }
+
+    /// The heap of this tree's database, for diagnostics. Not a stable API.
+    #[doc(hidden)]
+    pub fn debug_heap(&self) -> &Heap {
+        self.cache.heap()
+    }
+
+    /// Describes every leaf of this tree in key order, for tools that
+    /// examine the physical layout. Not a stable API.
+    ///
+    /// The index is snapshotted first, so leaves split or merged by
+    /// concurrent writes may be described as they were before. Resident
+    /// leaves are read from the cache without counting as an access, and
+    /// the stored copy of every other leaf is read and decoded without
+    /// adding it to the cache or loading values stored as blobs. Only the
+    /// length of a resident leaf's stored copy is used.
+    #[doc(hidden)]
+    pub fn leaf_layout(
+        &self,
+    ) -> io::Result<impl Iterator<Item = LeafInfo> + use<LEAF_FANOUT>> {
+        self.check_error()?;
+
+        let heap = self.cache.heap();
+        let nodes: Vec<(InlineArray, Object<LEAF_FANOUT>)> = self.index.iter().collect();
+
+        let mut leaves = Vec::with_capacity(nodes.len());
+        for (low_key, node) in nodes {
+            // keeps a slot vacated by a concurrent flush from being reused
+            // while it is read
+            let _heap_pin = self.cache.heap_object_id_pin();
+            let address = heap.location(node.object_id);
+            let stored = address.map(|address| heap.read_slab_address(address)).transpose()?;
+
+            let resident = {
+                let cache_box = node.inner.read();
+                cache_box.leaf.as_ref().map(|leaf| {
+                    (leaf.deleted.is_some(), leaf.len(), leaf.content_bytes())
+                })
+            };
+            let (deleted, entry_count, uncompressed_bytes) = match (resident, &stored) {
+                (Some(resident), _) => resident,
+                (None, Some(bytes)) => {
+                    let leaf: Box<Leaf<LEAF_FANOUT>> = Leaf::deserialize(
+                        bytes,
+                        self.cache.dictionaries(),
+                        self.cache.blobs(),
+                    )?;
+                    (leaf.deleted.is_some(), leaf.len(), leaf.content_bytes())
+                }
+                // freed by a concurrent merge after the index was read
+                (None, None) => continue,
+            };
+            if deleted {
+                continue;
+            }
+
+            leaves.push(LeafInfo {
+                low_key,
+                entry_count,
+                serialized_bytes: stored.as_ref().map(Vec::len),
+                uncompressed_bytes,
+                object_id: *node.object_id,
+                slab_file: address.map(|address| address.slab()),
+                in_cache: resident.is_some(),
+            });
+        }
+        Ok(leaves.into_iter())
+    }
+
+    /// Rebuilds the bloom filter shared by `trees`, which must be every
+    /// tree of one database, at a larger capacity from their live keys if
+    /// its estimated false positive rate has degraded past the target.
+    /// Returns `true` if the filter was rebuilt.
+    pub(crate) fn resize_shared_bloom_filter_if_needed(
+        trees: &[Tree<LEAF_FANOUT>],
+    ) -> io::Result<bool> {
+        let Some(first) = trees.first() else {
+            return Ok(false);
+        };
+        if !first.cache.bloom_filter_needs_resize() {
+            return Ok(false);
+        }
+
+        first.cache.rebuild_bloom_filter(|insert| {
+            for tree in trees {
+                for key_res in tree.iter().keys() {
+                    insert(&key_res?);
+                }
+            }
+            Ok(())
+        })?;
+
+        Ok(true)
+    }
+
+    fn split_leaf_if_full(
+        &self,
+        leaf: &mut Leaf<LEAF_FANOUT>,
+        new_epoch: FlushEpoch,
+    ) -> Option<(InlineArray, Object<LEAF_FANOUT>)> {
+        let split = leaf.split_if_full(
+            new_epoch,
+            &self.cache,
+            self.collection_id,
+            self.leaf_policy.split_threshold(),
+            self.leaf_policy.split_bias(),
+            self.leaf_policy.truncate_split_keys(),
+        );
+        if split.is_some() {
+            self.leaf_policy.splits.fetch_add(1, Ordering::Relaxed);
+        }
+        split
+    }
+
+    /// Whether a leaf that just had items removed is small enough to absorb
+    /// its right sibling. The final decision is made in
+    /// `merge_leaf_into_right_sibling` once the sibling's size is known.
+    fn is_merge_candidate(&self, leaf: &Leaf<LEAF_FANOUT>) -> bool {
+        if cfg!(feature = "monotonic-behavior") || leaf.hi.is_none() {
+            return false;
+        }
+        match self.leaf_policy.merge_threshold() {
+            None => leaf.is_empty(),
+            Some(threshold) => leaf.len() <= threshold,
+        }
+    }
+
+    // This is only pub for an extra assertion during testing.
+    #[doc(hidden)]
+    pub fn check_error(&self) -> io::Result<()> {
+        self.check_dropped()?;
+        self.cache.check_error()
+    }
+
+    /// Fails with `TreeDropped` once `Db::drop_tree` started freeing the
+    /// leaves of this tree. The collection id may be reused by a new tree
+    /// afterwards, so stale handles must not reach its leaves.
+    fn check_dropped(&self) -> io::Result<()> {
+        if self.dropped.load(Ordering::Acquire) {
+            return Err(dropped_tree_error(self.collection_id));
+        }
+        Ok(())
+    }
+
+    fn set_error(&self, error: &io::Error) {
+        self.cache.set_error(error)
+    }
+
+    // shared by every handle of the same tree and by no other tree
+    #[cfg(debug_assertions)]
+    fn closure_pin_id(&self) -> usize {
+        Arc::as_ptr(&self.bloom_counters) as usize
+    }
+
+    pub fn storage_stats(&self) -> Stats {
+        Stats { cache: self.cache.stats(), counters: None }
+    }
+
+    /// Advances the epoch-based reclamation of this handle and frees the
+    /// index nodes it retired, along with the garbage left behind by
+    /// dropped handles. See [`Db::collect_garbage`] for what else holds
+    /// on to retired memory.
+    pub fn collect_garbage(&self) {
+        crate::object_cache::reclaim_retired(&self.index);
+        self.cache.collect_garbage();
+    }
+
+    /// Synchronously flushes all dirty IO buffers and calls
+    /// fsync. If this succeeds, it is guaranteed that all
+    /// previous writes will be recovered if the system
+    /// crashes. Returns the number of bytes flushed during
+    /// this call.
+    ///
+    /// Flushing can take quite a lot of time, and you should
+    /// measure the performance impact of using it on
+    /// realistic sustained workloads running on realistic
+    /// hardware.
+    ///
+    /// This is called automatically on drop of the last open Db
+    /// instance.
+    ///
+    /// All trees of a `Db` share one flush, so this persists the writes of
+    /// every tree, and [`Db::flush_all`] does the same in one call. A call
+    /// made while another `Tree::flush` or `Db::flush_all` is running waits
+    /// for it, and returns empty stats if that flush already covered the
+    /// writes made before this call. See `Config::coalesce_flushes`.
+    pub fn flush(&self) -> io::Result<FlushStats> {
+        self.cache.flush_coalesced()
+    }
+
+    /// Records a successful write in the diagnostic journal, if enabled.
+    fn journal(&self, op: OpKind, key: &[u8], value: Option<&[u8]>) {
+        if let Some(journal) = self.cache.op_journal() {
+            journal.record(self.collection_id, op, key, value);
+        }
+    }
+
+    pub(crate) fn page_in(
+        &self,
+        key: &[u8],
+        flush_epoch: FlushEpoch,
+    ) -> io::Result<(
+        InlineArray,
+        ArcRwLockWriteGuard<RawRwLock, CacheBox<LEAF_FANOUT>>,
+        Object<LEAF_FANOUT>,
+    )> {
+        ClosurePin::check(self);
+
+        let before_read_io = Instant::now();
+
+                let mut loops: u64 = 0;
+        let mut last_continue = "none";
+        let mut warned = false;
+
+        loop {
+            loops += 1;
+
+            if loops > 10_000_000 && !warned {
+                warn_log!(
+                    "page_in spinning for a long time due to continue point {}",
+                    last_continue
+                );
+                warned = true;
+
+                #[cfg(feature = "for-internal-testing-only")]
+                assert!(
+                    loops <= 10_000_000,
+                    "stuck in loop at continue point: {}, search key: {:?}",
+                    last_continue,
+                    key,
+                );
+            }
+
+            self.check_dropped()?;
+
+            let _heap_pin = self.cache.heap_object_id_pin();
+
+            let Some((low_key, node)) = self.index.get_lte(key) else {
+                return Err(dropped_tree_error(self.collection_id));
+            };
+            if node.collection_id != self.collection_id {
+                trace_log!("retry due to mismatched collection id in page_in");
+
+                hint::spin_loop();
+
+                last_continue = concat!(file!(), ':', line!());
+
+                continue;
+            }
+
+                        let mut write = node.inner.write_arc();
+            if write.leaf.is_none() {
+                self.cache
+                    .read_stats
+                    .cache_misses
+                    .fetch_add(1, Ordering::Relaxed);
+
+                let leaf_bytes =
+                    if let Some(read_res) = self.cache.read(node.object_id) {
+                        match read_res {
+                            Ok(buf) => buf,
+                            // keep the location of a checksum failure
+                            // reachable through `CorruptionError::from_io_error`
+                            Err(e) if CorruptionError::from_io_error(&e).is_some() => {
+                                return Err(e);
+                            }
+                            Err(e) => return Err(annotate!(e)),
+                        }
+                    } else {
+                        hint::spin_loop();
+
+                        last_continue = concat!(file!(), ':', line!());
+
+                        continue;
+                    };
+
+                let read_io_latency_us =
+                    u64::try_from(before_read_io.elapsed().as_micros())
+                        .unwrap();
+                self.cache
+                    .read_stats
+                    .max_read_io_latency_us
+                    .fetch_max(read_io_latency_us, Ordering::Relaxed);
+                self.cache
+                    .read_stats
+                    .sum_read_io_latency_us
+                    .fetch_add(read_io_latency_us, Ordering::Relaxed);
+
+                let before_deserialization = Instant::now();
+
+                let mut leaf: Box<Leaf<LEAF_FANOUT>> =
+                    Leaf::deserialize(&leaf_bytes, self.cache.dictionaries(), self.cache.blobs())?;
+                leaf.loaded_epoch = Some(flush_epoch);
+
+                if leaf.lo != low_key {
+                    // TODO determine why this rare situation occurs and better
+                    // understand whether it is really benign.
+                    trace_log!("mismatch between object key and leaf low");
+
+                    hint::spin_loop();
+
+                    last_continue = concat!(file!(), ':', line!());
 
                     continue;
                 }
@@ -419,8 +1452,19 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         let successor_leaf = successor.leaf_write.leaf.as_mut().unwrap();
 
         assert!(predecessor_leaf.deleted.is_none());
-        assert!(predecessor_leaf.is_empty());
         assert!(successor_leaf.deleted.is_none());
+
+        match self.leaf_policy.merge_threshold() {
+            None => assert!(predecessor_leaf.is_empty()),
+            Some(threshold) => {
+                // only merge if the combined leaf stays well below the split
+                // threshold, so that it will not be split again right away
+                if predecessor_leaf.len() + successor_leaf.len() > threshold {
+                    return Ok(());
+                }
+            }
+        }
+
         assert_eq!(
             predecessor_leaf.hi.as_deref(),
             Some(successor_leaf.lo.as_ref()),
@@ -458,6 +1502,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             .cache
             .tree_leaves_merged
             .fetch_add(1, Ordering::Relaxed);
+        self.leaf_policy.merges.fetch_add(1, Ordering::Relaxed);
 
         assert_eq!(successor.low_key, successor_leaf.lo);
         assert_eq!(predecessor.low_key, predecessor_leaf.lo);
@@ -537,56 +1582,240 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         }
     }
 
-    fn cooperatively_serialize_leaf(
-        &self,
-        object_id: ObjectId,
-        leaf: &mut Leaf<LEAF_FANOUT>,
-    ) {
-        // cooperatively serialize and put into dirty
-        let old_dirty_epoch = leaf.dirty_flush_epoch.take().unwrap();
-        assert!(Some(old_dirty_epoch) > leaf.max_unflushed_epoch);
-        leaf.max_unflushed_epoch = Some(old_dirty_epoch);
-        leaf.page_out_on_flush.take();
+    /// Removes every leaf of this tree from the index and frees their
+    /// objects in a single flush epoch, so that the tree is gone after
+    /// recovery and its collection id can be reused. Used by
+    /// `Db::drop_tree`. Once the leaves are locked, operations on any
+    /// remaining handle or iterator of this tree return `TreeDropped`.
+    pub(crate) fn delete_all_leaves(&self) -> io::Result<()> {
+        self.cache.check_writable()?;
+
+        // NB: leaves are locked in key order, like batches do
+        let mut acquired_locks = vec![];
+        let mut next_key = Some(InlineArray::default());
+        while let Some(key) = next_key.take() {
+            let (low_key, write, node) =
+                self.page_in(&key, self.cache.current_flush_epoch())?;
+            next_key = write.leaf.as_ref().unwrap().hi.clone();
+            acquired_locks.push((low_key, write, node));
+        }
 
-        trace_log!(
-            "cooperatively serializing leaf id {:?} with low key {:?}",
-            object_id,
-            leaf.lo
-        );
+        // every other handle fails from now on, and operations waiting for
+        // these locks retry and fail once the leaves are marked deleted
+        self.dropped.store(true, Ordering::Release);
 
-        
+        let flush_epoch_guard = self.cache.check_into_flush_epoch();
+        let delete_epoch = flush_epoch_guard.epoch();
 
-        // be extra-explicit about serialized bytes
-        let leaf_ref: &Leaf<LEAF_FANOUT> = &*leaf;
+        for (low_key, write, node) in &mut acquired_locks {
+            let leaf = write.leaf.as_mut().unwrap();
+            if let Some(old_flush_epoch) = leaf.dirty_flush_epoch
+                && old_flush_epoch != delete_epoch
+            {
+                // persist writes from previous epochs before the deletion
+                assert!(old_flush_epoch < delete_epoch);
+                self.cooperatively_serialize_leaf(node.object_id, &mut *leaf);
+            }
 
-        let serialized =
-            leaf_ref.serialize(self.cache.config.zstd_compression_level);
+            leaf.release_values(self.cache.blobs());
+            leaf.deleted = Some(delete_epoch);
 
-        trace_log!(
-            "D adding node {} to dirty {:?}",
-            object_id.0,
-            old_dirty_epoch
-        );
+            self.index.remove(low_key).unwrap();
+            self.cache.object_id_index.remove(&node.object_id).unwrap();
 
-        self.cache.install_dirty(
-            old_dirty_epoch,
-            object_id,
-            Dirty::CooperativelySerialized {
-                object_id,
-                collection_id: self.collection_id,
-                low_key: leaf.lo.clone(),
-                mutation_count: leaf.mutation_count,
-                data: Arc::new(serialized),
-            },
-        );
+            self.cache.install_dirty(
+                delete_epoch,
+                node.object_id,
+                Dirty::MergedAndDeleted {
+                    object_id: node.object_id,
+                    collection_id: self.collection_id,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Write-locks every leaf of the tree in key order, like batches do.
+    /// Leaves that are not cached are locked without being paged in, so
+    /// their locks hold no leaf.
+    #[allow(clippy::type_complexity)]
+    fn lock_every_leaf(
+        &self,
+    ) -> io::Result<(
+        Vec<(InlineArray, Object<LEAF_FANOUT>)>,
+        Vec<ArcRwLockWriteGuard<RawRwLock, CacheBox<LEAF_FANOUT>>>,
+    )> {
+        loop {
+            let nodes: Vec<(InlineArray, Object<LEAF_FANOUT>)> =
+                self.index.iter().collect();
+            if nodes.is_empty() {
+                return Err(dropped_tree_error(self.collection_id));
+            }
+
+            let acquired_locks: Vec<_> =
+                nodes.iter().map(|(_, node)| node.inner.write_arc()).collect();
+
+            // a concurrent split or merge may have changed the leaves before
+            // all of them were locked, after which none of them can change
+            let unchanged = self
+                .index
+                .iter()
+                .map(|(low_key, node)| (low_key, node.object_id))
+                .eq(nodes.iter().map(|(low_key, node)| (low_key.clone(), node.object_id)));
+            if unchanged {
+                return Ok((nodes, acquired_locks));
+            }
+
+            trace_log!("retry due to concurrent split or merge while locking every leaf");
+            drop(acquired_locks);
+            hint::spin_loop();
+        }
+    }
+
+    /// Fills the empty index of `dest`, a tree that is not reachable by
+    /// anything else yet, with a copy-on-write clone of every leaf of this
+    /// tree. Leaves whose latest version is already in the heap share
+    /// their slot with the clone without being read, the rest are copied
+    /// in memory. Either tree rewriting a shared leaf stores the new
+    /// version elsewhere, and the slot is only freed once neither tree
+    /// references it.
+    pub(crate) fn share_leaves(&self, dest: &Tree<LEAF_FANOUT>) -> io::Result<()> {
+        assert_eq!(dest.index.iter().count(), 0);
+        dest.cache.check_writable()?;
+
+        // NB: every leaf is locked, so that the clone sees every leaf at
+        // the same moment
+        let (nodes, acquired_locks) = self.lock_every_leaf()?;
+
+        let flush_epoch_guard = self.cache.check_into_flush_epoch();
+        let clone_epoch = flush_epoch_guard.epoch();
+        let max_flushed_epoch = self.cache.max_flushed_epoch();
+
+        for ((low_key, node), write) in nodes.iter().zip(&acquired_locks) {
+            let object_id = self.cache.allocate_object_id(clone_epoch);
+
+            // a leaf that is not cached, or a clean leaf whose last
+            // serialization is durable, is identical to the object stored
+            // at its slot
+            let is_stored = write.leaf.as_ref().is_none_or(|leaf| {
+                leaf.dirty_flush_epoch.is_none()
+                    && leaf
+                        .max_unflushed_epoch
+                        .is_none_or(|epoch| epoch.get() <= max_flushed_epoch)
+            });
+            let shared_location = if is_stored {
+                self.cache.share_object(node.object_id, object_id, dest.collection_id)
+            } else {
+                None
+            };
+
+            let leaf_copy = if shared_location.is_none() {
+                // leaves are only paged out once they are stored
+                let leaf = write.leaf.as_ref().expect("uncached leaf is not stored");
+                let mut copy = leaf.clone();
+                copy.dirty_flush_epoch = Some(clone_epoch);
+                copy.page_out_on_flush = None;
+                copy.max_unflushed_epoch = None;
+                copy.incremental_changes = None;
+                if copy.incremental_serialization_enabled {
+                    copy.enable_incremental_serialization();
+                }
+                Some(copy)
+            } else {
+                None
+            };
+
+            let node = Object {
+                object_id,
+                collection_id: dest.collection_id,
+                low_key: low_key.clone(),
+                inner: Arc::new(RwLock::new(CacheBox {
+                    leaf: leaf_copy,
+                    logged_index: BTreeMap::default(),
+                })),
+            };
+
+            let dirty = match shared_location {
+                Some(location) => Dirty::Shared {
+                    object_id,
+                    collection_id: dest.collection_id,
+                    low_key: low_key.clone(),
+                    location,
+                },
+                None => Dirty::NotYetSerialized {
+                    collection_id: dest.collection_id,
+                    node: node.clone(),
+                    low_key: low_key.clone(),
+                },
+            };
+
+            self.cache.install_dirty(clone_epoch, object_id, dirty);
+            self.cache.object_id_index.insert(object_id, node.clone());
+            assert!(dest.index.insert(low_key.clone(), node).is_none());
+        }
+
+        drop(flush_epoch_guard);
+        drop(acquired_locks);
+
+        Ok(())
+    }
+
+    fn cooperatively_serialize_leaf(
+        &self,
+        object_id: ObjectId,
+        leaf: &mut Leaf<LEAF_FANOUT>,
+    ) {
+        // cooperatively serialize and put into dirty
+        let old_dirty_epoch = leaf.dirty_flush_epoch.take().unwrap();
+        assert!(Some(old_dirty_epoch) > leaf.max_unflushed_epoch);
+        leaf.max_unflushed_epoch = Some(old_dirty_epoch);
+        leaf.page_out_on_flush.take();
+
+        trace_log!(
+            "cooperatively serializing leaf id {:?} with low key {:?}",
+            object_id,
+            leaf.lo
+        );
+
+        
+
+        // be extra-explicit about serialized bytes
+        let leaf_ref: &Leaf<LEAF_FANOUT> = &*leaf;
+
+        let serialized = leaf_ref.serialize(&self.cache, self.collection_id);
+
+        trace_log!(
+            "D adding node {} to dirty {:?}",
+            object_id.0,
+            old_dirty_epoch
+        );
+
+        self.cache.install_dirty(
+            old_dirty_epoch,
+            object_id,
+            Dirty::CooperativelySerialized {
+                object_id,
+                collection_id: self.collection_id,
+                low_key: leaf.lo.clone(),
+                mutation_count: leaf.mutation_count,
+                data: Arc::new(serialized),
+            },
+        );
     }
 
     fn leaf_for_key<'a>(
         &'a self,
         key: &[u8],
     ) -> io::Result<LeafReadGuard<'a, LEAF_FANOUT>> {
+        ClosurePin::check(self);
+
                       loop {
-            let (low_key, node) = self.index.get_lte(key).unwrap();
+            self.check_dropped()?;
+
+            let Some((low_key, node)) = self.index.get_lte(key) else {
+                return Err(dropped_tree_error(self.collection_id));
+            };
 
                         let mut read = node.inner.read_arc();
 
@@ -652,10 +1881,134 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         }
     }
 
+    /// Like `leaf_for_key`, but applies `policy` to the cache interaction.
+    fn leaf_for_key_with_policy<'a>(
+        &'a self,
+        key: &[u8],
+        policy: CachePolicy,
+    ) -> io::Result<ReadLeaf<'a, LEAF_FANOUT>> {
+        ClosurePin::check(self);
+
+        if policy == CachePolicy::Normal && !self.cache.uses_admission_filter() {
+            return self.leaf_for_key(key).map(ReadLeaf::Cached);
+        }
+
+        loop {
+            self.check_dropped()?;
+
+            let _heap_pin = self.cache.heap_object_id_pin();
+
+            let Some((low_key, node)) = self.index.get_lte(key) else {
+                return Err(dropped_tree_error(self.collection_id));
+            };
+
+            let read = node.inner.read_arc();
+
+            if policy == CachePolicy::Normal
+                && (read.leaf.is_some() || self.cache.admit(node.object_id))
+            {
+                drop(read);
+                drop(_heap_pin);
+                return self.leaf_for_key(key).map(ReadLeaf::Cached);
+            }
+
+            if read.leaf.is_some() {
+                drop(_heap_pin);
+                self.cache
+                    .read_stats
+                    .cache_hits
+                    .fetch_add(1, Ordering::Relaxed);
+                self.cache
+                    .read_stats
+                    .cache_bypassed_reads
+                    .fetch_add(1, Ordering::Relaxed);
+
+                let leaf_guard = LeafReadGuard {
+                    leaf_read: ManuallyDrop::new(read),
+                    inner: self,
+                    low_key,
+                    object_id: node.object_id,
+                    // skip marking the leaf as accessed on drop
+                    external_cache_access_and_eviction: true,
+                };
+
+                let leaf = leaf_guard.leaf_read.leaf.as_ref().unwrap();
+
+                if leaf.deleted.is_some()
+                    || &*leaf.lo > key
+                    || leaf.hi.as_ref().is_some_and(|hi| &**hi <= key)
+                    || leaf.lo != node.low_key
+                {
+                    trace_log!("retry due to concurrent modification in leaf_for_key_with_policy");
+                    drop(leaf_guard);
+                    hint::spin_loop();
+                    continue;
+                }
+
+                return Ok(ReadLeaf::Cached(leaf_guard));
+            }
+
+            if policy == CachePolicy::ReadDontPromote {
+                // page the leaf in so that it enters the cache's entry segment
+                drop(read);
+                drop(_heap_pin);
+                return self.leaf_for_key(key).map(ReadLeaf::Cached);
+            }
+
+            // The leaf is not cached. While we hold the read lock it cannot be
+            // paged in and modified, so the copy on disk is current.
+            self.cache
+                .read_stats
+                .cache_misses
+                .fetch_add(1, Ordering::Relaxed);
+            if policy == CachePolicy::Normal {
+                self.cache
+                    .read_stats
+                    .cache_admission_rejections
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+
+            let leaf_bytes = match self.cache.read(node.object_id) {
+                Some(Ok(buf)) => buf,
+                Some(Err(e)) if CorruptionError::from_io_error(&e).is_some() => {
+                    return Err(e);
+                }
+                Some(Err(e)) => return Err(annotate!(e)),
+                None => {
+                    drop(read);
+                    hint::spin_loop();
+                    continue;
+                }
+            };
+
+            let leaf: Box<Leaf<LEAF_FANOUT>> =
+                Leaf::deserialize(&leaf_bytes, self.cache.dictionaries(), self.cache.blobs())?;
+
+            if leaf.deleted.is_some()
+                || leaf.lo != low_key
+                || &*leaf.lo > key
+                || leaf.hi.as_ref().is_some_and(|hi| &**hi <= key)
+            {
+                trace_log!("retry due to concurrent modification in leaf_for_key_with_policy");
+                drop(read);
+                hint::spin_loop();
+                continue;
+            }
+
+            self.cache
+                .read_stats
+                .cache_bypassed_reads
+                .fetch_add(1, Ordering::Relaxed);
+
+            return Ok(ReadLeaf::Uncached(leaf, read));
+        }
+    }
+
     fn leaf_for_key_mut<'a>(
         &'a self,
         key: &[u8],
     ) -> io::Result<LeafWriteGuard<'a, LEAF_FANOUT>> {
+        self.cache.check_writable()?;
         let reader_epoch = self.cache.current_flush_epoch();
 
         let (low_key, mut write, node) = self.page_in(key, reader_epoch)?;
@@ -700,6 +2053,8 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             }
         }
 
+        self.snapshots.preserve(leaf, self.cache.blobs())?;
+
         Ok(LeafWriteGuard {
             flush_epoch_guard,
             leaf_write: ManuallyDrop::new(write),
@@ -726,30 +2081,60 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     pub fn get<K: AsRef<[u8]>>(
         &self,
         key: K,
+    ) -> io::Result<Option<InlineArray>> {
+        self.get_with(key, GetOptions::default())
+    }
+
+    /// Retrieve a value from the `Tree` if it exists, controlling how the
+    /// read interacts with the leaf cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::{CachePolicy, GetOptions};
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(&[0], vec![0])?;
+    ///
+    /// let options = GetOptions { cache_policy: CachePolicy::BypassCache };
+    /// assert_eq!(db.get_with(&[0], options)?, Some(melange_db::InlineArray::from(vec![0])));
+    /// # Ok(()) }
+    /// ```
+    pub fn get_with<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        options: GetOptions,
     ) -> io::Result<Option<InlineArray>> {
         self.check_error()?;
 
         let key_ref = key.as_ref();
 
-        // 使用布隆过滤器进行快速过滤（仅用于调试统计）
+        // 查询布隆过滤器并与实际结果比较，用于 `bloom_stats` 统计
         // 注意：布隆过滤器不能用于确定key不存在，只能用于可能的性能优化
-        let _bloom_contains = self.cache.bloom_filter_contains(key_ref);
+        let bloom_contains = self.cache.bloom_filter_contains(key_ref);
 
-        let leaf_guard = self.leaf_for_key(key_ref)?;
+        let read_leaf =
+            self.leaf_for_key_with_policy(key_ref, options.cache_policy)?;
 
-        let leaf = leaf_guard.leaf_read.leaf.as_ref().unwrap();
+        let leaf = read_leaf.leaf();
 
         if let Some(ref hi) = leaf.hi {
             assert!(&**hi > key_ref);
         }
 
-        let result = leaf.get(key_ref).cloned();
+        let result = leaf.get(key_ref, self.cache.blobs())?;
+
+        drop(read_leaf);
+
+        self.bloom_counters.record(bloom_contains, result.is_some());
 
         Ok(result)
     }
 
-    /// Insert a key to a new value, returning the last value if it
-    /// was set.
+    /// Retrieve a value along with when it was last written and how many
+    /// bytes it occupies on disk, for conditional reads that skip values
+    /// that have not changed since an earlier epoch.
     ///
     /// # Examples
     ///
@@ -757,94 +2142,361 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// # let config = melange_db::Config::tmp().unwrap();
     /// # let db: melange_db::Db<1024> = config.open()?;
-    /// assert_eq!(db.insert(&[1, 2, 3], vec![0]).unwrap(), None);
-    /// assert_eq!(db.insert(&[1, 2, 3], vec![1]).unwrap(), Some(melange_db::InlineArray::from(&[0])));
+    /// db.insert(b"k", b"v1".as_slice())?;
+    /// let (_, before) = db.get_with_metadata(b"k")?.unwrap();
+    ///
+    /// db.flush()?;
+    /// db.insert(b"k", b"v2".as_slice())?;
+    /// let (value, after) = db.get_with_metadata(b"k")?.unwrap();
+    /// assert_eq!(value, b"v2");
+    /// assert!(after.write_epoch > before.write_epoch);
     /// # Ok(()) }
     /// ```
-    #[doc(alias = "set")]
-    #[doc(alias = "put")]
-    pub fn insert<K, V>(
+    pub fn get_with_metadata<K: AsRef<[u8]>>(
         &self,
         key: K,
-        value: V,
-    ) -> io::Result<Option<InlineArray>>
-    where
-        K: AsRef<[u8]>,
-        V: Into<InlineArray>,
-    {
+    ) -> io::Result<Option<(InlineArray, EntryMetadata)>> {
         self.check_error()?;
 
         let key_ref = key.as_ref();
 
-        let value_ivec = value.into();
-        let mut leaf_guard = self.leaf_for_key_mut(key_ref)?;
-        let new_epoch = leaf_guard.epoch();
-
-        let leaf = leaf_guard.leaf_write.leaf.as_mut().unwrap();
+        let bloom_contains = self.cache.bloom_filter_contains(key_ref);
 
-        let ret = leaf.insert(key_ref.into(), value_ivec.clone());
+        let read_leaf =
+            self.leaf_for_key_with_policy(key_ref, CachePolicy::default())?;
 
-        // 更新布隆过滤器
-        self.cache.bloom_filter_insert(key_ref);
+        let leaf = read_leaf.leaf();
 
-        // 记录写入统计（仅当智能flush启用时）
-        if self.cache.config.smart_flush_config.enabled {
-            let bytes_written = key_ref.len() + value_ivec.len();
-            self.cache.record_write(bytes_written);
+        if let Some(ref hi) = leaf.hi {
+            assert!(&**hi > key_ref);
         }
 
-        let old_size =
-            ret.as_ref().map(|v| key_ref.len() + v.len()).unwrap_or(0);
-        let new_size = key_ref.len() + value_ivec.len();
+        let current_epoch = self.cache.current_flush_epoch();
+        let result = leaf
+            .get_with_stored_len(key_ref, self.cache.blobs())?
+            .map(|(value, stored_bytes)| {
+                let write_epoch = leaf.write_epoch(key_ref, current_epoch);
+                (value, EntryMetadata { write_epoch, stored_bytes })
+            });
 
-        if new_size > old_size {
-            leaf.in_memory_size += new_size - old_size;
-        } else {
-            leaf.in_memory_size =
-                leaf.in_memory_size.saturating_sub(old_size - new_size);
-        }
+        drop(read_leaf);
 
-        let split =
-            leaf.split_if_full(new_epoch, &self.cache, self.collection_id);
-        if split.is_some() || Some(value_ivec) != ret {
-            leaf.mutation_count += 1;
-            leaf.set_dirty_epoch(new_epoch);
-            trace_log!(
-                "F adding node {} to dirty {:?}",
-                leaf_guard.node.object_id.0,
-                new_epoch
-            );
+        self.bloom_counters.record(bloom_contains, result.is_some());
 
-            self.cache.install_dirty(
-                new_epoch,
-                leaf_guard.node.object_id,
-                Dirty::NotYetSerialized {
-                    collection_id: self.collection_id,
-                    node: leaf_guard.node.clone(),
-                    low_key: leaf_guard.low_key.clone(),
-                },
-            );
+        Ok(result)
+    }
 
-            
-        }
-        if let Some((split_key, rhs_node)) = split {
-            assert_eq!(leaf.hi.as_ref().unwrap(), &split_key);
-            trace_log!(
-                "G adding new from split {:?} to dirty {:?}",
-                rhs_node.object_id,
-                new_epoch
-            );
+    /// Calls `f` with the value of `key`, borrowed in place from the
+    /// in-memory leaf, and returns its result, or `None` if the key is
+    /// absent.
+    ///
+    /// Unlike [`Tree::get`], the value is not copied into an
+    /// `InlineArray`, so reading a few fields out of a large value costs
+    /// no allocation. Values stored out of line because of
+    /// [`Config::inline_value_threshold`] are still read into a buffer.
+    ///
+    /// The leaf stays read-locked and the flush epoch stays pinned for as
+    /// long as `f` runs, blocking writers to every key in that leaf, so
+    /// `f` should be short. `f` must not call back into this tree: a write
+    /// to the same leaf would deadlock. Debug builds detect such calls and
+    /// panic instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"order_1", vec![7, 0, 0, 1, 0])?;
+    ///
+    /// let amount = db.get_in_place(b"order_1", |value| {
+    ///     u32::from_be_bytes(value[1..5].try_into().unwrap())
+    /// })?;
+    /// assert_eq!(amount, Some(256));
+    /// assert_eq!(db.get_in_place(b"order_2", |value| value.len())?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn get_in_place<K, F, R>(&self, key: K, f: F) -> io::Result<Option<R>>
+    where
+        K: AsRef<[u8]>,
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.check_error()?;
 
-            assert_ne!(rhs_node.object_id, leaf_guard.node.object_id);
-            assert!(!split_key.is_empty());
+        let key_ref = key.as_ref();
 
-            let rhs_object_id = rhs_node.object_id;
+        let bloom_contains = self.cache.bloom_filter_contains(key_ref);
 
-            self.cache.install_dirty(
-                new_epoch,
-                rhs_object_id,
-                Dirty::NotYetSerialized {
-                    collection_id: self.collection_id,
+        let read_leaf =
+            self.leaf_for_key_with_policy(key_ref, CachePolicy::Normal)?;
+
+        let leaf = read_leaf.leaf();
+
+        if let Some(ref hi) = leaf.hi {
+            assert!(&**hi > key_ref);
+        }
+
+        let result = {
+            let _pinned = ClosurePin::enter(self);
+            leaf.get_with(key_ref, self.cache.blobs(), f)?
+        };
+
+        drop(read_leaf);
+
+        self.bloom_counters.record(bloom_contains, result.is_some());
+
+        Ok(result)
+    }
+
+    /// Retrieve the values of several keys at once, returned in the same
+    /// order as `keys`. Duplicate keys are allowed.
+    ///
+    /// The keys are sorted internally so that each leaf is located and
+    /// locked once for all of the keys that fall into it, which is
+    /// cheaper than calling `get` in a loop when keys are clustered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"a", b"1".as_slice())?;
+    /// db.insert(b"c", b"3".as_slice())?;
+    ///
+    /// let values = db.get_many(&[b"c", b"b", b"a"])?;
+    /// assert_eq!(values[0].as_deref(), Some(b"3".as_slice()));
+    /// assert_eq!(values[1], None);
+    /// assert_eq!(values[2].as_deref(), Some(b"1".as_slice()));
+    /// # Ok(()) }
+    /// ```
+    pub fn get_many<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+    ) -> io::Result<Vec<Option<InlineArray>>> {
+        self.check_error()?;
+
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_unstable_by(|a, b| keys[*a].as_ref().cmp(keys[*b].as_ref()));
+
+        let mut results = vec![None; keys.len()];
+        let mut remaining = &order[..];
+        while let Some(first) = remaining.first() {
+            let read_leaf = self.leaf_for_key_with_policy(
+                keys[*first].as_ref(),
+                CachePolicy::Normal,
+            )?;
+            let leaf = read_leaf.leaf();
+
+            let in_leaf = match &leaf.hi {
+                Some(hi) => {
+                    remaining.partition_point(|i| keys[*i].as_ref() < &**hi)
+                }
+                None => remaining.len(),
+            };
+            let probes: Vec<&[u8]> =
+                remaining[..in_leaf].iter().map(|i| keys[*i].as_ref()).collect();
+            for (i, value) in remaining[..in_leaf]
+                .iter()
+                .zip(leaf.get_sorted(&probes, self.cache.blobs())?)
+            {
+                results[*i] = value;
+            }
+
+            drop(read_leaf);
+            remaining = &remaining[in_leaf..];
+        }
+
+        for (key, result) in keys.iter().zip(&results) {
+            let bloom_contains = self.cache.bloom_filter_contains(key.as_ref());
+            self.bloom_counters.record(bloom_contains, result.is_some());
+        }
+
+        Ok(results)
+    }
+
+    /// Insert a key to a new value, returning the last value if it
+    /// was set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// assert_eq!(db.insert(&[1, 2, 3], vec![0]).unwrap(), None);
+    /// assert_eq!(db.insert(&[1, 2, 3], vec![1]).unwrap(), Some(melange_db::InlineArray::from(&[0])));
+    /// # Ok(()) }
+    /// ```
+    #[doc(alias = "set")]
+    #[doc(alias = "put")]
+    pub fn insert<K, V>(
+        &self,
+        key: K,
+        value: V,
+    ) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        self.check_error()?;
+        self.check_quota(1)?;
+
+        let key_ref = key.as_ref();
+
+        let value_ivec = value.into();
+        let bytes = key_ref.len() + value_ivec.len();
+        let leaf_guard = self.leaf_for_key_mut(key_ref)?;
+
+        let journaled = self.cache.op_journal().is_some().then(|| value_ivec.clone());
+        let ret = self.insert_into_locked_leaf(leaf_guard, key_ref, value_ivec)?;
+        if let Some(value) = journaled {
+            self.journal(OpKind::Insert, key_ref, Some(&value));
+        }
+        self.record_quota_write(bytes)?;
+        Ok(ret)
+    }
+
+    /// Retrieve the value for a key, or compute and insert it if the key
+    /// is absent, returning the value that is stored afterwards.
+    ///
+    /// The closure runs while the leaf containing the key is locked, so
+    /// even when many threads race on the same absent key it runs at most
+    /// once. Because other writes to the same leaf are blocked while it
+    /// runs, it should be cheap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// let value = db.get_or_insert_with(b"k", || vec![1])?;
+    /// assert_eq!(value, melange_db::InlineArray::from(&[1]));
+    ///
+    /// // already present, so the closure is not called
+    /// let value = db.get_or_insert_with(b"k", || -> Vec<u8> { unreachable!() })?;
+    /// assert_eq!(value, melange_db::InlineArray::from(&[1]));
+    /// # Ok(()) }
+    /// ```
+    pub fn get_or_insert_with<K, V, F>(
+        &self,
+        key: K,
+        f: F,
+    ) -> io::Result<InlineArray>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+        F: FnOnce() -> V,
+    {
+        self.check_error()?;
+        self.check_quota(1)?;
+
+        let key_ref = key.as_ref();
+
+        let leaf_guard = self.leaf_for_key_mut(key_ref)?;
+
+        let leaf = leaf_guard.leaf_write.leaf.as_ref().unwrap();
+        if let Some(existing) = leaf.get(key_ref, self.cache.blobs())? {
+            return Ok(existing);
+        }
+
+        let value_ivec: InlineArray = f().into();
+
+        let previous = self.insert_into_locked_leaf(
+            leaf_guard,
+            key_ref,
+            value_ivec.clone(),
+        )?;
+        assert!(previous.is_none());
+        self.journal(OpKind::Insert, key_ref, Some(&value_ivec));
+        self.record_quota_write(key_ref.len() + value_ivec.len())?;
+
+        Ok(value_ivec)
+    }
+
+    fn insert_into_locked_leaf(
+        &self,
+        mut leaf_guard: LeafWriteGuard<'_, LEAF_FANOUT>,
+        key_ref: &[u8],
+        value_ivec: InlineArray,
+    ) -> io::Result<Option<InlineArray>> {
+        let new_epoch = leaf_guard.epoch();
+
+        let leaf = leaf_guard.leaf_write.leaf.as_mut().unwrap();
+
+        if self.enforces_write_once() && leaf.contains_key(key_ref) {
+            return Err(KeyAlreadyExists { key: key_ref.into() }.into());
+        }
+
+        let old_size = leaf
+            .stored_value_len(key_ref)
+            .map_or(0, |len| key_ref.len() + len);
+
+        let ret = leaf.insert(key_ref.into(), value_ivec.clone(), new_epoch, self.cache.blobs())?;
+
+        // 更新布隆过滤器
+        self.cache.bloom_filter_insert(key_ref);
+
+        // 记录写入统计（仅当智能flush启用时）
+        if self.cache.config.smart_flush_config.enabled {
+            let bytes_written = key_ref.len() + value_ivec.len();
+            match &*self.flush_group.read() {
+                Some(group) => group.record_write(bytes_written),
+                None => self.cache.record_write(bytes_written),
+            }
+        }
+
+        // 单独存储的值只计算叶子节点中的引用
+        let new_size = key_ref.len() + leaf.stored_value_len(key_ref).unwrap();
+
+        if new_size > old_size {
+            leaf.in_memory_size += new_size - old_size;
+        } else {
+            leaf.in_memory_size =
+                leaf.in_memory_size.saturating_sub(old_size - new_size);
+        }
+
+        let split = self.split_leaf_if_full(leaf, new_epoch);
+        if split.is_some() || Some(value_ivec) != ret {
+            leaf.mutation_count += 1;
+            leaf.set_dirty_epoch(new_epoch);
+            trace_log!(
+                "F adding node {} to dirty {:?}",
+                leaf_guard.node.object_id.0,
+                new_epoch
+            );
+
+            self.cache.install_dirty(
+                new_epoch,
+                leaf_guard.node.object_id,
+                Dirty::NotYetSerialized {
+                    collection_id: self.collection_id,
+                    node: leaf_guard.node.clone(),
+                    low_key: leaf_guard.low_key.clone(),
+                },
+            );
+
+            
+        }
+        if let Some((split_key, rhs_node)) = split {
+            assert_eq!(leaf.hi.as_ref().unwrap(), &split_key);
+            trace_log!(
+                "G adding new from split {:?} to dirty {:?}",
+                rhs_node.object_id,
+                new_epoch
+            );
+
+            assert_ne!(rhs_node.object_id, leaf_guard.node.object_id);
+            assert!(!split_key.is_empty());
+
+            let rhs_object_id = rhs_node.object_id;
+
+            self.cache.install_dirty(
+                new_epoch,
+                rhs_object_id,
+                Dirty::NotYetSerialized {
+                    collection_id: self.collection_id,
                     node: rhs_node.clone(),
                     low_key: split_key.clone(),
                 },
@@ -890,9 +2542,19 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         key: K,
     ) -> io::Result<Option<InlineArray>> {
         self.check_error()?;
+        self.check_write_once_removal("remove")?;
+        self.check_quota(1)?;
 
         let key_ref = key.as_ref();
+        let ret = self.remove_inner(key_ref)?;
+        self.journal(OpKind::Remove, key_ref, None);
+        if ret.is_some() {
+            self.record_quota_write(key_ref.len())?;
+        }
+        Ok(ret)
+    }
 
+    fn remove_inner(&self, key_ref: &[u8]) -> io::Result<Option<InlineArray>> {
         let mut leaf_guard = self.leaf_for_key_mut(key_ref)?;
 
         let new_epoch = leaf_guard.epoch();
@@ -901,7 +2563,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
 
         assert!(leaf.deleted.is_none());
 
-        let ret = leaf.remove(key_ref);
+        let ret = leaf.remove(key_ref, self.cache.blobs())?;
 
         if ret.is_some() {
             leaf.mutation_count += 1;
@@ -926,10 +2588,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
 
             
 
-            if cfg!(not(feature = "monotonic-behavior"))
-                && leaf.is_empty()
-                && leaf.hi.is_some()
-            {
+            if self.is_merge_candidate(leaf) {
                 self.merge_leaf_into_right_sibling(leaf_guard)?;
             }
         }
@@ -1001,18 +2660,55 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         OV: AsRef<[u8]>,
         NV: Into<InlineArray>,
     {
+        let ret = self.try_compare_and_swap(
+            key.as_ref(),
+            old.as_ref().map(AsRef::as_ref),
+            new.map(Into::into),
+        )?;
+        if ret.is_err() {
+            // the caller's expectation may have been stale or simply wrong,
+            // only the retrying helpers know that the key was contended
+            self.contention.record_mismatch();
+        }
+        Ok(ret)
+    }
+
+    /// `compare_and_swap` without recording a failure as a mismatch, for
+    /// the helpers that retry and record it as a conflict instead.
+    fn try_compare_and_swap(
+        &self,
+        key_ref: &[u8],
+        old: Option<&[u8]>,
+        proposed: Option<InlineArray>,
+    ) -> CompareAndSwapResult {
         self.check_error()?;
+        self.check_quota(1)?;
 
-        let key_ref = key.as_ref();
+        let bytes = key_ref.len() + proposed.as_ref().map_or(0, |value| value.len());
+
+        let journaled = self.cache.op_journal().is_some().then(|| proposed.clone());
+        let ret = self.compare_and_swap_inner(key_ref, old, proposed)?;
+        if ret.is_ok() {
+            if let Some(proposed) = journaled {
+                self.journal(OpKind::CompareAndSwap, key_ref, proposed.as_deref());
+            }
+            self.record_quota_write(bytes)?;
+        }
+        Ok(ret)
+    }
 
+    fn compare_and_swap_inner(
+        &self,
+        key_ref: &[u8],
+        old: Option<&[u8]>,
+        proposed: Option<InlineArray>,
+    ) -> CompareAndSwapResult {
         let mut leaf_guard = self.leaf_for_key_mut(key_ref)?;
         let new_epoch = leaf_guard.epoch();
 
-        let proposed: Option<InlineArray> = new.map(Into::into);
-
         let leaf = leaf_guard.leaf_write.leaf.as_mut().unwrap();
 
-        let current = leaf.get(key_ref).cloned();
+        let current = leaf.get(key_ref, self.cache.blobs())?;
 
         let previous_matches = match (old, &current) {
             (None, None) => true,
@@ -1024,11 +2720,20 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             _ => false,
         };
 
+        // creating a key cannot overwrite anything, so only a swap of an
+        // existing value violates write-once
+        if previous_matches && current.is_some() && self.enforces_write_once() {
+            return Err(match proposed {
+                Some(_) => KeyAlreadyExists { key: key_ref.into() }.into(),
+                None => write_once_removal_error("compare_and_swap to None"),
+            });
+        }
+
         let ret = if previous_matches {
             if let Some(ref new_value) = proposed {
-                leaf.insert(key_ref.into(), new_value.clone())
+                leaf.insert(key_ref.into(), new_value.clone(), new_epoch, self.cache.blobs())?
             } else {
-                leaf.remove(key_ref)
+                leaf.remove(key_ref, self.cache.blobs())?
             };
 
             Ok(CompareAndSwapSuccess {
@@ -1039,8 +2744,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             Err(CompareAndSwapError { current, proposed })
         };
 
-        let split =
-            leaf.split_if_full(new_epoch, &self.cache, self.collection_id);
+        let split = self.split_leaf_if_full(leaf, new_epoch);
         let split_happened = split.is_some();
         if split_happened || ret.is_ok() {
             leaf.mutation_count += 1;
@@ -1093,11 +2797,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             assert!(prev.is_none());
         }
 
-        if cfg!(not(feature = "monotonic-behavior"))
-            && leaf.is_empty()
-            && leaf.hi.is_some()
-        {
-            assert!(!split_happened);
+        if !split_happened && self.is_merge_candidate(leaf) {
             self.merge_leaf_into_right_sibling(leaf_guard)?;
         }
 
@@ -1151,26 +2851,71 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     pub fn update_and_fetch<K, V, F>(
         &self,
         key: K,
-        mut f: F,
+        f: F,
     ) -> io::Result<Option<InlineArray>>
     where
         K: AsRef<[u8]>,
         F: FnMut(Option<&[u8]>) -> Option<V>,
         V: Into<InlineArray>,
     {
-        let key_ref = key.as_ref();
+        self.update_inner(key.as_ref(), f, None).map(|(_, next)| next)
+    }
+
+    /// Like [`Tree::update_and_fetch`], but waits according to
+    /// `policy.backoff` before every retry and gives up after
+    /// `policy.max_retries` conflicts with an error of kind
+    /// `io::ErrorKind::ResourceBusy` that holds a
+    /// [`ContentionExceeded`]. The key is left as other threads wrote it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::RetryPolicy;
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    ///
+    /// let policy = RetryPolicy { max_retries: 10, ..RetryPolicy::default() };
+    /// let new = db.update_and_fetch_with_retry("counter", |_| Some(vec![1]), policy)?;
+    /// assert_eq!(new.unwrap(), [1]);
+    /// # Ok(()) }
+    /// ```
+    pub fn update_and_fetch_with_retry<K, V, F>(
+        &self,
+        key: K,
+        f: F,
+        policy: RetryPolicy,
+    ) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        self.update_inner(key.as_ref(), f, Some(policy)).map(|(_, next)| next)
+    }
+
+    /// Applies `f` to the value of `key` until the compare and swap of
+    /// its result succeeds, and returns the previous and the new value.
+    fn update_inner<V, F>(
+        &self,
+        key_ref: &[u8],
+        mut f: F,
+        policy: Option<RetryPolicy>,
+    ) -> io::Result<(Option<InlineArray>, Option<InlineArray>)>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        let mut retries = self.retries(policy);
         let mut current = self.get(key_ref)?;
 
         loop {
             let tmp = current.as_ref().map(AsRef::as_ref);
             let next = f(tmp).map(Into::into);
-            match self.compare_and_swap::<_, _, InlineArray>(
-                key_ref,
-                tmp,
-                next.clone(),
-            )? {
-                Ok(_) => return Ok(next),
+            match self.try_compare_and_swap(key_ref, tmp, next.clone())? {
+                Ok(_) => return Ok((current, next)),
                 Err(CompareAndSwapError { current: cur, .. }) => {
+                    retries.conflict(key_ref)?;
                     current = cur;
                 }
             }
@@ -1223,63 +2968,277 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     pub fn fetch_and_update<K, V, F>(
         &self,
         key: K,
-        mut f: F,
+        f: F,
     ) -> io::Result<Option<InlineArray>>
     where
         K: AsRef<[u8]>,
         F: FnMut(Option<&[u8]>) -> Option<V>,
         V: Into<InlineArray>,
     {
-        let key_ref = key.as_ref();
-        let mut current = self.get(key_ref)?;
-
-        loop {
-            let tmp = current.as_ref().map(AsRef::as_ref);
-            let next = f(tmp);
-            match self.compare_and_swap(key_ref, tmp, next)? {
-                Ok(_) => return Ok(current),
-                Err(CompareAndSwapError { current: cur, .. }) => {
-                    current = cur;
-                }
-            }
-        }
-    }
-
-    pub fn iter(&self) -> Iter<LEAF_FANOUT> {
-        Iter {
-            prefetched: VecDeque::new(),
-            prefetched_back: VecDeque::new(),
-            next_fetch: Some(InlineArray::MIN),
-            next_back_last_lo: None,
-            next_calls: 0,
-            next_back_calls: 0,
-            inner: self.clone(),
-            bounds: (Bound::Unbounded, Bound::Unbounded),
-        }
+        self.update_inner(key.as_ref(), f, None).map(|(previous, _)| previous)
     }
 
-    pub fn range<K, R>(&self, range: R) -> Iter<LEAF_FANOUT>
+    /// Like [`Tree::fetch_and_update`], with the retries bounded by
+    /// `policy` as in [`Tree::update_and_fetch_with_retry`].
+    pub fn fetch_and_update_with_retry<K, V, F>(
+        &self,
+        key: K,
+        f: F,
+        policy: RetryPolicy,
+    ) -> io::Result<Option<InlineArray>>
     where
         K: AsRef<[u8]>,
-        R: RangeBounds<K>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
     {
-        let start: Bound<InlineArray> =
-            map_bound(range.start_bound(), |b| InlineArray::from(b.as_ref()));
-        let end: Bound<InlineArray> =
-            map_bound(range.end_bound(), |b| InlineArray::from(b.as_ref()));
+        self.update_inner(key.as_ref(), f, Some(policy))
+            .map(|(previous, _)| previous)
+    }
 
-        let next_fetch = Some(match &start {
-            Bound::Included(b) | Bound::Excluded(b) => b.clone(),
-            Bound::Unbounded => InlineArray::MIN,
+    /// Like [`Tree::update_and_fetch`], but waits according to `backoff`
+    /// after every conflicting compare and swap instead of retrying
+    /// immediately, and gives up after `max_retries` conflicts.
+    ///
+    /// `compute` is called with the latest observed value before every
+    /// attempt. Returning `None` removes the key. The value that was
+    /// installed is returned.
+    ///
+    /// If the value kept changing underneath, an error of kind
+    /// `io::ErrorKind::ResourceBusy` holding a [`ContentionExceeded`] is
+    /// returned and the key is left as other threads wrote it. This is
+    /// [`Tree::update_and_fetch_with_retry`] with the policy spelled out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::Backoff;
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    ///
+    /// let increment = |old: Option<&[u8]>| {
+    ///     let number = old.map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()));
+    ///     Some((number + 1).to_be_bytes().to_vec())
+    /// };
+    ///
+    /// let new = db.compare_and_swap_retry("counter", increment, 100, Backoff::default())?;
+    /// assert_eq!(new.unwrap(), 1_u64.to_be_bytes());
+    /// # Ok(()) }
+    /// ```
+    pub fn compare_and_swap_retry<K, V, F>(
+        &self,
+        key: K,
+        compute: F,
+        max_retries: usize,
+        backoff: Backoff,
+    ) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        self.update_and_fetch_with_retry(
+            key,
+            compute,
+            RetryPolicy { max_retries, backoff },
+        )
+    }
+
+    pub fn iter(&self) -> Iter<LEAF_FANOUT> {
+        self.iter_with(self.default_iter_options())
+    }
+
+    /// The options used by `iter`, `range` and `scan_prefix`.
+    fn default_iter_options(&self) -> IterOptions {
+        IterOptions {
+            read_ahead_leaves: self.cache.config.read_ahead_leaves,
+            ..IterOptions::default()
+        }
+    }
+
+    /// Like [`Tree::iter`], with control over how the visited leaves
+    /// interact with the cache. Use [`CachePolicy::BypassCache`] or
+    /// [`CachePolicy::ReadDontPromote`] for full scans that should not
+    /// displace the hot working set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::{CachePolicy, IterOptions};
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(&[1], vec![10])?;
+    /// db.insert(&[2], vec![20])?;
+    ///
+    /// let options = IterOptions {
+    ///     cache_policy: CachePolicy::BypassCache,
+    ///     ..IterOptions::default()
+    /// };
+    /// assert_eq!(db.iter_with(options).count(), 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter_with(&self, options: IterOptions) -> Iter<LEAF_FANOUT> {
+        Iter {
+            cache_policy: options.cache_policy,
+            read_ahead: ReadAhead::new(
+                options.read_ahead_leaves,
+                options.cache_policy,
+            ),
+            prefetched: VecDeque::new(),
+            prefetched_back: VecDeque::new(),
+            next_fetch: Some(InlineArray::MIN),
+            next_back_last_lo: None,
+            next_calls: 0,
+            next_back_calls: 0,
+            clears_seen: self.clears.load(Ordering::SeqCst),
+            read_leaf: false,
+            interrupted: false,
+            keys_only: false,
+            inner: self.clone(),
+            bounds: (Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+
+    /// Iterate over a consistent point-in-time view of the tree, as it was
+    /// when this method was called. Inserts, removes and batches applied
+    /// concurrently, including by the iterating thread itself, are not
+    /// observed by the returned iterator.
+    ///
+    /// Writers preserve the pre-snapshot contents of every leaf they modify
+    /// ahead of the iterator's position before applying their change. This
+    /// means that while a snapshot iterator is alive, the first write to each
+    /// not-yet-visited leaf copies that leaf's entries, and the copies are
+    /// held in memory until the iterator passes them. A snapshot that is kept
+    /// alive without being advanced can therefore grow up to the size of the
+    /// tree under a heavy write load, so drop it as soon as it is no longer
+    /// needed. Flushes are not delayed by snapshots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(&[1], vec![10])?;
+    /// db.insert(&[2], vec![20])?;
+    ///
+    /// let mut snapshot = db.snapshot_iter();
+    ///
+    /// db.remove(&[1])?;
+    /// db.insert(&[3], vec![30])?;
+    ///
+    /// let (k, v) = snapshot.next().unwrap()?;
+    /// assert_eq!((&*k, &*v), (&[1][..], &[10][..]));
+    /// assert_eq!(snapshot.count(), 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn snapshot_iter(&self) -> SnapshotIter<LEAF_FANOUT> {
+        SnapshotIter {
+            state: self.snapshots.register(),
+            inner: self.clone(),
+            next_fetch: Some(InlineArray::MIN),
+            end: None,
+            prefetched: VecDeque::new(),
+        }
+    }
+
+    /// Like [`Tree::snapshot_iter`], restricted to the keys that start
+    /// with `prefix`.
+    ///
+    /// Writers only preserve entries that fall within the prefix, so
+    /// the cost of keeping this iterator alive is bounded by the writes
+    /// to the scanned range. The iterator holds no guards or locks
+    /// between calls to `next`, which makes it suitable for scans that
+    /// are consumed in chunks interleaved with other work.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"a:1", vec![1])?;
+    /// db.insert(b"b:1", vec![1])?;
+    ///
+    /// let mut snapshot = db.snapshot_scan_prefix(b"a:");
+    /// db.insert(b"a:2", vec![2])?;
+    ///
+    /// let (k, _v) = snapshot.next().unwrap()?;
+    /// assert_eq!(&*k, b"a:1");
+    /// assert!(snapshot.next().is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn snapshot_scan_prefix<P: AsRef<[u8]>>(
+        &self,
+        prefix: P,
+    ) -> SnapshotIter<LEAF_FANOUT> {
+        let lo = InlineArray::from(prefix.as_ref());
+        let mut upper = prefix.as_ref().to_vec();
+        let mut end = None;
+
+        while let Some(last) = upper.pop() {
+            if last < u8::MAX {
+                upper.push(last + 1);
+                end = Some(InlineArray::from(&*upper));
+                break;
+            }
+        }
+
+        SnapshotIter {
+            state: self.snapshots.register_range(lo.clone(), end.clone()),
+            inner: self.clone(),
+            next_fetch: Some(lo),
+            end,
+            prefetched: VecDeque::new(),
+        }
+    }
+
+    pub fn range<K, R>(&self, range: R) -> Iter<LEAF_FANOUT>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.range_with(range, self.default_iter_options())
+    }
+
+    /// Like [`Tree::range`], with control over how the visited leaves
+    /// interact with the cache.
+    pub fn range_with<K, R>(
+        &self,
+        range: R,
+        options: IterOptions,
+    ) -> Iter<LEAF_FANOUT>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let start: Bound<InlineArray> =
+            map_bound(range.start_bound(), |b| InlineArray::from(b.as_ref()));
+        let end: Bound<InlineArray> =
+            map_bound(range.end_bound(), |b| InlineArray::from(b.as_ref()));
+
+        let next_fetch = Some(match &start {
+            Bound::Included(b) | Bound::Excluded(b) => b.clone(),
+            Bound::Unbounded => InlineArray::MIN,
         });
 
         Iter {
+            cache_policy: options.cache_policy,
+            read_ahead: ReadAhead::new(
+                options.read_ahead_leaves,
+                options.cache_policy,
+            ),
             prefetched: VecDeque::new(),
             prefetched_back: VecDeque::new(),
             next_fetch,
             next_back_last_lo: None,
             next_calls: 0,
             next_back_calls: 0,
+            clears_seen: self.clears.load(Ordering::SeqCst),
+            read_leaf: false,
+            interrupted: false,
+            keys_only: false,
             inner: self.clone(),
             bounds: (start, end),
         }
@@ -1294,6 +3253,11 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// visible, unless later concurrent updates changed the values
     /// before the flush.
     ///
+    /// If the batch has guards (see [`Batch::guard`]) and any of them does
+    /// not hold, nothing is applied and an error is returned from which
+    /// the first violated guard can be retrieved with
+    /// [`BatchGuardError::from_io_error`].
+    ///
     /// # Examples
     ///
     /// ```
@@ -1316,6 +3280,410 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # Ok(()) }
     /// ```
     pub fn apply_batch(&self, batch: Batch) -> io::Result<()> {
+        self.apply_batch_inner(batch, false).map(|_| ())
+    }
+
+    /// Like [`Tree::apply_batch`], but also returns the value each written
+    /// key had before the batch was applied, in key order. A previous value
+    /// of `None` means the key was created by an insert (or was already
+    /// absent for a removal).
+    ///
+    /// This is opt-in because every written key and previous value is
+    /// cloned into the returned `Vec`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert("key_a", "old_a")?;
+    ///
+    /// let mut batch = melange_db::Batch::default();
+    /// batch.insert("key_b", "val_b");
+    /// batch.insert("key_a", "val_a");
+    ///
+    /// let previous = db.apply_batch_returning(batch)?;
+    /// assert_eq!(previous.len(), 2);
+    /// assert_eq!(&*previous[0].0, b"key_a");
+    /// assert_eq!(previous[0].1.as_deref(), Some(&b"old_a"[..]));
+    /// assert_eq!(&*previous[1].0, b"key_b");
+    /// assert_eq!(previous[1].1, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn apply_batch_returning(
+        &self,
+        batch: Batch,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        self.apply_batch_inner(batch, true)
+    }
+
+    /// Applies `f` to every entry in `range`, in key order, and writes
+    /// the value it returns, removing the entry when it returns `None`.
+    /// Returns the number of entries that were changed.
+    ///
+    /// Every leaf overlapping the range is locked before `f` is first
+    /// called and stays locked until every write is applied, so `f` sees
+    /// each entry exactly once, no concurrent write to the range can
+    /// interleave with this one, and readers see either none or all of
+    /// the changes. The changes are made in a single flush epoch, so a
+    /// crash recovers either all of them or none.
+    ///
+    /// `f` is always called with `Some` value, since only existing
+    /// entries are visited. It runs while the leaves are locked, so it
+    /// must not access this tree. Against a `TreeQuota`, the whole call
+    /// counts as one write.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert("stock/apple", 3_u64.to_be_bytes())?;
+    /// db.insert("stock/pear", 1_u64.to_be_bytes())?;
+    /// db.insert("total", 4_u64.to_be_bytes())?;
+    ///
+    /// // decrement every count under "stock/", removing the ones at zero
+    /// let changed = db.update_range("stock/".."stock0", |_key, value| {
+    ///     let count = u64::from_be_bytes(value?.try_into().unwrap());
+    ///     (count > 1).then(|| (count - 1).to_be_bytes().to_vec())
+    /// })?;
+    ///
+    /// assert_eq!(changed, 2);
+    /// assert_eq!(db.get("stock/apple")?.unwrap(), 2_u64.to_be_bytes());
+    /// assert_eq!(db.get("stock/pear")?, None);
+    /// assert_eq!(db.get("total")?.unwrap(), 4_u64.to_be_bytes());
+    /// # Ok(()) }
+    /// ```
+    pub fn update_range<K, R, V, F>(&self, range: R, mut f: F) -> io::Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+        F: FnMut(&[u8], Option<&[u8]>) -> Option<V>,
+        V: Into<InlineArray>,
+    {
+        self.check_error()?;
+        self.cache.check_writable()?;
+        self.check_quota(1)?;
+
+        let start: Bound<InlineArray> =
+            map_bound(range.start_bound(), |b| InlineArray::from(b.as_ref()));
+        let end: Bound<InlineArray> =
+            map_bound(range.end_bound(), |b| InlineArray::from(b.as_ref()));
+
+        // NB: leaves are locked in key order, like batches do
+        let mut acquired_locks = BTreeMap::new();
+        let mut next_key = Some(match &start {
+            Bound::Included(b) | Bound::Excluded(b) => b.clone(),
+            Bound::Unbounded => InlineArray::MIN,
+        });
+        while let Some(key) = next_key.take() {
+            let (low_key, write, node) =
+                self.page_in(&key, self.cache.current_flush_epoch())?;
+            next_key = write.leaf.as_ref().unwrap().hi.clone().filter(|hi| match &end {
+                Bound::Included(end) => hi <= end,
+                Bound::Excluded(end) => hi < end,
+                Bound::Unbounded => true,
+            });
+            acquired_locks.insert(low_key, (write, node));
+        }
+
+        let bounds = (start, end);
+        let mut writes = BTreeMap::new();
+        for (write, _node) in acquired_locks.values() {
+            let leaf = write.leaf.as_ref().unwrap();
+            for (key, stored) in leaf.iter_stored() {
+                if !bounds.contains(&key) {
+                    continue;
+                }
+                let value = self.cache.blobs().load(&stored)?;
+                let new_value = f(&key, Some(&value)).map(Into::into);
+                if new_value.as_ref() != Some(&value) {
+                    writes.insert(key, new_value);
+                }
+            }
+        }
+
+        if writes.is_empty() {
+            return Ok(0);
+        }
+
+        // like batches, a write-once tree rejects the whole update
+        if self.enforces_write_once() {
+            let (key, value) = writes.first_key_value().unwrap();
+            return Err(match value {
+                Some(_) => KeyAlreadyExists { key: key.clone() }.into(),
+                None => write_once_removal_error("update_range"),
+            });
+        }
+
+        for (write, _node) in acquired_locks.values() {
+            self.snapshots.preserve(write.leaf.as_ref().unwrap(), self.cache.blobs())?;
+        }
+
+        let changed = writes.len();
+        let bytes = writes
+            .iter()
+            .map(|(key, value)| key.len() + value.as_ref().map_or(0, |value| value.len()))
+            .sum();
+
+        let locked = LockedBatch { writes, spill: None, acquired_locks };
+
+        // NB: the flush epoch is checked into after every lock is held,
+        // as in `apply_batch_locked`
+        let flush_epoch_guard = self.cache.check_into_flush_epoch();
+        let new_epoch = flush_epoch_guard.epoch();
+
+        let (_, cache_accesses) = self.apply_locked_batch(locked, new_epoch, false)?;
+
+        for (object_id, size) in cache_accesses {
+            self.cache.mark_access_and_evict(object_id, size, new_epoch)?;
+        }
+        drop(flush_epoch_guard);
+
+        self.record_quota_write(bytes)?;
+
+        Ok(changed)
+    }
+
+    fn apply_batch_inner(
+        &self,
+        batch: Batch,
+        return_previous: bool,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        let (ops, bytes) = batch.quota_cost();
+        self.check_quota(ops)?;
+        let ret = self.apply_batch_locked(batch, return_previous)?;
+        self.record_quota_write(bytes)?;
+        Ok(ret)
+    }
+
+    fn apply_batch_locked(
+        &self,
+        batch: Batch,
+        return_previous: bool,
+    ) -> io::Result<Vec<(InlineArray, Option<InlineArray>)>> {
+        // an empty batch locks no leaves
+        self.check_dropped()?;
+        self.cache.check_writable()?;
+        let locked = self.lock_batch(batch)?;
+
+        // NB: add the flush epoch at the end of the lock acquisition
+        // process when all locks have been acquired, to avoid situations
+        // where a leaf is already dirty with an epoch "from the future".
+        let flush_epoch_guard = self.cache.check_into_flush_epoch();
+        let new_epoch = flush_epoch_guard.epoch();
+
+        let (previous_values, cache_accesses) =
+            self.apply_locked_batch(locked, new_epoch, return_previous)?;
+
+        // Perform cache maintenance
+        for (object_id, size) in cache_accesses {
+            self.cache.mark_access_and_evict(object_id, size, new_epoch)?;
+        }
+
+        Ok(previous_values)
+    }
+
+    /// Applies batches to several trees of the same database as this one
+    /// atomically.
+    /// The leaves of every tree are locked and every guard is checked
+    /// before anything is written, and all writes are made in the same
+    /// flush epoch, so a crash recovers either all of them or none.
+    pub(crate) fn apply_multi_tree_batch(
+        &self,
+        mut batches: Vec<(&Tree<LEAF_FANOUT>, Batch)>,
+    ) -> io::Result<()> {
+        let cache = &self.cache;
+        cache.check_writable()?;
+
+        // trees are locked in collection order so that concurrent
+        // multi-tree batches can not deadlock
+        batches.sort_by_key(|(tree, _)| tree.collection_id);
+        for pair in batches.windows(2) {
+            if pair[0].0.collection_id == pair[1].0.collection_id {
+                return Err(annotate!(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a multi-tree batch may contain only one batch per tree",
+                )));
+            }
+        }
+        if batches.iter().any(|(tree, _)| !tree.cache.is_same_cache(cache)) {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "all trees of a multi-tree batch must belong to the same database",
+            )));
+        }
+
+        for (tree, batch) in &batches {
+            tree.check_quota(batch.quota_cost().0)?;
+        }
+
+        let mut locked = Vec::with_capacity(batches.len());
+        for (tree, batch) in batches {
+            let bytes = batch.quota_cost().1;
+            locked.push((tree, bytes, tree.lock_batch(batch)?));
+        }
+
+        let flush_epoch_guard = cache.check_into_flush_epoch();
+        let new_epoch = flush_epoch_guard.epoch();
+
+        let mut applied = Vec::with_capacity(locked.len());
+        let mut apply_error = None;
+        for (tree, bytes, locked_batch) in locked {
+            // A failure here is a fatal I/O error that already set the
+            // global error, but the remaining trees still have to be
+            // unlocked, which dropping their locked batches does.
+            if apply_error.is_some() {
+                continue;
+            }
+            match tree.apply_locked_batch(locked_batch, new_epoch, false) {
+                Ok((_, cache_accesses)) => applied.push((tree, bytes, cache_accesses)),
+                Err(e) => apply_error = Some(e),
+            }
+        }
+        if let Some(e) = apply_error {
+            return Err(e);
+        }
+
+        for (tree, bytes, cache_accesses) in applied {
+            for (object_id, size) in cache_accesses {
+                tree.cache.mark_access_and_evict(object_id, size, new_epoch)?;
+            }
+            tree.record_quota_write(bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the entries of this tree in `range` into `dst` of the same
+    /// database, `chunk_len` entries per batch. See `Db::copy_tree_range`.
+    pub(crate) fn copy_range_into<K, R>(
+        &self,
+        dst: &Tree<LEAF_FANOUT>,
+        range: R,
+        policy: ConflictPolicy,
+        chunk_len: usize,
+    ) -> io::Result<CopyStats>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.check_error()?;
+        dst.cache.check_writable()?;
+        if !dst.cache.is_same_cache(&self.cache) {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the source and destination of a copy must belong to the same database",
+            )));
+        }
+        if dst.collection_id == self.collection_id {
+            return Err(annotate!(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a tree can not be copied into itself",
+            )));
+        }
+
+        let mut stats = CopyStats::default();
+        let mut chunk = Vec::with_capacity(chunk_len);
+        let mut iter = self.range(range);
+        loop {
+            chunk.clear();
+            for kv_res in iter.by_ref().take(chunk_len) {
+                chunk.push(kv_res?);
+            }
+            let Some((last_key, _)) = chunk.last() else {
+                return Ok(stats);
+            };
+            dst.copy_chunk(&chunk, policy, &mut stats)?;
+            stats.last_copied_key = Some(last_key.clone());
+        }
+    }
+
+    /// Writes one sorted chunk of a `copy_range_into` into this tree.
+    /// Every write is guarded on the value that the policy decision was
+    /// based on, and the chunk is retried if a concurrent write changed
+    /// one of them.
+    fn copy_chunk(
+        &self,
+        chunk: &[(InlineArray, InlineArray)],
+        policy: ConflictPolicy,
+        stats: &mut CopyStats,
+    ) -> io::Result<()> {
+        let first = &*chunk[0].0;
+        let last = &*chunk[chunk.len() - 1].0;
+
+        // When nothing in this tree falls between the first and last key,
+        // there is nothing to look up and the whole chunk goes in as is.
+        if self.range::<&[u8], _>(first..=last).next().transpose()?.is_none() {
+            let mut batch = Batch::default();
+            for (key, value) in chunk {
+                batch.guard(key.clone(), None::<&[u8]>);
+                batch.insert(key.clone(), value.clone());
+            }
+            match self.apply_batch(batch) {
+                Ok(()) => {
+                    stats.copied += chunk.len();
+                    return Ok(());
+                }
+                Err(e) if BatchGuardError::from_io_error(&e).is_some() => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut retries = self.retries(None);
+        loop {
+            let keys: Vec<&InlineArray> = chunk.iter().map(|(key, _)| key).collect();
+            let current = self.get_many(&keys)?;
+
+            let mut batch = Batch::default();
+            let (mut copied, mut overwritten, mut skipped) = (0, 0, 0);
+            let mut conflict = None;
+            for ((key, value), current) in chunk.iter().zip(current) {
+                match (current, policy) {
+                    (None, _) => batch.guard(key.clone(), None::<&[u8]>),
+                    (Some(_), ConflictPolicy::Skip) => {
+                        skipped += 1;
+                        continue;
+                    }
+                    (Some(current), ConflictPolicy::Overwrite) => {
+                        batch.guard(key.clone(), Some(current));
+                        overwritten += 1;
+                    }
+                    (Some(_), ConflictPolicy::Error) => {
+                        conflict = Some(key.clone());
+                        break;
+                    }
+                }
+                batch.insert(key.clone(), value.clone());
+                copied += 1;
+            }
+
+            match self.apply_batch(batch) {
+                Ok(()) => {}
+                Err(e) => match BatchGuardError::from_io_error(&e) {
+                    Some(guard) => {
+                        retries.conflict(&guard.key)?;
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+            }
+
+            stats.copied += copied;
+            stats.overwritten += overwritten;
+            stats.skipped += skipped;
+            return match conflict {
+                Some(key) => Err(KeyAlreadyExists { key }.into()),
+                None => Ok(()),
+            };
+        }
+    }
+
+    /// Locks the leaves that `batch` writes or guards, then checks its
+    /// guards and write-once constraints without modifying anything.
+    fn lock_batch(&self, mut batch: Batch) -> io::Result<LockedBatch<LEAF_FANOUT>> {
         // NB: we rely on lexicographic lock acquisition
         // by iterating over the batch's BTreeMap to avoid
         // deadlocks during 2PL
@@ -1334,7 +3702,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             Object<LEAF_FANOUT>,
         )> = None;
 
-        for key in batch.writes.keys() {
+        let mut lock_key = |key: &InlineArray| -> io::Result<()> {
             if let Some((_lo, w, _id)) = &last {
                 let leaf = w.leaf.as_ref().unwrap();
                 assert!(&leaf.lo <= key);
@@ -1352,17 +3720,106 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
                 last =
                     Some(self.page_in(key, self.cache.current_flush_epoch())?);
             }
+            Ok(())
+        };
+
+        // guarded keys need their leaves locked as well, so lock the union
+        // of written and guarded keys in order
+        let spill = batch.spill.take().filter(|spill| !spill.runs.is_empty());
+        if let Some(spill) = &spill {
+            // spilled writes are streamed in key order from disk, which also
+            // verifies every spill file before anything is modified
+            let mut guarded_keys = batch.guards.keys().peekable();
+            for write_res in spill.merged(batch.writes.clone())? {
+                let (key, _) = write_res?;
+                while let Some(guarded) = guarded_keys.next_if(|g| **g < key) {
+                    lock_key(guarded)?;
+                }
+                lock_key(&key)?;
+            }
+            for guarded in guarded_keys {
+                lock_key(guarded)?;
+            }
+        } else {
+            let locked_keys: std::collections::BTreeSet<&InlineArray> =
+                batch.writes.keys().chain(batch.guards.keys()).collect();
+
+            for key in locked_keys {
+                lock_key(key)?;
+            }
         }
 
         if let Some((lo, w, id)) = last.take() {
             acquired_locks.insert(lo, (w, id));
         }
 
-        // NB: add the flush epoch at the end of the lock acquisition
-        // process when all locks have been acquired, to avoid situations
-        // where a leaf is already dirty with an epoch "from the future".
-        let flush_epoch_guard = self.cache.check_into_flush_epoch();
-        let new_epoch = flush_epoch_guard.epoch();
+        // Validate guards before anything is modified. Returning here
+        // releases every lock without having applied any write.
+        for (key, expected) in &batch.guards {
+            let (_lo, (w, _object)) = acquired_locks
+                .range::<InlineArray, _>(..=key)
+                .next_back()
+                .unwrap();
+            let leaf = w.leaf.as_ref().unwrap();
+            let current = leaf.get(key, self.cache.blobs())?;
+
+            if &current != expected {
+                return Err(io::Error::other(BatchGuardError {
+                    key: key.clone(),
+                    expected: expected.clone(),
+                    current,
+                }));
+            }
+        }
+
+        // A write-once tree rejects the whole batch if any write would
+        // overwrite or remove a key, again before anything is modified.
+        if self.enforces_write_once() {
+            let check_write =
+                |key: &InlineArray, value: &Option<InlineArray>| -> io::Result<()> {
+                    if value.is_none() {
+                        return Err(write_once_removal_error("Batch::remove"));
+                    }
+                    let (_lo, (w, _object)) = acquired_locks
+                        .range::<InlineArray, _>(..=key)
+                        .next_back()
+                        .unwrap();
+                    if w.leaf.as_ref().unwrap().contains_key(key) {
+                        return Err(KeyAlreadyExists { key: key.clone() }.into());
+                    }
+                    Ok(())
+                };
+
+            if let Some(spill) = &spill {
+                for write_res in spill.merged(batch.writes.clone())? {
+                    let (key, value) = write_res?;
+                    check_write(&key, &value)?;
+                }
+            } else {
+                for (key, value) in &batch.writes {
+                    check_write(key, value)?;
+                }
+            }
+        }
+
+        for (write, _node) in acquired_locks.values() {
+            self.snapshots.preserve(write.leaf.as_ref().unwrap(), self.cache.blobs())?;
+        }
+
+        Ok(LockedBatch { writes: batch.writes, spill, acquired_locks })
+    }
+
+    /// Applies a batch locked by `Tree::lock_batch` in `new_epoch`, which
+    /// must have been checked into after the locks were acquired. Returns
+    /// the previous values if requested and the cache accesses that the
+    /// caller performs once the locks are released.
+    fn apply_locked_batch(
+        &self,
+        locked: LockedBatch<LEAF_FANOUT>,
+        new_epoch: FlushEpoch,
+        return_previous: bool,
+    ) -> io::Result<(PreviousValues, CacheAccesses)> {
+        let LockedBatch { writes, spill, mut acquired_locks } = locked;
 
         // Flush any leaves that are dirty from a previous flush epoch
         // before performing operations.
@@ -1391,8 +3848,34 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         let mut merges: BTreeMap<InlineArray, Object<LEAF_FANOUT>> =
             BTreeMap::new();
 
+        let mut previous_values = if return_previous {
+            Vec::with_capacity(writes.len())
+        } else {
+            vec![]
+        };
+
+        let writes: Box<dyn Iterator<Item = io::Result<_>>> = match &spill {
+            Some(spill) => Box::new(spill.merged(writes)?),
+            None => Box::new(writes.into_iter().map(Ok)),
+        };
+
+        // The spill files were fully read and verified while acquiring
+        // locks, so failing to read them again is a fatal I/O error, as is
+        // failing to read or write a blob once some writes are applied.
+        // Stop applying writes but keep the tree structurally consistent.
+        let mut apply_error = None;
+
         // Insert and split when full
-        for (key, value_opt) in batch.writes {
+        for write_res in writes {
+            let (key, value_opt) = match write_res {
+                Ok(write) => write,
+                Err(e) => {
+                    error_log!("failed to re-read spilled batch: {:?}", e);
+                    apply_error = Some(e);
+                    break;
+                }
+            };
+
             let range = ..=&key;
             let (lo, (w, object)) = acquired_locks
                 .range_mut::<InlineArray, _>(range)
@@ -1406,30 +3889,48 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
                 assert!(hi > &key);
             }
 
-            if let Some(value) = value_opt {
-                leaf.insert(key, value);
+            let returned_key = return_previous.then(|| key.clone());
+
+            match &value_opt {
+                Some(value) => self.journal(OpKind::BatchInsert, &key, Some(value)),
+                None => self.journal(OpKind::BatchRemove, &key, None),
+            }
+
+            let is_insert = value_opt.is_some();
+            let applied = match value_opt {
+                Some(value) => leaf.insert(key.clone(), value, new_epoch, self.cache.blobs()),
+                None => leaf.remove(&key, self.cache.blobs()),
+            };
+            let previous = match applied {
+                Ok(previous) => previous,
+                Err(e) => {
+                    error_log!("failed to apply batch write to {:?}: {:?}", key, e);
+                    apply_error = Some(e);
+                    break;
+                }
+            };
+
+            if is_insert {
                 merges.remove(lo);
 
                 merges.remove(&leaf.lo);
 
-                if let Some((split_key, rhs_node)) = leaf.split_if_full(
-                    new_epoch,
-                    &self.cache,
-                    self.collection_id,
-                ) {
+                if let Some((split_key, rhs_node)) =
+                    self.split_leaf_if_full(leaf, new_epoch)
+                {
                                         let write = rhs_node.inner.write_arc();
                     assert!(write.leaf.is_some());
 
                     splits.push((split_key.clone(), rhs_node.clone()));
                     acquired_locks.insert(split_key, (write, rhs_node));
                 }
-            } else {
-                leaf.remove(&key);
+            } else if leaf.is_empty() {
+                assert_eq!(leaf.lo, lo);
+                merges.insert(leaf.lo.clone(), object.clone());
+            }
 
-                if leaf.is_empty() {
-                    assert_eq!(leaf.lo, lo);
-                    merges.insert(leaf.lo.clone(), object.clone());
-                }
+            if let Some(key) = returned_key {
+                previous_values.push((key, previous));
             }
         }
 
@@ -1461,20 +3962,23 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             
         }
 
+        if let Some(e) = apply_error {
+            self.set_error(&e);
+            return Err(e);
+        }
+
         // Drop locks
         drop(acquired_locks);
 
-        // Perform cache maintenance
-        for (object_id, size) in cache_accesses {
-            self.cache.mark_access_and_evict(object_id, size, new_epoch)?;
-        }
-
-        Ok(())
+        Ok((previous_values, cache_accesses))
     }
 
     /// Returns `true` if the `Tree` contains a value for
     /// the specified key.
     ///
+    /// Only the keys of the leaf are searched: the value is neither
+    /// copied nor, when stored out of line, read from disk.
+    ///
     /// # Examples
     ///
     /// ```
@@ -1487,7 +3991,87 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # Ok(()) }
     /// ```
     pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> io::Result<bool> {
-        self.get(key).map(|v| v.is_some())
+        self.check_error()?;
+
+        let key_ref = key.as_ref();
+
+        let bloom_contains = self.cache.bloom_filter_contains(key_ref);
+
+        // only the leaf's key index is searched, the value is never read
+        let read_leaf =
+            self.leaf_for_key_with_policy(key_ref, CachePolicy::Normal)?;
+        let contains = read_leaf.leaf().contains_key(key_ref);
+        drop(read_leaf);
+
+        self.bloom_counters.record(bloom_contains, contains);
+
+        Ok(contains)
+    }
+
+    /// Returns a handle that only sees the keys starting with `prefix`.
+    /// Keys passed to the handle are relative to the prefix, which is
+    /// prepended on writes and stripped from returned keys, and its
+    /// iterators never leave the prefix. See [`ScopedTree`].
+    pub fn scoped<P: AsRef<[u8]>>(&self, prefix: P) -> ScopedTree<LEAF_FANOUT> {
+        ScopedTree::new(self.clone(), prefix.as_ref())
+    }
+
+    /// Returns a single-threaded handle that buffers writes and applies
+    /// them as batches once `TreeWriterOptions::default()` thresholds are
+    /// reached, so that consecutive writes to the same leaf share one
+    /// lookup and lock acquisition. Reads through the handle see its
+    /// buffered writes, other handles only see them once they are applied.
+    /// See [`TreeWriter`].
+    pub fn writer(&self) -> TreeWriter<LEAF_FANOUT> {
+        self.writer_with(TreeWriterOptions::default())
+    }
+
+    /// Like [`Tree::writer`], with the given buffering thresholds.
+    pub fn writer_with(&self, options: TreeWriterOptions) -> TreeWriter<LEAF_FANOUT> {
+        TreeWriter::new(self.clone(), options)
+    }
+
+    /// Returns `true` if the `Tree` contains any key that starts with
+    /// `prefix`.
+    ///
+    /// Unlike `scan_prefix(prefix).next().is_some()`, this only visits the
+    /// leaves that could hold the first matching key and returns as soon as
+    /// one is found, without cloning any keys or values. The bloom filter
+    /// is not consulted because it only holds complete keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"user:1", vec![1])?;
+    /// assert!(db.contains_prefix(b"user:")?);
+    /// assert!(!db.contains_prefix(b"order:")?);
+    /// # Ok(()) }
+    /// ```
+    pub fn contains_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> io::Result<bool> {
+        self.check_error()?;
+
+        let prefix = prefix.as_ref();
+        let mut search_key = InlineArray::from(prefix);
+
+        loop {
+            let read_leaf =
+                self.leaf_for_key_with_policy(&search_key, CachePolicy::Normal)?;
+            let leaf = read_leaf.leaf();
+
+            if leaf.contains_prefix(prefix) {
+                return Ok(true);
+            }
+
+            // Only the next leaf can hold a matching key, and only if its
+            // low key still starts with the prefix.
+            match &leaf.hi {
+                Some(hi) if hi.starts_with(prefix) => search_key = hi.clone(),
+                _ => return Ok(false),
+            }
+        }
     }
 
     /// Retrieve the key and value before the provided key,
@@ -1600,6 +4184,140 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             .transpose()
     }
 
+    /// Retrieve the entry with the smallest key that is greater than or
+    /// equal to the provided key.
+    ///
+    /// Unlike `range(key..).next()`, no iterator is created: the leaf
+    /// responsible for the key is searched directly, and only when it holds
+    /// no such key are the following leaves visited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::InlineArray;
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// for i in (0..10).step_by(2) {
+    ///     db.insert(&[i], vec![i])?;
+    /// }
+    ///
+    /// assert_eq!(
+    ///     db.ceiling(&[4])?,
+    ///     Some((InlineArray::from(&[4]), InlineArray::from(&[4])))
+    /// );
+    /// assert_eq!(
+    ///     db.ceiling(&[5])?,
+    ///     Some((InlineArray::from(&[6]), InlineArray::from(&[6])))
+    /// );
+    /// assert!(db.ceiling(&[9])?.is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn ceiling<K>(
+        &self,
+        key: K,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.check_error()?;
+
+        let key = key.as_ref();
+        let mut search_key = InlineArray::from(key);
+
+        loop {
+            let node = self
+                .leaf_for_key_with_policy(&search_key, CachePolicy::default())?;
+            let leaf = node.leaf();
+
+            if let Some((k, stored)) = leaf.ceiling_stored(key) {
+                let v = self.cache.blobs().load(&stored)?;
+                return Ok(Some((k, v)));
+            }
+
+            // nothing at or above the key in this leaf, so the answer is
+            // the first entry of a following leaf
+            match &leaf.hi {
+                Some(hi) => search_key = hi.clone(),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Retrieve the entry with the largest key that is less than or equal
+    /// to the provided key.
+    ///
+    /// Unlike `range(..=key).next_back()`, no iterator is created: the leaf
+    /// responsible for the key is searched directly, and only when it holds
+    /// no such key are the preceding leaves visited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use melange_db::InlineArray;
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// for i in (1..10).step_by(2) {
+    ///     db.insert(&[i], vec![i])?;
+    /// }
+    ///
+    /// assert_eq!(
+    ///     db.floor(&[5])?,
+    ///     Some((InlineArray::from(&[5]), InlineArray::from(&[5])))
+    /// );
+    /// assert_eq!(
+    ///     db.floor(&[4])?,
+    ///     Some((InlineArray::from(&[3]), InlineArray::from(&[3])))
+    /// );
+    /// assert!(db.floor(&[0])?.is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn floor<K>(
+        &self,
+        key: K,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.check_error()?;
+
+        let key = key.as_ref();
+        // the low key of the last leaf searched
+        let mut last_lo: Option<InlineArray> = None;
+
+        loop {
+            let search_key = match &last_lo {
+                None => InlineArray::from(key),
+                Some(lo) if lo == &InlineArray::MIN => return Ok(None),
+                Some(lo) => {
+                    self.index.range::<InlineArray, _>(..lo).next_back().unwrap().0
+                }
+            };
+
+            let node = self
+                .leaf_for_key_with_policy(&search_key, CachePolicy::default())?;
+            let leaf = node.leaf();
+
+            if let (Some(leaf_hi), Some(lo)) = (&leaf.hi, &last_lo)
+                && leaf_hi < lo
+            {
+                // the predecessor was split concurrently, retry
+                trace_log!("undershot in floor, retrying search");
+                continue;
+            }
+
+            if let Some((k, stored)) = leaf.floor_stored(key) {
+                let v = self.cache.blobs().load(&stored)?;
+                return Ok(Some((k, v)));
+            }
+
+            // nothing at or below the key in this leaf, so the answer is
+            // the last entry of a preceding leaf
+            last_lo = Some(leaf.lo.clone());
+        }
+    }
+
     /// Create an iterator over tuples of keys and values
     /// where all keys start with the given prefix.
     ///
@@ -1639,6 +4357,19 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # Ok(()) }
     /// ```
     pub fn scan_prefix<P>(&self, prefix: P) -> Iter<LEAF_FANOUT>
+    where
+        P: AsRef<[u8]>,
+    {
+        self.scan_prefix_with(prefix, self.default_iter_options())
+    }
+
+    /// Like [`Tree::scan_prefix`], with control over how the visited
+    /// leaves interact with the cache.
+    pub fn scan_prefix_with<P>(
+        &self,
+        prefix: P,
+        options: IterOptions,
+    ) -> Iter<LEAF_FANOUT>
     where
         P: AsRef<[u8]>,
     {
@@ -1648,11 +4379,311 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         while let Some(last) = upper.pop() {
             if last < u8::MAX {
                 upper.push(last + 1);
-                return self.range(prefix_ref..&upper);
+                return self.range_with(prefix_ref..&upper, options);
+            }
+        }
+
+        self.range_with(prefix.., options)
+    }
+
+    /// Scans `range` and collects the results of `f` for the entries it
+    /// accepts. See [`Tree::scan_filter_iter`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"order_1", vec![1, 250])?;
+    /// db.insert(b"order_2", vec![0, 10])?;
+    /// db.insert(b"order_3", vec![1, 99])?;
+    ///
+    /// // amounts of the orders whose first value byte is set
+    /// let amounts = db.scan_filter::<&[u8], _, _, _>(.., |_key, value| {
+    ///     (value[0] == 1).then(|| value[1])
+    /// })?;
+    /// assert_eq!(amounts, vec![250, 99]);
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_filter<K, B, F, R>(&self, range: B, f: F) -> io::Result<Vec<R>>
+    where
+        K: AsRef<[u8]>,
+        B: RangeBounds<K>,
+        F: FnMut(&[u8], &[u8]) -> Option<R>,
+    {
+        self.scan_filter_iter(range, f).collect()
+    }
+
+    /// Returns an iterator over the results of `f` for the entries in
+    /// `range` that it accepts, in key order.
+    ///
+    /// `f` is called with the key and value of each entry as slices
+    /// borrowed from the in-memory leaf while it is read-locked, before
+    /// anything is copied, so entries it rejects by returning `None` cost
+    /// no allocations. The borrows are only valid for the duration of the
+    /// call and cannot be kept in `R`.
+    ///
+    /// All entries of one leaf are passed to `f` at once while the leaf is
+    /// locked, so `f` should be cheap and must not write to this tree. A
+    /// panic in `f` releases the leaf and leaves the tree untouched.
+    pub fn scan_filter_iter<K, B, F, R>(
+        &self,
+        range: B,
+        f: F,
+    ) -> ScanFilter<LEAF_FANOUT, F, R>
+    where
+        K: AsRef<[u8]>,
+        B: RangeBounds<K>,
+        F: FnMut(&[u8], &[u8]) -> Option<R>,
+    {
+        let start: Bound<InlineArray> =
+            map_bound(range.start_bound(), |b| InlineArray::from(b.as_ref()));
+        let end: Bound<InlineArray> =
+            map_bound(range.end_bound(), |b| InlineArray::from(b.as_ref()));
+
+        let next_fetch = Some(match &start {
+            Bound::Included(b) | Bound::Excluded(b) => b.clone(),
+            Bound::Unbounded => InlineArray::MIN,
+        });
+
+        ScanFilter {
+            inner: self.clone(),
+            bounds: (start, end),
+            next_fetch,
+            key_buf: Vec::new(),
+            accepted: VecDeque::new(),
+            f,
+        }
+    }
+
+    /// Returns an iterator over the keys and values that start with
+    /// `prefix`, copied from the in-memory leaves into `arena` instead of
+    /// being allocated one by one.
+    ///
+    /// The returned slices borrow the arena and stay valid until it is
+    /// reset, so scanning many small entries costs a handful of chunk
+    /// allocations rather than two allocations per entry. Values stored
+    /// out of line are copied into the arena as well. See
+    /// [`Tree::scan_filter_iter`] for how the leaves are visited.
+    pub fn scan_prefix_in<'a, P>(
+        &self,
+        prefix: P,
+        arena: &'a ScanArena,
+    ) -> ArenaScan<'a, LEAF_FANOUT>
+    where
+        P: AsRef<[u8]>,
+    {
+        let prefix = prefix.as_ref();
+        let mut upper = prefix.to_vec();
+        while let Some(last) = upper.pop() {
+            if last < u8::MAX {
+                upper.push(last + 1);
+                break;
+            }
+        }
+        let end = if upper.is_empty() {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded(&upper[..])
+        };
+
+        let copy: ArenaCopy<'a> =
+            Box::new(move |k, v| Some((arena.alloc(k), arena.alloc(v))));
+        ArenaScan {
+            inner: self.scan_filter_iter::<&[u8], _, _, _>((Bound::Included(prefix), end), copy),
+        }
+    }
+
+    /// Collects every key and value that starts with `prefix`, decoding the
+    /// visited leaves in parallel on the rayon thread pool. Output is in key
+    /// order and equal to collecting [`Tree::scan_prefix`].
+    ///
+    /// Leaves are gathered on the calling thread while holding each leaf's
+    /// lock: cached leaves have their matching entries copied, and leaves
+    /// that are not cached are read from disk as raw bytes without being
+    /// inserted into the cache. Only the raw leaves are then decompressed
+    /// and deserialized in parallel, so this is only worthwhile for large
+    /// prefixes of large, compressible values that are mostly not cached.
+    /// For small scans the gathering overhead makes it slower than
+    /// `scan_prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(b"user_1", b"alice".as_slice())?;
+    /// db.insert(b"user_2", b"bob".as_slice())?;
+    /// db.insert(b"order_1", b"book".as_slice())?;
+    ///
+    /// let users = db.scan_prefix_par(b"user_")?;
+    /// assert_eq!(users.len(), 2);
+    /// assert_eq!(&*users[1].1, b"bob");
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefix_par<P>(
+        &self,
+        prefix: P,
+    ) -> io::Result<Vec<(InlineArray, InlineArray)>>
+    where
+        P: AsRef<[u8]>,
+    {
+        use rayon::prelude::*;
+
+        enum Gathered<const LEAF_FANOUT: usize> {
+            Cached {
+                lo: InlineArray,
+                hi: Option<InlineArray>,
+                entries: Vec<(InlineArray, InlineArray)>,
+            },
+            Raw {
+                low_key: InlineArray,
+                bytes: Vec<u8>,
+            },
+        }
+
+        self.check_error()?;
+
+        let prefix = prefix.as_ref();
+        let mut upper = prefix.to_vec();
+        while let Some(last) = upper.pop() {
+            if last < u8::MAX {
+                upper.push(last + 1);
+                break;
+            }
+        }
+        let upper: Option<InlineArray> =
+            if upper.is_empty() { None } else { Some(upper.into()) };
+
+        let Some((start, _)) = self.index.get_lte(prefix) else {
+            return Err(dropped_tree_error(self.collection_id));
+        };
+
+        let mut gathered: Vec<Gathered<LEAF_FANOUT>> = vec![];
+        for (low_key, node) in self.index.range(start..) {
+            if let Some(upper) = &upper
+                && low_key >= *upper
+            {
+                break;
+            }
+
+            let _heap_pin = self.cache.heap_object_id_pin();
+            let read = node.inner.read_arc();
+
+            if let Some(leaf) = &read.leaf {
+                self.cache.read_stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                gathered.push(Gathered::Cached {
+                    lo: leaf.lo.clone(),
+                    hi: leaf.hi.clone(),
+                    entries: leaf
+                        .iter_stored()
+                        .filter(|(k, _)| k.starts_with(prefix))
+                        .map(|(k, stored)| Ok((k, self.cache.blobs().load(&stored)?)))
+                        .collect::<io::Result<_>>()?,
+                });
+                continue;
+            }
+
+            // While we hold the read lock the leaf cannot be paged in and
+            // modified, so the copy on disk is current.
+            self.cache.read_stats.cache_misses.fetch_add(1, Ordering::Relaxed);
+            match self.cache.read(node.object_id) {
+                Some(Ok(bytes)) => {
+                    gathered.push(Gathered::Raw { low_key, bytes });
+                }
+                Some(Err(e)) if CorruptionError::from_io_error(&e).is_some() => {
+                    return Err(e);
+                }
+                Some(Err(e)) => return Err(annotate!(e)),
+                None => {
+                    trace_log!(
+                        "leaf freed concurrently in scan_prefix_par, falling back to serial scan"
+                    );
+                    return self.scan_prefix(prefix).collect();
+                }
+            }
+        }
+
+        type Decoded =
+            (InlineArray, Option<InlineArray>, Vec<(InlineArray, InlineArray)>);
+
+        let decoded: Vec<io::Result<Decoded>> =
+            gathered
+                .into_par_iter()
+                .map(|gathered| match gathered {
+                    Gathered::Cached { lo, hi, entries } => Ok((lo, hi, entries)),
+                    Gathered::Raw { low_key, bytes } => {
+                        let leaf = Leaf::<LEAF_FANOUT>::deserialize(
+                            &bytes,
+                            self.cache.dictionaries(),
+                            self.cache.blobs(),
+                        )?;
+                        // forces the chain check below to fail
+                        let changed = Ok((low_key.clone(), Some(InlineArray::MIN), vec![]));
+                        if leaf.deleted.is_some() || leaf.lo != low_key {
+                            return changed;
+                        }
+                        let mut entries = vec![];
+                        for (k, stored) in leaf.iter_stored() {
+                            if !k.starts_with(prefix) {
+                                continue;
+                            }
+                            match self.cache.blobs().load(&stored) {
+                                Ok(v) => entries.push((k, v)),
+                                // the value was overwritten and its blob
+                                // released after the leaf was read
+                                Err(e) if e.kind() == io::ErrorKind::NotFound => return changed,
+                                Err(e) => return Err(e),
+                            }
+                        }
+                        Ok((leaf.lo.clone(), leaf.hi.clone(), entries))
+                    }
+                })
+                .collect();
+
+        // The index and the leaves were read at different times, so a
+        // concurrent split or merge can leave gaps or overlaps between
+        // the gathered leaves. Verify that they form a contiguous chain
+        // covering the prefix, falling back to a serial scan otherwise.
+        let mut ret = vec![];
+        let mut expected_lo: Option<InlineArray> = None;
+        let mut last_hi: Option<InlineArray> = None;
+        let mut covered = false;
+        for leaf_res in decoded {
+            let (lo, hi, entries) = leaf_res?;
+
+            let contiguous = match &expected_lo {
+                Some(expected) => *expected == lo,
+                None => &*lo <= prefix,
+            };
+            if !contiguous || hi.as_ref().is_some_and(|hi| *hi <= lo) {
+                trace_log!(
+                    "concurrent structural change in scan_prefix_par, falling back to serial scan"
+                );
+                return self.scan_prefix(prefix).collect();
             }
+
+            ret.extend(entries);
+            expected_lo = hi.clone();
+            last_hi = hi;
+            covered = true;
+        }
+
+        let reached_end = match (&last_hi, &upper) {
+            (None, _) => covered,
+            (Some(hi), Some(upper)) => hi >= upper,
+            (Some(_), None) => false,
+        };
+        if !reached_end {
+            trace_log!(
+                "scan_prefix_par did not reach the end of the prefix, falling back to serial scan"
+            );
+            return self.scan_prefix(prefix).collect();
         }
 
-        self.range(prefix..)
+        Ok(ret)
     }
 
     /// Returns the first key and value in the `Tree`, or
@@ -1692,21 +4723,38 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # Ok(()) }
     /// ```
     pub fn pop_last(&self) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.check_write_once_removal("pop_last")?;
+        self.pop_last_inner(None)
+    }
+
+    /// Like [`Tree::pop_last`], with the retries after another thread
+    /// changed the last item bounded by `policy` as in
+    /// [`Tree::update_and_fetch_with_retry`].
+    pub fn pop_last_with_retry(
+        &self,
+        policy: RetryPolicy,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.check_write_once_removal("pop_last_with_retry")?;
+        self.pop_last_inner(Some(policy))
+    }
+
+    fn pop_last_inner(
+        &self,
+        policy: Option<RetryPolicy>,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        let mut retries = self.retries(policy);
         loop {
             if let Some(first_res) = self.iter().next_back() {
                 let first = first_res?;
                 if self
-                    .compare_and_swap::<_, _, &[u8]>(
-                        &first.0,
-                        Some(&first.1),
-                        None,
-                    )?
+                    .try_compare_and_swap(&first.0, Some(&first.1), None)?
                     .is_ok()
                 {
                     trace_log!("pop_last removed item {:?}", first);
                     return Ok(Some(first));
                 }
-            // try again
+                // try again
+                retries.conflict(&first.0)?;
             } else {
                 trace_log!("pop_last removed nothing from empty tree");
                 return Ok(None);
@@ -1765,6 +4813,8 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         K: AsRef<[u8]>,
         R: Clone + RangeBounds<K>,
     {
+        self.check_write_once_removal("pop_last_in_range")?;
+        let mut retries = self.retries(None);
         loop {
             let mut r = self.range(range.clone());
             let (k, v) = if let Some(kv_res) = r.next_back() {
@@ -1772,12 +4822,10 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             } else {
                 return Ok(None);
             };
-            if self
-                .compare_and_swap(&k, Some(&v), None as Option<InlineArray>)?
-                .is_ok()
-            {
+            if self.try_compare_and_swap(&k, Some(&v), None)?.is_ok() {
                 return Ok(Some((k, v)));
             }
+            retries.conflict(&k)?;
         }
     }
 
@@ -1806,21 +4854,38 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// # Ok(()) }
     /// ```
     pub fn pop_first(&self) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.check_write_once_removal("pop_first")?;
+        self.pop_first_inner(None)
+    }
+
+    /// Like [`Tree::pop_first`], with the retries after another thread
+    /// changed the first item bounded by `policy` as in
+    /// [`Tree::update_and_fetch_with_retry`].
+    pub fn pop_first_with_retry(
+        &self,
+        policy: RetryPolicy,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        self.check_write_once_removal("pop_first_with_retry")?;
+        self.pop_first_inner(Some(policy))
+    }
+
+    fn pop_first_inner(
+        &self,
+        policy: Option<RetryPolicy>,
+    ) -> io::Result<Option<(InlineArray, InlineArray)>> {
+        let mut retries = self.retries(policy);
         loop {
             if let Some(first_res) = self.iter().next() {
                 let first = first_res?;
                 if self
-                    .compare_and_swap::<_, _, &[u8]>(
-                        &first.0,
-                        Some(&first.1),
-                        None,
-                    )?
+                    .try_compare_and_swap(&first.0, Some(&first.1), None)?
                     .is_ok()
                 {
                     trace_log!("pop_first removed item {:?}", first);
                     return Ok(Some(first));
                 }
-            // try again
+                // try again
+                retries.conflict(&first.0)?;
             } else {
                 trace_log!("pop_first removed nothing from empty tree");
                 return Ok(None);
@@ -1874,6 +4939,8 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         K: AsRef<[u8]>,
         R: Clone + RangeBounds<K>,
     {
+        self.check_write_once_removal("pop_first_in_range")?;
+        let mut retries = self.retries(None);
         loop {
             let mut r = self.range(range.clone());
             let (k, v) = if let Some(kv_res) = r.next() {
@@ -1881,12 +4948,10 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
             } else {
                 return Ok(None);
             };
-            if self
-                .compare_and_swap(&k, Some(&v), None as Option<InlineArray>)?
-                .is_ok()
-            {
+            if self.try_compare_and_swap(&k, Some(&v), None)?.is_ok() {
                 return Ok(Some((k, v)));
             }
+            retries.conflict(&k)?;
         }
     }
 
@@ -1907,7 +4972,7 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
     /// ```
     pub fn len(&self) -> io::Result<usize> {
         let mut count = 0;
-        for item_res in self.iter() {
+        for item_res in self.iter().keys() {
             let _item = item_res?;
             count += 1;
         }
@@ -1929,17 +4994,162 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
 
     /// Clears the `Tree`, removing all values.
     ///
-    /// Note that this is not atomic.
+    /// This is atomic: every leaf is replaced by a single empty leaf in one
+    /// flush epoch, so after a crash the tree is recovered either with all of
+    /// its values or empty. Leaves are detached without being read, and the
+    /// space they occupy on disk is reclaimed by the next flush.
     ///
-    /// Beware: performs a full O(n) scan under the hood.
+    /// Iterators created by [`Tree::snapshot_iter`] before the clear still
+    /// observe every value. Other iterators that already read some values
+    /// before the clear return an error instead of continuing over the empty
+    /// tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// db.insert(&[1], vec![1])?;
+    /// db.insert(&[2], vec![2])?;
+    ///
+    /// db.clear()?;
+    ///
+    /// assert!(db.is_empty()?);
+    /// # Ok(()) }
+    /// ```
     pub fn clear(&self) -> io::Result<()> {
-        for k in self.iter().keys() {
-            let key = k?;
-            let _old = self.remove(key)?;
+        self.check_error()?;
+        self.cache.check_writable()?;
+        ClosurePin::check(self);
+        self.check_write_once_removal("clear")?;
+
+        let (nodes, mut acquired_locks) = self.lock_every_leaf()?;
+
+        // snapshots registered from here on observe the empty tree
+        let snapshots = self.snapshots.active_snapshots();
+
+        if !snapshots.is_empty() {
+            // preserved before anything is modified, since reading the
+            // values stored as blobs may fail
+            for (i, ((low_key, node), write)) in nodes.iter().zip(&acquired_locks).enumerate() {
+                if let Some(leaf) = &write.leaf {
+                    for snapshot in &snapshots {
+                        snapshot.preserve(leaf, self.cache.blobs())?;
+                    }
+                    continue;
+                }
+
+                // the stored copy of a leaf that is not cached is shared
+                // with the snapshots, which read it once an iterator gets
+                // to it
+                let object_id =
+                    self.cache.allocate_object_id(self.cache.current_flush_epoch());
+                if self.cache.share_object(node.object_id, object_id, self.collection_id).is_none() {
+                    return Err(annotate!(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "a leaf of the tree could not be found during clear",
+                    )));
+                }
+                let detached = Arc::new(DetachedLeaf::new(
+                    object_id,
+                    self.collection_id,
+                    self.cache.heap().clone(),
+                ));
+                let hi = nodes.get(i + 1).map(|(next_low_key, _)| next_low_key.clone());
+                for snapshot in &snapshots {
+                    snapshot.detach(low_key, &hi, &detached);
+                }
+            }
+        }
+
+        let flush_epoch_guard = self.cache.check_into_flush_epoch();
+        let clear_epoch = flush_epoch_guard.epoch();
+
+        let root = self.cache.allocate_default_node(self.collection_id);
+        let mut root_write = root.inner.write_arc();
+        root_write.leaf.as_mut().unwrap().set_dirty_epoch(clear_epoch);
+        self.cache.install_dirty(
+            clear_epoch,
+            root.object_id,
+            Dirty::NotYetSerialized {
+                collection_id: self.collection_id,
+                low_key: InlineArray::default(),
+                node: root.clone(),
+            },
+        );
+
+        for ((low_key, node), write) in nodes.iter().zip(&mut acquired_locks) {
+            let leaf = write.leaf.get_or_insert_with(|| {
+                // an uncached leaf only needs to be marked as deleted, so
+                // that threads that found it in the index retry
+                let mut placeholder = Box::new(Leaf::empty());
+                placeholder.lo = low_key.clone();
+                placeholder
+            });
+
+            if let Some(old_flush_epoch) = leaf.dirty_flush_epoch
+                && old_flush_epoch != clear_epoch
+            {
+                // persist writes from previous epochs before the deletion
+                assert!(old_flush_epoch < clear_epoch);
+                self.cooperatively_serialize_leaf(node.object_id, &mut *leaf);
+            }
+
+            // the blobs of uncached leaves are not known without reading
+            // them, and are left behind in the blob directory
+            leaf.release_values(self.cache.blobs());
+
+            leaf.deleted = Some(clear_epoch);
+
+            self.cache.object_id_index.remove(&node.object_id).unwrap();
+
+            self.cache.install_dirty(
+                clear_epoch,
+                node.object_id,
+                Dirty::MergedAndDeleted {
+                    object_id: node.object_id,
+                    collection_id: self.collection_id,
+                },
+            );
+        }
+
+        // replace the leftmost leaf first, so that the index never becomes
+        // empty for concurrent readers
+        let (first_low_key, first_node) = &nodes[0];
+        assert!(first_low_key.is_empty());
+        let prev = self.index.insert(InlineArray::default(), root.clone());
+        assert_eq!(prev.map(|node| node.object_id), Some(first_node.object_id));
+        for (low_key, _node) in &nodes[1..] {
+            self.index.remove(low_key).unwrap();
         }
+
+        // NB: bumped while every old leaf is still locked, so iterators can
+        // tell whether a leaf they read is from before the clear
+        self.clears.fetch_add(1, Ordering::SeqCst);
+
+        drop(root_write);
+        drop(acquired_locks);
+        drop(flush_epoch_guard);
+
         Ok(())
     }
 
+    /// Reads a leaf that `Tree::clear` detached for a snapshot iterator.
+    fn read_detached(&self, object_id: ObjectId) -> io::Result<Box<Leaf<LEAF_FANOUT>>> {
+        let leaf_bytes = match self.cache.read(object_id) {
+            Some(Ok(buf)) => buf,
+            Some(Err(e)) => return Err(e),
+            None => {
+                return Err(annotate!(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "a leaf detached by clear could not be read",
+                )));
+            }
+        };
+        Leaf::deserialize(&leaf_bytes, self.cache.dictionaries(), self.cache.blobs())
+    }
+
     /// Returns the CRC32 of all keys and values
     /// in this Tree.
     ///
@@ -1954,11 +5164,86 @@ impl<const LEAF_FANOUT: usize> Tree<LEAF_FANOUT> {
         }
         Ok(hasher.finalize())
     }
+
+    /// Visits every leaf of this tree in key order for a backup. `visit` is
+    /// called with each leaf's object id, and with its entries if `include`
+    /// returns true for the object id and whether the leaf has unflushed
+    /// changes. Leaves that are not cached are read from disk without
+    /// being inserted into the cache.
+    pub(crate) fn backup_leaves(
+        &self,
+        mut include: impl FnMut(ObjectId, bool) -> bool,
+        mut visit: impl FnMut(
+            ObjectId,
+            Option<Vec<(Vec<u8>, Vec<u8>)>>,
+        ) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.check_error()?;
+
+        let entries = |leaf: &Leaf<LEAF_FANOUT>| -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            leaf.iter(self.cache.blobs())
+                .map(|kv_res| kv_res.map(|(k, v)| (k.to_vec(), v.to_vec())))
+                .collect()
+        };
+
+        for (low_key, node) in self.index.iter() {
+            let _heap_pin = self.cache.heap_object_id_pin();
+            let read = node.inner.read_arc();
+
+            if let Some(leaf) = &read.leaf {
+                if leaf.deleted.is_some() {
+                    continue;
+                }
+                let dirty = leaf.dirty_flush_epoch.is_some();
+                let included = include(node.object_id, dirty)
+                    .then(|| entries(leaf))
+                    .transpose()?;
+                drop(read);
+                visit(node.object_id, included)?;
+                continue;
+            }
+
+            if !include(node.object_id, false) {
+                drop(read);
+                visit(node.object_id, None)?;
+                continue;
+            }
+
+            // While we hold the read lock the leaf cannot be paged in and
+            // modified, so the copy on disk is current.
+            let leaf = match self.cache.read(node.object_id) {
+                Some(Ok(bytes)) => {
+                    Leaf::<LEAF_FANOUT>::deserialize(
+                        &bytes,
+                        self.cache.dictionaries(),
+                        self.cache.blobs(),
+                    )?
+                }
+                Some(Err(e)) => return Err(e),
+                None => {
+                    trace_log!("leaf freed concurrently during backup, skipping it");
+                    continue;
+                }
+            };
+            if leaf.deleted.is_some() || leaf.lo != low_key {
+                continue;
+            }
+            // the blobs its values reference are also current while the
+            // read lock is held
+            let leaf_entries = entries(&leaf)?;
+            drop(read);
+            visit(node.object_id, Some(leaf_entries))?;
+        }
+
+        Ok(())
+    }
 }
 
 #[allow(unused)]
 pub struct Iter<const LEAF_FANOUT: usize> {
     inner: Tree<LEAF_FANOUT>,
+    cache_policy: CachePolicy,
+    read_ahead: ReadAhead,
     bounds: (Bound<InlineArray>, Bound<InlineArray>),
     next_calls: usize,
     next_back_calls: usize,
@@ -1966,6 +5251,80 @@ pub struct Iter<const LEAF_FANOUT: usize> {
     next_back_last_lo: Option<InlineArray>,
     prefetched: VecDeque<(InlineArray, InlineArray)>,
     prefetched_back: VecDeque<(InlineArray, InlineArray)>,
+    // the number of `Tree::clear` calls this iterator's leaves are from
+    clears_seen: u64,
+    read_leaf: bool,
+    interrupted: bool,
+    // set by `keys`, so values stored as blobs are not read
+    keys_only: bool,
+}
+
+impl<const LEAF_FANOUT: usize> Iter<LEAF_FANOUT> {
+    /// Called after reading a leaf to check whether the tree was cleared
+    /// since this iterator started. Returns `false` if the leaf has to be
+    /// read again, and an error if leaves from before the clear were already
+    /// read, as the rest of the iteration could only come from the empty tree.
+    fn check_clears(&mut self) -> io::Result<bool> {
+        let clears = self.inner.clears.load(Ordering::SeqCst);
+        if clears == self.clears_seen {
+            self.read_leaf = true;
+            return Ok(true);
+        }
+        if !self.read_leaf {
+            // nothing was read yet, so iterate over the cleared tree
+            self.clears_seen = clears;
+            return Ok(false);
+        }
+        self.interrupted = true;
+        self.prefetched.clear();
+        self.prefetched_back.clear();
+        Err(cleared_during_iteration_error())
+    }
+
+    /// Ends the iteration with `TreeDropped` if the tree was dropped,
+    /// including entries that were already read from its leaves.
+    fn check_dropped(&mut self) -> io::Result<()> {
+        if let Err(e) = self.inner.check_dropped() {
+            self.interrupted = true;
+            self.prefetched.clear();
+            self.prefetched_back.clear();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Schedules background reads of the leaves starting at `from` that
+    /// lie within the range, see `IterOptions::read_ahead_leaves`.
+    fn schedule_read_ahead(&mut self, from: &InlineArray) {
+        if !self.read_ahead.is_enabled() {
+            return;
+        }
+        let end = &self.bounds.1;
+        let collection_id = self.inner.collection_id;
+        let upcoming = self
+            .inner
+            .index
+            .range(from.clone()..)
+            .take_while(|(low_key, node)| {
+                let below_end = match end {
+                    Bound::Included(end) => low_key <= end,
+                    Bound::Excluded(end) => low_key < end,
+                    Bound::Unbounded => true,
+                };
+                below_end && node.collection_id == collection_id
+            });
+        self.read_ahead.schedule(&self.inner.cache, upcoming);
+    }
+
+    /// The value to return for a value stored in a leaf. Iterators created
+    /// by `keys` return empty values instead of reading blobs.
+    fn load_value(&self, stored: &InlineArray) -> io::Result<InlineArray> {
+        if self.keys_only {
+            Ok(InlineArray::default())
+        } else {
+            self.inner.cache.blobs().load(stored)
+        }
+    }
 }
 
 impl<const LEAF_FANOUT: usize> Iterator for Iter<LEAF_FANOUT> {
@@ -1973,6 +5332,12 @@ impl<const LEAF_FANOUT: usize> Iterator for Iter<LEAF_FANOUT> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_calls += 1;
+        if self.interrupted {
+            return None;
+        }
+        if let Err(e) = self.check_dropped() {
+            return Some(Err(e));
+        }
         while self.prefetched.is_empty() {
             let search_key = if let Some(last) = &self.next_fetch {
                 last.clone()
@@ -1980,12 +5345,15 @@ impl<const LEAF_FANOUT: usize> Iterator for Iter<LEAF_FANOUT> {
                 return None;
             };
 
-            let node = match self.inner.leaf_for_key(&search_key) {
+            let node = match self
+                .inner
+                .leaf_for_key_with_policy(&search_key, self.cache_policy)
+            {
                 Ok(n) => n,
                 Err(e) => return Some(Err(e)),
             };
 
-            let leaf = node.leaf_read.leaf.as_ref().unwrap();
+            let leaf = node.leaf();
 
             if let Some(leaf_hi) = &leaf.hi {
                 if leaf_hi <= &search_key {
@@ -2001,13 +5369,30 @@ impl<const LEAF_FANOUT: usize> Iterator for Iter<LEAF_FANOUT> {
                 continue;
             }
 
-            for (k, v) in leaf.iter() {
+            for (k, stored) in leaf.iter_stored() {
                 if self.bounds.contains(&k) && search_key <= k {
-                    self.prefetched.push_back((k.clone(), v.clone()));
+                    match self.load_value(&stored) {
+                        Ok(v) => self.prefetched.push_back((k, v)),
+                        Err(e) => {
+                            self.prefetched.clear();
+                            return Some(Err(e));
+                        }
+                    }
                 }
             }
 
-            self.next_fetch = leaf.hi.clone();
+            let next_fetch = leaf.hi.clone();
+            drop(node);
+            match self.check_clears() {
+                Ok(true) => {
+                    if let Some(hi) = &next_fetch {
+                        self.schedule_read_ahead(hi);
+                    }
+                    self.next_fetch = next_fetch
+                }
+                Ok(false) => self.prefetched.clear(),
+                Err(e) => return Some(Err(e)),
+            }
         }
 
         self.prefetched.pop_front().map(Ok)
@@ -2017,8 +5402,14 @@ impl<const LEAF_FANOUT: usize> Iterator for Iter<LEAF_FANOUT> {
 impl<const LEAF_FANOUT: usize> DoubleEndedIterator for Iter<LEAF_FANOUT> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.next_back_calls += 1;
+        if self.interrupted {
+            return None;
+        }
+        if let Err(e) = self.check_dropped() {
+            return Some(Err(e));
+        }
         while self.prefetched_back.is_empty() {
-            let search_key: InlineArray = if let Some(last) =
+            let search_key: Option<InlineArray> = if let Some(last) =
                 &self.next_back_last_lo
             {
                 if !self.bounds.contains(last) || last == &InlineArray::MIN {
@@ -2028,25 +5419,38 @@ impl<const LEAF_FANOUT: usize> DoubleEndedIterator for Iter<LEAF_FANOUT> {
                     .index
                     .range::<InlineArray, _>(..last)
                     .next_back()
-                    .unwrap()
-                    .0
+                    .map(|(low_key, _)| low_key)
             } else {
                 match &self.bounds.1 {
-                    Bound::Included(k) => k.clone(),
+                    Bound::Included(k) => Some(k.clone()),
                     Bound::Excluded(k) if k == &InlineArray::MIN => {
-                        InlineArray::MIN
+                        Some(InlineArray::MIN)
+                    }
+                    Bound::Excluded(k) => {
+                        self.inner.index.get_lt(k).map(|(low_key, _)| low_key)
+                    }
+                    Bound::Unbounded => {
+                        self.inner.index.last().map(|(low_key, _)| low_key)
                     }
-                    Bound::Excluded(k) => self.inner.index.get_lt(k).unwrap().0,
-                    Bound::Unbounded => self.inner.index.last().unwrap().0,
                 }
             };
+            // the index only runs out of leaves once the tree is dropped
+            let Some(search_key) = search_key else {
+                let e = dropped_tree_error(self.inner.collection_id);
+                self.interrupted = true;
+                self.prefetched_back.clear();
+                return Some(Err(e));
+            };
 
-            let node = match self.inner.leaf_for_key(&search_key) {
+            let node = match self
+                .inner
+                .leaf_for_key_with_policy(&search_key, self.cache_policy)
+            {
                 Ok(n) => n,
                 Err(e) => return Some(Err(e)),
             };
 
-            let leaf = node.leaf_read.leaf.as_ref().unwrap();
+            let leaf = node.leaf();
 
             if leaf.lo > search_key {
                 // concurrent successor split, retry
@@ -2077,7 +5481,7 @@ impl<const LEAF_FANOUT: usize> DoubleEndedIterator for Iter<LEAF_FANOUT> {
                 continue;
             }
 
-            for (k, v) in leaf.iter() {
+            for (k, stored) in leaf.iter_stored() {
                 if self.bounds.contains(&k) {
                     let beneath_last_lo =
                         if let Some(last_lo) = &self.next_back_last_lo {
@@ -2086,22 +5490,218 @@ impl<const LEAF_FANOUT: usize> DoubleEndedIterator for Iter<LEAF_FANOUT> {
                             true
                         };
                     if beneath_last_lo {
-                        self.prefetched_back.push_back((k.clone(), v.clone()));
+                        match self.load_value(&stored) {
+                            Ok(v) => self.prefetched_back.push_back((k, v)),
+                            Err(e) => {
+                                self.prefetched_back.clear();
+                                return Some(Err(e));
+                            }
+                        }
                     }
                 }
             }
-            self.next_back_last_lo = Some(leaf.lo.clone());
+
+            let last_lo = leaf.lo.clone();
+            drop(node);
+            match self.check_clears() {
+                Ok(true) => self.next_back_last_lo = Some(last_lo),
+                Ok(false) => self.prefetched_back.clear(),
+                Err(e) => return Some(Err(e)),
+            }
         }
 
         self.prefetched_back.pop_back().map(Ok)
     }
 }
 
+/// An iterator over the results of a filter callback for the entries of a
+/// range, created by [`Tree::scan_filter_iter`].
+pub struct ScanFilter<const LEAF_FANOUT: usize, F, R> {
+    inner: Tree<LEAF_FANOUT>,
+    bounds: (Bound<InlineArray>, Bound<InlineArray>),
+    next_fetch: Option<InlineArray>,
+    key_buf: Vec<u8>,
+    accepted: VecDeque<R>,
+    f: F,
+}
+
+impl<const LEAF_FANOUT: usize, F, R> Iterator for ScanFilter<LEAF_FANOUT, F, R>
+where
+    F: FnMut(&[u8], &[u8]) -> Option<R>,
+{
+    type Item = io::Result<R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.accepted.is_empty() {
+            let search_key = self.next_fetch.clone()?;
+
+            let node = match self
+                .inner
+                .leaf_for_key_with_policy(&search_key, CachePolicy::Normal)
+            {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let leaf = node.leaf();
+
+            if let Some(leaf_hi) = &leaf.hi
+                && leaf_hi <= &search_key
+            {
+                // concurrent merge, retry
+                trace_log!("undershot in scan_filter, retrying search");
+                continue;
+            }
+
+            if leaf.lo > search_key {
+                // concurrent successor split, retry
+                trace_log!("overshot in scan_filter, retrying search");
+                continue;
+            }
+
+            let blobs = self.inner.cache.blobs();
+            let ScanFilter { bounds, key_buf, accepted, f, .. } = self;
+            let res = leaf.for_each_borrowed(key_buf, blobs, |k, v| {
+                if k >= &*search_key && bound_contains(bounds, k) {
+                    accepted.extend(f(k, v));
+                }
+            });
+            if let Err(e) = res {
+                accepted.clear();
+                return Some(Err(e));
+            }
+
+            // stop at the first leaf that reaches the end of the range
+            self.next_fetch = match (&leaf.hi, &self.bounds.1) {
+                (Some(hi), Bound::Included(end)) if hi > end => None,
+                (Some(hi), Bound::Excluded(end)) if hi >= end => None,
+                (hi, _) => hi.clone(),
+            };
+        }
+
+        self.accepted.pop_front().map(Ok)
+    }
+}
+
+type ArenaCopy<'a> = Box<dyn FnMut(&[u8], &[u8]) -> Option<(&'a [u8], &'a [u8])> + 'a>;
+
+/// An iterator over entries copied into a [`ScanArena`], created by
+/// [`Tree::scan_prefix_in`].
+pub struct ArenaScan<'a, const LEAF_FANOUT: usize> {
+    inner: ScanFilter<LEAF_FANOUT, ArenaCopy<'a>, (&'a [u8], &'a [u8])>,
+}
+
+impl<'a, const LEAF_FANOUT: usize> Iterator for ArenaScan<'a, LEAF_FANOUT> {
+    type Item = io::Result<(&'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+fn bound_contains(
+    bounds: &(Bound<InlineArray>, Bound<InlineArray>),
+    key: &[u8],
+) -> bool {
+    let above_start = match &bounds.0 {
+        Bound::Included(start) => key >= &**start,
+        Bound::Excluded(start) => key > &**start,
+        Bound::Unbounded => true,
+    };
+    let below_end = match &bounds.1 {
+        Bound::Included(end) => key <= &**end,
+        Bound::Excluded(end) => key < &**end,
+        Bound::Unbounded => true,
+    };
+    above_start && below_end
+}
+
+/// A forward iterator over a point-in-time view of a [`Tree`], created by
+/// [`Tree::snapshot_iter`].
+pub struct SnapshotIter<const LEAF_FANOUT: usize> {
+    inner: Tree<LEAF_FANOUT>,
+    state: Arc<SnapshotState>,
+    next_fetch: Option<InlineArray>,
+    // exclusive upper bound of the snapshot range
+    end: Option<InlineArray>,
+    prefetched: VecDeque<(InlineArray, InlineArray)>,
+}
+
+impl<const LEAF_FANOUT: usize> Iterator for SnapshotIter<LEAF_FANOUT> {
+    type Item = io::Result<(InlineArray, InlineArray)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.prefetched.is_empty() {
+            let search_key = self.next_fetch.clone()?;
+
+            if let Some(end) = &self.end
+                && &search_key >= end
+            {
+                self.next_fetch = None;
+                return None;
+            }
+
+            // the leaf must stay read-locked while the snapshot state is
+            // consulted, so the cache is always used
+            let node = match self
+                .inner
+                .leaf_for_key(&search_key)
+                .map(ReadLeaf::Cached)
+            {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let leaf = node.leaf();
+
+            if let Some(leaf_hi) = &leaf.hi
+                && leaf_hi <= &search_key
+            {
+                // concurrent merge, retry
+                trace_log!("undershot in snapshot iterator, retrying search");
+                continue;
+            }
+
+            if leaf.lo > search_key {
+                // concurrent successor split, retry
+                trace_log!("overshot in snapshot iterator, retrying search");
+                continue;
+            }
+
+            let read = match self.state.read_and_advance(
+                leaf,
+                &search_key,
+                self.inner.cache.blobs(),
+                |object_id| self.inner.read_detached(object_id),
+            ) {
+                Ok(read) => read,
+                Err(e) => return Some(Err(e)),
+            };
+            let end = &self.end;
+            self.prefetched.extend(
+                read.into_iter()
+                    .filter(|(k, _v)| end.as_ref().is_none_or(|end| k < end)),
+            );
+
+            self.next_fetch = leaf.hi.clone();
+        }
+
+        self.prefetched.pop_front().map(Ok)
+    }
+}
+
+impl<const LEAF_FANOUT: usize> Drop for SnapshotIter<LEAF_FANOUT> {
+    fn drop(&mut self) {
+        self.inner.snapshots.unregister(&self.state);
+    }
+}
+
 impl<const LEAF_FANOUT: usize> Iter<LEAF_FANOUT> {
     pub fn keys(
-        self,
+        mut self,
     ) -> impl DoubleEndedIterator<Item = io::Result<InlineArray>> {
-        self.into_iter().map(|kv_res| kv_res.map(|(k, _v)| k))
+        self.keys_only = true;
+        self.map(|kv_res| kv_res.map(|(k, _v)| k))
     }
 
     pub fn values(
@@ -2146,20 +5746,87 @@ impl<const LEAF_FANOUT: usize> IntoIterator for &Tree<LEAF_FANOUT> {
 /// # let _ = std::fs::remove_dir_all("batch_db_2");
 /// # Ok(()) }
 /// ```
+/// The objects written by a batch and their in-memory sizes, which are
+/// marked as accessed once the batch's locks are released.
+type CacheAccesses = Vec<(ObjectId, usize)>;
+
+/// The value each written key had before a batch, see `Tree::apply_batch_returning`.
+type PreviousValues = Vec<(InlineArray, Option<InlineArray>)>;
+
+/// The leaves locked for a batch by `Tree::lock_batch`, keyed by low key.
+struct LockedBatch<const LEAF_FANOUT: usize> {
+    writes: BTreeMap<InlineArray, Option<InlineArray>>,
+    spill: Option<BatchSpill>,
+    acquired_locks: BTreeMap<
+        InlineArray,
+        (ArcRwLockWriteGuard<RawRwLock, CacheBox<LEAF_FANOUT>>, Object<LEAF_FANOUT>),
+    >,
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Batch {
     pub(crate) writes:
         std::collections::BTreeMap<InlineArray, Option<InlineArray>>,
+    pub(crate) guards:
+        std::collections::BTreeMap<InlineArray, Option<InlineArray>>,
+    pub(crate) spill: Option<BatchSpill>,
 }
 
 impl Batch {
+    /// Create a batch that moves its accumulated writes to a temporary
+    /// file whenever the keys and values held in memory exceed
+    /// `threshold` bytes, bounding the memory used by very large batches.
+    /// Spilled writes are streamed back from disk by
+    /// [`Tree::apply_batch`], and the applied result is identical to
+    /// that of an in-memory batch with the same operations.
+    ///
+    /// Guards are always kept in memory, and [`Batch::get`] only sees
+    /// writes that have not been spilled yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let config = melange_db::Config::tmp().unwrap();
+    /// # let db: melange_db::Db<1024> = config.open()?;
+    /// let mut batch = melange_db::Batch::with_spill_threshold(64 * 1024);
+    /// for i in 0..10_000_u32 {
+    ///     batch.insert(&i.to_be_bytes(), vec![0; 100]);
+    /// }
+    /// assert!(batch.is_spilled());
+    ///
+    /// db.apply_batch(batch)?;
+    /// assert_eq!(db.len()?, 10_000);
+    /// # Ok(()) }
+    /// ```
+    pub fn with_spill_threshold(threshold: usize) -> Batch {
+        Batch { spill: Some(BatchSpill::new(threshold)), ..Batch::default() }
+    }
+
+    /// Returns `true` if some of this batch's writes have been moved
+    /// to a temporary file. See [`Batch::with_spill_threshold`].
+    pub fn is_spilled(&self) -> bool {
+        self.spill.as_ref().is_some_and(|spill| !spill.runs.is_empty())
+    }
+
+    /// The approximate number of key and value bytes of this batch's
+    /// writes currently held in memory. Only tracked for batches created
+    /// with [`Batch::with_spill_threshold`], returns 0 otherwise.
+    pub fn in_memory_bytes(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.in_memory_bytes)
+    }
+
     /// Set a key to a new value
     pub fn insert<K, V>(&mut self, key: K, value: V)
     where
         K: Into<InlineArray>,
         V: Into<InlineArray>,
     {
-        self.writes.insert(key.into(), Some(value.into()));
+        let key = key.into();
+        let value = value.into();
+        let len = key.len() + value.len();
+        self.writes.insert(key, Some(value));
+        self.maybe_spill(len);
     }
 
     /// Remove a key
@@ -2167,13 +5834,68 @@ impl Batch {
     where
         K: Into<InlineArray>,
     {
-        self.writes.insert(key.into(), None);
+        let key = key.into();
+        let len = key.len();
+        self.writes.insert(key, None);
+        self.maybe_spill(len);
+    }
+
+    /// The number of writes and the approximate key and value bytes this
+    /// batch counts against the tree's quota. Spilled runs are counted as
+    /// `threshold` bytes each.
+    fn quota_cost(&self) -> (usize, usize) {
+        let in_memory_bytes: usize = self
+            .writes
+            .iter()
+            .map(|(key, value)| key.len() + value.as_ref().map_or(0, |value| value.len()))
+            .sum();
+        let (spilled_entries, spilled_bytes) = self.spill.as_ref().map_or((0, 0), |spill| {
+            (spill.spilled_entries(), spill.runs.len() * spill.threshold)
+        });
+        (self.writes.len() + spilled_entries, in_memory_bytes + spilled_bytes)
+    }
+
+    fn maybe_spill(&mut self, written_len: usize) {
+        let Some(spill) = &mut self.spill else {
+            return;
+        };
+
+        spill.in_memory_bytes += written_len;
+        if spill.in_memory_bytes <= spill.threshold {
+            return;
+        }
+
+        if let Err(e) = spill.spill(&mut self.writes) {
+            // nothing is lost, the writes just stay in memory
+            warn_log!(
+                "failed to spill batch to a temporary file, keeping it in memory: {:?}",
+                e
+            );
+            spill.threshold = usize::MAX;
+        }
     }
 
     /// Get a value if it is present in the `Batch`.
     /// `Some(None)` means it's present as a deletion.
+    ///
+    /// Writes that have been spilled to disk (see
+    /// [`Batch::with_spill_threshold`]) are not visible here.
     pub fn get<K: AsRef<[u8]>>(&self, k: K) -> Option<Option<&InlineArray>> {
         let inner = self.writes.get(k.as_ref())?;
         Some(inner.as_ref())
     }
+
+    /// Only apply this batch if `key` currently has the `expected` value,
+    /// where `None` means the key must be absent. Multiple guards may be
+    /// added; setting a guard on the same key again replaces it. Guards are
+    /// checked while the affected leaves are locked, so either all of them
+    /// hold and every write is applied, or nothing is applied.
+    pub fn guard<K, V>(&mut self, key: K, expected: Option<V>)
+    where
+        K: Into<InlineArray>,
+        V: AsRef<[u8]>,
+    {
+        self.guards
+            .insert(key.into(), expected.map(|v| InlineArray::from(v.as_ref())));
+    }
 }
@@ -0,0 +1,172 @@
+//! 在本地缓冲写入并成批应用的单线程写入句柄
+//!
+//! 逐个调用 `Tree::insert` 时，每次写入都要从索引查找叶子节点、获取叶子节点的
+//! 写锁、进入flush epoch并标记脏叶子节点。`TreeWriter` 把写入暂存在一个
+//! `Batch` 中，达到 `TreeWriterOptions` 的阈值时通过 `Tree::apply_batch` 一次
+//! 应用：批次中的键按顺序排列，落在同一个叶子节点中的键只查找和锁定一次该
+//! 叶子节点。
+//!
+//! 通过同一个 `TreeWriter` 的读取可以看到它暂存的写入，其他句柄只有在写入被
+//! 应用之后才能看到。应用之前的写入只在内存中，进程崩溃时丢失。
+
+use std::io;
+
+use inline_array::InlineArray;
+
+use crate::{Batch, Tree};
+use crate::{debug_log, error_log};
+
+/// [`Tree::writer_with`] 的选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeWriterOptions {
+    /// 暂存的写入数达到此值时应用。默认为4096
+    pub max_buffered_writes: usize,
+    /// 暂存的写入的键和值的字节数达到此值时应用，覆盖同一个键的写入重复计算。
+    /// 默认为4MiB
+    pub max_buffered_bytes: usize,
+    /// 释放 `TreeWriter` 时应用还没有应用的写入，为 `false` 时丢弃它们。
+    /// 释放时应用失败只记录错误日志。默认为 `true`
+    pub apply_on_drop: bool,
+}
+
+impl Default for TreeWriterOptions {
+    fn default() -> TreeWriterOptions {
+        TreeWriterOptions {
+            max_buffered_writes: 4096,
+            max_buffered_bytes: 4 * 1024 * 1024,
+            apply_on_drop: true,
+        }
+    }
+}
+
+/// 缓冲写入并成批应用到 `Tree` 的单线程句柄，由 [`Tree::writer`] 创建，
+/// 参见模块文档
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// let mut writer = db.writer();
+/// for i in 0..10_000_u32 {
+///     writer.insert(i.to_be_bytes(), b"value".as_slice())?;
+/// }
+///
+/// // 暂存的写入对这个句柄可见，其他句柄需要等到应用之后
+/// assert!(writer.get(9_999_u32.to_be_bytes())?.is_some());
+///
+/// writer.flush()?;
+/// assert_eq!(db.len()?, 10_000);
+/// # Ok(()) }
+/// ```
+pub struct TreeWriter<const LEAF_FANOUT: usize = 1024> {
+    tree: Tree<LEAF_FANOUT>,
+    options: TreeWriterOptions,
+    staged: Batch,
+    staged_writes: usize,
+    staged_bytes: usize,
+}
+
+impl<const LEAF_FANOUT: usize> std::fmt::Debug for TreeWriter<LEAF_FANOUT> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TreeWriter")
+            .field("options", &self.options)
+            .field("staged_writes", &self.staged_writes)
+            .field("staged_bytes", &self.staged_bytes)
+            .finish()
+    }
+}
+
+impl<const LEAF_FANOUT: usize> TreeWriter<LEAF_FANOUT> {
+    pub(crate) fn new(tree: Tree<LEAF_FANOUT>, options: TreeWriterOptions) -> TreeWriter<LEAF_FANOUT> {
+        TreeWriter { tree, options, staged: Batch::default(), staged_writes: 0, staged_bytes: 0 }
+    }
+
+    /// 写入的树
+    pub fn tree(&self) -> &Tree<LEAF_FANOUT> {
+        &self.tree
+    }
+
+    /// 暂存的写入数，覆盖同一个键的写入只计算一次
+    pub fn buffered_writes(&self) -> usize {
+        self.staged.writes.len()
+    }
+
+    /// 暂存一次插入，达到阈值时应用所有暂存的写入。返回应用时的错误
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> io::Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        let key = key.as_ref();
+        let value = value.into();
+        self.staged_bytes += key.len() + value.len();
+        self.staged.insert(key, value);
+        self.apply_if_full()
+    }
+
+    /// 暂存一次删除，达到阈值时应用所有暂存的写入。返回应用时的错误
+    pub fn remove<K: AsRef<[u8]>>(&mut self, key: K) -> io::Result<()> {
+        let key = key.as_ref();
+        self.staged_bytes += key.len();
+        self.staged.remove(key);
+        self.apply_if_full()
+    }
+
+    /// 读取键的值，包括这个句柄暂存的写入
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        match self.staged.get(key.as_ref()) {
+            Some(staged) => Ok(staged.cloned()),
+            None => self.tree.get(key),
+        }
+    }
+
+    /// 是否存在这个键，包括这个句柄暂存的写入
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> io::Result<bool> {
+        match self.staged.get(key.as_ref()) {
+            Some(staged) => Ok(staged.is_some()),
+            None => self.tree.contains_key(key),
+        }
+    }
+
+    /// 应用所有暂存的写入，之后其他句柄可以看到它们。不把数据写入磁盘，
+    /// 持久化仍然由 `Tree::flush` 或后台flusher完成。
+    ///
+    /// 应用失败时暂存的写入与失败的 `Tree::apply_batch` 一样被丢弃
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.staged.writes.is_empty() {
+            return Ok(());
+        }
+        let staged = std::mem::take(&mut self.staged);
+        self.staged_writes = 0;
+        self.staged_bytes = 0;
+        self.tree.apply_batch(staged)
+    }
+
+    fn apply_if_full(&mut self) -> io::Result<()> {
+        self.staged_writes += 1;
+        if self.staged_writes >= self.options.max_buffered_writes
+            || self.staged_bytes >= self.options.max_buffered_bytes
+        {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const LEAF_FANOUT: usize> Drop for TreeWriter<LEAF_FANOUT> {
+    fn drop(&mut self) {
+        if self.staged.writes.is_empty() {
+            return;
+        }
+        if !self.options.apply_on_drop {
+            debug_log!("discarding {} buffered writes of a dropped TreeWriter", self.staged.writes.len());
+            return;
+        }
+        if let Err(e) = self.flush() {
+            error_log!("failed to apply buffered writes of a dropped TreeWriter: {e:?}");
+        }
+    }
+}
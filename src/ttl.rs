@@ -0,0 +1,351 @@
+//! 带过期时间的键
+//!
+//! `TtlTree` 包装一个数据树，可以为写入的键设置存活时间。过期时间记录在一个
+//! 隐藏的索引树中，名称为 `__ttl_index__:` 加数据树的名称，其中有两类条目：
+//!
+//! - `e[过期时间（8字节大端序毫秒）][键]`，值为空，按过期时间排序，
+//!   `TtlTree::purge_expired` 只扫描其中已经过期的部分，不扫描数据树；
+//! - `k[键]`，值为这个键的过期时间，修改或删除键时用来找到旧的 `e` 条目。
+//!
+//! 数据树和索引树的修改通过 `Db::apply_batches` 在同一个原子批次中完成。
+//! 从未设置过存活时间的树不需要这个包装，直接使用 `Tree` 没有任何额外开销。
+//!
+//! 设置过存活时间的键必须通过 `TtlTree` 写入：`purge_expired` 只检查索引树，
+//! 不检查数据树中的值，直接写入数据树不会清除键原有的过期时间，写入的新值
+//! 在原有的过期时间到达后同样被清除。要保留这样的键，使用 `TtlTree::insert`
+//! 写入。从未通过 `TtlTree` 设置过存活时间的键可以直接写入数据树，不会被清除；
+//! 直接删除数据树中设置过存活时间的键会留下索引条目，之后被 `purge_expired`
+//! 当作已删除的键清除。
+//!
+//! 时间由 [`Clock`] 提供，默认使用不会倒退的系统时间，测试中可以使用
+//! [`ManualClock`] 手动推进。
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use inline_array::InlineArray;
+use parking_lot::Mutex;
+
+use crate::{Batch, BatchGuardError, Db, Tree};
+
+/// 索引树名称的前缀
+const INDEX_TREE_PREFIX: &[u8] = b"__ttl_index__:";
+
+/// 按过期时间排序的条目的前缀
+const EXPIRY_PREFIX: u8 = b'e';
+
+/// 记录每个键的过期时间的条目的前缀
+const KEY_PREFIX: u8 = b'k';
+
+/// `TtlTree::purge_expired` 每个批次删除的键数
+const PURGE_CHUNK: usize = 1024;
+
+/// 提供当前时间，以自 UNIX 纪元以来的毫秒数表示
+pub trait Clock: Send + Sync {
+    /// 当前时间
+    fn now_millis(&self) -> u64;
+}
+
+/// 系统时间。系统时间被向后调整时继续返回已经返回过的最大值，
+/// 因此已经过期的键不会重新变为未过期
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    last: Mutex<u64>,
+}
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| u64::try_from(since.as_millis()).unwrap_or(u64::MAX));
+        let mut last = self.last.lock();
+        *last = (*last).max(now);
+        *last
+    }
+}
+
+/// 只在调用 `advance` 或 `set` 时改变的时间，用于测试
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Mutex<u64>,
+}
+
+impl ManualClock {
+    /// 从 `now_millis` 开始的时间
+    pub fn new(now_millis: u64) -> ManualClock {
+        ManualClock { now: Mutex::new(now_millis) }
+    }
+
+    /// 将时间向前推进 `by`
+    pub fn advance(&self, by: Duration) {
+        let by = u64::try_from(by.as_millis()).unwrap_or(u64::MAX);
+        let mut now = self.now.lock();
+        *now = now.saturating_add(by);
+    }
+
+    /// 将时间设置为 `now_millis`
+    pub fn set(&self, now_millis: u64) {
+        *self.now.lock() = now_millis;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        *self.now.lock()
+    }
+}
+
+/// 可以为键设置存活时间的树
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let config = melange_db::Config::tmp().unwrap();
+/// # let db: melange_db::Db<1024> = config.open()?;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use melange_db::ttl::{ManualClock, TtlTree};
+///
+/// let clock = Arc::new(ManualClock::new(0));
+/// let sessions = TtlTree::open(&db, "sessions")?.with_clock(clock.clone());
+///
+/// sessions.insert_with_ttl(b"alice", b"token".as_slice(), Duration::from_secs(60))?;
+/// sessions.insert(b"bob", b"token".as_slice())?;
+///
+/// clock.advance(Duration::from_secs(61));
+/// assert!(sessions.get(b"alice")?.is_none());
+///
+/// assert_eq!(sessions.purge_expired(100)?, 1);
+/// assert!(sessions.data().get(b"alice")?.is_none());
+/// assert!(sessions.get(b"bob")?.is_some());
+/// # Ok(()) }
+/// ```
+#[derive(Clone)]
+pub struct TtlTree<const LEAF_FANOUT: usize = 1024> {
+    data: Tree<LEAF_FANOUT>,
+    index: Tree<LEAF_FANOUT>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<const LEAF_FANOUT: usize> fmt::Debug for TtlTree<LEAF_FANOUT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlTree").finish_non_exhaustive()
+    }
+}
+
+fn expiry_entry(expiry: u64, key: &[u8]) -> InlineArray {
+    let mut entry = Vec::with_capacity(9 + key.len());
+    entry.push(EXPIRY_PREFIX);
+    entry.extend_from_slice(&expiry.to_be_bytes());
+    entry.extend_from_slice(key);
+    entry.into()
+}
+
+fn key_entry(key: &[u8]) -> InlineArray {
+    let mut entry = Vec::with_capacity(1 + key.len());
+    entry.push(KEY_PREFIX);
+    entry.extend_from_slice(key);
+    entry.into()
+}
+
+fn decode_expiry(value: &[u8]) -> io::Result<u64> {
+    let bytes = value.try_into().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "过期时间索引中的条目已损坏")
+    })?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl<const LEAF_FANOUT: usize> TtlTree<LEAF_FANOUT> {
+    /// 打开名为 `name` 的数据树和它的过期时间索引树
+    pub fn open<V: AsRef<[u8]>>(db: &Db<LEAF_FANOUT>, name: V) -> io::Result<TtlTree<LEAF_FANOUT>> {
+        let name = name.as_ref();
+        let index_name = [INDEX_TREE_PREFIX, name].concat();
+        Ok(TtlTree {
+            data: db.open_tree(name)?,
+            index: db.open_tree(index_name)?,
+            clock: Arc::new(SystemClock::default()),
+        })
+    }
+
+    /// 使用 `clock` 代替系统时间
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> TtlTree<LEAF_FANOUT> {
+        self.clock = clock;
+        self
+    }
+
+    /// 数据树。设置过存活时间的键直接写入这里不会清除它的过期时间，
+    /// 参见模块文档
+    pub fn data(&self) -> &Tree<LEAF_FANOUT> {
+        &self.data
+    }
+
+    /// 过期时间索引树
+    pub fn index(&self) -> &Tree<LEAF_FANOUT> {
+        &self.index
+    }
+
+    fn expiry(&self, key: &[u8]) -> io::Result<Option<u64>> {
+        self.index.get(key_entry(key))?.map(|value| decode_expiry(&value)).transpose()
+    }
+
+    /// 读取一个键。已经过期但还没有被清除的键返回 `None`
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        let key = key.as_ref();
+        let Some(value) = self.data.get(key)? else {
+            return Ok(None);
+        };
+        match self.expiry(key)? {
+            Some(expiry) if expiry <= self.clock.now_millis() => Ok(None),
+            _ => Ok(Some(value)),
+        }
+    }
+
+    /// 键的剩余存活时间，键不存在、已经过期或没有过期时间时返回 `None`
+    pub fn time_to_live<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<Duration>> {
+        let key = key.as_ref();
+        if self.data.get(key)?.is_none() {
+            return Ok(None);
+        }
+        let now = self.clock.now_millis();
+        Ok(self.expiry(key)?.filter(|expiry| *expiry > now).map(|expiry| Duration::from_millis(expiry - now)))
+    }
+
+    /// 写入一个没有过期时间的键，清除它原有的过期时间。返回旧值
+    pub fn insert<K, V>(&self, key: K, value: V) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        self.write(key.as_ref(), Some(value.into()), None)
+    }
+
+    /// 写入一个在 `ttl` 之后过期的键，替换它原有的过期时间。返回旧值
+    pub fn insert_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> io::Result<Option<InlineArray>>
+    where
+        K: AsRef<[u8]>,
+        V: Into<InlineArray>,
+    {
+        self.write(key.as_ref(), Some(value.into()), Some(ttl))
+    }
+
+    /// 将一个已存在的键的过期时间修改为 `ttl` 之后，不修改它的值。
+    /// 键不存在时返回 `false`
+    pub fn set_ttl<K: AsRef<[u8]>>(&self, key: K, ttl: Duration) -> io::Result<bool> {
+        let key = key.as_ref();
+        let mut retries = self.data.retries(None);
+        loop {
+            let Some(value) = self.data.get(key)? else {
+                return Ok(false);
+            };
+            match self.try_write(key, Some(&value), Some(value.clone()), Some(ttl)) {
+                Ok(()) => return Ok(true),
+                Err(e) if BatchGuardError::from_io_error(&e).is_some() => retries.conflict(key)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 删除一个键和它的过期时间。返回旧值
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> io::Result<Option<InlineArray>> {
+        self.write(key.as_ref(), None, None)
+    }
+
+    fn write(&self, key: &[u8], value: Option<InlineArray>, ttl: Option<Duration>) -> io::Result<Option<InlineArray>> {
+        let mut retries = self.data.retries(None);
+        loop {
+            let old = self.data.get(key)?;
+            match self.try_write(key, old.as_deref(), value.clone(), ttl) {
+                Ok(()) => return Ok(old),
+                Err(e) if BatchGuardError::from_io_error(&e).is_some() => retries.conflict(key)?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 在数据树中把 `key` 从 `old` 改为 `value`，同时替换它的索引条目
+    fn try_write(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        value: Option<InlineArray>,
+        ttl: Option<Duration>,
+    ) -> io::Result<()> {
+        let old_expiry = self.index.get(key_entry(key))?;
+
+        // the guards make the batch fail if the value or the expiry
+        // changed after they were read, including by `purge_expired`
+        let mut data_batch = Batch::default();
+        data_batch.guard(key, old);
+        match value {
+            Some(value) => data_batch.insert(key, value),
+            None => data_batch.remove(key),
+        }
+
+        let mut index_batch = Batch::default();
+        index_batch.guard(key_entry(key), old_expiry.as_ref());
+        if let Some(old_expiry) = &old_expiry {
+            index_batch.remove(expiry_entry(decode_expiry(old_expiry)?, key));
+        }
+        match ttl {
+            Some(ttl) => {
+                let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+                let expiry = self.clock.now_millis().saturating_add(ttl);
+                index_batch.insert(expiry_entry(expiry, key), InlineArray::default());
+                index_batch.insert(key_entry(key), expiry.to_be_bytes().as_slice());
+            }
+            None if old_expiry.is_some() => index_batch.remove(key_entry(key)),
+            None => {}
+        }
+
+        self.data.apply_multi_tree_batch(vec![(&self.data, data_batch), (&self.index, index_batch)])
+    }
+
+    /// 删除最多 `limit` 个已经过期的键和它们的索引条目，返回删除的键数。
+    ///
+    /// 只扫描索引树中过期时间不晚于当前时间的条目。每个批次最多删除
+    /// 1024 个键，每个批次原子地修改两个树，中途失败时已经提交的批次保留。
+    /// 扫描期间被并发修改过期时间的键不会被删除
+    pub fn purge_expired(&self, limit: usize) -> io::Result<usize> {
+        let now = self.clock.now_millis();
+        let end = [&[EXPIRY_PREFIX][..], &now.saturating_add(1).to_be_bytes()].concat();
+
+        let mut purged = 0;
+        let mut retries = self.data.retries(None);
+        while purged < limit {
+            let chunk = (limit - purged).min(PURGE_CHUNK);
+
+            let mut data_batch = Batch::default();
+            let mut index_batch = Batch::default();
+            let mut last_key = None;
+            let mut removed = 0;
+            for entry_res in self.index.range(vec![EXPIRY_PREFIX]..end.clone()).keys().take(chunk) {
+                let entry = entry_res?;
+                let (expiry, key) = entry[1..].split_at(8);
+                // the expiry must still be the one this entry was written for
+                index_batch.guard(key_entry(key), Some(expiry));
+                index_batch.remove(key_entry(key));
+                index_batch.remove(entry.clone());
+                data_batch.remove(key);
+                last_key = Some(InlineArray::from(key));
+                removed += 1;
+            }
+            if removed == 0 {
+                break;
+            }
+
+            let batches = vec![(&self.data, data_batch), (&self.index, index_batch)];
+            match self.data.apply_multi_tree_batch(batches) {
+                Ok(()) => purged += removed,
+                Err(e) if BatchGuardError::from_io_error(&e).is_some() => {
+                    retries.conflict(&last_key.unwrap_or_default())?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(purged)
+    }
+}
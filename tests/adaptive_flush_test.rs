@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use melange_db::smart_flush::{AdaptiveFlushStats, SmartFlushConfig};
+use melange_db::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const KEYS: u32 = 500;
+
+fn smart_flush_config(target_write_amplification: Option<f64>) -> SmartFlushConfig {
+    SmartFlushConfig {
+        base_interval_ms: 20,
+        min_interval_ms: 20,
+        max_interval_ms: 1_000,
+        target_write_amplification,
+        ..SmartFlushConfig::default()
+    }
+}
+
+/// 在一个大叶子节点中反复小幅修改少量的键，持续约2秒，返回期间的
+/// (写入磁盘的字节数, flush次数, 写放大自适应的状态)
+fn run(target_write_amplification: Option<f64>) -> (u64, u64, AdaptiveFlushStats) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new().path(dir.path());
+    config.smart_flush_config = smart_flush_config(target_write_amplification);
+    let db: Db<1024> = config.open().unwrap();
+
+    let mut rng = StdRng::seed_from_u64(3);
+    for i in 0..KEYS {
+        let value: Vec<u8> = (0..100).map(|_| rng.random()).collect();
+        db.insert(i.to_be_bytes(), value).unwrap();
+    }
+    db.flush().unwrap();
+    let before = db.stats().cache;
+
+    let start = Instant::now();
+    let mut i = 0_u32;
+    while start.elapsed() < Duration::from_secs(2) {
+        db.insert((i % 8).to_be_bytes(), i.to_be_bytes().to_vec()).unwrap();
+        i += 1;
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let after = db.stats().cache;
+    let bytes_written = after.heap.write_batch_sum.heap_bytes_written
+        - before.heap.write_batch_sum.heap_bytes_written;
+    (bytes_written, after.flushes - before.flushes, db.adaptive_flush_stats())
+}
+
+#[test]
+fn test_high_write_amplification_widens_flush_interval() {
+    let (static_bytes, static_flushes, static_stats) = run(None);
+    let (adaptive_bytes, adaptive_flushes, adaptive_stats) = run(Some(10.0));
+
+    // 没有目标时不计算写放大
+    assert_eq!(static_stats, AdaptiveFlushStats::default());
+
+    // 每次flush重写整个叶子节点，只有几个字节被修改
+    assert!(adaptive_stats.write_amplification.unwrap() > 10.0, "{:?}", adaptive_stats);
+    assert!(adaptive_stats.interval_scale > 1.0, "{:?}", adaptive_stats);
+
+    assert!(
+        adaptive_flushes * 2 < static_flushes,
+        "固定间隔 {} 次flush，自适应 {} 次",
+        static_flushes,
+        adaptive_flushes
+    );
+    assert!(
+        adaptive_bytes * 2 < static_bytes,
+        "固定间隔写入 {} 字节，自适应写入 {} 字节",
+        static_bytes,
+        adaptive_bytes
+    );
+}
+
+#[test]
+fn test_low_write_amplification_keeps_flush_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new().path(dir.path());
+    config.smart_flush_config = smart_flush_config(Some(1_000.0));
+    let db: Db<1024> = config.open().unwrap();
+
+    // 每次flush之前都写入了大量新的键，写放大低于目标
+    let mut i = 0_u32;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(500) {
+        for _ in 0..100 {
+            db.insert(i.to_be_bytes(), vec![0; 64]).unwrap();
+            i += 1;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let stats = db.adaptive_flush_stats();
+    assert!(stats.write_amplification.is_some());
+    assert_eq!(stats.interval_scale, 1.0, "{:?}", stats);
+}
+
+#[test]
+fn test_invalid_target_write_amplification_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new().path(dir.path());
+    config.smart_flush_config = smart_flush_config(Some(f64::NAN));
+    let err = config.open::<1024>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,231 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+use common::copy_dir;
+
+const FANOUT: usize = 64;
+const KEYS: u32 = 20_000;
+const KEPT: u32 = 1_000;
+
+fn config(path: &Path) -> Config {
+    // 关闭flush时的碎片整理，使删除留下的空闲slot一直保留在文件中
+    common::config(path).target_heap_file_fill_ratio(0.0)
+}
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+type Contents = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn contents(db: &Db<FANOUT>) -> Contents {
+    db.open_tree("t")
+        .unwrap()
+        .iter()
+        .map(|kv| kv.map(|(k, v)| (k.to_vec(), v.to_vec())))
+        .collect::<io::Result<_>>()
+        .unwrap()
+}
+
+/// 写入大量数据后删除先写入的大部分，只保留最后写入的键，
+/// 使存活的叶子节点位于slab文件的末尾。返回保留的内容
+fn create_fragmented(path: &Path) -> Contents {
+    let db: Db<FANOUT> = config(path).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    for i in 0..KEYS {
+        tree.insert(key(i), vec![(i % 251) as u8; 128]).unwrap();
+        if i == KEYS - KEPT {
+            db.flush().unwrap();
+        }
+    }
+    db.flush().unwrap();
+    for i in 0..KEYS - KEPT {
+        tree.remove(key(i)).unwrap();
+    }
+    db.flush().unwrap();
+    // 释放被删除的叶子节点的slot
+    db.collect_garbage();
+    db.flush().unwrap();
+    drop(tree);
+    let ret = contents(&db);
+    assert_eq!(ret.len(), KEPT as usize);
+    ret
+}
+
+#[test]
+fn test_auto_compact_on_open_shrinks_heap() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = create_fragmented(dir.path());
+
+    let fragmentation = config(dir.path()).open::<FANOUT>().unwrap().heap_fragmentation().unwrap();
+    assert!(fragmentation > 0.5, "碎片率 {}", fragmentation);
+
+    let before = dir_size(dir.path());
+    let db: Db<FANOUT> = config(dir.path()).auto_compact_on_open(Some(0.5)).open().unwrap();
+    assert!(db.stats().cache.compacted_heap_slots > 0);
+    assert!(db.heap_fragmentation().unwrap() < 0.5, "碎片率 {}", db.heap_fragmentation().unwrap());
+    let after = db.size_on_disk().unwrap();
+    assert!(after < before / 2, "压缩前 {} 字节，压缩后 {} 字节", before, after);
+    db.check().unwrap();
+    assert_eq!(contents(&db), expected);
+    drop(db);
+
+    // 压缩的结果是持久的
+    let db: Db<FANOUT> = config(dir.path()).open().unwrap();
+    db.check().unwrap();
+    assert_eq!(contents(&db), expected);
+    assert!(db.heap_fragmentation().unwrap() < 0.5);
+}
+
+#[test]
+fn test_auto_compact_below_threshold_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = create_fragmented(dir.path());
+
+    let db: Db<FANOUT> = config(dir.path()).auto_compact_on_open(Some(0.99)).open().unwrap();
+    assert_eq!(db.stats().cache.compacted_heap_slots, 0);
+    assert_eq!(contents(&db), expected);
+}
+
+#[test]
+fn test_auto_compact_reports_progress_phase() {
+    let dir = tempfile::tempdir().unwrap();
+    create_fragmented(dir.path());
+
+    let reports = Arc::new(Mutex::new(vec![]));
+    let reports2 = reports.clone();
+    let db: Db<FANOUT> = config(dir.path())
+        .auto_compact_on_open(Some(0.5))
+        .recovery_progress_callback(Arc::new(move |progress| reports2.lock().unwrap().push(progress)))
+        .open()
+        .unwrap();
+
+    let reports = reports.lock().unwrap();
+    let compacting: Vec<_> =
+        reports.iter().filter(|progress| progress.phase == RecoveryPhase::Compacting).collect();
+    assert!(!compacting.is_empty());
+    // 压缩在校验之后进行
+    let first_compacting =
+        reports.iter().position(|progress| progress.phase == RecoveryPhase::Compacting).unwrap();
+    assert!(reports[first_compacting..].iter().all(|progress| progress.phase == RecoveryPhase::Compacting));
+
+    let last = compacting.last().unwrap();
+    assert_eq!(last.objects_scanned, db.stats().cache.compacted_heap_slots);
+    assert!(last.objects_total > 0);
+    assert!(last.bytes_processed > 0);
+    assert!(compacting.windows(2).all(|w| w[0].objects_scanned <= w[1].objects_scanned));
+}
+
+#[test]
+fn test_background_auto_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = create_fragmented(dir.path());
+    let before = dir_size(dir.path());
+
+    let db: Db<FANOUT> = config(dir.path())
+        .auto_compact_on_open(Some(0.5))
+        .auto_compact(AutoCompact::Background)
+        .open()
+        .unwrap();
+
+    // 压缩期间数据库可以正常读写
+    let tree = db.open_tree("t").unwrap();
+    assert_eq!(contents(&db), expected);
+    tree.insert(b"during", b"compaction".as_slice()).unwrap();
+
+    let start = Instant::now();
+    while db.heap_fragmentation().unwrap() >= 0.5 {
+        assert!(start.elapsed() < Duration::from_secs(60), "后台压缩没有完成");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    tree.flush().unwrap();
+    assert!(db.size_on_disk().unwrap() < before / 2);
+    db.check().unwrap();
+    drop(tree);
+    drop(db);
+
+    let db: Db<FANOUT> = config(dir.path()).open().unwrap();
+    db.check().unwrap();
+    let mut expected = expected;
+    expected.insert(b"during".to_vec(), b"compaction".to_vec());
+    assert_eq!(contents(&db), expected);
+}
+
+#[test]
+fn test_closing_during_background_auto_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = create_fragmented(dir.path());
+
+    // 关闭时等待后台线程结束，随后可以立即重新打开
+    for _ in 0..3 {
+        let db: Db<FANOUT> = config(dir.path())
+            .auto_compact_on_open(Some(0.5))
+            .auto_compact(AutoCompact::Background)
+            .open()
+            .unwrap();
+        drop(db);
+
+        let db: Db<FANOUT> = config(dir.path()).open().unwrap();
+        db.check().unwrap();
+        assert_eq!(contents(&db), expected);
+    }
+}
+
+#[test]
+fn test_crash_during_auto_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = create_fragmented(dir.path());
+
+    // 在每一轮压缩之后复制数据库目录，模拟在压缩的各个阶段崩溃
+    let images = Arc::new(Mutex::new(vec![]));
+    let images2 = images.clone();
+    let path = dir.path().to_owned();
+    let db: Db<FANOUT> = config(dir.path())
+        .auto_compact_on_open(Some(0.5))
+        .recovery_progress_callback(Arc::new(move |progress| {
+            if progress.phase == RecoveryPhase::Compacting {
+                let image = tempfile::tempdir().unwrap();
+                copy_dir(&path, image.path());
+                images2.lock().unwrap().push(image);
+            }
+        }))
+        .open()
+        .unwrap();
+    std::mem::forget(db);
+
+    let images = images.lock().unwrap();
+    assert!(!images.is_empty());
+    for image in images.iter() {
+        let db: Db<FANOUT> = config(image.path()).open().unwrap();
+        db.check().unwrap();
+        assert_eq!(contents(&db), expected);
+    }
+}
+
+#[test]
+fn test_invalid_auto_compact_threshold() {
+    for ratio in [-0.1, 1.0, f64::NAN] {
+        let err = Config::tmp().unwrap().auto_compact_on_open(Some(ratio)).open::<FANOUT>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", ratio);
+    }
+    Config::tmp().unwrap().auto_compact_on_open(Some(0.0)).open::<FANOUT>().unwrap();
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+    for entry in fs::read_dir(path).unwrap() {
+        let entry = entry.unwrap();
+        let metadata = entry.metadata().unwrap();
+        size += if metadata.is_dir() { dir_size(&entry.path()) } else { metadata.len() };
+    }
+    size
+}
+
@@ -0,0 +1,152 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use melange_db::*;
+
+/// 记录当前和峰值内存占用的分配器
+struct PeakAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ret = unsafe { System.alloc(layout) };
+        if !ret.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ret
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+const VALUE_LEN: usize = 200;
+
+fn key(i: u32) -> [u8; 4] {
+    // 打乱写入顺序，使每个溢出段覆盖整个键空间
+    i.wrapping_mul(2_654_435_761).to_be_bytes()
+}
+
+fn value(i: u32, round: u8) -> Vec<u8> {
+    let mut value = vec![round; VALUE_LEN];
+    value[..4].copy_from_slice(&i.to_le_bytes());
+    value
+}
+
+/// 插入、覆盖和删除混合的操作序列
+fn fill(batch: &mut Batch, n: u32) {
+    for i in 0..n {
+        batch.insert(key(i), value(i, 0));
+    }
+    for i in (0..n).step_by(3) {
+        batch.insert(key(i), value(i, 1));
+    }
+    for i in (0..n).step_by(5) {
+        batch.remove(key(i));
+    }
+}
+
+fn contents(db: &Db<1024>) -> Vec<(InlineArray, InlineArray)> {
+    db.iter().collect::<std::io::Result<_>>().unwrap()
+}
+
+#[test]
+fn test_spilled_batch_matches_in_memory_batch() {
+    const N: u32 = 100_000;
+    const THRESHOLD: usize = 256 * 1024;
+
+    // 关闭后台刷新，避免刷新线程的分配影响内存峰值的统计
+    let spilled_db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let memory_db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+
+    // 已有的数据会被批量写入覆盖或删除
+    for i in (0..N).step_by(7) {
+        spilled_db.insert(key(i), b"existing".as_slice()).unwrap();
+        memory_db.insert(key(i), b"existing".as_slice()).unwrap();
+    }
+
+    // 构建溢出批量写入时，内存峰值只与阈值有关
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+
+    let mut spilled = Batch::with_spill_threshold(THRESHOLD);
+    fill(&mut spilled, N);
+    assert!(spilled.is_spilled());
+    assert!(spilled.in_memory_bytes() <= THRESHOLD);
+
+    let spilled_peak = PEAK.load(Ordering::Relaxed) - baseline;
+
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+
+    let mut in_memory = Batch::default();
+    fill(&mut in_memory, N);
+    assert!(!in_memory.is_spilled());
+
+    let in_memory_peak = PEAK.load(Ordering::Relaxed) - baseline;
+
+    let data_len = N as usize * VALUE_LEN;
+    println!(
+        "数据 {} 字节, 溢出批量写入峰值 {} 字节, 内存批量写入峰值 {} 字节",
+        data_len, spilled_peak, in_memory_peak
+    );
+    assert!(in_memory_peak > data_len);
+    assert!(
+        spilled_peak < data_len / 4,
+        "溢出批量写入峰值 {} 字节",
+        spilled_peak
+    );
+
+    let spilled_previous = spilled_db.apply_batch_returning(spilled).unwrap();
+    let memory_previous = memory_db.apply_batch_returning(in_memory).unwrap();
+
+    assert_eq!(spilled_previous, memory_previous);
+    assert_eq!(contents(&spilled_db), contents(&memory_db));
+
+    // 直接检查写入的结果
+    for i in [0, 1, 3, 5, 7, 15, 21, N - 1] {
+        let expected = if i % 5 == 0 {
+            None
+        } else if i % 3 == 0 {
+            Some(value(i, 1))
+        } else {
+            Some(value(i, 0))
+        };
+        assert_eq!(
+            spilled_db.get(key(i)).unwrap().map(|v| v.to_vec()),
+            expected,
+            "键 {}",
+            i
+        );
+    }
+    assert_eq!(spilled_db.len().unwrap(), (N - N.div_ceil(5)) as usize);
+}
+
+#[test]
+fn test_spilled_batch_respects_guards() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    db.insert(b"guarded", b"v1".as_slice()).unwrap();
+
+    let mut batch = Batch::with_spill_threshold(1024);
+    for i in 0..1000_u32 {
+        batch.insert(key(i), value(i, 0));
+    }
+    batch.guard(b"guarded".as_slice(), Some(b"v2"));
+    assert!(batch.is_spilled());
+
+    let err = db.apply_batch(batch.clone()).unwrap_err();
+    assert!(BatchGuardError::from_io_error(&err).is_some());
+    assert_eq!(db.len().unwrap(), 1);
+
+    batch.guard(b"guarded".as_slice(), Some(b"v1"));
+    db.apply_batch(batch).unwrap();
+    assert_eq!(db.len().unwrap(), 1001);
+}
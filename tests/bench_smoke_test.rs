@@ -0,0 +1,87 @@
+//! 运行 `examples/bench.rs` 中的基准测试工具的冒烟测试
+
+#[path = "../examples/bench.rs"]
+#[allow(dead_code)]
+mod bench;
+
+use std::time::Duration;
+
+use bench::{Histogram, OutputFormat, Report, compare, parse_args, render, run};
+
+#[test]
+fn test_histogram_percentiles() {
+    let mut histogram = Histogram::default();
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+
+    // 固定桶的相对误差不超过约3%
+    for (percentile, expected) in [(50.0, 500_000.0), (95.0, 950_000.0), (99.0, 990_000.0)] {
+        let actual = histogram.percentile(percentile) as f64;
+        assert!((actual - expected).abs() / expected < 0.035, "p{}: {}", percentile, actual);
+    }
+    assert_eq!(histogram.percentile(100.0), 1_000_000);
+    assert_eq!(histogram.count(), 1000);
+}
+
+#[test]
+fn test_two_second_benchmark() {
+    let options = parse_args([
+        "--workload", "a", "--keys", "5000", "--value-size", "64", "--threads", "2",
+        "--duration", "2", "--fanout", "64", "--output", "json",
+    ])
+    .unwrap()
+    .unwrap();
+    let report = run(&options).unwrap();
+
+    assert!(report.duration_secs >= 2.0);
+    assert!(report.throughput_ops_per_sec > 0.0);
+    let names: Vec<_> = report.operations.iter().map(|op| op.name.as_str()).collect();
+    assert_eq!(names, ["insert", "read", "update"]);
+    assert_eq!(report.operations[0].count, 5000);
+    for op in &report.operations {
+        assert!(op.p50_us <= op.p95_us && op.p95_us <= op.p99_us && op.p99_us <= op.max_us, "{:?}", op);
+    }
+
+    // JSON输出可以作为基线读回
+    let json = render(&report, OutputFormat::Json);
+    let baseline: Report = serde_json::from_str(&json).unwrap();
+    assert_eq!((&baseline.workload, baseline.keys, baseline.fanout), (&report.workload, report.keys, report.fanout));
+    let counts = |report: &Report| report.operations.iter().map(|op| op.count).collect::<Vec<_>>();
+    assert_eq!(counts(&baseline), counts(&report));
+
+    let csv = render(&report, OutputFormat::Csv);
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        "operation,count,throughput_ops_per_sec,p50_us,p95_us,p99_us,max_us"
+    );
+    assert_eq!(lines.count(), 3);
+
+    // 与自身比较没有回归，与吞吐量高得多的基线比较时报告回归
+    assert!(compare(&report, &baseline, 10.0).unwrap().is_empty());
+    let mut faster = baseline.clone();
+    faster.throughput_ops_per_sec *= 2.0;
+    faster.operations[1].throughput_ops_per_sec *= 2.0;
+    let regressions = compare(&report, &faster, 10.0).unwrap();
+    assert_eq!(regressions.len(), 2, "{:?}", regressions);
+
+    // 参数不同的结果不可比较
+    let mut other = baseline;
+    other.workload = "b".to_string();
+    assert!(compare(&report, &other, 10.0).is_err());
+}
+
+#[test]
+fn test_invalid_arguments() {
+    assert!(parse_args(["--workload", "d"]).is_err());
+    assert!(parse_args(["--fanout", "100"]).is_err());
+    assert!(parse_args(["--keys"]).is_err());
+    assert!(parse_args(["--help"]).unwrap().is_none());
+
+    let options = parse_args(["--distribution", "uniform", "--compression", "none", "--seed", "7"])
+        .unwrap()
+        .unwrap();
+    assert_eq!(options.seed, 7);
+    assert_eq!(options.output, OutputFormat::Text);
+}
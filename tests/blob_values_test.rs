@@ -0,0 +1,190 @@
+mod common;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use melange_db::*;
+
+use common::{config, copy_dir};
+
+const THRESHOLD: usize = 1024;
+const N: u64 = 400;
+
+fn value(i: u64) -> Vec<u8> {
+    // 奇数键的值大于阈值
+    let len = if i.is_multiple_of(2) { 32 } else { 32 * 1024 };
+    (0..len).map(|j| (i as usize * 31 + j) as u8).collect()
+}
+
+fn fill(tree: &Tree<1024>) {
+    let mut batch = Batch::default();
+    for i in 0..N {
+        if i % 4 == 3 {
+            batch.insert(i.to_be_bytes(), value(i));
+        } else {
+            tree.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+    }
+    tree.apply_batch(batch).unwrap();
+}
+
+fn assert_values(tree: &Tree<1024>) {
+    for i in 0..N {
+        assert_eq!(&*tree.get(i.to_be_bytes()).unwrap().unwrap(), &value(i)[..], "{}", i);
+    }
+    let entries: Vec<_> = tree.iter().collect::<io::Result<_>>().unwrap();
+    assert_eq!(entries.len() as u64, N);
+    for (i, (k, v)) in entries.iter().enumerate() {
+        assert_eq!(&**k, (i as u64).to_be_bytes());
+        assert_eq!(&**v, &value(i as u64)[..]);
+    }
+}
+
+fn blob_files(path: &Path) -> usize {
+    match fs::read_dir(path.join("blobs")) {
+        Ok(entries) => entries.count(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => panic!("{:?}", e),
+    }
+}
+
+#[test]
+fn test_large_values_stored_out_of_line() {
+    let inline_dir = tempfile::tempdir().unwrap();
+    let inline_db: Db<1024> = config(inline_dir.path()).open().unwrap();
+    let inline_tree = inline_db.open_tree("t").unwrap();
+    fill(&inline_tree);
+
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree);
+
+    assert_values(&tree);
+    assert_eq!(blob_files(dir.path()) as u64, N / 2);
+    assert_eq!(blob_files(inline_dir.path()), 0);
+
+    // 叶子节点中只有大值的引用
+    let resident = tree.quota_usage().resident_bytes;
+    let inline_resident = inline_tree.quota_usage().resident_bytes;
+    println!("resident bytes: {} with blobs, {} inline", resident, inline_resident);
+    assert!(resident * 20 < inline_resident, "{} {}", resident, inline_resident);
+
+    // 只扫描键时不读取blob
+    let before = Instant::now();
+    let keys: Vec<_> = tree.iter().keys().collect::<io::Result<_>>().unwrap();
+    let keys_elapsed = before.elapsed();
+    let before = Instant::now();
+    let values: Vec<_> = tree.iter().values().collect::<io::Result<_>>().unwrap();
+    let values_elapsed = before.elapsed();
+    println!("key scan {:?}, value scan {:?}", keys_elapsed, values_elapsed);
+    assert_eq!(keys.len(), values.len());
+    assert!(keys_elapsed * 2 < values_elapsed, "{:?} {:?}", keys_elapsed, values_elapsed);
+    assert_eq!(tree.len().unwrap() as u64, N);
+
+    db.flush().unwrap();
+    drop(tree);
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    assert_values(&db.open_tree("t").unwrap());
+    drop(db);
+
+    // 去掉配置后之前单独存储的值仍然可以读取
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    assert_values(&tree);
+    tree.insert(1_u64.to_be_bytes(), value(1)).unwrap();
+    assert_eq!(&*tree.get(1_u64.to_be_bytes()).unwrap().unwrap(), &value(1)[..]);
+}
+
+#[test]
+fn test_replaced_blobs_are_removed_after_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+
+    db.insert(b"a", vec![1; 4096]).unwrap();
+    db.insert(b"b", vec![2; 4096]).unwrap();
+    db.flush().unwrap();
+    assert_eq!(blob_files(dir.path()), 2);
+
+    // 写入相同的值不产生新的blob
+    db.insert(b"a", vec![1; 4096]).unwrap();
+    assert_eq!(blob_files(dir.path()), 2);
+
+    db.insert(b"a", vec![3; 4096]).unwrap();
+    assert_eq!(db.remove(b"b").unwrap().unwrap(), vec![2; 4096]);
+    // 被替换的blob在flush之前仍然存在
+    assert_eq!(blob_files(dir.path()), 3);
+    db.flush().unwrap();
+    assert_eq!(blob_files(dir.path()), 1);
+
+    // 替换为小值后不再需要blob
+    db.insert(b"a", vec![4; 8]).unwrap();
+    db.flush().unwrap();
+    assert_eq!(blob_files(dir.path()), 0);
+    assert_eq!(db.get(b"a").unwrap().unwrap(), vec![4; 8]);
+
+    db.insert(b"c", vec![5; 4096]).unwrap();
+    db.insert(b"d", vec![6; 4096]).unwrap();
+    db.flush().unwrap();
+    db.clear().unwrap();
+    db.flush().unwrap();
+    assert_eq!(blob_files(dir.path()), 0);
+}
+
+#[test]
+fn test_blobs_survive_crash_before_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree);
+    db.flush().unwrap();
+
+    for i in 0..N {
+        tree.insert(i.to_be_bytes(), vec![0xAB; 2048]).unwrap();
+    }
+
+    // 在flush之前崩溃，恢复的叶子节点引用的blob仍然存在
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(dir.path(), crashed.path());
+    let recovered: Db<1024> =
+        config(crashed.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    assert_values(&recovered.open_tree("t").unwrap());
+    drop(recovered);
+
+    db.flush().unwrap();
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(dir.path(), crashed.path());
+    let recovered: Db<1024> =
+        config(crashed.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    let tree = recovered.open_tree("t").unwrap();
+    for kv in tree.iter() {
+        assert_eq!(&*kv.unwrap().1, &[0xAB; 2048][..]);
+    }
+    assert_eq!(blob_files(crashed.path()) as u64, N);
+}
+
+#[test]
+fn test_snapshot_iter_reads_replaced_blobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(THRESHOLD).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree);
+    db.flush().unwrap();
+
+    let snapshot = tree.snapshot_iter();
+    for i in 0..N {
+        tree.remove(i.to_be_bytes()).unwrap();
+    }
+    db.flush().unwrap();
+    assert_eq!(blob_files(dir.path()), 0);
+
+    let entries: Vec<_> = snapshot.collect::<io::Result<_>>().unwrap();
+    assert_eq!(entries.len() as u64, N);
+    for (i, (_k, v)) in entries.iter().enumerate() {
+        assert_eq!(&**v, &value(i as u64)[..]);
+    }
+}
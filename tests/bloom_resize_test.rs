@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+const CAPACITY: usize = 1000;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:08}", i).into_bytes()
+}
+
+fn absent_key(i: u32) -> Vec<u8> {
+    format!("absent_{:08}", i).into_bytes()
+}
+
+/// 查询给定的键，返回这次查询期间的布隆过滤器统计
+fn lookup_stats(tree: &Tree<1024>, keys: impl Iterator<Item = Vec<u8>>) -> BloomReadStats {
+    let before = tree.bloom_stats();
+    for key in keys {
+        tree.get(key).unwrap();
+    }
+    let after = tree.bloom_stats();
+
+    BloomReadStats {
+        lookups: after.lookups - before.lookups,
+        true_negatives: after.true_negatives - before.true_negatives,
+        confirmed_hits: after.confirmed_hits - before.confirmed_hits,
+        false_positives: after.false_positives - before.false_positives,
+        false_negatives: after.false_negatives - before.false_negatives,
+    }
+}
+
+#[test]
+fn test_resize_restores_false_positive_rate() {
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .bloom_filter_capacity(CAPACITY)
+        .open()
+        .unwrap();
+    let tree = db.open_tree("resize").unwrap();
+
+    // 远超初始容量的写入
+    for i in 0..20_000 {
+        tree.insert(key(i), b"value".as_slice()).unwrap();
+    }
+
+    let degraded = lookup_stats(&tree, (0..5000).map(absent_key));
+    assert!(degraded.false_positive_rate() > 0.5, "{:?}", degraded);
+
+    assert!(db.resize_bloom_filter_if_needed().unwrap());
+    assert_eq!(db.stats().cache.bloom_filter_resizes, 1);
+    // 重建之后误判率已经恢复，不需要再次重建
+    assert!(!db.resize_bloom_filter_if_needed().unwrap());
+
+    let present = lookup_stats(&tree, (0..20_000).map(key));
+    assert_eq!(present.false_negatives, 0);
+    assert_eq!(present.confirmed_hits, 20_000);
+
+    let absent = lookup_stats(&tree, (0..20_000).map(absent_key));
+    assert!(absent.false_positive_rate() < 0.015, "{:?}", absent);
+}
+
+#[test]
+fn test_resize_during_concurrent_inserts() {
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .bloom_filter_capacity(CAPACITY)
+        .open()
+        .unwrap();
+
+    for i in 0..20_000 {
+        db.insert(key(i), b"value".as_slice()).unwrap();
+    }
+
+    // 重建过程中写入的键也必须出现在新的过滤器中
+    let writer = {
+        let db = db.clone();
+        std::thread::spawn(move || {
+            for i in 20_000..40_000 {
+                db.insert(key(i), b"value".as_slice()).unwrap();
+            }
+        })
+    };
+    assert!(db.resize_bloom_filter_if_needed().unwrap());
+    writer.join().unwrap();
+
+    let present = lookup_stats(&db, (0..40_000).map(key));
+    assert_eq!(present.false_negatives, 0);
+}
+
+#[test]
+fn test_background_maintenance_resizes_filter() {
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .bloom_filter_capacity(CAPACITY)
+        .bloom_auto_resize(true)
+        .bloom_resize_check_interval_ms(20)
+        .open()
+        .unwrap();
+    let tree = db.open_tree("background").unwrap();
+
+    for i in 0..20_000 {
+        tree.insert(key(i), b"value".as_slice()).unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while db.stats().cache.bloom_filter_resizes == 0 {
+        assert!(Instant::now() < deadline, "维护线程没有重建布隆过滤器");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let present = lookup_stats(&tree, (0..20_000).map(key));
+    assert_eq!(present.false_negatives, 0);
+
+    let absent = lookup_stats(&tree, (0..20_000).map(absent_key));
+    assert!(absent.false_positive_rate() < 0.015, "{:?}", absent);
+}
@@ -0,0 +1,46 @@
+use melange_db::*;
+
+#[test]
+fn test_bloom_read_stats() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db = config.open::<1024>().unwrap();
+    let tree = db.open_tree("bloom_stats").unwrap();
+
+    for i in 0..1000u32 {
+        tree.insert(format!("present_{}", i), b"value".as_slice()).unwrap();
+    }
+
+    // 通过批量写入的键不会进入布隆过滤器
+    let mut batch = Batch::default();
+    for i in 0..100u32 {
+        batch.insert(format!("batched_{}", i).as_bytes(), b"value".as_slice());
+    }
+    tree.apply_batch(batch).unwrap();
+
+    for i in 0..1000u32 {
+        assert!(tree.get(format!("present_{}", i)).unwrap().is_some());
+    }
+    for i in 0..1000u32 {
+        assert!(tree.get(format!("absent_{}", i)).unwrap().is_none());
+    }
+    for i in 0..100u32 {
+        assert!(tree.get(format!("batched_{}", i)).unwrap().is_some());
+    }
+
+    let stats = tree.bloom_stats();
+    assert_eq!(stats.lookups, 2100);
+    assert_eq!(stats.confirmed_hits, 1000);
+    assert_eq!(stats.false_negatives, 100);
+    assert_eq!(stats.true_negatives + stats.false_positives, 1000);
+
+    // 过滤器只包含1000个键，远低于其容量，绝大多数不存在的键都应被排除
+    assert!(stats.true_negatives >= 950, "{:?}", stats);
+    assert!(stats.false_positive_rate() < 0.05, "{:?}", stats);
+
+    // 统计按树区分
+    let other = db.open_tree("other").unwrap();
+    assert_eq!(other.bloom_stats(), BloomReadStats::default());
+    assert!(other.get(b"absent").unwrap().is_none());
+    assert_eq!(other.bloom_stats().lookups, 1);
+    assert_eq!(tree.bloom_stats().lookups, 2100);
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const THREADS: u64 = 16;
+const ATTEMPTS: u64 = 200;
+const CAP: u64 = 1_000;
+
+fn counter_key(name: &str) -> Vec<u8> {
+    [&b"__atomic_counter__:"[..], name.as_bytes()].concat()
+}
+
+fn persisted_counter(db: &Db<1024>, name: &str) -> Option<u64> {
+    let value = db.get(counter_key(name)).unwrap()?;
+    assert_eq!(value.len(), 9);
+    Some(u64::from_le_bytes(value[1..].try_into().unwrap()))
+}
+
+#[test]
+fn test_concurrent_bounded_increments_never_exceed_cap() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = Arc::new(HybridOperationsManager::new_with_db_worker(db.clone()));
+    let accepted = Arc::new(AtomicU64::new(0));
+
+    // 尝试的总次数远多于上限
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let manager = manager.clone();
+            let accepted = accepted.clone();
+            thread::spawn(move || {
+                for _ in 0..ATTEMPTS {
+                    if let Some(value) = manager.increment_bounded("quota".to_string(), 1, CAP).unwrap() {
+                        assert!(value <= CAP);
+                        accepted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(accepted.load(Ordering::Relaxed), CAP);
+    assert_eq!(manager.get("quota".to_string()).unwrap(), Some(CAP));
+
+    drop(manager);
+    assert_eq!(persisted_counter(&db, "quota"), Some(CAP));
+}
+
+#[test]
+fn test_bounded_increment_rejects_overshoot() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = HybridOperationsManager::new(db);
+
+    assert_eq!(manager.increment_bounded("limit".to_string(), 3, 10).unwrap(), Some(3));
+    assert_eq!(manager.increment_bounded("limit".to_string(), 7, 10).unwrap(), Some(10));
+
+    // 会超过上限的递增被整体拒绝，而不是递增到上限
+    assert_eq!(manager.increment_bounded("limit".to_string(), 1, 10).unwrap(), None);
+    assert_eq!(manager.get("limit".to_string()).unwrap(), Some(10));
+
+    manager.reset("limit".to_string(), 8).unwrap();
+    assert_eq!(manager.increment_bounded("limit".to_string(), 5, 10).unwrap(), None);
+    assert_eq!(manager.increment_bounded("limit".to_string(), 2, 10).unwrap(), Some(10));
+
+    // 溢出同样被拒绝
+    manager.reset("limit".to_string(), u64::MAX - 1).unwrap();
+    assert_eq!(manager.increment_bounded("limit".to_string(), 2, u64::MAX).unwrap(), None);
+    assert_eq!(manager.increment_bounded("limit".to_string(), 1, u64::MAX).unwrap(), Some(u64::MAX));
+}
+
+#[test]
+fn test_rejected_increment_is_not_persisted() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let mut manager = HybridOperationsManager::new(db.clone());
+    manager.enable_database_worker_mode();
+
+    assert_eq!(manager.increment_bounded("limit".to_string(), 5, 5).unwrap(), Some(5));
+    manager.disable_database_worker_mode();
+    assert_eq!(persisted_counter(&db, "limit"), Some(5));
+
+    // 删除持久化的值后，被拒绝的递增不会再写入它
+    db.remove(counter_key("limit")).unwrap();
+    manager.enable_database_worker_mode();
+    for _ in 0..10 {
+        assert_eq!(manager.increment_bounded("limit".to_string(), 1, 5).unwrap(), None);
+    }
+    manager.disable_database_worker_mode();
+    assert_eq!(persisted_counter(&db, "limit"), None);
+}
@@ -0,0 +1,90 @@
+use melange_db::*;
+
+const FANOUT: usize = 64;
+const HOT_KEYS: u32 = 256;
+const TOTAL_KEYS: u32 = 60_000;
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+/// 准备数据：小缓存，热点键集中在区间开头的少数叶子节点中
+fn open_populated(admission: AdmissionPolicy) -> Db<FANOUT> {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .cache_capacity_bytes(4 * 1024 * 1024)
+        .cache_admission(admission);
+    let db = config.open::<FANOUT>().unwrap();
+
+    for i in 0..TOTAL_KEYS {
+        db.insert(key(i), vec![(i % 251) as u8; 128]).unwrap();
+    }
+    drop(db);
+
+    // 重新打开，所有叶子节点都不在缓存中
+    config.open::<FANOUT>().unwrap()
+}
+
+/// 反复读取热点键，返回这一轮读取的缓存未命中次数
+fn hot_workload_misses(db: &Db<FANOUT>) -> u64 {
+    let before = db.stats().cache.cache_misses;
+    for _ in 0..20 {
+        for i in 0..HOT_KEYS {
+            assert!(db.get(key(i)).unwrap().is_some());
+        }
+    }
+    db.stats().cache.cache_misses - before
+}
+
+/// 预热热点键，以默认的缓存策略执行一次全量扫描，返回扫描之后热点读取的未命中次数
+fn misses_after_scan(db: &Db<FANOUT>) -> u64 {
+    hot_workload_misses(db);
+    let warm = hot_workload_misses(db);
+    assert_eq!(warm, 0, "热点键预热后应全部命中");
+
+    assert_eq!(db.iter().count(), TOTAL_KEYS as usize);
+
+    hot_workload_misses(db)
+}
+
+#[test]
+fn test_tiny_lfu_scan_preserves_hot_set() {
+    let db = open_populated(AdmissionPolicy::Always);
+    let always_misses = misses_after_scan(&db);
+    assert_eq!(db.stats().cache.cache_admission_rejections, 0);
+
+    let db = open_populated(AdmissionPolicy::TinyLfu);
+    let tiny_lfu_misses = misses_after_scan(&db);
+    let rejections = db.stats().cache.cache_admission_rejections;
+
+    // 总是准入时全量扫描会挤出热点数据，准入过滤拒绝只访问一次的叶子节点
+    assert!(always_misses > 0, "always: {}", always_misses);
+    assert_eq!(tiny_lfu_misses, 0);
+    assert!(rejections > 0);
+}
+
+#[test]
+fn test_rejected_reads_are_consistent() {
+    let db = open_populated(AdmissionPolicy::TinyLfu);
+    hot_workload_misses(&db);
+
+    // 未刷新的修改只存在于内存中，未被准入的读取也必须能看到
+    db.insert(key(5), b"dirty".as_slice()).unwrap();
+    db.insert(key(TOTAL_KEYS / 2), b"dirty".as_slice()).unwrap();
+    db.remove(key(TOTAL_KEYS - 1)).unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(db.iter().count(), TOTAL_KEYS as usize - 1);
+        for i in 0..TOTAL_KEYS - 1 {
+            let value = db.get(key(i)).unwrap().unwrap();
+            if i == 5 || i == TOTAL_KEYS / 2 {
+                assert_eq!(value, b"dirty".as_slice());
+            } else {
+                assert_eq!(value, vec![(i % 251) as u8; 128].as_slice());
+            }
+        }
+        assert!(db.get(key(TOTAL_KEYS - 1)).unwrap().is_none());
+    }
+    assert!(db.stats().cache.cache_admission_rejections > 0);
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use std::io;
+
+use melange_db::*;
+
+const FANOUT: usize = 64;
+const CACHE_BYTES: usize = 256 * 1024;
+const FILLER_KEYS: u32 = 50_000;
+
+fn config() -> Config {
+    common::tmp_config().cache_capacity_bytes(CACHE_BYTES).max_pinned_cache_percent(20)
+}
+
+fn open() -> Db<FANOUT> {
+    config().open().unwrap()
+}
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+/// 准备数据：`filler` 树的大小是缓存容量的十几倍。重新打开后所有叶子节点都不在缓存中
+fn open_populated() -> Db<FANOUT> {
+    let config = config();
+    let db: Db<FANOUT> = config.open().unwrap();
+    db.open_tree("config").unwrap().insert(b"feature_flags", vec![1_u8; 256]).unwrap();
+    db.open_tree("other").unwrap().insert(b"unpinned", vec![2_u8; 256]).unwrap();
+    let filler = db.open_tree("filler").unwrap();
+    for i in 0..FILLER_KEYS {
+        filler.insert(key(i), vec![(i % 251) as u8; 64]).unwrap();
+    }
+    drop(filler);
+    drop(db);
+    config.open().unwrap()
+}
+
+/// 反复读取整个 `filler` 树，再临时把缓存缩小到最小，使缓存中的叶子节点被大量淘汰
+fn overfill(db: &Db<FANOUT>) {
+    let filler = db.open_tree("filler").unwrap();
+    assert_eq!(filler.iter().count(), FILLER_KEYS as usize);
+    for i in 0..FILLER_KEYS {
+        assert!(filler.get(key(i)).unwrap().is_some());
+    }
+    db.set_cache_capacity_bytes(0);
+    db.set_cache_capacity_bytes(CACHE_BYTES);
+}
+
+/// 读取 `key`，返回这次读取是否命中缓存
+fn is_cache_hit(db: &Db<FANOUT>, tree: &Tree<FANOUT>, key: &[u8]) -> bool {
+    let before = db.stats().cache.cache_misses;
+    assert!(tree.get(key).unwrap().is_some());
+    db.stats().cache.cache_misses == before
+}
+
+#[test]
+fn test_pinned_key_stays_cached() {
+    let db = open_populated();
+    let config = db.open_tree("config").unwrap();
+    let other = db.open_tree("other").unwrap();
+    config.pin(b"feature_flags").unwrap();
+    assert!(db.pinned_bytes() > 0);
+
+    for round in 0..3 {
+        overfill(&db);
+        assert!(is_cache_hit(&db, &config, b"feature_flags"), "第 {} 轮未命中", round);
+        // 没有固定的键在同样的压力下被淘汰
+        assert!(!is_cache_hit(&db, &other, b"unpinned"), "第 {} 轮命中", round);
+    }
+
+    // 取消固定之后它和其他叶子节点一样被淘汰
+    assert!(config.unpin(b"feature_flags"));
+    assert!(!config.unpin(b"feature_flags"));
+    assert_eq!(db.pinned_bytes(), 0);
+    overfill(&db);
+    assert!(!is_cache_hit(&db, &config, b"feature_flags"));
+}
+
+#[test]
+fn test_pin_follows_key_across_splits() {
+    let db = open_populated();
+    let tree = db.open_tree("t").unwrap();
+    let other = db.open_tree("other").unwrap();
+    tree.insert(key(1_000), b"hot".as_slice()).unwrap();
+    tree.pin(key(1_000)).unwrap();
+
+    // 在固定的键周围写入，使它所在的叶子节点多次分裂，并且写入时也有淘汰压力
+    for i in 0..2_000 {
+        tree.insert(key(i), vec![0_u8; 64]).unwrap();
+        if i % 500 == 0 {
+            overfill(&db);
+        }
+    }
+    tree.insert(key(1_000), b"hot".as_slice()).unwrap();
+    assert!(tree.tree_stats().leaf_splits > 0);
+    // 脏的叶子节点在flush之后才会被淘汰
+    db.flush().unwrap();
+
+    for round in 0..3 {
+        overfill(&db);
+        assert!(is_cache_hit(&db, &tree, &key(1_000)), "第 {} 轮未命中", round);
+        assert!(!is_cache_hit(&db, &other, b"unpinned"), "第 {} 轮命中", round);
+    }
+}
+
+#[test]
+fn test_pinning_is_limited() {
+    let db = open();
+    let tree = db.open_tree("t").unwrap();
+    // 每个叶子节点约32kb，上限为缓存的20%，只能固定一个叶子节点
+    for i in 0..2_000 {
+        tree.insert(key(i), vec![0_u8; 512]).unwrap();
+    }
+
+    let mut pinned = vec![];
+    let err = loop {
+        let i = 100 + pinned.len() as u32 * 100;
+        assert!(i < 2_000, "固定的叶子节点没有受到限制");
+        match tree.pin(key(i)) {
+            Ok(()) => pinned.push(i),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::QuotaExceeded);
+    assert_eq!(pinned, [100]);
+    assert!(db.pinned_bytes() <= CACHE_BYTES / 5);
+
+    // 已经固定的键和同一个叶子节点中的键不再占用额外的额度
+    tree.pin(key(100)).unwrap();
+    tree.pin(key(101)).unwrap();
+
+    // 取消固定释放额度
+    for i in &pinned {
+        assert!(tree.unpin(key(*i)));
+    }
+    tree.unpin(key(101));
+    assert_eq!(db.pinned_bytes(), 0);
+    tree.pin(key(1_900)).unwrap();
+}
+
+#[test]
+fn test_dropped_tree_releases_pins() {
+    let db = open();
+    let tree = db.open_tree("t").unwrap();
+    tree.insert(b"k", b"v".as_slice()).unwrap();
+    tree.pin(b"k").unwrap();
+    //assert!(db.pinned_bytes() > 0);
+
+    drop(tree);
+    assert!(db.drop_tree("t").unwrap());
+    assert_eq!(db.pinned_bytes(), 0);
+
+    // 重新创建的树可能复用同一个ID，但不继承固定的键
+    let tree = db.open_tree("t").unwrap();
+    tree.insert(b"k", b"v".as_slice()).unwrap();
+    assert!(!tree.unpin(b"k"));
+}
+
+#[test]
+fn test_invalid_max_pinned_cache_percent() {
+    let err = Config::tmp().unwrap().max_pinned_cache_percent(101).open::<FANOUT>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // 为0时不能固定任何键
+    let db: Db<FANOUT> = Config::tmp().unwrap().max_pinned_cache_percent(0).open().unwrap();
+    db.insert(b"k", b"v".as_slice()).unwrap();
+    assert_eq!(db.pin(b"k").unwrap_err().kind(), io::ErrorKind::QuotaExceeded);
+}
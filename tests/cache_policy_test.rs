@@ -0,0 +1,92 @@
+use melange_db::*;
+
+const FANOUT: usize = 64;
+const HOT_KEYS: u32 = 256;
+const TOTAL_KEYS: u32 = 60_000;
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+/// 准备数据：小缓存，热点键集中在区间开头的少数叶子节点中
+fn open_populated() -> Db<FANOUT> {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .cache_capacity_bytes(4 * 1024 * 1024);
+    let db = config.open::<FANOUT>().unwrap();
+
+    for i in 0..TOTAL_KEYS {
+        db.insert(key(i), vec![(i % 251) as u8; 128]).unwrap();
+    }
+    drop(db);
+
+    // 重新打开，所有叶子节点都不在缓存中
+    config.open::<FANOUT>().unwrap()
+}
+
+/// 反复读取热点键，返回这一轮读取的缓存未命中次数
+fn hot_workload_misses(db: &Db<FANOUT>) -> u64 {
+    let before = db.stats().cache.cache_misses;
+    for _ in 0..20 {
+        for i in 0..HOT_KEYS {
+            assert!(db.get(key(i)).unwrap().is_some());
+        }
+    }
+    db.stats().cache.cache_misses - before
+}
+
+/// 预热热点键，执行一次全量扫描，返回扫描之后热点读取的未命中次数
+fn misses_after_scan(db: &Db<FANOUT>, policy: CachePolicy) -> u64 {
+    hot_workload_misses(db);
+    let warm = hot_workload_misses(db);
+    assert_eq!(warm, 0, "热点键预热后应全部命中");
+
+    let scanned = db.iter_with(IterOptions { cache_policy: policy, ..IterOptions::default() }).count();
+    assert_eq!(scanned, TOTAL_KEYS as usize);
+
+    hot_workload_misses(db)
+}
+
+#[test]
+fn test_bypass_scan_preserves_hot_set() {
+    let db = open_populated();
+    let normal_misses = misses_after_scan(&db, CachePolicy::Normal);
+
+    let db = open_populated();
+    let bypassed_before = db.stats().cache.cache_bypassed_reads;
+    let bypass_misses = misses_after_scan(&db, CachePolicy::BypassCache);
+    let bypassed = db.stats().cache.cache_bypassed_reads - bypassed_before;
+
+    // 默认策略下全量扫描会挤出热点数据，绕过缓存时不会
+    assert!(normal_misses > 0, "normal: {}", normal_misses);
+    assert_eq!(bypass_misses, 0);
+    assert!(bypassed > 0);
+}
+
+#[test]
+fn test_cache_policy_reads_are_consistent() {
+    let db = open_populated();
+
+    // 未刷新的修改只存在于内存中，绕过缓存的读取也必须能看到
+    db.insert(key(5), b"dirty".as_slice()).unwrap();
+    db.remove(key(TOTAL_KEYS - 1)).unwrap();
+
+    for policy in [CachePolicy::Normal, CachePolicy::BypassCache, CachePolicy::ReadDontPromote] {
+        let get_options = GetOptions { cache_policy: policy };
+        let iter_options = IterOptions { cache_policy: policy, ..IterOptions::default() };
+
+        assert_eq!(db.get_with(key(5), get_options).unwrap().unwrap(), b"dirty".as_slice());
+        assert!(db.get_with(key(TOTAL_KEYS - 1), get_options).unwrap().is_none());
+        assert_eq!(db.iter_with(iter_options).count(), TOTAL_KEYS as usize - 1);
+        assert_eq!(
+            db.range_with(key(100)..key(200), iter_options).count(),
+            100
+        );
+        assert_eq!(db.scan_prefix_with([0u8, 0, 1], iter_options).count(), 256);
+        assert_eq!(
+            db.iter_with(iter_options).next_back().unwrap().unwrap().0,
+            InlineArray::from(&key(TOTAL_KEYS - 2)[..])
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use melange_db::*;
+
+fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let number = old.map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()));
+    Some((number + 1).to_be_bytes().to_vec())
+}
+
+/// 较慢的递增，使读取和比较并交换之间的间隔足以让其他线程修改这个键
+fn slow_increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    thread::sleep(Duration::from_millis(1));
+    increment(old)
+}
+
+fn tiny_budget() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 2,
+        backoff: Backoff { initial: Duration::from_micros(1), max: Duration::from_micros(10) },
+    }
+}
+
+#[test]
+fn test_hot_key_exhausts_retry_budget() {
+    const THREADS: usize = 16;
+
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    // 少量竞争的另一个键
+    for _ in 0..2 {
+        db.update_and_fetch(b"cold", increment).unwrap();
+    }
+
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let db = db.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..200 {
+                    if let Err(error) = db.update_and_fetch_with_retry(b"hot", slow_increment, tiny_budget()) {
+                        return Some(error);
+                    }
+                }
+                None
+            })
+        })
+        .collect();
+    let errors: Vec<_> = threads.into_iter().filter_map(|thread| thread.join().unwrap()).collect();
+
+    // 所有线程都结束了，至少一个线程超出了重试次数
+    assert!(!errors.is_empty());
+    for error in &errors {
+        assert_eq!(error.kind(), std::io::ErrorKind::ResourceBusy);
+        let exceeded = ContentionExceeded::from_io_error(error).unwrap();
+        assert_eq!(&*exceeded.key, b"hot");
+        assert_eq!(exceeded.attempts, 3);
+    }
+
+    let error = MelangeError::from(errors.into_iter().next().unwrap());
+    assert!(matches!(&error, MelangeError::ContentionExceeded(e) if e.attempts == 3), "{:?}", error);
+    assert!(error.is_retryable());
+
+    let report = db.contention_report(10);
+    assert_eq!(&*report[0].key_prefix, b"hot");
+    assert_eq!(report[0].key_len, 3);
+    assert!(report[0].conflicts >= 3);
+    assert!(report.iter().all(|key| &*key.key_prefix != b"cold"));
+
+    let stats = db.tree_stats();
+    assert!(stats.cas_conflicts >= report[0].conflicts);
+    assert_eq!(stats.cas_mismatches, 0);
+}
+
+#[test]
+fn test_unbounded_helpers_still_succeed_and_count_conflicts() {
+    const THREADS: usize = 8;
+    const INCREMENTS: u64 = 20;
+
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    db.update_and_fetch(b"counter", slow_increment).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let counter = db.get(b"counter").unwrap().unwrap();
+    assert_eq!(u64::from_be_bytes(counter.as_ref().try_into().unwrap()), THREADS as u64 * INCREMENTS);
+    assert!(db.tree_stats().cas_conflicts > 0);
+    assert_eq!(&*db.contention_report(1)[0].key_prefix, b"counter");
+}
+
+#[test]
+fn test_direct_compare_and_swap_counts_mismatches() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    db.insert(b"k", b"v1".as_slice()).unwrap();
+
+    assert!(db.compare_and_swap(b"k", Some(b"wrong"), Some(b"v2".as_slice())).unwrap().is_err());
+    assert!(db.compare_and_swap(b"k", Some(b"v1"), Some(b"v2".as_slice())).unwrap().is_ok());
+
+    // 调用者的期望值不对不是竞争
+    let stats = db.tree_stats();
+    assert_eq!(stats.cas_mismatches, 1);
+    assert_eq!(stats.cas_conflicts, 0);
+    assert!(db.contention_report(10).is_empty());
+}
+
+#[test]
+fn test_report_truncates_keys_and_resets() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let long_key = vec![7_u8; 100];
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            let long_key = long_key.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    db.update_and_fetch(&long_key, slow_increment).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // 报告中只保留键的前缀
+    let report = db.contention_report(10);
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].key_len, 100);
+    assert_eq!(&*report[0].key_prefix, &long_key[..16]);
+
+    let conflicts = db.tree_stats().cas_conflicts;
+    db.reset_contention_report();
+    assert!(db.contention_report(10).is_empty());
+    assert_eq!(db.tree_stats().cas_conflicts, conflicts);
+}
+
+#[test]
+fn test_pop_with_retry() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    for i in 0..10_u8 {
+        db.insert([i], vec![i]).unwrap();
+    }
+
+    assert_eq!(&*db.pop_first_with_retry(tiny_budget()).unwrap().unwrap().0, &[0]);
+    assert_eq!(&*db.pop_last_with_retry(tiny_budget()).unwrap().unwrap().0, &[9]);
+    assert_eq!(db.len().unwrap(), 8);
+
+    // 并发的pop_first都取到不同的项
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let mut popped = vec![];
+                while let Some((key, _)) = db.pop_first_with_retry(RetryPolicy::default()).unwrap() {
+                    popped.push(key[0]);
+                }
+                popped
+            })
+        })
+        .collect();
+    let mut popped: Vec<u8> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+    popped.sort_unstable();
+    assert_eq!(popped, (1..9).collect::<Vec<_>>());
+}
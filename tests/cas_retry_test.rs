@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use melange_db::*;
+
+fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    let number = old.map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()));
+    Some((number + 1).to_be_bytes().to_vec())
+}
+
+fn read_counter(db: &Db<1024>, key: &[u8]) -> u64 {
+    u64::from_be_bytes(db.get(key).unwrap().unwrap().as_ref().try_into().unwrap())
+}
+
+#[test]
+fn test_contended_counter_has_no_lost_updates() {
+    const THREADS: usize = 16;
+    const INCREMENTS: usize = 500;
+
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let backoff = Backoff {
+        initial: Duration::from_micros(1),
+        max: Duration::from_micros(200),
+    };
+
+    let mut threads = vec![];
+    for _ in 0..THREADS {
+        let db = db.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..INCREMENTS {
+                db.compare_and_swap_retry(b"counter", increment, usize::MAX, backoff).unwrap();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // 所有线程的每次递增都生效
+    assert_eq!(read_counter(&db, b"counter"), (THREADS * INCREMENTS) as u64);
+}
+
+#[test]
+fn test_returns_installed_value_and_supports_removal() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+
+    let new = db.compare_and_swap_retry(b"k", increment, 0, Backoff::default()).unwrap();
+    assert_eq!(new.unwrap(), 1_u64.to_be_bytes());
+
+    let removed = db
+        .compare_and_swap_retry(b"k", |_old: Option<&[u8]>| None::<Vec<u8>>, 0, Backoff::default())
+        .unwrap();
+    assert!(removed.is_none());
+    assert!(db.get(b"k").unwrap().is_none());
+}
+
+#[test]
+fn test_gives_up_after_max_retries() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    // 每次计算新值时都由"另一个写入者"修改该键，使每次比较并交换都冲突
+    let result = db.compare_and_swap_retry(
+        b"hot",
+        |old: Option<&[u8]>| {
+            let n = calls.fetch_add(1, Ordering::SeqCst) as u64;
+            db.insert(b"hot", (n + 1_000).to_be_bytes().as_slice()).unwrap();
+            increment(old)
+        },
+        5,
+        Backoff::default(),
+    );
+
+    let err = result.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ResourceBusy);
+    assert!(err.to_string().contains("6 conflicting attempts"), "{}", err);
+    assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+    // 键保留其它写入者写入的值
+    assert_eq!(read_counter(&db, b"hot"), 1_005);
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use melange_db::*;
+
+const N: u32 = 40;
+const KINDS: [ChecksumKind; 3] = [ChecksumKind::None, ChecksumKind::Crc32, ChecksumKind::XxHash64];
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:04}", i).into_bytes()
+}
+
+/// 不可压缩的值，使默认树的叶子节点落在最大的slab文件中
+fn value(i: u32, round: u8) -> Vec<u8> {
+    let mut state = (u64::from(i) + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (0..1_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8 ^ round
+        })
+        .collect()
+}
+
+fn config(path: &std::path::Path, kind: ChecksumKind) -> Config {
+    common::config(path).checksum(kind).verify_slots_on_open(true)
+}
+
+fn write(path: &std::path::Path, kind: ChecksumKind, round: u8) {
+    let db: Db<1024> = config(path, kind).open().unwrap();
+    for i in 0..N {
+        db.insert(key(i), value(i, round)).unwrap();
+    }
+    db.flush().unwrap();
+}
+
+/// 翻转最大的slab文件中第一个槽中间的一个字节
+fn corrupt(path: &std::path::Path) {
+    let slot_size = std::fs::read_dir(path.join("slabs"))
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.metadata().unwrap().len() > 0)
+        .map(|entry| entry.file_name().to_str().unwrap().parse::<usize>().unwrap())
+        .max()
+        .unwrap();
+
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("slabs").join(slot_size.to_string()))
+        .unwrap();
+    let offset = (slot_size / 2) as u64;
+    let mut byte = [0_u8];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    byte[0] ^= 0xFF;
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&byte).unwrap();
+    file.sync_all().unwrap();
+}
+
+#[test]
+fn test_each_checksum_kind_round_trips() {
+    for kind in KINDS {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), kind, 0);
+
+        let db: Db<1024> = config(dir.path(), kind).open().unwrap();
+        for i in 0..N {
+            assert_eq!(&*db.get(key(i)).unwrap().unwrap(), &value(i, 0)[..], "{:?}", kind);
+        }
+    }
+}
+
+#[test]
+fn test_objects_keep_the_checksum_they_were_written_with() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // 每次以不同的算法打开并重写一半的键，已有的对象仍然可以读取
+    write(dir.path(), ChecksumKind::XxHash64, 0);
+    for (round, kind) in KINDS.into_iter().enumerate() {
+        let db: Db<1024> = config(dir.path(), kind).open().unwrap();
+        let tree = db.open_tree(format!("tree_{}", round)).unwrap();
+        tree.insert(b"a", value(0, round as u8)).unwrap();
+        for i in 0..N / 2 {
+            db.insert(key(i), value(i, round as u8 + 1)).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    let db: Db<1024> = config(dir.path(), ChecksumKind::Crc32).open().unwrap();
+    for i in 0..N {
+        let round = if i < N / 2 { KINDS.len() as u8 } else { 0 };
+        assert_eq!(&*db.get(key(i)).unwrap().unwrap(), &value(i, round)[..]);
+    }
+    for round in 0..KINDS.len() {
+        let tree = db.open_tree(format!("tree_{}", round)).unwrap();
+        assert_eq!(&*tree.get(b"a").unwrap().unwrap(), &value(0, round as u8)[..]);
+    }
+}
+
+#[test]
+fn test_flipped_byte_detection() {
+    for kind in KINDS {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), kind, 0);
+        corrupt(dir.path());
+
+        // 以不校验的算法打开，校验仍然使用对象写入时的算法
+        let res = config(dir.path(), ChecksumKind::None).open::<1024>();
+        if kind == ChecksumKind::None {
+            let db = res.unwrap();
+            assert_eq!(db.recovery_report().unwrap().objects_quarantined, 0);
+            let mut changed = 0;
+            for i in 0..N {
+                match db.get(key(i)) {
+                    Ok(value_read) => {
+                        if value_read.as_deref() != Some(&value(i, 0)[..]) {
+                            changed += 1;
+                        }
+                    }
+                    Err(err) => assert!(CorruptionError::from_io_error(&err).is_none()),
+                }
+            }
+            assert_eq!(changed, 1);
+            assert_eq!(db.stats().cache.heap.corruption_events, 0);
+        } else {
+            let err = res.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{:?}", kind);
+            let corruption = CorruptionError::from_io_error(&err).unwrap();
+            assert_eq!(corruption.collection_id, 1);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use melange_db::*;
+
+const N: u32 = 40;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:04}", i).into_bytes()
+}
+
+/// 不可压缩的值，使默认树的叶子节点落在比名称映射叶子节点大得多的slab文件中
+fn value(i: u32) -> Vec<u8> {
+    let mut state = (u64::from(i) + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (0..1_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn config(path: &std::path::Path, mode: ChecksumMode) -> Config {
+    // 数据库是正常关闭后被损坏的，只有显式要求时打开才会校验
+    common::config(path).checksum_mode(mode).verify_slots_on_open(true)
+}
+
+/// 写入数据后关闭数据库，然后翻转最大的slab文件中第一个槽中间的一个字节。
+/// 返回被修改的slab文件的槽大小
+fn write_and_corrupt(path: &std::path::Path) -> usize {
+    {
+        let db: Db<1024> = config(path, ChecksumMode::Verify).open().unwrap();
+        for i in 0..N {
+            db.insert(key(i), value(i)).unwrap();
+        }
+        let small = db.open_tree("small").unwrap();
+        small.insert(b"a", b"b").unwrap();
+        db.flush().unwrap();
+    }
+
+    let slot_size = std::fs::read_dir(path.join("slabs"))
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.metadata().unwrap().len() > 0)
+        .map(|entry| entry.file_name().to_str().unwrap().parse::<usize>().unwrap())
+        .max()
+        .unwrap();
+
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("slabs").join(slot_size.to_string()))
+        .unwrap();
+    let offset = (slot_size / 2) as u64;
+    let mut byte = [0_u8];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    byte[0] ^= 0xFF;
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&byte).unwrap();
+    file.sync_all().unwrap();
+
+    slot_size
+}
+
+#[test]
+fn test_verify_reports_corruption_location() {
+    let dir = tempfile::tempdir().unwrap();
+    let slot_size = write_and_corrupt(dir.path());
+
+    let err = config(dir.path(), ChecksumMode::Verify).open::<1024>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let corruption = CorruptionError::from_io_error(&err).expect("应当返回结构化的损坏错误");
+    // 默认树的集合ID为1
+    assert_eq!(corruption.collection_id, 1);
+    assert_eq!(corruption.slot_size, slot_size);
+    assert_eq!(corruption.slot, 0);
+    assert!(corruption.object_id > 0);
+    assert!(err.to_string().contains(&format!("{} byte slab", slot_size)));
+}
+
+#[test]
+fn test_quarantine_fails_fast_and_records_event() {
+    let dir = tempfile::tempdir().unwrap();
+    let slot_size = write_and_corrupt(dir.path());
+
+    let db: Db<1024> = config(dir.path(), ChecksumMode::VerifyAndQuarantine).open().unwrap();
+    assert_eq!(db.recovery_report().unwrap().objects_quarantined, 1);
+
+    let quarantined = db.quarantined_objects();
+    assert_eq!(quarantined.len(), 1);
+    let corruption = quarantined[0];
+    assert_eq!(corruption.collection_id, 1);
+    assert_eq!(corruption.slot_size, slot_size);
+
+    let heap_stats = db.stats().cache.heap;
+    assert_eq!(heap_stats.corruption_events, 1);
+    assert_eq!(heap_stats.last_corruption, Some(corruption));
+
+    // 读取被隔离的对象返回同样的错误，且不再读取磁盘
+    for _ in 0..3 {
+        let err = db.get(key(0)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(CorruptionError::from_io_error(&err), Some(&corruption));
+    }
+    assert_eq!(db.stats().cache.heap.corruption_events, 1);
+
+    // 其它树不受影响
+    let small = db.open_tree("small").unwrap();
+    assert_eq!(&*small.get(b"a").unwrap().unwrap(), b"b");
+    small.insert(b"c", b"d").unwrap();
+    db.flush().unwrap();
+}
+
+#[test]
+fn test_checksum_mode_off_skips_verification() {
+    let dir = tempfile::tempdir().unwrap();
+    write_and_corrupt(dir.path());
+
+    let db: Db<1024> = config(dir.path(), ChecksumMode::Off).open().unwrap();
+    assert_eq!(db.recovery_report().unwrap().objects_quarantined, 0);
+    assert!(db.quarantined_objects().is_empty());
+
+    // 损坏的数据不会被识别为校验和错误
+    if let Err(err) = db.get(key(0)) {
+        assert!(CorruptionError::from_io_error(&err).is_none());
+    }
+    assert_eq!(db.stats().cache.heap.corruption_events, 0);
+
+    let small = db.open_tree("small").unwrap();
+    assert_eq!(&*small.get(b"a").unwrap().unwrap(), b"b");
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use fault_injection::FAULT_INJECT_COUNTER;
+use melange_db::*;
+
+use common::copy_dir;
+
+// 注入故障的计数器是全局的，这个文件中的测试依次运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const N: u64 = 20_000;
+
+fn config(path: &Path) -> Config {
+    // 只有显式的flush会写入数据，缓存很小，大部分叶子节点不在缓存中
+    common::config(path).cache_capacity_bytes(64 * 1024)
+}
+
+fn fill(tree: &Tree<1024>, round: u64) {
+    for i in 0..N {
+        tree.insert(i.to_be_bytes(), (i + round).to_le_bytes().as_slice()).unwrap();
+    }
+}
+
+/// 检查树中要么是 `fill` 写入的全部数据，要么为空，返回是否为空
+fn assert_all_or_nothing(tree: &Tree<1024>, round: u64) -> bool {
+    let entries: Vec<_> = tree.iter().collect::<io::Result<_>>().unwrap();
+    if entries.is_empty() {
+        return true;
+    }
+    assert_eq!(entries.len() as u64, N, "partially cleared tree recovered");
+    for (i, (k, v)) in entries.iter().enumerate() {
+        assert_eq!(&**k, (i as u64).to_be_bytes());
+        assert_eq!(&**v, (i as u64 + round).to_le_bytes());
+    }
+    false
+}
+
+#[test]
+fn test_clear_is_durable_after_flush() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, 0);
+    db.flush().unwrap();
+    // 未flush的覆盖写入也会被清除
+    fill(&tree, 1);
+
+    let before = Instant::now();
+    tree.clear().unwrap();
+    let elapsed = before.elapsed();
+    println!("clear of {} keys took {:?}", N, elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+    assert!(tree.is_empty().unwrap());
+    assert_eq!(tree.get(0_u64.to_be_bytes()).unwrap(), None);
+    assert_eq!(tree.iter().next_back().transpose().unwrap(), None);
+
+    tree.insert(b"after", b"clear".as_slice()).unwrap();
+    db.flush().unwrap();
+    drop(tree);
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    let entries: Vec<_> = tree.iter().collect::<io::Result<_>>().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(&*entries[0].0, b"after");
+}
+
+#[test]
+fn test_clear_crash_before_flush() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, 0);
+    db.flush().unwrap();
+
+    tree.clear().unwrap();
+
+    // 在flush之前崩溃，清除没有生效
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(dir.path(), crashed.path());
+    let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+    assert!(!assert_all_or_nothing(&recovered.open_tree("t").unwrap(), 0));
+    drop(recovered);
+
+    // 在flush之后崩溃，数据不会复活
+    db.flush().unwrap();
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(dir.path(), crashed.path());
+    let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+    assert!(assert_all_or_nothing(&recovered.open_tree("t").unwrap(), 0));
+}
+
+#[test]
+fn test_clear_crash_during_flush() {
+    let _serial = SERIAL.lock().unwrap();
+    let mut recovered_full = false;
+    let mut recovered_empty = false;
+
+    for fault_at in 1.. {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let tree = db.open_tree("t").unwrap();
+        fill(&tree, 0);
+        db.flush().unwrap();
+
+        tree.clear().unwrap();
+
+        FAULT_INJECT_COUNTER.store(fault_at, Ordering::Release);
+        let res = db.flush();
+        FAULT_INJECT_COUNTER.store(u64::MAX, Ordering::Release);
+
+        // 在注入故障的时刻崩溃
+        let crashed = tempfile::tempdir().unwrap();
+        copy_dir(dir.path(), crashed.path());
+        if res.is_ok() {
+            drop(tree);
+            drop(db);
+        } else {
+            // 进程在这里崩溃，不再运行关闭时的flush
+            std::mem::forget(tree);
+            std::mem::forget(db);
+        }
+
+        let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+        let empty = assert_all_or_nothing(&recovered.open_tree("t").unwrap(), 0);
+        recovered_full |= !empty;
+        recovered_empty |= empty;
+
+        if res.is_ok() {
+            assert!(empty);
+            break;
+        }
+    }
+
+    assert!(recovered_full);
+    assert!(recovered_empty);
+}
+
+#[test]
+fn test_failed_flush_is_retried() {
+    let _serial = SERIAL.lock().unwrap();
+    let mut retried = 0;
+
+    for fault_at in 1.. {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let tree = db.open_tree("t").unwrap();
+        fill(&tree, 0);
+        db.flush().unwrap();
+
+        tree.clear().unwrap();
+
+        FAULT_INJECT_COUNTER.store(fault_at, Ordering::Release);
+        let res = db.flush();
+        FAULT_INJECT_COUNTER.store(u64::MAX, Ordering::Release);
+        if res.is_ok() {
+            break;
+        }
+
+        // 失败的flush中的修改保留在缓存中，由下一次flush写入。
+        // 元数据日志处于未知状态时数据库不再接受写入
+        tree.insert(b"after", b"retry".as_slice()).ok();
+        if db.flush().is_err() {
+            continue;
+        }
+        retried += 1;
+        drop(tree);
+        drop(db);
+
+        let recovered: Db<1024> = config(dir.path()).open().unwrap();
+        let entries: Vec<_> = recovered
+            .open_tree("t")
+            .unwrap()
+            .iter()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1, "fault injected at {fault_at}");
+        assert_eq!(&*entries[0].0, b"after");
+    }
+
+    assert!(retried > 0);
+}
+
+#[test]
+fn test_clear_and_iterators() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, 0);
+    db.flush().unwrap();
+
+    let mut snapshot = tree.snapshot_iter();
+    let mut started = tree.iter();
+    let first = started.next().unwrap().unwrap();
+    assert_eq!(&*first.0, 0_u64.to_be_bytes());
+    let mut not_started = tree.iter();
+    let mut started_back = tree.iter();
+    started_back.next_back().unwrap().unwrap();
+    let first_snapshot = snapshot.next().unwrap().unwrap();
+
+    tree.clear().unwrap();
+    tree.insert(b"new", b"value".as_slice()).unwrap();
+
+    // 清除的叶子节点被释放之后，它们的存储位置也不会被其他写入复用
+    let other = db.open_tree("other").unwrap();
+    db.flush().unwrap();
+    fill(&other, 1);
+    db.flush().unwrap();
+
+    // 快照迭代器仍然观察到清除之前的全部数据
+    let mut snapshot_len = 1;
+    for kv in snapshot {
+        let (k, v) = kv.unwrap();
+        assert_ne!(&*k, b"new");
+        let i = u64::from_be_bytes(k[..].try_into().unwrap());
+        assert_eq!(&*v, i.to_le_bytes());
+        snapshot_len += 1;
+    }
+    assert_eq!(&*first_snapshot.0, 0_u64.to_be_bytes());
+    assert_eq!(snapshot_len, N);
+
+    // 已经读取了数据的迭代器返回错误，而不是混合清除前后的数据
+    let rest: Vec<_> = started.collect();
+    assert!(rest.iter().any(|kv| kv.is_err()), "{}", rest.len());
+    assert!(rest.last().unwrap().is_err());
+    let rest_back: Vec<_> = started_back.by_ref().rev().collect();
+    assert!(rest_back.last().unwrap().is_err());
+    assert!(started_back.next_back().is_none());
+
+    // 还没有读取数据的迭代器观察到清除之后的树
+    let keys: Vec<_> = not_started.by_ref().map(|kv| kv.unwrap().0).collect();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(&*keys[0], b"new");
+}
+
+#[test]
+fn test_clear_concurrent_writers() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, 0);
+
+    let writer = {
+        let tree = tree.clone();
+        std::thread::spawn(move || {
+            for i in N..2 * N {
+                tree.insert(i.to_be_bytes(), b"v".as_slice()).unwrap();
+                tree.remove((i - N).to_be_bytes()).unwrap();
+            }
+        })
+    };
+
+    for _ in 0..10 {
+        tree.clear().unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    writer.join().unwrap();
+
+    let before = tree.len().unwrap();
+    db.flush().unwrap();
+    drop(tree);
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(db.open_tree("t").unwrap().len().unwrap(), before);
+}
@@ -0,0 +1,356 @@
+mod common;
+
+use std::io;
+
+use melange_db::*;
+
+use common::{config, copy_dir};
+
+const N: u32 = 5_000;
+
+fn entries(tree: &Tree<1024>) -> Vec<(InlineArray, InlineArray)> {
+    tree.iter().collect::<io::Result<_>>().unwrap()
+}
+
+fn fill(tree: &Tree<1024>) {
+    for i in 0..N {
+        tree.insert(i.to_be_bytes(), format!("value_{}", i).into_bytes()).unwrap();
+    }
+}
+
+fn shared_slots(db: &Db<1024>) -> u64 {
+    db.stats().cache.heap.allocator.heap_slots_shared
+}
+
+#[test]
+fn test_clone_is_identical_and_independent() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    for i in 0..N {
+        src.insert(i.to_be_bytes(), format!("value_{}", i).into_bytes()).unwrap();
+    }
+    let original = entries(&src);
+
+    db.clone_tree("src", "dest").unwrap();
+    let dest = db.open_tree("dest").unwrap();
+    assert_eq!(entries(&dest), original);
+
+    // 修改副本不影响源树
+    for i in (0..N).step_by(3) {
+        dest.insert(i.to_be_bytes(), b"changed".as_slice()).unwrap();
+    }
+    for i in (1..N).step_by(3) {
+        dest.remove(i.to_be_bytes()).unwrap();
+    }
+    dest.insert(b"new", b"1".as_slice()).unwrap();
+    let dest_len = N as usize - (1..N).step_by(3).count() + 1;
+    assert_eq!(entries(&src), original);
+    assert_eq!(dest.len().unwrap(), dest_len);
+
+    // 修改源树不影响副本
+    src.clear().unwrap();
+    assert_eq!(dest.get(0_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"changed"[..]));
+
+    drop((src, dest));
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert!(db.open_tree("src").unwrap().is_empty().unwrap());
+    let dest = db.open_tree("dest").unwrap();
+    assert_eq!(dest.len().unwrap(), dest_len);
+    assert_eq!(dest.get(2_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"value_2"[..]));
+}
+
+#[test]
+fn test_clone_reads_a_snapshot() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    for i in 0..N {
+        src.insert(i.to_be_bytes(), i.to_le_bytes()).unwrap();
+    }
+
+    // 复制期间的并发写入不会使副本成为新旧数据的混合
+    let writer = {
+        let src = src.clone();
+        std::thread::spawn(move || {
+            for i in 0..N {
+                src.insert(i.to_be_bytes(), (i + 1).to_le_bytes()).unwrap();
+            }
+        })
+    };
+    db.clone_tree("src", "dest").unwrap();
+    writer.join().unwrap();
+
+    let dest = db.open_tree("dest").unwrap();
+    let values: Vec<u32> = entries(&dest)
+        .iter()
+        .map(|(_, v)| u32::from_le_bytes(v[..].try_into().unwrap()))
+        .collect();
+    assert_eq!(values.len(), N as usize);
+    let updated = values.iter().enumerate().filter(|(i, v)| **v != *i as u32).count();
+    // 写入者按键的顺序更新，快照中被更新的一定是前缀
+    assert!(values.iter().enumerate().take(updated).all(|(i, v)| *v == i as u32 + 1));
+}
+
+#[test]
+fn test_clone_errors() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    src.insert(b"a", b"1".as_slice()).unwrap();
+    db.open_tree("existing").unwrap().insert(b"b", b"2".as_slice()).unwrap();
+
+    let err = db.clone_tree("missing", "dest").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(!db.contains_tree("dest").unwrap());
+    assert!(!db.contains_tree("missing").unwrap());
+
+    let err = db.clone_tree("src", "existing").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(entries(&db.open_tree("existing").unwrap()).len(), 1);
+
+    let err = db.clone_tree("src", "src").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_clone_keeps_write_once_mode() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let ledger = db
+        .open_tree_with_options("ledger", TreeOptions::new().write_once(true))
+        .unwrap();
+    ledger.insert(b"tx_1", b"100".as_slice()).unwrap();
+
+    db.clone_tree("ledger", "ledger_copy").unwrap();
+    let copy = db.open_tree("ledger_copy").unwrap();
+    assert!(copy.is_write_once());
+    assert_eq!(copy.get(b"tx_1").unwrap().as_deref(), Some(&b"100"[..]));
+    assert!(copy.insert(b"tx_1", b"200".as_slice()).is_err());
+    copy.insert(b"tx_2", b"200".as_slice()).unwrap();
+    assert_eq!(ledger.get(b"tx_2").unwrap(), None);
+}
+
+#[test]
+fn test_clone_shares_stored_leaves() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    fill(&src);
+    db.flush().unwrap();
+    let original = entries(&src);
+
+    let dest = db.clone_tree("src", "dest").unwrap();
+    db.flush().unwrap();
+    let shared = shared_slots(&db);
+    assert!(shared > 1, "共享的槽位: {}", shared);
+    assert_eq!(entries(&dest), original);
+
+    // 修改副本的第一个叶子节点，它不再与源树共享
+    dest.insert(0_u32.to_be_bytes(), b"changed".as_slice()).unwrap();
+    db.flush().unwrap();
+    assert_eq!(shared_slots(&db), shared - 1);
+    assert_eq!(entries(&src), original);
+
+    // 修改源树的最后一个叶子节点
+    src.insert((N - 1).to_be_bytes(), b"changed_in_src".as_slice()).unwrap();
+    db.flush().unwrap();
+    assert_eq!(shared_slots(&db), shared - 2);
+    assert_eq!(dest.get((N - 1).to_be_bytes()).unwrap().as_deref(), Some(&b"value_4999"[..]));
+
+    drop((src, dest));
+    drop(db);
+
+    // 共享关系在重新打开后从元数据中恢复
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(shared_slots(&db), shared - 2);
+    let src = db.open_tree("src").unwrap();
+    let dest = db.open_tree("dest").unwrap();
+    assert_eq!(src.get(0_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"value_0"[..]));
+    assert_eq!(dest.get(0_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"changed"[..]));
+    assert_eq!(src.len().unwrap(), N as usize);
+    assert_eq!(dest.len().unwrap(), N as usize);
+    db.check().unwrap();
+}
+
+#[test]
+fn test_clone_does_not_read_uncached_leaves() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        fill(&db.open_tree("src").unwrap());
+    }
+
+    // 重新打开后叶子节点都不在缓存中，克隆时只共享它们的槽位而不读取
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    let misses = db.stats().cache.cache_misses;
+    let dest = db.clone_tree("src", "dest").unwrap();
+    assert_eq!(db.stats().cache.cache_misses, misses);
+
+    db.flush().unwrap();
+    assert!(shared_slots(&db) > 1);
+    assert_eq!(entries(&dest), entries(&src));
+}
+
+#[test]
+fn test_dropping_either_tree_keeps_shared_leaves() {
+    for dropped in ["src", "dest"] {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let src = db.open_tree("src").unwrap();
+        fill(&src);
+        db.flush().unwrap();
+        let original = entries(&src);
+        drop(src);
+
+        db.clone_tree("src", "dest").unwrap();
+        db.flush().unwrap();
+        assert!(db.drop_tree(dropped).unwrap());
+        db.flush().unwrap();
+        db.collect_garbage();
+        assert_eq!(shared_slots(&db), 0);
+
+        // 被释放的槽位会被其他树的写入复用，仍然被引用的槽位不能被覆盖
+        let other = db.open_tree("other").unwrap();
+        for round in 0..3_u32 {
+            for i in 0..N {
+                other.insert(i.to_be_bytes(), vec![round as u8; 64]).unwrap();
+            }
+            db.flush().unwrap();
+            db.collect_garbage();
+        }
+
+        let kept = if dropped == "src" { "dest" } else { "src" };
+        assert_eq!(entries(&db.open_tree(kept).unwrap()), original, "删除了 {}", dropped);
+        drop(other);
+        drop(db);
+
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        assert!(!db.contains_tree(dropped).unwrap());
+        assert_eq!(entries(&db.open_tree(kept).unwrap()), original, "删除了 {}", dropped);
+        db.check().unwrap();
+    }
+}
+
+#[test]
+fn test_crash_after_clone() {
+    let dir = tempfile::tempdir().unwrap();
+    let clone_flushed = tempfile::tempdir().unwrap();
+    let clone_unflushed = tempfile::tempdir().unwrap();
+    let original;
+    {
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let src = db.open_tree("src").unwrap();
+        fill(&src);
+        db.flush().unwrap();
+        original = entries(&src);
+
+        // 在克隆返回时崩溃
+        let dest = db.clone_tree("src", "dest").unwrap();
+        copy_dir(dir.path(), clone_unflushed.path());
+
+        // 在克隆持久化之后、第一次分叉的写入之前崩溃
+        db.flush().unwrap();
+        dest.insert(0_u32.to_be_bytes(), b"changed".as_slice()).unwrap();
+        src.remove(1_u32.to_be_bytes()).unwrap();
+        copy_dir(dir.path(), clone_flushed.path());
+
+        // 进程在这里崩溃，不再运行关闭时的flush
+        std::mem::forget((src, dest));
+        std::mem::forget(db);
+    }
+
+    // 副本要么不存在，要么是完整的
+    let db: Db<1024> = config(clone_unflushed.path()).open().unwrap();
+    assert_eq!(entries(&db.open_tree("src").unwrap()), original);
+    if db.contains_tree("dest").unwrap() {
+        assert_eq!(entries(&db.open_tree("dest").unwrap()), original);
+    } else {
+        assert_eq!(shared_slots(&db), 0);
+    }
+    db.check().unwrap();
+    drop(db);
+
+    let db: Db<1024> = config(clone_flushed.path()).open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    let dest = db.open_tree("dest").unwrap();
+    assert_eq!(entries(&src), original);
+    assert_eq!(entries(&dest), original);
+    assert!(shared_slots(&db) > 0);
+    db.check().unwrap();
+
+    // 恢复之后两个树仍然可以分叉，删除源树不影响副本
+    dest.insert(0_u32.to_be_bytes(), b"changed".as_slice()).unwrap();
+    src.clear().unwrap();
+    db.flush().unwrap();
+    drop(src);
+    assert!(db.drop_tree("src").unwrap());
+    drop((dest, db));
+
+    let db: Db<1024> = config(clone_flushed.path()).open().unwrap();
+    let dest = db.open_tree("dest").unwrap();
+    assert_eq!(dest.len().unwrap(), N as usize);
+    assert_eq!(dest.get(0_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"changed"[..]));
+    assert_eq!(dest.get(1_u32.to_be_bytes()).unwrap().as_deref(), Some(&b"value_1"[..]));
+    assert_eq!(shared_slots(&db), 0);
+    db.check().unwrap();
+}
+
+#[test]
+fn test_clone_with_separately_stored_values() {
+    let db: Db<1024> = Config::tmp().unwrap().inline_value_threshold(64).open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    src.insert(b"large", vec![7_u8; 1000]).unwrap();
+    src.insert(b"small", b"v".as_slice()).unwrap();
+    db.flush().unwrap();
+
+    // 单独存储的值不能被共享，退回到流式复制
+    let dest = db.clone_tree("src", "dest").unwrap();
+    assert_eq!(shared_slots(&db), 0);
+    db.drop_tree("src").unwrap();
+    assert_eq!(dest.get(b"large").unwrap().unwrap(), vec![7_u8; 1000]);
+    assert_eq!(dest.get(b"small").unwrap().as_deref(), Some(&b"v"[..]));
+}
+
+#[test]
+fn test_rename_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("users_v1").unwrap();
+    tree.insert(b"alice", b"1".as_slice()).unwrap();
+
+    db.rename_tree("users_v1", "users_v2").unwrap();
+    assert!(!db.contains_tree("users_v1").unwrap());
+    assert!(db.contains_tree("users_v2").unwrap());
+
+    // 已经打开的句柄指向同一个树
+    tree.insert(b"bob", b"2".as_slice()).unwrap();
+    let renamed = db.open_tree("users_v2").unwrap();
+    assert_eq!(renamed.get(b"bob").unwrap().as_deref(), Some(&b"2"[..]));
+
+    // 旧名称可以用于新的树
+    assert!(db.open_tree("users_v1").unwrap().is_empty().unwrap());
+    drop((tree, renamed));
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(db.open_tree("users_v2").unwrap().len().unwrap(), 2);
+    assert!(db.open_tree("users_v1").unwrap().is_empty().unwrap());
+    db.check().unwrap();
+}
+
+#[test]
+fn test_rename_tree_errors() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    db.open_tree("a").unwrap().insert(b"k", b"a".as_slice()).unwrap();
+    db.open_tree("b").unwrap().insert(b"k", b"b".as_slice()).unwrap();
+
+    let err = db.rename_tree("missing", "c").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(!db.contains_tree("c").unwrap());
+
+    let err = db.rename_tree("a", "b").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(db.open_tree("a").unwrap().get(b"k").unwrap().as_deref(), Some(&b"a"[..]));
+    assert_eq!(db.open_tree("b").unwrap().get(b"k").unwrap().as_deref(), Some(&b"b"[..]));
+}
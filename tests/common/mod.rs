@@ -0,0 +1,35 @@
+//! 集成测试共用的辅助函数
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+use melange_db::Config;
+
+/// 只有显式的flush会写入数据的配置：没有后台flusher，也不使用智能flush
+pub fn config(path: &Path) -> Config {
+    let mut config = Config::new().path(path).flush_every_ms(None);
+    config.smart_flush_config.enabled = false;
+    config
+}
+
+/// 与 `config` 相同，使用关闭时删除的临时目录
+pub fn tmp_config() -> Config {
+    let mut config = Config::tmp().unwrap().flush_every_ms(None);
+    config.smart_flush_config.enabled = false;
+    config
+}
+
+/// 递归复制目录，用于在数据库的副本上模拟崩溃或损坏
+pub fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use melange_db::*;
+
+const N: u32 = 4_000;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("user:{:08}", i).into_bytes()
+}
+
+/// 100到300字节之间、结构相同的小JSON值
+fn json_value(i: u32, generation: u32) -> Vec<u8> {
+    format!(
+        r#"{{"id":{},"name":"user_{}","email":"user_{}@example.com","status":"active","roles":["reader","writer"],"generation":{},"settings":{{"theme":"dark","language":"zh-CN","notifications":true}},"score":{}}}"#,
+        i,
+        i,
+        i,
+        generation,
+        (i * 7919) % 1000
+    )
+    .into_bytes()
+}
+
+fn config(path: &std::path::Path, dictionary: Option<CompressionDictionary>) -> Config {
+    common::config(path).compression_dictionary(dictionary)
+}
+
+fn train() -> CompressionDictionary {
+    CompressionDictionary::Train {
+        sample_bytes: 64 * 1024,
+        max_dict_size: 16 * 1024,
+    }
+}
+
+fn write_generation(db: &Db<16>, generation: u32) {
+    for i in 0..N {
+        db.insert(key(i), json_value(i, generation)).unwrap();
+    }
+    db.flush().unwrap();
+}
+
+fn verify_generation(db: &Db<16>, generation: u32) {
+    assert_eq!(db.len().unwrap(), N as usize);
+    for i in 0..N {
+        let value = db.get(key(i)).unwrap().unwrap();
+        assert_eq!(&*value, json_value(i, generation).as_slice(), "键 {} 的值不正确", i);
+    }
+}
+
+/// 写入两代数据并返回写入第二代时的压缩统计。第一代数据用于训练字典
+fn run_workload(path: &std::path::Path, dictionary: Option<CompressionDictionary>) -> Vec<DictionaryStats> {
+    let db: Db<16> = config(path, dictionary).open().unwrap();
+    write_generation(&db, 0);
+    let before = db.compression_stats();
+    write_generation(&db, 1);
+    verify_generation(&db, 1);
+
+    db.compression_stats()
+        .into_iter()
+        .map(|stats| {
+            let old = before
+                .iter()
+                .find(|b| b.dictionary_id == stats.dictionary_id)
+                .cloned()
+                .unwrap_or_default();
+            DictionaryStats {
+                leaves_written: stats.leaves_written - old.leaves_written,
+                uncompressed_bytes: stats.uncompressed_bytes - old.uncompressed_bytes,
+                compressed_bytes: stats.compressed_bytes - old.compressed_bytes,
+                ..stats
+            }
+        })
+        .filter(|stats| stats.leaves_written > 0)
+        .collect()
+}
+
+fn total_compressed(stats: &[DictionaryStats]) -> u64 {
+    stats.iter().map(|s| s.compressed_bytes).sum()
+}
+
+#[test]
+fn test_trained_dictionary_improves_small_json_compression() {
+    let plain_dir = tempfile::tempdir().unwrap();
+    let plain = run_workload(plain_dir.path(), None);
+    assert_eq!(plain.len(), 1);
+    assert_eq!(plain[0].dictionary_id, None);
+
+    let trained_dir = tempfile::tempdir().unwrap();
+    let trained = run_workload(trained_dir.path(), Some(train()));
+
+    // 第二代数据的所有叶子节点都使用训练好的字典
+    assert_eq!(trained.len(), 1, "{:?}", trained);
+    let dictionary = &trained[0];
+    assert_eq!(dictionary.dictionary_id, Some(1));
+    assert!(dictionary.dictionary_bytes > 0);
+    assert!(dictionary.dictionary_bytes <= 16 * 1024);
+
+    let plain_ratio = plain[0].compression_ratio();
+    let trained_ratio = dictionary.compression_ratio();
+    println!("不使用字典的压缩比: {:.2}, 使用训练字典的压缩比: {:.2}", plain_ratio, trained_ratio);
+    assert!(
+        trained_ratio > plain_ratio * 1.5,
+        "训练字典的压缩比 {:.2} 没有明显优于不使用字典的 {:.2}",
+        trained_ratio,
+        plain_ratio
+    );
+
+    // 写入磁盘的叶子节点明显更小
+    assert!(total_compressed(&trained) * 3 < total_compressed(&plain) * 2);
+
+    // 字典已持久化，重新打开时直接使用而不再训练
+    let db: Db<16> = config(trained_dir.path(), Some(train())).open().unwrap();
+    verify_generation(&db, 1);
+    let stats = db.compression_stats();
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[1].dictionary_id, Some(1));
+    write_generation(&db, 2);
+    let stats = db.compression_stats();
+    assert_eq!(stats.len(), 2);
+    assert!(stats[1].leaves_written > 0);
+}
+
+#[test]
+fn test_recovery_with_mixed_dictionary_generations() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // 第一代：不使用字典
+    {
+        let db: Db<16> = config(dir.path(), None).open().unwrap();
+        write_generation(&db, 0);
+    }
+
+    // 第二代：只覆盖一部分键，使用静态字典
+    let static_dictionary = zstd::dict::from_samples(
+        &(0..1_000).map(|i| json_value(i, 9)).collect::<Vec<_>>(),
+        8 * 1024,
+    )
+    .unwrap();
+    {
+        let db: Db<16> = config(
+            dir.path(),
+            Some(CompressionDictionary::Static(static_dictionary.clone())),
+        )
+        .open()
+        .unwrap();
+        for i in (0..N).filter(|i| i % 3 == 1) {
+            db.insert(key(i), json_value(i, 1)).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    // 第三代：训练新的字典并覆盖另一部分键
+    {
+        let db: Db<16> = config(dir.path(), Some(train())).open().unwrap();
+        for round in 0..3 {
+            for i in (0..N).filter(|i| i % 3 == 2) {
+                db.insert(key(i), json_value(i, 2 + round)).unwrap();
+            }
+            db.flush().unwrap();
+        }
+        for i in (0..N).filter(|i| i % 3 == 2) {
+            db.insert(key(i), json_value(i, 2)).unwrap();
+        }
+        db.flush().unwrap();
+
+        let ids: Vec<Option<u32>> =
+            db.compression_stats().iter().map(|s| s.dictionary_id).collect();
+        assert_eq!(ids, vec![None, Some(1), Some(2)]);
+    }
+
+    let expected = |i: u32| json_value(i, i % 3);
+
+    // 去掉字典配置后所有代的数据仍然可以读取
+    for dictionary in [None, Some(CompressionDictionary::Static(static_dictionary)), Some(train())] {
+        let db: Db<16> = config(dir.path(), dictionary).open().unwrap();
+        assert!(db.was_recovered());
+        assert_eq!(db.len().unwrap(), N as usize);
+        for i in 0..N {
+            let value = db.get(key(i)).unwrap().unwrap();
+            assert_eq!(&*value, expected(i).as_slice(), "键 {} 的值不正确", i);
+        }
+
+        // 重新打开不会产生新的字典
+        assert_eq!(db.compression_stats().len(), 3);
+    }
+}
+
+#[test]
+fn test_corrupted_dictionary_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<16> = config(dir.path(), Some(train())).open().unwrap();
+        write_generation(&db, 0);
+        write_generation(&db, 1);
+    }
+
+    let path = dir.path().join("dictionaries").join("00000001.dict");
+    let mut data = std::fs::read(&path).unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 0xFF;
+    std::fs::write(&path, data).unwrap();
+
+    let err = config(dir.path(), None).open::<16>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
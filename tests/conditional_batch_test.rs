@@ -0,0 +1,140 @@
+use melange_db::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[test]
+fn test_guarded_batch_applies_when_guards_hold() {
+    let config = Config::tmp().unwrap();
+    let db = config.open::<1024>().unwrap();
+
+    db.insert(b"version", b"1".as_slice()).unwrap();
+
+    let mut batch = Batch::default();
+    batch.guard(b"version".as_slice(), Some(b"1".as_slice()));
+    batch.guard(b"lock".as_slice(), None as Option<&[u8]>);
+    batch.insert(b"version".as_slice(), b"2".as_slice());
+    batch.insert(b"data".as_slice(), b"payload".as_slice());
+
+    db.apply_batch(batch).unwrap();
+
+    assert_eq!(db.get(b"version").unwrap(), Some(InlineArray::from(b"2".as_slice())));
+    assert_eq!(db.get(b"data").unwrap(), Some(InlineArray::from(b"payload".as_slice())));
+}
+
+#[test]
+fn test_guarded_batch_rejected_when_guard_fails() {
+    let config = Config::tmp().unwrap();
+    let db = config.open::<1024>().unwrap();
+
+    db.insert(b"a", b"1".as_slice()).unwrap();
+    db.insert(b"b", b"current".as_slice()).unwrap();
+
+    let mut batch = Batch::default();
+    batch.guard(b"a".as_slice(), Some(b"1".as_slice()));
+    batch.guard(b"b".as_slice(), Some(b"stale".as_slice()));
+    batch.guard(b"c".as_slice(), Some(b"missing".as_slice()));
+    batch.insert(b"a".as_slice(), b"2".as_slice());
+    batch.remove(b"b".as_slice());
+    batch.insert(b"d".as_slice(), b"new".as_slice());
+
+    let err = db.apply_batch(batch).unwrap_err();
+    let guard_error = BatchGuardError::from_io_error(&err).unwrap();
+
+    // 按键顺序报告第一个未满足的守卫条件
+    assert_eq!(guard_error.key, InlineArray::from(b"b".as_slice()));
+    assert_eq!(guard_error.expected, Some(InlineArray::from(b"stale".as_slice())));
+    assert_eq!(guard_error.current, Some(InlineArray::from(b"current".as_slice())));
+
+    // 没有任何写入被应用
+    assert_eq!(db.get(b"a").unwrap(), Some(InlineArray::from(b"1".as_slice())));
+    assert_eq!(db.get(b"b").unwrap(), Some(InlineArray::from(b"current".as_slice())));
+    assert_eq!(db.get(b"d").unwrap(), None);
+
+    // 数据库仍然可以正常使用
+    db.insert(b"e", b"ok".as_slice()).unwrap();
+    db.check_error().unwrap();
+}
+
+#[test]
+fn test_racing_guarded_batches_never_partially_apply() {
+    let config = Config::tmp().unwrap();
+    // 较小的叶子节点让键分布在多个叶子上
+    let db = config.open::<4>().unwrap();
+
+    let keys: Vec<String> = (0..16).map(|i| format!("key_{:02}", i)).collect();
+
+    let mut init = Batch::default();
+    init.insert(b"version".as_slice(), 0u64.to_be_bytes().as_slice());
+    for key in &keys {
+        init.insert(key.as_bytes(), 0u64.to_be_bytes().as_slice());
+    }
+    db.apply_batch(init).unwrap();
+
+    let successes = Arc::new(AtomicU64::new(0));
+    let rejections = Arc::new(AtomicU64::new(0));
+
+    let handles: Vec<_> = (0..2)
+        .map(|thread_id| {
+            let db = db.clone();
+            let keys = keys.clone();
+            let successes = successes.clone();
+            let rejections = rejections.clone();
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    let current = db.get(b"version").unwrap().unwrap();
+                    let version = u64::from_be_bytes(current.as_ref().try_into().unwrap());
+                    let next = (version + 1).to_be_bytes();
+
+                    // 两个线程写入重叠的键区间
+                    let range = if thread_id == 0 { &keys[..12] } else { &keys[4..] };
+
+                    let mut batch = Batch::default();
+                    batch.guard(b"version".as_slice(), Some(current.as_ref()));
+                    batch.insert(b"version".as_slice(), next.as_slice());
+                    for key in range {
+                        batch.insert(key.as_bytes(), next.as_slice());
+                    }
+
+                    match db.apply_batch(batch) {
+                        Ok(()) => {
+                            successes.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Err(e) => {
+                            assert!(BatchGuardError::from_io_error(&e).is_some(), "{:?}", e);
+                            rejections.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // 每次成功的批次都恰好把版本号加一
+    let version = u64::from_be_bytes(
+        db.get(b"version").unwrap().unwrap().as_ref().try_into().unwrap(),
+    );
+    assert_eq!(version, successes.load(Ordering::SeqCst));
+    assert_eq!(successes.load(Ordering::SeqCst) + rejections.load(Ordering::SeqCst), 400);
+
+    // 最后一次成功的批次写入的所有键都必须带有最终版本号，
+    // 任何键的版本号都不能超过最终版本号
+    let values: Vec<u64> = keys
+        .iter()
+        .map(|key| {
+            u64::from_be_bytes(db.get(key.as_bytes()).unwrap().unwrap().as_ref().try_into().unwrap())
+        })
+        .collect();
+    assert!(values.iter().all(|v| *v <= version));
+    assert!(
+        values[..12].iter().all(|v| *v == version) || values[4..].iter().all(|v| *v == version),
+        "{:?} version {}",
+        values,
+        version
+    );
+    // 两个线程共同写入的键总是由最后一次成功的批次写入
+    assert!(values[4..12].iter().all(|v| *v == version));
+}
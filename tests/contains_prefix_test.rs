@@ -0,0 +1,71 @@
+use melange_db::*;
+
+const FANOUT: usize = 8;
+
+fn open() -> Db<FANOUT> {
+    Config::tmp().unwrap().flush_every_ms(None).open().unwrap()
+}
+
+#[test]
+fn test_contains_prefix_populated_and_empty() {
+    let db = open();
+    assert!(!db.contains_prefix(b"").unwrap());
+
+    for i in 0..500 {
+        db.insert(format!("user:{:04}:name", i), vec![1; 16]).unwrap();
+        db.insert(format!("order:{:04}", i), vec![2; 16]).unwrap();
+    }
+
+    for prefix in ["", "user:", "user:0042:", "user:0042:name", "order:", "order:049", "o"] {
+        assert!(db.contains_prefix(prefix).unwrap(), "{}", prefix);
+        assert!(db.scan_prefix(prefix).next().is_some());
+    }
+    for prefix in ["usr", "user:0500", "user:0042:name:", "a", "zzz", "order;", "order:0499x"] {
+        assert!(!db.contains_prefix(prefix).unwrap(), "{}", prefix);
+        assert!(db.scan_prefix(prefix).next().is_none());
+    }
+}
+
+#[test]
+fn test_contains_prefix_skips_empty_leaves() {
+    let db = open();
+    for i in 0..400 {
+        db.insert(format!("p:{:04}", i), vec![0; 16]).unwrap();
+    }
+    db.insert(b"q", vec![0; 16]).unwrap();
+
+    // 删除前面的大部分键，前缀的前几个叶子节点变为空
+    for i in 0..390 {
+        db.remove(format!("p:{:04}", i)).unwrap();
+    }
+    assert!(db.contains_prefix(b"p:").unwrap());
+    assert!(db.contains_prefix(b"p:03").unwrap());
+    assert!(!db.contains_prefix(b"p:00").unwrap());
+
+    for i in 390..400 {
+        db.remove(format!("p:{:04}", i)).unwrap();
+    }
+    assert!(!db.contains_prefix(b"p:").unwrap());
+    assert!(!db.contains_prefix(b"p").unwrap());
+    assert!(db.contains_prefix(b"q").unwrap());
+}
+
+#[test]
+fn test_contains_prefix_touches_few_leaves() {
+    let db = open();
+    for i in 0..2_000 {
+        db.insert(format!("k:{:05}", i), vec![(i % 251) as u8; 1024]).unwrap();
+    }
+    // 所有叶子节点都在缓存中
+    assert_eq!(db.iter().count(), 2_000);
+
+    let before = db.stats().cache;
+    assert!(db.contains_prefix(b"k:").unwrap());
+    assert!(db.contains_prefix(b"k:01").unwrap());
+    assert!(!db.contains_prefix(b"k:9").unwrap());
+    let after = db.stats().cache;
+
+    // 只访问了几个缓存中的叶子节点，没有从磁盘读取和解压任何叶子节点
+    assert_eq!(after.cache_misses, before.cache_misses);
+    assert!(after.cache_hits - before.cache_hits <= 6, "{}", after.cache_hits - before.cache_hits);
+}
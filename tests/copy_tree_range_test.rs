@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::ops::Bound;
+
+use melange_db::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+fn contents<const LEAF_FANOUT: usize>(tree: &Tree<LEAF_FANOUT>) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    tree.iter()
+        .map(|kv| {
+            let (k, v) = kv.unwrap();
+            (k.to_vec(), v.to_vec())
+        })
+        .collect()
+}
+
+/// `src` 写入 0..count 的偶数键，`dst` 写入3的倍数的键，两者部分交错
+fn setup(db: &Db, count: u32) -> (Tree, Tree) {
+    let src = db.open_tree("src").unwrap();
+    let dst = db.open_tree("dst").unwrap();
+    for i in (0..count).step_by(2) {
+        src.insert(key(i), b"src".as_slice()).unwrap();
+    }
+    for i in (0..count).step_by(3) {
+        dst.insert(key(i), b"dst".as_slice()).unwrap();
+    }
+    (src, dst)
+}
+
+#[test]
+fn test_copy_tree_range_skip() {
+    let db: Db = Config::tmp().unwrap().open().unwrap();
+    // 跨越多个批次
+    let (src, dst) = setup(&db, 5_000);
+
+    let stats = db.copy_tree_range::<&[u8], _>(&src, &dst, .., ConflictPolicy::Skip).unwrap();
+    // 6的倍数同时存在于两个树中
+    assert_eq!(stats.skipped, 834);
+    assert_eq!(stats.copied, 2_500 - 834);
+    assert_eq!(stats.overwritten, 0);
+    assert_eq!(stats.last_copied_key.as_deref(), Some(&key(4_998)[..]));
+
+    for i in 0..5_000 {
+        let expected: Option<&[u8]> = match (i % 2 == 0, i % 3 == 0) {
+            (_, true) => Some(b"dst"),
+            (true, false) => Some(b"src"),
+            (false, false) => None,
+        };
+        assert_eq!(dst.get(key(i)).unwrap().as_deref(), expected, "{}", i);
+    }
+}
+
+#[test]
+fn test_copy_tree_range_overwrite() {
+    let db: Db = Config::tmp().unwrap().open().unwrap();
+    let (src, dst) = setup(&db, 5_000);
+
+    let stats = db.copy_tree_range::<&[u8], _>(&src, &dst, .., ConflictPolicy::Overwrite).unwrap();
+    assert_eq!(stats.copied, 2_500);
+    assert_eq!(stats.overwritten, 834);
+    assert_eq!(stats.skipped, 0);
+
+    for i in 0..5_000 {
+        let expected: Option<&[u8]> = match (i % 2 == 0, i % 3 == 0) {
+            (true, _) => Some(b"src"),
+            (false, true) => Some(b"dst"),
+            (false, false) => None,
+        };
+        assert_eq!(dst.get(key(i)).unwrap().as_deref(), expected, "{}", i);
+    }
+}
+
+#[test]
+fn test_copy_tree_range_error() {
+    let db: Db = Config::tmp().unwrap().open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    let dst = db.open_tree("dst").unwrap();
+    for i in 0..3_000 {
+        src.insert(key(i), b"src".as_slice()).unwrap();
+    }
+    dst.insert(key(2_500), b"dst".as_slice()).unwrap();
+
+    let err = db.copy_tree_range::<&[u8], _>(&src, &dst, .., ConflictPolicy::Error).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(&*KeyAlreadyExists::from_io_error(&err).unwrap().key, &key(2_500));
+
+    // 冲突的键之前的条目都已复制，之后的都没有
+    assert_eq!(dst.len().unwrap(), 2_501);
+    assert_eq!(dst.get(key(2_499)).unwrap().as_deref(), Some(&b"src"[..]));
+    assert_eq!(dst.get(key(2_500)).unwrap().as_deref(), Some(&b"dst"[..]));
+    assert_eq!(dst.get(key(2_501)).unwrap(), None);
+
+    // 解决冲突后从冲突的键继续
+    dst.remove(key(2_500)).unwrap();
+    let stats = db
+        .copy_tree_range(&src, &dst, key(2_500).., ConflictPolicy::Error)
+        .unwrap();
+    assert_eq!(stats.copied, 500);
+    assert_eq!(contents(&dst), contents(&src));
+}
+
+#[test]
+fn test_copy_tree_range_resumes_after_last_copied_key() {
+    let db: Db = Config::tmp().unwrap().open().unwrap();
+    let (src, dst) = setup(&db, 4_000);
+    let expected = {
+        let reference = db.open_tree("reference").unwrap();
+        for kv in dst.iter() {
+            let (k, v) = kv.unwrap();
+            reference.insert(k, v).unwrap();
+        }
+        db.copy_tree_range::<&[u8], _>(&src, &reference, .., ConflictPolicy::Skip).unwrap();
+        contents(&reference)
+    };
+
+    // 分几次复制，每次从上一次的 `last_copied_key` 之后继续
+    let mut total = CopyStats::default();
+    let mut start: Bound<Vec<u8>> = Bound::Unbounded;
+    for end in [1_000_u32, 1_001, 2_500, 4_000] {
+        let range = (start.clone(), Bound::Excluded(key(end).to_vec()));
+        let stats = db.copy_tree_range(&src, &dst, range, ConflictPolicy::Skip).unwrap();
+        total.copied += stats.copied;
+        total.skipped += stats.skipped;
+        if let Some(last) = stats.last_copied_key {
+            start = Bound::Excluded(last.to_vec());
+        }
+    }
+
+    assert_eq!(total.copied + total.skipped, 2_000);
+    assert_eq!(contents(&dst), expected);
+
+    // 从最后的键之后继续时没有剩余的条目
+    let stats = db.copy_tree_range(&src, &dst, (start, Bound::Unbounded), ConflictPolicy::Skip).unwrap();
+    assert_eq!(stats, CopyStats::default());
+}
+
+/// 逐条复制，作为 `copy_tree_range` 的参照
+fn naive_copy(
+    src: &BTreeMap<Vec<u8>, Vec<u8>>,
+    dst: &mut BTreeMap<Vec<u8>, Vec<u8>>,
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    policy: ConflictPolicy,
+) -> Result<(), Vec<u8>> {
+    for (k, v) in src.range(range) {
+        if dst.contains_key(k) {
+            match policy {
+                ConflictPolicy::Skip => continue,
+                ConflictPolicy::Overwrite => {}
+                ConflictPolicy::Error => return Err(k.clone()),
+            }
+        }
+        dst.insert(k.clone(), v.clone());
+    }
+    Ok(())
+}
+
+fn random_bound(rng: &mut StdRng) -> Bound<Vec<u8>> {
+    let k = rng.random_range(0..512_u16).to_be_bytes().to_vec();
+    match rng.random_range(0..3) {
+        0 => Bound::Unbounded,
+        1 => Bound::Included(k),
+        _ => Bound::Excluded(k),
+    }
+}
+
+#[test]
+fn test_copy_tree_range_matches_naive_copy() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let db: Db<16> = Config::tmp().unwrap().open().unwrap();
+
+    for round in 0..30 {
+        let src = db.open_tree(format!("src_{}", round)).unwrap();
+        let dst = db.open_tree(format!("dst_{}", round)).unwrap();
+        let mut expected_src = BTreeMap::new();
+        let mut expected_dst = BTreeMap::new();
+        for _ in 0..rng.random_range(0..300) {
+            let k = rng.random_range(0..512_u16).to_be_bytes().to_vec();
+            let v = vec![rng.random::<u8>(); rng.random_range(0..20)];
+            src.insert(&k, v.clone()).unwrap();
+            expected_src.insert(k, v);
+        }
+        for _ in 0..rng.random_range(0..300) {
+            let k = rng.random_range(0..512_u16).to_be_bytes().to_vec();
+            let v = vec![rng.random::<u8>(); rng.random_range(0..20)];
+            dst.insert(&k, v.clone()).unwrap();
+            expected_dst.insert(k, v);
+        }
+
+        let policy = [ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Error]
+            [rng.random_range(0..3)];
+        let range = (random_bound(&mut rng), random_bound(&mut rng));
+        // 空的范围
+        if let (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) =
+            &range
+            && s >= e
+        {
+            continue;
+        }
+
+        let expected = naive_copy(&expected_src, &mut expected_dst, range.clone(), policy);
+        let result = db.copy_tree_range(&src, &dst, range, policy);
+        match expected {
+            Ok(()) => {
+                result.unwrap();
+            }
+            Err(conflict) => {
+                let err = result.unwrap_err();
+                assert_eq!(&*KeyAlreadyExists::from_io_error(&err).unwrap().key, &conflict[..]);
+            }
+        }
+
+        assert_eq!(contents(&dst), expected_dst, "round {} {:?}", round, policy);
+    }
+}
+
+#[test]
+fn test_copy_tree_range_rejects_invalid_trees() {
+    let db: Db = Config::tmp().unwrap().open().unwrap();
+    let other: Db = Config::tmp().unwrap().open().unwrap();
+    let src = db.open_tree("src").unwrap();
+    src.insert("k", "v").unwrap();
+
+    let err = db.copy_tree_range::<&[u8], _>(&src, &src, .., ConflictPolicy::Overwrite).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let foreign = other.open_tree("dst").unwrap();
+    let err = db.copy_tree_range::<&[u8], _>(&src, &foreign, .., ConflictPolicy::Overwrite).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(foreign.is_empty().unwrap());
+}
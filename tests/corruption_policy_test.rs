@@ -0,0 +1,80 @@
+#![cfg(feature = "for-internal-testing-only")]
+
+mod common;
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use melange_db::*;
+
+const N: u32 = 200;
+
+fn config(path: &Path, policy: CorruptionPolicy) -> Config {
+    common::config(path).on_corruption(policy)
+}
+
+/// 写入数据后重新打开，使叶子节点不在缓存中，然后损坏第一个键所在的叶子节点。
+/// 返回损坏的键和另一个叶子节点中的键
+fn open_corrupted(path: &Path, policy: CorruptionPolicy) -> (Db<16>, Vec<u8>, Vec<u8>) {
+    {
+        let db: Db<16> = config(path, CorruptionPolicy::Error).open().unwrap();
+        for i in 0..N {
+            db.insert(i.to_be_bytes(), vec![i as u8; 100]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    let db: Db<16> = config(path, policy).open().unwrap();
+    let entries: Vec<(InlineArray, SlabAddress)> = db.debug_entries().unwrap().collect();
+    let (corrupted, address) = entries[0].clone();
+    let (intact, _) = entries.iter().find(|(_, other)| *other != address).unwrap().clone();
+    db.corrupt_slot_for_testing(address).unwrap();
+    (db, corrupted.to_vec(), intact.to_vec())
+}
+
+#[test]
+fn test_error_policy_returns_corruption_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let (db, corrupted, intact) = open_corrupted(dir.path(), CorruptionPolicy::default());
+
+    let err = db.get(&corrupted).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let corruption = *CorruptionError::from_io_error(&err).unwrap();
+    assert_eq!(corruption.collection_id, 1);
+    assert_eq!(db.stats().cache.heap.last_corruption, Some(corruption));
+
+    // 其他叶子节点照常读取
+    assert_eq!(db.get(&intact).unwrap().unwrap().len(), 100);
+}
+
+#[test]
+fn test_panic_policy_panics_on_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let (db, corrupted, _) = open_corrupted(dir.path(), CorruptionPolicy::Panic);
+
+    let panicked = catch_unwind(AssertUnwindSafe(|| db.get(&corrupted))).unwrap_err();
+    let message = panicked.downcast_ref::<String>().unwrap();
+    assert!(message.contains("checksum"), "{}", message);
+    assert_eq!(db.stats().cache.heap.corruption_events, 1);
+}
+
+#[test]
+fn test_callback_policy_is_invoked_before_the_error_is_returned() {
+    let dir = tempfile::tempdir().unwrap();
+    let reported: Arc<Mutex<Vec<CorruptionError>>> = Arc::default();
+    let policy = {
+        let reported = reported.clone();
+        CorruptionPolicy::Callback(Arc::new(move |error: &CorruptionError| {
+            reported.lock().unwrap().push(*error);
+        }))
+    };
+    let (db, corrupted, intact) = open_corrupted(dir.path(), policy);
+
+    assert_eq!(db.get(&intact).unwrap().unwrap().len(), 100);
+    assert!(reported.lock().unwrap().is_empty());
+
+    let err = db.get(&corrupted).unwrap_err();
+    let corruption = *CorruptionError::from_io_error(&err).unwrap();
+    assert_eq!(*reported.lock().unwrap(), vec![corruption]);
+}
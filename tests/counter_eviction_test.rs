@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const CAP: usize = 100;
+const COUNTERS: u64 = 1_000;
+
+fn name(i: u64) -> String {
+    format!("counter_{:05}", i)
+}
+
+fn open(path: &std::path::Path) -> Arc<Db<1024>> {
+    Arc::new(Config::new().path(path).flush_every_ms(None).open().unwrap())
+}
+
+fn populate(manager: &HybridOperationsManager) {
+    for i in 0..COUNTERS {
+        manager.increment(name(i), i + 1).unwrap();
+        manager.increment(name(i), i + 1).unwrap();
+        assert!(manager.resident_counter_count() <= CAP);
+    }
+}
+
+fn verify(manager: &HybridOperationsManager) {
+    for i in 0..COUNTERS {
+        assert_eq!(manager.get(name(i)).unwrap(), Some(2 * (i + 1)), "计数器 {} 的值不正确", i);
+        assert!(manager.resident_counter_count() <= CAP, "内存中的计数器数量超过上限");
+    }
+}
+
+#[test]
+fn test_resident_counters_stay_bounded() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+    let manager = HybridOperationsManager::new(db.clone());
+    manager.set_max_resident_counters(Some(CAP));
+
+    // 直接访问模式下被移出的计数器直接写入数据库
+    populate(&manager);
+    assert!(manager.resident_counter_count() <= CAP);
+
+    // 被移出后再访问的计数器从磁盘重新加载
+    verify(&manager);
+    assert_eq!(manager.increment(name(0), 1).unwrap(), 3);
+    assert_eq!(manager.get("missing".to_string()).unwrap(), None);
+}
+
+#[test]
+fn test_evicted_counters_survive_database_worker_mode_and_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let manager = HybridOperationsManager::new_with_db_worker(db.clone());
+        manager.set_max_resident_counters(Some(CAP));
+
+        populate(&manager);
+        verify(&manager);
+
+        // 不限制时不再移出计数器
+        manager.set_max_resident_counters(None);
+        for i in 0..COUNTERS {
+            manager.increment(name(i), 0).unwrap();
+        }
+        assert_eq!(manager.resident_counter_count(), COUNTERS as usize);
+        for i in 0..COUNTERS {
+            manager.reset(name(i), 2 * (i + 1)).unwrap();
+        }
+    }
+
+    // 重新打开后，不预热也能通过上限读取所有计数器
+    let db = open(dir.path());
+    let manager = HybridOperationsManager::new(db);
+    manager.set_max_resident_counters(Some(CAP));
+    verify(&manager);
+}
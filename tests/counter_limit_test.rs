@@ -0,0 +1,179 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use melange_db::hybrid_operations_manager::{
+    AtomicWorkerConfig, CounterEviction, CounterKeyHashing, CounterLimitExceeded, CounterStats,
+    HybridOperationsManager,
+};
+use melange_db::*;
+
+const LIMIT: usize = 200;
+
+fn name(i: usize) -> String {
+    format!("session:{:05}:hits", i)
+}
+
+fn open(path: &Path, eviction: CounterEviction) -> Arc<Db<1024>> {
+    let atomic_worker = AtomicWorkerConfig {
+        max_counters: Some(LIMIT),
+        eviction,
+        ..AtomicWorkerConfig::default()
+    };
+    Arc::new(Config::new().path(path).flush_every_ms(None).atomic_worker(atomic_worker).open().unwrap())
+}
+
+fn assert_limit_exceeded(error: &io::Error, i: usize) {
+    assert_eq!(error.kind(), io::ErrorKind::QuotaExceeded);
+    let exceeded = CounterLimitExceeded::from_io_error(error).unwrap();
+    assert_eq!(exceeded.counter_name, name(i));
+    assert_eq!(exceeded.max_counters, LIMIT);
+}
+
+#[test]
+fn test_reject_new_counters_over_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path(), CounterEviction::RejectNew);
+        assert!(db.stats().counters.is_none());
+        let manager = HybridOperationsManager::new_with_db_worker(db.clone());
+
+        for i in 0..LIMIT + 100 {
+            match manager.increment(name(i), i as u64 + 1) {
+                Ok(value) => {
+                    assert!(i < LIMIT);
+                    assert_eq!(value, i as u64 + 1);
+                }
+                Err(e) => {
+                    assert!(i >= LIMIT);
+                    assert_limit_exceeded(&e, i);
+                    assert!(matches!(MelangeError::from(e), MelangeError::CounterLimitExceeded(_)));
+                }
+            }
+        }
+        assert_eq!(manager.counter_cardinality(), LIMIT);
+        assert_eq!(db.stats().counters.unwrap().cardinality, LIMIT);
+
+        // 已有的计数器不受影响，读取不存在的计数器不创建它
+        assert_eq!(manager.increment(name(0), 1).unwrap(), 2);
+        assert_eq!(manager.get(name(LIMIT)).unwrap(), None);
+        assert!(manager.add_f64(name(LIMIT), 1.0).is_err());
+        assert_eq!(manager.counter_cardinality(), LIMIT);
+    }
+
+    // 重新打开后磁盘上的计数器计入总数，不在内存中也可以继续使用
+    let db = open(dir.path(), CounterEviction::RejectNew);
+    let manager = HybridOperationsManager::new(db.clone());
+    assert_eq!(manager.counter_cardinality(), LIMIT);
+    assert_eq!(manager.resident_counter_count(), 0);
+    assert_limit_exceeded(&manager.increment(name(LIMIT), 1).unwrap_err(), LIMIT);
+    assert_eq!(manager.increment(name(1), 1).unwrap(), 3);
+    assert_eq!(manager.get(name(LIMIT - 1)).unwrap(), Some(LIMIT as u64));
+    assert_eq!(manager.resident_counter_count(), 2);
+    assert_eq!(manager.counter_cardinality(), LIMIT);
+
+    // 预热不会重复计数
+    manager.preload_counters().unwrap();
+    let stats = db.stats().counters.unwrap();
+    assert_eq!(
+        stats,
+        CounterStats { cardinality: LIMIT, resident: LIMIT, capacity: stats.capacity }
+    );
+    assert!(stats.capacity >= LIMIT);
+}
+
+#[test]
+fn test_idle_counters_are_evicted_and_reloaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path(), CounterEviction::EvictIdlePersisted { idle_for: Duration::ZERO });
+    let manager = HybridOperationsManager::new_with_db_worker(db.clone());
+
+    for i in 0..LIMIT + 100 {
+        assert_eq!(manager.increment(name(i), i as u64 + 1).unwrap(), i as u64 + 1);
+        assert!(manager.resident_counter_count() <= LIMIT, "内存中的计数器数量超过上限");
+    }
+    assert_eq!(manager.counter_cardinality(), LIMIT + 100);
+    let stats = db.stats().counters.unwrap();
+    assert_eq!(stats.cardinality, LIMIT + 100);
+    assert!(stats.resident <= LIMIT);
+
+    // 被移出后再访问的计数器从持久化的值继续
+    for i in 0..LIMIT + 100 {
+        assert_eq!(manager.increment(name(i), 1).unwrap(), i as u64 + 2, "计数器 {} 的值不正确", i);
+        assert!(manager.resident_counter_count() <= LIMIT);
+    }
+    assert_eq!(manager.counter_cardinality(), LIMIT + 100);
+    drop(manager);
+
+    // 重新打开后同样从磁盘加载
+    drop(db);
+    let db = open(dir.path(), CounterEviction::EvictIdlePersisted { idle_for: Duration::ZERO });
+    let manager = HybridOperationsManager::new(db);
+    assert_eq!(manager.counter_cardinality(), LIMIT + 100);
+    for i in 0..LIMIT + 100 {
+        assert_eq!(manager.get(name(i)).unwrap(), Some(i as u64 + 2));
+    }
+}
+
+#[test]
+fn test_busy_counters_are_not_evicted() {
+    let dir = tempfile::tempdir().unwrap();
+    let idle_for = Duration::from_millis(500);
+    let db = open(dir.path(), CounterEviction::EvictIdlePersisted { idle_for });
+    let manager = HybridOperationsManager::new(db);
+
+    for i in 0..LIMIT {
+        manager.increment(name(i), 1).unwrap();
+    }
+
+    // 所有的计数器都刚被访问过，没有可以移出的
+    assert_limit_exceeded(&manager.increment(name(LIMIT), 1).unwrap_err(), LIMIT);
+    assert_eq!(manager.resident_counter_count(), LIMIT);
+    assert_eq!(manager.counter_cardinality(), LIMIT);
+
+    thread::sleep(idle_for);
+    for i in LIMIT..LIMIT + 100 {
+        manager.increment(name(i), 1).unwrap();
+    }
+    assert!(manager.resident_counter_count() <= LIMIT);
+    assert_eq!(manager.counter_cardinality(), LIMIT + 100);
+
+    // 重新加载被移出的计数器同样需要空闲的计数器腾出空间
+    thread::sleep(idle_for);
+    for i in 0..LIMIT {
+        assert_eq!(manager.get(name(i)).unwrap(), Some(1));
+    }
+    assert_limit_exceeded(&manager.get(name(LIMIT)).unwrap_err(), LIMIT);
+}
+
+#[test]
+fn test_key_hashing_and_shard_amount() {
+    for key_hashing in [CounterKeyHashing::SipHash, CounterKeyHashing::XxHash3, CounterKeyHashing::Fnv] {
+        let atomic_worker = AtomicWorkerConfig {
+            key_hashing,
+            shard_amount: Some(8),
+            ..AtomicWorkerConfig::default()
+        };
+        let db = Arc::new(Config::tmp().unwrap().atomic_worker(atomic_worker).open().unwrap());
+        let manager = HybridOperationsManager::new(db.clone());
+        for i in 0..1_000 {
+            manager.increment(name(i % 100), 1).unwrap();
+        }
+        for i in 0..100 {
+            assert_eq!(manager.get(name(i)).unwrap(), Some(10), "{:?}", key_hashing);
+        }
+        assert_eq!(manager.counter_cardinality(), 100);
+        assert_eq!(db.stats().counters.unwrap().resident, 100);
+    }
+
+    for atomic_worker in [
+        AtomicWorkerConfig { shard_amount: Some(6), ..AtomicWorkerConfig::default() },
+        AtomicWorkerConfig { shard_amount: Some(1), ..AtomicWorkerConfig::default() },
+        AtomicWorkerConfig { max_counters: Some(0), ..AtomicWorkerConfig::default() },
+    ] {
+        let err = Config::tmp().unwrap().atomic_worker(atomic_worker).open::<1024>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
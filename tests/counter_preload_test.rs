@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use melange_db::hybrid_operations_manager::{
+    HybridOperationsManager, PreloadIssue, PreloadIssueReason,
+};
+use melange_db::*;
+
+const PREFIX: &[u8] = b"__atomic_counter__:";
+
+fn counter_key(name: &[u8]) -> Vec<u8> {
+    [PREFIX, name].concat()
+}
+
+fn versioned(value: u64) -> Vec<u8> {
+    [&[1u8][..], &value.to_le_bytes()].concat()
+}
+
+fn open_db() -> Arc<Db<1024>> {
+    Arc::new(Config::tmp().unwrap().open().unwrap())
+}
+
+#[test]
+fn test_preload_reports_invalid_entries() {
+    let db = open_db();
+
+    db.insert(counter_key(b"legacy"), &7_u64.to_le_bytes()[..]).unwrap();
+    db.insert(counter_key(b"versioned"), versioned(42)).unwrap();
+    // 与前缀相同的用户键，值过短
+    db.insert(counter_key(b"short"), b"abcd".as_slice()).unwrap();
+    // 值过长，之前会被截断为前8个字节
+    db.insert(counter_key(b"long"), vec![1u8; 16]).unwrap();
+    db.insert(counter_key(b"future"), [&[9u8][..], &[0u8; 8]].concat())
+        .unwrap();
+    db.insert(counter_key(b"\xff\xfe"), versioned(1)).unwrap();
+
+    let manager = HybridOperationsManager::new(db.clone());
+    let report = manager.preload_counters_report().unwrap();
+
+    assert_eq!(
+        report.counters,
+        vec![("legacy".to_string(), 7), ("versioned".to_string(), 42)]
+    );
+    assert_eq!(report.legacy, vec!["legacy".to_string()]);
+    assert_eq!(
+        report.issues,
+        vec![
+            PreloadIssue {
+                key: counter_key(b"future"),
+                reason: PreloadIssueReason::UnknownVersion(9),
+                raw_len: 9,
+            },
+            PreloadIssue {
+                key: counter_key(b"long"),
+                reason: PreloadIssueReason::InvalidLength,
+                raw_len: 16,
+            },
+            PreloadIssue {
+                key: counter_key(b"short"),
+                reason: PreloadIssueReason::InvalidLength,
+                raw_len: 4,
+            },
+            PreloadIssue {
+                key: counter_key(b"\xff\xfe"),
+                reason: PreloadIssueReason::NonUtf8Name,
+                raw_len: 9,
+            },
+        ]
+    );
+
+    // 只有有效的计数器被加载
+    assert_eq!(manager.get("legacy".to_string()).unwrap(), Some(7));
+    assert_eq!(manager.get("versioned".to_string()).unwrap(), Some(42));
+    assert_eq!(manager.get("short".to_string()).unwrap(), None);
+    assert_eq!(manager.get("long".to_string()).unwrap(), None);
+
+    // 通过数据库Worker预热得到相同的结果
+    let worker_manager = HybridOperationsManager::new_with_db_worker(db);
+    assert_eq!(worker_manager.preload_counters_report().unwrap(), report);
+    assert_eq!(worker_manager.preload_counters().unwrap(), 2);
+}
+
+#[test]
+fn test_upgrade_legacy_counters() {
+    let db = open_db();
+    for (name, value) in [("a", 1_u64), ("b", u64::MAX)] {
+        db.insert(counter_key(name.as_bytes()), &value.to_le_bytes()[..])
+            .unwrap();
+    }
+    db.insert(counter_key(b"c"), versioned(3)).unwrap();
+
+    let manager = HybridOperationsManager::new(db.clone());
+    let before = manager.preload_counters_report().unwrap();
+    assert_eq!(before.legacy, vec!["a".to_string(), "b".to_string()]);
+
+    assert_eq!(manager.upgrade_counter_format().unwrap(), 2);
+    assert_eq!(
+        db.get(counter_key(b"b")).unwrap().unwrap().as_ref(),
+        &versioned(u64::MAX)[..]
+    );
+
+    // 升级后的值不变，也不再报告为旧格式
+    let after = manager.preload_counters_report().unwrap();
+    assert!(after.legacy.is_empty());
+    assert!(after.issues.is_empty());
+    assert_eq!(after.counters, before.counters);
+    assert_eq!(manager.upgrade_counter_format().unwrap(), 0);
+}
+
+#[test]
+fn test_persisted_counters_use_versioned_format() {
+    let db = open_db();
+    let manager = HybridOperationsManager::new_with_db_worker(db.clone());
+
+    manager.reset("hits".to_string(), 5).unwrap();
+
+    // 持久化是异步的
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let stored = loop {
+        if let Some(stored) = db.get(counter_key(b"hits")).unwrap() {
+            break stored;
+        }
+        assert!(Instant::now() < deadline, "计数器没有被持久化");
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    assert_eq!(stored.as_ref(), &versioned(5)[..]);
+
+    let reloaded = HybridOperationsManager::new(db);
+    let report = reloaded.preload_counters_report().unwrap();
+    assert_eq!(report.counters, vec![("hits".to_string(), 5)]);
+    assert!(report.legacy.is_empty());
+}
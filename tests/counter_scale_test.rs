@@ -0,0 +1,97 @@
+use std::io;
+use std::sync::Arc;
+
+use melange_db::hybrid_operations_manager::{HybridOperationsManager, RoundingMode};
+use melange_db::*;
+
+fn manager() -> HybridOperationsManager {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    HybridOperationsManager::new(db)
+}
+
+#[test]
+fn test_percentage_near_u64_max_does_not_overflow() {
+    let manager = manager();
+    let name = "big".to_string();
+
+    manager.reset(name.clone(), u64::MAX - 1).unwrap();
+    assert_eq!(manager.percentage(name.clone(), 50).unwrap(), (u64::MAX - 1) / 2);
+
+    manager.reset(name.clone(), u64::MAX).unwrap();
+    assert_eq!(manager.percentage(name.clone(), 100).unwrap(), u64::MAX);
+
+    manager.reset(name.clone(), u64::MAX).unwrap();
+    assert_eq!(
+        manager.basis_points(name.clone(), 9_999, RoundingMode::Down).unwrap(),
+        (u128::from(u64::MAX) * 9_999 / 10_000) as u64
+    );
+
+    // 结果超过u64::MAX时与乘法一样饱和
+    manager.reset(name.clone(), u64::MAX / 2 + 1).unwrap();
+    assert_eq!(manager.scale(name, 3, 1, RoundingMode::Down).unwrap(), u64::MAX);
+}
+
+#[test]
+fn test_rounding_modes_at_half() {
+    let manager = manager();
+    let name = "half".to_string();
+
+    // (值, 舍入方式, 期望结果)：值 * 50%，结果都恰好是 x.5
+    let cases = [
+        (5, RoundingMode::Down, 2),
+        (5, RoundingMode::Up, 3),
+        (5, RoundingMode::HalfEven, 2),
+        (7, RoundingMode::Down, 3),
+        (7, RoundingMode::Up, 4),
+        (7, RoundingMode::HalfEven, 4),
+    ];
+    for (value, rounding, expected) in cases {
+        manager.reset(name.clone(), value).unwrap();
+        assert_eq!(
+            manager.percentage_with(name.clone(), 50, rounding).unwrap(),
+            expected,
+            "{} {:?}",
+            value,
+            rounding
+        );
+    }
+
+    // 不在 .5 上时HalfEven按最近的整数舍入
+    manager.reset(name.clone(), 10).unwrap();
+    assert_eq!(manager.divide_with(name.clone(), 3, RoundingMode::HalfEven).unwrap(), 3);
+    manager.reset(name.clone(), 11).unwrap();
+    assert_eq!(manager.divide_with(name.clone(), 3, RoundingMode::HalfEven).unwrap(), 4);
+
+    // 12345的15个基点是18.5175，Up进位，Down截断
+    manager.reset(name.clone(), 12_345).unwrap();
+    assert_eq!(manager.basis_points(name.clone(), 15, RoundingMode::Up).unwrap(), 19);
+    manager.reset(name.clone(), 12_345).unwrap();
+    assert_eq!(manager.basis_points(name, 15, RoundingMode::Down).unwrap(), 18);
+}
+
+#[test]
+fn test_old_signatures_truncate() {
+    let manager = manager();
+    let name = "compat".to_string();
+
+    manager.reset(name.clone(), 99).unwrap();
+    assert_eq!(manager.percentage(name.clone(), 33).unwrap(), 32);
+    assert_eq!(manager.divide(name.clone(), 5).unwrap(), 6);
+    assert_eq!(manager.get(name).unwrap(), Some(6));
+}
+
+#[test]
+fn test_invalid_denominators_and_ratios() {
+    let manager = manager();
+    let name = "invalid".to_string();
+    manager.reset(name.clone(), 10).unwrap();
+
+    let kind = |result: io::Result<u64>| result.unwrap_err().kind();
+    assert_eq!(kind(manager.divide(name.clone(), 0)), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(manager.scale(name.clone(), 1, 0, RoundingMode::Up)), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(manager.percentage_with(name.clone(), 101, RoundingMode::Down)), io::ErrorKind::InvalidInput);
+    assert_eq!(kind(manager.basis_points(name.clone(), 10_001, RoundingMode::Down)), io::ErrorKind::InvalidInput);
+
+    // 被拒绝的操作不修改计数器
+    assert_eq!(manager.get(name).unwrap(), Some(10));
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use melange_db::*;
+
+const FANOUT: usize = 16;
+
+#[test]
+fn test_debug_entries_map_every_key_to_a_readable_slot() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(None).inline_value_threshold(64).open().unwrap();
+    let tree = db.open_tree("inspected").unwrap();
+    for i in 0..500_u32 {
+        // 部分值单独存储为blob
+        let len = if i % 10 == 0 { 200 } else { 8 };
+        tree.insert(i.to_be_bytes(), vec![i as u8; len]).unwrap();
+    }
+    for i in (0..500_u32).step_by(7) {
+        tree.remove(i.to_be_bytes()).unwrap();
+    }
+    db.flush().unwrap();
+
+    let entries: Vec<(InlineArray, SlabAddress)> = tree.debug_entries().unwrap().collect();
+    let keys: Vec<InlineArray> = tree.iter().keys().map(Result::unwrap).collect();
+    assert_eq!(entries.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>(), keys);
+
+    // 同一个叶子节点中的键共享一个地址
+    let mut by_address: BTreeMap<(u8, u64), usize> = BTreeMap::new();
+    for (_, address) in &entries {
+        *by_address.entry((address.slab(), address.slot())).or_default() += 1;
+    }
+    assert!(by_address.len() > 1);
+    assert!(by_address.values().all(|count| *count <= FANOUT));
+
+    let heap = tree.debug_heap();
+    for (_, address) in &entries {
+        let bytes = heap.read_slab_address(*address).unwrap();
+        assert!(!bytes.is_empty() && bytes.len() <= address.slot_size());
+    }
+}
+
+#[test]
+fn test_debug_entries_reflect_the_last_flush() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let tree = db.open_tree("inspected").unwrap();
+    assert_eq!(tree.debug_entries().unwrap().count(), 0);
+
+    tree.insert(b"a", b"1".as_slice()).unwrap();
+    // 从未flush的叶子节点没有地址
+    assert_eq!(tree.debug_entries().unwrap().count(), 0);
+
+    db.flush().unwrap();
+    let before: Vec<_> = tree.debug_entries().unwrap().collect();
+    assert_eq!(before.len(), 1);
+
+    tree.insert(b"b", b"2".as_slice()).unwrap();
+    assert_eq!(tree.debug_entries().unwrap().collect::<Vec<_>>(), before);
+
+    // flush之后叶子节点被写入新的位置
+    db.flush().unwrap();
+    let after: Vec<_> = tree.debug_entries().unwrap().collect();
+    assert_eq!(after.iter().map(|(key, _)| &key[..]).collect::<Vec<_>>(), [b"a", b"b"]);
+    assert_ne!(after[0].1, before[0].1);
+}
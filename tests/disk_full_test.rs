@@ -0,0 +1,277 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+const HEADROOM: u64 = 1024 * 1024;
+
+/// 挂载在临时目录上的小tmpfs，释放时卸载
+struct SmallFilesystem {
+    dir: tempfile::TempDir,
+}
+
+impl SmallFilesystem {
+    /// 需要挂载的权限，使用它的测试默认被忽略
+    fn mount() -> SmallFilesystem {
+        let dir = tempfile::tempdir().unwrap();
+        let status = Command::new("mount")
+            .args(["-t", "tmpfs", "-o", "size=32m", "tmpfs"])
+            .arg(dir.path())
+            .status();
+        match status {
+            Ok(status) if status.success() => SmallFilesystem { dir },
+            res => panic!("无法挂载tmpfs: {res:?}"),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for SmallFilesystem {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(self.dir.path()).status();
+    }
+}
+
+/// 写入一个文件直到文件系统已满，返回它的路径
+fn fill(fs_root: &Path) -> PathBuf {
+    let path = fs_root.join("filler");
+    let mut file = fs::File::create(&path).unwrap();
+    let chunk = vec![1_u8; 4096];
+    loop {
+        if let Err(e) = file.write_all(&chunk) {
+            assert_eq!(e.kind(), ErrorKind::StorageFull);
+            return path;
+        }
+    }
+}
+
+fn config(path: &Path) -> Config {
+    common::config(path).disk_full_headroom_bytes(HEADROOM)
+}
+
+fn value(i: u32) -> Vec<u8> {
+    vec![i as u8; 100]
+}
+
+#[test]
+#[ignore = "需要挂载tmpfs的权限，使用 --ignored 运行"]
+fn test_disk_full_degrades_to_read_only_and_resumes() {
+    let small = SmallFilesystem::mount();
+    let db_path = small.path().join("db");
+
+    {
+        let db: Db<64> = config(&db_path).open().unwrap();
+        assert_eq!(fs::metadata(db_path.join("headroom")).unwrap().len(), HEADROOM);
+        for i in 0..1_000_u32 {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        db.flush().unwrap();
+        assert_eq!(db.write_state(), WriteState::Writable);
+
+        let filler = fill(small.path());
+
+        // 空间不足之前接受的写入，flush时释放预留空间后完成
+        for i in 1_000..2_000_u32 {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        db.flush().unwrap();
+        assert_eq!(db.write_state(), WriteState::ReadOnlyDegraded);
+        assert!(!db_path.join("headroom").exists());
+
+        let err = db.insert(b"rejected", b"v".as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        assert_eq!(DiskFull::from_io_error(&err).unwrap().path, db_path);
+        let err = MelangeError::from(err);
+        assert!(matches!(err, MelangeError::DiskFull(_)), "{:?}", err);
+        assert!(err.is_retryable());
+        assert!(db.remove(0_u32.to_be_bytes()).is_err());
+
+        // 读取和迭代照常进行
+        assert_eq!(db.get(1_500_u32.to_be_bytes()).unwrap().unwrap(), value(1_500));
+        assert_eq!(db.iter().count(), 2_000);
+        assert_eq!(db.get(b"rejected").unwrap(), None);
+        db.flush().unwrap();
+
+        // 空间仍然不足
+        assert!(!db.try_resume_writes().unwrap());
+        assert_eq!(db.write_state(), WriteState::ReadOnlyDegraded);
+
+        fs::remove_file(filler).unwrap();
+        assert!(db.try_resume_writes().unwrap());
+        assert_eq!(db.write_state(), WriteState::Writable);
+        assert_eq!(fs::metadata(db_path.join("headroom")).unwrap().len(), HEADROOM);
+
+        db.insert(b"accepted", b"v".as_slice()).unwrap();
+        db.flush().unwrap();
+    }
+
+    let db: Db<64> = config(&db_path).open().unwrap();
+    assert_eq!(db.len().unwrap(), 2_001);
+    for i in 0..2_000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), value(i));
+    }
+    assert_eq!(db.get(b"accepted").unwrap().unwrap(), b"v");
+}
+
+#[test]
+#[ignore = "需要挂载tmpfs的权限，使用 --ignored 运行"]
+fn test_flusher_resumes_writes_once_space_is_freed() {
+    let small = SmallFilesystem::mount();
+    let db_path = small.path().join("db");
+    let db: Db<64> = config(&db_path).flush_every_ms(Some(10)).open().unwrap();
+
+    let filler = fill(small.path());
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut i = 0_u32;
+    while db.write_state() == WriteState::Writable {
+        assert!(Instant::now() < deadline, "写入没有被拒绝");
+        // 正在进行的flush之后才进入降级状态，之前的写入仍然被接受
+        if let Err(e) = db.insert(i.to_be_bytes(), value(i)) {
+            assert_eq!(e.kind(), ErrorKind::StorageFull);
+            break;
+        }
+        i += 1;
+    }
+    assert!(db.insert(b"rejected", b"v".as_slice()).is_err());
+    if i > 0 {
+        assert_eq!(db.get((i / 2).to_be_bytes()).unwrap().unwrap(), value(i / 2));
+    }
+
+    fs::remove_file(filler).unwrap();
+    while db.write_state() == WriteState::ReadOnlyDegraded {
+        assert!(Instant::now() < deadline, "flusher没有恢复写入");
+        thread::sleep(Duration::from_millis(50));
+    }
+    db.insert(b"accepted", b"v".as_slice()).unwrap();
+    db.flush().unwrap();
+}
+
+#[test]
+#[ignore = "需要挂载tmpfs的权限，使用 --ignored 运行"]
+fn test_disk_full_without_headroom_keeps_unflushed_writes() {
+    let small = SmallFilesystem::mount();
+    let db_path = small.path().join("db");
+
+    {
+        let db: Db<64> = config(&db_path).disk_full_headroom_bytes(0).open().unwrap();
+        for i in 0..1_000_u32 {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        db.flush().unwrap();
+
+        let filler = fill(small.path());
+
+        // 没有预留空间，flush失败，修改保留在缓存中
+        for i in 1_000..2_000_u32 {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        let err = db.flush().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        assert_eq!(db.write_state(), WriteState::ReadOnlyDegraded);
+        assert!(db.insert(b"rejected", b"v".as_slice()).is_err());
+
+        // 没有设置全局错误，读取照常进行，再次flush仍然失败
+        assert_eq!(db.get(1_500_u32.to_be_bytes()).unwrap().unwrap(), value(1_500));
+        assert_eq!(db.iter().count(), 2_000);
+        assert!(db.flush().is_err());
+        assert!(!db.try_resume_writes().unwrap());
+
+        // 空间释放之后重试的flush写入之前失败的修改
+        fs::remove_file(filler).unwrap();
+        assert!(db.try_resume_writes().unwrap());
+        db.flush().unwrap();
+        db.insert(b"accepted", b"v".as_slice()).unwrap();
+    }
+
+    let db: Db<64> = config(&db_path).open().unwrap();
+    assert_eq!(db.len().unwrap(), 2_001);
+    for i in 0..2_000_u32 {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), value(i));
+    }
+    assert_eq!(db.get(b"accepted").unwrap().unwrap(), b"v");
+}
+
+#[test]
+#[ignore = "需要挂载tmpfs的权限，使用 --ignored 运行"]
+fn test_flusher_retries_without_headroom() {
+    let small = SmallFilesystem::mount();
+    let db_path = small.path().join("db");
+
+    let written = {
+        let db: Db<64> = config(&db_path)
+            .disk_full_headroom_bytes(0)
+            .flush_every_ms(Some(10))
+            .open()
+            .unwrap();
+
+        let filler = fill(small.path());
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut i = 0_u32;
+        while db.write_state() == WriteState::Writable {
+            assert!(Instant::now() < deadline, "写入没有被拒绝");
+            if db.insert(i.to_be_bytes(), value(i)).is_err() {
+                break;
+            }
+            i += 1;
+        }
+
+        // 后台flusher没有中止进程，空间释放之后写入之前失败的修改
+        fs::remove_file(filler).unwrap();
+        while db.write_state() == WriteState::ReadOnlyDegraded {
+            assert!(Instant::now() < deadline, "flusher没有恢复写入");
+            thread::sleep(Duration::from_millis(50));
+        }
+        db.flush().unwrap();
+        i
+    };
+
+    let db: Db<64> = config(&db_path).open().unwrap();
+    assert_eq!(db.len().unwrap(), written as usize);
+    for i in 0..written {
+        assert_eq!(db.get(i.to_be_bytes()).unwrap().unwrap(), value(i));
+    }
+}
+
+#[test]
+fn test_headroom_is_not_counted_in_size_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<64> = config(dir.path()).open().unwrap();
+        assert!(db.size_on_disk().unwrap() < HEADROOM);
+    }
+
+    // 不预留空间时删除之前留下的文件
+    let _db: Db<64> = config(dir.path()).disk_full_headroom_bytes(0).open().unwrap();
+    assert!(!dir.path().join("headroom").exists());
+}
+
+#[test]
+fn test_reopen_keeps_existing_headroom() {
+    let dir = tempfile::tempdir().unwrap();
+    let headroom = dir.path().join("headroom");
+    drop(config(dir.path()).open::<64>().unwrap());
+
+    // 大小正确的预留文件在重新打开时被保留，而不是截断后重新分配
+    let mut file = fs::OpenOptions::new().write(true).open(&headroom).unwrap();
+    file.write_all(b"kept").unwrap();
+    drop(file);
+    drop(config(dir.path()).open::<64>().unwrap());
+    assert_eq!(&fs::read(&headroom).unwrap()[..4], b"kept");
+
+    // 大小改变时重新分配
+    drop(config(dir.path()).disk_full_headroom_bytes(2 * HEADROOM).open::<64>().unwrap());
+    let contents = fs::read(&headroom).unwrap();
+    assert_eq!(contents.len() as u64, 2 * HEADROOM);
+    assert_eq!(&contents[..4], &[0; 4]);
+}
@@ -0,0 +1,114 @@
+use melange_db::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const VALUE_LEN: usize = 1000;
+
+/// 写入 `count` 个随机（不可压缩的）值，返回写入的键和值的总字节数
+fn populate(tree: &Tree<1024>, count: u32, rng: &mut StdRng) -> u64 {
+    let mut written = 0;
+    for i in 0..count {
+        let key = format!("key_{:08}", i).into_bytes();
+        let mut value = vec![0; VALUE_LEN];
+        rng.fill(&mut value[..]);
+        written += (key.len() + value.len()) as u64;
+        tree.insert(key, value).unwrap();
+    }
+    written
+}
+
+fn assert_close(estimate: u64, truth: u64) {
+    let ratio = estimate as f64 / truth as f64;
+    assert!(
+        (0.85..=1.15).contains(&ratio),
+        "估算值 {} 与实际值 {} 相差超过15%",
+        estimate,
+        truth
+    );
+}
+
+fn tree_usage<'a>(report: &'a DiskUsageReport, name: &str) -> &'a TreeDiskUsage {
+    report
+        .trees
+        .iter()
+        .find(|tree| tree.name.as_deref() == Some(name.as_bytes()))
+        .unwrap()
+}
+
+#[test]
+fn test_per_tree_disk_usage() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+
+    let users_bytes = {
+        let db: Db<1024> = config.open().unwrap();
+        let sessions = db.open_tree("sessions").unwrap();
+        let users = db.open_tree("users").unwrap();
+
+        let sessions_written = populate(&sessions, 4000, &mut rng);
+        let users_written = populate(&users, 1000, &mut rng);
+        db.flush().unwrap();
+
+        assert_close(sessions.estimated_disk_bytes(), sessions_written);
+        assert_close(users.estimated_disk_bytes(), users_written);
+        assert_eq!(db.estimated_disk_bytes(), 0);
+
+        let report = db.disk_usage().unwrap();
+        assert_eq!(report.total_bytes, db.size_on_disk().unwrap());
+        assert!(report.metadata_bytes > 0);
+        assert!(!report.slab_files.is_empty());
+        assert!(
+            report.slab_files.iter().map(|f| f.file_bytes).sum::<u64>()
+                <= report.total_bytes
+        );
+
+        // 默认树排在第一位
+        assert_eq!(report.trees[0].name, None);
+        let sessions_usage = tree_usage(&report, "sessions");
+        assert_eq!(sessions_usage.leaf_bytes, sessions.estimated_disk_bytes());
+        assert!(sessions_usage.estimated_bytes >= sessions_usage.leaf_bytes);
+
+        // 共享结构按比例分摊后，各树的估算之和等于总大小
+        let estimated_sum: u64 =
+            report.trees.iter().map(|tree| tree.estimated_bytes).sum();
+        assert!(report.total_bytes - estimated_sum < report.trees.len() as u64);
+
+        let sessions_share =
+            sessions_usage.estimated_bytes as f64 / report.total_bytes as f64;
+        assert!((0.7..0.9).contains(&sessions_share), "{}", sessions_share);
+
+        // 删除一个树的数据后，叶子节点被合并和回收，占用随之下降
+        let users_before = users.estimated_disk_bytes();
+        for i in 0..4000 {
+            sessions.remove(format!("key_{:08}", i)).unwrap();
+        }
+        db.flush().unwrap();
+        db.flush().unwrap();
+
+        assert!(
+            sessions.estimated_disk_bytes() < sessions_written / 100,
+            "{}",
+            sessions.estimated_disk_bytes()
+        );
+        assert_eq!(users.estimated_disk_bytes(), users_before);
+
+        let report = db.disk_usage().unwrap();
+        let users_share = tree_usage(&report, "users").estimated_bytes as f64
+            / report.total_bytes as f64;
+        assert!(users_share > 0.9, "{}", users_share);
+
+        users.estimated_disk_bytes()
+    };
+
+    // 正常关闭后从关闭时记录的大小重建统计
+    let db: Db<1024> = config.open().unwrap();
+    let users = db.open_tree("users").unwrap();
+    assert_eq!(users.estimated_disk_bytes(), users_bytes);
+    assert_close(users.estimated_disk_bytes(), 1000 * (12 + VALUE_LEN as u64));
+    drop(users);
+    drop(db);
+
+    // 校验slot时从读到的大小重建统计
+    let db: Db<1024> = config.clone().verify_slots_on_open(true).open().unwrap();
+    let users = db.open_tree("users").unwrap();
+    assert_eq!(users.estimated_disk_bytes(), users_bytes);
+}
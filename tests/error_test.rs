@@ -0,0 +1,105 @@
+use std::io;
+
+use melange_db::smart_flush::SmartFlushConfig;
+use melange_db::*;
+
+fn classify<T: std::fmt::Debug>(result: io::Result<T>) -> MelangeError {
+    MelangeError::from(result.unwrap_err())
+}
+
+#[test]
+fn test_operations_return_specific_variants() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+
+    // 没有启动智能flusher时不能更新它的配置
+    let error = classify(db.update_smart_flush_config(SmartFlushConfig::default()));
+    assert!(matches!(error, MelangeError::Unsupported(_)), "{:?}", error);
+    assert!(!error.is_retryable());
+
+    // 无效的参数
+    let error = classify(db.set_quota(TreeQuota { max_dirty_bytes: Some(0), ..TreeQuota::default() }));
+    assert!(matches!(error, MelangeError::InvalidInput(_)), "{:?}", error);
+
+    // 只写一次的树：覆盖已存在的键和删除键
+    let ledger = db
+        .open_tree_with_options("ledger", TreeOptions { write_once: true, ..TreeOptions::default() })
+        .unwrap();
+    ledger.insert(b"k", b"v".as_slice()).unwrap();
+    let error = classify(ledger.insert(b"k", b"w".as_slice()));
+    assert!(matches!(&error, MelangeError::KeyAlreadyExists(e) if &*e.key == b"k"), "{:?}", error);
+    let error = classify(ledger.remove(b"k"));
+    assert!(matches!(error, MelangeError::PermissionDenied(_)), "{:?}", error);
+
+    // 条件批次的守卫条件不成立
+    let mut batch = Batch::default();
+    batch.guard(b"missing", Some(b"x"));
+    batch.insert(b"a", b"1");
+    let error = classify(db.apply_batch(batch));
+    assert!(matches!(&error, MelangeError::BatchGuard(e) if &*e.key == b"missing"), "{:?}", error);
+    assert!(error.is_retryable());
+
+    // 比较并交换冲突
+    db.insert(b"cas", b"1".as_slice()).unwrap();
+    let conflict = db.compare_and_swap(b"cas", Some(b"2"), Some(b"3")).unwrap().unwrap_err();
+    let error = MelangeError::from(conflict);
+    assert!(matches!(&error, MelangeError::CompareAndSwap(e) if e.current.as_deref() == Some(b"1".as_slice())));
+    assert!(error.is_retryable());
+
+    // 超出树的写入速率限制
+    let tenant = db.open_tree("tenant").unwrap();
+    tenant
+        .set_quota(TreeQuota {
+            max_write_ops_per_sec: Some(1),
+            policy: QuotaPolicy::Reject,
+            ..TreeQuota::default()
+        })
+        .unwrap();
+    tenant.insert(b"a", b"1".as_slice()).unwrap();
+    let error = classify(tenant.insert(b"b", b"2".as_slice()));
+    assert!(matches!(&error, MelangeError::QuotaExceeded(e) if e.max_write_ops_per_sec == 1), "{:?}", error);
+    assert_eq!(error.kind(), io::ErrorKind::QuotaExceeded);
+    assert!(error.is_retryable());
+}
+
+#[test]
+fn test_open_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+
+    // 同一个目录不能同时被两个 `Db` 打开
+    let error = classify(Config::new().path(dir.path()).open::<1024>());
+    assert!(matches!(&error, MelangeError::DatabaseLocked(e) if e.path == dir.path()), "{:?}", error);
+    drop(db);
+
+    // 以不同的 `LEAF_FANOUT` 重新打开
+    let error = classify(Config::new().path(dir.path()).open::<64>());
+    assert!(
+        matches!(error, MelangeError::LeafFanoutMismatch(LeafFanoutMismatch { created_with: 1024, opened_with: 64 })),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_conversion_back_to_io_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+    let ledger = db
+        .open_tree_with_options("ledger", TreeOptions { write_once: true, ..TreeOptions::default() })
+        .unwrap();
+    ledger.insert(b"k", b"v".as_slice()).unwrap();
+
+    let original = ledger.insert(b"k", b"w".as_slice()).unwrap_err();
+    let message = original.to_string();
+    let error = io::Error::from(MelangeError::from(original));
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(error.to_string(), message);
+    assert!(KeyAlreadyExists::from_io_error(&error).is_some());
+
+    let error = MelangeError::from(io::Error::new(io::ErrorKind::TimedOut, "slow disk"));
+    assert!(matches!(error, MelangeError::Io(_)));
+    assert!(error.is_retryable());
+    assert_eq!(io::Error::from(error).kind(), io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,72 @@
+use std::io;
+use std::sync::Arc;
+
+use melange_db::hybrid_operations_manager::{AtomicWorkerConfig, HybridOperationsManager};
+use melange_db::*;
+
+const ENTRIES: u32 = 20_000;
+const COUNTERS: usize = 5_000;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:08}", i).into_bytes()
+}
+
+/// 批量导入 `ENTRIES` 个条目，返回导入的内容和布隆过滤器是否需要重建
+fn bulk_load(expected_entries: usize) -> (Vec<(InlineArray, InlineArray)>, bool) {
+    let db: Db<1024> = Config::tmp()
+        .unwrap()
+        .bloom_filter_capacity(1000)
+        .expected_entries(expected_entries)
+        .open()
+        .unwrap();
+    let tree = db.open_tree("bulk").unwrap();
+    for i in 0..ENTRIES {
+        tree.insert(key(i), i.to_le_bytes()).unwrap();
+    }
+
+    let contents = tree.iter().collect::<io::Result<Vec<_>>>().unwrap();
+    let resized = db.resize_bloom_filter_if_needed().unwrap();
+    assert_eq!(db.stats().cache.bloom_filter_resizes, u64::from(resized));
+    (contents, resized)
+}
+
+#[test]
+fn test_expected_entries_presizes_bloom_filter() {
+    let (without_hint, resized_without_hint) = bulk_load(0);
+    let (with_hint, resized_with_hint) = bulk_load(ENTRIES as usize);
+
+    assert_eq!(with_hint.len(), ENTRIES as usize);
+    assert_eq!(with_hint, without_hint);
+    // 没有提示时，导入的条目数远超初始容量，过滤器需要重建
+    assert!(resized_without_hint);
+    assert!(!resized_with_hint);
+}
+
+/// 创建 `COUNTERS` 个计数器，返回它们的值以及创建前后的映射容量
+fn create_counters(expected_counters: Option<usize>) -> (Vec<Option<u64>>, usize, usize) {
+    let atomic_worker = AtomicWorkerConfig { expected_counters, ..AtomicWorkerConfig::default() };
+    let db: Arc<Db<1024>> =
+        Arc::new(Config::tmp().unwrap().atomic_worker(atomic_worker).open().unwrap());
+    let manager = HybridOperationsManager::new(db.clone());
+
+    let before = db.stats().counters.unwrap().capacity;
+    for i in 0..COUNTERS {
+        manager.increment(format!("counter_{}", i), i as u64 + 1).unwrap();
+    }
+    let after = db.stats().counters.unwrap().capacity;
+
+    let values = (0..COUNTERS).map(|i| manager.get(format!("counter_{}", i)).unwrap()).collect();
+    (values, before, after)
+}
+
+#[test]
+fn test_expected_counters_presizes_counter_map() {
+    let (without_hint, before, after) = create_counters(None);
+    assert!(after > before, "容量从 {} 到 {}", before, after);
+
+    let (with_hint, before, after) = create_counters(Some(COUNTERS));
+    assert_eq!(with_hint, without_hint);
+    assert!(before >= COUNTERS, "预先分配的容量 {}", before);
+    // 创建期间没有扩容
+    assert_eq!(after, before);
+}
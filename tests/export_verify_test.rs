@@ -0,0 +1,140 @@
+use std::io;
+
+use melange_db::export::{self, MAGIC};
+use melange_db::*;
+
+fn open(dir: &tempfile::TempDir) -> Db<1024> {
+    Config::new().path(dir.path()).flush_every_ms(None).open().unwrap()
+}
+
+/// 包含三个树的导出文件，`users` 树最后导出
+fn archive() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(&dir);
+    let accounts = db.open_tree("accounts").unwrap();
+    for i in 0..100_u64 {
+        accounts.insert(i.to_be_bytes(), vec![0; 64]).unwrap();
+    }
+    let users = db.open_tree("users").unwrap();
+    for i in 0..50_u64 {
+        users.insert(i.to_be_bytes(), vec![1; 64]).unwrap();
+    }
+    db.open_tree("empty").unwrap();
+
+    let mut archive = vec![];
+    let report = db.export_to(&mut archive).unwrap();
+    assert_eq!(report.bytes, archive.len() as u64);
+    assert_eq!(report.hash_algorithm, export::HashAlgorithm::default_for_build());
+    archive
+}
+
+fn entries(report: &export::VerifyReport, name: &[u8]) -> u64 {
+    report.collections.iter().find(|c| c.name == name).unwrap().entries
+}
+
+#[test]
+fn test_round_trip() {
+    let archive = archive();
+    assert_eq!(archive[..8], MAGIC);
+
+    let report = export::verify(&archive[..]).unwrap();
+    assert!(report.is_valid(), "{:?}", report);
+    assert_eq!(report.bytes_read, archive.len() as u64);
+    assert_eq!(entries(&report, b"users"), 50);
+    assert_eq!(entries(&report, b"accounts"), 100);
+    assert_eq!(entries(&report, b"empty"), 0);
+    assert_eq!(report.records, 150 + report.collections.len() as u64);
+
+    // 导入时逐条校验
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(&dir);
+    let imported = db.import_from(&archive[..]).unwrap();
+    assert_eq!(imported, report);
+    assert_eq!(db.open_tree("accounts").unwrap().len().unwrap(), 100);
+    let users = db.open_tree("users").unwrap();
+    assert_eq!(users.len().unwrap(), 50);
+    assert_eq!(&*users.get(7_u64.to_be_bytes()).unwrap().unwrap(), &[1; 64][..]);
+}
+
+#[test]
+fn test_bit_flip_in_value() {
+    let clean = archive();
+    let clean_report = export::verify(&clean[..]).unwrap();
+
+    // 每条键值记录为13字节的记录头、8字节的键、64字节的值和4字节的CRC32
+    let position = clean.windows(64).rposition(|w| w == [1; 64]).unwrap() + 10;
+    let mut archive = clean.clone();
+    archive[position] ^= 0x10;
+
+    let report = export::verify(&archive[..]).unwrap();
+    assert!(!report.is_valid());
+    assert!(!report.footer_valid);
+    assert_eq!(report.truncated_at, None);
+    let offset = report.first_corrupt_offset.unwrap();
+    assert!(offset <= position as u64 && position as u64 - offset < 13 + 8 + 64 + 4);
+    // 损坏的记录之前的键值对都已校验
+    assert_eq!(report.bytes_read, offset);
+    assert_eq!(entries(&report, b"users"), 49);
+    assert!(report.records < clean_report.records);
+
+    // 导入在损坏的记录处停止
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(&dir);
+    let error = db.import_from(&archive[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_truncated_tail() {
+    let clean = archive();
+    let footer_offset = clean.len() as u64 - 33;
+
+    // 截断在文件尾中
+    let report = export::verify(&clean[..clean.len() - 5]).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(report.truncated_at, Some(footer_offset));
+    assert_eq!(report.first_corrupt_offset, None);
+    assert_eq!(entries(&report, b"users"), 50);
+
+    // 截断在键值记录的值中
+    let report = export::verify(&clean[..clean.len() - 33 - 20]).unwrap();
+    let last_entry_offset = footer_offset - (13 + 8 + 64 + 4);
+    assert_eq!(report.truncated_at, Some(last_entry_offset));
+    assert_eq!(report.bytes_read, last_entry_offset);
+
+    // 截断在文件头中，以及空文件
+    for len in [0, 3] {
+        let report = export::verify(&clean[..len]).unwrap();
+        assert_eq!(report.truncated_at, Some(0));
+        assert_eq!(report.hash_algorithm, None);
+        assert!(report.collections.is_empty());
+    }
+}
+
+#[test]
+fn test_corrupted_footer() {
+    let clean = archive();
+
+    // 文件尾的哈希值被修改后，记录的CRC32也随之不匹配
+    let mut archive = clean.clone();
+    let last = archive.len() - 5;
+    archive[last] ^= 1;
+    let report = export::verify(&archive[..]).unwrap();
+    assert_eq!(report.first_corrupt_offset, Some(clean.len() as u64 - 33));
+    assert!(!report.footer_valid);
+    // 所有键值对都已校验
+    assert_eq!(entries(&report, b"users"), 50);
+
+    // 文件尾之后的多余内容
+    let mut archive = clean.clone();
+    archive.push(0);
+    let report = export::verify(&archive[..]).unwrap();
+    assert_eq!(report.first_corrupt_offset, Some(clean.len() as u64));
+
+    // 损坏的文件头
+    let mut archive = clean.clone();
+    archive[2] ^= 1;
+    let report = export::verify(&archive[..]).unwrap();
+    assert_eq!(report.first_corrupt_offset, Some(0));
+    assert_eq!(report.records, 0);
+}
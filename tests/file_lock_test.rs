@@ -0,0 +1,39 @@
+use std::io;
+
+use melange_db::*;
+
+fn open(path: &std::path::Path) -> io::Result<Db<1024>> {
+    Config::new().path(path).flush_every_ms(None).open()
+}
+
+#[test]
+fn test_second_open_of_same_path_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path()).unwrap();
+    db.insert(b"key", b"value".as_slice()).unwrap();
+
+    let error = open(dir.path()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+    let locked = DatabaseLocked::from_io_error(&error).expect("应该是DatabaseLocked错误");
+    assert_eq!(locked.path, dir.path());
+
+    // 失败的打开不影响已打开的数据库
+    assert_eq!(&*db.get(b"key").unwrap().unwrap(), b"value");
+}
+
+#[test]
+fn test_lock_is_released_when_db_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path()).unwrap();
+    db.insert(b"key", b"value".as_slice()).unwrap();
+    db.flush().unwrap();
+
+    // 任何一个克隆仍然存在时锁都不会被释放
+    let clone = db.clone();
+    drop(db);
+    assert!(DatabaseLocked::from_io_error(&open(dir.path()).unwrap_err()).is_some());
+    drop(clone);
+
+    let db = open(dir.path()).unwrap();
+    assert_eq!(&*db.get(b"key").unwrap().unwrap(), b"value");
+}
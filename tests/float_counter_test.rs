@@ -0,0 +1,181 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use melange_db::hybrid_operations_manager::{
+    CounterKind, CounterTypeMismatch, HybridOperationsManager,
+};
+use melange_db::*;
+
+const THREADS: usize = 8;
+const ADDS_PER_THREAD: usize = 10_000;
+
+fn open(path: &std::path::Path) -> Arc<Db<1024>> {
+    Arc::new(Config::new().path(path).flush_every_ms(None).open().unwrap())
+}
+
+fn managers(db: &Arc<Db<1024>>) -> [HybridOperationsManager; 2] {
+    [
+        HybridOperationsManager::new(db.clone()),
+        HybridOperationsManager::new_with_db_worker(db.clone()),
+    ]
+}
+
+#[test]
+fn test_concurrent_add_f64_is_exact() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+
+    for manager in managers(&db) {
+        let manager = Arc::new(manager);
+        manager.reset_f64("sum".to_string(), 0.0).unwrap();
+
+        // 0.25、0.5、0.75...的和在累加过程中都能被f64精确表示
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let manager = manager.clone();
+                std::thread::spawn(move || {
+                    let delta = (t + 1) as f64 * 0.25;
+                    for _ in 0..ADDS_PER_THREAD {
+                        manager.add_f64("sum".to_string(), delta).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let expected = (1..=THREADS).map(|t| t as f64 * 0.25).sum::<f64>() * ADDS_PER_THREAD as f64;
+        assert_eq!(manager.get_f64("sum".to_string()).unwrap(), Some(expected));
+    }
+}
+
+#[test]
+fn test_nan_and_infinity() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+
+    for manager in managers(&db) {
+        assert_eq!(manager.add_f64("x".to_string(), 1.5).unwrap(), 1.5);
+
+        let err = manager.add_f64("x".to_string(), f64::NAN).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = manager.reset_f64("x".to_string(), f64::NAN).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(manager.get_f64("x".to_string()).unwrap(), Some(1.5));
+
+        // 无穷大饱和到最大的有限值
+        assert_eq!(manager.add_f64("x".to_string(), f64::INFINITY).unwrap(), f64::MAX);
+        assert_eq!(manager.add_f64("x".to_string(), f64::MAX).unwrap(), f64::MAX);
+        manager.reset_f64("x".to_string(), f64::NEG_INFINITY).unwrap();
+        assert_eq!(manager.get_f64("x".to_string()).unwrap(), Some(f64::MIN));
+
+        assert_eq!(manager.get_f64("missing".to_string()).unwrap(), None);
+        manager.reset_f64("x".to_string(), 0.0).unwrap();
+    }
+}
+
+#[test]
+fn test_mixing_counter_kinds_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+
+    for manager in managers(&db) {
+        manager.increment("int".to_string(), 3).unwrap();
+        manager.add_f64("float".to_string(), 0.5).unwrap();
+
+        let err = manager.add_f64("int".to_string(), 1.0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mismatch = CounterTypeMismatch::from_io_error(&err).unwrap();
+        assert_eq!(mismatch.counter_name, "int");
+        assert_eq!(mismatch.expected, CounterKind::F64);
+        assert_eq!(mismatch.found, CounterKind::U64);
+        assert!(matches!(
+            MelangeError::from(err),
+            MelangeError::CounterTypeMismatch(_)
+        ));
+
+        for err in [
+            manager.increment("float".to_string(), 1).unwrap_err(),
+            manager.get("float".to_string()).unwrap_err(),
+            manager.reset("float".to_string(), 0).unwrap_err(),
+        ] {
+            let mismatch = CounterTypeMismatch::from_io_error(&err).unwrap();
+            assert_eq!(mismatch.expected, CounterKind::U64);
+            assert_eq!(mismatch.found, CounterKind::F64);
+        }
+        assert!(CounterTypeMismatch::from_io_error(
+            &manager.get_f64("int".to_string()).unwrap_err()
+        )
+        .is_some());
+
+        // 失败的操作不改变计数器的值
+        assert_eq!(manager.get("int".to_string()).unwrap(), Some(3));
+        assert_eq!(manager.get_f64("float".to_string()).unwrap(), Some(0.5));
+        manager.reset("int".to_string(), 0).unwrap();
+        manager.reset_f64("float".to_string(), 0.0).unwrap();
+    }
+}
+
+#[test]
+fn test_float_counters_persist_and_preload() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let manager = HybridOperationsManager::new_with_db_worker(db.clone());
+        manager.add_f64("float".to_string(), 2.5).unwrap();
+        manager.add_f64("float".to_string(), -0.125).unwrap();
+        manager.increment("int".to_string(), 7).unwrap();
+
+        // 持久化是异步的
+        let expected = [&[2u8][..], &2.375_f64.to_le_bytes()].concat();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while db.get(b"__atomic_counter__:float").unwrap().as_deref() != Some(&expected[..]) {
+            assert!(Instant::now() < deadline, "计数器没有被持久化");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        while db.get(b"__atomic_counter__:int").unwrap().is_none() {
+            assert!(Instant::now() < deadline, "计数器没有被持久化");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(manager);
+        db.flush().unwrap();
+    }
+
+    let db = open(dir.path());
+    for manager in managers(&db) {
+        let report = manager.preload_counters_report().unwrap();
+        assert_eq!(report.float_counters, vec![("float".to_string(), 2.375)]);
+        assert_eq!(report.counters, vec![("int".to_string(), 7)]);
+        assert!(report.issues.is_empty());
+        assert_eq!(manager.preload_counters().unwrap(), 2);
+        assert_eq!(manager.get_f64("float".to_string()).unwrap(), Some(2.375));
+        assert!(manager.get("float".to_string()).is_err());
+    }
+}
+
+#[test]
+fn test_evicted_float_counters_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+
+    for manager in managers(&db) {
+        manager.set_max_resident_counters(Some(10));
+        for i in 0..100 {
+            manager.add_f64(format!("f{}", i), i as f64 + 0.5).unwrap();
+            manager.increment(format!("u{}", i), i).unwrap();
+        }
+        assert!(manager.resident_counter_count() <= 10);
+
+        for i in 0..100 {
+            assert_eq!(manager.add_f64(format!("f{}", i), 0.25).unwrap(), i as f64 + 0.75);
+            assert!(manager.add_f64(format!("u{}", i), 1.0).is_err());
+            assert!(manager.get(format!("f{}", i)).is_err());
+        }
+        for i in 0..100 {
+            manager.reset_f64(format!("f{}", i), 0.0).unwrap();
+            manager.reset(format!("u{}", i), 0).unwrap();
+        }
+    }
+}
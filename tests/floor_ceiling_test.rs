@@ -0,0 +1,138 @@
+use std::io;
+
+use melange_db::*;
+
+const FANOUT: usize = 8;
+
+fn open() -> Db<FANOUT> {
+    Config::tmp().unwrap().flush_every_ms(None).open().unwrap()
+}
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+fn entry(i: u32) -> Option<(InlineArray, InlineArray)> {
+    Some((InlineArray::from(&key(i)), InlineArray::from(format!("v{}", i).as_bytes())))
+}
+
+/// 键为 10, 20, ..., 1000，跨越很多叶子节点
+fn populate(db: &Db<FANOUT>) {
+    for i in (10..=1000).step_by(10) {
+        db.insert(key(i), format!("v{}", i).into_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn test_exact_match() {
+    let db = open();
+    populate(&db);
+    for i in (10..=1000).step_by(10) {
+        assert_eq!(db.ceiling(key(i)).unwrap(), entry(i));
+        assert_eq!(db.floor(key(i)).unwrap(), entry(i));
+    }
+}
+
+#[test]
+fn test_gaps() {
+    let db = open();
+    populate(&db);
+    for i in 10..1000 {
+        let below = i / 10 * 10;
+        let above = below + 10;
+        if i % 10 != 0 {
+            assert_eq!(db.ceiling(key(i)).unwrap(), entry(above), "ceiling({})", i);
+            assert_eq!(db.floor(key(i)).unwrap(), entry(below), "floor({})", i);
+        }
+    }
+
+    // 比较不同长度的键：短键是长键的前缀时排在前面
+    assert_eq!(db.ceiling([0, 0, 1]).unwrap(), entry(260));
+    assert_eq!(db.floor([0, 0, 1]).unwrap(), entry(250));
+    assert_eq!(db.ceiling([0, 0, 0, 10, 0]).unwrap(), entry(20));
+    assert_eq!(db.floor([0, 0, 0, 10, 0]).unwrap(), entry(10));
+}
+
+#[test]
+fn test_boundaries() {
+    let db = open();
+    assert_eq!(db.ceiling(b"").unwrap(), None);
+    assert_eq!(db.floor([255; 8]).unwrap(), None);
+
+    populate(&db);
+
+    // 最小的键之下和最大的键之上
+    assert_eq!(db.ceiling(b"").unwrap(), entry(10));
+    assert_eq!(db.floor(b"").unwrap(), None);
+    assert_eq!(db.floor(key(9)).unwrap(), None);
+    assert_eq!(db.ceiling(key(9)).unwrap(), entry(10));
+    assert_eq!(db.ceiling(key(1001)).unwrap(), None);
+    assert_eq!(db.ceiling([255; 8]).unwrap(), None);
+    assert_eq!(db.floor(key(1001)).unwrap(), entry(1000));
+    assert_eq!(db.floor([255; 8]).unwrap(), entry(1000));
+    assert_eq!(db.first().unwrap(), db.ceiling(b"").unwrap());
+    assert_eq!(db.last().unwrap(), db.floor([255; 8]).unwrap());
+
+    // 空键本身也可以被找到
+    db.insert(b"", b"empty".as_slice()).unwrap();
+    let empty = Some((InlineArray::from(b""), InlineArray::from(b"empty")));
+    assert_eq!(db.ceiling(b"").unwrap(), empty);
+    assert_eq!(db.floor(b"").unwrap(), empty);
+    assert_eq!(db.floor(key(9)).unwrap(), empty);
+}
+
+#[test]
+fn test_skips_empty_leaves() {
+    let db = open();
+    populate(&db);
+
+    // 删除中间的大部分键，留下空的叶子节点
+    for i in (110..=900).step_by(10) {
+        db.remove(key(i)).unwrap();
+    }
+    for i in [100, 101, 500, 899, 900] {
+        assert_eq!(db.floor(key(i)).unwrap(), entry(100), "floor({})", i);
+    }
+    for i in [101, 500, 900, 901, 910] {
+        assert_eq!(db.ceiling(key(i)).unwrap(), entry(910), "ceiling({})", i);
+    }
+
+    // 删除所有的键
+    for i in (10..=1000).step_by(10) {
+        db.remove(key(i)).unwrap();
+    }
+    assert_eq!(db.ceiling(b"").unwrap(), None);
+    assert_eq!(db.floor([255; 8]).unwrap(), None);
+}
+
+#[test]
+fn test_matches_range_iteration() {
+    let db = open();
+    let tree = db.open_tree("sparse").unwrap();
+    for i in 0..2_000_u32 {
+        if i.wrapping_mul(2_654_435_761) % 7 < 2 {
+            tree.insert(key(i), key(i)).unwrap();
+        }
+    }
+
+    for i in (0..2_100).step_by(3) {
+        let ceiling = tree.range(key(i)..).next().transpose().unwrap();
+        let floor = tree.range(..=key(i)).next_back().transpose().unwrap();
+        assert_eq!(tree.ceiling(key(i)).unwrap(), ceiling);
+        assert_eq!(tree.floor(key(i)).unwrap(), floor);
+    }
+}
+
+#[test]
+fn test_values_stored_as_blobs() -> io::Result<()> {
+    let db: Db<FANOUT> = Config::tmp()?.inline_value_threshold(64).open()?;
+    let large = vec![7_u8; 1000];
+    db.insert(b"a", large.clone())?;
+    db.insert(b"c", b"small".as_slice())?;
+    db.flush()?;
+
+    assert_eq!(db.ceiling(b"")?.unwrap().1, large);
+    assert_eq!(db.floor(b"b")?.unwrap().1, large);
+    assert_eq!(db.ceiling(b"b")?.unwrap().1, b"small".as_slice());
+    Ok(())
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use std::time::Duration;
+
+use melange_db::*;
+
+use common::copy_dir;
+
+#[test]
+fn test_flush_async_makes_writes_durable() {
+    let dir = tempfile::tempdir().unwrap();
+    let copy = tempfile::tempdir().unwrap();
+
+    // 后台flusher的间隔很长，只有flush_async会写入数据
+    let mut config = Config::new().path(dir.path()).flush_every_ms(Some(60_000));
+    config.smart_flush_config.enabled = false;
+    let db: Db<1024> = config.open().unwrap();
+
+    for i in 0..1000_u64 {
+        db.insert(i.to_be_bytes(), i.to_le_bytes().as_slice()).unwrap();
+    }
+
+    let handle = db.flush_async();
+
+    // 在flush期间做一些无关的工作
+    let sum: u64 = (0..100_000_u64).sum();
+    assert_eq!(sum, 4_999_950_000);
+    std::thread::sleep(Duration::from_millis(10));
+
+    handle.wait().unwrap();
+    assert!(handle.is_complete());
+
+    // 在数据库仍然打开时复制目录，复制的数据只可能来自这次flush
+    copy_dir(dir.path(), copy.path().join("db").as_path());
+    drop(db);
+
+    let reopened: Db<1024> = Config::new().path(copy.path().join("db")).open().unwrap();
+    assert_eq!(reopened.len().unwrap(), 1000);
+    for i in 0..1000_u64 {
+        assert_eq!(
+            reopened.get(i.to_be_bytes()).unwrap().unwrap(),
+            i.to_le_bytes().as_slice()
+        );
+    }
+}
+
+#[test]
+fn test_flush_async_without_flusher_thread() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+
+    db.insert(b"key", b"value".as_slice()).unwrap();
+
+    let handle = db.flush_async();
+    handle.wait().unwrap();
+    assert!(handle.is_complete());
+
+    // 已经完成的句柄不会因为之后的写入而变回未完成
+    db.insert(b"other", b"value".as_slice()).unwrap();
+    assert!(handle.is_complete());
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+const TREES: usize = 10;
+
+fn config(path: &Path) -> Config {
+    // 没有后台flusher时每次释放Tree都会flush，使用一个不会触发的间隔
+    common::config(path).flush_every_ms(Some(3_600_000))
+}
+
+fn open_trees(db: &Db<1024>) -> Vec<Tree<1024>> {
+    (0..TREES).map(|i| db.open_tree(format!("tree_{}", i)).unwrap()).collect()
+}
+
+fn write(trees: &[Tree<1024>], round: u64) {
+    for tree in trees {
+        for i in 0..100_u64 {
+            tree.insert(i.to_be_bytes(), round.to_be_bytes()).unwrap();
+        }
+    }
+}
+
+/// 在 `get_or_insert_with` 的闭包中阻塞，使flush停在等待epoch静止的阶段
+fn hold_epoch_guard(tree: &Tree<1024>) -> (mpsc::Sender<()>, JoinHandle<()>) {
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let tree = tree.clone();
+    let holder = std::thread::spawn(move || {
+        tree.get_or_insert_with(b"held", || {
+            entered_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            vec![1]
+        })
+        .unwrap();
+    });
+    entered_rx.recv().unwrap();
+    (release_tx, holder)
+}
+
+#[test]
+fn test_flush_all_flushes_every_tree_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let trees = open_trees(&db);
+
+    write(&trees, 1);
+    let before = db.stats().cache.flushes;
+    let stats = db.flush_all().unwrap();
+    let flush_all_flushes = db.stats().cache.flushes - before;
+    assert!(stats.objects_flushed >= TREES as u64);
+
+    write(&trees, 2);
+    let before = db.stats().cache.flushes;
+    for tree in &trees {
+        tree.flush().unwrap();
+    }
+    let tree_flushes = db.stats().cache.flushes - before;
+
+    println!("flush_all: {} flushes, Tree::flush: {} flushes", flush_all_flushes, tree_flushes);
+    assert_eq!(flush_all_flushes, 1);
+    assert_eq!(tree_flushes, TREES as u64);
+
+    write(&trees, 3);
+    db.flush_all().unwrap();
+    drop(trees);
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    for tree in open_trees(&db) {
+        assert_eq!(tree.len().unwrap(), 100);
+        for kv in tree.iter() {
+            assert_eq!(&*kv.unwrap().1, 3_u64.to_be_bytes());
+        }
+    }
+}
+
+#[test]
+fn test_tree_flush_joins_running_flush_all() {
+    for coalesce in [true, false] {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).coalesce_flushes(coalesce).open().unwrap();
+        let trees = open_trees(&db);
+        write(&trees, 1);
+
+        let before = db.stats().cache;
+        let (release, holder) = hold_epoch_guard(&trees[0]);
+        let flush_all = {
+            let db = db.clone();
+            std::thread::spawn(move || db.flush_all().unwrap())
+        };
+        let start = Instant::now();
+        while db.debug_flush_state().flushes_in_progress.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let flushers: Vec<_> = trees
+            .iter()
+            .map(|tree| {
+                let tree = tree.clone();
+                std::thread::spawn(move || tree.flush().unwrap())
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(100));
+
+        release.send(()).unwrap();
+        holder.join().unwrap();
+        flush_all.join().unwrap();
+        for flusher in flushers {
+            flusher.join().unwrap();
+        }
+
+        let after = db.stats().cache;
+        let flushes = after.flushes - before.flushes;
+        let coalesced = after.flushes_coalesced - before.flushes_coalesced;
+        println!("coalesce_flushes {}: {} flushes, {} coalesced", coalesce, flushes, coalesced);
+        if coalesce {
+            // 运行中的flush_all开始之后的调用共用一次后续的flush
+            assert!(flushes <= 3, "{}", flushes);
+            assert!(coalesced >= TREES as u64 - 2, "{}", coalesced);
+        } else {
+            assert_eq!(flushes, TREES as u64 + 1);
+            assert_eq!(coalesced, 0);
+        }
+        assert!(db.debug_flush_state().dirty_collections.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use melange_db::*;
+
+use common::config;
+
+/// 在 `get_or_insert_with` 的闭包中阻塞，使写入持有的flush epoch guard
+/// 一直不被释放，直到向返回的发送端发送消息
+fn hold_epoch_guard(tree: &Tree<1024>) -> (mpsc::Sender<()>, JoinHandle<()>) {
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let tree = tree.clone();
+    let holder = std::thread::spawn(move || {
+        tree.get_or_insert_with(b"held", || {
+            entered_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            vec![1]
+        })
+        .unwrap();
+    });
+    entered_rx.recv().unwrap();
+    (release_tx, holder)
+}
+
+fn wait_for_stage(db: &Db<1024>, stage: FlushStage) -> FlushDebugReport {
+    let start = Instant::now();
+    loop {
+        let report = db.debug_flush_state();
+        if report.flushes_in_progress.iter().any(|flush| flush.stage == stage) {
+            return report;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "{:#?}", report);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_debug_flush_state_reports_held_guard() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("stuck").unwrap();
+    for i in 0..10_u64 {
+        tree.insert(i.to_be_bytes(), vec![0; 8]).unwrap();
+    }
+
+    let report = db.debug_flush_state();
+    assert!(report.flushes_in_progress.is_empty());
+    let dirty = report
+        .dirty_collections
+        .iter()
+        .find(|dirty| dirty.name.as_deref() == Some(&b"stuck"[..]))
+        .unwrap();
+    assert!(dirty.dirty_objects > 0);
+    assert!(report.oldest_dirty_age.is_some());
+    // 没有后台flusher线程
+    assert!(!report.flusher_alive);
+    assert_eq!(report.flusher_heartbeat, None);
+
+    let (release, holder) = hold_epoch_guard(&tree);
+
+    let report = db.debug_flush_state();
+    let active = &report.outstanding_guards[0];
+    assert_eq!(active.epoch, report.current_epoch);
+    assert!(!active.sealed);
+    assert_eq!(active.count, 1);
+
+    // flush封闭当前epoch，然后一直等待被持有的guard
+    let flusher = {
+        let db = db.clone();
+        std::thread::spawn(move || db.flush().unwrap())
+    };
+    let report = wait_for_stage(&db, FlushStage::WaitingForQuiescence);
+    let stuck = report
+        .outstanding_guards
+        .iter()
+        .find(|guards| guards.sealed)
+        .unwrap();
+    assert_eq!(stuck.count, 1);
+    assert_eq!(stuck.epoch, report.flushes_in_progress[0].epoch);
+    assert!(report.current_epoch > stuck.epoch);
+    assert_eq!(report.flushed_epoch, stuck.epoch - 1);
+
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(!flusher.is_finished());
+
+    release.send(()).unwrap();
+    holder.join().unwrap();
+    flusher.join().unwrap();
+
+    let report = db.debug_flush_state();
+    assert!(report.flushes_in_progress.is_empty());
+    assert!(report.flushed_epoch >= stuck.epoch);
+    assert!(report.outstanding_guards.iter().all(|guards| guards.count == 0));
+}
+
+#[test]
+fn test_flusher_heartbeat() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new()
+        .path(dir.path())
+        .flush_every_ms(Some(10))
+        .open()
+        .unwrap();
+
+    let first = db.debug_flush_state();
+    assert!(first.flusher_alive);
+    let first_heartbeat = first.flusher_heartbeat.unwrap();
+    assert!(first_heartbeat <= SystemTime::now());
+
+    let start = Instant::now();
+    while db.debug_flush_state().flusher_heartbeat.unwrap() == first_heartbeat {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_flush_watchdog_reports_stuck_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let (report_tx, report_rx) = mpsc::channel();
+    let report_tx = std::sync::Mutex::new(report_tx);
+    let db: Db<1024> = config(dir.path())
+        .flush_watchdog(Some(Duration::from_millis(50)))
+        .flush_watchdog_callback(Arc::new(move |report: &FlushDebugReport| {
+            report_tx.lock().unwrap().send(report.clone()).unwrap();
+        }))
+        .open()
+        .unwrap();
+    db.insert(b"a", vec![1]).unwrap();
+
+    // 正常的flush不触发看门狗
+    db.flush().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(report_rx.try_recv().is_err());
+
+    let (release, holder) = hold_epoch_guard(&db);
+    let flusher = {
+        let db = db.clone();
+        std::thread::spawn(move || db.flush().unwrap())
+    };
+
+    let report = report_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    let flush = &report.flushes_in_progress[0];
+    assert_eq!(flush.stage, FlushStage::WaitingForQuiescence);
+    assert!(flush.elapsed > Duration::from_millis(50));
+    assert!(report.outstanding_guards.iter().any(|guards| guards.sealed && guards.count == 1));
+
+    // 同一次flush只报告一次
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(report_rx.try_recv().is_err());
+
+    release.send(()).unwrap();
+    holder.join().unwrap();
+    flusher.join().unwrap();
+
+    let res = config(dir.path()).flush_watchdog(Some(Duration::ZERO)).open::<1024>();
+    assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(all(feature = "epoch-guard-backtraces", debug_assertions))]
+#[test]
+fn test_debug_flush_state_guard_backtraces() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+
+    let (release, holder) = hold_epoch_guard(&db);
+    let report = db.debug_flush_state();
+    let active = &report.outstanding_guards[0];
+    assert_eq!(active.backtraces.len(), 1);
+    assert!(active.backtraces[0].contains("get_or_insert_with"), "{}", active.backtraces[0]);
+
+    release.send(()).unwrap();
+    holder.join().unwrap();
+    assert!(db.debug_flush_state().outstanding_guards[0].backtraces.is_empty());
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+use melange_db::smart_flush::SmartFlushConfig;
+
+fn interval(base_interval_ms: usize) -> SmartFlushConfig {
+    SmartFlushConfig {
+        base_interval_ms,
+        min_interval_ms: base_interval_ms / 2,
+        max_interval_ms: base_interval_ms * 2,
+        ..SmartFlushConfig::default()
+    }
+}
+
+fn group_stats<const LEAF_FANOUT: usize>(db: &Db<LEAF_FANOUT>, name: &str) -> FlushGroupStats {
+    db.flush_group_stats().into_iter().find(|stats| stats.name == name).unwrap()
+}
+
+#[test]
+fn test_flush_groups_follow_their_own_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    // 默认调度的间隔很长，flush几乎都由两个组触发
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    db.update_smart_flush_config(interval(5_000)).unwrap();
+
+    let fast = db.open_tree_in_group("tenant_fast", "fast", interval(20)).unwrap();
+    let slow = db.open_tree_in_group("tenant_slow", "slow", interval(400)).unwrap();
+
+    // 两个组都有持续的写入，持续约2秒
+    let start = Instant::now();
+    let mut i = 0_u64;
+    while start.elapsed() < Duration::from_secs(2) {
+        fast.insert(i.to_be_bytes(), b"fast".as_slice()).unwrap();
+        slow.insert(i.to_be_bytes(), b"slow".as_slice()).unwrap();
+        i += 1;
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // 低负载时间隔最多延长到 max_interval_ms：快组约40ms一次，慢组约800ms一次
+    let fast_stats = group_stats(&db, "fast");
+    let slow_stats = group_stats(&db, "slow");
+    assert!(fast_stats.flushes >= 15, "{:?}", fast_stats);
+    assert!((1..=5).contains(&slow_stats.flushes), "{:?}", slow_stats);
+    assert!(fast_stats.flushes >= 5 * slow_stats.flushes, "{:?} {:?}", fast_stats, slow_stats);
+
+    // 每个组只记录自己的树的写入
+    assert_eq!(fast_stats.bytes_written, i * (8 + 4));
+    assert_eq!(slow_stats.bytes_written, i * (8 + 4));
+    assert_eq!(slow_stats.config.base_interval_ms, 400);
+
+    drop((fast, slow));
+    drop(db);
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    let slow = db.open_tree("tenant_slow").unwrap();
+    assert_eq!(slow.len().unwrap() as u64, i);
+}
+
+#[test]
+fn test_reopening_a_group_replaces_its_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+
+    let a = db.open_tree_in_group("a", "group", interval(100)).unwrap();
+    let b = db.open_tree_in_group("b", "group", interval(1_000)).unwrap();
+
+    let stats = db.flush_group_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].config.base_interval_ms, 1_000);
+
+    // 两个树的写入都计入同一个组，已经打开的句柄也属于这个组
+    a.insert(b"k", b"v".as_slice()).unwrap();
+    b.insert(b"k", b"v".as_slice()).unwrap();
+    db.open_tree("a").unwrap().insert(b"k2", b"v".as_slice()).unwrap();
+    assert_eq!(db.flush_group_stats()[0].bytes_written, 2 + 2 + 3);
+}
+
+#[test]
+fn test_invalid_flush_group() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    let invalid = SmartFlushConfig { min_interval_ms: 100, base_interval_ms: 50, ..interval(50) };
+    assert_eq!(
+        db.open_tree_in_group("t", "g", invalid).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    assert!(db.flush_group_stats().is_empty());
+    drop(db);
+
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+    assert_eq!(
+        db.open_tree_in_group("t", "g", interval(50)).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::Path;
+use std::thread;
+#[cfg(feature = "for-internal-testing-only")]
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+// 较小的叶子节点使每个树有很多脏叶子节点
+const FANOUT: usize = 16;
+const TREES: u32 = 8;
+const KEYS: u32 = 200;
+
+fn config(path: &Path, flush_threads: usize) -> Config {
+    common::config(path).flush_threads(flush_threads)
+}
+
+fn value(tree: u32, i: u32, round: u32) -> Vec<u8> {
+    format!("{}-{}-{}", tree, i, round).into_bytes()
+}
+
+fn open_trees(db: &Db<FANOUT>) -> Vec<Tree<FANOUT>> {
+    (0..TREES).map(|t| db.open_tree(format!("tree_{}", t)).unwrap()).collect()
+}
+
+/// 在每个树中写入所有的键。没有后台flusher时释放树的句柄会flush，
+/// 因此由调用者持有句柄
+fn write_all(trees: &[Tree<FANOUT>], round: u32) {
+    for (t, tree) in (0..).zip(trees) {
+        for i in 0..KEYS {
+            tree.insert(i.to_be_bytes(), value(t, i, round)).unwrap();
+        }
+    }
+}
+
+fn assert_contents(trees: &[Tree<FANOUT>], round: u32) {
+    for (t, tree) in (0..).zip(trees) {
+        assert_eq!(tree.len().unwrap(), KEYS as usize);
+        for i in 0..KEYS {
+            assert_eq!(tree.get(i.to_be_bytes()).unwrap().unwrap(), value(t, i, round));
+        }
+    }
+}
+
+/// 写入并flush一轮数据，返回 `flush_all` 的耗时
+#[cfg(feature = "for-internal-testing-only")]
+fn flush_time(flush_threads: usize) -> Duration {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<FANOUT> = config(dir.path(), flush_threads).open().unwrap();
+    let trees = open_trees(&db);
+    write_all(&trees, 0);
+    db.set_serialize_latency_for_testing(Duration::from_millis(1));
+
+    let start = Instant::now();
+    let stats = db.flush_all().unwrap();
+    let elapsed = start.elapsed();
+    assert!(stats.objects_flushed > 100, "{}", stats.objects_flushed);
+    elapsed
+}
+
+#[test]
+#[cfg(feature = "for-internal-testing-only")]
+fn test_flush_threads_parallelize_flush() {
+    let one = flush_time(1);
+    let four = flush_time(4);
+    // 每个叶子节点的序列化都较慢时，耗时大致与线程数成反比
+    assert!(four < one / 2, "1个线程 {:?}，4个线程 {:?}", one, four);
+}
+
+#[test]
+fn test_flush_threads_recover_correct_data() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<FANOUT> = config(dir.path(), 4).open().unwrap();
+        let trees = open_trees(&db);
+        for round in 0..3 {
+            write_all(&trees, round);
+            db.flush_all().unwrap();
+        }
+        // 删除后重新写入的键
+        trees[0].remove(0_u32.to_be_bytes()).unwrap();
+        db.flush_all().unwrap();
+        trees[0].insert(0_u32.to_be_bytes(), value(0, 0, 2)).unwrap();
+        db.flush_all().unwrap();
+    }
+
+    let db: Db<FANOUT> = config(dir.path(), 1).open().unwrap();
+    assert_contents(&open_trees(&db), 2);
+}
+
+#[test]
+fn test_flush_threads_with_concurrent_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<FANOUT> = config(dir.path(), 4).open().unwrap();
+        let trees = open_trees(&db);
+        write_all(&trees, 0);
+
+        // flush与写入同时进行，每次flush都只包含它的epoch之前的写入
+        let flusher = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    db.flush_all().unwrap();
+                }
+            })
+        };
+        for round in 1..=5 {
+            write_all(&trees, round);
+        }
+        flusher.join().unwrap();
+
+        db.flush_all().unwrap();
+        assert_contents(&trees, 5);
+    }
+
+    let db: Db<FANOUT> = config(dir.path(), 4).open().unwrap();
+    assert_contents(&open_trees(&db), 5);
+}
+
+#[test]
+fn test_flush_threads_must_be_positive() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = config(dir.path(), 0).open::<FANOUT>().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
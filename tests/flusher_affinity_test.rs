@@ -0,0 +1,206 @@
+#![cfg(target_os = "linux")]
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+use std::sync::Arc;
+
+const THREAD_NAME: &str = "melange-flushr";
+
+/// 返回当前进程允许使用的CPU核心
+fn allowed_cores(pid: libc::pid_t) -> Vec<usize> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::sched_getaffinity(pid, std::mem::size_of::<libc::cpu_set_t>(), &mut set)
+    };
+    assert_eq!(ret, 0, "{:?}", std::io::Error::last_os_error());
+
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|core| unsafe { libc::CPU_ISSET(*core, &set) })
+        .collect()
+}
+
+/// 通过 /proc/self/task 按线程名称查找线程ID。Linux的线程名称最长15个字节，
+/// 超出部分会被截断
+fn find_thread_by_name(name: &str) -> Option<libc::pid_t> {
+    let name = &name[..name.len().min(15)];
+    for entry in std::fs::read_dir("/proc/self/task").unwrap() {
+        let entry = entry.unwrap();
+        let comm = match std::fs::read_to_string(entry.path().join("comm")) {
+            Ok(comm) => comm,
+            Err(_) => continue,
+        };
+        if comm.trim_end() == name {
+            return entry.file_name().to_str().unwrap().parse().ok();
+        }
+    }
+    None
+}
+
+/// 读取线程的nice值（/proc/self/task/<tid>/stat 的第19个字段）
+fn thread_nice(tid: libc::pid_t) -> i32 {
+    let stat = std::fs::read_to_string(format!("/proc/self/task/{}/stat", tid)).unwrap();
+    // 线程名称可能包含空格，从最后一个右括号之后开始解析
+    let fields: Vec<&str> = stat[stat.rfind(')').unwrap() + 2..].split(' ').collect();
+    fields[16].parse().unwrap()
+}
+
+/// 等待指定名称的线程出现并满足条件
+fn wait_for_thread<T: PartialEq>(name: &str, expected: T, f: impl Fn(libc::pid_t) -> T) -> Option<T> {
+    let mut last = None;
+    for _ in 0..100 {
+        if let Some(tid) = find_thread_by_name(name) {
+            let value = f(tid);
+            if value == expected {
+                return Some(value);
+            }
+            last = Some(value);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    last
+}
+
+#[test]
+fn test_flusher_thread_affinity() {
+    // 选择当前进程允许使用的最后一个核心
+    let core = *allowed_cores(0).last().unwrap();
+
+    let db = Config::tmp()
+        .unwrap()
+        .flush_every_ms(Some(10))
+        .flusher_thread_name(Some(THREAD_NAME.to_string()))
+        .flusher_thread_affinity(Some(core))
+        .open::<1024>()
+        .unwrap();
+
+    db.insert(b"key", b"value".as_slice()).unwrap();
+
+    // 线程启动后才会设置亲和性，等待它生效
+    let mut pinned = vec![];
+    for _ in 0..100 {
+        if let Some(tid) = find_thread_by_name(THREAD_NAME) {
+            pinned = allowed_cores(tid);
+            if pinned == vec![core] {
+                break;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    assert_eq!(pinned, vec![core]);
+    assert_eq!(db.get(b"key").unwrap().unwrap(), b"value".as_slice());
+}
+
+#[test]
+fn test_flusher_cpu_affinity_set() {
+    const NAME: &str = "melange-fl-set";
+
+    // 选择当前进程允许使用的最后两个核心
+    let allowed = allowed_cores(0);
+    let cores: Vec<usize> = allowed[allowed.len().saturating_sub(2)..].to_vec();
+
+    let db = Config::tmp()
+        .unwrap()
+        .flush_every_ms(Some(10))
+        .flusher_thread_name(Some(NAME.to_string()))
+        // 核心集合优先于单个核心
+        .flusher_thread_affinity(Some(allowed[0]))
+        .flusher_cpu_affinity(Some(cores.clone()))
+        .open::<1024>()
+        .unwrap();
+
+    assert_eq!(wait_for_thread(NAME, cores.clone(), allowed_cores), Some(cores));
+
+    db.insert(b"key", b"value".as_slice()).unwrap();
+    assert_eq!(db.get(b"key").unwrap().unwrap(), b"value".as_slice());
+}
+
+#[test]
+fn test_flusher_thread_priority() {
+    const NAME: &str = "melange-fl-prio";
+
+    // 降低优先级不需要特殊权限
+    let db = Config::tmp()
+        .unwrap()
+        .flush_every_ms(Some(10))
+        .flusher_thread_name(Some(NAME.to_string()))
+        .flusher_thread_priority(Some(ThreadPriority::Lowest))
+        .open::<1024>()
+        .unwrap();
+
+    assert_eq!(wait_for_thread(NAME, 19, thread_nice), Some(19));
+    assert_eq!(ThreadPriority::Native(7).native_value().unwrap(), 7);
+    drop(db);
+}
+
+#[test]
+fn test_invalid_affinity_does_not_fail_open() {
+    // 无效的核心编号和可能没有权限的优先级只会输出警告
+    let db = Config::tmp()
+        .unwrap()
+        .flush_every_ms(Some(10))
+        .flusher_thread_name(Some("melange-fl-bad".to_string()))
+        .flusher_cpu_affinity(Some(vec![libc::CPU_SETSIZE as usize + 1]))
+        .flusher_thread_priority(Some(ThreadPriority::Highest))
+        .open::<1024>()
+        .unwrap();
+
+    db.insert(b"key", b"value".as_slice()).unwrap();
+    db.flush().unwrap();
+    assert_eq!(db.get(b"key").unwrap().unwrap(), b"value".as_slice());
+}
+
+#[test]
+fn test_internal_thread_names() {
+    let db = Config::tmp()
+        .unwrap()
+        .flush_every_ms(Some(10))
+        .bloom_auto_resize(true)
+        .open::<1024>()
+        .unwrap();
+    let manager = HybridOperationsManager::new_with_db_worker(Arc::new(db));
+    manager.increment("counter".to_string(), 1).unwrap();
+
+    for name in [
+        "melange-flush",
+        "melange-metadata",
+        "melange-bloom",
+        "melange-atomic-worker",
+        "melange-db-worker",
+    ] {
+        assert!(
+            wait_for_thread(name, true, |_| true).is_some(),
+            "没有找到名为 {} 的线程",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_flush_and_read_ahead_pools_use_flusher_thread_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = || {
+        Config::new()
+            .path(dir.path())
+            .flush_every_ms(None)
+            .flush_threads(2)
+            .flusher_thread_priority(Some(ThreadPriority::Lowest))
+    };
+
+    {
+        let db: Db<16> = config().open().unwrap();
+        for i in 0..2_000u32 {
+            db.insert(i.to_be_bytes(), vec![0; 64]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    // 重新打开后叶子节点都不在缓存中，扫描时在预读线程池中读取后面的叶子节点
+    let db: Db<16> = config().open().unwrap();
+    let options = IterOptions { read_ahead_leaves: 8, ..IterOptions::default() };
+    assert_eq!(db.iter_with(options).count(), 2_000);
+
+    for name in ["melange-flush-0", "melange-read-ahead-0"] {
+        assert_eq!(wait_for_thread(name, 19, thread_nice), Some(19), "{}", name);
+    }
+}
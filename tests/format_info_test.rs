@@ -0,0 +1,74 @@
+use melange_db::*;
+
+#[test]
+fn test_mismatched_leaf_fanout_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<256> = Config::new().path(dir.path()).open().unwrap();
+        db.insert(b"key", b"value".as_slice()).unwrap();
+        db.flush().unwrap();
+    }
+
+    let err = Config::new().path(dir.path()).open::<1024>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    let mismatch = LeafFanoutMismatch::from_io_error(&err).unwrap();
+    assert_eq!(mismatch.created_with, 256);
+    assert_eq!(mismatch.opened_with, 1024);
+    assert!(
+        err.to_string().contains("database was created with LEAF_FANOUT=256, opened with 1024"),
+        "{}",
+        err
+    );
+
+    // 拒绝打开后数据不受影响，使用正确的参数仍然可以打开
+    let db: Db<256> = Config::new().path(dir.path()).open().unwrap();
+    assert_eq!(&*db.get(b"key").unwrap().unwrap(), b"value");
+}
+
+#[test]
+fn test_format_info_of_open_db() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<64> = Config::new()
+        .path(dir.path())
+        .compression_algorithm(CompressionAlgorithm::Lz4)
+        .open()
+        .unwrap();
+
+    let info = db.format_info();
+    assert_eq!(info.leaf_fanout, 64);
+    assert_eq!(info.format_version, 2);
+    assert_eq!(info.compression_algorithm, Some(CompressionAlgorithm::Lz4));
+    assert_eq!(info.created_by_crate_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+
+    // 打开时显式的检查与数据库句柄返回的格式一致
+    assert_eq!(inspect(dir.path()).unwrap(), info);
+}
+
+#[test]
+fn test_inspect_closed_db() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<128> = Config::new().path(dir.path()).open().unwrap();
+        db.insert(b"key", b"value".as_slice()).unwrap();
+        db.flush().unwrap();
+    }
+
+    // 不需要const泛型参数即可读取格式，再据此打开数据库
+    let info = inspect(dir.path()).unwrap();
+    assert_eq!(info.leaf_fanout, 128);
+    assert_eq!(info.compression_algorithm, Some(CompressionAlgorithm::default()));
+
+    // 之后以不同的压缩算法打开不会改变创建时记录的格式
+    let db: Db<128> = Config::new()
+        .path(dir.path())
+        .compression_algorithm(CompressionAlgorithm::Zstd)
+        .open()
+        .unwrap();
+    assert_eq!(db.format_info(), info);
+    drop(db);
+
+    let missing = dir.path().join("missing");
+    let err = inspect(&missing).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(!missing.exists());
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use melange_db::*;
+
+use common::config;
+
+const N: u32 = 2_000;
+#[cfg(feature = "testing-count-allocator")]
+const VALUE_LEN: usize = 1024;
+
+// 计数分配器的统计是全局的，测试不能并行运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[cfg(feature = "testing-count-allocator")]
+#[test]
+fn test_memory_held_by_snapshot_is_reclaimed() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    for i in 0..N {
+        db.insert(i.to_be_bytes(), vec![0_u8; VALUE_LEN]).unwrap();
+    }
+    db.collect_garbage();
+
+    // 快照创建之后的修改使它保留旧值
+    let mut snapshot = db.snapshot_iter();
+    let (first, _) = snapshot.next().unwrap().unwrap();
+    assert_eq!(&first[..], 0_u32.to_be_bytes());
+
+    melange_db::alloc::reset();
+    for i in 0..N {
+        db.insert(i.to_be_bytes(), vec![1_u8; VALUE_LEN]).unwrap();
+    }
+    // 释放快照持有的树句柄会执行flush，先flush使之后的比较不受它影响
+    db.flush().unwrap();
+
+    // 新值和快照保留的旧值同时驻留在内存中
+    let retained = melange_db::alloc::resident();
+    assert!(retained >= N as usize * VALUE_LEN, "保留了 {} 字节", retained);
+
+    // 快照仍然读到旧值
+    let (_, value) = snapshot.next().unwrap().unwrap();
+    assert_eq!(&value[..], [0_u8; VALUE_LEN]);
+
+    drop(snapshot);
+    db.collect_garbage();
+    let after = melange_db::alloc::resident();
+    println!("释放快照前保留 {} 字节，释放并回收后 {} 字节", retained, after);
+    assert!(
+        retained - after >= N as usize * VALUE_LEN * 9 / 10,
+        "释放快照前保留 {} 字节，释放并回收后 {} 字节",
+        retained,
+        after
+    );
+}
+
+#[test]
+fn test_collect_garbage_keeps_data_intact() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).gc_interval(Some(Duration::from_millis(5))).open().unwrap();
+    let trees: Vec<_> = (0..4).map(|t| db.open_tree(format!("tree_{}", t)).unwrap()).collect();
+
+    // 大量的分裂和合并使索引节点不断被替换
+    let writers: Vec<_> = trees
+        .iter()
+        .cloned()
+        .map(|tree| {
+            std::thread::spawn(move || {
+                for round in 0..3_u32 {
+                    for i in 0..N {
+                        tree.insert(i.to_be_bytes(), round.to_le_bytes()).unwrap();
+                    }
+                    for i in (0..N).filter(|i| i % 4 != 0) {
+                        tree.remove(i.to_be_bytes()).unwrap();
+                    }
+                    tree.collect_garbage();
+                }
+            })
+        })
+        .collect();
+    for _ in 0..20 {
+        db.collect_garbage();
+        std::thread::sleep(Duration::from_millis(2));
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    db.collect_garbage();
+    for tree in &trees {
+        assert_eq!(tree.len().unwrap(), N as usize / 4);
+        assert_eq!(tree.get(4_u32.to_be_bytes()).unwrap().as_deref(), Some(&2_u32.to_le_bytes()[..]));
+    }
+    drop(trees);
+    drop(db);
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(db.open_tree("tree_0").unwrap().len().unwrap(), N as usize / 4);
+    db.check().unwrap();
+}
+
+#[test]
+fn test_zero_gc_interval_is_rejected() {
+    let err = Config::tmp().unwrap().gc_interval(Some(Duration::ZERO)).open::<1024>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
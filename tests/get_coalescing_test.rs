@@ -0,0 +1,98 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const THREADS: usize = 32;
+const GETS_PER_THREAD: usize = 50;
+
+fn open_manager() -> Arc<HybridOperationsManager> {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    Arc::new(HybridOperationsManager::new_with_db_worker(db))
+}
+
+fn hammer_hot_key(manager: &Arc<HybridOperationsManager>) {
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let manager = manager.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..GETS_PER_THREAD {
+                    let value = manager.get_data(b"hot").unwrap().unwrap();
+                    assert_eq!(&*value, b"hot value");
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_concurrent_gets_share_reads() {
+    let manager = open_manager();
+    manager.insert(b"hot", b"hot value").unwrap();
+    manager.set_get_coalescing(true);
+
+    hammer_hot_key(&manager);
+
+    let stats = manager.database_worker_stats().unwrap();
+    let requests = (THREADS * GETS_PER_THREAD) as u64;
+    println!("请求数: {}, 实际读取数: {}", stats.get_requests, stats.reads);
+    assert_eq!(stats.get_requests, requests);
+    assert_eq!(stats.reads + stats.coalesced_gets, requests);
+    assert!(
+        stats.reads * 4 < requests,
+        "合并后仍执行了 {} 次读取（共 {} 个请求）",
+        stats.reads,
+        requests
+    );
+}
+
+#[test]
+fn test_coalescing_disabled_by_default() {
+    let manager = open_manager();
+    manager.insert(b"hot", b"hot value").unwrap();
+
+    hammer_hot_key(&manager);
+
+    let stats = manager.database_worker_stats().unwrap();
+    assert_eq!(stats.reads, (THREADS * GETS_PER_THREAD) as u64);
+    assert_eq!(stats.coalesced_gets, 0);
+}
+
+#[test]
+fn test_coalesced_get_observes_preceding_write() {
+    let manager = open_manager();
+    manager.set_get_coalescing(true);
+
+    // 每次写入之后的读取都能看到刚写入的值，不会共享写入之前开始的读取
+    for i in 0..200_u32 {
+        let value = i.to_le_bytes();
+        manager.insert(b"key", &value).unwrap();
+        assert_eq!(&*manager.get_data(b"key").unwrap().unwrap(), &value);
+    }
+    assert_eq!(manager.get_data(b"missing").unwrap(), None);
+}
+
+#[test]
+fn test_coalescing_setting_survives_worker_mode_toggle() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let mut manager = HybridOperationsManager::new(db);
+    assert!(manager.database_worker_stats().is_none());
+
+    manager.set_get_coalescing(true);
+    manager.enable_database_worker_mode();
+    manager.insert(b"hot", b"hot value").unwrap();
+
+    let manager = Arc::new(manager);
+    hammer_hot_key(&manager);
+
+    let stats = manager.database_worker_stats().unwrap();
+    assert!(stats.coalesced_gets > 0, "{:?}", stats);
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+
+use melange_db::*;
+
+use common::config;
+
+// 只统计当前线程的分配次数，后台线程的分配不影响结果
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const N: u32 = 1_000;
+
+fn value(i: u32, len: usize) -> Vec<u8> {
+    (0..len).map(|j| (i as usize * 31 + j) as u8).collect()
+}
+
+/// 只读取值中的两个字段
+fn fields(value: &[u8]) -> (u8, u8) {
+    (value[0], value[value.len() - 1])
+}
+
+#[test]
+fn test_get_in_place_matches_get() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(1024).open().unwrap();
+
+    // 偶数键的值内联在叶子节点中，奇数键的值单独存储
+    for i in 0..N {
+        let len = if i % 2 == 0 { 512 } else { 4096 };
+        db.insert(i.to_be_bytes(), value(i, len)).unwrap();
+    }
+
+    for i in 0..N {
+        let expected = db.get(i.to_be_bytes()).unwrap().unwrap();
+        let read = db.get_in_place(i.to_be_bytes(), |value| (value.len(), fields(value))).unwrap();
+        assert_eq!(read, Some((expected.len(), fields(&expected))));
+        assert!(db.contains_key(i.to_be_bytes()).unwrap());
+    }
+    assert_eq!(db.get_in_place(N.to_be_bytes(), |value| value.to_vec()).unwrap(), None);
+    assert!(!db.contains_key(N.to_be_bytes()).unwrap());
+
+    let stats = db.bloom_stats();
+    assert_eq!(stats.lookups, 3 * N as u64 + 2);
+}
+
+#[test]
+fn test_contains_key_does_not_read_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(1024).open().unwrap();
+    db.insert(b"large", value(0, 32 * 1024)).unwrap();
+    db.flush().unwrap();
+
+    // 删除blob文件后读取值失败，检查键是否存在不受影响
+    for entry in fs::read_dir(dir.path().join("blobs")).unwrap() {
+        fs::remove_file(entry.unwrap().path()).unwrap();
+    }
+    assert!(db.get(b"large").is_err());
+    assert!(db.get_in_place(b"large", |value| value.len()).is_err());
+    assert!(db.contains_key(b"large").unwrap());
+}
+
+#[test]
+fn test_hot_path_is_allocation_free() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).cache_capacity_bytes(64 * 1024 * 1024).open().unwrap();
+    let keys: Vec<[u8; 4]> = (0..N).map(u32::to_be_bytes).collect();
+    for (i, key) in keys.iter().enumerate() {
+        db.insert(key, value(i as u32, 4096)).unwrap();
+    }
+
+    // 预热缓存
+    for key in &keys {
+        db.get_in_place(key, fields).unwrap().unwrap();
+    }
+
+    let before = allocations();
+    let mut checksum = 0_u64;
+    for key in &keys {
+        let (first, last) = db.get_in_place(key, fields).unwrap().unwrap();
+        checksum += u64::from(first) + u64::from(last);
+        assert!(db.contains_key(key).unwrap());
+    }
+    let in_place = allocations() - before;
+
+    let expected: u64 = keys
+        .iter()
+        .map(|key| {
+            let value = db.get(key).unwrap().unwrap();
+            u64::from(value[0]) + u64::from(value[value.len() - 1])
+        })
+        .sum();
+    assert_eq!(checksum, expected);
+    assert_eq!(in_place, 0, "{} 次读取分配了 {} 次", N, in_place);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "called back into the tree")]
+fn test_reentrant_call_panics_in_debug_builds() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    db.insert(b"a", b"1".as_slice()).unwrap();
+
+    db.get_in_place(b"a", |value| db.insert(b"a", value)).unwrap();
+}
+
+#[test]
+fn test_other_trees_are_accessible_from_the_closure() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let other = db.open_tree("other").unwrap();
+    db.insert(b"a", b"1".as_slice()).unwrap();
+
+    db.get_in_place(b"a", |value| other.insert(b"copy", value).unwrap()).unwrap();
+    assert_eq!(other.get(b"copy").unwrap().as_deref(), Some(&b"1"[..]));
+
+    // 闭包panic之后同一个树仍然可以访问
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        db.get_in_place(b"a", |_| panic!("closure panicked")).unwrap();
+    }));
+    assert!(res.is_err());
+    db.insert(b"a", b"2".as_slice()).unwrap();
+    assert_eq!(db.get_in_place(b"a", <[u8]>::to_vec).unwrap(), Some(b"2".to_vec()));
+}
@@ -0,0 +1,74 @@
+use melange_db::*;
+use quickcheck::{QuickCheck, TestResult};
+
+fn open<const LEAF_FANOUT: usize>(dir: &tempfile::TempDir) -> Db<LEAF_FANOUT> {
+    Config::new().path(dir.path()).flush_every_ms(None).open().unwrap()
+}
+
+/// 叶子节点被访问的次数
+fn leaf_loads<const LEAF_FANOUT: usize>(db: &Db<LEAF_FANOUT>) -> u64 {
+    let stats = db.stats().cache;
+    stats.cache_hits + stats.cache_misses
+}
+
+#[test]
+fn test_get_many_empty_and_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<1024>(&dir);
+
+    let empty: [&[u8]; 0] = [];
+    assert!(db.get_many(&empty).unwrap().is_empty());
+
+    db.insert(b"a", b"1".as_slice()).unwrap();
+    db.insert(b"b", b"2".as_slice()).unwrap();
+
+    let values = db.get_many(&[b"b", b"a", b"x", b"b"]).unwrap();
+    let values: Vec<Option<&[u8]>> = values.iter().map(|v| v.as_deref()).collect();
+    assert_eq!(values, [Some(b"2".as_slice()), Some(b"1".as_slice()), None, Some(b"2".as_slice())]);
+}
+
+#[test]
+fn test_get_many_matches_get() {
+    fn prop(entries: Vec<(Vec<u8>, Vec<u8>)>, probes: Vec<Vec<u8>>) -> TestResult {
+        let dir = tempfile::tempdir().unwrap();
+        // 较小的叶子节点使键分布在多个叶子节点中
+        let db = open::<4>(&dir);
+        for (key, value) in &entries {
+            db.insert(key, value.as_slice()).unwrap();
+        }
+
+        // 探测键同时包含存在和不存在的键
+        let mut keys = probes;
+        keys.extend(entries.iter().map(|(key, _)| key.clone()));
+
+        let expected: Vec<Option<InlineArray>> = keys.iter().map(|key| db.get(key).unwrap()).collect();
+        TestResult::from_bool(db.get_many(&keys).unwrap() == expected)
+    }
+
+    QuickCheck::new()
+        .tests(200)
+        .quickcheck(prop as fn(Vec<(Vec<u8>, Vec<u8>)>, Vec<Vec<u8>>) -> TestResult);
+}
+
+#[test]
+fn test_get_many_loads_each_leaf_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<64>(&dir);
+    for i in 0..10_000_u64 {
+        db.insert(i.to_be_bytes(), i.to_le_bytes().as_slice()).unwrap();
+    }
+
+    // 请求中的键聚集在少数几个叶子节点中，顺序被打乱
+    let keys: Vec<[u8; 8]> = (0..200_u64).map(|i| (5_000 + (i * 37) % 200).to_be_bytes()).collect();
+
+    let before = leaf_loads(&db);
+    let expected: Vec<Option<InlineArray>> = keys.iter().map(|key| db.get(key).unwrap()).collect();
+    let loop_loads = leaf_loads(&db) - before;
+
+    let before = leaf_loads(&db);
+    assert_eq!(db.get_many(&keys).unwrap(), expected);
+    let batch_loads = leaf_loads(&db) - before;
+
+    assert_eq!(loop_loads, 200);
+    assert!(batch_loads <= 10, "get_many 访问了 {} 次叶子节点", batch_loads);
+}
@@ -0,0 +1,108 @@
+use melange_db::*;
+
+const FANOUT: usize = 16;
+
+#[test]
+fn test_overwrite_advances_write_epoch() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+
+    db.insert(b"k", vec![1; 10]).unwrap();
+    let (value, before) = db.get_with_metadata(b"k").unwrap().unwrap();
+    assert_eq!(value, vec![1; 10]);
+    assert_eq!(before.write_epoch, db.current_epoch());
+    assert_eq!(before.stored_bytes, 10);
+
+    db.flush().unwrap();
+
+    // flush之后读取，写入的epoch不变
+    let (_, unchanged) = db.get_with_metadata(b"k").unwrap().unwrap();
+    assert_eq!(unchanged, before);
+
+    db.insert(b"k", vec![2; 300]).unwrap();
+    let (value, after) = db.get_with_metadata(b"k").unwrap().unwrap();
+    assert_eq!(value, vec![2; 300]);
+    assert!(after.write_epoch > before.write_epoch, "{:?} {:?}", before, after);
+    assert_eq!(after.write_epoch, db.current_epoch());
+    assert_eq!(after.stored_bytes, 300);
+
+    assert_eq!(db.get_with_metadata(b"missing").unwrap(), None);
+    db.remove(b"k").unwrap();
+    assert_eq!(db.get_with_metadata(b"k").unwrap(), None);
+}
+
+#[test]
+fn test_unmodified_values_keep_their_epoch() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let tree = db.open_tree("metadata").unwrap();
+
+    tree.insert(b"a", b"1".as_slice()).unwrap();
+    let first = tree.get_with_metadata(b"a").unwrap().unwrap().1.write_epoch;
+    db.flush().unwrap();
+
+    // 写入相同的值不算修改
+    tree.insert(b"a", b"1".as_slice()).unwrap();
+    assert_eq!(tree.get_with_metadata(b"a").unwrap().unwrap().1.write_epoch, first);
+
+    // 同一个叶子节点中其他键的写入、分裂和合并不影响它的epoch
+    for i in 0..500_u32 {
+        tree.insert(i.to_be_bytes(), i.to_be_bytes().to_vec()).unwrap();
+    }
+    db.flush().unwrap();
+    for i in 0..500_u32 {
+        tree.remove(i.to_be_bytes()).unwrap();
+    }
+    db.flush().unwrap();
+    assert_eq!(tree.get_with_metadata(b"a").unwrap().unwrap().1.write_epoch, first);
+
+    // 比较并交换和批量写入同样记录写入的epoch
+    tree.compare_and_swap(b"a", Some(b"1".as_slice()), Some(b"2".as_slice())).unwrap().unwrap();
+    let swapped = tree.get_with_metadata(b"a").unwrap().unwrap().1.write_epoch;
+    assert!(swapped > first);
+    db.flush().unwrap();
+
+    let mut batch = Batch::default();
+    batch.insert(b"a", b"3".as_slice());
+    tree.apply_batch(batch).unwrap();
+    assert!(tree.get_with_metadata(b"a").unwrap().unwrap().1.write_epoch > swapped);
+}
+
+#[test]
+fn test_write_epoch_after_reopen_is_stable_upper_bound() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<FANOUT> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+        for i in 0..100_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 8]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    let db: Db<FANOUT> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+    let (_, loaded) = db.get_with_metadata(7_u32.to_be_bytes()).unwrap().unwrap();
+    assert!(loaded.write_epoch <= db.current_epoch());
+    assert_eq!(loaded.stored_bytes, 8);
+
+    // 叶子节点留在缓存中时，未修改的值的epoch不变
+    db.flush().unwrap();
+    db.flush().unwrap();
+    let (_, again) = db.get_with_metadata(7_u32.to_be_bytes()).unwrap().unwrap();
+    assert_eq!(again, loaded);
+
+    db.insert(7_u32.to_be_bytes(), vec![1; 8]).unwrap();
+    let (_, written) = db.get_with_metadata(7_u32.to_be_bytes()).unwrap().unwrap();
+    assert!(written.write_epoch > loaded.write_epoch);
+}
+
+#[test]
+fn test_stored_bytes_of_blob_values() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().inline_value_threshold(64).open().unwrap();
+
+    // 内联的值带有一个标记字节，blob的大小包括引用和blob文件
+    db.insert(b"small", vec![0; 10]).unwrap();
+    db.insert(b"large", vec![0; 1_000]).unwrap();
+    let (_, small) = db.get_with_metadata(b"small").unwrap().unwrap();
+    let (value, large) = db.get_with_metadata(b"large").unwrap().unwrap();
+    assert_eq!(value.len(), 1_000);
+    assert_eq!(small.stored_bytes, 1 + 10);
+    assert!(large.stored_bytes > 1_000 && large.stored_bytes < 1_100, "{:?}", large);
+}
@@ -0,0 +1,148 @@
+use std::io;
+
+use melange_db::*;
+use melange_db::smart_flush::SmartFlushConfig;
+
+const N: usize = 100_000;
+
+fn key(i: usize) -> [u8; 8] {
+    (i as u64).to_be_bytes()
+}
+
+/// 不易压缩的值，使flush写入的字节数接近值的大小
+fn value(i: usize) -> Vec<u8> {
+    let mut x = i as u64 ^ 0x9E37_79B9_7F4A_7C15;
+    (0..100)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_lowering_cache_capacity_evicts_without_data_loss() {
+    let dir = tempfile::tempdir().unwrap();
+    // 缓存的每个分片至少保留一个叶子节点，使用较小的叶子节点使新容量能容纳
+    // 每个分片的多个叶子节点
+    let db: Db<64> = Config::new()
+        .path(dir.path())
+        .flush_every_ms(None)
+        .cache_capacity_bytes(256 * 1024 * 1024)
+        .open()
+        .unwrap();
+
+    for i in 0..N {
+        db.insert(key(i), value(i)).unwrap();
+    }
+
+    let limit = 4 * 1024 * 1024;
+    assert!(db.cache_resident_bytes() > limit, "{}", db.cache_resident_bytes());
+
+    // 尚未flush的叶子节点在flush之后才被淘汰
+    db.set_cache_capacity_bytes(limit);
+    assert_eq!(db.stats().cache.cache_capacity_bytes, limit);
+    db.flush().unwrap();
+    assert!(db.cache_resident_bytes() <= limit, "{}", db.cache_resident_bytes());
+
+    // 被淘汰的叶子节点从磁盘读回，读取过程中内存占用保持在新容量附近
+    for i in 0..N {
+        assert_eq!(&*db.get(key(i)).unwrap().unwrap(), &value(i)[..]);
+    }
+    assert!(db.cache_resident_bytes() <= 2 * limit, "{}", db.cache_resident_bytes());
+
+    // 再次扩大容量后叶子节点可以重新留在缓存中
+    db.set_cache_capacity_bytes(256 * 1024 * 1024);
+    for i in 0..N {
+        db.get(key(i)).unwrap();
+    }
+    assert!(db.cache_resident_bytes() > limit, "{}", db.cache_resident_bytes());
+}
+
+#[test]
+fn test_update_smart_flush_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+
+    let config = SmartFlushConfig {
+        base_interval_ms: 20,
+        min_interval_ms: 10,
+        max_interval_ms: 40,
+        ..SmartFlushConfig::default()
+    };
+    db.update_smart_flush_config(config).unwrap();
+    assert_eq!(db.smart_flush_config().base_interval_ms, 20);
+
+    // 无效的配置被拒绝，当前配置不变
+    let invalid = SmartFlushConfig {
+        min_interval_ms: 100,
+        base_interval_ms: 50,
+        ..SmartFlushConfig::default()
+    };
+    let error = db.update_smart_flush_config(invalid).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(db.smart_flush_config().base_interval_ms, 20);
+
+    // 新配置生效后写入仍然被后台flush
+    db.insert(b"key", b"value".as_slice()).unwrap();
+    drop(db);
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    assert_eq!(&*db.get(b"key").unwrap().unwrap(), b"value");
+}
+
+#[test]
+fn test_update_smart_flush_config_without_smart_flusher() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+
+    let error = db.update_smart_flush_config(SmartFlushConfig::default()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_flush_io_rate_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+
+    assert_eq!(
+        db.set_flush_io_rate_limit(Some(0)).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    // 每秒8MB的限制下，写入约10MB的flush至少需要一秒多
+    let limit = 8 * 1024 * 1024;
+    db.set_flush_io_rate_limit(Some(limit)).unwrap();
+    assert_eq!(db.stats().cache.flush_io_rate_limit, Some(limit));
+    for i in 0..N {
+        db.insert(key(i), value(i)).unwrap();
+    }
+    let stats = db.flush().unwrap();
+    let written = stats.write_batch.heap_bytes_written + stats.write_batch.metadata_bytes_written;
+    assert!(written > 2 * 1024 * 1024, "{}", written);
+    assert!(
+        stats.storage_latency + stats.rate_limit_latency
+            >= std::time::Duration::from_secs_f64(written as f64 / limit as f64),
+        "{:?}",
+        stats
+    );
+
+    // 取消限制后不再等待
+    db.set_flush_io_rate_limit(None).unwrap();
+    for i in 0..N {
+        db.insert(key(i), value(i + 1)).unwrap();
+    }
+    assert_eq!(db.flush().unwrap().rate_limit_latency, std::time::Duration::ZERO);
+}
+
+#[test]
+fn test_invalid_flush_io_rate_limit_at_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let error = Config::new()
+        .path(dir.path())
+        .flush_io_rate_limit(Some(0))
+        .open::<1024>()
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::path::Path;
+
+use melange_db::*;
+
+const N: u32 = 50_000;
+
+fn open(path: &Path) -> Db<1024> {
+    Config::new().path(path).flush_every_ms(None).open().unwrap()
+}
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:08}", i).into_bytes()
+}
+
+fn populate(db: &Db<1024>) {
+    let users = db.open_tree("users").unwrap();
+    for i in 0..N {
+        db.insert(key(i), format!("default_{}", i).as_bytes()).unwrap();
+        users.insert(key(i), vec![(i % 251) as u8; 64]).unwrap();
+    }
+}
+
+/// 默认树和所有命名树的内容
+fn contents(db: &Db<1024>, names: &[&str]) -> Vec<Vec<(InlineArray, InlineArray)>> {
+    let mut all = vec![db.iter().collect::<io::Result<Vec<_>>>().unwrap()];
+    for name in names {
+        if db.contains_tree(name).unwrap() {
+            all.push(db.open_tree(name).unwrap().iter().collect::<io::Result<Vec<_>>>().unwrap());
+        } else {
+            all.push(vec![]);
+        }
+    }
+    all
+}
+
+fn objects_bytes(backup: &Path) -> u64 {
+    std::fs::metadata(backup.join("objects")).unwrap().len()
+}
+
+#[test]
+fn test_full_and_incremental_backup_restore() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let backups = tempfile::tempdir().unwrap();
+    let (full, delta) = (backups.path().join("full"), backups.path().join("delta"));
+
+    let db = open(db_dir.path());
+    populate(&db);
+    let epoch = db.backup(&full).unwrap();
+    assert_eq!(BackupEpoch::of_backup(&full).unwrap(), epoch);
+
+    // 修改少量的键：更新、删除、插入，以及新建一个树
+    let users = db.open_tree("users").unwrap();
+    for i in (0..N).step_by(20_000) {
+        db.insert(key(i), b"updated".as_slice()).unwrap();
+        users.remove(key(i + 1)).unwrap();
+    }
+    users.insert(b"zzz_new", b"new".as_slice()).unwrap();
+    db.open_tree("orders").unwrap().insert(b"order_1", b"book".as_slice()).unwrap();
+
+    let next_epoch = db.backup_incremental(&delta, epoch).unwrap();
+    assert_ne!(next_epoch, epoch);
+
+    println!("全量备份: {} 字节, 增量备份: {} 字节", objects_bytes(&full), objects_bytes(&delta));
+    assert!(objects_bytes(&delta) * 5 < objects_bytes(&full));
+
+    // 恢复到一个新的路径
+    let restored_dir = tempfile::tempdir().unwrap();
+    let restored = open(restored_dir.path());
+    restored.restore_incremental(&full, &[&delta]).unwrap();
+
+    let names = ["users", "orders"];
+    assert_eq!(contents(&restored, &names), contents(&db, &names));
+
+    // 只恢复全量备份得到修改之前的内容
+    let base_only = open(tempfile::tempdir().unwrap().path());
+    base_only.restore_incremental::<_, &Path>(&full, &[]).unwrap();
+    assert_eq!(&*base_only.get(key(0)).unwrap().unwrap(), b"default_0");
+    assert!(!base_only.contains_tree("orders").unwrap());
+}
+
+#[test]
+fn test_incremental_backup_across_reopen() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let backups = tempfile::tempdir().unwrap();
+    let paths: Vec<_> = (0..3).map(|i| backups.path().join(format!("backup_{}", i))).collect();
+    let names = ["users"];
+
+    let epoch = {
+        let db = open(db_dir.path());
+        populate(&db);
+        db.backup(&paths[0]).unwrap()
+    };
+
+    // 重新打开后修改记录丢失，增量备份包含所有叶子节点
+    let db = open(db_dir.path());
+    let users = db.open_tree("users").unwrap();
+    for i in 0..100 {
+        users.remove(key(i)).unwrap();
+    }
+    let epoch = db.backup_incremental(&paths[1], epoch).unwrap();
+    assert!(objects_bytes(&paths[1]) * 10 > objects_bytes(&paths[0]) * 9);
+
+    // 之后在同一个进程中的增量备份只包含修改过的叶子节点，被删除的叶子节点
+    // 不会被恢复
+    for i in 0..100 {
+        db.remove(key(i)).unwrap();
+    }
+    users.clear().unwrap();
+    db.backup_incremental(&paths[2], epoch).unwrap();
+    assert!(objects_bytes(&paths[2]) * 5 < objects_bytes(&paths[1]));
+
+    let restored = open(tempfile::tempdir().unwrap().path());
+    restored.restore_incremental(&paths[0], &paths[1..]).unwrap();
+    assert_eq!(contents(&restored, &names), contents(&db, &names));
+    assert!(restored.open_tree("users").unwrap().is_empty().unwrap());
+    assert_eq!(restored.len().unwrap(), (N - 100) as usize);
+}
+
+#[test]
+fn test_broken_backup_chain_is_rejected() {
+    let backups = tempfile::tempdir().unwrap();
+    let paths: Vec<_> = (0..3).map(|i| backups.path().join(format!("backup_{}", i))).collect();
+
+    let db = open(tempfile::tempdir().unwrap().path());
+    populate(&db);
+    let epoch = db.backup(&paths[0]).unwrap();
+
+    // 备份目录必须为空
+    let error = db.backup(&paths[0]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+    db.insert(b"a", b"1".as_slice()).unwrap();
+    let epoch = db.backup_incremental(&paths[1], epoch).unwrap();
+    db.insert(b"b", b"2".as_slice()).unwrap();
+    db.backup_incremental(&paths[2], epoch).unwrap();
+
+    let restored = open(tempfile::tempdir().unwrap().path());
+
+    // 缺少中间的增量备份
+    let error = restored.restore_incremental(&paths[0], &[&paths[2]]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // 增量备份不能作为基础备份
+    let error = restored.restore_incremental(&paths[1], &[&paths[2]]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    // 不完整的备份
+    std::fs::remove_file(paths[2].join("MANIFEST")).unwrap();
+    let error = restored.restore_incremental(&paths[0], &paths[1..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    assert!(restored.is_empty().unwrap());
+}
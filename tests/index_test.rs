@@ -0,0 +1,259 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+use fault_injection::FAULT_INJECT_COUNTER;
+use melange_db::index::{IndexedTree, RebuildProgress};
+use melange_db::*;
+
+use common::copy_dir;
+
+// 注入故障的计数器是全局的，这个文件中的测试依次运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const N: u64 = 2_000;
+
+fn config(path: &Path) -> Config {
+    common::config(path).cache_capacity_bytes(64 * 1024)
+}
+
+/// 值是逗号分隔的索引键
+fn extract(_key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+    value.split(|b| *b == b',').filter(|k| !k.is_empty()).map(<[u8]>::to_vec).collect()
+}
+
+fn indexed(db: &Db<1024>) -> IndexedTree<1024> {
+    IndexedTree::new(db.open_tree("users").unwrap(), db.open_tree("users_by_tag").unwrap(), extract)
+}
+
+fn user(i: u64) -> Vec<u8> {
+    format!("user:{:05}", i).into_bytes()
+}
+
+fn tags(i: u64, round: u64) -> Vec<u8> {
+    format!("email{}@r{},group{},round{}", i, round, (i + round) % 7, round).into_bytes()
+}
+
+/// 检查索引树恰好包含基础树中每个条目的索引条目，返回基础树的条目数
+fn assert_consistent(users: &IndexedTree<1024>) -> usize {
+    let mut expected = BTreeSet::new();
+    let mut entries = 0;
+    for kv in users.base().iter() {
+        let (key, value) = kv.unwrap();
+        entries += 1;
+        for index_key in extract(&key, &value) {
+            let mut entry = (index_key.len() as u32).to_be_bytes().to_vec();
+            entry.extend_from_slice(&index_key);
+            entry.extend_from_slice(&key);
+            expected.insert(entry);
+        }
+    }
+    let actual: BTreeSet<Vec<u8>> =
+        users.index().iter().keys().map(|k| k.unwrap().to_vec()).collect();
+    assert_eq!(actual.len(), expected.len(), "基础树与索引树不一致");
+    assert!(actual == expected, "基础树与索引树不一致");
+    entries
+}
+
+fn by_index(users: &IndexedTree<1024>, index_key: &str) -> Vec<Vec<u8>> {
+    users
+        .get_by_index(index_key)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _v)| k.to_vec())
+        .collect()
+}
+
+#[test]
+fn test_updates_move_index_entries() {
+    let _serial = SERIAL.lock().unwrap();
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let users = indexed(&db);
+
+    assert_eq!(users.insert(user(1), b"a@x,admin,staff".as_slice()).unwrap(), None);
+    users.insert(user(2), b"b@x,staff".as_slice()).unwrap();
+    users.insert(user(3), b"c@x".as_slice()).unwrap();
+    assert_eq!(by_index(&users, "staff"), vec![user(1), user(2)]);
+    assert_eq!(by_index(&users, "a@x"), vec![user(1)]);
+
+    // 修改后旧值独有的索引键被删除，共同的保留，新的被添加
+    let old = users.insert(user(1), b"a@y,staff,owner,owner".as_slice()).unwrap();
+    assert_eq!(old.as_deref(), Some(&b"a@x,admin,staff"[..]));
+    assert!(by_index(&users, "a@x").is_empty());
+    assert!(by_index(&users, "admin").is_empty());
+    assert_eq!(by_index(&users, "a@y"), vec![user(1)]);
+    assert_eq!(by_index(&users, "owner"), vec![user(1)]);
+    assert_eq!(by_index(&users, "staff"), vec![user(1), user(2)]);
+    assert_eq!(assert_consistent(&users), 3);
+
+    // 不再有索引键的值
+    users.insert(user(2), b"".as_slice()).unwrap();
+    assert_eq!(by_index(&users, "staff"), vec![user(1)]);
+    assert_eq!(users.get(user(2)).unwrap().as_deref(), Some(&b""[..]));
+
+    assert!(users.remove(user(1)).unwrap().is_some());
+    assert_eq!(users.remove(user(1)).unwrap(), None);
+    assert!(by_index(&users, "staff").is_empty());
+    assert_eq!(assert_consistent(&users), 2);
+    assert_eq!(users.index().len().unwrap(), 1);
+
+    // 长度前缀使 "ab" + "c" 与 "a" + "bc" 不混淆
+    users.insert(b"c", b"ab".as_slice()).unwrap();
+    users.insert(b"bc", b"a".as_slice()).unwrap();
+    assert_eq!(by_index(&users, "ab"), vec![b"c".to_vec()]);
+    assert_eq!(by_index(&users, "a"), vec![b"bc".to_vec()]);
+}
+
+#[test]
+fn test_concurrent_writers_keep_index_consistent() {
+    let _serial = SERIAL.lock().unwrap();
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let users = indexed(&db);
+
+    let writers: Vec<_> = (0..4_u64)
+        .map(|t| {
+            let users = users.clone();
+            std::thread::spawn(move || {
+                for round in 0..200 {
+                    let i = (round * 7 + t) % 20;
+                    if round % 5 == 4 {
+                        users.remove(user(i)).unwrap();
+                    } else {
+                        users.insert(user(i), tags(i, round + t)).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_consistent(&users);
+}
+
+#[test]
+fn test_rebuild_reports_progress() {
+    let _serial = SERIAL.lock().unwrap();
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let users = indexed(&db);
+
+    // 直接写入基础树和索引树，绕过索引维护
+    for i in 0..N {
+        users.base().insert(user(i), tags(i, 0)).unwrap();
+    }
+    users.index().insert(b"stale", b"".as_slice()).unwrap();
+
+    let mut reports = vec![];
+    let report = users.rebuild(|progress| reports.push(progress)).unwrap();
+    assert_eq!(report, RebuildProgress { entries_scanned: N, index_entries_written: 3 * N });
+    assert_eq!(reports.last(), Some(&report));
+    assert!(reports.len() > 1);
+    assert!(reports.windows(2).all(|w| w[0].index_entries_written < w[1].index_entries_written));
+
+    assert_eq!(assert_consistent(&users), N as usize);
+    assert_eq!(by_index(&users, "email7@r0"), vec![user(7)]);
+}
+
+#[test]
+fn test_apply_batches_is_all_or_nothing() {
+    let _serial = SERIAL.lock().unwrap();
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let a = db.open_tree("a").unwrap();
+    let b = db.open_tree("b").unwrap();
+    a.insert(b"k", b"1".as_slice()).unwrap();
+
+    let mut batch_a = Batch::default();
+    batch_a.insert(b"x".as_slice(), b"1".as_slice());
+    let mut batch_b = Batch::default();
+    batch_b.insert(b"y".as_slice(), b"1".as_slice());
+    batch_b.guard(b"missing".as_slice(), Some(b"1"));
+    let err = db.apply_batches(vec![(&a, batch_a.clone()), (&b, batch_b)]).unwrap_err();
+    assert!(BatchGuardError::from_io_error(&err).is_some());
+    assert_eq!(a.get(b"x").unwrap(), None);
+    assert_eq!(b.get(b"y").unwrap(), None);
+
+    let err = db.apply_batches(vec![(&a, batch_a.clone()), (&a, Batch::default())]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let other: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let err = db.apply_batches(vec![(&*other, batch_a.clone())]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    db.apply_batches(vec![(&b, Batch::default()), (&a, batch_a)]).unwrap();
+    assert_eq!(a.get(b"x").unwrap().as_deref(), Some(&b"1"[..]));
+}
+
+#[test]
+fn test_base_and_index_never_diverge_after_crash() {
+    let _serial = SERIAL.lock().unwrap();
+    let mut recovered_old = false;
+    let mut recovered_new = false;
+
+    for fault_at in 1.. {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let users = indexed(&db);
+        for i in 0..N {
+            users.insert(user(i), tags(i, 0)).unwrap();
+        }
+        db.flush().unwrap();
+
+        // 更新改变每个条目的索引键，删除一部分条目
+        for i in 0..N {
+            if i % 10 == 0 {
+                users.remove(user(i)).unwrap();
+            } else {
+                users.insert(user(i), tags(i, 1)).unwrap();
+            }
+        }
+
+        if fault_at == 1 {
+            // 在flush之前崩溃
+            let crashed = tempfile::tempdir().unwrap();
+            copy_dir(dir.path(), crashed.path());
+            let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+            assert_eq!(assert_consistent(&indexed(&recovered)), N as usize);
+        }
+
+        FAULT_INJECT_COUNTER.store(fault_at, Ordering::Release);
+        let res = db.flush();
+        FAULT_INJECT_COUNTER.store(u64::MAX, Ordering::Release);
+
+        // 在注入故障的时刻崩溃
+        let crashed = tempfile::tempdir().unwrap();
+        copy_dir(dir.path(), crashed.path());
+        if res.is_ok() {
+            drop(users);
+            drop(db);
+        } else {
+            // 进程在这里崩溃，不再运行关闭时的flush
+            std::mem::forget(users);
+            std::mem::forget(db);
+        }
+
+        let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+        let users = indexed(&recovered);
+        let entries = assert_consistent(&users);
+        if entries == N as usize {
+            assert_eq!(by_index(&users, "round0").len(), N as usize);
+            recovered_old = true;
+        } else {
+            assert_eq!(entries, (N - N / 10) as usize);
+            assert!(by_index(&users, "round0").is_empty());
+            recovered_new = true;
+        }
+
+        if res.is_ok() {
+            assert_eq!(entries, (N - N / 10) as usize);
+            break;
+        }
+    }
+
+    assert!(recovered_old);
+    assert!(recovered_new);
+}
@@ -1,4 +1,6 @@
 use melange_db::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 #[test]
@@ -152,4 +154,109 @@ fn test_incremental_serialization() {
     drop(tree);
     drop(db);
     std::fs::remove_dir_all("incremental_integration_test_db").unwrap();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_get_or_insert_with_concurrent() {
+    let config = Config::new()
+        .path("get_or_insert_with_integration_test_db");
+
+    if std::path::Path::new("get_or_insert_with_integration_test_db").exists() {
+        std::fs::remove_dir_all("get_or_insert_with_integration_test_db").unwrap();
+    }
+
+    let db = config.open::<1024>().unwrap();
+    let tree = db.open_tree("memo").unwrap();
+    let computed = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(std::sync::Barrier::new(16));
+
+    // 多个线程同时请求同一个不存在的键，计算闭包只能执行一次
+    let handles: Vec<_> = (0..16)
+        .map(|_| {
+            let tree = tree.clone();
+            let computed = computed.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                tree.get_or_insert_with(b"memo_key", || {
+                    computed.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    b"expensive".to_vec()
+                })
+                .unwrap()
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), InlineArray::from(b"expensive".as_slice()));
+    }
+
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+    assert_eq!(tree.get(b"memo_key").unwrap(), Some(InlineArray::from(b"expensive".as_slice())));
+
+    // 清理
+    drop(tree);
+    drop(db);
+    std::fs::remove_dir_all("get_or_insert_with_integration_test_db").unwrap();
+}
+
+#[test]
+fn test_integer_key_ordering() {
+    let config = Config::new()
+        .path("integer_key_integration_test_db");
+
+    // 确保测试目录干净
+    if std::path::Path::new("integer_key_integration_test_db").exists() {
+        std::fs::remove_dir_all("integer_key_integration_test_db").unwrap();
+    }
+
+    let db = config.open::<1024>().unwrap();
+    let timestamps = db.open_tree("timestamps").unwrap();
+    let offsets = db.open_tree("offsets").unwrap();
+
+    // 乱序插入无符号整数键，包含跨越字节边界的值
+    let unsigned = [1_000_000u64, 3, u64::MAX, 256, 0, 255, 1 << 40, 42];
+    for ts in unsigned {
+        timestamps.insert(BigEndianU64(ts).to_bytes(), b"event").unwrap();
+    }
+
+    // 乱序插入有符号整数键
+    let signed = [7i64, -1, i64::MIN, 0, 300, -300, i64::MAX, 1];
+    for offset in signed {
+        offsets.insert(BigEndianI64(offset).to_bytes(), b"offset").unwrap();
+    }
+
+    // iter() 按数值顺序返回
+    let mut expected_unsigned = unsigned.to_vec();
+    expected_unsigned.sort_unstable();
+    let actual_unsigned: Vec<u64> = timestamps
+        .iter()
+        .keys()
+        .map(|k| BigEndianU64::try_from(k.unwrap()).unwrap().0)
+        .collect();
+    assert_eq!(actual_unsigned, expected_unsigned);
+
+    let mut expected_signed = signed.to_vec();
+    expected_signed.sort_unstable();
+    let actual_signed: Vec<i64> = offsets
+        .iter()
+        .keys()
+        .map(|k| BigEndianI64::try_from(k.unwrap()).unwrap().0)
+        .collect();
+    assert_eq!(actual_signed, expected_signed);
+
+    // 范围扫描同样按数值边界工作
+    let in_range: Vec<i64> = offsets
+        .range(BigEndianI64(-300).to_bytes()..=BigEndianI64(7).to_bytes())
+        .keys()
+        .map(|k| BigEndianI64::try_from(k.unwrap()).unwrap().0)
+        .collect();
+    assert_eq!(in_range, vec![-300, -1, 0, 1, 7]);
+
+    // 清理
+    drop(timestamps);
+    drop(offsets);
+    drop(db);
+    std::fs::remove_dir_all("integer_key_integration_test_db").unwrap();
+}
@@ -0,0 +1,116 @@
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use melange_db::*;
+
+const FANOUT: usize = 16;
+
+fn assert_sorted(leaves: &[LeafInfo]) {
+    assert!(leaves[0].low_key.is_empty());
+    for pair in leaves.windows(2) {
+        assert!(pair[0].low_key < pair[1].low_key, "{:?} {:?}", pair[0].low_key, pair[1].low_key);
+    }
+}
+
+#[test]
+fn test_leaf_layout_covers_every_entry() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let tree = db.open_tree("layout").unwrap();
+    for i in 0..2_000_u32 {
+        tree.insert(i.to_be_bytes(), vec![i as u8; 20]).unwrap();
+    }
+    for i in (0..2_000_u32).step_by(3) {
+        tree.remove(i.to_be_bytes()).unwrap();
+    }
+
+    // 从未flush的叶子节点没有写入磁盘的副本
+    let leaves: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+    assert_sorted(&leaves);
+    assert_eq!(leaves.iter().map(|leaf| leaf.entry_count).sum::<usize>(), tree.len().unwrap());
+    assert!(leaves.iter().all(|leaf| leaf.in_cache && leaf.serialized_bytes.is_none() && leaf.slab_file.is_none()));
+
+    db.flush().unwrap();
+    let leaves: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+    assert_sorted(&leaves);
+    assert_eq!(leaves.iter().map(|leaf| leaf.entry_count).sum::<usize>(), tree.len().unwrap());
+    for leaf in &leaves {
+        assert!(leaf.entry_count <= FANOUT);
+        assert!(leaf.serialized_bytes.unwrap() > 0);
+        assert!(leaf.slab_file.is_some());
+        assert!(leaf.uncompressed_bytes >= leaf.entry_count * (4 + 20));
+    }
+
+    // 与 `Tree::leaf_layout` 相同，每个叶子节点是不同的对象
+    assert_eq!(tree.leaf_layout().unwrap().collect::<Vec<_>>(), leaves);
+    let mut object_ids: Vec<u64> = leaves.iter().map(|leaf| leaf.object_id).collect();
+    object_ids.dedup();
+    assert_eq!(object_ids.len(), leaves.len());
+}
+
+#[test]
+fn test_leaf_layout_reads_evicted_leaves_from_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<FANOUT> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+        let tree = db.open_tree("layout").unwrap();
+        for i in 0..1_000_u32 {
+            tree.insert(i.to_be_bytes(), vec![i as u8; 50]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    // 重新打开后叶子节点都不在缓存中
+    let db: Db<FANOUT> = Config::new().path(dir.path()).flush_every_ms(None).open().unwrap();
+    let leaves: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+    assert_sorted(&leaves);
+    assert!(leaves.iter().any(|leaf| !leaf.in_cache));
+    assert_eq!(leaves.iter().map(|leaf| leaf.entry_count).sum::<usize>(), 1_000);
+    assert_eq!(leaves.iter().map(|leaf| leaf.uncompressed_bytes).sum::<usize>(), 1_000 * (4 + 50));
+
+    // 读取布局不会把叶子节点加入缓存
+    let again: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+    assert_eq!(again, leaves);
+}
+
+#[test]
+fn test_leaf_layout_with_concurrent_writes() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().flush_every_ms(Some(5)).open().unwrap();
+    let tree = db.open_tree("layout").unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writer = {
+        let tree = tree.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            for i in 0..5_000_u32 {
+                // 插入和删除交替，叶子节点被分裂和合并
+                tree.insert((i % 1_500).to_be_bytes(), i.to_be_bytes().to_vec()).unwrap();
+                if i % 4 == 0 {
+                    tree.remove(((i * 7) % 1_500).to_be_bytes()).unwrap();
+                }
+            }
+            done.store(true, Ordering::Release);
+        })
+    };
+
+    let mut scans = 0;
+    while !done.load(Ordering::Acquire) || scans == 0 {
+        let leaves: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+        assert_sorted(&leaves);
+        scans += 1;
+    }
+    writer.join().unwrap();
+
+    let leaves: Vec<LeafInfo> = db.leaf_layout("layout").unwrap().collect();
+    assert_sorted(&leaves);
+    assert_eq!(leaves.iter().map(|leaf| leaf.entry_count).sum::<usize>(), tree.len().unwrap());
+}
+
+#[test]
+fn test_leaf_layout_of_missing_tree() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    let err = db.leaf_layout("missing").map(|_| ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
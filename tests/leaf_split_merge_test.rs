@@ -0,0 +1,185 @@
+use melange_db::*;
+
+const FANOUT: usize = 16;
+
+/// 在同一键区间内反复批量插入和删除，返回预热之后各轮的分裂和合并次数
+fn run_delete_reinsert_cycles(tree: &Tree<FANOUT>) -> TreeStats {
+    // 区间两侧的背景数据，保证被清空的叶子节点总有右侧兄弟节点
+    for i in 0..64 {
+        tree.insert(format!("a_{:03}", i), b"background".as_slice()).unwrap();
+        tree.insert(format!("z_{:03}", i), b"background".as_slice()).unwrap();
+    }
+
+    let cycle = |tree: &Tree<FANOUT>| {
+        for i in 0..64 {
+            tree.insert(format!("m_{:03}", i), b"value".as_slice()).unwrap();
+        }
+        for i in 0..64 {
+            tree.remove(format!("m_{:03}", i)).unwrap();
+        }
+    };
+
+    // 预热一轮，让叶子节点结构稳定下来
+    cycle(tree);
+    let before = tree.tree_stats();
+
+    for _ in 0..5 {
+        cycle(tree);
+    }
+
+    let after = tree.tree_stats();
+
+    assert_eq!(tree.len().unwrap(), 128);
+
+    TreeStats {
+        leaf_splits: after.leaf_splits - before.leaf_splits,
+        leaf_merges: after.leaf_merges - before.leaf_merges,
+        ..TreeStats::default()
+    }
+}
+
+#[test]
+fn test_default_thresholds_ping_pong() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db = config.open::<FANOUT>().unwrap();
+    let tree = db.open_tree("ping_pong").unwrap();
+
+    let stats = run_delete_reinsert_cycles(&tree);
+
+    // 默认设置下，被清空的叶子节点会立即合并，重新插入时又会分裂
+    assert!(stats.leaf_splits > 0, "{:?}", stats);
+    assert!(stats.leaf_merges > 0, "{:?}", stats);
+}
+
+#[test]
+fn test_hysteresis_eliminates_ping_pong() {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .leaf_split_threshold(FANOUT)
+        .leaf_merge_threshold(FANOUT / 4);
+    let db = config.open::<FANOUT>().unwrap();
+    let tree = db.open_tree("hysteresis").unwrap();
+
+    let stats = run_delete_reinsert_cycles(&tree);
+
+    assert_eq!(stats, TreeStats { leaf_splits: 0, leaf_merges: 0, ..TreeStats::default() });
+}
+
+#[test]
+fn test_tree_options_override() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db = config.open::<FANOUT>().unwrap();
+
+    let tree = db
+        .open_tree_with_options(
+            "override",
+            TreeOptions::new().leaf_merge_threshold(FANOUT / 4),
+        )
+        .unwrap();
+    let stats = run_delete_reinsert_cycles(&tree);
+    assert_eq!(stats, TreeStats { leaf_splits: 0, leaf_merges: 0, ..TreeStats::default() });
+
+    // 其他树仍然使用默认设置
+    let other = db.open_tree("default_settings").unwrap();
+    let stats = run_delete_reinsert_cycles(&other);
+    assert!(stats.leaf_merges > 0, "{:?}", stats);
+}
+
+#[test]
+fn test_small_leaves_merge_with_threshold() {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .leaf_merge_threshold(FANOUT / 4);
+    let mut db = config.open::<FANOUT>().unwrap();
+
+    for i in 0..256u32 {
+        db.insert(i.to_be_bytes(), b"value".as_slice()).unwrap();
+    }
+    let splits = db.tree_stats().leaf_splits;
+    assert!(splits > 0);
+
+    for i in 0..256u32 {
+        if i % 32 != 0 {
+            db.remove(i.to_be_bytes()).unwrap();
+        }
+    }
+
+    // 叶子节点在未完全清空时就已合并
+    assert!(db.tree_stats().leaf_merges > 0);
+
+    let remaining: Vec<InlineArray> =
+        db.iter().keys().map(|k| k.unwrap()).collect();
+    let expected: Vec<InlineArray> = (0..256u32)
+        .filter(|i| i % 32 == 0)
+        .map(|i| InlineArray::from(&i.to_be_bytes()[..]))
+        .collect();
+    assert_eq!(remaining, expected);
+
+    // 合并后的叶子节点能够正确持久化
+    db.flush().unwrap();
+    drop(db);
+    db = config.open::<FANOUT>().unwrap();
+    let recovered: Vec<InlineArray> =
+        db.iter().keys().map(|k| k.unwrap()).collect();
+    assert_eq!(recovered, expected);
+}
+
+#[test]
+fn test_invalid_thresholds_rejected() {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .leaf_split_threshold(FANOUT)
+        .leaf_merge_threshold(FANOUT / 2);
+    let err = config.open::<FANOUT>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .leaf_split_threshold(FANOUT * 2);
+    assert!(config.open::<FANOUT>().is_err());
+
+    let db = Config::tmp().unwrap().flush_every_ms(None).open::<FANOUT>().unwrap();
+    let err = db
+        .open_tree_with_options(
+            "invalid",
+            TreeOptions::new().leaf_split_threshold(8).leaf_merge_threshold(4),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(!db.contains_tree("invalid").unwrap());
+}
+
+#[test]
+fn test_split_bias_last_for_appends() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db = config.open::<FANOUT>().unwrap();
+
+    let middle = db
+        .open_tree_with_options("middle", TreeOptions::new().split_bias(SplitBias::Middle))
+        .unwrap();
+    let last = db
+        .open_tree_with_options("last", TreeOptions::new().split_bias(SplitBias::Last))
+        .unwrap();
+
+    for i in 0..1024u32 {
+        middle.insert(i.to_be_bytes(), b"value".as_slice()).unwrap();
+        last.insert(i.to_be_bytes(), b"value".as_slice()).unwrap();
+    }
+
+    // 在末尾附近分裂时，左侧叶子节点几乎是满的，所需的分裂次数更少
+    assert!(
+        last.tree_stats().leaf_splits * 3 < middle.tree_stats().leaf_splits * 2,
+        "last: {:?} middle: {:?}",
+        last.tree_stats(),
+        middle.tree_stats()
+    );
+    assert_eq!(last.len().unwrap(), 1024);
+    assert_eq!(
+        last.iter().keys().map(|k| k.unwrap()).collect::<Vec<_>>(),
+        middle.iter().keys().map(|k| k.unwrap()).collect::<Vec<_>>()
+    );
+}
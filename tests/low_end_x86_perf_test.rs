@@ -22,6 +22,7 @@ fn low_end_x86_performance_test() {
         max_interval_ms: 1500,     // 降低最大间隔
         write_rate_threshold: 4000, // 提高到4K ops/sec
         accumulated_bytes_threshold: 2 * 1024 * 1024, // 提高到2MB，减少flush次数
+        target_write_amplification: None,
     };
 
     // 清理旧的测试数据库
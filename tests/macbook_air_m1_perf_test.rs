@@ -22,6 +22,7 @@ fn macbook_air_m1_performance_test() {
         max_interval_ms: 800,      // 较低的最大间隔，保证数据安全
         write_rate_threshold: 15000, // 提高到15K ops/sec，M1可以处理更高负载
         accumulated_bytes_threshold: 8 * 1024 * 1024, // 8MB，平衡性能和持久化
+        target_write_amplification: None,
     };
 
     // 清理旧的测试数据库
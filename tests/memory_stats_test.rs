@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::Arc;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const VALUE_SIZE: usize = 1024;
+const KEYS_PER_ROUND: u32 = 4_000;
+
+fn config() -> Config {
+    // 缓存足够大，写入的叶子节点都留在缓存中
+    common::tmp_config().cache_capacity_bytes(256 * 1024 * 1024)
+}
+
+fn write_round(tree: &Tree, round: u32) {
+    for i in round * KEYS_PER_ROUND..(round + 1) * KEYS_PER_ROUND {
+        tree.insert(i.to_be_bytes(), vec![(i % 251) as u8; VALUE_SIZE]).unwrap();
+    }
+}
+
+#[test]
+fn test_object_cache_bytes_grow_with_written_volume() {
+    let db: Db<1024> = config().open().unwrap();
+    let tree = db.open_tree("data").unwrap();
+    let round_bytes = KEYS_PER_ROUND as usize * VALUE_SIZE;
+
+    let mut previous = db.memory_stats();
+    for round in 0..3 {
+        write_round(&tree, round);
+        let stats = db.memory_stats();
+        let growth = stats.object_cache_bytes - previous.object_cache_bytes;
+        // 增长与写入的数据量大致成正比
+        assert!(
+            growth >= round_bytes && growth < round_bytes * 2,
+            "第 {} 轮写入 {} 字节，缓存增长 {} 字节",
+            round,
+            round_bytes,
+            growth
+        );
+        assert!(stats.index_bytes > previous.index_bytes);
+        assert_eq!(stats.object_cache_bytes, db.cache_resident_bytes());
+        previous = stats;
+    }
+
+    // 索引只保存叶子节点的低键，远小于缓存的数据
+    assert!(previous.index_bytes * 10 < previous.object_cache_bytes, "{:?}", previous);
+    // 布隆过滤器的大小由配置的容量决定，不随写入增长
+    assert_eq!(previous.bloom_filter_bytes, db.memory_stats().bloom_filter_bytes);
+    assert!(previous.bloom_filter_bytes > 0);
+    assert_eq!(previous.counter_bytes, 0);
+    assert_eq!(
+        previous.total_bytes(),
+        previous.object_cache_bytes + previous.index_bytes + previous.bloom_filter_bytes
+    );
+}
+
+#[test]
+fn test_bloom_filter_bytes_follow_capacity() {
+    let small: Db<1024> = config().bloom_filter_capacity(10_000).open().unwrap();
+    let large: Db<1024> = config().bloom_filter_capacity(1_000_000).open().unwrap();
+    let small = small.memory_stats().bloom_filter_bytes;
+    let large = large.memory_stats().bloom_filter_bytes;
+    assert!(large > small * 50, "{} {}", small, large);
+}
+
+#[test]
+fn test_counter_bytes_grow_with_counters() {
+    let db: Arc<Db<1024>> = Arc::new(config().open().unwrap());
+    let manager = HybridOperationsManager::new(db.clone());
+    manager.increment("warmup".to_string(), 1).unwrap();
+
+    let before = db.memory_stats().counter_bytes;
+    assert!(before > 0);
+    for i in 0..10_000 {
+        manager.increment(format!("counter_{}", i), 1).unwrap();
+    }
+    let after = db.memory_stats().counter_bytes;
+    // 每个计数器至少占用名称和值的空间
+    assert!(after - before >= 10_000 * "counter_0000".len(), "{} {}", before, after);
+    assert!(after < before + 10_000 * 1024, "{} {}", before, after);
+}
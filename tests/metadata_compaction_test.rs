@@ -0,0 +1,113 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use melange_db::*;
+
+fn config(path: &std::path::Path) -> Config {
+    common::config(path).leaf_split_threshold(16).leaf_merge_threshold(4)
+}
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:06}", i).into_bytes()
+}
+
+/// 反复插入和删除并刷新，使叶子节点频繁分裂和合并，每次刷新都追加元数据
+fn churn(db: &Db<1024>, rounds: u32) {
+    for round in 0..rounds {
+        for i in 0..400 {
+            db.insert(key(i), format!("value_{}_{}", round, i).as_bytes()).unwrap();
+        }
+        db.flush().unwrap();
+        for i in (0..400).filter(|i| i % 4 != 0) {
+            db.remove(key(i)).unwrap();
+        }
+        db.flush().unwrap();
+    }
+}
+
+fn contents(db: &Db<1024>) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    db.iter()
+        .map(|item| {
+            let (key, value) = item.unwrap();
+            (key.to_vec(), value.to_vec())
+        })
+        .collect()
+}
+
+#[test]
+fn test_compact_metadata_shrinks_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected = {
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        churn(&db, 20);
+        // 最后一次写入确保当前的元数据日志不为空
+        db.insert(b"last", b"value").unwrap();
+        db.flush().unwrap();
+
+        let before = db.metadata_stats().unwrap();
+        println!("压缩前: {:?}", before);
+        assert!(before.dead_entries > 0);
+        assert!(before.live_entries > 0);
+
+        db.compact_metadata().unwrap();
+
+        let after = db.metadata_stats().unwrap();
+        println!("压缩后: {:?}", after);
+        assert_eq!(after.dead_entries, 0);
+        assert_eq!(after.live_entries, before.live_entries);
+        assert!(after.file_bytes < before.file_bytes);
+        assert!(after.last_compaction > before.last_compaction);
+
+        // 压缩之后可以继续写入
+        db.insert(b"after", b"compaction").unwrap();
+        db.flush().unwrap();
+        contents(&db)
+    };
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(contents(&db), expected);
+    db.check().unwrap();
+}
+
+#[test]
+fn test_auto_compaction_bounds_dead_entries() {
+    // 不自动压缩时失效条目持续累积
+    let plain_dir = tempfile::tempdir().unwrap();
+    let plain: Db<1024> = config(plain_dir.path()).open().unwrap();
+    churn(&plain, 20);
+    let plain_stats = plain.metadata_stats().unwrap();
+    println!("不自动压缩: {:?}", plain_stats);
+
+    let dir = tempfile::tempdir().unwrap();
+    let expected = {
+        let db: Db<1024> =
+            config(dir.path()).metadata_auto_compact_ratio(Some(0.5)).open().unwrap();
+        let opened = db.metadata_stats().unwrap().last_compaction;
+
+        churn(&db, 20);
+
+        let stats = db.metadata_stats().unwrap();
+        println!("自动压缩: {:?}", stats);
+        assert!(stats.last_compaction > opened);
+        assert!(stats.dead_entries * 4 < plain_stats.dead_entries);
+        assert!(stats.file_bytes < plain_stats.file_bytes);
+        contents(&db)
+    };
+
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    assert_eq!(contents(&db), expected);
+    assert_eq!(contents(&db), contents(&plain));
+}
+
+#[test]
+fn test_invalid_auto_compact_ratio_is_rejected() {
+    for ratio in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let err = Config::tmp()
+            .unwrap()
+            .metadata_auto_compact_ratio(Some(ratio))
+            .open::<1024>()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,145 @@
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+use melange_db::hybrid_operations_manager::{CounterTypeMismatch, HybridOperationsManager};
+use melange_db::*;
+
+fn counter_key(name: &str) -> Vec<u8> {
+    [&b"__atomic_counter__:"[..], name.as_bytes()].concat()
+}
+
+fn persisted_counter(db: &Db<1024>, name: &str) -> Option<u64> {
+    let value = db.get(counter_key(name)).unwrap()?;
+    Some(u64::from_le_bytes(value[1..].try_into().unwrap()))
+}
+
+fn ops(entries: &[(&str, u64, u64)]) -> Vec<(String, u64, u64)> {
+    entries.iter().map(|(name, expected, new_value)| (name.to_string(), *expected, *new_value)).collect()
+}
+
+#[test]
+fn test_partial_mismatch_leaves_all_counters_unchanged() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let mut manager = HybridOperationsManager::new_with_db_worker(db.clone());
+    manager.reset("a".to_string(), 1).unwrap();
+    manager.reset("b".to_string(), 2).unwrap();
+    manager.reset("c".to_string(), 3).unwrap();
+
+    // 只有最后一个期望值不匹配，前面的计数器也不被修改
+    let swapped = manager.multi_compare_and_swap(ops(&[("a", 1, 10), ("b", 2, 20), ("c", 4, 30)])).unwrap();
+    assert!(!swapped);
+    // 不存在的计数器按0比较，失败时不会被创建
+    let swapped = manager.multi_compare_and_swap(ops(&[("a", 1, 10), ("missing", 5, 50)])).unwrap();
+    assert!(!swapped);
+
+    assert_eq!(manager.get("a".to_string()).unwrap(), Some(1));
+    assert_eq!(manager.get("b".to_string()).unwrap(), Some(2));
+    assert_eq!(manager.get("c".to_string()).unwrap(), Some(3));
+    assert_eq!(manager.get("missing".to_string()).unwrap(), None);
+
+    manager.disable_database_worker_mode();
+    assert_eq!(persisted_counter(&db, "a"), Some(1));
+    assert_eq!(persisted_counter(&db, "b"), Some(2));
+    assert_eq!(persisted_counter(&db, "c"), Some(3));
+    assert_eq!(persisted_counter(&db, "missing"), None);
+}
+
+#[test]
+fn test_full_match_updates_all_counters() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let mut manager = HybridOperationsManager::new_with_db_worker(db.clone());
+    manager.reset("a".to_string(), 1).unwrap();
+    manager.reset("b".to_string(), 2).unwrap();
+
+    let swapped = manager.multi_compare_and_swap(ops(&[("a", 1, 10), ("b", 2, 20), ("new", 0, 30)])).unwrap();
+    assert!(swapped);
+    assert_eq!(manager.get("a".to_string()).unwrap(), Some(10));
+    assert_eq!(manager.get("b".to_string()).unwrap(), Some(20));
+    assert_eq!(manager.get("new".to_string()).unwrap(), Some(30));
+
+    // 旧的期望值不再匹配
+    assert!(!manager.multi_compare_and_swap(ops(&[("a", 1, 100), ("b", 2, 200)])).unwrap());
+    assert!(manager.multi_compare_and_swap(vec![]).unwrap());
+
+    manager.disable_database_worker_mode();
+    assert_eq!(persisted_counter(&db, "a"), Some(10));
+    assert_eq!(persisted_counter(&db, "b"), Some(20));
+    assert_eq!(persisted_counter(&db, "new"), Some(30));
+}
+
+#[test]
+fn test_concurrent_transfers_preserve_the_total() {
+    const THREADS: u64 = 8;
+    const TRANSFERS: u64 = 200;
+    const INITIAL: u64 = 1_000_000;
+
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = Arc::new(HybridOperationsManager::new(db));
+    for name in ["x", "y", "z"] {
+        manager.reset(name.to_string(), INITIAL).unwrap();
+    }
+
+    // 每次转移从一个计数器减去1，加到另一个计数器上，两个计数器必须一起修改
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let manager = manager.clone();
+            thread::spawn(move || {
+                let names = ["x", "y", "z"];
+                for i in 0..TRANSFERS {
+                    let from = names[((t + i) % 3) as usize].to_string();
+                    let to = names[((t + i + 1) % 3) as usize].to_string();
+                    loop {
+                        let from_value = manager.get(from.clone()).unwrap().unwrap();
+                        let to_value = manager.get(to.clone()).unwrap().unwrap();
+                        let ops = vec![(from.clone(), from_value, from_value - 1), (to.clone(), to_value, to_value + 1)];
+                        if manager.multi_compare_and_swap(ops).unwrap() {
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let total: u64 = ["x", "y", "z"].iter().map(|name| manager.get(name.to_string()).unwrap().unwrap()).sum();
+    assert_eq!(total, 3 * INITIAL);
+}
+
+#[test]
+fn test_evicted_counters_are_loaded_before_comparing() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Config::new().path(dir.path()).flush_every_ms(None).open().unwrap());
+    let manager = HybridOperationsManager::new(db.clone());
+    manager.set_max_resident_counters(Some(2));
+
+    for i in 0..10_u64 {
+        manager.reset(format!("counter_{}", i), i).unwrap();
+    }
+    assert!(manager.resident_counter_count() <= 2);
+
+    // 比较的计数器多于常驻上限时，它们互相之间不会被移出
+    let all: Vec<(String, u64, u64)> = (0..10_u64).map(|i| (format!("counter_{}", i), i, i + 100)).collect();
+    assert!(manager.multi_compare_and_swap(all).unwrap());
+    for i in 0..10_u64 {
+        assert_eq!(manager.get(format!("counter_{}", i)).unwrap(), Some(i + 100));
+    }
+}
+
+#[test]
+fn test_invalid_operations_are_rejected() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = HybridOperationsManager::new(db);
+    manager.reset("a".to_string(), 1).unwrap();
+    manager.add_f64("float".to_string(), 1.5).unwrap();
+
+    let err = manager.multi_compare_and_swap(ops(&[("a", 1, 2), ("a", 1, 3)])).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let err = manager.multi_compare_and_swap(ops(&[("a", 1, 2), ("float", 0, 1)])).unwrap_err();
+    assert!(CounterTypeMismatch::from_io_error(&err).is_some(), "{:?}", err);
+    assert_eq!(manager.get("a".to_string()).unwrap(), Some(1));
+}
@@ -0,0 +1,250 @@
+use std::io;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use melange_db::op_journal::{
+    self, JournalValue, OpJournalConfig, OpJournalEntry, OpJournalReader, OpKind,
+};
+use melange_db::*;
+
+type Summary<'a> = (OpKind, Option<&'a [u8]>, &'a [u8], &'a JournalValue);
+
+fn dump(db: &Db<1024>) -> Vec<OpJournalEntry> {
+    let mut buf = vec![];
+    let count = db.dump_op_journal(&mut buf).unwrap();
+    let entries = OpJournalReader::new(&buf[..])
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries.len() as u64, count);
+    entries
+}
+
+#[test]
+fn test_op_journal_records_mutations() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes: 1 << 20, redact_values: false });
+    let db: Db<1024> = config.open().unwrap();
+    let tree = db.open_tree("users").unwrap();
+
+    db.insert(b"a", b"1".as_slice()).unwrap();
+    tree.insert(b"b", b"2".as_slice()).unwrap();
+    tree.compare_and_swap(b"b", Some(b"2"), Some(b"3".as_slice())).unwrap().unwrap();
+    // 失败的compare_and_swap没有修改数据，不被记录
+    tree.compare_and_swap(b"b", Some(b"2"), Some(b"4".as_slice())).unwrap().unwrap_err();
+    let mut batch = Batch::default();
+    batch.insert(b"c", b"5".as_slice());
+    batch.remove(b"a");
+    db.apply_batch(batch).unwrap();
+    tree.remove(b"b").unwrap();
+
+    // 读取操作不被记录
+    db.get(b"c").unwrap();
+
+    let entries = dump(&db);
+    let summary: Vec<Summary<'_>> = entries
+        .iter()
+        .map(|e| (e.op, e.tree.as_deref(), &e.key[..], &e.value))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (OpKind::Insert, None, &b"a"[..], &JournalValue::Full(b"1".to_vec())),
+            (OpKind::Insert, Some(&b"users"[..]), &b"b"[..], &JournalValue::Full(b"2".to_vec())),
+            (OpKind::CompareAndSwap, Some(&b"users"[..]), &b"b"[..], &JournalValue::Full(b"3".to_vec())),
+            (OpKind::BatchRemove, None, &b"a"[..], &JournalValue::Absent),
+            (OpKind::BatchInsert, None, &b"c"[..], &JournalValue::Full(b"5".to_vec())),
+            (OpKind::Remove, Some(&b"users"[..]), &b"b"[..], &JournalValue::Absent),
+        ]
+    );
+
+    let thread_id = entries[0].thread_id;
+    assert!(entries.iter().all(|e| e.thread_id == thread_id));
+    assert!(entries.windows(2).all(|w| w[0].timestamp_us <= w[1].timestamp_us));
+
+    // 另一个线程的操作有不同的线程编号
+    let db2 = db.clone();
+    std::thread::spawn(move || db2.insert(b"d", b"6".as_slice()).unwrap())
+        .join()
+        .unwrap();
+    let last = dump(&db).pop().unwrap();
+    assert_eq!(last.key, b"d");
+    assert_ne!(last.thread_id, thread_id);
+}
+
+#[test]
+fn test_op_journal_redacts_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes: 1 << 20, redact_values: true });
+    let db: Db<1024> = config.open().unwrap();
+
+    db.insert(b"secret", b"hunter2".as_slice()).unwrap();
+
+    let mut buf = vec![];
+    db.dump_op_journal(&mut buf).unwrap();
+    assert!(!buf.windows(7).any(|w| w == b"hunter2"));
+
+    let entries = dump(&db);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0].value,
+        JournalValue::Hashed { hash: op_journal::hash_value(b"hunter2"), len: 7 }
+    );
+    drop(db);
+
+    // 磁盘上的分段中也没有值
+    for name in ["segment.0", "segment.1"] {
+        let segment = std::fs::read(dir.path().join("op_journal").join(name)).unwrap();
+        assert!(!segment.windows(7).any(|w| w == b"hunter2"));
+    }
+}
+
+#[test]
+fn test_op_journal_wraps_around() {
+    let dir = tempfile::tempdir().unwrap();
+    let max_bytes = 16 * 1024;
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes, redact_values: false });
+    let db: Db<1024> = config.open().unwrap();
+
+    let count = 10_000_u64;
+    for i in 0..count {
+        db.insert(i.to_be_bytes(), i.to_le_bytes().as_slice()).unwrap();
+    }
+
+    // 只保留最近的记录，并且它们是连续的
+    let entries = dump(&db);
+    assert!(!entries.is_empty());
+    assert!((entries.len() as u64) < count);
+    let keys: Vec<u64> = entries
+        .iter()
+        .map(|e| u64::from_be_bytes(e.key[..].try_into().unwrap()))
+        .collect();
+    assert_eq!(*keys.last().unwrap(), count - 1);
+    assert!(keys.windows(2).all(|w| w[0] + 1 == w[1]));
+    drop(db);
+
+    let on_disk: u64 = ["segment.0", "segment.1"]
+        .iter()
+        .map(|name| std::fs::metadata(dir.path().join("op_journal").join(name)).unwrap().len())
+        .sum();
+    assert!(on_disk <= max_bytes, "{}", on_disk);
+
+    // 离线读取与转储的内容相同，重新打开后继续追加
+    let offline = op_journal::read_dir(dir.path()).unwrap();
+    assert_eq!(offline.last().unwrap().key, (count - 1).to_be_bytes());
+
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes, redact_values: false });
+    let db: Db<1024> = config.open().unwrap();
+    db.insert(b"after reopen", b"x".as_slice()).unwrap();
+    let entries = dump(&db);
+    assert_eq!(entries.last().unwrap().key, b"after reopen");
+    assert_eq!(
+        entries[entries.len() - 2].key,
+        (count - 1).to_be_bytes(),
+    );
+}
+
+#[test]
+fn test_op_journal_ignores_torn_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes: 1 << 20, redact_values: false });
+    let db: Db<1024> = config.open().unwrap();
+    for i in 0..10_u64 {
+        db.insert(i.to_be_bytes(), vec![0; 16]).unwrap();
+    }
+    drop(db);
+
+    // 模拟崩溃时写了一半的记录
+    let segment = dir.path().join("op_journal").join("segment.0");
+    let len = std::fs::metadata(&segment).unwrap().len();
+    let file = std::fs::OpenOptions::new().write(true).open(&segment).unwrap();
+    file.set_len(len - 5).unwrap();
+    drop(file);
+
+    assert_eq!(op_journal::read_dir(dir.path()).unwrap().len(), 9);
+
+    let config = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes: 1 << 20, redact_values: false });
+    let db: Db<1024> = config.open().unwrap();
+    db.insert(b"next", vec![1]).unwrap();
+    let entries = dump(&db);
+    assert_eq!(entries.len(), 10);
+    assert_eq!(entries.last().unwrap().key, b"next");
+
+    // 转储中损坏的记录被报告为错误
+    let mut buf = vec![];
+    db.dump_op_journal(&mut buf).unwrap();
+    let last = buf.len() - 1;
+    buf[last] ^= 1;
+    let results: Vec<_> = OpJournalReader::new(&buf[..]).unwrap().collect();
+    assert_eq!(results.len(), 10);
+    assert_eq!(results[9].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_op_journal_config_validation() {
+    let dir = tempfile::tempdir().unwrap();
+    let res = Config::new()
+        .path(dir.path())
+        .op_journal(OpJournalConfig { max_bytes: 100, redact_values: false })
+        .open::<1024>();
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    assert_eq!(
+        db.dump_op_journal(io::sink()).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+}
+
+fn timed_inserts(db: &Db<1024>, keys: Range<u64>) -> Duration {
+    let start = Instant::now();
+    for i in keys {
+        db.insert(i.to_be_bytes(), vec![0xAB; 64]).unwrap();
+    }
+    start.elapsed()
+}
+
+#[test]
+#[cfg_attr(debug_assertions, ignore = "开销预算针对发布构建，使用 cargo test --release 运行")]
+fn test_op_journal_overhead() {
+    let batch = 1_000;
+    let batches = 200;
+
+    let dir = tempfile::tempdir().unwrap();
+    let plain: Db<1024> =
+        Config::new().path(dir.path().join("plain")).flush_every_ms(None).open().unwrap();
+    let journaled: Db<1024> = Config::new()
+        .path(dir.path().join("journaled"))
+        .flush_every_ms(None)
+        .op_journal(OpJournalConfig { max_bytes: 64 << 20, redact_values: true })
+        .open()
+        .unwrap();
+
+    // 两个数据库交替插入同样的一批键，取每批耗时之比的中位数，
+    // 机器负载的变化和偶尔的停顿对两者的影响相同，不会影响结果
+    let mut ratios = vec![];
+    for b in 0..batches {
+        let keys = b * batch..(b + 1) * batch;
+        let plain_time = timed_inserts(&plain, keys.clone());
+        let journaled_time = timed_inserts(&journaled, keys);
+        ratios.push(journaled_time.as_secs_f64() / plain_time.as_secs_f64());
+    }
+    assert_eq!(journaled.op_journal_dropped(), 0);
+
+    ratios.sort_by(f64::total_cmp);
+    let overhead = ratios[ratios.len() / 2] - 1.0;
+    println!("操作日志开销: {:.1}%", overhead * 100.0);
+    assert!(overhead < 0.05, "{:.1}%", overhead * 100.0);
+}
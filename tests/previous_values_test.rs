@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+/// 操作序列中每一步的结果
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Single(Option<Vec<u8>>),
+    Batch(Vec<(Vec<u8>, Option<Vec<u8>>)>),
+}
+
+fn single(previous: Option<InlineArray>) -> Outcome {
+    Outcome::Single(previous.map(|v| v.to_vec()))
+}
+
+fn batch(previous: Vec<(InlineArray, Option<InlineArray>)>) -> Outcome {
+    Outcome::Batch(
+        previous
+            .into_iter()
+            .map(|(k, v)| (k.to_vec(), v.map(|v| v.to_vec())))
+            .collect(),
+    )
+}
+
+/// 对不同的访问方式执行相同的操作序列
+trait Target {
+    fn insert(&self, key: &[u8], value: &[u8]) -> Option<InlineArray>;
+    fn remove(&self, key: &[u8]) -> Option<InlineArray>;
+    fn apply_batch(&self, batch: Batch);
+    fn apply_batch_returning(&self, batch: Batch) -> Vec<(InlineArray, Option<InlineArray>)>;
+    fn contents(&self) -> Contents;
+}
+
+impl Target for Db<1024> {
+    fn insert(&self, key: &[u8], value: &[u8]) -> Option<InlineArray> {
+        Tree::insert(self, key, value).unwrap()
+    }
+    fn remove(&self, key: &[u8]) -> Option<InlineArray> {
+        Tree::remove(self, key).unwrap()
+    }
+    fn apply_batch(&self, batch: Batch) {
+        Tree::apply_batch(self, batch).unwrap()
+    }
+    fn apply_batch_returning(&self, batch: Batch) -> Vec<(InlineArray, Option<InlineArray>)> {
+        Tree::apply_batch_returning(self, batch).unwrap()
+    }
+    fn contents(&self) -> Contents {
+        self.iter()
+            .map(|kv| {
+                let (k, v) = kv.unwrap();
+                (k.to_vec(), v.to_vec())
+            })
+            .collect()
+    }
+}
+
+impl Target for HybridOperationsManager {
+    fn insert(&self, key: &[u8], value: &[u8]) -> Option<InlineArray> {
+        HybridOperationsManager::insert(self, key, value).unwrap()
+    }
+    fn remove(&self, key: &[u8]) -> Option<InlineArray> {
+        HybridOperationsManager::remove(self, key).unwrap()
+    }
+    fn apply_batch(&self, batch: Batch) {
+        HybridOperationsManager::apply_batch(self, batch).unwrap()
+    }
+    fn apply_batch_returning(&self, batch: Batch) -> Vec<(InlineArray, Option<InlineArray>)> {
+        HybridOperationsManager::apply_batch_returning(self, batch).unwrap()
+    }
+    fn contents(&self) -> Contents {
+        self.scan_prefix(b"").unwrap()
+    }
+}
+
+type Contents = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn run_sequence(target: &dyn Target) -> (Vec<Outcome>, Contents) {
+    // 新建、覆盖、删除存在和不存在的键
+    let mut outcomes = vec![
+        single(target.insert(b"a", b"1")),
+        single(target.insert(b"b", b"2")),
+        single(target.insert(b"a", b"3")),
+        single(target.remove(b"b")),
+        single(target.remove(b"missing")),
+    ];
+
+    let mut plain = Batch::default();
+    plain.insert(b"c".as_slice(), b"4".as_slice());
+    plain.insert(b"d".as_slice(), b"5".as_slice());
+    target.apply_batch(plain);
+
+    // 混合新建、覆盖和删除的批量写入，乱序添加
+    let mut returning = Batch::default();
+    returning.insert(b"e".as_slice(), b"6".as_slice());
+    returning.remove(b"c".as_slice());
+    returning.insert(b"a".as_slice(), b"7".as_slice());
+    returning.remove(b"never".as_slice());
+    returning.insert(b"d".as_slice(), b"8".as_slice());
+    outcomes.push(batch(target.apply_batch_returning(returning)));
+
+    // 足够多的键使批量写入跨越多个叶子
+    let mut large = Batch::default();
+    for i in 0..3000_u32 {
+        large.insert(i.to_be_bytes().as_slice(), b"x".as_slice());
+    }
+    outcomes.push(batch(target.apply_batch_returning(large)));
+
+    let mut overwrite = Batch::default();
+    for i in (0..3000_u32).step_by(7) {
+        overwrite.insert(i.to_be_bytes().as_slice(), i.to_le_bytes().as_slice());
+    }
+    outcomes.push(batch(target.apply_batch_returning(overwrite)));
+
+    (outcomes, target.contents())
+}
+
+#[test]
+fn test_previous_values_consistent_across_access_paths() {
+    let tree_db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let expected = run_sequence(&tree_db);
+
+    let direct = HybridOperationsManager::new(Arc::new(Config::tmp().unwrap().open().unwrap()));
+    let worker = HybridOperationsManager::new_with_db_worker(Arc::new(
+        Config::tmp().unwrap().open().unwrap(),
+    ));
+
+    assert_eq!(run_sequence(&direct), expected);
+    assert_eq!(run_sequence(&worker), expected);
+
+    // 检查直接访问的结果本身
+    let (outcomes, contents) = expected;
+    assert_eq!(outcomes[0], Outcome::Single(None));
+    assert_eq!(outcomes[2], Outcome::Single(Some(b"1".to_vec())));
+    assert_eq!(outcomes[3], Outcome::Single(Some(b"2".to_vec())));
+    assert_eq!(outcomes[4], Outcome::Single(None));
+    assert_eq!(
+        outcomes[5],
+        Outcome::Batch(vec![
+            (b"a".to_vec(), Some(b"3".to_vec())),
+            (b"c".to_vec(), Some(b"4".to_vec())),
+            (b"d".to_vec(), Some(b"5".to_vec())),
+            (b"e".to_vec(), None),
+            (b"never".to_vec(), None),
+        ])
+    );
+
+    match &outcomes[6] {
+        Outcome::Batch(previous) => {
+            assert_eq!(previous.len(), 3000);
+            assert!(previous.iter().all(|(_, v)| v.is_none()));
+            assert!(previous.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+        other => panic!("{:?}", other),
+    }
+    match &outcomes[7] {
+        Outcome::Batch(previous) => {
+            assert_eq!(previous.len(), 3000 / 7 + 1);
+            assert!(previous.iter().all(|(_, v)| v.as_deref() == Some(b"x".as_slice())));
+        }
+        other => panic!("{:?}", other),
+    }
+
+    assert_eq!(contents.len(), 3000 + 3);
+}
+
+#[test]
+fn test_apply_batch_returning_guard_failure_applies_nothing() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let tree = db.open_tree("guarded").unwrap();
+    tree.insert(b"k", b"v".as_slice()).unwrap();
+
+    let mut batch = Batch::default();
+    batch.insert(b"k".as_slice(), b"new".as_slice());
+    batch.guard(b"k".as_slice(), Some(b"other"));
+
+    let err = tree.apply_batch_returning(batch).unwrap_err();
+    assert!(BatchGuardError::from_io_error(&err).is_some());
+    assert_eq!(tree.get(b"k").unwrap().as_deref(), Some(b"v".as_slice()));
+}
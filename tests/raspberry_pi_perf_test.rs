@@ -22,6 +22,7 @@ fn raspberry_pi_3b_plus_performance_test() {
         max_interval_ms: 2000,     // 增加最大间隔，减少写入频率
         write_rate_threshold: 2000, // 降低到2K ops/sec，适应SD卡性能
         accumulated_bytes_threshold: 1 * 1024 * 1024, // 降低到1MB，减少单次写入数据量
+        target_write_amplification: None,
     };
 
     // 清理旧的测试数据库
@@ -0,0 +1,201 @@
+mod common;
+
+#[cfg(feature = "for-internal-testing-only")]
+use std::ops::Bound;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+// 较小的叶子节点让扫描跨越大量叶子
+const FANOUT: usize = 16;
+const KEYS: u32 = 8_000;
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_be_bytes()
+}
+
+fn config(path: &Path) -> Config {
+    common::config(path).cache_capacity_bytes(256 * 1024 * 1024)
+}
+
+/// 写入数据后重新打开，所有叶子节点都不在缓存中
+fn open_cold(dir: &tempfile::TempDir) -> Db<FANOUT> {
+    {
+        let db: Db<FANOUT> = config(dir.path()).open().unwrap();
+        if db.is_empty().unwrap() {
+            for i in 0..KEYS {
+                db.insert(key(i), vec![(i % 251) as u8; 512]).unwrap();
+            }
+            db.flush().unwrap();
+        }
+    }
+    config(dir.path()).open().unwrap()
+}
+
+fn options(policy: CachePolicy, read_ahead_leaves: usize) -> IterOptions {
+    IterOptions { cache_policy: policy, read_ahead_leaves }
+}
+
+fn read_ahead_leaves(db: &Db<FANOUT>) -> u64 {
+    db.stats().cache.read_ahead_leaves
+}
+
+/// 等待后台的预读达到 `expected` 个叶子节点
+fn wait_for_read_ahead(db: &Db<FANOUT>, expected: u64) {
+    let start = Instant::now();
+    while read_ahead_leaves(db) < expected {
+        assert!(start.elapsed() < Duration::from_secs(30), "预读没有完成: {}", read_ahead_leaves(db));
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// 以 `CachePolicy::BypassCache` 读取 `i`，返回它所在的叶子节点是否在缓存中
+fn is_cached(db: &Db<FANOUT>, i: u32) -> bool {
+    let misses = db.stats().cache.cache_misses;
+    db.get_with(key(i), GetOptions { cache_policy: CachePolicy::BypassCache }).unwrap().unwrap();
+    db.stats().cache.cache_misses == misses
+}
+
+#[test]
+fn test_read_ahead_pages_in_following_leaves() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_cold(&dir);
+
+    let mut iter = db.iter_with(options(CachePolicy::Normal, 8));
+    assert_eq!(&*iter.next().unwrap().unwrap().0, &key(0));
+    // 进入第一个叶子节点时安排之后的8个叶子节点，迭代器不前进就不再预读
+    wait_for_read_ahead(&db, 8);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(read_ahead_leaves(&db), 8);
+
+    let misses = db.stats().cache.cache_misses;
+    let mut expected = 1;
+    for kv in iter {
+        assert_eq!(&*kv.unwrap().0, &key(expected));
+        expected += 1;
+    }
+    assert_eq!(expected, KEYS);
+    // 迭代器自己读取的叶子节点和预读的叶子节点合起来覆盖整个树
+    let read_by_iter = db.stats().cache.cache_misses - misses;
+    assert!(read_ahead_leaves(&db) > 8);
+    assert!(read_by_iter + read_ahead_leaves(&db) >= u64::from(KEYS) / FANOUT as u64);
+}
+
+#[test]
+#[cfg(feature = "for-internal-testing-only")]
+fn test_read_ahead_stops_at_range_end() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_cold(&dir);
+    // 较慢的读取使预读先于迭代器到达之后的叶子节点
+    db.set_read_latency_for_testing(Duration::from_millis(1));
+
+    for (end, len) in [(Bound::Excluded(key(100)), 100), (Bound::Included(key(100)), 101)] {
+        let iter = db.range_with::<[u8; 4], _>((Bound::Included(key(0)), end), options(CachePolicy::Normal, 64));
+        // 只消费范围之内的条目，迭代器自己不会读取范围之后的叶子节点
+        let keys: Vec<_> = iter.take(len).map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys.len(), len);
+        assert_eq!(&*keys[len - 1], &key(len as u32 - 1));
+    }
+
+    // 范围之内的叶子节点都已读入缓存，范围之后的叶子节点都没有被预读
+    assert!(read_ahead_leaves(&db) > 0);
+    assert!(is_cached(&db, 99));
+    for i in (120..KEYS).step_by(FANOUT) {
+        assert!(!is_cached(&db, i), "{}", i);
+    }
+}
+
+#[test]
+fn test_dropping_iterator_cancels_read_ahead() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open_cold(&dir);
+
+    let mut iter = db.iter_with(options(CachePolicy::Normal, 256));
+    iter.next().unwrap().unwrap();
+    drop(iter);
+
+    // 丢弃迭代器时等待进行中的读取，之后不再有叶子节点被预读
+    let after_drop = read_ahead_leaves(&db);
+    assert!(after_drop <= 256, "{}", after_drop);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(read_ahead_leaves(&db), after_drop);
+}
+
+#[test]
+fn test_read_ahead_respects_cache_policy() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // 绕过缓存的扫描不预读，也不把叶子节点放入缓存
+    let db = open_cold(&dir);
+    assert_eq!(db.iter_with(options(CachePolicy::BypassCache, 8)).count(), KEYS as usize);
+    assert_eq!(read_ahead_leaves(&db), 0);
+    assert!(!is_cached(&db, KEYS / 2));
+    drop(db);
+
+    // 不提升的扫描预读到缓存的入口段
+    let db = open_cold(&dir);
+    assert_eq!(db.iter_with(options(CachePolicy::ReadDontPromote, 8)).count(), KEYS as usize);
+    assert!(read_ahead_leaves(&db) > 0);
+}
+
+#[test]
+fn test_config_default_read_ahead() {
+    let dir = tempfile::tempdir().unwrap();
+    drop(open_cold(&dir));
+
+    let db: Db<FANOUT> = config(dir.path()).read_ahead_leaves(4).open().unwrap();
+    let mut iter = db.iter();
+    iter.next().unwrap().unwrap();
+    wait_for_read_ahead(&db, 4);
+    drop(iter);
+    assert_eq!(read_ahead_leaves(&db), 4);
+    drop(db);
+
+    // 显式的 `IterOptions` 不使用配置的默认值
+    let db: Db<FANOUT> = config(dir.path()).read_ahead_leaves(4).open().unwrap();
+    assert_eq!(db.iter_with(IterOptions::default()).count(), KEYS as usize);
+    assert_eq!(read_ahead_leaves(&db), 0);
+}
+
+/// 每次从堆文件读取叶子节点都等待这么久，模拟较慢的存储设备
+#[cfg(feature = "for-internal-testing-only")]
+const READ_LATENCY: Duration = Duration::from_micros(500);
+
+/// 对冷的数据库执行一次全量扫描，返回扫描的耗时和预读的叶子节点数
+#[cfg(feature = "for-internal-testing-only")]
+fn slow_scan(dir: &tempfile::TempDir, leaves: usize) -> (Duration, u64) {
+    let db = open_cold(dir);
+    db.set_read_latency_for_testing(READ_LATENCY);
+    let start = Instant::now();
+    assert_eq!(db.iter_with(options(CachePolicy::Normal, leaves)).count(), KEYS as usize);
+    (start.elapsed(), read_ahead_leaves(&db))
+}
+
+#[test]
+#[cfg(feature = "for-internal-testing-only")]
+fn test_read_ahead_reads_ahead_of_slow_scans() {
+    let dir = tempfile::tempdir().unwrap();
+    drop(open_cold(&dir));
+
+    // 每次读取都较慢时，迭代器在预读的读取进行时前进，由后台读取的叶子节点被计数
+    let [none, two, eight] = [0, 2, 8].map(|k| slow_scan(&dir, k).1);
+    assert_eq!(none, 0);
+    assert!(two > 0, "预读2个时后台读取了 {} 个叶子节点", two);
+    assert!(eight > 0, "预读8个时后台读取了 {} 个叶子节点", eight);
+}
+
+/// 依赖机器负载的耗时比较，使用 `cargo test -- --ignored` 运行
+#[test]
+#[ignore]
+#[cfg(feature = "for-internal-testing-only")]
+fn test_read_ahead_hides_read_latency() {
+    let dir = tempfile::tempdir().unwrap();
+    drop(open_cold(&dir));
+
+    let [none, two, eight] = [0, 2, 8].map(|k| slow_scan(&dir, k).0);
+    // 读取延迟占主要部分时，扫描耗时大致与同时进行的读取数成反比
+    assert!(two < none * 3 / 4, "不预读 {:?}，预读2个 {:?}", none, two);
+    assert!(eight < two / 2, "预读2个 {:?}，预读8个 {:?}", two, eight);
+}
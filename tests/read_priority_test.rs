@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const WRITERS: usize = 64;
+const READS: usize = 200;
+
+fn open_manager() -> Arc<HybridOperationsManager> {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    Arc::new(HybridOperationsManager::new_with_db_worker(db))
+}
+
+/// 大量线程持续写入时测量读取的延迟，返回延迟的中位数和第95百分位
+fn read_latency_under_write_storm(manager: &Arc<HybridOperationsManager>) -> (Duration, Duration) {
+    manager.insert(b"hot", b"hot value").unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|t| {
+            let manager = manager.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let value = vec![t as u8; 512];
+                let mut i = 0_u64;
+                while !stop.load(Ordering::Relaxed) {
+                    let key = [&(t as u64).to_be_bytes()[..], &(i % 1000).to_be_bytes()].concat();
+                    manager.insert(&key, &value).unwrap();
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+
+    // 等待写入队列建立起来
+    thread::sleep(Duration::from_millis(200));
+
+    let mut latencies: Vec<Duration> = (0..READS)
+        .map(|i| {
+            let start = Instant::now();
+            if i % 2 == 0 {
+                assert_eq!(&*manager.get_data(b"hot").unwrap().unwrap(), b"hot value");
+            } else {
+                assert!(manager.contains_key(b"hot").unwrap());
+            }
+            let latency = start.elapsed();
+            thread::sleep(Duration::from_micros(200));
+            latency
+        })
+        .collect();
+
+    stop.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    latencies.sort();
+    (latencies[READS / 2], latencies[READS * 95 / 100])
+}
+
+#[test]
+fn test_reads_overtake_write_storm() {
+    let fifo = read_latency_under_write_storm(&open_manager());
+
+    let manager = open_manager();
+    manager.set_read_priority(true);
+    let prioritized = read_latency_under_write_storm(&manager);
+
+    println!("读取延迟（中位数, p95）FIFO: {:?}, 读取优先: {:?}", fifo, prioritized);
+    assert!(prioritized.0 * 2 < fifo.0, "FIFO: {:?}, 读取优先: {:?}", fifo, prioritized);
+    assert!(prioritized.1 < fifo.1, "FIFO: {:?}, 读取优先: {:?}", fifo, prioritized);
+}
+
+#[test]
+fn test_scans_and_writes_complete_with_read_priority() {
+    let manager = open_manager();
+    manager.set_read_priority(true);
+
+    for i in 0..5_000_u32 {
+        manager.insert(&[b"p:", &i.to_be_bytes()[..]].concat(), &i.to_le_bytes()).unwrap();
+    }
+
+    // 持续的读取不会饿死写入，分批执行的扫描也留在读取队列中
+    let stop = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let manager = manager.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    manager.get_data(b"p:").unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for i in 0..200_u32 {
+        manager.insert(&[b"q:", &i.to_be_bytes()[..]].concat(), b"").unwrap();
+    }
+    let scanned = manager.scan_prefix(b"p:").unwrap();
+    assert_eq!(scanned.len(), 5_000);
+    for (i, (key, value)) in scanned.iter().enumerate() {
+        assert_eq!(&key[2..], (i as u32).to_be_bytes());
+        assert_eq!(&value[..], (i as u32).to_le_bytes());
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // 关闭读取优先后按提交顺序处理
+    manager.set_read_priority(false);
+    manager.insert(b"after", b"1").unwrap();
+    assert!(manager.contains_key(b"after").unwrap());
+    assert_eq!(manager.scan_prefix(b"q:").unwrap().len(), 200);
+}
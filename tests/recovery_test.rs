@@ -0,0 +1,247 @@
+mod common;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+use common::copy_dir;
+
+const TREE_NAMES: [&str; 3] = ["users", "orders", "logs"];
+
+/// 写入测试数据：不同大小的值分布在多个堆文件中
+fn populate(config: &Config, n: u32, max_value_len: usize) {
+    let db = config.open::<1024>().unwrap();
+    let trees: Vec<Tree<1024>> =
+        TREE_NAMES.iter().map(|name| db.open_tree(name).unwrap()).collect();
+
+    for i in 0..n {
+        let value = vec![(i % 251) as u8; (i as usize * 7) % max_value_len + 1];
+        db.insert(i.to_be_bytes(), value.clone()).unwrap();
+        for tree in &trees {
+            tree.insert(i.to_be_bytes(), value.clone()).unwrap();
+        }
+    }
+
+    db.flush().unwrap();
+}
+
+type Contents = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// 以给定的恢复线程数打开数据库，返回所有树的内容和回调收到的进度
+fn recover(config: &Config, threads: usize) -> (Vec<Contents>, Vec<RecoveryProgress>, Duration) {
+    let progress = Arc::new(Mutex::new(vec![]));
+    let progress2 = progress.clone();
+
+    let before = Instant::now();
+    let db = config
+        .clone()
+        .verify_slots_on_open(true)
+        .recovery_threads(threads)
+        .recovery_progress_callback(Arc::new(move |p| progress2.lock().unwrap().push(p)))
+        .open::<1024>()
+        .unwrap();
+    let elapsed = before.elapsed();
+
+    assert!(db.was_recovered());
+    db.check().unwrap();
+
+    let collect = |tree: &Tree<1024>| -> Contents {
+        tree.iter()
+            .map(|kv| {
+                let (k, v) = kv.unwrap();
+                (k.to_vec(), v.to_vec())
+            })
+            .collect()
+    };
+
+    let mut contents = vec![collect(&db)];
+    for name in TREE_NAMES {
+        contents.push(collect(&db.open_tree(name).unwrap()));
+    }
+
+    let progress = progress.lock().unwrap().clone();
+    (contents, progress, elapsed)
+}
+
+/// 进度必须单调递增，并以全部对象校验完成结束
+fn assert_progress_monotonic(progress: &[RecoveryProgress]) {
+    assert!(!progress.is_empty());
+    for pair in progress.windows(2) {
+        assert!(pair[0].objects_scanned <= pair[1].objects_scanned, "{:?}", pair);
+        assert!(pair[0].bytes_processed <= pair[1].bytes_processed, "{:?}", pair);
+        assert_eq!(pair[0].objects_total, pair[1].objects_total);
+    }
+
+    let last = progress.last().unwrap();
+    assert!(last.objects_total > 0);
+    assert_eq!(last.objects_scanned, last.objects_total);
+    assert!(last.bytes_processed > 0);
+}
+
+#[test]
+fn test_parallel_recovery_matches_single_threaded() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    populate(&config, 20_000, 3000);
+
+    let (single, single_progress, _) = recover(&config, 1);
+    let (parallel, parallel_progress, _) = recover(&config, 4);
+
+    assert_eq!(single.len(), TREE_NAMES.len() + 1);
+    assert!(single.iter().all(|tree| tree.len() == 20_000));
+    assert_eq!(single, parallel);
+
+    assert_progress_monotonic(&single_progress);
+    assert_progress_monotonic(&parallel_progress);
+    assert_eq!(single_progress.last(), parallel_progress.last());
+}
+
+#[test]
+fn test_recovery_detects_corrupted_slab() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    populate(&config, 1000, 100);
+
+    // 破坏每个非空堆文件的第一个槽位
+    for entry in std::fs::read_dir(config.path.join("slabs")).unwrap() {
+        let path = entry.unwrap().path();
+        let mut data = std::fs::read(&path).unwrap();
+        if !data.is_empty() {
+            data[0] ^= 0xFF;
+            std::fs::write(&path, data).unwrap();
+        }
+    }
+
+    // 正常关闭之后只在显式要求时校验，损坏在第一次读取时才被发现
+    let err = config.clone().verify_slots_on_open(true).open::<1024>().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_slots_are_only_verified_after_unclean_shutdown() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    populate(&config, 1000, 100);
+
+    let reports = Arc::new(AtomicU64::new(0));
+    let reports2 = reports.clone();
+    let config = config.recovery_progress_callback(Arc::new(move |_| {
+        reports2.fetch_add(1, Ordering::Relaxed);
+    }));
+
+    // 正常关闭之后打开不读取堆文件
+    let db = config.open::<1024>().unwrap();
+    let report = db.recovery_report().unwrap();
+    assert!(report.clean_shutdown);
+    assert_eq!(report.bytes_verified, 0);
+    assert_eq!(reports.load(Ordering::Relaxed), 0);
+    assert_eq!(db.len().unwrap(), 1000);
+
+    // 模拟崩溃：复制没有正常关闭的目录，打开副本时校验所有叶子节点
+    let crashed = tempfile::tempdir().unwrap();
+    copy_dir(&config.path, crashed.path());
+    drop(db);
+    let db = config.clone().path(crashed.path()).open::<1024>().unwrap();
+    let report = db.recovery_report().unwrap();
+    assert!(!report.clean_shutdown);
+    assert!(report.bytes_verified > 0);
+    assert!(reports.load(Ordering::Relaxed) > 0);
+    assert_eq!(db.len().unwrap(), 1000);
+    drop(db);
+
+    // 显式要求时正常关闭之后也校验
+    reports.store(0, Ordering::Relaxed);
+    let db = config.clone().verify_slots_on_open(true).open::<1024>().unwrap();
+    let report = db.recovery_report().unwrap();
+    assert!(report.clean_shutdown);
+    assert!(report.bytes_verified > 0);
+    assert!(reports.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn test_recovery_report() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    assert!(config.open::<1024>().unwrap().recovery_report().is_none());
+
+    populate(&config, 1000, 100);
+
+    let (_, progress, _) = recover(&config, 1);
+    let db = config.clone().verify_slots_on_open(true).open::<1024>().unwrap();
+    let report = db.recovery_report().unwrap();
+
+    assert!(report.is_clean());
+    assert!(report.snapshot_recovered);
+    assert!(report.logs_replayed > 0);
+    // 默认树、名称映射树以及3个命名树
+    assert_eq!(report.collections_recovered, TREE_NAMES.len() as u64 + 2);
+    let last = progress.last().unwrap();
+    assert_eq!(report.objects_recovered, last.objects_total);
+    assert_eq!(report.bytes_verified, last.bytes_processed);
+    assert_eq!(report.torn_bytes_truncated, 0);
+    drop(db);
+
+    // 每次恢复都会推进日志序列号
+    let again = config.open::<1024>().unwrap().recovery_report().unwrap();
+    assert!(again.recovered_to_lsn > report.recovered_to_lsn);
+    assert_eq!(again.objects_recovered, report.objects_recovered);
+}
+
+#[test]
+fn test_recovery_report_torn_write() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    populate(&config, 1000, 100);
+
+    // 模拟flush写入元数据日志的中途崩溃，以及未完成的快照
+    let metadata_dir = config.path.join("metadata");
+    let mut logs: Vec<_> = std::fs::read_dir(&metadata_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.file_name().unwrap().to_str().unwrap().starts_with("log"))
+        .collect();
+    logs.sort();
+    let newest_log = logs.last().unwrap();
+    let mut data = std::fs::read(newest_log).unwrap();
+    assert!(!data.is_empty());
+    data.extend_from_slice(&[0xAB; 37]);
+    std::fs::write(newest_log, data).unwrap();
+    std::fs::write(metadata_dir.join("snapshot_00000000000000ff.tmp"), b"partial").unwrap();
+
+    let db = config.open::<1024>().unwrap();
+    let report = db.recovery_report().unwrap();
+
+    assert!(!report.is_clean());
+    assert_eq!(report.torn_writes_truncated, 1);
+    assert_eq!(report.torn_bytes_truncated, 37);
+    assert_eq!(report.incomplete_snapshots_removed, 1);
+    assert!(report.batches_replayed > 0);
+
+    // 完整的批次不受影响
+    db.check().unwrap();
+    for name in TREE_NAMES {
+        assert_eq!(db.open_tree(name).unwrap().len().unwrap(), 1000);
+    }
+}
+
+#[test]
+#[ignore]
+fn test_large_parallel_recovery() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    populate(&config, 250_000, 4096);
+
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).max(4);
+
+    let (single, single_progress, single_elapsed) = recover(&config, 1);
+    let (parallel, parallel_progress, parallel_elapsed) = recover(&config, threads);
+
+    println!(
+        "恢复 {} 个对象: 单线程 {:?}, {} 线程 {:?}",
+        single_progress.last().unwrap().objects_total,
+        single_elapsed,
+        threads,
+        parallel_elapsed
+    );
+
+    assert_eq!(single, parallel);
+    assert_progress_monotonic(&single_progress);
+    assert_progress_monotonic(&parallel_progress);
+    assert!(parallel_elapsed < single_elapsed);
+}
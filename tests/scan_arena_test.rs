@@ -0,0 +1,104 @@
+mod common;
+
+use std::io;
+use std::sync::Mutex;
+
+use melange_db::*;
+
+use common::config;
+
+// 计数分配器的统计是全局的，测试不能并行运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn key(i: u64) -> Vec<u8> {
+    let mut key = b"scan_arena_".to_vec();
+    key.extend_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn value(i: u64) -> Vec<u8> {
+    (0..32).map(|j| (i as usize * 7 + j) as u8).collect()
+}
+
+fn fill(tree: &Tree<1024>, n: u64) {
+    let mut batch = Batch::default();
+    for i in 0..n {
+        batch.insert(key(i), value(i));
+        if i % 10_000 == 9_999 {
+            tree.apply_batch(std::mem::take(&mut batch)).unwrap();
+        }
+    }
+    tree.apply_batch(batch).unwrap();
+}
+
+#[test]
+fn test_scan_prefix_in_matches_scan_prefix() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).inline_value_threshold(1024).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, 5000);
+    tree.insert(b"other", vec![1]).unwrap();
+    tree.insert(b"scan_arena_empty", vec![]).unwrap();
+    tree.insert(b"scan_arena_large", vec![9; 4096]).unwrap();
+
+    let mut arena = ScanArena::with_capacity(1024);
+    for prefix in [&b"scan_arena_"[..], b"", b"scan_arena_\0\0\0\0\0\0\x01", b"missing"] {
+        let expected: Vec<_> = tree.scan_prefix(prefix).collect::<io::Result<_>>().unwrap();
+        let scanned: Vec<_> =
+            tree.scan_prefix_in(prefix, &arena).collect::<io::Result<_>>().unwrap();
+        assert_eq!(scanned.len(), expected.len());
+        for ((k, v), (ek, ev)) in scanned.iter().zip(&expected) {
+            assert_eq!(*k, &**ek);
+            assert_eq!(*v, &**ev);
+        }
+        drop(scanned);
+        arena.reset();
+    }
+
+    // 上一次扫描的块在reset后被复用
+    let capacity = arena.capacity();
+    assert_eq!(tree.scan_prefix_in(b"scan_arena_", &arena).count(), 5002);
+    assert_eq!(arena.capacity(), capacity);
+    assert!(arena.allocated_bytes() > 5000 * 32);
+}
+
+#[cfg(feature = "testing-count-allocator")]
+#[test]
+fn test_scan_prefix_in_avoids_per_entry_allocations() {
+    const N: u64 = 1_000_000;
+    let _serial = SERIAL.lock().unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path()).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    fill(&tree, N);
+
+    // 先扫描一次使所有叶子节点都在缓存中
+    assert_eq!(tree.scan_prefix(b"scan_arena_").count() as u64, N);
+
+    melange_db::alloc::reset();
+    let mut count = 0;
+    for kv in tree.scan_prefix(b"scan_arena_") {
+        let (k, v) = kv.unwrap();
+        count += k.len() + v.len();
+    }
+    let iter_allocations = melange_db::alloc::allocations();
+
+    let arena = ScanArena::new();
+    melange_db::alloc::reset();
+    let mut arena_count = 0;
+    for kv in tree.scan_prefix_in(b"scan_arena_", &arena) {
+        let (k, v) = kv.unwrap();
+        arena_count += k.len() + v.len();
+    }
+    let arena_allocations = melange_db::alloc::allocations();
+
+    println!(
+        "allocations for {} entries: {} with scan_prefix, {} with scan_prefix_in",
+        N, iter_allocations, arena_allocations
+    );
+    assert_eq!(arena_count, count);
+    assert!(iter_allocations as u64 >= N, "{}", iter_allocations);
+    assert!((arena_allocations as u64) < N / 100, "{}", arena_allocations);
+}
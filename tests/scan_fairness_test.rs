@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const SCAN_KEYS: u64 = 500_000;
+
+#[test]
+fn test_large_scan_does_not_stall_worker() {
+    let db: Arc<Db<1024>> = Arc::new(Config::tmp().unwrap().open().unwrap());
+    let mut batch = Batch::default();
+    for i in 0..SCAN_KEYS {
+        let mut key = b"scan:".to_vec();
+        key.extend_from_slice(&i.to_be_bytes());
+        batch.insert(key, i.to_le_bytes().to_vec());
+    }
+    db.apply_batch(batch).unwrap();
+    db.insert(b"other", b"value".as_slice()).unwrap();
+    db.flush().unwrap();
+
+    let manager = Arc::new(HybridOperationsManager::new_with_db_worker(db));
+
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let scanner = {
+        let manager = manager.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            started_tx.send(()).unwrap();
+            let items = manager.scan_prefix(b"scan:").unwrap();
+            (items, start.elapsed())
+        })
+    };
+    started_rx.recv().unwrap();
+
+    // 扫描期间不断递增计数器并通过数据库Worker读取数据
+    let mut latencies = vec![];
+    while !scanner.is_finished() {
+        let start = Instant::now();
+        manager.increment("requests".to_string(), 1).unwrap();
+        assert_eq!(&*manager.get_data(b"other").unwrap().unwrap(), b"value");
+        latencies.push(start.elapsed());
+    }
+    let (items, scan_time) = scanner.join().unwrap();
+
+    assert_eq!(items.len() as u64, SCAN_KEYS);
+    for (i, (key, value)) in items.iter().enumerate() {
+        assert_eq!(&key[5..], &(i as u64).to_be_bytes());
+        assert_eq!(value, &(i as u64).to_le_bytes());
+    }
+
+    latencies.sort();
+    let max = *latencies.last().unwrap();
+    println!("扫描耗时 {:?}，期间完成 {} 次操作，最大延迟 {:?}", scan_time, latencies.len(), max);
+    // 扫描分批执行，其他操作只需等待一批
+    assert!(latencies.len() >= 10, "扫描期间只完成了 {} 次操作", latencies.len());
+    assert!(max < Duration::from_millis(200), "最大延迟 {:?}，扫描耗时 {:?}", max, scan_time);
+    assert_eq!(manager.get("requests".to_string()).unwrap(), Some(latencies.len() as u64));
+}
+
+#[test]
+fn test_scan_returns_consistent_snapshot() {
+    let db: Arc<Db<1024>> = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    for i in 0..10_000_u64 {
+        db.insert([b"k:".as_slice(), &i.to_be_bytes()].concat(), b"old".as_slice()).unwrap();
+    }
+    let manager = Arc::new(HybridOperationsManager::new_with_db_worker(db));
+
+    // 扫描分批执行时并发写入同一范围的键
+    let writer = {
+        let manager = manager.clone();
+        thread::spawn(move || {
+            for i in 0..10_000_u64 {
+                manager.insert(&[b"k:".as_slice(), &i.to_be_bytes()].concat(), b"new").unwrap();
+                manager.remove(&[b"k:".as_slice(), &(9_999 - i).to_be_bytes()].concat()).unwrap();
+            }
+        })
+    };
+    let items = manager.scan_prefix(b"k:").unwrap();
+    writer.join().unwrap();
+
+    // 结果要么是扫描开始前的状态，要么是写入期间某一时刻的一致状态，
+    // 每个键最多出现一次并且有序
+    assert!(items.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(items.iter().all(|(key, _)| key.starts_with(b"k:")));
+}
@@ -0,0 +1,161 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use melange_db::*;
+
+/// 只统计当前线程的分配次数，不受其他测试线程和后台线程的影响
+struct ThreadCountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for ThreadCountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: ThreadCountingAllocator = ThreadCountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const N: u32 = 20_000;
+
+/// 值的第一个字节为1的行约占2%
+fn value(i: u32) -> Vec<u8> {
+    let mut value = vec![u8::from(i.is_multiple_of(50))];
+    value.extend_from_slice(&i.to_be_bytes());
+    value.resize(100, 0xAB);
+    value
+}
+
+fn populated() -> Db<1024> {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    for i in 0..N {
+        db.insert(i.to_be_bytes(), value(i)).unwrap();
+    }
+    db
+}
+
+fn accept(key: &[u8], value: &[u8]) -> Option<(Vec<u8>, u8)> {
+    (value[0] == 1).then(|| (key.to_vec(), value[4]))
+}
+
+#[test]
+fn test_scan_filter_matches_filter_after_iter() {
+    let db = populated();
+
+    let expected = |range: std::ops::Range<u32>| -> Vec<(Vec<u8>, u8)> {
+        db.range(range.start.to_be_bytes()..range.end.to_be_bytes())
+            .map(|item| item.unwrap())
+            .filter_map(|(k, v)| accept(&k, &v))
+            .collect()
+    };
+
+    for range in [0..N, 0..1, 100..7_777, 5_000..5_001, 19_000..N + 10, 300..300] {
+        let actual = db
+            .scan_filter(range.start.to_be_bytes()..range.end.to_be_bytes(), accept)
+            .unwrap();
+        assert_eq!(actual, expected(range.clone()), "范围 {:?}", range);
+    }
+
+    // 流式版本与一次性收集的结果相同，包含上界的范围包含上界
+    let streamed: Vec<_> = db
+        .scan_filter_iter(50u32.to_be_bytes()..=150u32.to_be_bytes(), accept)
+        .map(|item| item.unwrap())
+        .collect();
+    let keys: Vec<u32> = streamed
+        .iter()
+        .map(|(k, _)| u32::from_be_bytes(k[..].try_into().unwrap()))
+        .collect();
+    assert_eq!(keys, vec![50, 100, 150]);
+
+    // 全表扫描只保留约2%的行
+    let all = db.scan_filter::<&[u8], _, _, _>(.., accept).unwrap();
+    assert_eq!(all.len(), N as usize / 50);
+}
+
+#[test]
+fn test_rejected_rows_do_not_allocate() {
+    let db = populated();
+
+    // 预热缓存
+    assert_eq!(db.iter().count(), N as usize);
+
+    let before = allocations();
+    let rejected = db
+        .scan_filter::<&[u8], _, _, ()>(.., |_key, _value| None)
+        .unwrap();
+    let scan_filter_allocations = allocations() - before;
+    assert!(rejected.is_empty());
+
+    let before = allocations();
+    let filtered = db
+        .iter()
+        .filter(|item| item.as_ref().is_ok_and(|(_, v)| v[0] == 2))
+        .count();
+    let iter_allocations = allocations() - before;
+    assert_eq!(filtered, 0);
+
+    println!(
+        "拒绝所有行: scan_filter分配 {} 次，iter后过滤分配 {} 次",
+        scan_filter_allocations, iter_allocations
+    );
+    // scan_filter只有每个叶子节点的固定开销
+    assert!(iter_allocations >= N as usize);
+    assert!(
+        scan_filter_allocations * 20 < iter_allocations,
+        "{} {}",
+        scan_filter_allocations,
+        iter_allocations
+    );
+}
+
+#[test]
+fn test_panic_in_callback_does_not_poison_tree() {
+    let db = populated();
+    let poisoned_key = 1_234u32.to_be_bytes();
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        db.scan_filter::<&[u8], _, _, ()>(.., |key, _value| {
+            if key == poisoned_key {
+                panic!("回调中的panic");
+            }
+            None
+        })
+    }));
+    assert!(result.is_err());
+
+    // 流式版本在迭代中途panic
+    let mut streamed = db.scan_filter_iter::<&[u8], _, _, _>(.., accept);
+    assert!(streamed.next().unwrap().is_ok());
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        db.scan_filter_iter::<&[u8], _, _, ()>(.., |key, _value| {
+            assert_ne!(key, poisoned_key);
+            None
+        })
+        .count()
+    }));
+    assert!(result.is_err());
+    drop(streamed);
+
+    // panic时所在的叶子节点没有被锁住，可以继续读写
+    db.insert(poisoned_key, value(50)).unwrap();
+    assert_eq!(&*db.get(poisoned_key).unwrap().unwrap(), &value(50)[..]);
+    assert_eq!(db.len().unwrap(), N as usize);
+    assert_eq!(
+        db.scan_filter::<&[u8], _, _, _>(.., accept).unwrap().len(),
+        N as usize / 50 + 1
+    );
+    db.flush().unwrap();
+}
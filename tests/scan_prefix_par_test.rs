@@ -0,0 +1,73 @@
+use melange_db::*;
+
+type Entries = Vec<(InlineArray, InlineArray)>;
+
+fn serial(tree: &Tree<64>, prefix: &[u8]) -> Entries {
+    tree.scan_prefix(prefix).collect::<std::io::Result<_>>().unwrap()
+}
+
+#[test]
+fn test_scan_prefix_par_matches_serial() {
+    let config = Config::tmp().unwrap();
+    let db = config.open::<64>().unwrap();
+
+    for i in 0..5_000_u32 {
+        let prefix = match i % 3 {
+            0 => "user",
+            1 => "order",
+            _ => "log",
+        };
+        let key = format!("{}_{:06}", prefix, i);
+        db.insert(key.as_bytes(), format!("value_{}", i).as_bytes())
+            .unwrap();
+    }
+    // 一部分数据存在于脏叶子中
+    db.remove(b"user_000003").unwrap();
+
+    for prefix in [&b"user_"[..], b"order_", b"log_0012", b"", b"missing", b"\xff"] {
+        let par = db.scan_prefix_par(prefix).unwrap();
+        assert_eq!(par, serial(&db, prefix), "前缀 {:?}", prefix);
+    }
+
+    assert_eq!(db.scan_prefix_par(b"user_").unwrap().len(), 1666);
+}
+
+#[test]
+fn test_scan_prefix_par_large_values_from_disk() {
+    let config = Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .cache_capacity_bytes(1024 * 1024);
+
+    {
+        let db = config.open::<64>().unwrap();
+        for i in 0..4_000_u32 {
+            // 较大且可压缩的值
+            let value = format!("{:08}", i).repeat(512);
+            let mut key = BigEndianU64(i as u64 % 4).to_bytes().to_vec();
+            key.extend_from_slice(&i.to_be_bytes());
+            db.insert(key, value.as_bytes()).unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    // 重新打开数据库，使叶子只存在于磁盘上
+    let db = config.open::<64>().unwrap();
+
+    let prefix = BigEndianU64(2).to_bytes();
+    let before = db.stats().cache.cache_misses;
+    let par = db.scan_prefix_par(prefix).unwrap();
+    let after = db.stats().cache.cache_misses;
+
+    assert_eq!(par.len(), 1000);
+    assert!(after > before);
+    for pair in par.windows(2) {
+        assert!(pair[0].0 < pair[1].0);
+    }
+    for (k, v) in &par {
+        let i = u32::from_be_bytes(k[8..].try_into().unwrap());
+        assert_eq!(&**v, format!("{:08}", i).repeat(512).as_bytes());
+    }
+
+    assert_eq!(par, serial(&db, &prefix));
+}
@@ -0,0 +1,150 @@
+use melange_db::*;
+
+fn open() -> Db<64> {
+    Config::tmp().unwrap().flush_every_ms(None).open().unwrap()
+}
+
+fn keys(iter: impl Iterator<Item = std::io::Result<(InlineArray, InlineArray)>>) -> Vec<Vec<u8>> {
+    iter.map(|item| item.unwrap().0.to_vec()).collect()
+}
+
+/// 在作用域前后放置容易越界的键
+fn populate_neighbours(db: &Db<64>) {
+    for key in [
+        &b""[..],
+        b"a",
+        b"a.",
+        b"a0",
+        b"a/",
+        b"a/x",
+        b"a/\xff",
+        b"a/\xff\xff\x00",
+        b"b",
+        b"b/x",
+    ] {
+        db.insert(key, b"outside".as_slice()).unwrap();
+    }
+}
+
+#[test]
+fn test_scoped_reads_stay_inside_prefix() {
+    let db = open();
+    populate_neighbours(&db);
+    let scope = db.scoped(b"a/");
+
+    // 空键就是前缀本身
+    assert_eq!(
+        keys(scope.iter()),
+        vec![b"".to_vec(), b"x".to_vec(), b"\xff".to_vec(), b"\xff\xff\x00".to_vec()]
+    );
+    assert_eq!(&*scope.first().unwrap().unwrap().0, b"");
+    assert_eq!(&*scope.last().unwrap().unwrap().0, b"\xff\xff\x00");
+    assert_eq!(keys(scope.iter().rev()).len(), 4);
+    assert_eq!(scope.len().unwrap(), 4);
+
+    // 相对范围的边界无法越过前缀
+    assert_eq!(keys(scope.range(b"\xff".as_slice()..)).len(), 2);
+    assert_eq!(keys(scope.range(..=b"\xff\xff\xff".as_slice())).len(), 4);
+    assert_eq!(keys(scope.range(..b"x".as_slice())), vec![b"".to_vec()]);
+    assert_eq!(keys(scope.scan_prefix(b"\xff")).len(), 2);
+    assert_eq!(keys(scope.scan_prefix(b"")).len(), 4);
+
+    assert!(scope.get(b"x").unwrap().is_some());
+    assert!(scope.get(b"b/x").unwrap().is_none());
+    assert!(!scope.contains_key(b"0").unwrap());
+    assert!(!scope.is_empty().unwrap());
+    assert!(db.scoped(b"c/").is_empty().unwrap());
+}
+
+#[test]
+fn test_scoped_writes_stay_inside_prefix() {
+    let db = open();
+    populate_neighbours(&db);
+    let before: Vec<_> = db.iter().map(|item| item.unwrap()).collect();
+    let scope = db.scoped(b"a/");
+
+    scope.insert(b"", b"root".as_slice()).unwrap();
+    scope.insert(b"new", b"inside".as_slice()).unwrap();
+    scope.remove(b"x").unwrap();
+    assert!(scope.compare_and_swap(b"\xff", Some(b"outside"), Some(b"swapped".as_slice())).unwrap().is_ok());
+    assert!(scope.compare_and_swap(b"", Some(b"outside"), None as Option<&[u8]>).unwrap().is_err());
+    scope.update_and_fetch(b"counter", |old| Some(old.map_or(1, |v| v[0] + 1).to_be_bytes().to_vec())).unwrap();
+
+    // 删除作用域内的所有键
+    for key in keys(scope.iter()) {
+        scope.remove(key).unwrap();
+    }
+    assert!(scope.is_empty().unwrap());
+
+    // 作用域外的键没有变化
+    let after: Vec<_> = db.iter().map(|item| item.unwrap()).collect();
+    let outside = |items: &[(InlineArray, InlineArray)]| -> Vec<(InlineArray, InlineArray)> {
+        items.iter().filter(|(k, _)| !k.starts_with(b"a/")).cloned().collect()
+    };
+    assert_eq!(outside(&before), outside(&after));
+    assert_eq!(outside(&after).len(), 6);
+    assert_eq!(after.len(), 6);
+}
+
+#[test]
+fn test_scoped_ff_boundaries() {
+    let db = open();
+    for key in [&b"\xfe\xff"[..], b"\xff", b"\xff\xfe", b"\xff\xff", b"\xff\xff\x00", b"\xff\xff\xff"] {
+        db.insert(key, b"v".as_slice()).unwrap();
+    }
+
+    // 全部是0xFF的前缀没有上界
+    let scope = db.scoped(b"\xff\xff");
+    assert_eq!(keys(scope.iter()), vec![b"".to_vec(), b"\x00".to_vec(), b"\xff".to_vec()]);
+    assert_eq!(keys(scope.iter().rev()).len(), 3);
+
+    // 以0xFF结尾的前缀的上界进位到前一个字节
+    let scope = db.scoped(b"\xfe\xff");
+    assert_eq!(keys(scope.iter()), vec![b"".to_vec()]);
+    scope.insert(b"\xff\xff", b"v".as_slice()).unwrap();
+    assert_eq!(keys(scope.range(b"\x00".as_slice()..)), vec![b"\xff\xff".to_vec()]);
+    assert!(db.get(b"\xfe\xff\xff\xff").unwrap().is_some());
+
+    // 空前缀的作用域就是整个树
+    assert_eq!(keys(db.scoped(b"").iter()).len(), 7);
+}
+
+#[test]
+fn test_scoped_batches_and_nesting() {
+    let db = open();
+    populate_neighbours(&db);
+    let scope = db.scoped(b"a/");
+
+    let mut batch = Batch::default();
+    batch.insert(b"1".as_slice(), b"one".as_slice());
+    batch.remove(b"x".as_slice());
+    batch.guard(b"\xff".as_slice(), Some(b"outside"));
+    scope.apply_batch(batch).unwrap();
+    assert_eq!(&*db.get(b"a/1").unwrap().unwrap(), b"one");
+    assert!(db.get(b"a/x").unwrap().is_none());
+    assert!(db.get(b"b/x").unwrap().is_some());
+
+    // 守卫也是相对于前缀的键，未满足时什么都不写入
+    let mut batch = Batch::default();
+    batch.insert(b"2".as_slice(), b"two".as_slice());
+    batch.guard(b"b/x".as_slice(), Some(b"outside"));
+    assert!(scope.apply_batch(batch).is_err());
+    assert!(scope.get(b"2").unwrap().is_none());
+
+    // 溢出到磁盘的批次同样加上前缀
+    let mut batch = Batch::with_spill_threshold(256);
+    for i in 0..200u32 {
+        batch.insert(i.to_be_bytes().to_vec(), vec![0; 16]);
+    }
+    assert!(batch.is_spilled());
+    scope.apply_batch(batch).unwrap();
+    assert_eq!(scope.scan_prefix([0, 0]).count(), 200);
+    assert!(db.get(0u32.to_be_bytes()).unwrap().is_none());
+
+    // 嵌套的作用域
+    let nested = scope.scoped([0, 0, 0]);
+    assert_eq!(nested.prefix(), b"a/\x00\x00\x00");
+    assert_eq!(nested.len().unwrap(), 200);
+    nested.insert(b"z", b"nested".as_slice()).unwrap();
+    assert_eq!(&*db.get(b"a/\x00\x00\x00z").unwrap().unwrap(), b"nested");
+}
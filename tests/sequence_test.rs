@@ -0,0 +1,202 @@
+mod common;
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use fault_injection::FAULT_INJECT_COUNTER;
+use melange_db::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use common::{config, copy_dir};
+
+// 注入故障的计数器是全局的，这个文件中的测试依次运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn persisted_ceiling(db: &Db<1024>, name: &str) -> Option<u64> {
+    let key = [&b"__sequence__:"[..], name.as_bytes()].concat();
+    let value = db.get(key).unwrap()?;
+    Some(u64::from_be_bytes(value.as_ref().try_into().unwrap()))
+}
+
+#[test]
+fn test_next_peek_and_set_floor() {
+    let _serial = SERIAL.lock().unwrap();
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let sequence = db.sequence_with_batch_size("ids", 10).unwrap();
+    assert_eq!(sequence.batch_size(), 10);
+    assert_eq!(sequence.peek(), 0);
+    assert_eq!(persisted_ceiling(&db, "ids"), None);
+
+    assert_eq!(sequence.next().unwrap(), 0);
+    assert_eq!(sequence.peek(), 1);
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(10));
+
+    // 一个批次只持久化一次上界
+    for expected in 1..10 {
+        assert_eq!(sequence.next().unwrap(), expected);
+    }
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(10));
+    assert_eq!(sequence.next().unwrap(), 10);
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(20));
+
+    // 不大于下一个ID的下限不起作用
+    sequence.set_floor(5).unwrap();
+    assert_eq!(sequence.peek(), 11);
+
+    // 批次之内的下限不需要持久化
+    sequence.set_floor(15).unwrap();
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(20));
+    assert_eq!(sequence.next().unwrap(), 15);
+
+    // 超过上界的下限先被持久化
+    sequence.set_floor(1_000).unwrap();
+    assert_eq!(sequence.peek(), 1_000);
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(1_000));
+    assert_eq!(sequence.next().unwrap(), 1_000);
+    assert_eq!(persisted_ceiling(&db, "ids"), Some(1_010));
+
+    // 同名的句柄共享同一个序列，新的批次大小作用于所有句柄
+    let other = db.sequence_with_batch_size("ids", 100).unwrap();
+    assert_eq!(other.next().unwrap(), 1_001);
+    assert_eq!(sequence.batch_size(), 100);
+    assert_eq!(db.sequence("other").unwrap().next().unwrap(), 0);
+
+    let err = db.sequence_with_batch_size("ids", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_concurrent_handles_never_hand_out_the_same_id() {
+    let _serial = SERIAL.lock().unwrap();
+    const THREADS: usize = 4;
+    const IDS: usize = 500;
+
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let sequence = db.sequence_with_batch_size("shared", 64).unwrap();
+            thread::spawn(move || {
+                let mut ids = Vec::with_capacity(IDS);
+                for _ in 0..IDS {
+                    let id = sequence.next().unwrap();
+                    // 每个线程看到的ID严格递增
+                    assert!(ids.last().is_none_or(|last| *last < id));
+                    ids.push(id);
+                }
+                ids
+            })
+        })
+        .collect();
+
+    let mut all = HashSet::new();
+    for handle in handles {
+        for id in handle.join().unwrap() {
+            assert!(all.insert(id), "ID {} 被交出了两次", id);
+        }
+    }
+    assert_eq!(all.len(), THREADS * IDS);
+    assert_eq!(*all.iter().max().unwrap(), (THREADS * IDS - 1) as u64);
+    assert_eq!(persisted_ceiling(&db, "shared"), Some(((THREADS * IDS) as u64).div_ceil(64) * 64));
+}
+
+#[test]
+fn test_no_id_is_handed_out_twice_across_crashes() {
+    let _serial = SERIAL.lock().unwrap();
+    const BATCH_SIZE: u64 = 50;
+    let mut rng = StdRng::seed_from_u64(42);
+
+    let mut dir = tempfile::tempdir().unwrap();
+    let mut last: Option<u64> = None;
+    let mut handed_out = HashSet::new();
+
+    for round in 0..20 {
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let sequence = db.sequence_with_batch_size("ids", BATCH_SIZE).unwrap();
+        let tree = db.open_tree("data").unwrap();
+
+        // 重启后第一个ID大于崩溃前交出的所有ID，最多跳过一个批次
+        let first = sequence.next().unwrap();
+        if let Some(last) = last {
+            assert!(first > last, "第 {} 轮: {} 不大于崩溃前的 {}", round, first, last);
+            assert!(first - last <= BATCH_SIZE, "第 {} 轮跳过了 {} 个ID", round, first - last);
+        }
+        let mut ids = vec![first];
+
+        for _ in 0..rng.random_range(0..200) {
+            let id = sequence.next().unwrap();
+            // 没有flush的写入在崩溃后丢失，不影响序列
+            tree.insert(id.to_be_bytes(), b"row".as_slice()).unwrap();
+            ids.push(id);
+        }
+        if rng.random_bool(0.2) {
+            let floor = sequence.peek() + rng.random_range(0..3 * BATCH_SIZE);
+            sequence.set_floor(floor).unwrap();
+            ids.push(sequence.next().unwrap());
+        }
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        for id in &ids {
+            assert!(handed_out.insert(*id), "ID {} 被交出了两次", id);
+        }
+        last = ids.last().copied();
+
+        // 在没有flush的时刻崩溃
+        let crashed = tempfile::tempdir().unwrap();
+        copy_dir(dir.path(), crashed.path());
+        std::mem::forget(tree);
+        std::mem::forget(sequence);
+        std::mem::forget(db);
+        dir = crashed;
+    }
+}
+
+#[test]
+fn test_failed_ceiling_write_hands_out_nothing() {
+    let _serial = SERIAL.lock().unwrap();
+    const BATCH_SIZE: u64 = 10;
+    let mut recovered_after_failure = false;
+
+    for fault_at in 1.. {
+        let dir = tempfile::tempdir().unwrap();
+        let db: Db<1024> = config(dir.path()).open().unwrap();
+        let sequence = db.sequence_with_batch_size("ids", BATCH_SIZE).unwrap();
+        for expected in 0..BATCH_SIZE {
+            assert_eq!(sequence.next().unwrap(), expected);
+        }
+
+        // 持久化第二个批次的上界时注入故障
+        FAULT_INJECT_COUNTER.store(fault_at, Ordering::Release);
+        let res = sequence.next();
+        FAULT_INJECT_COUNTER.store(u64::MAX, Ordering::Release);
+
+        let crashed = tempfile::tempdir().unwrap();
+        copy_dir(dir.path(), crashed.path());
+        let last = match res {
+            Ok(id) => {
+                assert_eq!(id, BATCH_SIZE);
+                drop(sequence);
+                drop(db);
+                id
+            }
+            Err(_) => {
+                // 进程在这里崩溃，不再运行关闭时的flush
+                std::mem::forget(sequence);
+                std::mem::forget(db);
+                recovered_after_failure = true;
+                BATCH_SIZE - 1
+            }
+        };
+
+        let recovered: Db<1024> = config(crashed.path()).open().unwrap();
+        let id = recovered.sequence_with_batch_size("ids", BATCH_SIZE).unwrap().next().unwrap();
+        assert!(id > last, "故障点 {}: 恢复后交出 {}，崩溃前已交出 {}", fault_at, id, last);
+
+        if res.is_ok() {
+            break;
+        }
+    }
+    assert!(recovered_after_failure);
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use std::thread;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const THREADS: u64 = 8;
+const INCREMENTS: u64 = 500;
+
+fn persisted_counter(db: &Db<1024>, name: &str) -> Option<u64> {
+    let key = [&b"__atomic_counter__:"[..], name.as_bytes()].concat();
+    let value = db.get(key).unwrap()?;
+    assert_eq!(value.len(), 9);
+    Some(u64::from_le_bytes(value[1..].try_into().unwrap()))
+}
+
+#[test]
+fn test_managers_on_one_db_share_counters() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let first = Arc::new(HybridOperationsManager::new_with_db_worker(db.clone()));
+    // 通过数据库的克隆创建的管理器同样共享Worker
+    let second = Arc::new(HybridOperationsManager::new_with_db_worker(Arc::new((*db).clone())));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let manager = if t % 2 == 0 { first.clone() } else { second.clone() };
+            thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    manager.increment("shared".to_string(), 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let total = THREADS * INCREMENTS;
+    assert_eq!(first.get("shared".to_string()).unwrap(), Some(total));
+    assert_eq!(second.get("shared".to_string()).unwrap(), Some(total));
+
+    // 另一个管理器预热时不会用可能落后的持久化值覆盖内存中的计数器
+    let third = HybridOperationsManager::new(db.clone());
+    third.preload_counters().unwrap();
+    assert_eq!(third.increment("shared".to_string(), 1).unwrap(), total + 1);
+
+    // 关闭数据库Worker之前会处理完所有持久化指令，持久化的是最终值
+    drop(third);
+    drop(first);
+    drop(second);
+    assert_eq!(persisted_counter(&db, "shared"), Some(total + 1));
+}
+
+#[test]
+fn test_worker_mode_toggle_keeps_counters() {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let mut manager = HybridOperationsManager::new(db.clone());
+    let observer = manager.clone();
+
+    assert_eq!(manager.increment("toggle".to_string(), 5).unwrap(), 5);
+    // 直接访问模式下不持久化
+    assert_eq!(persisted_counter(&db, "toggle"), None);
+
+    manager.enable_database_worker_mode();
+    assert_eq!(manager.increment("toggle".to_string(), 5).unwrap(), 10);
+    assert_eq!(observer.get("toggle".to_string()).unwrap(), Some(10));
+
+    manager.disable_database_worker_mode();
+    assert_eq!(persisted_counter(&db, "toggle"), Some(10));
+
+    // 没有管理器使用数据库Worker后不再持久化
+    assert_eq!(observer.increment("toggle".to_string(), 1).unwrap(), 11);
+    assert_eq!(persisted_counter(&db, "toggle"), Some(10));
+}
+
+#[test]
+fn test_managers_on_different_dbs_are_independent() {
+    let a = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let b = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+
+    let manager_a = HybridOperationsManager::new(a);
+    let manager_b = HybridOperationsManager::new(b);
+
+    manager_a.increment("counter".to_string(), 3).unwrap();
+    assert_eq!(manager_b.get("counter".to_string()).unwrap(), None);
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "sled-import")]
+
+use melange_db::migrate::{MigrationOptions, from_sled};
+use melange_db::*;
+
+// sled的后台线程在数据库被释放后仍会短暂持有文件锁，重试直到锁被释放
+fn open_sled(path: &std::path::Path) -> sled::Db {
+    for _ in 0..100 {
+        if let Ok(db) = sled::open(path) {
+            return db;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    sled::open(path).unwrap()
+}
+
+fn create_sled_db(path: &std::path::Path) {
+    {
+        let sled_db = open_sled(path);
+
+        for i in 0..100u32 {
+            sled_db.insert(i.to_be_bytes(), format!("default_{}", i).as_bytes()).unwrap();
+        }
+
+        let users = sled_db.open_tree("users").unwrap();
+        for i in 0..50u32 {
+            users.insert(format!("user_{:03}", i).as_bytes(), format!("name_{}", i).as_bytes()).unwrap();
+        }
+        // 超过测试中设置的最大键长度，应被跳过
+        users.insert(vec![b'x'; 64], b"too_long".as_slice()).unwrap();
+
+        let logs = sled_db.open_tree("logs").unwrap();
+        for i in 0..10u32 {
+            logs.insert(i.to_be_bytes(), b"log".as_slice()).unwrap();
+        }
+
+        sled_db.flush().unwrap();
+    }
+
+    // 等到文件锁被释放，再交给 `from_sled` 打开
+    drop(open_sled(path));
+}
+
+#[test]
+fn test_sled_import() {
+    let sled_dir = tempfile::tempdir().unwrap();
+    let melange_dir = tempfile::tempdir().unwrap();
+
+    create_sled_db(sled_dir.path());
+
+    let mut options = MigrationOptions {
+        max_key_len: 32,
+        batch_size: 16,
+        ..MigrationOptions::default()
+    };
+    options.tree_renames.insert(b"logs".to_vec(), b"audit_logs".to_vec());
+
+    let report = from_sled(
+        sled_dir.path(),
+        Config::new().path(melange_dir.path()),
+        options,
+    )
+    .unwrap();
+
+    assert!(!report.dry_run);
+    assert_eq!(report.total_items(), 160);
+    assert_eq!(report.total_skipped(), 1);
+
+    let users_report = report.trees.iter().find(|t| t.source_name == b"users").unwrap();
+    assert_eq!(users_report.items, 50);
+    assert_eq!(users_report.skipped_keys, vec![vec![b'x'; 64]]);
+
+    // 对比迁移后的内容和迭代顺序
+    let sled_db = open_sled(sled_dir.path());
+    let db = Config::new().path(melange_dir.path()).open::<1024>().unwrap();
+
+    let expected: Vec<(Vec<u8>, Vec<u8>)> =
+        sled_db.iter().map(|kv| { let (k, v) = kv.unwrap(); (k.to_vec(), v.to_vec()) }).collect();
+    let actual: Vec<(Vec<u8>, Vec<u8>)> =
+        db.iter().map(|kv| { let (k, v) = kv.unwrap(); (k.to_vec(), v.to_vec()) }).collect();
+    assert_eq!(expected, actual);
+
+    let expected: Vec<Vec<u8>> = sled_db
+        .open_tree("users").unwrap()
+        .iter().keys().map(|k| k.unwrap().to_vec())
+        .filter(|k| k.len() <= 32)
+        .collect();
+    let actual: Vec<Vec<u8>> = db
+        .open_tree("users").unwrap()
+        .iter().keys().map(|k| k.unwrap().to_vec())
+        .collect();
+    assert_eq!(expected, actual);
+
+    assert!(!db.contains_tree("logs").unwrap());
+    assert_eq!(db.open_tree("audit_logs").unwrap().len().unwrap(), 10);
+}
+
+#[test]
+fn test_sled_import_dry_run() {
+    let sled_dir = tempfile::tempdir().unwrap();
+    let melange_dir = tempfile::tempdir().unwrap();
+    let melange_path = melange_dir.path().join("db");
+
+    create_sled_db(sled_dir.path());
+
+    let options = MigrationOptions {
+        dry_run: true,
+        ..MigrationOptions::default()
+    };
+
+    let report = from_sled(
+        sled_dir.path(),
+        Config::new().path(&melange_path),
+        options,
+    )
+    .unwrap();
+
+    assert!(report.dry_run);
+    assert_eq!(report.total_items(), 161);
+    assert_eq!(report.total_skipped(), 0);
+    assert!(report.total_bytes() > 0);
+
+    // dry-run 不应创建 melange_db 数据库
+    assert!(!melange_path.exists());
+}
+
+#[test]
+fn test_sled_import_missing_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+
+    let err = from_sled(&missing, Config::new().path(dir.path().join("db")), MigrationOptions::default())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+    // 不会在错误的路径上创建空的sled数据库
+    assert!(!missing.exists());
+    let err = from_sled(dir.path(), Config::new().path(dir.path().join("db")), MigrationOptions::default())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(!dir.path().join("conf").exists());
+}
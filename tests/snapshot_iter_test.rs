@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use melange_db::*;
+
+const N: u32 = 5_000;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:08}", i).into_bytes()
+}
+
+fn collect(iter: impl Iterator<Item = std::io::Result<(InlineArray, InlineArray)>>) -> Vec<(InlineArray, InlineArray)> {
+    iter.collect::<std::io::Result<_>>().unwrap()
+}
+
+/// 使用较小的叶子节点，使并发写入频繁触发分裂和合并
+fn open_db() -> Db<16> {
+    Config::tmp()
+        .unwrap()
+        .flush_every_ms(None)
+        .leaf_merge_threshold(4)
+        .open()
+        .unwrap()
+}
+
+fn populate(db: &Db<16>) {
+    for i in 0..N {
+        db.insert(key(i * 2), format!("v{}", i).as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn test_snapshot_iter_matches_pre_mutation_state() {
+    let db = open_db();
+    populate(&db);
+    let expected = collect(db.iter());
+
+    let mut snapshot = db.snapshot_iter();
+
+    // 先读取一部分，之后的写入会同时发生在迭代器位置的前后
+    let mut seen: Vec<(InlineArray, InlineArray)> = vec![];
+    for _ in 0..100 {
+        seen.push(snapshot.next().unwrap().unwrap());
+    }
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut writers = vec![];
+    for t in 0..4_u32 {
+        let db = db.clone();
+        let done = done.clone();
+        writers.push(std::thread::spawn(move || {
+            let mut round = 0_u32;
+            while !done.load(Ordering::Relaxed) {
+                for i in (t..N).step_by(4) {
+                    match (i + round) % 4 {
+                        0 => {
+                            db.insert(key(i * 2 + 1), b"new".as_slice()).unwrap();
+                        }
+                        1 => {
+                            db.remove(key(i * 2)).unwrap();
+                        }
+                        2 => {
+                            db.insert(key(i * 2), b"overwritten".as_slice()).unwrap();
+                        }
+                        _ => {
+                            let mut batch = Batch::default();
+                            batch.remove(key(i * 2 + 1));
+                            batch.insert(key(i * 2), b"batched".as_slice());
+                            db.apply_batch(batch).unwrap();
+                        }
+                    }
+                }
+                round += 1;
+            }
+        }));
+    }
+
+    for kv in snapshot.by_ref() {
+        seen.push(kv.unwrap());
+        if seen.len().is_multiple_of(500) {
+            std::thread::yield_now();
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(seen.len(), expected.len());
+    assert!(seen == expected, "快照迭代器观察到了并发写入");
+
+    // 普通迭代器能看到写入后的状态
+    assert_ne!(collect(db.iter()), expected);
+}
+
+#[test]
+fn test_snapshot_iter_ignores_own_writes() {
+    let db = open_db();
+    populate(&db);
+    let expected = collect(db.iter());
+
+    // 在迭代的同时由同一个线程从末尾开始删除所有键，并在迭代器前方插入新的键
+    let mut seen = vec![];
+    for (j, kv) in db.snapshot_iter().enumerate() {
+        let ahead = N - 1 - j as u32;
+        db.remove(key(ahead * 2)).unwrap();
+        db.insert(key(ahead * 2 + 1), b"new".as_slice()).unwrap();
+        seen.push(kv.unwrap());
+    }
+
+    assert!(seen == expected);
+    assert_eq!(db.len().unwrap(), N as usize);
+}
+
+#[test]
+fn test_snapshot_does_not_block_flush() {
+    let db = open_db();
+    populate(&db);
+
+    let mut snapshot = db.snapshot_iter();
+    let first = snapshot.next().unwrap().unwrap();
+
+    db.insert(b"after", b"value".as_slice()).unwrap();
+    db.flush().unwrap();
+
+    assert_eq!(first.0, key(0));
+    assert_eq!(snapshot.count(), N as usize - 1);
+
+    // 快照释放后新的快照能看到之前的写入
+    assert_eq!(db.snapshot_iter().count(), N as usize + 1);
+}
@@ -0,0 +1,81 @@
+use melange_db::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const VALUE_LEN: usize = 1000;
+
+fn write_values(tree: &Tree<1024>, keys: impl Iterator<Item = u32>, rng: &mut StdRng) {
+    for i in keys {
+        let mut value = vec![0; VALUE_LEN];
+        rng.fill(&mut value[..]);
+        tree.insert(format!("key_{:08}", i), value).unwrap();
+    }
+}
+
+#[test]
+fn test_space_amplification_report() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db: Db<1024> = config.open().unwrap();
+
+    let tree = db.open_tree("data").unwrap();
+    write_values(&tree, 0..4000, &mut rng);
+    db.insert(b"default", vec![1; 10]).unwrap();
+    db.flush().unwrap();
+
+    let initial = db.space_amplification_report().unwrap();
+    assert_eq!(initial.disk_bytes, db.size_on_disk().unwrap());
+    // 名称映射树不计入逻辑字节数
+    assert_eq!(initial.logical_bytes, 4000 * (12 + VALUE_LEN as u64) + 7 + 10);
+    assert_eq!(initial.ratio(), db.space_amplification().unwrap());
+    println!("初始空间放大: {:.3} {:?}", initial.ratio(), initial);
+
+    // 反复覆盖一半的键，逻辑字节数不变。目前观察到每一轮都使堆文件增长约一份
+    // 被重写的叶子节点的大小，比例从约1.04依次升到约6.2
+    for round in 0..5 {
+        write_values(&tree, (0..4000).step_by(2), &mut rng);
+        db.flush().unwrap();
+        let report = db.space_amplification_report().unwrap();
+        println!("第{}轮覆盖后的空间放大: {:.3} {:?}", round + 1, report.ratio(), report);
+        assert_eq!(report.logical_bytes, initial.logical_bytes);
+    }
+    let overwritten = db.space_amplification_report().unwrap();
+    assert!(
+        overwritten.ratio() > initial.ratio(),
+        "{:?} {:?}",
+        overwritten,
+        initial
+    );
+
+    // 没有独立的压缩操作。压缩元数据日志并经过几次没有写入的flush后，
+    // 放大比例不会升高；目前观察到它基本不变，因为堆文件的长度没有缩小
+    db.compact_metadata().unwrap();
+    for _ in 0..3 {
+        db.flush().unwrap();
+    }
+    let settled = db.space_amplification_report().unwrap();
+    println!("压缩元数据后的空间放大: {:.3} {:?}", settled.ratio(), settled);
+    assert_eq!(settled.logical_bytes, initial.logical_bytes);
+    assert!(settled.ratio() <= overwritten.ratio(), "{:?} {:?}", settled, overwritten);
+
+    drop(tree);
+    drop(db);
+    let db: Db<1024> = config.open().unwrap();
+    let reopened = db.space_amplification_report().unwrap();
+    println!("重新打开后的空间放大: {:.3} {:?}", reopened.ratio(), reopened);
+    // 重新打开也不会回收这部分空间
+    assert_eq!(reopened.logical_bytes, initial.logical_bytes);
+    assert!(reopened.ratio() <= overwritten.ratio(), "{:?} {:?}", reopened, overwritten);
+}
+
+#[test]
+fn test_space_amplification_of_empty_database() {
+    let config = Config::tmp().unwrap().flush_every_ms(None);
+    let db: Db<1024> = config.open().unwrap();
+
+    let report = db.space_amplification_report().unwrap();
+    assert_eq!(report.logical_bytes, 0);
+    assert_eq!(report.ratio(), f64::INFINITY);
+
+    assert_eq!(SpaceAmplification { disk_bytes: 0, logical_bytes: 0 }.ratio(), 0.0);
+    assert_eq!(SpaceAmplification { disk_bytes: 300, logical_bytes: 200 }.ratio(), 1.5);
+}
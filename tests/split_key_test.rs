@@ -0,0 +1,191 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+
+use melange_db::*;
+
+const FANOUT: usize = 4;
+
+fn config(path: &Path, truncate_split_keys: bool) -> Config {
+    common::config(path).truncate_split_keys(truncate_split_keys)
+}
+
+/// 很长的公共前缀，只有最后一个字节不同
+fn long_shared_prefix() -> Vec<Vec<u8>> {
+    (0..=255_u8)
+        .step_by(3)
+        .map(|last| {
+            let mut key = b"tenant_a:".repeat(30);
+            key.push(last);
+            key
+        })
+        .collect()
+}
+
+/// 互为前缀的键
+fn prefixes_of_each_other() -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = (1..=120).map(|len| vec![b'a'; len]).collect();
+    keys.extend((1..=40).map(|len| {
+        let mut key = vec![b'a'; len];
+        key.push(b'b');
+        key
+    }));
+    keys
+}
+
+/// 由0x00和0xFF组成的键
+fn edge_bytes() -> Vec<Vec<u8>> {
+    let mut keys = vec![vec![]];
+    for len in 1..=24 {
+        keys.push(vec![0x00; len]);
+        keys.push(vec![0xFF; len]);
+        let mut key = vec![0xFF; len];
+        key.push(0x00);
+        keys.push(key);
+        let mut key = vec![0x00; len];
+        key.push(0xFF);
+        keys.push(key);
+    }
+    keys
+}
+
+fn collect(iter: impl Iterator<Item = io::Result<(InlineArray, InlineArray)>>) -> Vec<Vec<u8>> {
+    iter.map(|kv| kv.unwrap().0.to_vec()).collect()
+}
+
+/// 将树的内容与期望的键集合比较，包括以键为边界的范围和前缀查询
+fn verify(tree: &Tree<FANOUT>, expected: &BTreeSet<Vec<u8>>) {
+    let all: Vec<Vec<u8>> = expected.iter().cloned().collect();
+    assert_eq!(collect(tree.iter()), all);
+    assert_eq!(collect(tree.iter().rev()), all.iter().rev().cloned().collect::<Vec<_>>());
+
+    for key in expected {
+        assert_eq!(tree.get(key).unwrap().as_deref(), Some(&key[..]));
+    }
+
+    // 范围查询的开销与键的数量成正比，只检查一部分键
+    let step = (expected.len() / 60).max(1);
+    for key in expected.iter().step_by(step) {
+        let at_or_above: Vec<_> = expected.range(key.clone()..).cloned().collect();
+        let below: Vec<_> = expected.range(..key.clone()).cloned().collect();
+        let above: Vec<_> = expected
+            .range((Bound::Excluded(key.clone()), Bound::Unbounded))
+            .cloned()
+            .collect();
+        assert_eq!(collect(tree.range(&key[..]..)), at_or_above);
+        assert_eq!(collect(tree.range(..&key[..]).rev()), below.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!(
+            collect(tree.range::<&[u8], _>((Bound::Excluded(&key[..]), Bound::Unbounded))),
+            above
+        );
+
+        let with_prefix: Vec<_> =
+            expected.iter().filter(|k| k.starts_with(key)).cloned().collect();
+        assert_eq!(collect(tree.scan_prefix(key)), with_prefix);
+        assert_eq!(tree.contains_prefix(key).unwrap(), !with_prefix.is_empty());
+
+        assert_eq!(tree.ceiling(key).unwrap().map(|kv| kv.0.to_vec()), Some(key.clone()));
+        assert_eq!(tree.floor(key).unwrap().map(|kv| kv.0.to_vec()), Some(key.clone()));
+    }
+}
+
+fn run(keys: Vec<Vec<u8>>, truncate_split_keys: bool) -> usize {
+    let dir = tempfile::tempdir().unwrap();
+    let expected: BTreeSet<Vec<u8>> = keys.iter().cloned().collect();
+    let index_key_bytes;
+    {
+        let db: Db<FANOUT> = config(dir.path(), truncate_split_keys).open().unwrap();
+        let tree = db.open_tree("t").unwrap();
+        // 逆序插入使分裂发生在树的各个位置
+        for key in keys.iter().rev() {
+            tree.insert(key, key.clone()).unwrap();
+        }
+        assert!(tree.tree_stats().leaf_splits > 0);
+        verify(&tree, &expected);
+        index_key_bytes = tree.index_key_bytes();
+        db.flush().unwrap();
+    }
+
+    let db: Db<FANOUT> = config(dir.path(), truncate_split_keys).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    verify(&tree, &expected);
+    db.check().unwrap();
+    index_key_bytes
+}
+
+#[test]
+fn test_long_shared_prefix() {
+    let truncated = run(long_shared_prefix(), true);
+    let full = run(long_shared_prefix(), false);
+    // 分隔键不会比公共前缀加一个字节更短，但不需要包含整个键
+    assert!(truncated <= full, "缩短后 {} 字节，完整的键 {} 字节", truncated, full);
+}
+
+#[test]
+fn test_keys_that_are_prefixes_of_each_other() {
+    let truncated = run(prefixes_of_each_other(), true);
+    let full = run(prefixes_of_each_other(), false);
+    assert!(truncated <= full, "缩短后 {} 字节，完整的键 {} 字节", truncated, full);
+}
+
+#[test]
+fn test_edge_bytes() {
+    run(edge_bytes(), true);
+    run(edge_bytes(), false);
+}
+
+#[test]
+fn test_truncation_shrinks_index() {
+    // 长的复合键，不同的部分在前面
+    let keys: Vec<Vec<u8>> = (0..2_000_u32)
+        .map(|i| format!("tenant_a:{:06}:{}", i, "x".repeat(100)).into_bytes())
+        .collect();
+    let truncated = run(keys.clone(), true);
+    let full = run(keys, false);
+    assert!(truncated * 5 < full, "缩短后 {} 字节，完整的键 {} 字节", truncated, full);
+}
+
+#[test]
+fn test_recovery_with_mixed_separators() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = |i: u32| format!("tenant_a:{:06}:{}", i, "x".repeat(40)).into_bytes();
+    let mut expected = BTreeSet::new();
+
+    // 先以完整的键分裂，再以缩短的键分裂，同一个树中两种低键共存
+    for (round, truncate_split_keys) in [(0, false), (1, true), (2, false)] {
+        let db: Db<FANOUT> = config(dir.path(), truncate_split_keys).open().unwrap();
+        let tree = db.open_tree("t").unwrap();
+        verify(&tree, &expected);
+        for i in (round..600).step_by(3) {
+            tree.insert(key(i), key(i)).unwrap();
+            expected.insert(key(i));
+        }
+        verify(&tree, &expected);
+        db.flush().unwrap();
+    }
+
+    let db: Db<FANOUT> = config(dir.path(), true).open().unwrap();
+    let tree = db.open_tree("t").unwrap();
+    verify(&tree, &expected);
+    db.check().unwrap();
+}
+
+#[test]
+fn test_tree_option_overrides_config() {
+    let keys: Vec<Vec<u8>> = (0..500_u32)
+        .map(|i| format!("{:06}:{}", i, "y".repeat(60)).into_bytes())
+        .collect();
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    let truncated = db.open_tree("truncated").unwrap();
+    let full = db
+        .open_tree_with_options("full", TreeOptions::new().truncate_split_keys(false))
+        .unwrap();
+    for key in &keys {
+        truncated.insert(key, key.clone()).unwrap();
+        full.insert(key, key.clone()).unwrap();
+    }
+    assert!(truncated.index_key_bytes() * 5 < full.index_key_bytes());
+}
@@ -30,6 +30,7 @@ fn surface_book_2_performance_test() {
         max_interval_ms: 500,      // 500ms最大间隔，平衡延迟
         write_rate_threshold: 8000,  // 8K ops/sec阈值，稳定高负载检测
         accumulated_bytes_threshold: 8 * 1024 * 1024, // 8MB累积字节，最佳平衡点
+        target_write_amplification: None,
     };
 
     let db = config.open::<1024>().unwrap();
@@ -0,0 +1,242 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+use fault_injection::FAULT_INJECT_COUNTER;
+use melange_db::*;
+
+use common::{config, copy_dir};
+
+// 注入故障的计数器是全局的，这个文件中的测试依次运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const TREE_NAMES: [&str; 2] = ["a", "b"];
+
+type Contents = Vec<BTreeMap<Vec<u8>, Vec<u8>>>;
+
+// 释放树句柄会执行flush，写入期间一直持有它们
+fn open_trees(db: &Db<64>) -> Vec<Tree<64>> {
+    TREE_NAMES.iter().map(|name| db.open_tree(name).unwrap()).collect()
+}
+
+/// 向每个树写入第 `round` 轮的数据，每个树只有一个很小的叶子节点
+fn write_round(trees: &[Tree<64>], round: u8) {
+    for tree in trees {
+        for i in 0..8_u8 {
+            tree.insert([i], vec![round; 1 + i as usize]).unwrap();
+        }
+        tree.insert([100 + round], b"new".as_slice()).unwrap();
+    }
+}
+
+fn contents(trees: &[Tree<64>]) -> Contents {
+    trees
+        .iter()
+        .map(|tree| {
+            tree.iter()
+                .map(|kv| kv.map(|(k, v)| (k.to_vec(), v.to_vec())))
+                .collect::<io::Result<_>>()
+                .unwrap()
+        })
+        .collect()
+}
+
+/// 打开崩溃后的目录，检查恢复成功并且内容是两个持久状态之一
+fn recover(path: &Path, states: &[&Contents]) -> Contents {
+    let db: Db<64> = config(path).open().unwrap();
+    db.check().unwrap();
+    let recovered = contents(&open_trees(&db));
+    assert!(states.contains(&&recovered), "恢复出了不完整的状态: {:?}", recovered);
+    recovered
+}
+
+/// slab文件中在崩溃时被写入的槽，即内容与之前的持久状态不同的槽
+fn written_slots(durable: &[u8], crashed: &[u8], slot_size: usize) -> Vec<usize> {
+    (0..crashed.len().div_ceil(slot_size))
+        .filter(|slot| {
+            let range = slot * slot_size..((slot + 1) * slot_size).min(crashed.len());
+            durable.get(range.clone()) != Some(&crashed[range])
+        })
+        .collect()
+}
+
+/// 构造 `slot` 的写入在 `offset` 处中断时的slab文件：其他被写入的槽已经写完，
+/// 这个槽只写入了前 `offset` 个字节
+fn torn_slab(
+    durable: &[u8],
+    crashed: &[u8],
+    slot_size: usize,
+    written: &[usize],
+    slot: usize,
+    offset: usize,
+) -> Vec<u8> {
+    let start = slot * slot_size;
+    let others_end = written
+        .iter()
+        .filter(|other| **other != slot)
+        .map(|other| ((other + 1) * slot_size).min(crashed.len()))
+        .max()
+        .unwrap_or(0);
+    let mut torn = vec![0; durable.len().max(others_end).max(start + offset)];
+    torn[..durable.len()].copy_from_slice(durable);
+    for other in written.iter().filter(|other| **other != slot) {
+        let range = other * slot_size..((other + 1) * slot_size).min(crashed.len());
+        torn[range.clone()].copy_from_slice(&crashed[range]);
+    }
+    torn[start..start + offset].copy_from_slice(&crashed[start..start + offset]);
+    torn
+}
+
+#[test]
+fn test_torn_object_writes_are_never_referenced() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let durable_dir = tempfile::tempdir().unwrap();
+    let durable_state;
+    let new_state;
+    {
+        let db: Db<64> = config(dir.path()).open().unwrap();
+        let trees = open_trees(&db);
+        write_round(&trees, 1);
+        db.flush().unwrap();
+        durable_state = contents(&trees);
+        copy_dir(dir.path(), durable_dir.path());
+        write_round(&trees, 2);
+        new_state = contents(&trees);
+    }
+
+    let mut swept = vec![];
+    let mut images = 0;
+    for fault_at in 1.. {
+        let crashed = tempfile::tempdir().unwrap();
+        copy_dir(durable_dir.path(), crashed.path());
+        let db: Db<64> = config(crashed.path()).open().unwrap();
+        let trees = open_trees(&db);
+        write_round(&trees, 2);
+
+        FAULT_INJECT_COUNTER.store(fault_at, Ordering::Release);
+        let res = db.flush();
+        FAULT_INJECT_COUNTER.store(u64::MAX, Ordering::Release);
+        let completed = res.is_ok();
+        if completed {
+            drop(trees);
+            drop(db);
+        } else {
+            // 进程在这里崩溃，不再运行关闭时的flush
+            std::mem::forget(trees);
+            std::mem::forget(db);
+        }
+
+        let image = tempfile::tempdir().unwrap();
+        copy_dir(crashed.path(), image.path());
+        let recovered = recover(image.path(), &[&durable_state, &new_state]);
+        if completed {
+            assert_eq!(recovered, new_state);
+            break;
+        }
+        if recovered != durable_state {
+            // 元数据已经发布，对象的写入此前已经完成
+            continue;
+        }
+
+        // 在中断时已经写入的每个对象的每个字节处再次中断
+        let slabs = crashed.path().join("slabs");
+        for entry in fs::read_dir(&slabs).unwrap() {
+            let name = entry.unwrap().file_name();
+            let slot_size: usize = name.to_str().unwrap().parse().unwrap();
+            let durable = fs::read(durable_dir.path().join("slabs").join(&name)).unwrap_or_default();
+            let written_bytes = fs::read(slabs.join(&name)).unwrap();
+            let written = written_slots(&durable, &written_bytes, slot_size);
+            if written.is_empty() || swept.contains(&(slot_size, written.clone())) {
+                continue;
+            }
+
+            for slot in &written {
+                let slot_len = (written_bytes.len() - slot * slot_size).min(slot_size);
+                for offset in 0..slot_len {
+                    let image = tempfile::tempdir().unwrap();
+                    copy_dir(crashed.path(), image.path());
+                    let torn =
+                        torn_slab(&durable, &written_bytes, slot_size, &written, *slot, offset);
+                    fs::write(image.path().join("slabs").join(&name), torn).unwrap();
+                    assert_eq!(recover(image.path(), &[&durable_state]), durable_state);
+                    images += 1;
+                }
+            }
+            swept.push((slot_size, written));
+        }
+    }
+
+    // 至少有一次中断发生在对象写入之后、元数据发布之前
+    assert!(!swept.is_empty());
+    println!("从 {} 个中断的对象写入中恢复", images);
+}
+
+#[test]
+fn test_torn_trailing_object_is_quarantined() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<64> = config(dir.path()).open().unwrap();
+        write_round(&open_trees(&db), 1);
+        db.flush().unwrap();
+    }
+
+    // 被元数据引用的最后一个槽只有一部分在文件中，例如存储设备没有按顺序持久化写入。
+    // 树的叶子节点比名称映射的叶子节点大，位于最大的slab文件中
+    let slab = fs::read_dir(dir.path().join("slabs"))
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.metadata().unwrap().len() > 0)
+        .max_by_key(|entry| entry.file_name().to_str().unwrap().parse::<usize>().unwrap())
+        .unwrap();
+    let len = slab.metadata().unwrap().len();
+    let file = fs::OpenOptions::new().write(true).open(slab.path()).unwrap();
+    file.set_len(len - 1).unwrap();
+
+    // 数据库是正常关闭的，只有显式要求时打开才会校验
+    let err = config(dir.path()).verify_slots_on_open(true).open::<64>().unwrap_err();
+    assert!(CorruptionError::from_io_error(&err).is_some(), "{:?}", err);
+
+    let db: Db<64> = config(dir.path())
+        .verify_slots_on_open(true)
+        .checksum_mode(ChecksumMode::VerifyAndQuarantine)
+        .open()
+        .unwrap();
+    let report = db.recovery_report().unwrap();
+    assert_eq!(report.objects_quarantined, 1);
+}
+
+#[test]
+fn test_recovery_reports_unreferenced_trailing_bytes() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<64> = config(dir.path()).open().unwrap();
+        write_round(&open_trees(&db), 1);
+        db.flush().unwrap();
+    }
+    // 被释放的槽在文件变得足够稀疏之前不会被截断
+    let before = config(dir.path()).open::<64>().unwrap().recovery_report().unwrap();
+
+    // 未完成的flush在文件末尾留下的没有被引用的数据
+    let slab = fs::read_dir(dir.path().join("slabs"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| fs::metadata(path).unwrap().len() > 0)
+        .unwrap();
+    let mut data = fs::read(&slab).unwrap();
+    data.extend_from_slice(&[0xAB; 37]);
+    fs::write(&slab, data).unwrap();
+
+    let db: Db<64> = config(dir.path()).open().unwrap();
+    let report = db.recovery_report().unwrap();
+    assert_eq!(report.unreferenced_trailing_bytes, before.unreferenced_trailing_bytes + 37);
+    assert_eq!(report.objects_quarantined, 0);
+    db.check().unwrap();
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "tracing")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(feature = "for-internal-testing-only")]
+use std::time::Duration;
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+type Fields = HashMap<String, String>;
+
+/// 把所有事件的字段收集到内存中的订阅者
+struct Collector {
+    events: Arc<Mutex<Vec<Fields>>>,
+}
+
+struct FieldVisitor<'a>(&'a mut Fields);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// 订阅者是全局的，测试共享同一个事件列表
+fn events() -> Arc<Mutex<Vec<Fields>>> {
+    static EVENTS: OnceLock<Arc<Mutex<Vec<Fields>>>> = OnceLock::new();
+    EVENTS
+        .get_or_init(|| {
+            let events = Arc::new(Mutex::new(vec![]));
+            tracing::subscriber::set_global_default(Collector { events: events.clone() }).unwrap();
+            events
+        })
+        .clone()
+}
+
+fn find(events: &[Fields], op: &str, field: &str, value: &str) -> bool {
+    events.iter().any(|fields| {
+        fields.get("op").map(String::as_str) == Some(op)
+            && fields.get(field).map(String::as_str) == Some(value)
+    })
+}
+
+#[test]
+fn test_operations_emit_structured_events() {
+    let events = events();
+
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = HybridOperationsManager::new(db);
+
+    manager.insert(b"tracing_key", b"tracing_value").unwrap();
+    assert_eq!(&*manager.get_data(b"tracing_key").unwrap().unwrap(), b"tracing_value");
+    assert_eq!(manager.increment("tracing_counter".to_string(), 5).unwrap(), 5);
+
+    let events = events.lock().unwrap().clone();
+
+    // 直接操作带有键和值的长度
+    assert!(find(&events, "insert", "key_len", "11"), "{:?}", events);
+    assert!(find(&events, "insert", "value_len", "13"));
+    assert!(find(&events, "get", "key_len", "11"));
+
+    // 计数器操作带有计数器名称和结果
+    assert!(find(&events, "increment", "counter", "tracing_counter"));
+    assert!(find(&events, "increment", "delta", "5"));
+    assert!(find(&events, "increment", "value", "5"));
+
+    // 格式化的消息作为 `message` 字段保留
+    assert!(events.iter().any(|fields| {
+        fields.get("message").is_some_and(|message| message.contains("tracing_counter"))
+    }));
+}
+
+/// 暂停Worker，在另一个线程中递增 `counter_name`，使这次递增在往返中等待 `delay`
+#[cfg(feature = "for-internal-testing-only")]
+fn delayed_increment(manager: &HybridOperationsManager, counter_name: &str, delay: Duration) -> u64 {
+    let pause = manager.pause_workers_for_testing();
+    let delayed = manager.clone();
+    let counter_name = counter_name.to_string();
+    let increment = std::thread::spawn(move || delayed.increment(counter_name, 1).unwrap());
+    std::thread::sleep(delay);
+    drop(pause);
+    increment.join().unwrap()
+}
+
+#[cfg(feature = "for-internal-testing-only")]
+fn slow_op_events(events: &Mutex<Vec<Fields>>) -> Vec<Fields> {
+    events.lock().unwrap().iter().filter(|fields| fields.contains_key("slow_op")).cloned().collect()
+}
+
+#[test]
+#[cfg(feature = "for-internal-testing-only")]
+fn test_slow_operations_are_logged() {
+    let events = events();
+
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    let manager = HybridOperationsManager::new(db);
+    manager.set_slow_op_threshold(Duration::from_millis(100));
+
+    // 快的操作不被记录
+    for _ in 0..10 {
+        manager.increment("fast_counter".to_string(), 1).unwrap();
+        manager.insert(b"fast_key", b"value").unwrap();
+        manager.get_data(b"fast_key").unwrap();
+    }
+    assert!(slow_op_events(&events).is_empty());
+
+    assert_eq!(delayed_increment(&manager, "delayed_counter_name", Duration::from_millis(300)), 1);
+    let slow = slow_op_events(&events);
+    assert_eq!(slow.len(), 1, "{:?}", slow);
+    assert_eq!(slow[0]["slow_op"], "increment");
+    assert_eq!(slow[0]["key_len"], "20");
+    assert!(slow[0]["elapsed_us"].parse::<u64>().unwrap() >= 100_000);
+    assert!(slow[0].contains_key("request_id"));
+    assert!(slow[0]["message"].contains("increment"));
+
+    // 关闭后不再记录
+    manager.set_slow_op_threshold(Duration::MAX);
+    assert_eq!(delayed_increment(&manager, "delayed_counter_name", Duration::from_millis(200)), 2);
+    assert_eq!(slow_op_events(&events).len(), 1);
+}
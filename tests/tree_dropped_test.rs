@@ -0,0 +1,165 @@
+use std::io;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use melange_db::*;
+
+fn open(path: &std::path::Path) -> Db<16> {
+    Config::new().path(path).flush_every_ms(None).open().unwrap()
+}
+
+fn fill(tree: &Tree<16>, n: u32) {
+    for i in 0..n {
+        tree.insert(i.to_be_bytes(), i.to_be_bytes().to_vec()).unwrap();
+    }
+}
+
+fn assert_dropped(err: io::Error) {
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(TreeDropped::from_io_error(&err).is_some(), "{:?}", err);
+    assert!(matches!(MelangeError::from(err), MelangeError::TreeDropped(_)));
+}
+
+#[test]
+fn test_every_operation_on_a_dropped_tree_fails() {
+    let db: Db<16> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let tree = db.open_tree("dropped").unwrap();
+    fill(&tree, 200);
+    let clone = tree.clone();
+
+    assert!(db.drop_tree("dropped").unwrap());
+
+    assert_dropped(tree.get(1_u32.to_be_bytes()).unwrap_err());
+    assert_dropped(clone.insert(b"k", b"v".as_slice()).unwrap_err());
+    assert_dropped(tree.remove(1_u32.to_be_bytes()).unwrap_err());
+    assert_dropped(tree.get_with_metadata(1_u32.to_be_bytes()).unwrap_err());
+    assert_dropped(tree.compare_and_swap(b"k", None::<&[u8]>, Some(b"v".as_slice())).unwrap_err());
+    assert_dropped(tree.apply_batch(Batch::default()).unwrap_err());
+    assert_dropped(tree.clear().unwrap_err());
+    assert_dropped(tree.len().unwrap_err());
+    assert_dropped(tree.first().unwrap_err());
+    assert_dropped(tree.iter().next().unwrap().unwrap_err());
+    // 反向迭代不会因为空的索引而panic
+    assert_dropped(tree.iter().next_back().unwrap().unwrap_err());
+    assert_dropped(tree.range(10_u32.to_be_bytes()..).next_back().unwrap().unwrap_err());
+}
+
+#[test]
+fn test_stale_handle_does_not_reach_tree_reusing_its_id() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let stale = db.open_tree("t").unwrap();
+        fill(&stale, 100);
+        assert!(db.drop_tree("t").unwrap());
+
+        // 回收的集合ID被之后创建的树使用
+        let mut recreated = vec![];
+        for i in 0..20 {
+            db.flush().unwrap();
+            let tree = db.open_tree(format!("t_{}", i)).unwrap();
+            fill(&tree, 10);
+            recreated.push(tree);
+        }
+
+        assert_dropped(stale.insert(0_u32.to_be_bytes(), b"stale".as_slice()).unwrap_err());
+        assert_dropped(stale.remove(5_u32.to_be_bytes()).unwrap_err());
+        for tree in &recreated {
+            assert_eq!(tree.len().unwrap(), 10);
+            assert_eq!(tree.get(0_u32.to_be_bytes()).unwrap().unwrap(), 0_u32.to_be_bytes());
+        }
+        db.flush().unwrap();
+    }
+
+    let db = open(dir.path());
+    assert!(!db.contains_tree("t").unwrap());
+    for i in 0..20 {
+        assert_eq!(db.open_tree(format!("t_{}", i)).unwrap().len().unwrap(), 10);
+    }
+}
+
+#[test]
+fn test_drop_tree_during_iteration_in_another_thread() {
+    let db: Db<16> = Config::tmp().unwrap().flush_every_ms(Some(5)).open().unwrap();
+    let kept = db.open_tree("kept").unwrap();
+    fill(&kept, 500);
+    let tree = db.open_tree("dropped").unwrap();
+    fill(&tree, 2_000);
+    db.flush().unwrap();
+
+    let barrier = Arc::new(Barrier::new(3));
+
+    // 迭代到一半时树被删除
+    let iterate = |reverse: bool| {
+        let tree = tree.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            let mut iter = tree.iter();
+            let mut next = || if reverse { iter.next_back() } else { iter.next() };
+            for _ in 0..100 {
+                next().unwrap().unwrap();
+            }
+            barrier.wait();
+            barrier.wait();
+
+            assert_dropped(next().unwrap().unwrap_err());
+            assert!(next().is_none());
+            assert_dropped(tree.get(1_u32.to_be_bytes()).unwrap_err());
+        })
+    };
+    let forward = iterate(false);
+    let backward = iterate(true);
+
+    barrier.wait();
+    assert!(db.drop_tree("dropped").unwrap());
+    barrier.wait();
+
+    forward.join().unwrap();
+    backward.join().unwrap();
+
+    db.flush().unwrap();
+    assert_eq!(kept.len().unwrap(), 500);
+}
+
+#[test]
+fn test_drop_tree_during_concurrent_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let kept = db.open_tree("kept").unwrap();
+        let tree = db.open_tree("dropped").unwrap();
+        fill(&tree, 1_000);
+
+        let writers: Vec<_> = (0..3_u32)
+            .map(|w| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    // 每个写入要么在删除之前完成，要么返回 `TreeDropped`
+                    for i in 0.. {
+                        let key = (i * 3 + w) % 5_000;
+                        let res = match i % 3 {
+                            0 => tree.insert(key.to_be_bytes(), vec![0; 32]).map(drop),
+                            1 => tree.remove(key.to_be_bytes()).map(drop),
+                            _ => tree.iter().next().unwrap_or(Ok(Default::default())).map(drop),
+                        };
+                        if let Err(e) = res {
+                            assert_dropped(e);
+                            return;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        fill(&kept, 1_000);
+        assert!(db.drop_tree("dropped").unwrap());
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    let db = open(dir.path());
+    assert!(!db.contains_tree("dropped").unwrap());
+    assert_eq!(db.open_tree("kept").unwrap().len().unwrap(), 1_000);
+}
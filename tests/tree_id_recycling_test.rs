@@ -0,0 +1,99 @@
+use melange_db::*;
+
+fn open(path: &std::path::Path) -> Db<16> {
+    Config::new().path(path).flush_every_ms(None).open().unwrap()
+}
+
+fn fill(tree: &Tree<16>, n: u32) {
+    for i in 0..n {
+        tree.insert(format!("key_{:06}", i).as_bytes(), format!("value_{}", i).as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn test_create_drop_loop_recycles_collection_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open(dir.path());
+
+    for round in 0..500_u32 {
+        let name = format!("tree_{}", round);
+        let tree = db.open_tree(&name).unwrap();
+        fill(&tree, 50);
+        drop(tree);
+        assert!(db.drop_tree(&name).unwrap());
+        db.flush().unwrap();
+
+        // 回收的ID在epoch推进后才会被重新使用，最大ID保持有界
+        let max_id = db.max_collection_id().unwrap();
+        assert!(max_id < 100, "第 {} 轮之后最大集合ID增长到 {}", round, max_id);
+    }
+
+    assert!(!db.drop_tree("tree_0").unwrap());
+    assert!(!db.contains_tree("tree_499").unwrap());
+}
+
+#[test]
+fn test_reopen_after_drop_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let kept = db.open_tree("kept").unwrap();
+        fill(&kept, 2_000);
+        let dropped = db.open_tree("dropped").unwrap();
+        fill(&dropped, 2_000);
+        db.flush().unwrap();
+
+        assert!(db.drop_tree("dropped").unwrap());
+        db.flush().unwrap();
+    }
+
+    let db = open(dir.path());
+    assert!(db.was_recovered());
+    assert!(db.contains_tree("kept").unwrap());
+    assert!(!db.contains_tree("dropped").unwrap());
+    assert_eq!(db.open_tree("kept").unwrap().len().unwrap(), 2_000);
+
+    // 同名的新树是空的
+    let recreated = db.open_tree("dropped").unwrap();
+    assert!(recreated.is_empty().unwrap());
+}
+
+#[test]
+fn test_stale_handle_after_drop_tree() {
+    let db: Db<16> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let tree = db.open_tree("stale").unwrap();
+    fill(&tree, 100);
+
+    assert!(db.drop_tree("stale").unwrap());
+
+    // 已删除的树的句柄返回错误而不是访问被释放的叶子节点
+    let err = tree.get(b"key_000001").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(tree.insert(b"key", b"value").is_err());
+    assert!(tree.iter().next().unwrap().is_err());
+}
+
+#[test]
+fn test_dropped_ids_are_reused() {
+    let db: Db<16> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    for i in 0..10 {
+        fill(&db.open_tree(format!("tree_{}", i)).unwrap(), 10);
+    }
+    let max_before = db.max_collection_id().unwrap();
+
+    // 回收的ID按批次释放，足够多的删除之后新建的树使用回收的ID
+    for round in 0..20 {
+        for i in 0..10 {
+            assert!(db.drop_tree(format!("tree_{}", i)).unwrap());
+        }
+        db.flush().unwrap();
+        for i in 0..10 {
+            let tree = db.open_tree(format!("tree_{}", i)).unwrap();
+            assert!(tree.is_empty().unwrap(), "第 {} 轮重新创建的树不是空的", round);
+            fill(&tree, 10);
+        }
+    }
+
+    let max_after = db.max_collection_id().unwrap();
+    assert!(max_after < max_before + 40, "最大集合ID从 {} 增长到 {}", max_before, max_after);
+}
@@ -0,0 +1,185 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+
+fn open<const LEAF_FANOUT: usize>(dir: &tempfile::TempDir) -> Db<LEAF_FANOUT> {
+    Config::new().path(dir.path()).flush_every_ms(None).open().unwrap()
+}
+
+/// 向 `tree` 写入 `n` 个键，返回插入延迟的第95百分位数
+fn p95_insert_latency(tree: &Tree, n: u64) -> Duration {
+    let mut latencies: Vec<Duration> = (0..n)
+        .map(|i| {
+            let start = Instant::now();
+            tree.insert(i.to_be_bytes(), vec![1; 100]).unwrap();
+            start.elapsed()
+        })
+        .collect();
+    latencies.sort();
+    latencies[latencies.len() * 95 / 100]
+}
+
+#[test]
+fn test_quota_isolates_noisy_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = Config::new().path(dir.path()).open().unwrap();
+    let quiet = db.open_tree("quiet").unwrap();
+    let noisy = db.open_tree("noisy").unwrap();
+
+    // 基线：没有其他租户写入时的延迟
+    let baseline = p95_insert_latency(&quiet, 2_000);
+
+    noisy
+        .set_quota(TreeQuota {
+            max_write_ops_per_sec: Some(200),
+            max_dirty_bytes: Some(1024 * 1024),
+            ..TreeQuota::default()
+        })
+        .unwrap();
+
+    // 4个线程尽可能快地向受限的树写入较大的值
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers: Vec<_> = (0..4_u64)
+        .map(|t| {
+            let noisy = noisy.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut i = 0_u64;
+                while !stop.load(Ordering::Relaxed) {
+                    noisy.insert((t << 32 | i).to_be_bytes(), vec![2; 4096]).unwrap();
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+
+    // 写入者耗尽令牌桶、开始被限速之后才测量
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while noisy.quota_usage().writes_delayed == 0 {
+        assert!(Instant::now() < deadline, "{:?}", noisy.quota_usage());
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let with_noise = p95_insert_latency(&quiet, 2_000);
+    stop.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let usage = noisy.quota_usage();
+    assert!(
+        with_noise <= (baseline * 10).max(Duration::from_millis(2)),
+        "baseline {:?}, with noise {:?}, {:?}",
+        baseline,
+        with_noise,
+        usage
+    );
+    // 没有配额的树不受影响
+    assert_eq!(quiet.quota_usage().writes_delayed, 0);
+}
+
+#[test]
+fn test_reject_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<1024>(&dir);
+    let tree = db.open_tree("tenant").unwrap();
+    tree.set_quota(TreeQuota {
+        max_write_ops_per_sec: Some(10),
+        policy: QuotaPolicy::Reject,
+        ..TreeQuota::default()
+    })
+    .unwrap();
+
+    // 令牌桶的容量是一秒的写入量，批次中的每个写入各计一次
+    let mut batch = Batch::default();
+    for i in 0..8_u8 {
+        batch.insert([i], [i]);
+    }
+    tree.apply_batch(batch).unwrap();
+    tree.insert(b"a", b"1".as_slice()).unwrap();
+    tree.remove(b"a").unwrap();
+
+    let error = tree.insert(b"b", b"2".as_slice()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::QuotaExceeded);
+    let exceeded = QuotaExceeded::from_io_error(&error).unwrap();
+    assert_eq!(exceeded.max_write_ops_per_sec, 10);
+    assert!(exceeded.retry_after > Duration::ZERO);
+    // 被拒绝的写入没有修改树
+    assert!(tree.get(b"b").unwrap().is_none());
+
+    std::thread::sleep(exceeded.retry_after + Duration::from_millis(10));
+    tree.insert(b"b", b"2".as_slice()).unwrap();
+    assert_eq!(tree.quota_usage().writes_rejected, 1);
+
+    // 取消配额后不再限制，其他树从未受限
+    tree.set_quota(TreeQuota::default()).unwrap();
+    for i in 0..100_u8 {
+        tree.insert([i], [i]).unwrap();
+    }
+    assert_eq!(db.open_tree("other").unwrap().quota_usage().quota, TreeQuota::default());
+}
+
+#[test]
+fn test_dirty_bytes_limit_flushes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<1024>(&dir);
+    let tree = db.open_tree("tenant").unwrap();
+    let max_dirty_bytes = 64 * 1024;
+    tree.set_quota(TreeQuota { max_dirty_bytes: Some(max_dirty_bytes), ..TreeQuota::default() })
+        .unwrap();
+
+    // 写入约1MB，没有后台flusher时只有配额会触发flush
+    for i in 0..1_000_u64 {
+        tree.insert(i.to_be_bytes(), vec![0; 1024]).unwrap();
+        assert!(tree.quota_usage().dirty_bytes <= max_dirty_bytes + 1024 + 8);
+    }
+    let usage = tree.quota_usage();
+    assert!(usage.dirty_flushes >= 10, "{:?}", usage);
+}
+
+#[test]
+fn test_cache_bytes_limit_pages_out_own_leaves() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<64>(&dir);
+    let other = db.open_tree("other").unwrap();
+    let tree = db.open_tree("tenant").unwrap();
+    for i in 0..2_000_u64 {
+        other.insert(i.to_be_bytes(), vec![1; 100]).unwrap();
+    }
+
+    let max_cache_bytes = 256 * 1024;
+    tree.set_quota(TreeQuota { max_cache_bytes: Some(max_cache_bytes), ..TreeQuota::default() })
+        .unwrap();
+    for i in 0..20_000_u64 {
+        tree.insert(i.to_be_bytes(), vec![2; 100]).unwrap();
+    }
+    // 尚未flush的叶子节点在flush之后才被淘汰
+    db.flush().unwrap();
+    for i in 0..64_u64 {
+        tree.insert(i.to_be_bytes(), vec![3; 100]).unwrap();
+    }
+
+    let usage = tree.quota_usage();
+    assert!(usage.leaves_paged_out > 0, "{:?}", usage);
+    assert!(usage.resident_bytes <= 2 * max_cache_bytes, "{:?}", usage);
+    // 另一个树的叶子节点都还在缓存中
+    assert!(other.quota_usage().resident_bytes >= 2_000 * 108, "{:?}", other.quota_usage());
+
+    for i in 0..20_000_u64 {
+        let expected = if i < 64 { 3 } else { 2 };
+        assert_eq!(&*tree.get(i.to_be_bytes()).unwrap().unwrap(), &[expected; 100][..]);
+    }
+}
+
+#[test]
+fn test_invalid_quota() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = open::<1024>(&dir);
+    let error = db
+        .set_quota(TreeQuota { max_cache_bytes: Some(0), ..TreeQuota::default() })
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(db.quota_usage().quota, TreeQuota::default());
+}
@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use melange_db::*;
+use rand::seq::SliceRandom;
+use rand::{SeedableRng, rngs::StdRng};
+
+const N: u32 = 100_000;
+
+fn open() -> Db<1024> {
+    Config::tmp().unwrap().flush_every_ms(None).open().unwrap()
+}
+
+fn keys(shuffled: bool) -> Vec<[u8; 4]> {
+    let mut keys: Vec<[u8; 4]> = (0..N).map(|i| i.to_be_bytes()).collect();
+    if shuffled {
+        keys.shuffle(&mut StdRng::seed_from_u64(7));
+    }
+    keys
+}
+
+fn naive(keys: &[[u8; 4]]) -> Duration {
+    let db = open();
+    let start = Instant::now();
+    for key in keys {
+        db.insert(key, key.as_slice()).unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(db.len().unwrap(), N as usize);
+    elapsed
+}
+
+fn buffered(keys: &[[u8; 4]]) -> Duration {
+    let db = open();
+    let start = Instant::now();
+    let mut writer = db.writer();
+    for key in keys {
+        writer.insert(key, key.as_slice()).unwrap();
+    }
+    writer.flush().unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(db.len().unwrap(), N as usize);
+    elapsed
+}
+
+fn assert_faster(shuffled: bool) {
+    let keys = keys(shuffled);
+    // 取多次中最快的一次，减少其他测试的干扰
+    let naive = (0..3).map(|_| naive(&keys)).min().unwrap();
+    let buffered = (0..3).map(|_| buffered(&keys)).min().unwrap();
+    println!("shuffled: {}, naive: {:?}, TreeWriter: {:?}", shuffled, naive, buffered);
+    assert!(buffered * 3 < naive * 2, "naive {:?}, TreeWriter {:?}", naive, buffered);
+}
+
+#[test]
+fn test_writer_is_faster_for_sorted_keys() {
+    assert_faster(false);
+}
+
+#[test]
+fn test_writer_is_faster_for_random_keys() {
+    assert_faster(true);
+}
+
+#[test]
+fn test_read_your_writes() {
+    let db = open();
+    db.insert(b"existing", b"old".as_slice()).unwrap();
+    db.insert(b"removed", b"v".as_slice()).unwrap();
+
+    let mut writer = db.writer();
+    writer.insert(b"new", b"1".as_slice()).unwrap();
+    writer.insert(b"existing", b"new".as_slice()).unwrap();
+    writer.remove(b"removed").unwrap();
+    assert_eq!(writer.buffered_writes(), 3);
+
+    // 暂存的写入只对这个句柄可见
+    assert_eq!(writer.get(b"new").unwrap().unwrap(), b"1");
+    assert_eq!(writer.get(b"existing").unwrap().unwrap(), b"new");
+    assert_eq!(writer.get(b"removed").unwrap(), None);
+    assert!(!writer.contains_key(b"removed").unwrap());
+    assert_eq!(db.get(b"new").unwrap(), None);
+    assert_eq!(db.get(b"existing").unwrap().unwrap(), b"old");
+    assert!(db.contains_key(b"removed").unwrap());
+
+    writer.flush().unwrap();
+    assert_eq!(writer.buffered_writes(), 0);
+    assert_eq!(db.get(b"new").unwrap().unwrap(), b"1");
+    assert_eq!(db.get(b"existing").unwrap().unwrap(), b"new");
+    assert_eq!(db.get(b"removed").unwrap(), None);
+}
+
+#[test]
+fn test_thresholds_apply_automatically() {
+    let db = open();
+    let tree = db.open_tree("t").unwrap();
+
+    let options = TreeWriterOptions { max_buffered_writes: 100, ..TreeWriterOptions::default() };
+    let mut writer = tree.writer_with(options);
+    for i in 0..250_u32 {
+        writer.insert(i.to_be_bytes(), vec![0; 10]).unwrap();
+    }
+    assert_eq!(tree.len().unwrap(), 200);
+    assert_eq!(writer.buffered_writes(), 50);
+
+    let options = TreeWriterOptions { max_buffered_bytes: 1_000, ..TreeWriterOptions::default() };
+    let mut writer = tree.writer_with(options);
+    for i in 0..10_u32 {
+        writer.insert(format!("large_{}", i), vec![0; 300]).unwrap();
+    }
+    // 每4次写入超过1000字节
+    assert_eq!(tree.scan_prefix(b"large_").count(), 8);
+    assert_eq!(writer.buffered_writes(), 2);
+}
+
+#[test]
+fn test_drop_applies_or_discards() {
+    let db = open();
+
+    let mut writer = db.writer();
+    writer.insert(b"applied", b"v".as_slice()).unwrap();
+    drop(writer);
+    assert!(db.contains_key(b"applied").unwrap());
+
+    let options = TreeWriterOptions { apply_on_drop: false, ..TreeWriterOptions::default() };
+    let mut writer = db.writer_with(options);
+    writer.insert(b"discarded", b"v".as_slice()).unwrap();
+    drop(writer);
+    assert!(!db.contains_key(b"discarded").unwrap());
+}
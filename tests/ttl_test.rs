@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use melange_db::ttl::{ManualClock, TtlTree};
+use melange_db::*;
+
+const TTL_KEYS: u64 = 100_000;
+const PLAIN_KEYS: u64 = 1_000;
+
+fn ttl_key(i: u64) -> [u8; 9] {
+    let mut key = [b't'; 9];
+    key[1..].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn plain_key(i: u64) -> [u8; 9] {
+    let mut key = [b'p'; 9];
+    key[1..].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+/// 第 `i` 个键在 `i % 100 + 1` 秒后过期
+fn ttl_of(i: u64) -> Duration {
+    Duration::from_secs(i % 100 + 1)
+}
+
+fn open(db: &Db<1024>, clock: &Arc<ManualClock>) -> TtlTree<1024> {
+    TtlTree::open(db, "sessions").unwrap().with_clock(clock.clone())
+}
+
+#[test]
+fn test_purge_shrinks_both_trees_consistently() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let tree = open(&db, &clock);
+
+    for i in 0..TTL_KEYS {
+        tree.insert_with_ttl(ttl_key(i), i.to_le_bytes().as_slice(), ttl_of(i)).unwrap();
+    }
+    // 一半没有过期时间的键直接写入数据树，另一半通过包装写入
+    for i in 0..PLAIN_KEYS {
+        if i % 2 == 0 {
+            tree.data().insert(plain_key(i), i.to_le_bytes().as_slice()).unwrap();
+        } else {
+            tree.insert(plain_key(i), i.to_le_bytes().as_slice()).unwrap();
+        }
+    }
+    assert_eq!(tree.data().len().unwrap() as u64, TTL_KEYS + PLAIN_KEYS);
+    assert_eq!(tree.index().len().unwrap() as u64, 2 * TTL_KEYS);
+
+    // 过期时间不晚于50秒的键有一半
+    clock.advance(Duration::from_secs(50));
+    let expired = TTL_KEYS / 2;
+
+    assert_eq!(tree.purge_expired(10_000).unwrap(), 10_000);
+    assert_eq!(tree.data().len().unwrap() as u64, TTL_KEYS + PLAIN_KEYS - 10_000);
+    assert_eq!(tree.index().len().unwrap() as u64, 2 * (TTL_KEYS - 10_000));
+
+    assert_eq!(tree.purge_expired(3_333).unwrap(), 3_333);
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap() as u64, expired - 13_333);
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap(), 0);
+    assert_eq!(tree.data().len().unwrap() as u64, TTL_KEYS + PLAIN_KEYS - expired);
+    assert_eq!(tree.index().len().unwrap() as u64, 2 * (TTL_KEYS - expired));
+
+    for i in (0..TTL_KEYS).step_by(7) {
+        let value = tree.data().get(ttl_key(i)).unwrap();
+        assert_eq!(value.is_none(), ttl_of(i) <= Duration::from_secs(50), "键 {}", i);
+    }
+    for i in 0..PLAIN_KEYS {
+        assert_eq!(&*tree.get(plain_key(i)).unwrap().unwrap(), &i.to_le_bytes());
+    }
+
+    // 所有键都过期后只剩下没有过期时间的键，索引树为空
+    clock.advance(Duration::from_secs(51));
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap() as u64, TTL_KEYS - expired);
+    assert_eq!(tree.data().len().unwrap() as u64, PLAIN_KEYS);
+    assert!(tree.index().is_empty().unwrap());
+}
+
+#[test]
+fn test_ttl_changes_replace_the_index_entry() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let clock = Arc::new(ManualClock::new(0));
+    let tree = open(&db, &clock);
+
+    tree.insert_with_ttl(b"a", b"1".as_slice(), Duration::from_secs(10)).unwrap();
+    tree.insert_with_ttl(b"b", b"2".as_slice(), Duration::from_secs(10)).unwrap();
+    tree.insert_with_ttl(b"c", b"3".as_slice(), Duration::from_secs(10)).unwrap();
+    tree.insert_with_ttl(b"d", b"4".as_slice(), Duration::from_secs(10)).unwrap();
+
+    // 延长a的存活时间，b改为没有过期时间，删除c
+    assert!(tree.set_ttl(b"a", Duration::from_secs(100)).unwrap());
+    assert!(!tree.set_ttl(b"missing", Duration::from_secs(100)).unwrap());
+    tree.insert(b"b", b"22".as_slice()).unwrap();
+    assert_eq!(&*tree.remove(b"c").unwrap().unwrap(), b"3");
+    assert_eq!(tree.time_to_live(b"a").unwrap(), Some(Duration::from_secs(100)));
+    assert_eq!(tree.time_to_live(b"b").unwrap(), None);
+    assert_eq!(tree.index().len().unwrap(), 4);
+
+    // 过期但还没有清除的键不可见
+    clock.advance(Duration::from_secs(10));
+    assert!(tree.get(b"d").unwrap().is_none());
+    assert!(tree.data().get(b"d").unwrap().is_some());
+
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap(), 1);
+    assert_eq!(&*tree.get(b"a").unwrap().unwrap(), b"1");
+    assert_eq!(&*tree.get(b"b").unwrap().unwrap(), b"22");
+    assert!(tree.data().get(b"d").unwrap().is_none());
+    assert_eq!(tree.index().len().unwrap(), 2);
+
+    // 剩下的键过期后索引树为空
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap(), 1);
+    assert!(tree.get(b"a").unwrap().is_none());
+    assert!(tree.index().is_empty().unwrap());
+}
+
+#[test]
+fn test_direct_writes_keep_the_expiry() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let clock = Arc::new(ManualClock::new(0));
+    let tree = open(&db, &clock);
+
+    tree.insert_with_ttl(b"direct", b"1".as_slice(), Duration::from_secs(10)).unwrap();
+    tree.insert_with_ttl(b"wrapped", b"1".as_slice(), Duration::from_secs(10)).unwrap();
+
+    // 直接写入数据树不清除过期时间，只有通过 `TtlTree` 写入才会
+    tree.data().insert(b"direct", b"2".as_slice()).unwrap();
+    tree.insert(b"wrapped", b"2".as_slice()).unwrap();
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(tree.purge_expired(usize::MAX).unwrap(), 1);
+    assert!(tree.data().get(b"direct").unwrap().is_none());
+    assert_eq!(&*tree.get(b"wrapped").unwrap().unwrap(), b"2");
+}
@@ -0,0 +1,184 @@
+use std::io::ErrorKind;
+
+use melange_db::*;
+
+// 较小的叶子节点让范围跨越多个叶子
+const FANOUT: usize = 8;
+
+fn key(prefix: &str, i: u32) -> Vec<u8> {
+    format!("{}/{:04}", prefix, i).into_bytes()
+}
+
+fn count(value: &[u8]) -> u64 {
+    u64::from_be_bytes(value.try_into().unwrap())
+}
+
+fn increment(by: u64) -> impl FnMut(&[u8], Option<&[u8]>) -> Option<Vec<u8>> {
+    move |_key, value| Some((count(value.unwrap()) + by).to_be_bytes().to_vec())
+}
+
+fn populate(tree: &Tree<FANOUT>) {
+    for prefix in ["inu", "inv", "inw"] {
+        for i in 0..200 {
+            tree.insert(key(prefix, i), u64::from(i).to_be_bytes()).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_update_range_increments_prefix() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    populate(&db);
+
+    let mut visited = vec![];
+    let changed = db
+        .update_range(b"inv/".as_slice()..b"inv0".as_slice(), |key, value| {
+            visited.push(key.to_vec());
+            Some((count(value.unwrap()) + 5).to_be_bytes().to_vec())
+        })
+        .unwrap();
+    assert_eq!(changed, 200);
+    // 每个条目按键顺序恰好访问一次
+    assert_eq!(visited, (0..200).map(|i| key("inv", i)).collect::<Vec<_>>());
+
+    for i in 0..200 {
+        assert_eq!(count(&db.get(key("inv", i)).unwrap().unwrap()), u64::from(i) + 5);
+        // 范围之外的条目保持不变
+        assert_eq!(count(&db.get(key("inu", i)).unwrap().unwrap()), u64::from(i));
+        assert_eq!(count(&db.get(key("inw", i)).unwrap().unwrap()), u64::from(i));
+    }
+    assert_eq!(db.len().unwrap(), 600);
+}
+
+#[test]
+fn test_update_range_bounds_and_removal() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    populate(&db);
+
+    // 包含上界，返回 `None` 删除条目，返回原值不计入修改数
+    let changed = db
+        .update_range(key("inv", 10)..=key("inv", 19), |_key, value| {
+            (count(value.unwrap()) % 2 == 1).then(|| value.unwrap().to_vec())
+        })
+        .unwrap();
+    assert_eq!(changed, 5);
+    for i in 10..20 {
+        assert_eq!(db.contains_key(key("inv", i)).unwrap(), i % 2 == 1, "{}", i);
+    }
+    assert_eq!(db.len().unwrap(), 595);
+
+    // 不包含上界
+    assert_eq!(db.update_range(key("inw", 0)..key("inw", 3), increment(1)).unwrap(), 3);
+    assert_eq!(count(&db.get(key("inw", 3)).unwrap().unwrap()), 3);
+
+    // 空范围不调用闭包
+    let changed = db
+        .update_range(b"x".as_slice()..b"y".as_slice(), |_key, _value| -> Option<Vec<u8>> {
+            unreachable!()
+        })
+        .unwrap();
+    assert_eq!(changed, 0);
+
+    // 无界范围覆盖整个树
+    assert_eq!(db.update_range::<&[u8], _, _, _>(.., increment(1)).unwrap(), 595);
+}
+
+#[test]
+fn test_update_range_is_atomic_with_concurrent_writes() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    let tree = db.open_tree("counts").unwrap();
+    for i in 0..100 {
+        tree.insert(key("c", i), 0_u64.to_be_bytes()).unwrap();
+    }
+
+    const RANGE_UPDATES: u64 = 50;
+    const SINGLE_UPDATES: u32 = 20;
+
+    let range_updater = {
+        let tree = tree.clone();
+        std::thread::spawn(move || {
+            for _ in 0..RANGE_UPDATES {
+                assert_eq!(tree.update_range(b"c/".as_slice().., increment(1000)).unwrap(), 100);
+            }
+        })
+    };
+    let single_updaters: Vec<_> = (0..4)
+        .map(|t| {
+            let tree = tree.clone();
+            std::thread::spawn(move || {
+                for _ in 0..SINGLE_UPDATES {
+                    for i in (t..100).step_by(4) {
+                        tree.update_and_fetch(key("c", i), |value| {
+                            Some((count(value.unwrap()) + 1).to_be_bytes().to_vec())
+                        })
+                        .unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    // 读取者看到的每一次范围更新要么完全生效，要么完全没有生效
+    let reader = {
+        let tree = tree.clone();
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                let first = count(&tree.get(key("c", 0)).unwrap().unwrap()) / 1000;
+                let last = count(&tree.get(key("c", 99)).unwrap().unwrap()) / 1000;
+                // 后读取的键不会落后于先读取的键
+                assert!(last >= first, "first {} last {}", first, last);
+            }
+        })
+    };
+
+    range_updater.join().unwrap();
+    for updater in single_updaters {
+        updater.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    // 没有丢失任何一次更新
+    for i in 0..100 {
+        let value = count(&tree.get(key("c", i)).unwrap().unwrap());
+        assert_eq!(value, RANGE_UPDATES * 1000 + u64::from(SINGLE_UPDATES), "{}", i);
+    }
+}
+
+#[test]
+fn test_update_range_persists() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::new().path(dir.path()).flush_every_ms(None);
+    {
+        let db: Db<FANOUT> = config.open().unwrap();
+        populate(&db);
+        db.update_range(b"inv/".as_slice()..b"inv0".as_slice(), increment(7)).unwrap();
+        db.flush().unwrap();
+    }
+
+    let db: Db<FANOUT> = config.open().unwrap();
+    db.check().unwrap();
+    for i in 0..200 {
+        assert_eq!(count(&db.get(key("inv", i)).unwrap().unwrap()), u64::from(i) + 7);
+        assert_eq!(count(&db.get(key("inw", i)).unwrap().unwrap()), u64::from(i));
+    }
+}
+
+#[test]
+fn test_update_range_on_write_once_tree() {
+    let db: Db<FANOUT> = Config::tmp().unwrap().open().unwrap();
+    let audit = db.open_tree_with_options("audit", TreeOptions::new().write_once(true)).unwrap();
+    for i in 0..20 {
+        audit.insert(key("e", i), u64::from(i).to_be_bytes()).unwrap();
+    }
+
+    let err = audit.update_range(b"e/".as_slice().., increment(1)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    let err = audit.update_range(b"e/".as_slice().., |_key, _value| None as Option<Vec<u8>>).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+    // 不修改任何条目的更新是允许的
+    assert_eq!(audit.update_range(b"e/".as_slice().., |_key, value| value.map(<[u8]>::to_vec)).unwrap(), 0);
+    for i in 0..20 {
+        assert_eq!(count(&audit.get(key("e", i)).unwrap().unwrap()), u64::from(i));
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use melange_db::*;
+
+fn config(path: &std::path::Path, algorithm: CompressionAlgorithm) -> Config {
+    common::config(path).compression_algorithm(algorithm).value_compression_threshold(1024)
+}
+
+fn large_value() -> Vec<u8> {
+    let mut value = Vec::with_capacity(10 * 1024);
+    let mut i = 0;
+    while value.len() < 10 * 1024 {
+        value.extend_from_slice(format!(r#"{{"id":{},"status":"active"}},"#, i).as_bytes());
+        i += 1;
+    }
+    value.truncate(10 * 1024);
+    value
+}
+
+const SMALL_VALUE: &[u8] = b"0123456789";
+
+fn write_and_verify(algorithm: CompressionAlgorithm) {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<1024> = config(dir.path(), algorithm).open().unwrap();
+        db.insert(b"small", SMALL_VALUE).unwrap();
+        db.insert(b"large", large_value()).unwrap();
+        db.flush().unwrap();
+
+        // 小值低于阈值，原样存储；大值单独压缩
+        let stats = db.stats().cache;
+        assert_eq!(stats.values_compressed, 1, "{:?}", stats);
+        assert_eq!(stats.values_stored_uncompressed, 1, "{:?}", stats);
+    }
+
+    // 重新打开后从磁盘读取两种存储方式的值
+    let db: Db<1024> = config(dir.path(), algorithm).open().unwrap();
+    assert_eq!(&*db.get(b"small").unwrap().unwrap(), SMALL_VALUE);
+    assert_eq!(&*db.get(b"large").unwrap().unwrap(), large_value().as_slice());
+}
+
+#[test]
+fn test_small_values_stored_uncompressed_under_zstd() {
+    write_and_verify(CompressionAlgorithm::Zstd);
+}
+
+#[test]
+fn test_small_values_stored_uncompressed_under_lz4() {
+    write_and_verify(CompressionAlgorithm::Lz4);
+}
+
+#[test]
+fn test_incompressible_value_stored_uncompressed() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path(), CompressionAlgorithm::Zstd).open().unwrap();
+
+    // 伪随机字节无法压缩，即使超过阈值也原样存储
+    let mut state = 0x2545_f491_u32;
+    let random: Vec<u8> = (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    db.insert(b"random", random.clone()).unwrap();
+    db.flush().unwrap();
+
+    let stats = db.stats().cache;
+    assert_eq!(stats.values_compressed, 0);
+    assert_eq!(stats.values_stored_uncompressed, 1);
+    assert_eq!(&*db.get(b"random").unwrap().unwrap(), random.as_slice());
+}
+
+#[test]
+fn test_switching_compression_algorithm_keeps_data_readable() {
+    let dir = tempfile::tempdir().unwrap();
+    let algorithms = [
+        CompressionAlgorithm::None,
+        CompressionAlgorithm::Zstd,
+        CompressionAlgorithm::Lz4,
+        CompressionAlgorithm::None,
+    ];
+
+    // 每次使用不同的压缩算法写入一个新的树，之前写入的树保持原来的存储格式
+    for (generation, algorithm) in algorithms.iter().enumerate() {
+        let db: Db<1024> = config(dir.path(), *algorithm).open().unwrap();
+        let tree = db.open_tree(format!("tree_{}", generation)).unwrap();
+        tree.insert(b"small", SMALL_VALUE).unwrap();
+        tree.insert(b"large", large_value()).unwrap();
+        db.flush().unwrap();
+
+        for previous in 0..=generation {
+            let tree = db.open_tree(format!("tree_{}", previous)).unwrap();
+            assert_eq!(&*tree.get(b"small").unwrap().unwrap(), SMALL_VALUE);
+            assert_eq!(&*tree.get(b"large").unwrap().unwrap(), large_value().as_slice());
+        }
+    }
+
+    // 不配置压缩算法时不使用按值压缩的格式
+    let db: Db<1024> = config(dir.path(), CompressionAlgorithm::None).open().unwrap();
+    db.insert(b"large", large_value()).unwrap();
+    db.flush().unwrap();
+    let stats = db.stats().cache;
+    assert_eq!(stats.values_compressed + stats.values_stored_uncompressed, 0);
+}
+
+#[test]
+fn test_value_compressed_leaves_are_compressed_once() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<1024> = config(dir.path(), CompressionAlgorithm::Zstd).open().unwrap();
+        db.insert(b"small", SMALL_VALUE).unwrap();
+        db.insert(b"large", large_value()).unwrap();
+        db.flush().unwrap();
+
+        // 大值已经单独压缩，叶子节点不再经过叶子节点级别的zstd压缩
+        let leaves_written: u64 = db.compression_stats().iter().map(|s| s.leaves_written).sum();
+        assert_eq!(leaves_written, 0);
+        assert_eq!(db.stats().cache.values_compressed, 1);
+    }
+
+    let db: Db<1024> = config(dir.path(), CompressionAlgorithm::Zstd).open().unwrap();
+    assert_eq!(&*db.get(b"small").unwrap().unwrap(), SMALL_VALUE);
+    assert_eq!(&*db.get(b"large").unwrap().unwrap(), large_value().as_slice());
+}
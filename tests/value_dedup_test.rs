@@ -0,0 +1,138 @@
+use melange_db::*;
+
+const N: u32 = 100_000;
+const DISTINCT: u32 = 100;
+const VALUE_SIZE: usize = 1024;
+
+fn key(i: u32) -> Vec<u8> {
+    format!("key_{:08}", i).into_bytes()
+}
+
+/// 不可压缩的1KB值，共 `DISTINCT` 种
+fn distinct_value(n: u32) -> Vec<u8> {
+    let mut state = 0x9E37_79B9_7F4A_7C15_u64 ^ (n as u64 + 1);
+    (0..VALUE_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    distinct_value(i.wrapping_mul(2_654_435_761) % DISTINCT)
+}
+
+fn open(path: &std::path::Path) -> Db<1024> {
+    Config::new().path(path).flush_every_ms(None).open().unwrap()
+}
+
+fn dedup() -> TreeOptions {
+    TreeOptions::new().dedup_values(true)
+}
+
+fn populate(tree: &Tree<1024>) {
+    for i in 0..N {
+        tree.insert(key(i), value(i)).unwrap();
+    }
+    tree.flush().unwrap();
+}
+
+fn verify(tree: &Tree<1024>, expected: impl Fn(u32) -> Vec<u8>) {
+    assert_eq!(tree.len().unwrap(), N as usize);
+    for i in 0..N {
+        let actual = tree.get(key(i)).unwrap().unwrap();
+        assert!(*actual == *expected(i), "键 {} 的值不正确", i);
+    }
+}
+
+#[test]
+fn test_dedup_stores_each_distinct_value_once_per_leaf() {
+    let dir = tempfile::tempdir().unwrap();
+    let (dedup_bytes, leaves) = {
+        let db = open(dir.path());
+        let tree = db.open_tree_with_options("statuses", dedup()).unwrap();
+        populate(&tree);
+        (tree.estimated_disk_bytes(), tree.tree_stats().leaf_splits + 1)
+    };
+
+    let total_bytes = N as u64 * VALUE_SIZE as u64;
+    // 每个叶子节点中最多有 `DISTINCT` 个不同的值
+    let unique_bytes = leaves * DISTINCT as u64 * VALUE_SIZE as u64;
+    println!(
+        "总字节数: {}, 按叶子节点计算的不同值字节数: {}, 去重后: {}",
+        total_bytes, unique_bytes, dedup_bytes
+    );
+    assert!(dedup_bytes <= unique_bytes * 3 / 2, "去重后的大小 {} 远大于不同值的大小 {}", dedup_bytes, unique_bytes);
+    assert!(dedup_bytes * 4 < total_bytes);
+
+    // 重新打开后所有值都能正确读取，不启用去重也能读取去重写入的叶子节点
+    let db = open(dir.path());
+    verify(&db.open_tree("statuses").unwrap(), value);
+}
+
+#[test]
+fn test_modifying_one_of_several_sharing_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let replacement = distinct_value(DISTINCT + 1);
+    let expected = |i: u32| if i.is_multiple_of(1_000) { distinct_value(DISTINCT + 1) } else { value(i) };
+
+    {
+        let db = open(dir.path());
+        let tree = db.open_tree_with_options("statuses", dedup()).unwrap();
+        populate(&tree);
+
+        // 修改共享同一个值的多个条目中的一个，其余条目不受影响
+        for i in (0..N).step_by(1_000) {
+            let result = tree
+                .compare_and_swap(key(i), Some(value(i)), Some(replacement.clone()))
+                .unwrap();
+            assert!(result.is_ok());
+        }
+        verify(&tree, expected);
+        tree.flush().unwrap();
+        verify(&tree, expected);
+
+        // 之前共享的值仍然被引用时比较并交换依然使用完整的值
+        let result = tree
+            .compare_and_swap(key(1), Some(replacement.clone()), Some(value(2)))
+            .unwrap();
+        assert!(result.is_err());
+    }
+
+    let db = open(dir.path());
+    let tree = db.open_tree_with_options("statuses", dedup()).unwrap();
+    verify(&tree, expected);
+
+    // 删除一部分共享值的条目后再次写入并重新打开
+    for i in (1..N).step_by(3) {
+        tree.remove(key(i)).unwrap();
+    }
+    for i in (1..N).step_by(3) {
+        tree.insert(key(i), expected(i)).unwrap();
+    }
+    tree.flush().unwrap();
+    drop(tree);
+    drop(db);
+
+    let db = open(dir.path());
+    verify(&db.open_tree("statuses").unwrap(), expected);
+}
+
+#[test]
+fn test_small_values_are_not_deduplicated() {
+    let db: Db<1024> = Config::tmp().unwrap().flush_every_ms(None).open().unwrap();
+    let options = dedup().dedup_min_value_size(VALUE_SIZE + 1);
+    let tree = db.open_tree_with_options("statuses", options).unwrap();
+    populate(&tree);
+
+    // 所有值都小于最小去重大小，与不去重时的大小相同
+    let other = db.open_tree("plain").unwrap();
+    populate(&other);
+    let (a, b) = (tree.estimated_disk_bytes(), other.estimated_disk_bytes());
+    assert!(a.abs_diff(b) * 20 < b, "{} {}", a, b);
+
+    verify(&tree, value);
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use melange_db::*;
+
+fn config(path: &Path, retained_flush_epochs: usize) -> Config {
+    common::config(path).retained_flush_epochs(retained_flush_epochs)
+}
+
+fn value(db: &Tree, key: &str) -> Option<Vec<u8>> {
+    db.get(key).unwrap().map(|v| v.to_vec())
+}
+
+fn view_value(view: &EpochView, key: &str) -> Option<Vec<u8>> {
+    view.get(key).unwrap().map(|v| v.to_vec())
+}
+
+fn entries<const LEAF_FANOUT: usize>(tree: &EpochTree<LEAF_FANOUT>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    tree.iter().map(|kv| kv.map(|(k, v)| (k.to_vec(), v.to_vec()))).collect::<Result<_, _>>().unwrap()
+}
+
+#[test]
+fn test_view_at_epoch_reads_earlier_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 8).open().unwrap();
+    let events = db.open_tree("events").unwrap();
+
+    db.insert("k", "v1").unwrap();
+    db.insert("removed", "x").unwrap();
+    events.insert("e", "v1").unwrap();
+    let epoch = db.current_epoch();
+    db.flush().unwrap();
+
+    db.insert("k", "v2").unwrap();
+    db.remove("removed").unwrap();
+    events.insert("e", "v2").unwrap();
+    db.open_tree("later").unwrap().insert("l", "1").unwrap();
+    db.flush().unwrap();
+
+    let view = db.view_at_epoch(epoch).unwrap();
+    assert_eq!(view.epoch(), epoch);
+    assert_eq!(view_value(&view, "k"), Some(b"v1".to_vec()));
+    assert_eq!(view_value(&view, "removed"), Some(b"x".to_vec()));
+    let events_view = view.tree("events").unwrap().unwrap();
+    assert_eq!(events_view.get("e").unwrap().as_deref(), Some(&b"v1"[..]));
+    // 之后创建的树不存在
+    assert!(view.tree("later").unwrap().is_none());
+    assert_eq!(view.tree_names().unwrap(), vec![InlineArray::from(&b"events"[..])]);
+
+    // 当前的数据库不受影响
+    assert_eq!(value(&db, "k"), Some(b"v2".to_vec()));
+    assert_eq!(value(&db, "removed"), None);
+    assert_eq!(value(&events, "e"), Some(b"v2".to_vec()));
+}
+
+#[test]
+fn test_view_at_epoch_iterates_in_key_order() {
+    let dir = tempfile::tempdir().unwrap();
+    // 较小的叶子节点使条目分布在多个叶子节点中
+    let db: Db<16> = config(dir.path(), 8).open().unwrap();
+
+    for i in 0..1_000_u32 {
+        db.insert(i.to_be_bytes(), i.to_le_bytes().to_vec()).unwrap();
+    }
+    let epoch = db.current_epoch();
+    db.flush().unwrap();
+
+    for i in (0..1_000_u32).step_by(2) {
+        db.remove(i.to_be_bytes()).unwrap();
+    }
+    db.flush().unwrap();
+
+    let view = db.view_at_epoch(epoch).unwrap();
+    let expected: Vec<(Vec<u8>, Vec<u8>)> =
+        (0..1_000_u32).map(|i| (i.to_be_bytes().to_vec(), i.to_le_bytes().to_vec())).collect();
+    assert_eq!(entries(&view.default_tree().unwrap()), expected);
+    assert_eq!(view.get(999_u32.to_be_bytes()).unwrap().as_deref(), Some(&999_u32.to_le_bytes()[..]));
+    assert_eq!(view.get(1_000_u32.to_be_bytes()).unwrap(), None);
+    assert_eq!(db.len().unwrap(), 500);
+}
+
+#[test]
+fn test_view_reads_trees_when_they_are_first_accessed() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 1).open().unwrap();
+    let events = db.open_tree("events").unwrap();
+
+    db.insert("k", "v1").unwrap();
+    events.insert("e", "v1").unwrap();
+    let epoch = db.current_epoch();
+    db.flush().unwrap();
+
+    let view = db.view_at_epoch(epoch).unwrap();
+    let default_tree = view.default_tree().unwrap();
+
+    // 之后的两次flush使这个epoch超出保留的范围
+    for round in 0..2_u32 {
+        db.insert("k", round.to_be_bytes().to_vec()).unwrap();
+        events.insert("e", round.to_be_bytes().to_vec()).unwrap();
+        db.flush().unwrap();
+    }
+
+    // 已经读取的树仍然可以访问，尚未读取的树不能再读取
+    assert_eq!(default_tree.get("k").unwrap().as_deref(), Some(&b"v1"[..]));
+    assert_eq!(view_value(&view, "k"), Some(b"v1".to_vec()));
+    assert_eq!(view.tree("events").err().map(|e| e.kind()), Some(ErrorKind::NotFound));
+}
+
+#[test]
+fn test_view_at_unflushed_epoch_flushes_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 8).open().unwrap();
+
+    // 当前epoch的写入还没有flush
+    db.insert("k", "v1").unwrap();
+    let view = db.view_at_epoch(db.current_epoch()).unwrap();
+    assert_eq!(view_value(&view, "k"), Some(b"v1".to_vec()));
+
+    let later = db.current_epoch().increment();
+    let err = db.view_at_epoch(later).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_view_at_reclaimed_epoch_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 2).open().unwrap();
+
+    let mut epochs = vec![];
+    for i in 0..5_u32 {
+        db.insert("k", i.to_be_bytes().to_vec()).unwrap();
+        epochs.push(db.current_epoch());
+        db.flush().unwrap();
+    }
+
+    // 最近2次flush之前的状态仍然可以读取
+    for (i, epoch) in epochs.iter().enumerate().skip(2) {
+        let view = db.view_at_epoch(*epoch).unwrap();
+        assert_eq!(view_value(&view, "k"), Some((i as u32).to_be_bytes().to_vec()));
+    }
+    for epoch in &epochs[..2] {
+        let err = db.view_at_epoch(*epoch).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
+
+#[test]
+fn test_view_at_epoch_without_retention() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 0).open().unwrap();
+
+    db.insert("k", "v1").unwrap();
+    let epoch = db.current_epoch();
+    db.flush().unwrap();
+    db.insert("k", "v2").unwrap();
+    db.flush().unwrap();
+
+    // 默认只能读取当前的状态
+    let err = db.view_at_epoch(epoch).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let view = db.view_at_epoch(db.current_epoch()).unwrap();
+    assert_eq!(view_value(&view, "k"), Some(b"v2".to_vec()));
+}
+
+#[test]
+fn test_view_at_epoch_keeps_replaced_blobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db = config(dir.path(), 1).inline_value_threshold(64).open().unwrap();
+
+    let v1 = vec![1_u8; 4096];
+    db.insert("k", v1.clone()).unwrap();
+    let first = db.current_epoch();
+    db.flush().unwrap();
+
+    let v2 = vec![2_u8; 4096];
+    db.insert("k", v2.clone()).unwrap();
+    let second = db.current_epoch();
+    db.flush().unwrap();
+
+    // 被替换的blob在对应的叶子节点被保留时仍然可以读取
+    let view = db.view_at_epoch(first).unwrap();
+    assert_eq!(view_value(&view, "k"), Some(v1));
+    drop(view);
+
+    db.insert("k", vec![3_u8; 4096]).unwrap();
+    db.flush().unwrap();
+    assert_eq!(db.view_at_epoch(first).err().unwrap().kind(), ErrorKind::NotFound);
+    let view = db.view_at_epoch(second).unwrap();
+    assert_eq!(view_value(&view, "k"), Some(v2));
+}
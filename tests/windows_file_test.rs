@@ -0,0 +1,95 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use melange_db::*;
+
+const N: u32 = 200;
+
+fn config(path: &Path, write_through: bool) -> Config {
+    common::config(path).windows_write_through(write_through)
+}
+
+/// 小值和大值分别写入slot不对齐和按扇区对齐的slab文件
+fn value(i: u32) -> Vec<u8> {
+    let len = if i.is_multiple_of(2) { 16 } else { 6000 };
+    vec![i as u8; len]
+}
+
+#[test]
+fn test_write_through_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db: Db<4> = config(dir.path(), true).open().unwrap();
+        for i in 0..N {
+            db.insert(i.to_be_bytes(), value(i)).unwrap();
+        }
+        db.flush().unwrap();
+        for i in (0..N).step_by(3) {
+            db.insert(i.to_be_bytes(), value(i + 1)).unwrap();
+        }
+        db.flush().unwrap();
+
+        let report = db.disk_usage().unwrap();
+        for slab in &report.slab_files {
+            // 只有Windows上扇区对齐的slab文件绕过系统缓存
+            let expected = cfg!(windows) && slab.slot_size.is_multiple_of(4096);
+            assert_eq!(slab.unbuffered, expected, "slot大小为 {} 的slab文件", slab.slot_size);
+        }
+        if cfg!(windows) {
+            assert!(report.slab_files.iter().any(|slab| slab.unbuffered));
+        }
+    }
+
+    // 数据与打开方式无关
+    for write_through in [true, false] {
+        let db: Db<4> = config(dir.path(), write_through).open().unwrap();
+        for i in 0..N {
+            let expected = if i.is_multiple_of(3) { value(i + 1) } else { value(i) };
+            assert_eq!(db.get(i.to_be_bytes()).unwrap().as_deref(), Some(&expected[..]));
+        }
+        db.check().unwrap();
+        let report = db.disk_usage().unwrap();
+        assert_eq!(
+            report.slab_files.iter().any(|slab| slab.unbuffered),
+            cfg!(windows) && write_through
+        );
+    }
+}
+
+#[test]
+fn test_sparse_slab_files_report_allocated_bytes() {
+    const HOLE: u64 = 64 * 1024 * 1024;
+
+    let dir = tempfile::tempdir().unwrap();
+    let db: Db<1024> = config(dir.path(), false).open().unwrap();
+    for i in 0..N {
+        db.insert(i.to_be_bytes(), value(i)).unwrap();
+    }
+    db.flush().unwrap();
+
+    let before = db.disk_usage().unwrap();
+    let slab = before.slab_files[0];
+    assert!(slab.allocated_bytes > 0);
+
+    // 越过文件末尾的区域没有被写入，不占用磁盘空间
+    let slab_path = dir.path().join("slabs").join(slab.slot_size.to_string());
+    let file = fs::OpenOptions::new().write(true).open(slab_path).unwrap();
+    file.set_len(slab.file_bytes + HOLE).unwrap();
+    drop(file);
+
+    let after = db.disk_usage().unwrap();
+    let grown = after
+        .slab_files
+        .iter()
+        .find(|other| other.slot_size == slab.slot_size)
+        .unwrap();
+    assert_eq!(grown.file_bytes, slab.file_bytes + HOLE);
+    assert!(
+        grown.allocated_bytes < slab.allocated_bytes + HOLE / 2,
+        "文件大小 {} 字节，占用 {} 字节",
+        grown.file_bytes,
+        grown.allocated_bytes
+    );
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "for-internal-testing-only")]
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use melange_db::hybrid_operations_manager::HybridOperationsManager;
+use melange_db::*;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+fn open_manager() -> HybridOperationsManager {
+    let db = Arc::new(Config::tmp().unwrap().flush_every_ms(None).open().unwrap());
+    HybridOperationsManager::new_with_db_worker(db)
+}
+
+/// 在 `TIMEOUT` 之后不久返回 `TimedOut`
+fn assert_times_out<T: std::fmt::Debug>(f: impl FnOnce() -> io::Result<T>) {
+    let start = Instant::now();
+    let error = f().unwrap_err();
+    let elapsed = start.elapsed();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut, "{:?}", error);
+    assert!(elapsed >= TIMEOUT, "{:?}", elapsed);
+    assert!(elapsed < TIMEOUT + Duration::from_millis(500), "{:?}", elapsed);
+}
+
+#[test]
+fn test_timeout_when_workers_stalled() {
+    let manager = open_manager();
+    manager.increment("visits".to_string(), 3).unwrap();
+    manager.insert(b"key", b"value").unwrap();
+
+    let pause = manager.pause_workers_for_testing();
+    assert_times_out(|| manager.get_timeout("visits".to_string(), TIMEOUT));
+    assert_times_out(|| manager.get_data_timeout(b"key", TIMEOUT));
+    assert_times_out(|| manager.insert_timeout(b"late", b"1", TIMEOUT));
+    assert_times_out(|| manager.remove_timeout(b"key", TIMEOUT));
+    assert_times_out(|| manager.scan_prefix_timeout(b"k", TIMEOUT));
+    drop(pause);
+
+    // Worker恢复后处理排队的操作，超时的写入仍然被执行
+    assert_eq!(manager.get_timeout("visits".to_string(), TIMEOUT).unwrap(), Some(3));
+    assert_eq!(manager.get("visits".to_string()).unwrap(), Some(3));
+    assert_eq!(&*manager.get_data_timeout(b"late", TIMEOUT).unwrap().unwrap(), b"1");
+    assert!(manager.get_data(b"key").unwrap().is_none());
+}
+
+#[test]
+fn test_timeout_variants_succeed_without_stall() {
+    let manager = open_manager();
+    assert_eq!(manager.insert_timeout(b"a", b"1", TIMEOUT).unwrap(), None);
+    assert_eq!(&*manager.get_data_timeout(b"a", TIMEOUT).unwrap().unwrap(), b"1");
+    assert_eq!(manager.scan_prefix_timeout(b"a", TIMEOUT).unwrap(), vec![(b"a".to_vec(), b"1".to_vec())]);
+    assert_eq!(&*manager.remove_timeout(b"a", TIMEOUT).unwrap().unwrap(), b"1");
+    assert_eq!(manager.get_timeout("missing".to_string(), TIMEOUT).unwrap(), None);
+
+    // 直接访问模式下只有计数器操作经过Worker
+    let mut manager = manager;
+    manager.disable_database_worker_mode();
+    let _pause = manager.pause_workers_for_testing();
+    assert_eq!(manager.insert_timeout(b"b", b"2", TIMEOUT).unwrap(), None);
+    assert_eq!(&*manager.get_data_timeout(b"b", TIMEOUT).unwrap().unwrap(), b"2");
+}
@@ -0,0 +1,155 @@
+use std::io::ErrorKind;
+
+use melange_db::*;
+
+fn write_once() -> TreeOptions {
+    TreeOptions::new().write_once(true)
+}
+
+fn open(path: &std::path::Path) -> Db<1024> {
+    Config::new().path(path).flush_every_ms(None).open().unwrap()
+}
+
+fn key(i: u32) -> Vec<u8> {
+    format!("entry_{:06}", i).into_bytes()
+}
+
+#[test]
+fn test_write_once_rejects_overwrites_and_removals() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let audit = db.open_tree_with_options("audit", write_once()).unwrap();
+    assert!(audit.is_write_once());
+
+    assert_eq!(audit.insert(b"e1", b"created".as_slice()).unwrap(), None);
+
+    // 覆盖已存在的键返回独立的错误类型
+    let err = audit.insert(b"e1", b"tampered".as_slice()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(KeyAlreadyExists::from_io_error(&err).unwrap().key, InlineArray::from(b"e1".as_slice()));
+
+    for err in [
+        audit.remove(b"e1").unwrap_err(),
+        audit.clear().unwrap_err(),
+        audit.pop_first().unwrap_err(),
+        audit.pop_last().unwrap_err(),
+        audit.pop_first_in_range(b"e".as_slice()..).unwrap_err(),
+        audit.pop_last_in_range(b"e".as_slice()..).unwrap_err(),
+        db.drop_tree("audit").unwrap_err(),
+    ] {
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied, "{}", err);
+    }
+
+    // 从 None 到 Some 的比较并交换不会覆盖任何值
+    assert!(audit.compare_and_swap(b"e2", None as Option<&[u8]>, Some(b"created".as_slice())).unwrap().is_ok());
+    let err = audit
+        .compare_and_swap(b"e2", Some(b"created".as_slice()), Some(b"tampered".as_slice()))
+        .unwrap_err();
+    assert!(KeyAlreadyExists::from_io_error(&err).is_some());
+    let err = audit
+        .compare_and_swap(b"e2", Some(b"created".as_slice()), None as Option<&[u8]>)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    // 不匹配的比较并交换照常返回当前值
+    assert!(audit.compare_and_swap(b"e2", Some(b"other".as_slice()), None as Option<&[u8]>).unwrap().is_err());
+    assert_eq!(audit.get_or_insert_with(b"e2", || b"unused".to_vec()).unwrap(), InlineArray::from(b"created".as_slice()));
+
+    assert_eq!(audit.len().unwrap(), 2);
+    assert_eq!(&*audit.get(b"e1").unwrap().unwrap(), b"created");
+
+    // 管理工具可以通过 force 句柄修改，普通句柄仍然受限
+    let admin = audit.force();
+    assert_eq!(admin.insert(b"e1", b"corrected".as_slice()).unwrap().unwrap(), InlineArray::from(b"created".as_slice()));
+    assert!(admin.remove(b"e2").unwrap().is_some());
+    assert!(audit.remove(b"e1").is_err());
+    assert!(db.force_drop_tree("audit").unwrap());
+    assert!(!db.contains_tree("audit").unwrap());
+}
+
+#[test]
+fn test_violating_batches_are_rejected_wholesale() {
+    let db: Db<1024> = Config::tmp().unwrap().open().unwrap();
+    let audit = db.open_tree_with_options("audit", write_once()).unwrap();
+    for i in 0..10 {
+        audit.insert(key(i), b"original".as_slice()).unwrap();
+    }
+
+    // 批次中只有最后一个键覆盖已存在的值
+    let mut batch = Batch::default();
+    for i in 10..100 {
+        batch.insert(key(i), b"new".as_slice());
+    }
+    batch.insert(key(5), b"tampered".as_slice());
+    let err = audit.apply_batch(batch).unwrap_err();
+    assert_eq!(KeyAlreadyExists::from_io_error(&err).unwrap().key, InlineArray::from(key(5)));
+    assert_eq!(audit.len().unwrap(), 10);
+
+    // 包含删除的批次也被整体拒绝
+    let mut batch = Batch::default();
+    batch.insert(key(10), b"new".as_slice());
+    batch.remove(key(99));
+    let err = audit.apply_batch(batch).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(audit.len().unwrap(), 10);
+
+    // 溢出到磁盘的批次同样在修改之前被校验
+    let mut batch = Batch::with_spill_threshold(1024);
+    for i in 10..2_000 {
+        batch.insert(key(i), vec![7_u8; 64]);
+    }
+    batch.insert(key(0), b"tampered".as_slice());
+    assert!(batch.is_spilled());
+    let err = audit.apply_batch(batch).unwrap_err();
+    assert!(KeyAlreadyExists::from_io_error(&err).is_some());
+    assert_eq!(audit.len().unwrap(), 10);
+    for i in 0..10 {
+        assert_eq!(&*audit.get(key(i)).unwrap().unwrap(), b"original");
+    }
+
+    // 只创建新键的批次正常应用
+    let mut batch = Batch::default();
+    for i in 10..100 {
+        batch.insert(key(i), b"new".as_slice());
+    }
+    audit.apply_batch(batch).unwrap();
+    assert_eq!(audit.len().unwrap(), 100);
+}
+
+#[test]
+fn test_write_once_flag_persists_across_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = open(dir.path());
+        let audit = db.open_tree_with_options("audit", write_once()).unwrap();
+        audit.insert(b"e1", b"created".as_slice()).unwrap();
+        let normal = db.open_tree("normal").unwrap();
+        normal.insert(b"k", b"v".as_slice()).unwrap();
+
+        // 同一个进程中也不能改变已存在的树的模式
+        let err = db.open_tree_with_options("audit", TreeOptions::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = db.open_tree_with_options("normal", write_once()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        db.flush().unwrap();
+    }
+
+    let db = open(dir.path());
+
+    // 不指定选项打开时保持持久化的模式
+    let audit = db.open_tree("audit").unwrap();
+    assert!(audit.is_write_once());
+    assert!(KeyAlreadyExists::from_io_error(&audit.insert(b"e1", b"x".as_slice()).unwrap_err()).is_some());
+    assert!(audit.remove(b"e1").is_err());
+    assert!(db.open_tree_with_options("audit", write_once()).is_ok());
+
+    let normal = db.open_tree("normal").unwrap();
+    assert!(!normal.is_write_once());
+    assert_eq!(normal.insert(b"k", b"v2".as_slice()).unwrap().unwrap(), InlineArray::from(b"v".as_slice()));
+
+    let err = db.open_tree_with_options("normal", write_once()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = db.open_tree_with_options("audit", TreeOptions::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // 普通的树可以正常删除
+    assert!(db.drop_tree("normal").unwrap());
+}